@@ -1,15 +1,32 @@
 //! Content-addressed blob storage
 //!
-//! Stores file content using BLAKE3 hashes for deduplication.
+//! Stores file content using BLAKE3 hashes for deduplication. Content
+//! larger than one chunk is cut into content-defined chunks by a
+//! [`Chunker`] and stored as the chunks plus a manifest listing them, so
+//! versions of a large file share the chunks they have in common. Either
+//! way a blob's id is the hash of its whole content.
+//!
+//! Under the base directory, each sharded by the first two hex digits:
+//! - `ab/cdef…`: blobs stored whole
+//! - `manifests/ab/cdef…`: manifests, named by the id of the content
+//! - `chunks/ab/cdef…`: chunks, named by their own hash
 
 use blake3::Hasher;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use cfk_core::backend::ByteStream;
+use chrono::{DateTime, Utc};
+use futures::stream;
 use lz4_flex::{compress_prepend_size, decompress_size_prepended};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedRwLockReadGuard, RwLock};
 
-use crate::{CacheError, CacheResult};
+use crate::chunker::{Chunker, ChunkerConfig};
+use crate::policy::CacheEntryInfo;
+use crate::{CacheError, CacheResult, CacheStats};
 
 /// Content identifier (BLAKE3 hash)
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -64,6 +81,19 @@ pub struct BlobStoreConfig {
     pub compress_threshold: usize,
     /// Verify content on read
     pub verify_on_read: bool,
+    /// Chunk sizes for content larger than `max_size`; `None` stores all
+    /// content whole
+    pub chunking: Option<ChunkerConfig>,
+}
+
+impl BlobStoreConfig {
+    fn manifest_dir(&self) -> PathBuf {
+        self.path.join("manifests")
+    }
+
+    fn chunk_dir(&self) -> PathBuf {
+        self.path.join("chunks")
+    }
 }
 
 impl Default for BlobStoreConfig {
@@ -77,6 +107,7 @@ fn default() -> Self {
             compress: true,
             compress_threshold: 1024, // 1KB
             verify_on_read: true,
+            chunking: Some(ChunkerConfig::default()),
         }
     }
 }
@@ -84,6 +115,10 @@ fn default() -> Self {
 /// Content-addressed blob store
 pub struct BlobStore {
     config: BlobStoreConfig,
+    chunker: Option<Chunker>,
+    /// Held shared while chunks are stored ahead of their manifest, and
+    /// exclusively by a sweep for unreferenced chunks
+    writing: Arc<RwLock<()>>,
 }
 
 impl BlobStore {
@@ -94,7 +129,11 @@ pub async fn new(config: BlobStoreConfig) -> CacheResult<Self> {
             .await
             .map_err(|e| CacheError::Io(e.to_string()))?;
 
-        Ok(Self { config })
+        Ok(Self {
+            chunker: config.chunking.map(Chunker::new),
+            config,
+            writing: Arc::new(RwLock::new(())),
+        })
     }
 
     /// Create with default configuration
@@ -118,44 +157,16 @@ pub async fn put(&self, data: Bytes) -> CacheResult<ContentId> {
             return Ok(content_id);
         }
 
-        // Create parent directory
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)
-                .await
-                .map_err(|e| CacheError::Io(e.to_string()))?;
-        }
-
-        // Compress if enabled and above threshold
-        let stored_data = if self.config.compress && data.len() >= self.config.compress_threshold {
-            let compressed = compress_prepend_size(&data);
-            // Only use compressed if it's smaller
-            if compressed.len() < data.len() {
-                Bytes::from(compressed)
-            } else {
-                data
+        match &self.chunker {
+            Some(chunker) if data.len() > chunker.config().max_size => {
+                let mut chunks = ChunkedWrite::new(self).await;
+                for chunk in chunker.chunks(&data) {
+                    chunks.push(data.slice_ref(chunk)).await?;
+                }
+                chunks.finish(&content_id).await?;
             }
-        } else {
-            data
-        };
-
-        // Write atomically using temp file
-        let temp_path = path.with_extension("tmp");
-        let mut file = fs::File::create(&temp_path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
-
-        file.write_all(&stored_data)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
-
-        file.sync_all()
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
-
-        // Rename to final path
-        fs::rename(&temp_path, &path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+            _ => write_blob(&self.config, &path, data).await?,
+        }
 
         Ok(content_id)
     }
@@ -163,95 +174,151 @@ pub async fn put(&self, data: Bytes) -> CacheResult<ContentId> {
     /// Retrieve blob by content ID
     pub async fn get(&self, content_id: &ContentId) -> CacheResult<Bytes> {
         let path = content_id.storage_path(&self.config.path);
+        if path.exists() {
+            return read_blob(&path, content_id, self.config.verify_on_read).await;
+        }
 
-        if !path.exists() {
-            return Err(CacheError::NotFound(content_id.to_string()));
+        let manifest = self.manifest(content_id).await?;
+        let chunk_dir = self.config.chunk_dir();
+        let mut data = BytesMut::with_capacity(manifest.size() as usize);
+        for chunk in &manifest.chunks {
+            let path = chunk.id.storage_path(&chunk_dir);
+            data.extend_from_slice(&read_blob(&path, &chunk.id, true).await?);
         }
+        let data = data.freeze();
 
-        let mut file = fs::File::open(&path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+        if self.config.verify_on_read && Self::hash(&data) != *content_id {
+            return Err(CacheError::CorruptedContent(content_id.to_string()));
+        }
 
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+        Ok(data)
+    }
 
-        // Try to decompress
-        let decompressed = match decompress_size_prepended(&data) {
-            Ok(d) => Bytes::from(d),
-            Err(_) => Bytes::from(data), // Not compressed
-        };
+    /// Stream a blob, or the part of it in `range`, reading a chunked blob
+    /// one chunk at a time
+    ///
+    /// The stream only opens when every chunk it needs is there. Chunks are
+    /// checked against their ids as they are read, and with
+    /// `verify_on_read` a whole blob against its id at the end.
+    pub async fn get_stream(
+        &self,
+        content_id: &ContentId,
+        range: Option<(u64, u64)>,
+    ) -> CacheResult<ByteStream> {
+        let path = content_id.storage_path(&self.config.path);
+        if path.exists() {
+            let data = read_blob(&path, content_id, self.config.verify_on_read).await?;
+            let data = slice(data, range);
+            return Ok(Box::pin(stream::once(async move { Ok(data) })));
+        }
 
-        // Verify content if enabled
-        if self.config.verify_on_read {
-            let computed_id = Self::hash(&decompressed);
-            if computed_id != *content_id {
-                return Err(CacheError::CorruptedContent(content_id.to_string()));
+        let manifest = self.manifest(content_id).await?;
+        let (start, end) = range.unwrap_or((0, u64::MAX));
+        let chunk_dir = self.config.chunk_dir();
+        let mut parts = Vec::new();
+        let mut offset = 0u64;
+        for chunk in manifest.chunks {
+            let chunk_end = offset + u64::from(chunk.len);
+            if chunk_end > start && offset < end {
+                let path = chunk.id.storage_path(&chunk_dir);
+                if !path.exists() {
+                    return Err(CacheError::NotFound(chunk.id.to_string()));
+                }
+                let part = (start.saturating_sub(offset), end.min(chunk_end) - offset);
+                parts.push((path, chunk.id, part));
             }
+            offset = chunk_end;
         }
 
-        Ok(decompressed)
+        let hasher = (self.config.verify_on_read && range.is_none()).then(Hasher::new);
+        let state = Some((parts.into_iter(), hasher, content_id.clone()));
+        Ok(Box::pin(stream::unfold(state, |state| async move {
+            let (mut parts, mut hasher, content_id) = state?;
+            let Some((path, id, part)) = parts.next() else {
+                return match hasher {
+                    Some(hasher) if ContentId(*hasher.finalize().as_bytes()) != content_id => {
+                        let e = CacheError::CorruptedContent(content_id.to_string());
+                        Some((Err(e.into()), None))
+                    }
+                    _ => None,
+                };
+            };
+            match read_blob(&path, &id, true).await {
+                Ok(data) => {
+                    if let Some(hasher) = hasher.as_mut() {
+                        hasher.update(&data);
+                    }
+                    let data = slice(data, Some(part));
+                    Some((Ok(data), Some((parts, hasher, content_id))))
+                }
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        })))
     }
 
     /// Check if blob exists
     pub async fn exists(&self, content_id: &ContentId) -> bool {
-        let path = content_id.storage_path(&self.config.path);
-        path.exists()
+        content_id.storage_path(&self.config.path).exists()
+            || content_id.storage_path(&self.config.manifest_dir()).exists()
     }
 
     /// Delete blob by content ID
+    ///
+    /// The chunks of a chunked blob stay until
+    /// [`sweep_chunks`](Self::sweep_chunks) finds nothing else uses them.
     pub async fn delete(&self, content_id: &ContentId) -> CacheResult<()> {
-        let path = content_id.storage_path(&self.config.path);
-
-        if path.exists() {
-            fs::remove_file(&path)
-                .await
-                .map_err(|e| CacheError::Io(e.to_string()))?;
+        for path in [
+            content_id.storage_path(&self.config.path),
+            content_id.storage_path(&self.config.manifest_dir()),
+        ] {
+            if path.exists() {
+                fs::remove_file(&path)
+                    .await
+                    .map_err(|e| CacheError::Io(e.to_string()))?;
+            }
         }
 
         Ok(())
     }
 
     /// Get size of stored blob (compressed size)
+    ///
+    /// A chunked blob counts its manifest and all of its chunks, including
+    /// those it shares with other blobs.
     pub async fn size(&self, content_id: &ContentId) -> CacheResult<u64> {
         let path = content_id.storage_path(&self.config.path);
+        if path.exists() {
+            let metadata = fs::metadata(&path)
+                .await
+                .map_err(|e| CacheError::Io(e.to_string()))?;
+            return Ok(metadata.len());
+        }
 
-        let metadata = fs::metadata(&path)
+        let manifest_path = content_id.storage_path(&self.config.manifest_dir());
+        let mut size = fs::metadata(&manifest_path)
             .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+            .map_err(|e| CacheError::Io(e.to_string()))?
+            .len();
+        let chunk_dir = self.config.chunk_dir();
+        for chunk in self.manifest(content_id).await?.chunks {
+            if let Ok(metadata) = fs::metadata(chunk.id.storage_path(&chunk_dir)).await {
+                size += metadata.len();
+            }
+        }
 
-        Ok(metadata.len())
+        Ok(size)
     }
 
     /// Get total size of blob store
     pub async fn total_size(&self) -> CacheResult<u64> {
         let mut total = 0u64;
 
-        let mut entries = fs::read_dir(&self.config.path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
-
-        while let Some(entry) = entries
-            .next_entry()
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?
-        {
-            if entry.path().is_dir() {
-                let mut subdir = fs::read_dir(entry.path())
-                    .await
-                    .map_err(|e| CacheError::Io(e.to_string()))?;
-
-                while let Some(file) = subdir
-                    .next_entry()
-                    .await
-                    .map_err(|e| CacheError::Io(e.to_string()))?
-                {
-                    if let Ok(meta) = file.metadata().await {
-                        total += meta.len();
-                    }
-                }
-            }
+        for dir in [
+            self.config.path.clone(),
+            self.config.manifest_dir(),
+            self.config.chunk_dir(),
+        ] {
+            total += shard_files(&dir).await?.iter().map(|(_, meta)| meta.len()).sum::<u64>();
         }
 
         Ok(total)
@@ -259,69 +326,412 @@ pub async fn total_size(&self) -> CacheResult<u64> {
 
     /// List all content IDs
     pub async fn list(&self) -> CacheResult<Vec<ContentId>> {
-        let mut ids = Vec::new();
+        let mut ids: Vec<ContentId> = shard_files(&self.config.path)
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        // Content stored whole before chunking was turned on may have a
+        // manifest as well
+        let whole: HashSet<ContentId> = ids.iter().cloned().collect();
+        for (id, _) in shard_files(&self.config.manifest_dir()).await? {
+            if !whole.contains(&id) {
+                ids.push(id);
+            }
+        }
 
-        let mut entries = fs::read_dir(&self.config.path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+        Ok(ids)
+    }
 
-        while let Some(entry) = entries
-            .next_entry()
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?
-        {
-            let dir_name = entry.file_name().to_string_lossy().to_string();
-            if dir_name.len() != 2 || !entry.path().is_dir() {
-                continue;
+    /// Every stored blob with its size, when it was stored and last read,
+    /// for a [`CachePolicy`](crate::CachePolicy) to pick up where an earlier
+    /// process left off
+    pub async fn entries(&self) -> CacheResult<Vec<CacheEntryInfo>> {
+        let mut entries = Vec::new();
+
+        let whole = shard_files(&self.config.path).await?;
+        let stored: HashSet<ContentId> = whole.iter().map(|(id, _)| id.clone()).collect();
+        let mut blobs: Vec<_> = whole.into_iter().map(|(id, meta)| (id, meta, false)).collect();
+        for (id, meta) in shard_files(&self.config.manifest_dir()).await? {
+            if !stored.contains(&id) {
+                blobs.push((id, meta, true));
             }
+        }
 
-            let mut subdir = fs::read_dir(entry.path())
-                .await
-                .map_err(|e| CacheError::Io(e.to_string()))?;
+        for (id, metadata, chunked) in blobs {
+            let size = if chunked {
+                match self.size(&id).await {
+                    Ok(size) => size,
+                    Err(_) => continue,
+                }
+            } else {
+                metadata.len()
+            };
+            let stored = metadata
+                .modified()
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| Utc::now());
+            // Access times are coarse under relatime, but good enough for LRU
+            let read = metadata.accessed().map(DateTime::<Utc>::from).unwrap_or(stored);
+
+            let mut info = CacheEntryInfo::new(id, size);
+            info.created = stored;
+            info.last_accessed = read.max(stored);
+            entries.push(info);
+        }
 
-            while let Some(file) = subdir
-                .next_entry()
-                .await
-                .map_err(|e| CacheError::Io(e.to_string()))?
-            {
-                let file_name = file.file_name().to_string_lossy().to_string();
-                let hex = format!("{}{}", dir_name, file_name);
+        Ok(entries)
+    }
 
-                if let Ok(id) = ContentId::from_hex(&hex) {
-                    ids.push(id);
+    /// Garbage collect blobs not in the provided set, and the chunks only
+    /// they used
+    pub async fn gc(&self, keep: &HashSet<ContentId>) -> CacheResult<u64> {
+        let mut freed = 0u64;
+
+        for dir in [self.config.path.clone(), self.config.manifest_dir()] {
+            for (id, metadata) in shard_files(&dir).await? {
+                if !keep.contains(&id) {
+                    fs::remove_file(id.storage_path(&dir))
+                        .await
+                        .map_err(|e| CacheError::Io(e.to_string()))?;
+                    freed += metadata.len();
                 }
             }
         }
 
-        Ok(ids)
+        let _writing = self.writing.write().await;
+        freed += self.sweep().await?;
+
+        Ok(freed)
     }
 
-    /// Garbage collect blobs not in the provided set
-    pub async fn gc(&self, keep: &std::collections::HashSet<ContentId>) -> CacheResult<u64> {
+    /// Delete the chunks no manifest refers to any more; returns the bytes
+    /// freed
+    ///
+    /// Nothing is swept while blobs are being written, since their chunks
+    /// are stored before the manifest that refers to them.
+    pub async fn sweep_chunks(&self) -> CacheResult<u64> {
+        let Ok(_writing) = self.writing.try_write() else {
+            return Ok(0);
+        };
+        self.sweep().await
+    }
+
+    async fn sweep(&self) -> CacheResult<u64> {
+        let mut referenced = HashSet::new();
+        let manifest_dir = self.config.manifest_dir();
+        for (id, _) in shard_files(&manifest_dir).await? {
+            match self.manifest(&id).await {
+                Ok(manifest) => referenced.extend(manifest.chunks.into_iter().map(|c| c.id)),
+                // Its content is lost either way
+                Err(CacheError::CorruptedContent(_)) => {
+                    fs::remove_file(id.storage_path(&manifest_dir))
+                        .await
+                        .map_err(|e| CacheError::Io(e.to_string()))?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
         let mut freed = 0u64;
+        let chunk_dir = self.config.chunk_dir();
+        for (id, metadata) in shard_files(&chunk_dir).await? {
+            if !referenced.contains(&id) {
+                fs::remove_file(id.storage_path(&chunk_dir))
+                    .await
+                    .map_err(|e| CacheError::Io(e.to_string()))?;
+                freed += metadata.len();
+            }
+        }
 
-        let all_ids = self.list().await?;
+        Ok(freed)
+    }
+
+    /// How much content is stored and how much room deduplication saves
+    ///
+    /// Blobs stored whole count with their size on disk; chunked blobs with
+    /// their full length before, and their distinct chunks after.
+    pub async fn stats(&self) -> CacheResult<CacheStats> {
+        let mut stats = CacheStats::default();
+
+        let whole = shard_files(&self.config.path).await?;
+        let stored: HashSet<ContentId> = whole.iter().map(|(id, _)| id.clone()).collect();
+        for (_, metadata) in &whole {
+            stats.entries += 1;
+            stats.total_size += metadata.len();
+            stats.deduplicated_size += metadata.len();
+        }
 
-        for id in all_ids {
-            if !keep.contains(&id) {
-                if let Ok(size) = self.size(&id).await {
-                    freed += size;
+        let mut chunks = HashSet::new();
+        for (id, _) in shard_files(&self.config.manifest_dir()).await? {
+            if stored.contains(&id) {
+                continue;
+            }
+            let manifest = self.manifest(&id).await?;
+            stats.entries += 1;
+            stats.total_size += manifest.size();
+            for chunk in manifest.chunks {
+                if chunks.insert(chunk.id) {
+                    stats.deduplicated_size += u64::from(chunk.len);
                 }
-                self.delete(&id).await?;
             }
         }
 
-        Ok(freed)
+        Ok(stats)
+    }
+
+    /// The manifest of chunked blob `content_id`
+    async fn manifest(&self, content_id: &ContentId) -> CacheResult<Manifest> {
+        let path = content_id.storage_path(&self.config.manifest_dir());
+        let data = fs::read(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => CacheError::NotFound(content_id.to_string()),
+            _ => CacheError::Io(e.to_string()),
+        })?;
+        Manifest::decode(&data).ok_or_else(|| CacheError::CorruptedContent(content_id.to_string()))
+    }
+}
+
+/// A chunk of a chunked blob
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChunkRef {
+    id: ContentId,
+    len: u32,
+}
+
+/// The chunks a blob is made of, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Manifest {
+    chunks: Vec<ChunkRef>,
+}
+
+impl Manifest {
+    const MAGIC: &'static [u8; 8] = b"cfkmnf01";
+    const RECORD: usize = 32 + 4;
+
+    fn size(&self) -> u64 {
+        self.chunks.iter().map(|c| u64::from(c.len)).sum()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(Self::MAGIC.len() + self.chunks.len() * Self::RECORD);
+        data.extend_from_slice(Self::MAGIC);
+        for chunk in &self.chunks {
+            data.extend_from_slice(&chunk.id.0);
+            data.extend_from_slice(&chunk.len.to_le_bytes());
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let records = data.strip_prefix(Self::MAGIC)?;
+        if records.len() % Self::RECORD != 0 {
+            return None;
+        }
+        let chunks = records
+            .chunks_exact(Self::RECORD)
+            .map(|record| {
+                let (id, len) = record.split_at(32);
+                ChunkRef {
+                    id: ContentId(id.try_into().unwrap()),
+                    len: u32::from_le_bytes(len.try_into().unwrap()),
+                }
+            })
+            .collect();
+        Some(Self { chunks })
+    }
+}
+
+/// The chunks of one blob as they are stored, then its manifest
+struct ChunkedWrite {
+    config: BlobStoreConfig,
+    manifest: Manifest,
+    /// Keeps sweeps away until the manifest refers to the chunks
+    _writing: OwnedRwLockReadGuard<()>,
+}
+
+impl ChunkedWrite {
+    async fn new(store: &BlobStore) -> Self {
+        Self {
+            config: store.config.clone(),
+            manifest: Manifest::default(),
+            _writing: store.writing.clone().read_owned().await,
+        }
+    }
+
+    async fn push(&mut self, data: Bytes) -> CacheResult<()> {
+        let id = BlobStore::hash(&data);
+        let len = data.len() as u32;
+        write_blob(&self.config, &id.storage_path(&self.config.chunk_dir()), data).await?;
+        self.manifest.chunks.push(ChunkRef { id, len });
+        Ok(())
+    }
+
+    async fn finish(self, content_id: &ContentId) -> CacheResult<()> {
+        // Written as is: it is not named by its own hash, so a compressed
+        // one could not be told apart
+        let path = content_id.storage_path(&self.config.manifest_dir());
+        write_atomic(&path, &self.manifest.encode()).await
+    }
+}
+
+/// Read the blob `content_id` stored at `path`
+async fn read_blob(path: &Path, content_id: &ContentId, verify: bool) -> CacheResult<Bytes> {
+    if !path.exists() {
+        return Err(CacheError::NotFound(content_id.to_string()));
+    }
+
+    let mut file = fs::File::open(path)
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    // Blobs carry no compression marker, and raw data may happen to
+    // decompress: the content is whichever reading hashes to the id
+    let decompressed = match decompress_size_prepended(&data) {
+        Ok(d) if BlobStore::hash(&d) == *content_id => Bytes::from(d),
+        _ => Bytes::from(data), // Not compressed
+    };
+
+    // Verify content if enabled
+    if verify {
+        let computed_id = BlobStore::hash(&decompressed);
+        if computed_id != *content_id {
+            return Err(CacheError::CorruptedContent(content_id.to_string()));
+        }
+    }
+
+    Ok(decompressed)
+}
+
+/// Store `data` at `path` unless it is there already, compressed if the
+/// config asks for it and that makes it smaller
+async fn write_blob(config: &BlobStoreConfig, path: &Path, data: Bytes) -> CacheResult<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    // Compress if enabled and above threshold
+    let stored_data = if config.compress && data.len() >= config.compress_threshold {
+        let compressed = compress_prepend_size(&data);
+        // Only use compressed if it's smaller
+        if compressed.len() < data.len() {
+            Bytes::from(compressed)
+        } else {
+            data
+        }
+    } else {
+        data
+    };
+
+    write_atomic(path, &stored_data).await
+}
+
+/// Write `data` to `path` through a temporary file, unless `path` exists
+async fn write_atomic(path: &Path, data: &[u8]) -> CacheResult<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    // Create parent directory
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|e| CacheError::Io(e.to_string()))?;
+    }
+
+    // Write atomically using temp file
+    let temp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp_path)
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    file.write_all(data)
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    file.sync_all()
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    // Rename to final path
+    fs::rename(&temp_path, path)
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?;
+
+    Ok(())
+}
+
+/// The blobs stored under `root`, with their file metadata
+async fn shard_files(root: &Path) -> CacheResult<Vec<(ContentId, std::fs::Metadata)>> {
+    let mut files = Vec::new();
+
+    let mut entries = match fs::read_dir(root).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(CacheError::Io(e.to_string())),
+    };
+
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| CacheError::Io(e.to_string()))?
+    {
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !entry.path().is_dir() {
+            continue;
+        }
+
+        let mut subdir = fs::read_dir(entry.path())
+            .await
+            .map_err(|e| CacheError::Io(e.to_string()))?;
+
+        while let Some(file) = subdir
+            .next_entry()
+            .await
+            .map_err(|e| CacheError::Io(e.to_string()))?
+        {
+            let file_name = file.file_name().to_string_lossy().to_string();
+            let hex = format!("{}{}", dir_name, file_name);
+
+            if let Ok(id) = ContentId::from_hex(&hex) {
+                if let Ok(metadata) = file.metadata().await {
+                    files.push((id, metadata));
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// `data`, or the part of it in `range`
+pub(crate) fn slice(data: Bytes, range: Option<(u64, u64)>) -> Bytes {
+    match range {
+        Some((start, end)) => {
+            let len = data.len() as u64;
+            let start = start.min(len);
+            data.slice(start as usize..end.clamp(start, len) as usize)
+        }
+        None => data,
     }
 }
 
 /// Streaming blob writer for large files
+///
+/// With chunking, chunks are stored as soon as they are cut, and only the
+/// part not cut yet is held in memory.
 pub struct BlobWriter {
     hasher: Hasher,
     temp_path: PathBuf,
     file: Option<fs::File>,
     compress: bool,
     buffer: Vec<u8>,
+    chunked: Option<(Chunker, ChunkedWrite)>,
 }
 
 impl BlobWriter {
@@ -332,16 +742,23 @@ pub async fn new(store: &BlobStore) -> CacheResult<Self> {
             .path
             .join(format!("upload_{}", uuid_simple()));
 
-        let file = fs::File::create(&temp_path)
-            .await
-            .map_err(|e| CacheError::Io(e.to_string()))?;
+        let (file, chunked) = match &store.chunker {
+            Some(chunker) => (None, Some((chunker.clone(), ChunkedWrite::new(store).await))),
+            None => {
+                let file = fs::File::create(&temp_path)
+                    .await
+                    .map_err(|e| CacheError::Io(e.to_string()))?;
+                (Some(file), None)
+            }
+        };
 
         Ok(Self {
             hasher: Hasher::new(),
             temp_path,
-            file: Some(file),
+            file,
             compress: store.config.compress,
             buffer: Vec::new(),
+            chunked,
         })
     }
 
@@ -349,6 +766,19 @@ pub async fn new(store: &BlobStore) -> CacheResult<Self> {
     pub async fn write(&mut self, data: &[u8]) -> CacheResult<()> {
         self.hasher.update(data);
 
+        if let Some((chunker, chunks)) = &mut self.chunked {
+            self.buffer.extend_from_slice(data);
+            // A cut only looks at `max_size` bytes, so this finds the same
+            // chunks as `put` would for the whole content
+            while self.buffer.len() > chunker.config().max_size {
+                let cut = chunker.cut(&self.buffer);
+                let chunk = Bytes::copy_from_slice(&self.buffer[..cut]);
+                self.buffer.drain(..cut);
+                chunks.push(chunk).await?;
+            }
+            return Ok(());
+        }
+
         if let Some(ref mut file) = self.file {
             file.write_all(data)
                 .await
@@ -360,7 +790,7 @@ pub async fn write(&mut self, data: &[u8]) -> CacheResult<()> {
 
     /// Finish writing and return content ID
     pub async fn finish(mut self, store: &BlobStore) -> CacheResult<ContentId> {
-        if let Some(mut file) = self.file.take() {
+        if let Some(file) = self.file.take() {
             file.sync_all()
                 .await
                 .map_err(|e| CacheError::Io(e.to_string()))?;
@@ -371,6 +801,19 @@ pub async fn finish(mut self, store: &BlobStore) -> CacheResult<ContentId> {
 
         let final_path = content_id.storage_path(&store.config.path);
 
+        if let Some((chunker, mut chunks)) = self.chunked.take() {
+            let rest = Bytes::from(std::mem::take(&mut self.buffer));
+            if chunks.manifest.chunks.is_empty() && rest.len() <= chunker.config().max_size {
+                write_blob(&store.config, &final_path, rest).await?;
+            } else if !final_path.exists() {
+                for chunk in chunker.chunks(&rest) {
+                    chunks.push(rest.slice_ref(chunk)).await?;
+                }
+                chunks.finish(&content_id).await?;
+            }
+            return Ok(content_id);
+        }
+
         // Create parent directory
         if let Some(parent) = final_path.parent() {
             fs::create_dir_all(parent)
@@ -427,6 +870,7 @@ async fn test_blob_store() {
             compress: true,
             compress_threshold: 10,
             verify_on_read: true,
+            chunking: None,
         };
 
         let store = BlobStore::new(config).await.unwrap();
@@ -446,4 +890,95 @@ async fn test_blob_store() {
         store.delete(&id).await.unwrap();
         assert!(!store.exists(&id).await);
     }
+
+    fn chunked_config(dir: &Path) -> BlobStoreConfig {
+        BlobStoreConfig {
+            path: dir.to_path_buf(),
+            chunking: Some(ChunkerConfig {
+                min_size: 1024,
+                avg_size: 4096,
+                max_size: 16 * 1024,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    async fn collect(stream: ByteStream) -> Vec<u8> {
+        use futures::TryStreamExt;
+        let parts: Vec<Bytes> = stream.try_collect().await.unwrap();
+        parts.concat()
+    }
+
+    #[tokio::test]
+    async fn test_large_blobs_are_chunked_and_reassembled() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(chunked_config(dir.path())).await.unwrap();
+        let data = Bytes::from(noise(200 * 1024, 1));
+
+        let id = store.put(data.clone()).await.unwrap();
+        assert_eq!(id, BlobStore::hash(&data));
+        assert!(!id.storage_path(dir.path()).exists());
+        assert!(store.exists(&id).await);
+        assert_eq!(store.list().await.unwrap(), vec![id.clone()]);
+
+        assert_eq!(store.get(&id).await.unwrap(), data);
+        assert_eq!(collect(store.get_stream(&id, None).await.unwrap()).await, data);
+        let part = collect(store.get_stream(&id, Some((50_000, 150_000))).await.unwrap()).await;
+        assert_eq!(part, data[50_000..150_000]);
+
+        // Streaming in odd pieces finds the same chunks
+        let mut writer = BlobWriter::new(&store).await.unwrap();
+        for piece in data.chunks(7_000) {
+            writer.write(piece).await.unwrap();
+        }
+        let before = shard_files(&store.config.chunk_dir()).await.unwrap().len();
+        assert_eq!(writer.finish(&store).await.unwrap(), id);
+        assert_eq!(shard_files(&store.config.chunk_dir()).await.unwrap().len(), before);
+
+        // Small content is still stored whole
+        let small = Bytes::from_static(b"small");
+        let small_id = store.put(small.clone()).await.unwrap();
+        assert!(small_id.storage_path(dir.path()).exists());
+        assert_eq!(collect(store.get_stream(&small_id, None).await.unwrap()).await, small);
+    }
+
+    #[tokio::test]
+    async fn test_similar_blobs_share_chunks_until_gc() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(chunked_config(dir.path())).await.unwrap();
+        let original = noise(400 * 1024, 2);
+        let mut edited = original.clone();
+        edited[200_000..200_010].copy_from_slice(b"0123456789");
+
+        let first = store.put(Bytes::from(original.clone())).await.unwrap();
+        let second = store.put(Bytes::from(edited.clone())).await.unwrap();
+
+        let stats = store.stats().await.unwrap();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.total_size, 800 * 1024);
+        assert!(stats.dedup_ratio() > 1.8, "ratio {}", stats.dedup_ratio());
+
+        // Dropping the first keeps every chunk the second needs
+        let keep: HashSet<ContentId> = [second.clone()].into();
+        assert!(store.gc(&keep).await.unwrap() > 0);
+        assert!(!store.exists(&first).await);
+        assert_eq!(store.get(&second).await.unwrap(), edited);
+        assert_eq!(store.stats().await.unwrap().dedup_ratio(), 1.0);
+
+        store.delete(&second).await.unwrap();
+        store.sweep_chunks().await.unwrap();
+        assert!(shard_files(&store.config.chunk_dir()).await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,1311 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! A cache in front of a storage backend
+//!
+//! [`CachedBackend`] answers metadata lookups and directory listings from the
+//! [`MetadataCache`] while they are within their TTL, and reads that ask for
+//! `use_cache` from the [`BlobStore`]. Every change made through it goes to
+//! the backend first and then drops what it made stale; changes reported by
+//! [`watch`](StorageBackend::watch) do the same. Content is evicted as the
+//! [`CachePolicy`] decides.
+//!
+//! The cache is an optimisation: when it fails, operations go to the backend
+//! as if it were not there.
+//!
+//! Pinned paths are downloaded in full and never evicted. In offline mode the
+//! backend is left alone: lookups and reads are answered from whatever is
+//! cached, expired or not, and changes are applied to the cache and queued
+//! until [`upload_queued`](CachedBackend::upload_queued) replays them.
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use cfk_core::{
+    backend::{ByteStream, FileVersion, RetryStats, SearchOptions, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeEvent, ChangeKind, ChangeStream, CfkError, CfkResult, Entry, Metadata, ResumableUpload,
+    StorageBackend, StorageCapabilities, UploadSession, VirtualPath,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::blob_store::{self, BlobStore, BlobStoreConfig, BlobWriter, ContentId};
+use crate::metadata_cache::{
+    CachedEntry, CachedEntryKind, MetadataCache, MetadataCacheConfig, QueuedChange, WriteBase,
+};
+use crate::policy::{CacheEntryInfo, CachePolicy, PolicyConfig, PINNED_PRIORITY};
+use crate::CacheResult;
+
+/// Files larger than this are read straight from the backend
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The stores cached backends keep their copies in
+///
+/// One set is shared by every backend wrapped with it, so the policy sees
+/// all of the content at once.
+pub struct CacheStores {
+    pub metadata: MetadataCache,
+    pub blobs: BlobStore,
+    policy: Mutex<CachePolicy>,
+}
+
+impl CacheStores {
+    /// Open the stores, picking up the content an earlier process cached
+    pub async fn open(
+        metadata: MetadataCacheConfig,
+        blobs: BlobStoreConfig,
+        policy: PolicyConfig,
+    ) -> CacheResult<Self> {
+        let metadata = MetadataCache::new(metadata)?;
+        let blobs = BlobStore::new(blobs).await?;
+
+        let mut policy = CachePolicy::new(policy);
+        for info in blobs.entries().await? {
+            policy.record_add(info);
+        }
+        for id in metadata.pinned_content().await? {
+            policy.set_priority(&id, PINNED_PRIORITY);
+        }
+
+        let stores = Self {
+            metadata,
+            blobs,
+            policy: Mutex::new(policy),
+        };
+        // The limits may have shrunk since
+        stores.evict().await;
+        Ok(stores)
+    }
+
+    /// Open the stores in the default cache directory
+    pub async fn open_default() -> CacheResult<Self> {
+        Self::open(
+            MetadataCacheConfig::default(),
+            BlobStoreConfig::default(),
+            PolicyConfig::default(),
+        )
+        .await
+    }
+
+    fn policy(&self) -> std::sync::MutexGuard<'_, CachePolicy> {
+        self.policy.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drop the pins at or below `path`, letting their content be evicted;
+    /// returns how many files were released
+    pub async fn unpin(&self, path: &VirtualPath) -> CacheResult<usize> {
+        let released = self.metadata.unpin(path).await?;
+        // Content may be pinned through another path, or wait for upload
+        let kept: HashSet<ContentId> = self.metadata.pinned_content().await?.into_iter().collect();
+        let released: Vec<_> = released.into_iter().filter(|id| !kept.contains(id)).collect();
+        {
+            let mut policy = self.policy();
+            for id in &released {
+                policy.set_priority(id, 0);
+            }
+        }
+        self.evict().await;
+        Ok(released.len())
+    }
+
+    /// Store `data` and account for it
+    async fn put_blob(&self, data: Bytes, pinned: bool) -> CacheResult<ContentId> {
+        let id = self.blobs.put(data).await?;
+        self.record(&id, pinned).await?;
+        Ok(id)
+    }
+
+    /// Store a stream without holding it in memory; returns the content id
+    /// and the length
+    async fn put_stream(&self, mut stream: ByteStream, pinned: bool) -> CfkResult<(ContentId, u64)> {
+        let mut writer = BlobWriter::new(&self.blobs).await?;
+        let mut len = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            writer.write(&chunk).await?;
+            len += chunk.len() as u64;
+        }
+        let id = writer.finish(&self.blobs).await?;
+        self.record(&id, pinned).await?;
+        Ok((id, len))
+    }
+
+    /// Account for a stored blob and make room
+    async fn record(&self, id: &ContentId, pinned: bool) -> CacheResult<()> {
+        let size = self.blobs.size(id).await?;
+        let priority = if pinned { PINNED_PRIORITY } else { 0 };
+        self.policy()
+            .record_add(CacheEntryInfo::new(id.clone(), size).with_priority(priority));
+        self.evict().await;
+        Ok(())
+    }
+
+    /// Pin or release a blob already stored
+    fn keep(&self, id: &ContentId, pinned: bool) {
+        let priority = if pinned { PINNED_PRIORITY } else { 0 };
+        self.policy().set_priority(id, priority);
+    }
+
+    /// The content of blob `id`, or the part of it in `range`, if it is
+    /// still there
+    async fn stream_blob(&self, id: &ContentId, range: Option<(u64, u64)>) -> Option<ByteStream> {
+        match self.blobs.get_stream(id, range).await {
+            Ok(stream) => {
+                self.policy().record_access(id);
+                Some(stream)
+            }
+            Err(e) => {
+                tracing::debug!("cached blob {}: {}", id, e);
+                self.policy().record_remove(id);
+                None
+            }
+        }
+    }
+
+    /// Delete what the policy picks until the cache is back within limits
+    ///
+    /// Entries keep pointing at evicted blobs; reading one finds the blob
+    /// gone and goes to the backend.
+    async fn evict(&self) {
+        let evicted = {
+            let mut policy = self.policy();
+            let result = policy.select_evictions();
+            for id in &result.evicted {
+                policy.record_remove(id);
+            }
+            result.evicted
+        };
+        if evicted.is_empty() {
+            return;
+        }
+        for id in evicted {
+            if let Err(e) = self.blobs.delete(&id).await {
+                tracing::debug!("evicting blob {}: {}", id, e);
+            }
+        }
+        logged("sweep", self.blobs.sweep_chunks().await);
+    }
+}
+
+/// What [`CachedBackend::pin`] did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PinReport {
+    /// Files now pinned
+    pub files: u64,
+    /// Files that had to be downloaded, and their size
+    pub downloaded: u64,
+    pub bytes: u64,
+}
+
+/// A backend behind the metadata and content caches
+pub struct CachedBackend<B: StorageBackend + ?Sized> {
+    inner: Arc<B>,
+    stores: Arc<CacheStores>,
+    capabilities: StorageCapabilities,
+    max_file_size: u64,
+    refresh: bool,
+    offline: bool,
+}
+
+impl<B: StorageBackend + ?Sized> CachedBackend<B> {
+    pub fn new(inner: Arc<B>, stores: Arc<CacheStores>) -> Self {
+        // Pinned content stays readable whatever the backend is
+        let capabilities = StorageCapabilities {
+            offline: true,
+            ..inner.capabilities().clone()
+        };
+        Self {
+            inner,
+            stores,
+            capabilities,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            refresh: false,
+            offline: false,
+        }
+    }
+
+    /// Keep the content of files up to `size` bytes
+    pub fn with_max_file_size(mut self, size: u64) -> Self {
+        self.max_file_size = size;
+        self
+    }
+
+    /// Ask the backend every time, only updating the cache with the answers;
+    /// file content is read straight from the backend
+    ///
+    /// For callers that must see the current state, like sync, while still
+    /// keeping the cache coherent for everyone else.
+    pub fn refreshing(mut self) -> Self {
+        self.refresh = true;
+        self
+    }
+
+    /// Leave the backend alone: answer from the cache and queue changes
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self.capabilities.range_reads = true;
+        self.capabilities.resumable_uploads = false;
+        self.capabilities.search = false;
+        self.capabilities.versioning = false;
+        self.capabilities.watch = false;
+        self
+    }
+
+    pub fn inner(&self) -> &Arc<B> {
+        &self.inner
+    }
+
+    /// Download `path`, with everything below it for a directory, and keep
+    /// it until it is unpinned
+    ///
+    /// Pinning again fetches what changed since.
+    pub async fn pin(&self, path: &VirtualPath) -> CfkResult<PinReport> {
+        let metadata = &self.stores.metadata;
+        let root = self.inner.get_metadata(path).await?;
+        metadata.pin(path).await?;
+        metadata.put_entry(&root).await?;
+
+        let mut report = PinReport::default();
+        let mut pending = vec![root];
+        while let Some(entry) = pending.pop() {
+            if entry.is_directory() {
+                let entries = self.list_all(&entry.path).await?;
+                metadata.put_directory(&entry.path, &entries).await?;
+                pending.extend(entries);
+            } else if entry.is_file() {
+                if let Some(bytes) = self.pin_file(&entry).await? {
+                    report.downloaded += 1;
+                    report.bytes += bytes;
+                }
+                report.files += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Make sure the content of `entry` is stored and kept; returns the
+    /// size when it had to be downloaded
+    async fn pin_file(&self, entry: &Entry) -> CfkResult<Option<u64>> {
+        // Caching the entry kept the content of an unchanged file
+        let cached = self.stores.metadata.get_entry_offline(&entry.path).await?;
+        if let Some(id) = cached.and_then(|c| c.content()) {
+            if self.stores.blobs.exists(&id).await {
+                self.stores.keep(&id, true);
+                return Ok(None);
+            }
+        }
+
+        let stream = self.inner.read_file(&entry.path, &ReadOptions::default()).await?;
+        let (id, len) = self.stores.put_stream(stream, true).await?;
+        self.stores.metadata.put_content(entry, &id).await?;
+        Ok(Some(len))
+    }
+
+    /// Replay the changes queued offline, oldest first; returns how many
+    /// the backend took
+    ///
+    /// Stops at the first change the backend refuses, leaving it and the
+    /// ones after it queued.
+    pub async fn upload_queued(&self) -> CfkResult<usize> {
+        let metadata = &self.stores.metadata;
+        let queued = metadata.queued_changes(self.inner.id()).await?;
+
+        let mut uploaded = 0;
+        for (seq, change) in queued {
+            self.replay(&change).await?;
+            metadata.dequeue(self.inner.id(), seq).await?;
+            uploaded += 1;
+        }
+        Ok(uploaded)
+    }
+
+    /// Changes waiting for [`upload_queued`](Self::upload_queued)
+    pub async fn queued(&self) -> CfkResult<Vec<QueuedChange>> {
+        let queued = self.stores.metadata.queued_changes(self.inner.id()).await?;
+        Ok(queued.into_iter().map(|(_, change)| change).collect())
+    }
+
+    /// Apply a queued change to the backend; the cache already shows it
+    async fn replay(&self, change: &QueuedChange) -> CfkResult<()> {
+        match change {
+            QueuedChange::Write { path, content_id, base } => {
+                if let Some(base) = base {
+                    let current = match self.inner.get_metadata(path).await {
+                        Ok(entry) => Some(entry),
+                        Err(CfkError::NotFound(_)) => None,
+                        Err(e) => return Err(e),
+                    };
+                    if !base.matches(current.as_ref()) {
+                        return Err(CfkError::Conflict(format!(
+                            "{} changed on the backend since it was edited offline",
+                            path
+                        )));
+                    }
+                }
+                let id = ContentId::from_hex(content_id)?;
+                let data = self.stores.blobs.get(&id).await?;
+                let options = WriteOptions {
+                    overwrite: true,
+                    create_parents: true,
+                    ..Default::default()
+                };
+                let entry = self.inner.write_file(path, data, &options).await?;
+                self.stores.keep(&id, self.stores.metadata.is_pinned(path));
+                logged("caching", self.stores.metadata.put_content(&entry, &id).await);
+            }
+            QueuedChange::CreateDirectory { path } => {
+                match self.inner.create_directory(path).await {
+                    Ok(_) | Err(CfkError::AlreadyExists(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            QueuedChange::Delete { path, recursive } => {
+                let options = DeleteOptions {
+                    recursive: *recursive,
+                    force: false,
+                };
+                match self.inner.delete(path, &options).await {
+                    Ok(()) | Err(CfkError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            QueuedChange::Rename { from, to } => {
+                let options = MoveOptions { overwrite: true };
+                self.inner.rename(from, to, &options).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// What the cache knows about `path`, however old
+    ///
+    /// A path missing from a cached listing of its directory does not
+    /// exist; anything else unknown is [`CfkError::OfflineNoCache`].
+    async fn offline_entry(&self, path: &VirtualPath) -> CfkResult<CachedEntry> {
+        let metadata = &self.stores.metadata;
+        if let Some(cached) = metadata.get_entry_offline(path).await? {
+            return Ok(cached);
+        }
+        if metadata.get_directory_offline(path).await?.is_some() {
+            let entry = Entry::directory(path.clone(), Metadata::new());
+            return Ok(CachedEntry::from_entry(&entry, None));
+        }
+        match path.parent() {
+            Some(parent) if metadata.get_directory_offline(&parent).await?.is_some() => {
+                Err(CfkError::NotFound(path.to_string()))
+            }
+            _ => Err(CfkError::OfflineNoCache),
+        }
+    }
+
+    /// Store a file's new content and queue it for upload
+    async fn write_offline(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if !options.overwrite && self.offline_entry(path).await.is_ok() {
+            return Err(CfkError::AlreadyExists(path.to_string()));
+        }
+
+        let (id, len) = self.stores.put_stream(stream, true).await?;
+        let metadata = Metadata::new().with_size(len).with_modified(Utc::now());
+        let entry = Entry::file(path.clone(), metadata);
+        self.queue_write(path, &id).await?;
+        self.stores.metadata.put_local(&entry, Some(&id)).await?;
+        Ok(entry)
+    }
+
+    /// Queue `id` for upload as the content of `path`
+    ///
+    /// It takes the place of an upload of `path` still queued, so both are
+    /// checked against what the backend had before the first.
+    async fn queue_write(&self, path: &VirtualPath, id: &ContentId) -> CfkResult<()> {
+        let metadata = &self.stores.metadata;
+        let queued = metadata.queued_changes(self.inner.id()).await?;
+        let (replaced, base) = match queued.iter().rev().find(|(_, c)| c.touches(path)) {
+            Some((seq, QueuedChange::Write { path: written, base, .. })) if written == path => {
+                (Some(*seq), base.clone())
+            }
+            // Moved or deleted offline first: nothing to compare against
+            Some(_) => (None, None),
+            None => (None, self.write_base(path).await?),
+        };
+        self.queue(QueuedChange::Write {
+            path: path.clone(),
+            content_id: id.to_hex(),
+            base,
+        })
+        .await?;
+        if let Some(seq) = replaced {
+            metadata.dequeue(self.inner.id(), seq).await?;
+        }
+        Ok(())
+    }
+
+    /// What the backend had at `path` when last seen, if known
+    async fn write_base(&self, path: &VirtualPath) -> CfkResult<Option<WriteBase>> {
+        match self.offline_entry(path).await {
+            Ok(cached) if cached.kind == CachedEntryKind::File => Ok(Some(WriteBase::of(&cached))),
+            Err(CfkError::NotFound(_)) => Ok(Some(WriteBase::Missing)),
+            Ok(_) | Err(CfkError::OfflineNoCache) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn queue(&self, change: QueuedChange) -> CfkResult<()> {
+        Ok(self.stores.metadata.queue_change(self.inner.id(), &change).await?)
+    }
+
+    async fn cached_entry(&self, path: &VirtualPath) -> Option<crate::CachedEntry> {
+        if self.refresh {
+            return None;
+        }
+        logged("lookup", self.stores.metadata.get_entry(path).await).flatten()
+    }
+
+    /// Drop what a change made through this backend made stale
+    async fn forget(&self, path: &VirtualPath, kind: ChangeKind) {
+        let change = ChangeEvent::new(path.clone(), kind);
+        logged("invalidation", self.stores.metadata.apply_change(&change).await);
+    }
+
+    async fn remember(&self, entry: &Entry) {
+        logged("caching", self.stores.metadata.put_entry(entry).await);
+    }
+
+    /// Keep `data` as the content of `entry`
+    async fn remember_content(&self, entry: &Entry, data: Bytes) {
+        let pinned = self.stores.metadata.is_pinned(&entry.path);
+        let Some(id) = logged("caching content", self.stores.put_blob(data, pinned).await) else {
+            return;
+        };
+        logged(
+            "caching",
+            self.stores.metadata.put_content(entry, &id).await,
+        );
+    }
+
+    /// All of a directory, following the backend's pages to the end
+    async fn list_all(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        let mut options = ListOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let mut listing = self.inner.list_directory(path, &options).await?;
+        while listing.has_more {
+            let Some(cursor) = listing.cursor.take() else {
+                break;
+            };
+            options.cursor = Some(cursor);
+            let page = self.inner.list_directory(path, &options).await?;
+            listing.entries.extend(page.entries);
+            listing.cursor = page.cursor;
+            listing.has_more = page.has_more;
+        }
+        Ok(listing.entries)
+    }
+
+    fn online(&self) -> CfkResult<()> {
+        if self.offline {
+            return Err(CfkError::OfflineNoCache);
+        }
+        Ok(())
+    }
+
+    fn uploads(&self) -> CfkResult<&dyn ResumableUpload> {
+        self.inner
+            .resumable()
+            .ok_or_else(|| CfkError::Unsupported("Resumable uploads not supported".into()))
+    }
+
+    /// Read a whole file from the backend into the cache
+    async fn fetch(&self, entry: &Entry) -> CfkResult<Bytes> {
+        let stream = self
+            .inner
+            .read_file(&entry.path, &ReadOptions::default())
+            .await?;
+        let data = stream
+            .try_fold(BytesMut::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await?
+            .freeze();
+        self.remember_content(entry, data.clone()).await;
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + ?Sized> StorageBackend for CachedBackend<B> {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn display_name(&self) -> &str {
+        self.inner.display_name()
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
+
+    async fn is_available(&self) -> bool {
+        self.offline || self.inner.is_available().await
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        if self.offline {
+            return Ok(self.offline_entry(path).await?.to_entry());
+        }
+        if let Some(cached) = self.cached_entry(path).await {
+            return Ok(cached.to_entry());
+        }
+        let entry = self.inner.get_metadata(path).await?;
+        self.remember(&entry).await;
+        Ok(entry)
+    }
+
+    async fn fresh_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        // Offline, the cache is all there is
+        if self.offline {
+            return self.get_metadata(path).await;
+        }
+        let entry = self.inner.get_metadata(path).await?;
+        self.remember(&entry).await;
+        Ok(entry)
+    }
+
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let metadata = &self.stores.metadata;
+        // Only whole, flat listings are cached
+        if options.recursive {
+            if self.offline {
+                return Err(CfkError::OfflineNoCache);
+            }
+            return self.inner.list_directory(path, options).await;
+        }
+        if !self.offline && (options.limit.is_some() || options.cursor.is_some()) {
+            return self.inner.list_directory(path, options).await;
+        }
+
+        let cached = if self.offline {
+            Some(metadata.get_directory_offline(path).await?.ok_or(CfkError::OfflineNoCache)?)
+        } else if self.refresh {
+            None
+        } else {
+            logged("lookup", metadata.get_directory(path).await).flatten()
+        };
+        let mut entries = match cached {
+            Some(entries) => entries,
+            None => {
+                let entries = self.list_all(path).await?;
+                logged("caching", metadata.put_directory(path, &entries).await);
+                entries
+            }
+        };
+
+        if !options.include_hidden {
+            entries.retain(|e| !e.name().is_some_and(|n| n.starts_with('.')));
+        }
+        Ok(DirectoryListing::new(path.clone(), entries))
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        if self.offline {
+            let cached = self.offline_entry(path).await?;
+            let stream = match cached.content() {
+                Some(id) => self.stores.stream_blob(&id, options.range).await,
+                None => None,
+            };
+            return stream.ok_or(CfkError::OfflineNoCache);
+        }
+        if !options.use_cache || self.refresh {
+            return self.inner.read_file(path, options).await;
+        }
+
+        let cached = self.cached_entry(path).await;
+        if let Some(id) = cached.as_ref().and_then(|c| c.content()) {
+            if let Some(stream) = self.stores.stream_blob(&id, options.range).await {
+                return Ok(stream);
+            }
+        }
+
+        let entry = match cached {
+            Some(cached) => cached.to_entry(),
+            None => self.get_metadata(path).await?,
+        };
+        let fits = entry
+            .metadata
+            .size
+            .is_some_and(|size| size <= self.max_file_size);
+        if !entry.is_file() || !fits {
+            return self.inner.read_file(path, options).await;
+        }
+        let data = self.fetch(&entry).await?;
+        Ok(ranged(data, options.range))
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if self.offline {
+            let stream = Box::pin(stream::once(async move { Ok(data) }));
+            return self.write_offline(path, stream, options).await;
+        }
+        let size = data.len() as u64;
+        let entry = self.inner.write_file(path, data.clone(), options).await?;
+        self.forget(path, ChangeKind::Modified).await;
+        // What was just written is the content, as long as the backend
+        // stored all of it
+        if size <= self.max_file_size && entry.metadata.size.is_none_or(|s| s == size) {
+            self.remember_content(&entry, data).await;
+        }
+        Ok(entry)
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if self.offline {
+            return self.write_offline(path, stream, options).await;
+        }
+        let entry = self
+            .inner
+            .write_file_stream(path, stream, size_hint, options)
+            .await?;
+        self.forget(path, ChangeKind::Modified).await;
+        self.remember(&entry).await;
+        Ok(entry)
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        if self.offline {
+            if self.offline_entry(path).await.is_ok() {
+                return Err(CfkError::AlreadyExists(path.to_string()));
+            }
+            let entry = Entry::directory(path.clone(), Metadata::new().with_modified(Utc::now()));
+            let metadata = &self.stores.metadata;
+            metadata.put_local(&entry, None).await?;
+            metadata.put_directory(path, &[]).await?;
+            self.queue(QueuedChange::CreateDirectory { path: path.clone() }).await?;
+            return Ok(entry);
+        }
+        let entry = self.inner.create_directory(path).await?;
+        self.forget(path, ChangeKind::Created).await;
+        Ok(entry)
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if self.offline {
+            self.offline_entry(path).await?;
+            self.stores.metadata.remove_local(path).await?;
+            return self
+                .queue(QueuedChange::Delete {
+                    path: path.clone(),
+                    recursive: options.recursive,
+                })
+                .await;
+        }
+        let result = self.inner.delete(path, options).await;
+        // A recursive delete may have got partway
+        self.forget(path, ChangeKind::Deleted).await;
+        result
+    }
+
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        if self.offline {
+            let cached = self.offline_entry(source).await?;
+            let Some(id) = cached.content().filter(|_| cached.kind == CachedEntryKind::File) else {
+                return Err(CfkError::Unsupported("Copying directories offline".into()));
+            };
+            if !options.overwrite && self.offline_entry(dest).await.is_ok() {
+                return Err(CfkError::AlreadyExists(dest.to_string()));
+            }
+            let mut entry = cached.to_entry();
+            entry.path = dest.clone();
+            self.stores.keep(&id, true);
+            self.queue_write(dest, &id).await?;
+            self.stores.metadata.put_local(&entry, Some(&id)).await?;
+            return Ok(entry);
+        }
+        let entry = self.inner.copy(source, dest, options).await?;
+        // Whatever the copy replaced is gone
+        self.forget(dest, ChangeKind::Deleted).await;
+        Ok(entry)
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        if self.offline {
+            self.offline_entry(source).await?;
+            if !options.overwrite && self.offline_entry(dest).await.is_ok() {
+                return Err(CfkError::AlreadyExists(dest.to_string()));
+            }
+            self.stores.metadata.move_local(source, dest).await?;
+            self.queue(QueuedChange::Rename {
+                from: source.clone(),
+                to: dest.clone(),
+            })
+            .await?;
+            return Ok(self.offline_entry(dest).await?.to_entry());
+        }
+        let entry = self.inner.rename(source, dest, options).await?;
+        self.forget(dest, ChangeKind::Deleted).await;
+        self.forget(
+            dest,
+            ChangeKind::Renamed {
+                from: source.clone(),
+            },
+        )
+        .await;
+        Ok(entry)
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        self.online()?;
+        self.inner.get_space_info().await
+    }
+
+    async fn search(&self, options: &SearchOptions) -> CfkResult<Vec<Entry>> {
+        self.online()?;
+        self.inner.search(options).await
+    }
+
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        self.online()?;
+        self.inner.get_versions(path).await
+    }
+
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        self.online()?;
+        self.inner.get_version(path, version_id).await
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        if self.offline {
+            return None;
+        }
+        self.inner.resumable().map(|_| self as &dyn ResumableUpload)
+    }
+
+    fn retry_stats(&self) -> Option<RetryStats> {
+        self.inner.retry_stats()
+    }
+
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        if self.offline {
+            return Ok(false);
+        }
+        self.inner.refresh_credentials().await
+    }
+
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        if self.offline {
+            return Err(CfkError::Unsupported("Setting metadata offline".into()));
+        }
+        self.inner.set_metadata(path, metadata).await?;
+        self.forget(path, ChangeKind::Modified).await;
+        Ok(())
+    }
+
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        self.online()?;
+        let changes = self.inner.watch(path, recursive).await?;
+        let stores = self.stores.clone();
+        Ok(Box::pin(changes.then(move |change| {
+            let stores = stores.clone();
+            async move {
+                if let Ok(change) = &change {
+                    logged("invalidation", stores.metadata.apply_change(change).await);
+                }
+                change
+            }
+        })))
+    }
+}
+
+/// Uploads go to the backend's sessions; finishing one drops the stale entry
+#[async_trait]
+impl<B: StorageBackend + ?Sized> ResumableUpload for CachedBackend<B> {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        self.uploads()?.create_session(path, size, options).await
+    }
+
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        self.uploads()?.upload_chunk(session, data).await
+    }
+
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+        self.uploads()?.query_offset(session).await
+    }
+
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        let entry = self.uploads()?.finalize(session).await?;
+        self.forget(&session.path, ChangeKind::Modified).await;
+        self.remember(&entry).await;
+        Ok(entry)
+    }
+
+    async fn abort(&self, session: &UploadSession) -> CfkResult<()> {
+        self.uploads()?.abort(session).await
+    }
+}
+
+/// `data`, or the part of it in `range`
+fn ranged(data: Bytes, range: Option<(u64, u64)>) -> ByteStream {
+    let data = blob_store::slice(data, range);
+    Box::pin(stream::once(async move { Ok(data) }))
+}
+
+/// The value of a cache operation, or `None` after logging why it failed
+fn logged<T>(what: &str, result: CacheResult<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            tracing::debug!("cache {}: {}", what, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+
+    /// Files in memory, counting the calls that would go over the network
+    #[derive(Default)]
+    struct Remote {
+        files: Mutex<BTreeMap<String, Bytes>>,
+        capabilities: StorageCapabilities,
+        calls: AtomicUsize,
+    }
+
+    impl Remote {
+        fn with_files(files: &[(&str, &str)]) -> Arc<Self> {
+            let remote = Self::default();
+            for (path, data) in files {
+                let data = Bytes::copy_from_slice(data.as_bytes());
+                remote.files.lock().unwrap().insert(path.to_string(), data);
+            }
+            Arc::new(remote)
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn call(&self) {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+        }
+
+        /// Directories exist as long as there are files in them
+        fn entry(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            let files = self.files.lock().unwrap();
+            if let Some(data) = files.get(&path.to_path_string()) {
+                let mut metadata = Metadata::new().with_size(data.len() as u64);
+                metadata.content_hash = Some(blake3::hash(data).to_hex().to_string());
+                return Ok(Entry::file(path.clone(), metadata));
+            }
+            if files.keys().any(|file| VirtualPath::new("mem", file).starts_with(path)) {
+                return Ok(Entry::directory(path.clone(), Metadata::new()));
+            }
+            Err(CfkError::NotFound(path.to_string()))
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for Remote {
+        fn id(&self) -> &str {
+            "mem"
+        }
+
+        fn display_name(&self) -> &str {
+            "Memory"
+        }
+
+        fn capabilities(&self) -> &StorageCapabilities {
+            &self.capabilities
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            self.call();
+            self.entry(path)
+        }
+
+        async fn list_directory(
+            &self,
+            path: &VirtualPath,
+            _options: &ListOptions,
+        ) -> CfkResult<DirectoryListing> {
+            self.call();
+            let files: Vec<String> = self.files.lock().unwrap().keys().cloned().collect();
+            let mut children: Vec<VirtualPath> = files
+                .iter()
+                .map(|file| VirtualPath::new("mem", file))
+                .filter(|file| file.starts_with(path) && file != path)
+                .map(|file| VirtualPath {
+                    segments: file.segments[..=path.segments.len()].to_vec(),
+                    ..file
+                })
+                .collect();
+            children.dedup();
+            let entries = children.iter().map(|child| self.entry(child)).collect::<CfkResult<_>>()?;
+            Ok(DirectoryListing::new(path.clone(), entries))
+        }
+
+        async fn read_file(&self, path: &VirtualPath, _options: &ReadOptions) -> CfkResult<ByteStream> {
+            self.call();
+            let data = self.files.lock().unwrap().get(&path.to_path_string()).cloned();
+            let data = data.ok_or_else(|| CfkError::NotFound(path.to_string()))?;
+            Ok(Box::pin(stream::once(async move { Ok(data) })))
+        }
+
+        async fn write_file(
+            &self,
+            path: &VirtualPath,
+            data: Bytes,
+            _options: &WriteOptions,
+        ) -> CfkResult<Entry> {
+            self.call();
+            self.files.lock().unwrap().insert(path.to_path_string(), data);
+            self.entry(path)
+        }
+
+        async fn write_file_stream(
+            &self,
+            _path: &VirtualPath,
+            _stream: ByteStream,
+            _size_hint: Option<u64>,
+            _options: &WriteOptions,
+        ) -> CfkResult<Entry> {
+            Err(CfkError::Unsupported("streams".into()))
+        }
+
+        async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            Ok(Entry::directory(path.clone(), Metadata::new()))
+        }
+
+        async fn delete(&self, path: &VirtualPath, _options: &DeleteOptions) -> CfkResult<()> {
+            self.call();
+            self.files.lock().unwrap().remove(&path.to_path_string());
+            Ok(())
+        }
+
+        async fn copy(
+            &self,
+            _source: &VirtualPath,
+            _dest: &VirtualPath,
+            _options: &CopyOptions,
+        ) -> CfkResult<Entry> {
+            Err(CfkError::Unsupported("copy".into()))
+        }
+
+        async fn rename(
+            &self,
+            source: &VirtualPath,
+            dest: &VirtualPath,
+            _options: &MoveOptions,
+        ) -> CfkResult<Entry> {
+            self.call();
+            let mut files = self.files.lock().unwrap();
+            let moved: Vec<String> = files
+                .keys()
+                .filter(|file| VirtualPath::new("mem", file).starts_with(source))
+                .cloned()
+                .collect();
+            if moved.is_empty() {
+                return Err(CfkError::NotFound(source.to_string()));
+            }
+            let (from, to) = (source.to_path_string(), dest.to_path_string());
+            for file in moved {
+                let data = files.remove(&file).unwrap();
+                files.insert(file.replacen(&from, &to, 1), data);
+            }
+            drop(files);
+            self.entry(dest)
+        }
+
+        async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+            Ok(SpaceInfo::unknown())
+        }
+    }
+
+    async fn stores(dir: &TempDir, max_size: u64) -> Arc<CacheStores> {
+        let stores = CacheStores::open(
+            MetadataCacheConfig {
+                db_path: dir.path().join("metadata"),
+                ..Default::default()
+            },
+            BlobStoreConfig {
+                path: dir.path().join("blobs"),
+                ..Default::default()
+            },
+            PolicyConfig {
+                max_size,
+                min_ttl: 0,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        Arc::new(stores)
+    }
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("mem", p)
+    }
+
+    fn names(listing: &DirectoryListing) -> Vec<&str> {
+        listing.entries.iter().filter_map(|e| e.name()).collect()
+    }
+
+    async fn read(backend: &dyn StorageBackend, p: &str, range: Option<(u64, u64)>) -> Bytes {
+        let options = ReadOptions {
+            range,
+            use_cache: true,
+        };
+        let chunks: Vec<Bytes> = backend
+            .read_file(&path(p), &options)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat().into()
+    }
+
+    #[tokio::test]
+    async fn test_listings_and_metadata_come_from_cache() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[("/docs/a.txt", "alpha"), ("/docs/.hidden", "h")]);
+        let cached = CachedBackend::new(remote.clone(), stores(&dir, 1 << 20).await);
+
+        let listing = cached
+            .list_directory(&path("/docs"), &ListOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&listing), ["a.txt"]);
+        assert_eq!(remote.calls(), 1);
+
+        let all = ListOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let listing = cached.list_directory(&path("/docs"), &all).await.unwrap();
+        assert_eq!(names(&listing), [".hidden", "a.txt"]);
+        let entry = cached.get_metadata(&path("/docs/a.txt")).await.unwrap();
+        assert_eq!(entry.metadata.size, Some(5));
+        assert_eq!(remote.calls(), 1);
+
+        // Refreshing asks the backend but leaves the answer for others
+        let refreshing = CachedBackend::new(remote.clone(), cached.stores.clone()).refreshing();
+        refreshing.get_metadata(&path("/docs/a.txt")).await.unwrap();
+        assert_eq!(remote.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_changes_invalidate_and_write_through() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[("/docs/a.txt", "alpha")]);
+        let cached = CachedBackend::new(remote.clone(), stores(&dir, 1 << 20).await);
+        let (docs, options) = (path("/docs"), ListOptions::default());
+        let list = || cached.list_directory(&docs, &options);
+
+        list().await.unwrap();
+        let written = Bytes::from_static(b"bravo!");
+        cached
+            .write_file(&path("/docs/b.txt"), written.clone(), &WriteOptions::default())
+            .await
+            .unwrap();
+        let calls = remote.calls();
+        // The write's content is cached, but the listing went stale
+        assert_eq!(read(&cached, "/docs/b.txt", None).await, written);
+        assert_eq!(remote.calls(), calls);
+        assert_eq!(names(&list().await.unwrap()), ["a.txt", "b.txt"]);
+        assert_eq!(remote.calls(), calls + 1);
+
+        cached
+            .rename(&path("/docs/a.txt"), &path("/docs/c.txt"), &MoveOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&list().await.unwrap()), ["b.txt", "c.txt"]);
+        assert!(cached.get_metadata(&path("/docs/a.txt")).await.is_err());
+
+        cached
+            .delete(&path("/docs/b.txt"), &DeleteOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&list().await.unwrap()), ["c.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_reads_come_from_blobs_until_evicted() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[("/a.txt", "hello world"), ("/b.txt", "goodbye world")]);
+        // Room for one of the files at a time
+        let cached = CachedBackend::new(remote.clone(), stores(&dir, 16).await);
+
+        assert_eq!(read(&cached, "/a.txt", Some((6, 100))).await, "world");
+        let calls = remote.calls();
+        assert_eq!(read(&cached, "/a.txt", None).await, "hello world");
+        assert_eq!(read(&cached, "/a.txt", Some((20, 30))).await, "");
+        assert_eq!(remote.calls(), calls);
+
+        // Caching the second file pushes the first one out
+        assert_eq!(read(&cached, "/b.txt", None).await, "goodbye world");
+        let calls = remote.calls();
+        assert_eq!(read(&cached, "/a.txt", None).await, "hello world");
+        assert_eq!(remote.calls(), calls + 1);
+
+        // Reads that do not ask for the cache go to the backend
+        let calls = remote.calls();
+        let _ = cached
+            .read_file(&path("/a.txt"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(remote.calls(), calls + 1);
+    }
+    #[tokio::test]
+    async fn test_pinned_content_works_offline_and_changes_queue() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[
+            ("/docs/a.txt", "alpha"),
+            ("/docs/sub/b.txt", "bravo"),
+            ("/other.txt", "not pinned"),
+        ]);
+        // Too small to keep anything but pinned content
+        let stores = stores(&dir, 4).await;
+        let online = CachedBackend::new(remote.clone(), stores.clone());
+
+        let report = online.pin(&path("/docs")).await.unwrap();
+        assert_eq!((report.files, report.downloaded, report.bytes), (2, 2, 10));
+        assert_eq!(read(&online, "/other.txt", None).await, "not pinned");
+        // Pinning again only looks for changes
+        assert_eq!(online.pin(&path("/docs")).await.unwrap().downloaded, 0);
+
+        let offline = CachedBackend::new(remote.clone(), stores.clone()).offline();
+        let calls = remote.calls();
+        let docs = offline
+            .list_directory(&path("/docs"), &ListOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&docs), ["a.txt", "sub"]);
+        assert_eq!(read(&offline, "/docs/sub/b.txt", Some((1, 3))).await, "ra");
+        assert!(matches!(
+            offline.read_file(&path("/other.txt"), &ReadOptions::default()).await,
+            Err(CfkError::OfflineNoCache)
+        ));
+        assert!(matches!(
+            offline.get_metadata(&path("/docs/missing")).await,
+            Err(CfkError::NotFound(_))
+        ));
+
+        offline
+            .write_file(&path("/docs/c.txt"), Bytes::from_static(b"charlie"), &WriteOptions::default())
+            .await
+            .unwrap();
+        offline
+            .rename(&path("/docs/sub"), &path("/docs/moved"), &MoveOptions::default())
+            .await
+            .unwrap();
+        offline
+            .delete(&path("/docs/a.txt"), &DeleteOptions::default())
+            .await
+            .unwrap();
+        let docs = offline
+            .list_directory(&path("/docs"), &ListOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&docs), ["c.txt", "moved"]);
+        assert_eq!(read(&offline, "/docs/moved/b.txt", None).await, "bravo");
+        assert_eq!(read(&offline, "/docs/c.txt", None).await, "charlie");
+        assert_eq!(remote.calls(), calls);
+        assert_eq!(offline.queued().await.unwrap().len(), 3);
+
+        // Back online, the changes go up in order
+        assert_eq!(online.upload_queued().await.unwrap(), 3);
+        let files: Vec<String> = remote.files.lock().unwrap().keys().cloned().collect();
+        assert_eq!(files, ["/docs/c.txt", "/docs/moved/b.txt", "/other.txt"]);
+        assert!(online.queued().await.unwrap().is_empty());
+
+        assert_eq!(stores.unpin(&path("/docs")).await.unwrap(), 2);
+        assert!(stores.unpin(&path("/docs")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_queued_writes_do_not_overwrite_changes_made_elsewhere() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[("/docs/a.txt", "alpha"), ("/docs/b.txt", "bravo")]);
+        let stores = stores(&dir, 1 << 20).await;
+        let online = CachedBackend::new(remote.clone(), stores.clone());
+        online.pin(&path("/docs")).await.unwrap();
+
+        let offline = CachedBackend::new(remote.clone(), stores.clone()).offline();
+        let overwrite = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        for (p, data) in [("/docs/b.txt", "draft"), ("/docs/b.txt", "final"), ("/docs/a.txt", "mine!")] {
+            offline
+                .write_file(&path(p), Bytes::from(data), &overwrite)
+                .await
+                .unwrap();
+        }
+        // Someone else edits the file in the meantime, keeping its size
+        remote.files.lock().unwrap().insert("/docs/a.txt".into(), Bytes::from_static(b"other"));
+
+        let result = online.upload_queued().await;
+        assert!(matches!(result, Err(CfkError::Conflict(_))), "{:?}", result);
+        let files = remote.files.lock().unwrap().clone();
+        assert_eq!(files["/docs/a.txt"], "other");
+        assert_eq!(files["/docs/b.txt"], "final");
+        assert_eq!(online.queued().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pinning_a_missing_path_pins_nothing() {
+        let dir = TempDir::new().unwrap();
+        let remote = Remote::with_files(&[("/docs/a.txt", "alpha")]);
+        let stores = stores(&dir, 1 << 20).await;
+        let cached = CachedBackend::new(remote, stores.clone());
+
+        let result = cached.pin(&path("/dosc")).await;
+        assert!(matches!(result, Err(CfkError::NotFound(_))), "{:?}", result);
+        assert!(!stores.metadata.is_pinned(&path("/dosc")));
+    }
+}
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Content-defined chunking
+//!
+//! Splits data where its content says so rather than at fixed offsets, so an
+//! edit only changes the chunks around it and the rest deduplicate against
+//! the previous version. This is FastCDC: a gear rolling hash with
+//! normalized chunking, which uses a stricter mask before the average size
+//! and a looser one after it to keep chunk sizes close to the average.
+
+/// Chunk size limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkerConfig {
+    /// No chunk is cut shorter than this, except the last one
+    pub min_size: usize,
+    /// Chunk size to aim for; rounded to a power of two
+    pub avg_size: usize,
+    /// Chunks are cut here when the content gives no boundary
+    pub max_size: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 64 * 1024,
+            avg_size: 256 * 1024,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Finds chunk boundaries
+#[derive(Debug, Clone)]
+pub struct Chunker {
+    config: ChunkerConfig,
+    /// Used before the average size: boundaries are rarer
+    mask_small: u64,
+    /// Used after it: boundaries are more likely
+    mask_large: u64,
+}
+
+impl Chunker {
+    pub fn new(config: ChunkerConfig) -> Self {
+        let bits = config.avg_size.max(16).ilog2();
+        Self {
+            config,
+            mask_small: top_bits(bits + 2),
+            mask_large: top_bits(bits - 2),
+        }
+    }
+
+    pub fn config(&self) -> &ChunkerConfig {
+        &self.config
+    }
+
+    /// Length of the first chunk of `data`
+    ///
+    /// Only the first `max_size` bytes are looked at, so a stream can be cut
+    /// as soon as that much is buffered and gets the same chunks as the
+    /// whole data would.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let ChunkerConfig { min_size, avg_size, max_size } = self.config;
+        let end = data.len().min(max_size);
+        if end <= min_size {
+            return end;
+        }
+        let normal = end.min(avg_size);
+
+        let mut hash = 0u64;
+        let mut i = min_size;
+        while i < normal {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_small == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        while i < end {
+            hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+            if hash & self.mask_large == 0 {
+                return i + 1;
+            }
+            i += 1;
+        }
+        end
+    }
+
+    /// All chunks of `data`, in order
+    pub fn chunks<'a>(&'a self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let (chunk, rest) = data.split_at(self.cut(data));
+            data = rest;
+            Some(chunk)
+        })
+    }
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::new(ChunkerConfig::default())
+    }
+}
+
+/// A mask of the `n` highest bits; the gear hash shifts left, so those are
+/// the ones that depend on the most recent bytes
+fn top_bits(n: u32) -> u64 {
+    if n == 0 {
+        0
+    } else {
+        u64::MAX << (64 - n.min(64))
+    }
+}
+
+/// Random values per byte for the gear hash
+///
+/// Generated with splitmix64 from a fixed seed. Changing them moves every
+/// boundary and defeats deduplication against what is already stored.
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0x6366_6b2d_6364_6331u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_respect_limits_and_cover_the_data() {
+        let chunker = Chunker::default();
+        let data = noise(8 * 1024 * 1024, 1);
+        let chunks: Vec<&[u8]> = chunker.chunks(&data).collect();
+
+        assert_eq!(chunks.concat(), data);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(last.len() <= chunker.config().max_size);
+        for chunk in rest {
+            assert!(chunk.len() >= chunker.config().min_size);
+            assert!(chunk.len() <= chunker.config().max_size);
+        }
+        // Normalized chunking keeps the count near size / average
+        assert!((16..=64).contains(&chunks.len()), "{} chunks", chunks.len());
+    }
+
+    #[test]
+    fn test_an_edit_only_changes_nearby_chunks() {
+        let chunker = Chunker::default();
+        let before = noise(4 * 1024 * 1024, 2);
+        let mut after = before.clone();
+        after.splice(2_000_000..2_000_000, b"inserted".iter().copied());
+
+        let old: std::collections::HashSet<&[u8]> = chunker.chunks(&before).collect();
+        let new: Vec<&[u8]> = chunker.chunks(&after).collect();
+        let changed = new.iter().filter(|chunk| !old.contains(*chunk)).count();
+        assert!(changed <= 2, "{} of {} chunks changed", changed, new.len());
+    }
+}
@@ -2,9 +2,12 @@
 //!
 //! Features:
 //! - Content-addressed blob storage with BLAKE3 hashing
+//! - Content-defined chunking, so large files that differ a little share
+//!   most of their storage
 //! - LZ4 compression for efficient storage
 //! - Metadata caching with TTL support
 //! - Multiple eviction policies (LRU, LFU, FIFO, etc.)
+//! - [`CachedBackend`], which puts all of the above in front of a backend
 //!
 //! Supports multiple backends:
 //! - sled: Pure Rust embedded KV (default)
@@ -21,12 +24,16 @@
 use thiserror::Error;
 
 pub mod blob_store;
+pub mod cached_backend;
+pub mod chunker;
 pub mod metadata_cache;
 pub mod policy;
 
 pub use blob_store::{BlobStore, BlobStoreConfig, ContentId};
-pub use metadata_cache::{MetadataCache, MetadataCacheConfig, CachedEntry};
-pub use policy::{CachePolicy, PolicyConfig, EvictionPolicy};
+pub use cached_backend::{CacheStores, CachedBackend};
+pub use chunker::{Chunker, ChunkerConfig};
+pub use metadata_cache::{MetadataCache, MetadataCacheConfig, CachedEntry, QueuedChange, WriteBase};
+pub use policy::{CachePolicy, PolicyConfig, EvictionPolicy, PINNED_PRIORITY};
 
 /// Cache-specific errors
 #[derive(Debug, Error)]
@@ -56,6 +63,12 @@ pub enum CacheError {
 /// Cache result type
 pub type CacheResult<T> = Result<T, CacheError>;
 
+impl From<CacheError> for cfk_core::CfkError {
+    fn from(e: CacheError) -> Self {
+        cfk_core::CfkError::Cache(e.to_string())
+    }
+}
+
 /// Cache trait for different backends
 #[async_trait]
 pub trait CacheBackend: Send + Sync {
@@ -86,6 +99,8 @@ pub trait CacheBackend: Send + Sync {
 pub struct CacheStats {
     pub entries: u64,
     pub total_size: u64,
+    /// What `total_size` comes down to with shared chunks counted once
+    pub deduplicated_size: u64,
     pub hit_count: u64,
     pub miss_count: u64,
 }
@@ -95,6 +110,11 @@ pub fn hit_rate(&self) -> f64 {
         let total = self.hit_count + self.miss_count;
         if total == 0 { 0.0 } else { self.hit_count as f64 / total as f64 }
     }
+
+    /// How many times over deduplication fits the content, 1.0 when it saves nothing
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.deduplicated_size == 0 { 1.0 } else { self.total_size as f64 / self.deduplicated_size as f64 }
+    }
 }
 
 /// Content-addressed blob storage
@@ -167,6 +187,12 @@ pub fn evict_candidates(&self, needed_space: u64) -> Vec<String> {
 #[cfg(feature = "sled")]
 pub mod sled_backend;
 
+#[cfg(feature = "sled")]
+pub mod uploads;
+
+#[cfg(feature = "sled")]
+pub use uploads::{PendingUpload, SourceFingerprint, UploadStore};
+
 #[cfg(feature = "surrealdb")]
 pub mod surreal_backend;
 
@@ -3,7 +3,7 @@
 //!
 //! Caches file and directory metadata for offline access and performance.
 
-use cfk_core::{Entry, EntryKind, Metadata, VirtualPath};
+use cfk_core::{metadata::Permissions, ChangeEvent, ChangeKind, Entry, EntryKind, Metadata, VirtualPath};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -33,12 +33,21 @@ pub struct CachedEntry {
     pub checksum: Option<String>,
     /// MIME type
     pub mime_type: Option<String>,
+    /// Unix permission bits
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Provider revision
+    #[serde(default)]
+    pub revision: Option<String>,
     /// Local blob content ID (if cached)
     pub content_id: Option<String>,
     /// When this entry was cached
     pub cached_at: DateTime<Utc>,
     /// When this entry expires
     pub expires_at: Option<DateTime<Utc>>,
+    /// Kept past expiry: pinned, or changed offline and not uploaded yet
+    #[serde(default)]
+    pub pinned: bool,
     /// Custom metadata
     #[serde(default)]
     pub custom: HashMap<String, String>,
@@ -87,22 +96,29 @@ pub fn from_entry(entry: &Entry, ttl_secs: Option<i64>) -> Self {
             created: entry.metadata.created,
             checksum: entry.metadata.content_hash.clone(),
             mime_type: entry.metadata.mime_type.clone(),
+            mode: entry.metadata.permissions.map(|p| p.mode),
+            revision: entry.metadata.revision.clone(),
             content_id: None,
             cached_at: Utc::now(),
             expires_at: ttl_secs.map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
+            pinned: false,
             custom: entry.metadata.custom.clone(),
         }
     }
 
     /// Convert back to cfk_core Entry
     pub fn to_entry(&self) -> Entry {
-        let mut metadata = Metadata::default();
-        metadata.size = self.size;
-        metadata.modified = self.modified;
-        metadata.created = self.created;
-        metadata.content_hash = self.checksum.clone();
-        metadata.mime_type = self.mime_type.clone();
-        metadata.custom = self.custom.clone();
+        let metadata = Metadata {
+            size: self.size,
+            modified: self.modified,
+            created: self.created,
+            content_hash: self.checksum.clone(),
+            mime_type: self.mime_type.clone(),
+            permissions: self.mode.map(Permissions::new),
+            revision: self.revision.clone(),
+            custom: self.custom.clone(),
+            ..Default::default()
+        };
 
         Entry {
             path: VirtualPath::parse_uri(&self.path).unwrap_or_else(|| {
@@ -127,6 +143,100 @@ pub fn with_content_id(mut self, content_id: &ContentId) -> Self {
         self.content_id = Some(content_id.to_hex());
         self
     }
+
+    /// The blob holding the content, if any
+    pub fn content(&self) -> Option<ContentId> {
+        self.content_id.as_deref().and_then(|hex| ContentId::from_hex(hex).ok())
+    }
+
+    /// Whether `other` describes the same version of the file
+    fn same_version(&self, other: &CachedEntry) -> bool {
+        self.kind == other.kind
+            && self.size == other.size
+            && self.modified == other.modified
+            && self.checksum == other.checksum
+            && self.revision == other.revision
+    }
+}
+
+/// A change made while offline, waiting to be replayed on the backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueuedChange {
+    /// Upload the blob `content_id` as the file's content, as long as the
+    /// backend still has the file `base` describes
+    Write {
+        path: VirtualPath,
+        content_id: String,
+        /// Not checked when unknown
+        #[serde(default)]
+        base: Option<WriteBase>,
+    },
+    CreateDirectory { path: VirtualPath },
+    Delete { path: VirtualPath, recursive: bool },
+    Rename { from: VirtualPath, to: VirtualPath },
+}
+
+impl QueuedChange {
+    /// Whether replaying this changes `path`, or a directory above it
+    pub fn touches(&self, path: &VirtualPath) -> bool {
+        match self {
+            Self::Write { path: changed, .. } | Self::CreateDirectory { path: changed } => {
+                changed == path
+            }
+            Self::Delete { path: changed, .. } => path.starts_with(changed),
+            Self::Rename { from, to } => path.starts_with(from) || path.starts_with(to),
+        }
+    }
+}
+
+/// What a write made offline replaces on the backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteBase {
+    /// Nothing; the file is new
+    Missing,
+    /// The file as last seen from the backend
+    Version {
+        size: Option<u64>,
+        modified: Option<DateTime<Utc>>,
+        checksum: Option<String>,
+        revision: Option<String>,
+    },
+}
+
+impl WriteBase {
+    pub fn of(cached: &CachedEntry) -> Self {
+        Self::Version {
+            size: cached.size,
+            modified: cached.modified,
+            checksum: cached.checksum.clone(),
+            revision: cached.revision.clone(),
+        }
+    }
+
+    /// Whether `current`, what the backend has now, is still the base
+    ///
+    /// The strongest identity both sides know decides: the revision, then
+    /// the content hash, then size and modification time.
+    pub fn matches(&self, current: Option<&Entry>) -> bool {
+        let Self::Version { size, modified, checksum, revision } = self else {
+            return current.is_none();
+        };
+        let Some(entry) = current.filter(|e| e.is_file()) else {
+            return false;
+        };
+        let metadata = &entry.metadata;
+        if let (Some(base), Some(now)) = (revision, &metadata.revision) {
+            return base == now;
+        }
+        if let (Some(base), Some(now)) = (checksum, &metadata.content_hash) {
+            return base == now;
+        }
+        let same_time = match (modified, metadata.modified) {
+            (Some(base), Some(now)) => *base == now,
+            _ => true,
+        };
+        *size == metadata.size && same_time
+    }
 }
 
 /// Cached directory listing
@@ -142,6 +252,9 @@ pub struct CachedDirectory {
     pub cached_at: DateTime<Utc>,
     /// When this listing expires
     pub expires_at: Option<DateTime<Utc>>,
+    /// Kept past expiry, like [`CachedEntry::pinned`]
+    #[serde(default)]
+    pub pinned: bool,
 }
 
 impl CachedDirectory {
@@ -153,6 +266,7 @@ pub fn new(path: &VirtualPath, children: Vec<String>, ttl_secs: Option<i64>) ->
             children,
             cached_at: Utc::now(),
             expires_at: ttl_secs.map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
+            pinned: false,
         }
     }
 
@@ -197,6 +311,8 @@ pub struct MetadataCache {
     db: sled::Db,
     /// In-memory LRU cache for hot entries
     memory_cache: Arc<RwLock<lru::LruCache<String, CachedEntry>>>,
+    /// Pinned paths, mirrored from the `pin:` keys
+    pins: std::sync::RwLock<Vec<VirtualPath>>,
 }
 
 impl MetadataCache {
@@ -209,10 +325,21 @@ pub fn new(config: MetadataCacheConfig) -> CacheResult<Self> {
             std::num::NonZeroUsize::new(10000).unwrap(),
         )));
 
+        let pins = db
+            .scan_prefix("pin:")
+            .keys()
+            .flatten()
+            .filter_map(|key| {
+                let key = String::from_utf8_lossy(&key).into_owned();
+                VirtualPath::parse_uri(key.strip_prefix("pin:")?)
+            })
+            .collect();
+
         Ok(Self {
             config,
             db,
             memory_cache,
+            pins: std::sync::RwLock::new(pins),
         })
     }
 
@@ -223,32 +350,39 @@ pub fn default_cache() -> CacheResult<Self> {
 
     /// Cache entry metadata
     pub async fn put_entry(&self, entry: &Entry) -> CacheResult<()> {
-        let cached = CachedEntry::from_entry(entry, Some(self.config.default_ttl));
-        let key = entry.path.to_string();
-        let value = serde_json::to_vec(&cached)
-            .map_err(|e| CacheError::Serialization(e.to_string()))?;
-
-        self.db
-            .insert(format!("entry:{}", key), value)
-            .map_err(|e| CacheError::Database(e.to_string()))?;
-
-        // Update memory cache
-        self.memory_cache.write().await.put(key, cached);
-
-        Ok(())
+        self.put_cached(CachedEntry::from_entry(entry, Some(self.config.default_ttl)))
+            .await
     }
 
     /// Cache entry with custom TTL
     pub async fn put_entry_with_ttl(&self, entry: &Entry, ttl_secs: i64) -> CacheResult<()> {
-        let cached = CachedEntry::from_entry(entry, Some(ttl_secs));
-        let key = entry.path.to_string();
-        let value = serde_json::to_vec(&cached)
-            .map_err(|e| CacheError::Serialization(e.to_string()))?;
+        self.put_cached(CachedEntry::from_entry(entry, Some(ttl_secs))).await
+    }
 
-        self.db
-            .insert(format!("entry:{}", key), value)
-            .map_err(|e| CacheError::Database(e.to_string()))?;
+    /// Cache entry metadata along with the blob holding its content
+    pub async fn put_content(&self, entry: &Entry, content_id: &ContentId) -> CacheResult<()> {
+        let cached = CachedEntry::from_entry(entry, Some(self.config.default_ttl));
+        self.put_cached(cached.with_content_id(content_id)).await
+    }
+
+    /// Store an entry as given, except that entries below a pin are kept
+    /// and a new copy of an unchanged file keeps its cached content
+    pub async fn put_cached(&self, mut cached: CachedEntry) -> CacheResult<()> {
+        let key = cached.path.clone();
+        let db_key = format!("entry:{}", key);
 
+        if let Some(path) = VirtualPath::parse_uri(&key) {
+            cached.pinned |= self.is_pinned(&path);
+        }
+        if cached.content_id.is_none() {
+            if let Some(old) = self.read::<CachedEntry>(&db_key)? {
+                if old.same_version(&cached) {
+                    cached.content_id = old.content_id;
+                }
+            }
+        }
+
+        self.write(&db_key, &cached)?;
         self.memory_cache.write().await.put(key, cached);
 
         Ok(())
@@ -256,13 +390,26 @@ pub async fn put_entry_with_ttl(&self, entry: &Entry, ttl_secs: i64) -> CacheRes
 
     /// Get cached entry
     pub async fn get_entry(&self, path: &VirtualPath) -> CacheResult<Option<CachedEntry>> {
+        self.find_entry(path, false).await
+    }
+
+    /// Get cached entry even if it expired, for answering without the backend
+    pub async fn get_entry_offline(&self, path: &VirtualPath) -> CacheResult<Option<CachedEntry>> {
+        self.find_entry(path, true).await
+    }
+
+    async fn find_entry(
+        &self,
+        path: &VirtualPath,
+        allow_expired: bool,
+    ) -> CacheResult<Option<CachedEntry>> {
         let key = path.to_string();
 
         // Check memory cache first
         {
             let mut cache = self.memory_cache.write().await;
             if let Some(entry) = cache.get(&key) {
-                if !entry.is_expired() {
+                if allow_expired || !entry.is_expired() {
                     return Ok(Some(entry.clone()));
                 }
             }
@@ -270,15 +417,14 @@ pub async fn get_entry(&self, path: &VirtualPath) -> CacheResult<Option<CachedEn
 
         // Check database
         let db_key = format!("entry:{}", key);
-        if let Some(data) = self.db.get(&db_key).map_err(|e| CacheError::Database(e.to_string()))? {
-            let cached: CachedEntry = serde_json::from_slice(&data)
-                .map_err(|e| CacheError::Serialization(e.to_string()))?;
-
-            if cached.is_expired() {
-                // Remove expired entry
-                self.db
-                    .remove(&db_key)
-                    .map_err(|e| CacheError::Database(e.to_string()))?;
+        if let Some(cached) = self.read::<CachedEntry>(&db_key)? {
+            if cached.is_expired() && !allow_expired {
+                // Remove expired entry, unless it has to stay
+                if !cached.pinned {
+                    self.db
+                        .remove(&db_key)
+                        .map_err(|e| CacheError::Database(e.to_string()))?;
+                }
                 return Ok(None);
             }
 
@@ -294,15 +440,10 @@ pub async fn get_entry(&self, path: &VirtualPath) -> CacheResult<Option<CachedEn
     /// Cache directory listing
     pub async fn put_directory(&self, path: &VirtualPath, entries: &[Entry]) -> CacheResult<()> {
         let children: Vec<String> = entries.iter().map(|e| e.path.to_string()).collect();
-        let cached = CachedDirectory::new(path, children, Some(self.config.default_ttl));
+        let mut cached = CachedDirectory::new(path, children, Some(self.config.default_ttl));
+        cached.pinned = self.is_pinned(path);
 
-        let key = format!("dir:{}", path);
-        let value = serde_json::to_vec(&cached)
-            .map_err(|e| CacheError::Serialization(e.to_string()))?;
-
-        self.db
-            .insert(key, value)
-            .map_err(|e| CacheError::Database(e.to_string()))?;
+        self.write(&format!("dir:{}", path), &cached)?;
 
         // Also cache individual entries
         for entry in entries {
@@ -314,16 +455,32 @@ pub async fn put_directory(&self, path: &VirtualPath, entries: &[Entry]) -> Cach
 
     /// Get cached directory listing
     pub async fn get_directory(&self, path: &VirtualPath) -> CacheResult<Option<Vec<Entry>>> {
-        let key = format!("dir:{}", path);
+        self.find_directory(path, false).await
+    }
 
-        if let Some(data) = self.db.get(&key).map_err(|e| CacheError::Database(e.to_string()))? {
-            let cached: CachedDirectory = serde_json::from_slice(&data)
-                .map_err(|e| CacheError::Serialization(e.to_string()))?;
+    /// Get cached directory listing even if it expired, for answering
+    /// without the backend
+    pub async fn get_directory_offline(
+        &self,
+        path: &VirtualPath,
+    ) -> CacheResult<Option<Vec<Entry>>> {
+        self.find_directory(path, true).await
+    }
 
-            if cached.is_expired() {
-                self.db
-                    .remove(&key)
-                    .map_err(|e| CacheError::Database(e.to_string()))?;
+    async fn find_directory(
+        &self,
+        path: &VirtualPath,
+        allow_expired: bool,
+    ) -> CacheResult<Option<Vec<Entry>>> {
+        let key = format!("dir:{}", path);
+
+        if let Some(cached) = self.read::<CachedDirectory>(&key)? {
+            if cached.is_expired() && !allow_expired {
+                if !cached.pinned {
+                    self.db
+                        .remove(&key)
+                        .map_err(|e| CacheError::Database(e.to_string()))?;
+                }
                 return Ok(None);
             }
 
@@ -334,7 +491,7 @@ pub async fn get_directory(&self, path: &VirtualPath) -> CacheResult<Option<Vec<
                     VirtualPath::new(&cached.backend_id, child_path)
                 });
 
-                if let Some(entry) = self.get_entry(&virtual_path).await? {
+                if let Some(entry) = self.find_entry(&virtual_path, allow_expired).await? {
                     entries.push(entry.to_entry());
                 }
             }
@@ -345,6 +502,24 @@ pub async fn get_directory(&self, path: &VirtualPath) -> CacheResult<Option<Vec<
         Ok(None)
     }
 
+    fn read<T: serde::de::DeserializeOwned>(&self, key: &str) -> CacheResult<Option<T>> {
+        match self.db.get(key).map_err(|e| CacheError::Database(e.to_string()))? {
+            Some(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| CacheError::Serialization(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn write<T: Serialize>(&self, key: &str, value: &T) -> CacheResult<()> {
+        let value = serde_json::to_vec(value)
+            .map_err(|e| CacheError::Serialization(e.to_string()))?;
+        self.db
+            .insert(key, value)
+            .map_err(|e| CacheError::Database(e.to_string()))?;
+        Ok(())
+    }
+
     /// Invalidate entry
     pub async fn invalidate(&self, path: &VirtualPath) -> CacheResult<()> {
         let key = path.to_string();
@@ -360,45 +535,292 @@ pub async fn invalidate(&self, path: &VirtualPath) -> CacheResult<()> {
 
     /// Invalidate directory and all children
     pub async fn invalidate_directory(&self, path: &VirtualPath) -> CacheResult<()> {
-        let prefix = format!("entry:{}:", path);
+        let key = path.to_string();
+        let below = below(path);
 
-        // Remove all entries with this prefix
-        for result in self.db.scan_prefix(&prefix) {
-            if let Ok((key, _)) = result {
+        for prefix in [format!("entry:{}", below), format!("dir:{}", below)] {
+            for (key, _) in self.db.scan_prefix(&prefix).flatten() {
                 self.db
                     .remove(&key)
                     .map_err(|e| CacheError::Database(e.to_string()))?;
             }
         }
+        for db_key in [format!("entry:{}", key), format!("dir:{}", key)] {
+            self.db
+                .remove(db_key)
+                .map_err(|e| CacheError::Database(e.to_string()))?;
+        }
+
+        self.memory_cache
+            .write()
+            .await
+            .retain(|k| k != &key && !k.starts_with(&below));
+
+        Ok(())
+    }
 
-        // Remove directory listing
+    /// Drop what a reported change makes stale: the entry and the listing
+    /// it appears in, for renames on both sides, and for a rescan everything
+    /// at or below the path
+    pub async fn apply_change(&self, change: &ChangeEvent) -> CacheResult<()> {
+        if change.kind == ChangeKind::Rescan {
+            return self.invalidate_directory(&change.path).await;
+        }
+        // A moved or deleted directory takes its contents along
+        let gone = match &change.kind {
+            ChangeKind::Renamed { from } => Some(from),
+            ChangeKind::Deleted => Some(&change.path),
+            _ => None,
+        };
+        if let Some(gone) = gone {
+            self.invalidate_directory(gone).await?;
+        }
+
+        for path in change.stale_paths() {
+            self.invalidate(&path).await?;
+            self.db
+                .remove(format!("dir:{}", path))
+                .map_err(|e| CacheError::Database(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Keep everything at or below `path` past expiry from now on
+    pub async fn pin(&self, path: &VirtualPath) -> CacheResult<()> {
         self.db
-            .remove(format!("dir:{}", path))
+            .insert(format!("pin:{}", path), &[][..])
             .map_err(|e| CacheError::Database(e.to_string()))?;
 
+        let mut pins = self.pins.write().unwrap_or_else(|e| e.into_inner());
+        if !pins.contains(path) {
+            pins.push(path.clone());
+        }
         Ok(())
     }
 
-    /// Clear all cached data for a backend
-    pub async fn clear_backend(&self, backend_id: &str) -> CacheResult<()> {
-        let prefix = format!("entry:{}:", backend_id);
+    /// Drop the pins at or below `path`, returning the content no pin
+    /// keeps any more
+    pub async fn unpin(&self, path: &VirtualPath) -> CacheResult<Vec<ContentId>> {
+        let removed: Vec<VirtualPath> = {
+            let mut pins = self.pins.write().unwrap_or_else(|e| e.into_inner());
+            let (removed, kept) = pins.drain(..).partition(|p| p.starts_with(path));
+            *pins = kept;
+            removed
+        };
+        if removed.is_empty() {
+            return Err(CacheError::NotFound(format!("{} is not pinned", path)));
+        }
 
-        for result in self.db.scan_prefix(&prefix) {
-            if let Ok((key, _)) = result {
-                self.db
-                    .remove(&key)
-                    .map_err(|e| CacheError::Database(e.to_string()))?;
+        let mut released = Vec::new();
+        for root in &removed {
+            self.db
+                .remove(format!("pin:{}", root))
+                .map_err(|e| CacheError::Database(e.to_string()))?;
+
+            for (key, mut cached) in self.scan_below::<CachedEntry>("entry:", root) {
+                let still_pinned = VirtualPath::parse_uri(&cached.path)
+                    .is_some_and(|p| self.is_pinned(&p));
+                if !cached.pinned || still_pinned {
+                    continue;
+                }
+                cached.pinned = false;
+                released.extend(cached.content());
+                self.write(&key, &cached)?;
+                self.memory_cache.write().await.pop(&cached.path);
+            }
+            for (key, mut cached) in self.scan_below::<CachedDirectory>("dir:", root) {
+                let still_pinned = VirtualPath::parse_uri(&cached.path)
+                    .is_some_and(|p| self.is_pinned(&p));
+                if cached.pinned && !still_pinned {
+                    cached.pinned = false;
+                    self.write(&key, &cached)?;
+                }
             }
         }
+        Ok(released)
+    }
 
-        let dir_prefix = format!("dir:{}:", backend_id);
-        for result in self.db.scan_prefix(&dir_prefix) {
-            if let Ok((key, _)) = result {
-                self.db
-                    .remove(&key)
-                    .map_err(|e| CacheError::Database(e.to_string()))?;
+    /// Pinned paths
+    pub fn pins(&self) -> Vec<VirtualPath> {
+        self.pins.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Whether `path` is pinned, itself or through a directory above it
+    pub fn is_pinned(&self, path: &VirtualPath) -> bool {
+        self.pins
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|pin| path.starts_with(pin))
+    }
+
+    /// Content that must not be evicted: what the pins cover and what
+    /// queued changes still have to upload
+    pub async fn pinned_content(&self) -> CacheResult<Vec<ContentId>> {
+        let mut content = Vec::new();
+        for pin in self.pins() {
+            content.extend(
+                self.scan_below::<CachedEntry>("entry:", &pin)
+                    .into_iter()
+                    .filter_map(|(_, cached)| cached.content()),
+            );
+        }
+        for (_, value) in self.db.scan_prefix("queue:").flatten() {
+            if let Ok(QueuedChange::Write { content_id, .. }) = serde_json::from_slice(&value) {
+                content.extend(ContentId::from_hex(&content_id).ok());
+            }
+        }
+        Ok(content)
+    }
+
+    /// Record an entry changed without the backend and list it in its
+    /// directory; it is kept until the backend has the change
+    pub async fn put_local(&self, entry: &Entry, content_id: Option<&ContentId>) -> CacheResult<()> {
+        let mut cached = CachedEntry::from_entry(entry, Some(self.config.default_ttl));
+        cached.content_id = content_id.map(ContentId::to_hex);
+        cached.pinned = true;
+        self.put_cached(cached).await?;
+
+        if let Some(parent) = entry.path.parent() {
+            let child = entry.path.to_string();
+            self.edit_listing(&parent, |children| {
+                if !children.contains(&child) {
+                    children.push(child.clone());
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Forget `path` and everything below it, and drop it from its
+    /// directory's listing, after removing it without the backend
+    pub async fn remove_local(&self, path: &VirtualPath) -> CacheResult<()> {
+        self.invalidate_directory(path).await?;
+
+        if let Some(parent) = path.parent() {
+            let child = path.to_string();
+            self.edit_listing(&parent, |children| children.retain(|c| c != &child))?;
+        }
+        Ok(())
+    }
+
+    /// Move what is cached at and below `from` to `to`, after renaming it
+    /// without the backend
+    pub async fn move_local(&self, from: &VirtualPath, to: &VirtualPath) -> CacheResult<()> {
+        let (from_key, to_key) = (from.to_string(), to.to_string());
+        let moved = |key: &str| match key.strip_prefix(&from_key) {
+            Some(rest) => format!("{}{}", to_key, rest),
+            None => key.to_string(),
+        };
+
+        let entries = self.scan_below::<CachedEntry>("entry:", from);
+        let dirs = self.scan_below::<CachedDirectory>("dir:", from);
+        self.remove_local(to).await?;
+        self.remove_local(from).await?;
+
+        for (_, mut cached) in entries {
+            cached.path = moved(&cached.path);
+            cached.pinned = true;
+            self.put_cached(cached).await?;
+        }
+        for (_, mut cached) in dirs {
+            cached.path = moved(&cached.path);
+            cached.children = cached.children.iter().map(|c| moved(c)).collect();
+            cached.pinned = true;
+            self.write(&format!("dir:{}", cached.path), &cached)?;
+        }
+
+        if let Some(parent) = to.parent() {
+            self.edit_listing(&parent, |children| {
+                if !children.contains(&to_key) {
+                    children.push(to_key.clone());
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Change the cached listing of `dir`, if there is one, and keep it
+    fn edit_listing(&self, dir: &VirtualPath, edit: impl FnOnce(&mut Vec<String>)) -> CacheResult<()> {
+        let key = format!("dir:{}", dir);
+        if let Some(mut cached) = self.read::<CachedDirectory>(&key)? {
+            edit(&mut cached.children);
+            cached.pinned = true;
+            self.write(&key, &cached)?;
+        }
+        Ok(())
+    }
+
+    /// Queue a change for the backend `backend_id`, after those already
+    /// queued for it
+    pub async fn queue_change(&self, backend_id: &str, change: &QueuedChange) -> CacheResult<()> {
+        let seq = self
+            .db
+            .generate_id()
+            .map_err(|e| CacheError::Database(e.to_string()))?;
+        self.write(&format!("queue:{}/{:020}", backend_id, seq), change)
+    }
+
+    /// Changes queued for `backend_id`, oldest first, with the number to
+    /// dequeue each by
+    pub async fn queued_changes(&self, backend_id: &str) -> CacheResult<Vec<(u64, QueuedChange)>> {
+        let prefix = format!("queue:{}/", backend_id);
+        let mut changes = Vec::new();
+        for item in self.db.scan_prefix(&prefix) {
+            let (key, value) = item.map_err(|e| CacheError::Database(e.to_string()))?;
+            let seq = String::from_utf8_lossy(&key[prefix.len()..])
+                .parse()
+                .map_err(|_| CacheError::Database("bad queue key".into()))?;
+            let change = serde_json::from_slice(&value)
+                .map_err(|e| CacheError::Serialization(e.to_string()))?;
+            changes.push((seq, change));
+        }
+        Ok(changes)
+    }
+
+    /// Remove a queued change once the backend has it
+    pub async fn dequeue(&self, backend_id: &str, seq: u64) -> CacheResult<()> {
+        self.db
+            .remove(format!("queue:{}/{:020}", backend_id, seq))
+            .map_err(|e| CacheError::Database(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Keys and values of a kind (`entry:` or `dir:`) at or below `path`
+    fn scan_below<T: serde::de::DeserializeOwned>(&self, kind: &str, path: &VirtualPath) -> Vec<(String, T)> {
+        let exact = format!("{}{}", kind, path);
+        let below = format!("{}{}", kind, below(path));
+        let mut found = Vec::new();
+        if exact != below {
+            if let Ok(Some(value)) = self.read::<T>(&exact) {
+                found.push((exact, value));
+            }
+        }
+        for (key, value) in self.db.scan_prefix(&below).flatten() {
+            if let Ok(value) = serde_json::from_slice(&value) {
+                found.push((String::from_utf8_lossy(&key).into_owned(), value));
             }
         }
+        found
+    }
+
+    /// Clear all cached data for a backend
+    pub async fn clear_backend(&self, backend_id: &str) -> CacheResult<()> {
+        let root = below(&VirtualPath::root(backend_id));
+        let prefix = format!("entry:{}", root);
+
+        for (key, _) in self.db.scan_prefix(&prefix).flatten() {
+            self.db
+                .remove(&key)
+                .map_err(|e| CacheError::Database(e.to_string()))?;
+        }
+
+        let dir_prefix = format!("dir:{}", root);
+        for (key, _) in self.db.scan_prefix(&dir_prefix).flatten() {
+            self.db
+                .remove(&key)
+                .map_err(|e| CacheError::Database(e.to_string()))?;
+        }
 
         self.memory_cache.write().await.clear();
 
@@ -431,28 +853,24 @@ pub async fn stats(&self) -> CacheStats {
     pub async fn prune_expired(&self) -> CacheResult<usize> {
         let mut pruned = 0;
 
-        for result in self.db.scan_prefix("entry:") {
-            if let Ok((key, value)) = result {
-                if let Ok(cached) = serde_json::from_slice::<CachedEntry>(&value) {
-                    if cached.is_expired() {
-                        self.db
-                            .remove(&key)
-                            .map_err(|e| CacheError::Database(e.to_string()))?;
-                        pruned += 1;
-                    }
+        for (key, value) in self.db.scan_prefix("entry:").flatten() {
+            if let Ok(cached) = serde_json::from_slice::<CachedEntry>(&value) {
+                if cached.is_expired() {
+                    self.db
+                        .remove(&key)
+                        .map_err(|e| CacheError::Database(e.to_string()))?;
+                    pruned += 1;
                 }
             }
         }
 
-        for result in self.db.scan_prefix("dir:") {
-            if let Ok((key, value)) = result {
-                if let Ok(cached) = serde_json::from_slice::<CachedDirectory>(&value) {
-                    if cached.is_expired() {
-                        self.db
-                            .remove(&key)
-                            .map_err(|e| CacheError::Database(e.to_string()))?;
-                        pruned += 1;
-                    }
+        for (key, value) in self.db.scan_prefix("dir:").flatten() {
+            if let Ok(cached) = serde_json::from_slice::<CachedDirectory>(&value) {
+                if cached.is_expired() {
+                    self.db
+                        .remove(&key)
+                        .map_err(|e| CacheError::Database(e.to_string()))?;
+                    pruned += 1;
                 }
             }
         }
@@ -461,6 +879,16 @@ pub async fn prune_expired(&self) -> CacheResult<usize> {
     }
 }
 
+/// Key prefix of everything below `path`
+fn below(path: &VirtualPath) -> String {
+    let uri = path.to_uri();
+    if uri.ends_with('/') {
+        uri
+    } else {
+        format!("{}/", uri)
+    }
+}
+
 /// Cache statistics
 #[derive(Debug, Clone, Default)]
 pub struct CacheStats {
@@ -526,9 +954,65 @@ pub fn len(&self) -> usize {
             self.map.len()
         }
 
+        /// Keep only the keys `keep` accepts
+        pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+            self.map.retain(|k, _| keep(k));
+            self.order.retain(|k| keep(k));
+        }
+
         pub fn clear(&mut self) {
             self.map.clear();
             self.order.clear();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn cache(dir: &TempDir) -> MetadataCache {
+        MetadataCache::new(MetadataCacheConfig {
+            db_path: dir.path().join("metadata"),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("local", p)
+    }
+
+    #[tokio::test]
+    async fn test_apply_change_drops_stale_entries() {
+        let dir = TempDir::new().unwrap();
+        let cache = cache(&dir);
+        let docs = vec![
+            Entry::file(path("docs/a.txt"), Metadata::default()),
+            Entry::directory(path("docs/sub"), Metadata::default()),
+        ];
+        cache.put_directory(&path("docs"), &docs).await.unwrap();
+        let sub = vec![Entry::file(path("docs/sub/b.txt"), Metadata::default())];
+        cache.put_directory(&path("docs/sub"), &sub).await.unwrap();
+        cache.put_entry(&Entry::file(path("other.txt"), Metadata::default())).await.unwrap();
+
+        let edit = ChangeEvent::new(path("docs/a.txt"), ChangeKind::Modified);
+        cache.apply_change(&edit).await.unwrap();
+        assert!(cache.get_entry(&path("docs/a.txt")).await.unwrap().is_none());
+        assert!(cache.get_directory(&path("docs")).await.unwrap().is_none());
+        assert!(cache.get_directory(&path("docs/sub")).await.unwrap().is_some());
+
+        // Moving a directory away takes its contents along
+        let from = path("docs/sub");
+        let moved = ChangeEvent::new(path("moved"), ChangeKind::Renamed { from });
+        cache.apply_change(&moved).await.unwrap();
+        assert!(cache.get_entry(&path("docs/sub/b.txt")).await.unwrap().is_none());
+        assert!(cache.get_directory(&path("docs/sub")).await.unwrap().is_none());
+        assert!(cache.get_entry(&path("other.txt")).await.unwrap().is_some());
+
+        let rescan = ChangeEvent::new(VirtualPath::root("local"), ChangeKind::Rescan);
+        cache.apply_change(&rescan).await.unwrap();
+        assert!(cache.get_entry(&path("other.txt")).await.unwrap().is_none());
+    }
+}
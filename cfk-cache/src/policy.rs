@@ -4,11 +4,14 @@
 //! LRU, LFU, FIFO, and size-based eviction strategies.
 
 use chrono::{DateTime, Utc};
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::HashMap;
 use std::cmp::Ordering;
 
 use crate::blob_store::ContentId;
 
+/// Priority of content that must stay, like pinned files: never evicted
+pub const PINNED_PRIORITY: i32 = i32::MAX;
+
 /// Cache entry info for eviction decisions
 #[derive(Debug, Clone)]
 pub struct CacheEntryInfo {
@@ -46,9 +49,10 @@ pub fn with_priority(mut self, priority: i32) -> Self {
 }
 
 /// Eviction policy type
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum EvictionPolicy {
     /// Least Recently Used
+    #[default]
     Lru,
     /// Least Frequently Used
     Lfu,
@@ -62,12 +66,6 @@ pub enum EvictionPolicy {
     Adaptive,
 }
 
-impl Default for EvictionPolicy {
-    fn default() -> Self {
-        Self::Lru
-    }
-}
-
 /// Cache policy configuration
 #[derive(Debug, Clone)]
 pub struct PolicyConfig {
@@ -123,7 +121,14 @@ pub fn new(config: PolicyConfig) -> Self {
     }
 
     /// Record an entry being added to cache
-    pub fn record_add(&mut self, info: CacheEntryInfo) {
+    ///
+    /// Adding content that is already tracked replaces its entry, keeping
+    /// the higher priority: the same content may be stored for several paths.
+    pub fn record_add(&mut self, mut info: CacheEntryInfo) {
+        if let Some(old) = self.entries.remove(&info.content_id) {
+            self.total_size = self.total_size.saturating_sub(old.size);
+            info.priority = info.priority.max(old.priority);
+        }
         self.total_size += info.size;
         self.entries.insert(info.content_id.clone(), info);
     }
@@ -135,6 +140,13 @@ pub fn record_access(&mut self, content_id: &ContentId) {
         }
     }
 
+    /// Change how important an entry is; [`PINNED_PRIORITY`] keeps it
+    pub fn set_priority(&mut self, content_id: &ContentId, priority: i32) {
+        if let Some(entry) = self.entries.get_mut(content_id) {
+            entry.priority = priority;
+        }
+    }
+
     /// Record an entry being removed
     pub fn record_remove(&mut self, content_id: &ContentId) {
         if let Some(entry) = self.entries.remove(content_id) {
@@ -171,6 +183,7 @@ pub fn select_evictions(&self) -> EvictionResult {
         let mut candidates: Vec<_> = self
             .entries
             .values()
+            .filter(|e| e.priority != PINNED_PRIORITY)
             .filter(|e| {
                 // Don't evict entries newer than min_ttl
                 let age = Utc::now()
@@ -183,19 +196,19 @@ pub fn select_evictions(&self) -> EvictionResult {
         // Sort by policy
         match self.config.policy {
             EvictionPolicy::Lru => {
-                candidates.sort_by(|a, b| a.last_accessed.cmp(&b.last_accessed));
+                candidates.sort_by_key(|a| a.last_accessed);
             }
             EvictionPolicy::Lfu => {
-                candidates.sort_by(|a, b| a.access_count.cmp(&b.access_count));
+                candidates.sort_by_key(|a| a.access_count);
             }
             EvictionPolicy::Fifo => {
-                candidates.sort_by(|a, b| a.created.cmp(&b.created));
+                candidates.sort_by_key(|a| a.created);
             }
             EvictionPolicy::LargestFirst => {
-                candidates.sort_by(|a, b| b.size.cmp(&a.size));
+                candidates.sort_by_key(|a| std::cmp::Reverse(a.size));
             }
             EvictionPolicy::SmallestFirst => {
-                candidates.sort_by(|a, b| a.size.cmp(&b.size));
+                candidates.sort_by_key(|a| a.size);
             }
             EvictionPolicy::Adaptive => {
                 // ARC-like: balance between LRU and LFU
@@ -420,4 +433,24 @@ fn test_lru_eviction() {
         assert!(!result.evicted.is_empty());
         assert!(result.size_freed > 0);
     }
+    #[test]
+    fn test_pinned_entries_are_never_evicted() {
+        let mut policy = CachePolicy::new(PolicyConfig {
+            max_size: 100,
+            min_ttl: 0,
+            ..Default::default()
+        });
+
+        let pinned = ContentId::from_bytes([1; 32]);
+        policy.record_add(CacheEntryInfo::new(pinned.clone(), 80).with_priority(PINNED_PRIORITY));
+        let other = ContentId::from_bytes([2; 32]);
+        policy.record_add(CacheEntryInfo::new(other.clone(), 80));
+
+        assert_eq!(policy.select_evictions().evicted, vec![other.clone()]);
+
+        policy.record_remove(&other);
+        policy.record_add(CacheEntryInfo::new(other.clone(), 80));
+        policy.set_priority(&pinned, 0);
+        assert_eq!(policy.select_evictions().evicted.len(), 1);
+    }
 }
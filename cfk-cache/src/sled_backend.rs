@@ -7,6 +7,7 @@
 use crate::{CacheError, CacheResult};
 
 /// Sled-based storage backend
+#[derive(Clone)]
 pub struct SledBackend {
     db: Db,
 }
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Persisted resumable upload sessions
+//!
+//! An upload session is only worth resuming if the source is still the file
+//! it was started from, so each one is stored with the source URI and a
+//! fingerprint of the source. Sessions are keyed by destination URI.
+
+use crate::sled_backend::SledBackend;
+use cfk_core::{CfkError, CfkResult, Entry, UploadSession, VirtualPath};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What the source of an upload looked like when the session was opened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFingerprint {
+    pub size: Option<u64>,
+    /// Modification time, seconds since the epoch
+    pub modified: Option<i64>,
+    pub content_hash: Option<String>,
+    pub revision: Option<String>,
+}
+
+impl SourceFingerprint {
+    pub fn of(entry: &Entry) -> Self {
+        let m = &entry.metadata;
+        Self {
+            size: m.size,
+            modified: m.modified.map(|t| t.timestamp()),
+            content_hash: m.content_hash.clone(),
+            revision: m.revision.clone(),
+        }
+    }
+}
+
+/// An upload that has not finished yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingUpload {
+    /// URI of the file being uploaded
+    pub source: String,
+    pub fingerprint: SourceFingerprint,
+    pub session: UploadSession,
+}
+
+/// Upload sessions in progress
+///
+/// Clones share the same open database.
+#[derive(Clone)]
+pub struct UploadStore {
+    db: SledBackend,
+}
+
+impl UploadStore {
+    pub fn open(path: impl AsRef<Path>) -> CfkResult<Self> {
+        Ok(Self {
+            db: SledBackend::open(path)?,
+        })
+    }
+
+    /// Default database location in the user cache directory
+    pub fn default_path() -> PathBuf {
+        directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.cache_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-cache"))
+            .join("uploads.db")
+    }
+
+    fn key(destination: &VirtualPath) -> Vec<u8> {
+        format!("upload\0{}", destination.to_uri()).into_bytes()
+    }
+
+    pub fn get(&self, destination: &VirtualPath) -> CfkResult<Option<PendingUpload>> {
+        match self.db.get(&Self::key(destination))? {
+            Some(value) => serde_json::from_slice(&value)
+                .map(Some)
+                .map_err(|e| CfkError::Serialization(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the upload and flush, so it survives the process dying
+    pub fn put(&self, upload: &PendingUpload) -> CfkResult<()> {
+        let value =
+            serde_json::to_vec(upload).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        self.db.insert(&Self::key(&upload.session.path), &value)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, destination: &VirtualPath) -> CfkResult<()> {
+        self.db.remove(&Self::key(destination))?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_upload_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = UploadStore::open(tmp.path().join("uploads.db")).unwrap();
+        let destination = VirtualPath::new("s3", "/big.iso");
+
+        let mut session = UploadSession::new(destination.clone(), 100, "u1", 40, false);
+        session.offset = 40;
+        session.state.insert("key".into(), "value".into());
+        let upload = PendingUpload {
+            source: "local:///big.iso".into(),
+            fingerprint: SourceFingerprint {
+                size: Some(100),
+                modified: Some(1),
+                content_hash: None,
+                revision: None,
+            },
+            session,
+        };
+
+        assert_eq!(store.get(&destination).unwrap(), None);
+        store.put(&upload).unwrap();
+        assert_eq!(store.get(&destination).unwrap(), Some(upload));
+        store.remove(&destination).unwrap();
+        assert_eq!(store.get(&destination).unwrap(), None);
+    }
+}
@@ -3,17 +3,54 @@
 
 use cfk_core::{
     entry::EntryKind,
-    operations::{CopyOptions, DeleteOptions, ListOptions, MoveOptions, ReadOptions, WriteOptions},
-    CfkError, CfkResult, VirtualPath,
+    backend::RetryStats,
+    operations::{DeleteOptions, ListOptions, MoveOptions, WriteOptions},
+    CfkError, CfkResult, StorageBackend, VirtualPath,
 };
-use cfk_providers::{BackendRegistry, LocalBackend};
+use cfk_cache::{CacheStores, CachedBackend, QueuedChange};
+use cfk_providers::{BackendRegistry, CfkConfig, FileTokenStore, LocalBackend, NfsBackend, RemoteConfig, TokenStore};
 use chrono::{DateTime, Utc};
 use console::style;
 use futures::StreamExt;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tabled::{Table, Tabled};
 
+/// Set by `--offline`: remotes answer from the cache and changes queue
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Work from the cache for the rest of this run
+pub fn go_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+/// Remotes built during this run, for `report_retries`
+static REMOTES: Mutex<Vec<Arc<dyn StorageBackend>>> = Mutex::new(Vec::new());
+
+/// Print how often each remote had to retry during this run
+pub fn report_retries() {
+    let mut totals: BTreeMap<String, RetryStats> = BTreeMap::new();
+    for backend in REMOTES.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        if let Some(stats) = backend.retry_stats() {
+            let total = totals.entry(backend.id().to_string()).or_default();
+            total.retries += stats.retries;
+            total.rate_limited += stats.rate_limited;
+            total.token_refreshes += stats.token_refreshes;
+            total.exhausted += stats.exhausted;
+        }
+    }
+    for (id, stats) in totals {
+        if stats != RetryStats::default() {
+            eprintln!(
+                "Retries on {}: {} ({} rate limited), {} token refresh(es), {} gave up",
+                id, stats.retries, stats.rate_limited, stats.token_refreshes, stats.exhausted
+            );
+        }
+    }
+}
+
 /// Initialize the backend registry with available backends
 fn init_registry() -> BackendRegistry {
     let mut registry = BackendRegistry::new();
@@ -21,26 +58,135 @@ fn init_registry() -> BackendRegistry {
     // Register local filesystem with root as base
     registry.register(Arc::new(LocalBackend::new("local", "/")));
 
-    // Future: register cloud backends based on config
+    // Named remotes from the config file
+    match CfkConfig::load(CfkConfig::default_path()) {
+        Ok(config) => {
+            let tokens = if config.remotes.values().any(RemoteConfig::uses_oauth) {
+                open_token_store()
+            } else {
+                None
+            };
+            config.register_remotes(&mut registry, tokens.as_ref());
+            REMOTES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(config.remotes.keys().filter_map(|name| registry.get(name)));
+        }
+        Err(e) => eprintln!("{} {}", style("Warning:").yellow(), e),
+    }
 
     registry
 }
 
+/// The backend registry with network remotes behind the local cache
+///
+/// With `refresh`, lookups still go to the remotes but leave their answers
+/// in the cache. When the cache cannot be opened, say because another cfk
+/// is using it, the remotes are used directly. Changes queued by earlier
+/// offline runs are uploaded first; after [`go_offline`] the remotes are
+/// never contacted and new changes join the queue instead.
+async fn cached_registry(refresh: bool, verbose: bool) -> BackendRegistry {
+    let mut registry = init_registry();
+    let offline = OFFLINE.load(Ordering::Relaxed);
+    let stores = match open_cache().await {
+        Ok(stores) => stores,
+        Err(e) if offline => {
+            // Without the cache there is nothing to answer from
+            for id in remote_ids(&registry) {
+                registry.remove(&id);
+                registry.register_failed(&id, format!("offline, and the cache is unavailable: {}", e));
+            }
+            return registry;
+        }
+        Err(e) => {
+            if verbose {
+                eprintln!("Cache disabled: {}", e);
+            }
+            return registry;
+        }
+    };
+
+    for id in remote_ids(&registry) {
+        let Some(backend) = registry.get(&id) else {
+            continue;
+        };
+        let mut cached = CachedBackend::new(backend, stores.clone());
+        if offline {
+            cached = cached.offline();
+        } else {
+            if refresh {
+                cached = cached.refreshing();
+            }
+            upload_queued(&cached).await;
+        }
+        registry.register(Arc::new(cached));
+    }
+    registry
+}
+
+/// Registered backends whose data is not local, which are the ones worth caching
+fn remote_ids(registry: &BackendRegistry) -> Vec<String> {
+    registry
+        .list()
+        .into_iter()
+        .filter(|id| registry.get(id).is_some_and(|backend| !backend.capabilities().offline))
+        .map(String::from)
+        .collect()
+}
+
+async fn open_cache() -> CfkResult<Arc<CacheStores>> {
+    Ok(Arc::new(CacheStores::open_default().await?))
+}
+
+/// Upload changes made during earlier `--offline` runs; whatever fails stays queued
+async fn upload_queued<B: StorageBackend + ?Sized>(cached: &CachedBackend<B>) {
+    let id = cached.id();
+    match cached.upload_queued().await {
+        Ok(0) => {}
+        Ok(n) => eprintln!("Uploaded {} change(s) made offline to {}", n, id),
+        Err(e) => {
+            let left = cached.queued().await.map(|changes| changes.len()).unwrap_or_default();
+            eprintln!(
+                "{} {} change(s) made offline to {} are still queued: {}",
+                style("Warning:").yellow(),
+                left,
+                id,
+                e
+            );
+        }
+    }
+}
+
+/// The OAuth token store, or `None` with a warning when it cannot be opened
+fn open_token_store() -> Option<Arc<dyn TokenStore>> {
+    match FileTokenStore::open_default() {
+        Ok(store) => Some(Arc::new(store)),
+        Err(e) => {
+            eprintln!("{} cannot open token store: {}", style("Warning:").yellow(), e);
+            None
+        }
+    }
+}
+
 /// Parse a path string into a VirtualPath
 /// Supports:
 /// - cfk://backend/path - explicit URI
 /// - /absolute/path - local absolute path
+/// - nfs://server/export/path - NFS export, opened without mounting
 /// - relative/path - local relative path
-fn parse_path(path: &str) -> CfkResult<VirtualPath> {
+async fn parse_path(registry: &mut BackendRegistry, path: &str) -> CfkResult<VirtualPath> {
     if let Some(vpath) = VirtualPath::parse_uri(path) {
         return Ok(vpath);
     }
+    if path.starts_with("nfs://") {
+        return open_nfs(registry, path).await;
+    }
 
     // Treat as local path
     let path_buf = if path.starts_with('/') {
         PathBuf::from(path)
     } else {
-        let cwd = std::env::current_dir().map_err(|e| CfkError::Io(e))?;
+        let cwd = std::env::current_dir().map_err(CfkError::Io)?;
         cwd.join(path)
     };
 
@@ -52,6 +198,14 @@ fn parse_path(path: &str) -> CfkResult<VirtualPath> {
     Ok(VirtualPath::new("local", canonical.to_string_lossy()))
 }
 
+/// Register a backend for the export an NFS URL points into
+async fn open_nfs(registry: &mut BackendRegistry, url: &str) -> CfkResult<VirtualPath> {
+    let host = url["nfs://".len()..].split(['/', ':']).next().unwrap_or_default();
+    let (backend, vpath) = NfsBackend::open_url(format!("nfs-{}", host), url).await?;
+    registry.register(Arc::new(backend));
+    Ok(vpath)
+}
+
 /// Format a timestamp for display
 fn format_time(dt: Option<DateTime<Utc>>) -> String {
     dt.map(|t| t.format("%Y-%m-%d %H:%M").to_string())
@@ -112,20 +266,31 @@ struct LsEntry {
 
 /// List directory contents
 pub async fn ls(path: &str, long: bool, all: bool, human: bool, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
-    let vpath = parse_path(path)?;
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
 
     if verbose {
         eprintln!("Listing: {}", vpath);
     }
 
     let backend = registry.get_or_err(&vpath.backend)?;
-    let options = ListOptions {
+    let mut options = ListOptions {
         include_hidden: all,
         ..Default::default()
     };
 
-    let listing = backend.list_directory(&vpath, &options).await?;
+    // Providers page large directories; follow the cursor to the end
+    let mut listing = backend.list_directory(&vpath, &options).await?;
+    while listing.has_more {
+        let Some(cursor) = listing.cursor.take() else {
+            break;
+        };
+        options.cursor = Some(cursor);
+        let page = backend.list_directory(&vpath, &options).await?;
+        listing.entries.extend(page.entries);
+        listing.cursor = page.cursor;
+        listing.has_more = page.has_more;
+    }
 
     if long {
         let entries: Vec<LsEntry> = listing
@@ -169,17 +334,21 @@ pub async fn ls(path: &str, long: bool, all: bool, human: bool, verbose: bool) -
 
 /// Display file contents
 pub async fn cat(path: &str, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
-    let vpath = parse_path(path)?;
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
 
     if verbose {
         eprintln!("Reading: {}", vpath);
     }
 
     let backend = registry.get_or_err(&vpath.backend)?;
-    let options = ReadOptions::default();
+    let options = cfk_sync::DownloadOptions {
+        use_cache: true,
+        ..Default::default()
+    };
 
-    let mut stream = backend.read_file(&vpath, &options).await?;
+    // Large files come down in parallel ranges where the backend allows it
+    let mut stream = cfk_sync::download::read_file(backend, &vpath, None, &options).await?;
     while let Some(chunk) = stream.next().await {
         let bytes = chunk?;
         // Write raw bytes to stdout
@@ -190,54 +359,151 @@ pub async fn cat(path: &str, verbose: bool) -> CfkResult<()> {
     Ok(())
 }
 
-/// Copy files
-pub async fn cp(source: &str, dest: &str, _recursive: bool, force: bool, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
-    let src_path = parse_path(source)?;
-    let dst_path = parse_path(dest)?;
+/// Copy files, recursing into directories with `recursive`
+pub async fn cp(
+    source: &str,
+    dest: &str,
+    recursive: bool,
+    mut options: cfk_sync::TransferOptions,
+    verbose: bool,
+) -> CfkResult<()> {
+    use cfk_sync::{copy_target, TransferEngine};
+
+    // Files already in the content cache are not downloaded again
+    options.download.use_cache = true;
+
+    let mut registry = cached_registry(false, verbose).await;
+    let src_path = parse_path(&mut registry, source).await?;
+    let dst_path = parse_path(&mut registry, dest).await?;
+    let src_backend = registry.get_or_err(&src_path.backend)?;
+    let dst_backend = registry.get_or_err(&dst_path.backend)?;
+
+    if !recursive && src_backend.get_metadata(&src_path).await?.is_directory() {
+        return Err(CfkError::Other(format!(
+            "{} is a directory (use -r to copy it)",
+            src_path
+        )));
+    }
+    let target = copy_target(&src_path, dst_backend.as_ref(), &dst_path).await?;
 
     if verbose {
-        eprintln!("Copying: {} -> {}", src_path, dst_path);
+        eprintln!("Copying: {} -> {}", src_path, target);
     }
 
-    // Check if source and dest are on the same backend
-    if src_path.backend == dst_path.backend {
-        let backend = registry.get_or_err(&src_path.backend)?;
-        let options = CopyOptions {
-            overwrite: force,
-            preserve_metadata: true,
-        };
-        backend.copy(&src_path, &dst_path, &options).await?;
-    } else {
-        // Cross-backend copy: read from source, write to dest
-        let src_backend = registry.get_or_err(&src_path.backend)?;
-        let dst_backend = registry.get_or_err(&dst_path.backend)?;
+    let progress = Arc::new(CopyProgress::new());
+    let mut engine = TransferEngine::new(src_backend, dst_backend, options)
+        .with_progress(progress.clone());
+    // Without the store, large uploads still work but cannot be resumed
+    match open_upload_store() {
+        Ok(uploads) => engine = engine.with_uploads(uploads),
+        Err(e) if verbose => eprintln!("Resumable uploads disabled: {}", e),
+        Err(_) => {}
+    }
+    let report = engine.copy(&src_path, &target).await?;
+    progress.finish();
+
+    println!(
+        "Copied {} files ({}) -> {}",
+        report.files,
+        bytesize::ByteSize(report.bytes),
+        target
+    );
+    if !report.errors.is_empty() {
+        for error in &report.errors {
+            eprintln!("{} {}", style("Error:").red(), error);
+        }
+        return Err(CfkError::Other(format!("{} files failed", report.errors.len())));
+    }
+    Ok(())
+}
 
-        let read_options = ReadOptions::default();
-        let stream = src_backend.read_file(&src_path, &read_options).await?;
+fn open_upload_store() -> CfkResult<cfk_cache::UploadStore> {
+    let path = cfk_cache::UploadStore::default_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    cfk_cache::UploadStore::open(path)
+}
 
-        let write_options = WriteOptions {
-            overwrite: force,
-            create_parents: true,
-            ..Default::default()
-        };
+/// Progress bars for `cp`: one per file in flight plus an overall one
+struct CopyProgress {
+    bars: indicatif::MultiProgress,
+    total: indicatif::ProgressBar,
+    files: std::sync::Mutex<std::collections::HashMap<String, indicatif::ProgressBar>>,
+    queued: AtomicU64,
+    done: AtomicU64,
+}
 
-        // Get source metadata for size hint
-        let src_meta = src_backend.get_metadata(&src_path).await?;
-        dst_backend
-            .write_file_stream(&dst_path, stream, src_meta.metadata.size, &write_options)
-            .await?;
+impl CopyProgress {
+    fn new() -> Self {
+        let bars = indicatif::MultiProgress::new();
+        let total = bars.add(indicatif::ProgressBar::new(0));
+        total.set_style(
+            indicatif::ProgressStyle::with_template(
+                "{msg:>12} [{wide_bar}] {bytes}/{total_bytes} {binary_bytes_per_sec} {eta}",
+            )
+            .expect("valid template")
+            .progress_chars("=> "),
+        );
+        Self {
+            bars,
+            total,
+            files: Default::default(),
+            queued: AtomicU64::new(0),
+            done: AtomicU64::new(0),
+        }
     }
 
-    println!("Copied {} -> {}", source, dest);
-    Ok(())
+    fn update_message(&self) {
+        let done = self.done.load(Ordering::Relaxed);
+        let queued = self.queued.load(Ordering::Relaxed);
+        self.total.set_message(format!("{}/{} files", done, queued));
+    }
+
+    fn finish(&self) {
+        self.total.finish_and_clear();
+    }
+}
+
+impl cfk_sync::TransferProgress for CopyProgress {
+    fn queued(&self, files: u64, bytes: u64) {
+        self.total.set_length(bytes);
+        self.queued.store(files, Ordering::Relaxed);
+        self.update_message();
+    }
+
+    fn file_started(&self, path: &str, size: Option<u64>) {
+        let bar = self.bars.add(indicatif::ProgressBar::new(size.unwrap_or(0)));
+        bar.set_style(
+            indicatif::ProgressStyle::with_template("  {bytes:>10}/{total_bytes:<10} {msg}")
+                .expect("valid template"),
+        );
+        bar.set_message(path.to_string());
+        self.files.lock().unwrap().insert(path.to_string(), bar);
+    }
+
+    fn file_progress(&self, path: &str, bytes: u64) {
+        self.total.inc(bytes);
+        if let Some(bar) = self.files.lock().unwrap().get(path) {
+            bar.inc(bytes);
+        }
+    }
+
+    fn file_finished(&self, path: &str, _result: &CfkResult<u64>) {
+        if let Some(bar) = self.files.lock().unwrap().remove(path) {
+            bar.finish_and_clear();
+            self.bars.remove(&bar);
+        }
+        self.done.fetch_add(1, Ordering::Relaxed);
+        self.update_message();
+    }
 }
 
 /// Move/rename files
 pub async fn mv(source: &str, dest: &str, force: bool, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
-    let src_path = parse_path(source)?;
-    let dst_path = parse_path(dest)?;
+    let mut registry = cached_registry(false, verbose).await;
+    let src_path = parse_path(&mut registry, source).await?;
+    let dst_path = parse_path(&mut registry, dest).await?;
 
     if verbose {
         eprintln!("Moving: {} -> {}", src_path, dst_path);
@@ -250,7 +516,11 @@ pub async fn mv(source: &str, dest: &str, force: bool, verbose: bool) -> CfkResu
         backend.rename(&src_path, &dst_path, &options).await?;
     } else {
         // Cross-backend: copy then delete
-        cp(source, dest, true, force, verbose).await?;
+        let options = cfk_sync::TransferOptions {
+            overwrite: force,
+            ..Default::default()
+        };
+        cp(source, dest, true, options, verbose).await?;
         rm(&[source.to_string()], true, true, verbose).await?;
     }
 
@@ -260,10 +530,10 @@ pub async fn mv(source: &str, dest: &str, force: bool, verbose: bool) -> CfkResu
 
 /// Remove files or directories
 pub async fn rm(paths: &[String], recursive: bool, force: bool, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
+    let mut registry = cached_registry(false, verbose).await;
 
     for path in paths {
-        let vpath = parse_path(path)?;
+        let vpath = parse_path(&mut registry, path).await?;
 
         if verbose {
             eprintln!("Removing: {}", vpath);
@@ -281,10 +551,10 @@ pub async fn rm(paths: &[String], recursive: bool, force: bool, verbose: bool) -
 
 /// Create directories
 pub async fn mkdir(paths: &[String], parents: bool, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
+    let mut registry = cached_registry(false, verbose).await;
 
     for path in paths {
-        let vpath = parse_path(path)?;
+        let vpath = parse_path(&mut registry, path).await?;
 
         if verbose {
             eprintln!("Creating directory: {}", vpath);
@@ -317,8 +587,8 @@ pub async fn mkdir(paths: &[String], parents: bool, verbose: bool) -> CfkResult<
 
 /// Show file/directory information
 pub async fn stat(path: &str, verbose: bool) -> CfkResult<()> {
-    let registry = init_registry();
-    let vpath = parse_path(path)?;
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
 
     if verbose {
         eprintln!("Getting info: {}", vpath);
@@ -353,6 +623,207 @@ pub async fn stat(path: &str, verbose: bool) -> CfkResult<()> {
     Ok(())
 }
 
+#[derive(Tabled)]
+struct VersionEntry {
+    #[tabled(rename = "Version")]
+    id: String,
+    #[tabled(rename = "Modified")]
+    modified: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Author")]
+    author: String,
+}
+
+/// The backend holding `path`, if it keeps versions
+fn versioned_backend(registry: &BackendRegistry, path: &VirtualPath) -> CfkResult<Arc<dyn StorageBackend>> {
+    let backend = registry.get_or_err(&path.backend)?;
+    if !backend.capabilities().versioning {
+        return Err(CfkError::Unsupported(format!(
+            "{} does not keep file versions",
+            path.backend
+        )));
+    }
+    Ok(backend)
+}
+
+/// List the versions of a file, newest first
+pub async fn versions(path: &str, human: bool, verbose: bool) -> CfkResult<()> {
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
+    let backend = versioned_backend(&registry, &vpath)?;
+
+    if verbose {
+        eprintln!("Listing versions: {}", vpath);
+    }
+
+    let versions = backend.get_versions(&vpath).await?;
+    if versions.is_empty() {
+        println!("(no versions)");
+        return Ok(());
+    }
+
+    let entries: Vec<VersionEntry> = versions
+        .into_iter()
+        .map(|v| VersionEntry {
+            id: v.id,
+            modified: format_time(Some(v.modified)),
+            size: format_size(v.size, human),
+            author: v.author.unwrap_or_else(|| "-".to_string()),
+        })
+        .collect();
+    println!("{}", Table::new(entries));
+    Ok(())
+}
+
+/// Write an earlier version of a file over it
+pub async fn restore(path: &str, version_id: &str, verbose: bool) -> CfkResult<()> {
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
+    let backend = versioned_backend(&registry, &vpath)?;
+
+    // Some providers address versions on their own, so make sure this one
+    // belongs to the file
+    let version = backend
+        .get_versions(&vpath)
+        .await?
+        .into_iter()
+        .find(|v| v.id == version_id)
+        .ok_or_else(|| CfkError::NotFound(format!("version {} of {}", version_id, vpath)))?;
+
+    if verbose {
+        eprintln!("Restoring: {} from version {} of {}", vpath, version.id, format_time(Some(version.modified)));
+    }
+
+    let data = backend.get_version(&vpath, &version.id).await?;
+    let options = WriteOptions {
+        overwrite: true,
+        ..Default::default()
+    };
+    let entry = backend
+        .write_file_stream(&vpath, data, version.size, &options)
+        .await?;
+
+    println!(
+        "Restored {} to version {} ({})",
+        vpath,
+        version.id,
+        bytesize::ByteSize(entry.metadata.size.or(version.size).unwrap_or(0))
+    );
+    Ok(())
+}
+
+/// Print change notifications for a path until interrupted
+pub async fn watch(path: &str, recursive: bool, verbose: bool) -> CfkResult<()> {
+    use cfk_core::ChangeKind;
+
+    let mut registry = cached_registry(false, verbose).await;
+    let vpath = parse_path(&mut registry, path).await?;
+    let backend = registry.get_or_err(&vpath.backend)?;
+    if !backend.capabilities().watch {
+        return Err(CfkError::Unsupported(format!(
+            "{} cannot report changes",
+            backend.display_name()
+        )));
+    }
+
+    if verbose {
+        eprintln!("Watching: {}{}", vpath, if recursive { " (recursive)" } else { "" });
+    }
+
+    let mut changes = backend.watch(&vpath, recursive).await?;
+    while let Some(change) = changes.next().await {
+        // The stream keeps trying after an error
+        let change = match change {
+            Ok(change) => change,
+            Err(e) => {
+                eprintln!("{} {}", style("Error:").red(), e);
+                continue;
+            }
+        };
+        match change.kind {
+            ChangeKind::Created => println!("created  {}", change.path),
+            ChangeKind::Modified => println!("modified {}", change.path),
+            ChangeKind::Deleted => println!("deleted  {}", change.path),
+            ChangeKind::Renamed { from } => println!("renamed  {} -> {}", from, change.path),
+            ChangeKind::Rescan => println!("rescan   {}", change.path),
+        }
+    }
+
+    Ok(())
+}
+
+/// Keep a path available offline, or list the pins and queued changes
+pub async fn pin(path: Option<&str>, verbose: bool) -> CfkResult<()> {
+    let Some(path) = path else {
+        return list_pins(verbose).await;
+    };
+    if OFFLINE.load(Ordering::Relaxed) {
+        return Err(CfkError::Other("pinning downloads from the remote; run without --offline".into()));
+    }
+
+    let mut registry = init_registry();
+    let vpath = parse_path(&mut registry, path).await?;
+    let backend = registry.get_or_err(&vpath.backend)?;
+    if backend.capabilities().offline {
+        println!("{} is available offline already", vpath);
+        return Ok(());
+    }
+
+    let cached = CachedBackend::new(backend, open_cache().await?);
+    let report = cached.pin(&vpath).await?;
+    println!(
+        "Pinned {}: {} file(s), {} downloaded ({})",
+        vpath,
+        report.files,
+        report.downloaded,
+        bytesize::ByteSize(report.bytes)
+    );
+    Ok(())
+}
+
+async fn list_pins(verbose: bool) -> CfkResult<()> {
+    let stores = open_cache().await?;
+    let pins = stores.metadata.pins();
+    if pins.is_empty() {
+        println!("Nothing pinned");
+    }
+    for pin in pins {
+        println!("{}", pin);
+    }
+
+    let registry = init_registry();
+    let mut ids = remote_ids(&registry);
+    ids.sort();
+    for id in ids {
+        let queued = stores.metadata.queued_changes(&id).await?;
+        if queued.is_empty() {
+            continue;
+        }
+        println!("{} change(s) queued for {}", queued.len(), id);
+        if verbose {
+            for (_, change) in queued {
+                match change {
+                    QueuedChange::Write { path, .. } => println!("  write  {}", path),
+                    QueuedChange::CreateDirectory { path } => println!("  mkdir  {}", path),
+                    QueuedChange::Delete { path, .. } => println!("  delete {}", path),
+                    QueuedChange::Rename { from, to } => println!("  rename {} -> {}", from, to),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Let pinned content be evicted again
+pub async fn unpin(path: &str, _verbose: bool) -> CfkResult<()> {
+    let mut registry = init_registry();
+    let vpath = parse_path(&mut registry, path).await?;
+    let released = open_cache().await?.unpin(&vpath).await?;
+    println!("Unpinned {}: {} file(s) may be evicted", vpath, released);
+    Ok(())
+}
+
 /// List registered backends
 pub async fn backends(_verbose: bool) -> CfkResult<()> {
     let registry = init_registry();
@@ -368,6 +839,9 @@ pub async fn backends(_verbose: bool) -> CfkResult<()> {
             println!("  {} ({}) - {}", id, backend.display_name(), available);
         }
     }
+    for (id, reason) in registry.failures() {
+        println!("  {} - {}: {}", id, style("broken").red(), reason);
+    }
 
     Ok(())
 }
@@ -399,3 +873,572 @@ pub async fn df(backend_id: &str, verbose: bool) -> CfkResult<()> {
 
     Ok(())
 }
+
+/// Describe one side of a sync conflict
+fn describe_snapshot(snapshot: Option<&cfk_sync::Snapshot>) -> String {
+    match snapshot {
+        None => "deleted".to_string(),
+        Some(s) if s.directory => "directory".to_string(),
+        Some(s) => {
+            let modified = s
+                .modified
+                .and_then(|t| DateTime::<Utc>::from_timestamp(t, 0));
+            format!("{}, modified {}", format_size(s.size, true), format_time(modified))
+        }
+    }
+}
+
+/// Ask the user how to settle a sync conflict, on a blocking thread
+fn prompt_conflict(conflict: cfk_sync::Conflict) -> futures::future::BoxFuture<'static, cfk_sync::Resolution> {
+    Box::pin(async move {
+        tokio::task::spawn_blocking(move || ask_conflict(&conflict))
+            .await
+            .unwrap_or(cfk_sync::Resolution::Skip)
+    })
+}
+
+fn ask_conflict(conflict: &cfk_sync::Conflict) -> cfk_sync::Resolution {
+    use cfk_sync::Resolution;
+
+    eprintln!("{} {}", style("Conflict:").yellow().bold(), conflict.path);
+    eprintln!("  source:      {}", describe_snapshot(conflict.source.as_ref()));
+    eprintln!("  destination: {}", describe_snapshot(conflict.destination.as_ref()));
+
+    let choices = ["Keep source", "Keep destination", "Keep both", "Skip"];
+    let choice = dialoguer::Select::new()
+        .with_prompt("Resolve")
+        .items(&choices)
+        .default(3)
+        .interact()
+        .unwrap_or(3);
+
+    match choice {
+        0 => Resolution::KeepSource,
+        1 => Resolution::KeepDestination,
+        2 => Resolution::KeepBoth,
+        _ => Resolution::Skip,
+    }
+}
+
+/// Two-way sync between two directories
+pub async fn sync(
+    source: &str,
+    dest: &str,
+    dry_run: bool,
+    conflict: &str,
+    state_path: Option<&str>,
+    all: bool,
+    verbose: bool,
+) -> CfkResult<()> {
+    use cfk_sync::{ConflictPolicy, SyncEngine, SyncState};
+
+    let mut registry = cached_registry(true, verbose).await;
+    let src_path = parse_path(&mut registry, source).await?;
+    let dst_path = parse_path(&mut registry, dest).await?;
+    let policy: ConflictPolicy = conflict.parse()?;
+
+    let state_path = state_path.map(PathBuf::from).unwrap_or_else(SyncState::default_path);
+    if verbose {
+        eprintln!("Syncing: {} <-> {}", src_path, dst_path);
+        eprintln!("Using state at {}", state_path.display());
+    }
+    if let Some(parent) = state_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let state = SyncState::open(&state_path, &src_path, &dst_path)?;
+
+    let mut engine = SyncEngine::new(
+        registry.get_or_err(&src_path.backend)?,
+        src_path.clone(),
+        registry.get_or_err(&dst_path.backend)?,
+        dst_path.clone(),
+        state,
+    )
+    .with_policy(policy)
+    .with_hidden(all);
+
+    // A dry run lists conflicts instead of asking about them
+    if !dry_run {
+        engine = engine.with_prompt(Box::new(prompt_conflict));
+    }
+
+    let plan = engine.plan().await?;
+    if plan.is_empty() {
+        println!("Already in sync");
+        return Ok(());
+    }
+
+    for action in plan.changes() {
+        println!("{}", action);
+    }
+    if dry_run {
+        println!("{} changes (dry run, nothing done)", plan.changes().count());
+        return Ok(());
+    }
+
+    let report = engine.apply(&plan).await?;
+    println!(
+        "Applied {} changes, skipped {} conflicts",
+        report.applied, report.skipped
+    );
+    if !report.errors.is_empty() {
+        for error in &report.errors {
+            eprintln!("{} {}", style("Error:").red(), error);
+        }
+        return Err(CfkError::Other(format!("{} actions failed", report.errors.len())));
+    }
+
+    Ok(())
+}
+
+/// Make a destination match a source, one way
+pub async fn mirror(
+    source: &str,
+    dest: &str,
+    options: cfk_sync::MirrorOptions,
+    dry_run: bool,
+    verbose: bool,
+) -> CfkResult<()> {
+    use cfk_sync::MirrorEngine;
+
+    let mut registry = cached_registry(true, verbose).await;
+    let src_path = parse_path(&mut registry, source).await?;
+    let dst_path = parse_path(&mut registry, dest).await?;
+    if verbose {
+        eprintln!("Mirroring: {} -> {}", src_path, dst_path);
+    }
+
+    let engine = MirrorEngine::new(
+        registry.get_or_err(&src_path.backend)?,
+        src_path,
+        registry.get_or_err(&dst_path.backend)?,
+        dst_path,
+        options,
+    );
+
+    let plan = engine.plan().await?;
+    if plan.is_empty() {
+        println!("Up to date ({} files unchanged)", plan.unchanged);
+        return Ok(());
+    }
+
+    for action in &plan.actions {
+        println!("{}", action);
+    }
+    if dry_run {
+        println!(
+            "{} actions, {} to send (dry run, nothing done)",
+            plan.actions.len(),
+            bytesize::ByteSize(plan.bytes())
+        );
+        return Ok(());
+    }
+
+    let report = engine.apply(&plan).await?;
+    println!(
+        "Sent {} files ({}), {} unchanged, {} directories created, {} deleted",
+        report.files_transferred,
+        bytesize::ByteSize(report.bytes_transferred),
+        report.files_unchanged,
+        report.directories_created,
+        report.deleted
+    );
+    if !report.errors.is_empty() {
+        for error in &report.errors {
+            eprintln!("{} {}", style("Error:").red(), error);
+        }
+        return Err(CfkError::Other(format!("{} actions failed", report.errors.len())));
+    }
+
+    Ok(())
+}
+
+/// Crawl a backend into the search index
+pub async fn index(
+    backend_id: &str,
+    path: Option<&str>,
+    index_dir: Option<&str>,
+    full: bool,
+    concurrency: usize,
+    max_content_size: u64,
+    verbose: bool,
+) -> CfkResult<()> {
+    use cfk_search::{Indexer, IndexerConfig, SearchIndex, TantivyIndex};
+
+    let registry = cached_registry(true, verbose).await;
+    let backend = registry.get_or_err(backend_id)?;
+
+    let index_dir = index_dir.map(PathBuf::from).unwrap_or_else(TantivyIndex::default_path);
+    if verbose {
+        eprintln!("Using index at {}", index_dir.display());
+    }
+    let index = Arc::new(TantivyIndex::new(&index_dir)?);
+
+    let config = IndexerConfig {
+        checkpoint_dir: index_dir.join("checkpoints"),
+        concurrency,
+        max_content_bytes: max_content_size,
+        ..Default::default()
+    };
+    let indexer = Indexer::new(index.clone(), config);
+    if full {
+        indexer.reset(backend_id).await?;
+    }
+
+    let root = match path {
+        Some(p) => VirtualPath::new(backend_id, p),
+        None => VirtualPath::root(backend_id),
+    };
+    println!("Indexing {}...", root);
+    let report = indexer.crawl_from(backend.as_ref(), &root).await?;
+    index.commit()?;
+
+    println!(
+        "Scanned {}, indexed {}, unchanged {}, removed {}",
+        report.scanned, report.indexed, report.unchanged, report.removed
+    );
+    if !report.errors.is_empty() {
+        eprintln!("{} {} errors", style("Warning:").yellow(), report.errors.len());
+        if verbose {
+            for error in &report.errors {
+                eprintln!("  {}", error);
+            }
+        }
+    }
+
+    let stats = index.stats().await?;
+    println!(
+        "Index: {} documents, {}",
+        stats.document_count,
+        bytesize::ByteSize(stats.size_bytes)
+    );
+
+    Ok(())
+}
+
+/// Mount a backend and serve it until interrupted
+pub async fn mount(backend_id: &str, mount_point: &str, options: cfk_vfs::MountOptions, verbose: bool) -> CfkResult<()> {
+    let registry = init_registry();
+
+    if verbose {
+        eprintln!("Mounting {} at {}", backend_id, mount_point);
+    }
+
+    let mount = cfk_vfs::VfsMount::mount(&registry, backend_id, mount_point, options)?;
+    println!("Mounted {} at {} (Ctrl-C to unmount)", style(backend_id).bold(), mount_point);
+
+    // Stop on Ctrl-C, or when the mount goes away underneath us (umount)
+    let mut poll = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result?;
+                break;
+            }
+            _ = poll.tick() => {
+                if !mount.is_mounted() {
+                    println!("{} was unmounted externally", mount_point);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    mount.unmount()?;
+    println!("Unmounted {}", mount_point);
+    Ok(())
+}
+
+/// Export a directory as a 9P2000.L server until Ctrl-C
+pub async fn serve_9p(
+    remote: &str,
+    root: &str,
+    listen: &str,
+    unix: Option<&std::path::Path>,
+    options: cfk_providers::NinePServerOptions,
+    verbose: bool,
+) -> CfkResult<()> {
+    let registry = init_registry();
+    let backend = registry.get_or_err(remote)?;
+    let root = VirtualPath::new(remote, root);
+    let entry = backend.get_metadata(&root).await?;
+    if !entry.is_directory() {
+        return Err(CfkError::NotADirectory(root.to_string()));
+    }
+
+    if verbose {
+        eprintln!("Serving {} (msize {}, read-only: {})", root, options.msize, options.read_only);
+    }
+    let server = Arc::new(cfk_providers::NinePServer::new(backend, root.clone(), options));
+
+    let serving = match unix {
+        #[cfg(unix)]
+        Some(socket) => {
+            // A socket left behind by an earlier run would fail the bind
+            if socket.exists() {
+                std::fs::remove_file(socket)?;
+            }
+            let listener = tokio::net::UnixListener::bind(socket)?;
+            println!("Serving {} over 9P at unix:{} (Ctrl-C to stop)", style(&root).bold(), socket.display());
+            tokio::spawn(server.serve_unix(listener))
+        }
+        #[cfg(not(unix))]
+        Some(_) => return Err(CfkError::Unsupported("Unix sockets".into())),
+        None => {
+            let listener = tokio::net::TcpListener::bind(listen).await?;
+            println!("Serving {} over 9P at {} (Ctrl-C to stop)", style(&root).bold(), listener.local_addr()?);
+            tokio::spawn(server.serve(listener))
+        }
+    };
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        result = serving => match result {
+            Ok(result) => result?,
+            Err(e) => return Err(CfkError::Other(e.to_string())),
+        },
+    }
+    if let Some(socket) = unix {
+        let _ = std::fs::remove_file(socket);
+    }
+    println!("Stopped");
+    Ok(())
+}
+
+/// Export a remote over WebDAV until Ctrl-C
+pub async fn serve_webdav(
+    remote: &str,
+    root: &str,
+    listen: &str,
+    user: Option<String>,
+    password: Option<String>,
+    read_only: bool,
+    verbose: bool,
+) -> CfkResult<()> {
+    let registry = init_registry();
+    let backend = registry.get_or_err(remote)?;
+    let root = VirtualPath::new(remote, root);
+    let entry = backend.get_metadata(&root).await?;
+    if !entry.is_directory() {
+        return Err(CfkError::NotADirectory(root.to_string()));
+    }
+
+    let credentials = match user {
+        Some(user) => {
+            let password = password
+                .or_else(|| std::env::var("CFK_WEBDAV_PASSWORD").ok())
+                .ok_or_else(|| CfkError::Config("--user needs --password or CFK_WEBDAV_PASSWORD".into()))?;
+            Some((user, password))
+        }
+        None => None,
+    };
+    if credentials.is_none() && verbose {
+        eprintln!("No --user given: anyone who can reach {} has access", listen);
+    }
+
+    let options = cfk_providers::WebDavServerOptions { read_only, credentials };
+    let server = Arc::new(cfk_providers::WebDavServer::new(backend, root.clone(), options));
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    println!(
+        "Serving {} over WebDAV at http://{}/ (Ctrl-C to stop)",
+        style(&root).bold(),
+        listener.local_addr()?
+    );
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        result = server.serve(listener) => result?,
+    }
+    println!("Stopped");
+    Ok(())
+}
+
+/// Serve a directory of a remote as an S3 bucket
+pub async fn serve_s3(
+    remote: &str,
+    root: &str,
+    listen: &str,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    mut options: cfk_providers::S3GatewayOptions,
+    verbose: bool,
+) -> CfkResult<()> {
+    let registry = init_registry();
+    let backend = registry.get_or_err(remote)?;
+    let root = VirtualPath::new(remote, root);
+    let entry = backend.get_metadata(&root).await?;
+    if !entry.is_directory() {
+        return Err(CfkError::NotADirectory(root.to_string()));
+    }
+
+    options.credentials = match access_key {
+        Some(access_key) => {
+            let secret = secret_key
+                .or_else(|| std::env::var("CFK_S3_SECRET_KEY").ok())
+                .ok_or_else(|| CfkError::Config("--access-key needs --secret-key or CFK_S3_SECRET_KEY".into()))?;
+            Some((access_key, secret))
+        }
+        None => None,
+    };
+    if options.credentials.is_none() && verbose {
+        eprintln!("No --access-key given: anyone who can reach {} has access", listen);
+    }
+
+    let bucket = options.bucket.clone();
+    let gateway = Arc::new(cfk_providers::S3Gateway::new(backend, root.clone(), options));
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    println!(
+        "Serving {} as bucket {} at http://{}/ (Ctrl-C to stop)",
+        style(&root).bold(),
+        style(&bucket).bold(),
+        listener.local_addr()?
+    );
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        result = gateway.serve(listener) => result?,
+    }
+    println!("Stopped");
+    Ok(())
+}
+
+/// Add a named remote to the config file
+pub async fn remote_add(name: &str, provider: &str, options: &[String], force: bool, verbose: bool) -> CfkResult<()> {
+    let path = CfkConfig::default_path();
+    let mut config = CfkConfig::load(&path)?;
+
+    let mut remote = RemoteConfig::new(provider);
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| CfkError::Config(format!("expected key=value, got {:?}", option)))?;
+        remote.set_option(key.trim(), value)?;
+    }
+
+    // Refuse options the provider would not understand, and layered
+    // remotes over a remote that doesn't exist
+    remote.build_with(name, None, &init_registry())?;
+    config.add_remote(name, remote, force)?;
+    config.save(&path)?;
+
+    if verbose {
+        eprintln!("Wrote {}", path.display());
+    }
+    println!("Added remote {} ({}): cfk://{}/", style(name).bold(), provider, name);
+    Ok(())
+}
+
+/// List the configured remotes
+pub async fn remote_list(verbose: bool) -> CfkResult<()> {
+    let path = CfkConfig::default_path();
+    let config = CfkConfig::load(&path)?;
+
+    if verbose {
+        eprintln!("Config: {}", path.display());
+    }
+    if config.remotes.is_empty() {
+        println!("No remotes configured");
+        return Ok(());
+    }
+    let registry = init_registry();
+    for (name, remote) in &config.remotes {
+        match registry.get_or_err(name) {
+            Ok(_) => println!("  {} ({})", style(name).bold(), remote.provider),
+            Err(e) => println!("  {} ({}) - {}: {}", style(name).bold(), remote.provider, style("broken").red(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove a remote from the config file
+pub async fn remote_remove(name: &str, verbose: bool) -> CfkResult<()> {
+    let path = CfkConfig::default_path();
+    let mut config = CfkConfig::load(&path)?;
+    let remote = config.remove_remote(name)?;
+    config.save(&path)?;
+    if remote.uses_oauth() {
+        if let Some(store) = open_token_store() {
+            store.remove(name)?;
+        }
+    }
+
+    if verbose {
+        eprintln!("Wrote {}", path.display());
+    }
+    println!("Removed remote {}", name);
+    Ok(())
+}
+
+/// Print a remote's settings, with secrets masked
+pub async fn remote_show(name: &str, _verbose: bool) -> CfkResult<()> {
+    let config = CfkConfig::load(CfkConfig::default_path())?;
+    let remote = config
+        .remotes
+        .get(name)
+        .ok_or_else(|| CfkError::BackendNotFound(name.to_string()))?;
+
+    let mut shown = CfkConfig::default();
+    shown.remotes.insert(name.to_string(), remote.redacted());
+    let text = toml::to_string(&shown).map_err(|e| CfkError::Serialization(e.to_string()))?;
+    print!("{}", text);
+    Ok(())
+}
+
+/// Authorize an OAuth remote in the browser and store its tokens
+pub async fn auth(name: &str, open_browser: bool, verbose: bool) -> CfkResult<()> {
+    let config = CfkConfig::load(CfkConfig::default_path())?;
+    let remote = config
+        .remotes
+        .get(name)
+        .ok_or_else(|| CfkError::BackendNotFound(name.to_string()))?;
+    let store: Arc<dyn TokenStore> = Arc::new(FileTokenStore::open_default()?);
+    let backend = remote.build_oauth(name, Some(&store))?;
+
+    // Listen before the browser can possibly come back
+    let listener = cfk_providers::LoopbackListener::bind(backend.redirect_uri()).await?;
+    let request = backend.start_auth();
+
+    println!("Authorize cfk for {} at:\n\n  {}\n", style(name).bold(), request.url);
+    if open_browser {
+        launch_browser(&request.url);
+    }
+    if verbose {
+        eprintln!("Waiting for the redirect to {}", backend.redirect_uri());
+    }
+
+    let code = tokio::time::timeout(
+        std::time::Duration::from_secs(300),
+        listener.wait(&request.state),
+    )
+    .await
+    .map_err(|_| CfkError::Timeout)??;
+    let tokens = backend.complete_auth(&code, request.verifier).await?;
+
+    println!("Authorized {}", style(name).bold());
+    if tokens.refresh_token.is_none() {
+        println!(
+            "{} no refresh token was granted; run `cfk auth {}` again when the access token expires",
+            style("Note:").yellow(),
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Best effort; the URL is printed either way
+fn launch_browser(url: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let _ = command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+}
@@ -5,8 +5,9 @@
 
 mod commands;
 
-use clap::{Parser, Subcommand};
+use clap::{builder::PossibleValuesParser, Parser, Subcommand};
 use std::process::ExitCode;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "cfk")]
@@ -18,6 +19,11 @@ struct Cli {
     /// Verbose output
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Work from the cache: pinned content stays readable and changes queue
+    /// until a later run without --offline
+    #[arg(long, global = true)]
+    offline: bool,
 }
 
 #[derive(Subcommand)]
@@ -63,6 +69,14 @@ enum Commands {
         /// Force overwrite existing files
         #[arg(short, long)]
         force: bool,
+
+        /// Number of files copied in parallel
+        #[arg(short = 'j', long, default_value = "4")]
+        jobs: usize,
+
+        /// Do not carry modification times and permissions over
+        #[arg(long)]
+        no_preserve: bool,
     },
 
     /// Move or rename files
@@ -110,6 +124,48 @@ enum Commands {
         path: String,
     },
 
+    /// List the versions a provider keeps of a file, newest first
+    Versions {
+        /// File whose history to show
+        path: String,
+
+        /// Human-readable sizes
+        #[arg(short = 'H', long)]
+        human: bool,
+    },
+
+    /// Bring back an earlier version of a file, as its newest version
+    Restore {
+        /// File to restore
+        path: String,
+
+        /// Version to restore, as listed by `cfk versions`
+        #[arg(long)]
+        version: String,
+    },
+
+    /// Print changes to a path as they happen
+    Watch {
+        /// File or directory to watch
+        path: String,
+
+        /// Include everything below a directory, not just its entries
+        #[arg(short, long)]
+        recursive: bool,
+    },
+
+    /// Keep a file or directory available offline, or list what is pinned
+    Pin {
+        /// Path to pin; without one, list pins and queued changes
+        path: Option<String>,
+    },
+
+    /// Let pinned content be evicted from the cache again
+    Unpin {
+        /// Pinned path
+        path: String,
+    },
+
     /// List registered backends
     Backends,
 
@@ -119,11 +175,295 @@ enum Commands {
         #[arg(default_value = "local")]
         backend: String,
     },
+
+    /// Two-way sync between two directories, on any backends
+    Sync {
+        /// Source directory
+        source: String,
+
+        /// Destination directory
+        dest: String,
+
+        /// Show what would be done without changing anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Conflict policy: newer-wins, keep-both or prompt
+        #[arg(long, default_value = "newer-wins")]
+        conflict: String,
+
+        /// Sync state database (defaults to the user data directory)
+        #[arg(long)]
+        state: Option<String>,
+
+        /// Include hidden files
+        #[arg(short, long)]
+        all: bool,
+    },
+
+    /// Make a destination directory match a source, one way
+    Mirror {
+        /// Source directory
+        source: String,
+
+        /// Destination directory
+        dest: String,
+
+        /// How to detect changed files: size-mtime, checksum or blake3
+        #[arg(long, default_value = "size-mtime")]
+        compare: cfk_sync::CompareMode,
+
+        /// Delete destination files that are not in the source
+        #[arg(long)]
+        delete: bool,
+
+        /// Only mirror files matching this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip files and directories matching this glob (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Show what would be done without changing anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Include hidden files
+        #[arg(short, long)]
+        all: bool,
+    },
+
+    /// Crawl a backend into the search index
+    Index {
+        /// Backend to index
+        backend: String,
+
+        /// Only crawl below this path
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Index directory (defaults to the user data directory)
+        #[arg(long)]
+        index_dir: Option<String>,
+
+        /// Forget the previous crawl and re-index everything
+        #[arg(long)]
+        full: bool,
+
+        /// Number of entries fetched and indexed at once
+        #[arg(short = 'j', long, default_value_t = 8)]
+        concurrency: usize,
+
+        /// Largest file whose content is indexed, in bytes
+        #[arg(long, default_value_t = 10 * 1024 * 1024)]
+        max_content_size: u64,
+    },
+
+    /// Mount a backend as a local filesystem (FUSE)
+    Mount {
+        /// Backend to mount
+        backend: String,
+
+        /// Local directory to mount at
+        mountpoint: String,
+
+        /// Mount read-only
+        #[arg(long)]
+        read_only: bool,
+
+        /// Allow other users to access the mount
+        #[arg(long)]
+        allow_other: bool,
+
+        /// Allow root to access the mount
+        #[arg(long)]
+        allow_root: bool,
+
+        /// Cache attributes in the kernel and backend reads
+        #[arg(long)]
+        cache: bool,
+
+        /// Attribute cache timeout in seconds (implies --cache)
+        #[arg(long)]
+        cache_timeout: Option<u64>,
+
+        /// Largest file that can be written, in bytes (default 1 GiB)
+        #[arg(long)]
+        max_file_size: Option<u64>,
+
+        /// Log every filesystem operation
+        #[arg(long)]
+        debug: bool,
+    },
+
+    /// Authorize an OAuth remote (Dropbox, Google Drive, OneDrive, Box)
+    Auth {
+        /// Remote to authorize
+        remote: String,
+
+        /// Print the authorization URL without opening a browser
+        #[arg(long)]
+        no_browser: bool,
+    },
+
+    /// Manage named remotes in the config file
+    Remote {
+        #[command(subcommand)]
+        action: RemoteAction,
+    },
+
+    /// Export a path over a network file protocol
+    Serve {
+        #[command(subcommand)]
+        protocol: ServeProtocol,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServeProtocol {
+    /// Serve 9P2000.L, mountable with `mount -t 9p`
+    #[command(name = "9p")]
+    NineP {
+        /// Remote to export, by name (`local` for this machine)
+        #[arg(long)]
+        remote: String,
+
+        /// Directory inside the remote to export
+        #[arg(long, default_value = "/")]
+        root: String,
+
+        /// TCP address to listen on
+        #[arg(long, default_value = "127.0.0.1:5640", conflicts_with = "unix")]
+        listen: String,
+
+        /// Listen on a Unix socket instead of TCP
+        #[arg(long)]
+        unix: Option<PathBuf>,
+
+        /// Refuse all changes
+        #[arg(long)]
+        read_only: bool,
+
+        /// Largest message size to negotiate
+        #[arg(long, default_value_t = 512 * 1024)]
+        msize: u32,
+
+        /// Largest file clients may write, in bytes
+        #[arg(long, default_value_t = 1024 * 1024 * 1024)]
+        max_file_size: u64,
+    },
+
+    /// Serve WebDAV (class 1 and 2), for file managers and office suites
+    #[command(name = "webdav")]
+    WebDav {
+        /// Remote to export, by name (`local` for this machine)
+        #[arg(long)]
+        remote: String,
+
+        /// Directory inside the remote to export
+        #[arg(long, default_value = "/")]
+        root: String,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Require HTTP basic auth as this user; the password is read from
+        /// CFK_WEBDAV_PASSWORD unless given with --password
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Password for --user
+        #[arg(long, requires = "user")]
+        password: Option<String>,
+
+        /// Refuse all changes
+        #[arg(long)]
+        read_only: bool,
+    },
+
+    /// Serve an S3-compatible endpoint with a single bucket
+    #[command(name = "s3")]
+    S3 {
+        /// Remote to export, by name (`local` for this machine)
+        #[arg(long)]
+        remote: String,
+
+        /// Directory inside the remote to export
+        #[arg(long, default_value = "/")]
+        root: String,
+
+        /// Bucket name clients use; defaults to the remote name
+        #[arg(long)]
+        bucket: Option<String>,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        listen: String,
+
+        /// Access key requests must be signed with; the secret is read from
+        /// CFK_S3_SECRET_KEY unless given with --secret-key
+        #[arg(long)]
+        access_key: Option<String>,
+
+        /// Secret key for --access-key
+        #[arg(long, requires = "access_key")]
+        secret_key: Option<String>,
+
+        /// Region reported to clients
+        #[arg(long, default_value = "us-east-1")]
+        region: String,
+
+        /// Refuse all changes
+        #[arg(long)]
+        read_only: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteAction {
+    /// Add a remote, reachable afterwards as cfk://NAME/
+    Add {
+        /// Remote name
+        name: String,
+
+        /// Provider type
+        #[arg(value_parser = PossibleValuesParser::new(cfk_providers::config::PROVIDERS))]
+        provider: String,
+
+        /// Provider options as key=value; dotted keys set nested tables
+        options: Vec<String>,
+
+        /// Replace an existing remote of the same name
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// List configured remotes
+    #[command(alias = "ls")]
+    List,
+
+    /// Remove a remote
+    #[command(alias = "rm")]
+    Remove {
+        /// Remote name
+        name: String,
+    },
+
+    /// Show a remote's settings with secrets masked
+    Show {
+        /// Remote name
+        name: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
     let cli = Cli::parse();
+    if cli.offline {
+        commands::go_offline();
+    }
 
     let result = match cli.command {
         Commands::Ls { path, long, all, human } => {
@@ -132,8 +472,14 @@ async fn main() -> ExitCode {
         Commands::Cat { path } => {
             commands::cat(&path, cli.verbose).await
         }
-        Commands::Cp { source, dest, recursive, force } => {
-            commands::cp(&source, &dest, recursive, force, cli.verbose).await
+        Commands::Cp { source, dest, recursive, force, jobs, no_preserve } => {
+            let options = cfk_sync::TransferOptions {
+                parallelism: jobs,
+                overwrite: force,
+                preserve_metadata: !no_preserve,
+                ..Default::default()
+            };
+            commands::cp(&source, &dest, recursive, options, cli.verbose).await
         }
         Commands::Mv { source, dest, force } => {
             commands::mv(&source, &dest, force, cli.verbose).await
@@ -147,14 +493,89 @@ async fn main() -> ExitCode {
         Commands::Stat { path } => {
             commands::stat(&path, cli.verbose).await
         }
+        Commands::Versions { path, human } => {
+            commands::versions(&path, human, cli.verbose).await
+        }
+        Commands::Restore { path, version } => {
+            commands::restore(&path, &version, cli.verbose).await
+        }
+        Commands::Watch { path, recursive } => {
+            commands::watch(&path, recursive, cli.verbose).await
+        }
+        Commands::Pin { path } => {
+            commands::pin(path.as_deref(), cli.verbose).await
+        }
+        Commands::Unpin { path } => {
+            commands::unpin(&path, cli.verbose).await
+        }
         Commands::Backends => {
             commands::backends(cli.verbose).await
         }
         Commands::Df { backend } => {
             commands::df(&backend, cli.verbose).await
         }
+        Commands::Sync { source, dest, dry_run, conflict, state, all } => {
+            commands::sync(&source, &dest, dry_run, &conflict, state.as_deref(), all, cli.verbose).await
+        }
+        Commands::Mirror { source, dest, compare, delete, include, exclude, dry_run, all } => {
+            let options = cfk_sync::MirrorOptions {
+                compare,
+                delete,
+                include,
+                exclude,
+                include_hidden: all,
+            };
+            commands::mirror(&source, &dest, options, dry_run, cli.verbose).await
+        }
+        Commands::Index { backend, path, index_dir, full, concurrency, max_content_size } => {
+            commands::index(&backend, path.as_deref(), index_dir.as_deref(), full, concurrency, max_content_size, cli.verbose).await
+        }
+        Commands::Mount { backend, mountpoint, read_only, allow_other, allow_root, cache, cache_timeout, max_file_size, debug } => {
+            let options = cfk_vfs::MountOptions {
+                allow_other,
+                allow_root,
+                read_only,
+                cache: cache || cache_timeout.is_some(),
+                cache_timeout_secs: cache_timeout,
+                max_file_size,
+                debug,
+            };
+            commands::mount(&backend, &mountpoint, options, cli.verbose).await
+        }
+        Commands::Auth { remote, no_browser } => {
+            commands::auth(&remote, !no_browser, cli.verbose).await
+        }
+        Commands::Remote { action } => match action {
+            RemoteAction::Add { name, provider, options, force } => {
+                commands::remote_add(&name, &provider, &options, force, cli.verbose).await
+            }
+            RemoteAction::List => commands::remote_list(cli.verbose).await,
+            RemoteAction::Remove { name } => commands::remote_remove(&name, cli.verbose).await,
+            RemoteAction::Show { name } => commands::remote_show(&name, cli.verbose).await,
+        },
+        Commands::Serve { protocol } => match protocol {
+            ServeProtocol::NineP { remote, root, listen, unix, read_only, msize, max_file_size } => {
+                let options = cfk_providers::NinePServerOptions { read_only, msize, max_file_size };
+                commands::serve_9p(&remote, &root, &listen, unix.as_deref(), options, cli.verbose).await
+            }
+            ServeProtocol::WebDav { remote, root, listen, user, password, read_only } => {
+                commands::serve_webdav(&remote, &root, &listen, user, password, read_only, cli.verbose).await
+            }
+            ServeProtocol::S3 { remote, root, bucket, listen, access_key, secret_key, region, read_only } => {
+                let options = cfk_providers::S3GatewayOptions {
+                    bucket: bucket.unwrap_or_else(|| remote.clone()),
+                    region,
+                    credentials: None,
+                    read_only,
+                };
+                commands::serve_s3(&remote, &root, &listen, access_key, secret_key, options, cli.verbose).await
+            }
+        },
     };
 
+    if cli.verbose {
+        commands::report_retries();
+    }
     match result {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
@@ -8,7 +8,10 @@
 use crate::{
     entry::{DirectoryListing, Entry},
     error::CfkResult,
+    metadata::Metadata,
     operations::*,
+    upload::ResumableUpload,
+    watch::ChangeStream,
     VirtualPath,
 };
 
@@ -27,10 +30,16 @@ pub struct StorageCapabilities {
     pub search: bool,
     pub versioning: bool,
     pub sharing: bool,
+    /// Content stays readable without connectivity: the data is local, or a
+    /// cache holds pinned copies of it
     pub offline: bool,
     pub streaming: bool,
+    /// Reads honour `ReadOptions::range` without fetching the whole file
+    pub range_reads: bool,
     pub resumable_uploads: bool,
     pub content_hashing: bool,
+    /// [`StorageBackend::watch`] reports changes
+    pub watch: bool,
 }
 
 impl StorageCapabilities {
@@ -39,7 +48,8 @@ pub fn full() -> Self {
             read: true, write: true, delete: true, rename: true,
             copy: true, list: true, search: true, versioning: true,
             sharing: true, offline: true, streaming: true,
-            resumable_uploads: true, content_hashing: true,
+            range_reads: true, resumable_uploads: true, content_hashing: true,
+            watch: true,
         }
     }
 
@@ -51,7 +61,7 @@ pub fn local_filesystem() -> Self {
         Self {
             read: true, write: true, delete: true, rename: true,
             copy: true, list: true, search: true, offline: true,
-            streaming: true, content_hashing: true,
+            streaming: true, range_reads: true, content_hashing: true,
             ..Default::default()
         }
     }
@@ -80,6 +90,18 @@ pub struct FileVersion {
     pub author: Option<String>,
 }
 
+/// Retry counters of a backend that retries failed operations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Attempts beyond the first
+    pub retries: u64,
+    /// Failures the server attributed to rate limiting
+    pub rate_limited: u64,
+    pub token_refreshes: u64,
+    /// Operations that still failed after the last retry
+    pub exhausted: u64,
+}
+
 /// Search options
 #[derive(Debug, Clone, Default)]
 pub struct SearchOptions {
@@ -120,4 +142,40 @@ async fn get_versions(&self, _path: &VirtualPath) -> CfkResult<Vec<FileVersion>>
     async fn get_version(&self, _path: &VirtualPath, _version_id: &str) -> CfkResult<ByteStream> {
         Err(crate::CfkError::Unsupported("Versioning not supported".into()))
     }
+
+    /// Resumable upload support, for backends that advertise `resumable_uploads`
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        None
+    }
+
+    /// Obtain fresh credentials after the server rejected the current ones
+    ///
+    /// Returns `false` when the backend has no way to refresh them.
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        Ok(false)
+    }
+
+    /// Apply the modification time and permissions of `metadata`, where set
+    async fn set_metadata(&self, _path: &VirtualPath, _metadata: &Metadata) -> CfkResult<()> {
+        Err(crate::CfkError::Unsupported("Setting metadata not supported".into()))
+    }
+
+    /// Metadata of `path` as the provider has it now, past any cache
+    ///
+    /// The same as [`get_metadata`](Self::get_metadata) for backends that
+    /// keep no cache.
+    async fn fresh_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        self.get_metadata(path).await
+    }
+
+    /// How often operations were retried so far, for backends that retry
+    fn retry_stats(&self) -> Option<RetryStats> {
+        None
+    }
+
+    /// Report changes at `path`, and below it when `recursive`, for backends
+    /// that advertise `watch`
+    async fn watch(&self, _path: &VirtualPath, _recursive: bool) -> CfkResult<ChangeStream> {
+        Err(crate::CfkError::Unsupported("Watching not supported".into()))
+    }
 }
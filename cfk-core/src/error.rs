@@ -62,6 +62,9 @@ pub enum CfkError {
     #[error("Serialization error: {0}")]
     Serialization(String),
 
+    #[error("Configuration error: {0}")]
+    Config(String),
+
     #[error("Cache error: {0}")]
     Cache(String),
 
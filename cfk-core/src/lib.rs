@@ -9,9 +9,13 @@
 pub mod operations;
 pub mod path;
 pub mod platform;
+pub mod upload;
+pub mod watch;
 
 pub use backend::{StorageBackend, StorageCapabilities};
 pub use entry::{Entry, EntryKind};
 pub use error::{CfkError, CfkResult};
 pub use metadata::Metadata;
 pub use path::VirtualPath;
+pub use upload::{ResumableUpload, UploadPart, UploadSession};
+pub use watch::{ChangeEvent, ChangeKind, ChangeStream};
@@ -12,6 +12,8 @@ pub struct ListOptions {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ReadOptions {
+    /// Byte range `start..end`, end exclusive; a range running past the
+    /// end of the file is cut short there
     pub range: Option<(u64, u64)>,
     pub use_cache: bool,
 }
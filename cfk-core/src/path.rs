@@ -73,6 +73,11 @@ pub fn is_root(&self) -> bool {
         self.segments.is_empty()
     }
 
+    /// Whether this is `base` or lies below it, on the same backend
+    pub fn starts_with(&self, base: &VirtualPath) -> bool {
+        self.backend == base.backend && self.segments.starts_with(&base.segments)
+    }
+
     pub fn to_path_string(&self) -> String {
         if self.segments.is_empty() {
             "/".to_string()
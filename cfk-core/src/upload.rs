@@ -0,0 +1,119 @@
+//! Resumable uploads
+//!
+//! Large uploads go through a server-side session, so an interrupted
+//! transfer can continue where it stopped instead of starting over. A
+//! session is plain data: callers persist it between chunks and hand it
+//! back to the backend to carry on.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{error::CfkResult, operations::WriteOptions, Entry, VirtualPath};
+
+/// A chunk the server has acknowledged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadPart {
+    /// 1-based part number
+    pub number: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// Provider handle for the part (S3 ETag, Box part ID)
+    pub tag: String,
+    /// Provider checksum of the part, where the commit needs it
+    pub checksum: Option<String>,
+}
+
+/// State of an in-progress resumable upload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub path: VirtualPath,
+    /// Total size of the upload
+    pub size: u64,
+    /// Provider session handle: upload ID, session ID or upload URL
+    pub id: String,
+    /// Every chunk but the last must be exactly this long
+    pub chunk_size: u64,
+    /// Bytes the server has confirmed
+    pub offset: u64,
+    pub parts: Vec<UploadPart>,
+    pub overwrite: bool,
+    /// Provider-specific state
+    pub state: HashMap<String, String>,
+    pub created: DateTime<Utc>,
+}
+
+impl UploadSession {
+    pub fn new(
+        path: VirtualPath,
+        size: u64,
+        id: impl Into<String>,
+        chunk_size: u64,
+        overwrite: bool,
+    ) -> Self {
+        Self {
+            path,
+            size,
+            id: id.into(),
+            chunk_size,
+            offset: 0,
+            parts: Vec::new(),
+            overwrite,
+            state: HashMap::new(),
+            created: Utc::now(),
+        }
+    }
+
+    /// Length of the chunk that goes next
+    pub fn next_chunk_len(&self) -> u64 {
+        self.chunk_size.min(self.size.saturating_sub(self.offset))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.size
+    }
+
+    /// The byte range a chunk of `len` bytes covers, as `first-last/total`
+    /// for a `Content-Range` header
+    pub fn content_range(&self, len: u64) -> String {
+        format!(
+            "bytes {}-{}/{}",
+            self.offset,
+            self.offset + len.max(1) - 1,
+            self.size
+        )
+    }
+}
+
+/// Chunked uploads that survive interruption
+///
+/// Chunks go up strictly in order: each one starts at `session.offset` and
+/// is `session.next_chunk_len()` bytes long.
+#[async_trait]
+pub trait ResumableUpload: Send + Sync {
+    /// Open a session for a `size`-byte upload to `path`
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession>;
+
+    /// Upload the next chunk and advance `session.offset`
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()>;
+
+    /// Ask the server how far the upload got, updating `session`
+    ///
+    /// Fails with `NotFound` once the server has dropped the session.
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64>;
+
+    /// Commit a complete upload
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry>;
+
+    /// Discard the session and whatever was uploaded
+    async fn abort(&self, _session: &UploadSession) -> CfkResult<()> {
+        Ok(())
+    }
+}
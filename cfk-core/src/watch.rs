@@ -0,0 +1,101 @@
+//! Change notification
+//!
+//! Backends that can report changes hand out a [`ChangeStream`] from
+//! [`StorageBackend::watch`](crate::StorageBackend::watch). Events describe
+//! what happened, not the new state: consumers drop whatever they cached
+//! for the affected paths and look again when they need to.
+
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+
+use crate::{error::CfkResult, VirtualPath};
+
+/// What happened at a path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    /// Moved here from `from`
+    Renamed { from: VirtualPath },
+    /// Events were lost (a queue overflowed, a cursor expired): anything
+    /// at or below the path may have changed
+    Rescan,
+}
+
+/// A change reported by a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub path: VirtualPath,
+    pub kind: ChangeKind,
+}
+
+impl ChangeEvent {
+    pub fn new(path: VirtualPath, kind: ChangeKind) -> Self {
+        Self { path, kind }
+    }
+
+    /// Whether a watch on `root` reports this event: anything below it when
+    /// `recursive`, otherwise only `root` and its direct children
+    pub fn is_within(&self, root: &VirtualPath, recursive: bool) -> bool {
+        let within = |path: &VirtualPath| {
+            path.starts_with(root) && (recursive || path.segments.len() <= root.segments.len() + 1)
+        };
+        match &self.kind {
+            ChangeKind::Renamed { from } => within(&self.path) || within(from),
+            // Lost events above the root may have touched it
+            ChangeKind::Rescan => within(&self.path) || root.starts_with(&self.path),
+            _ => within(&self.path),
+        }
+    }
+
+    /// Paths whose cached state the event makes stale: the path itself, the
+    /// directory listing it appears in, and for renames the same for the
+    /// old path
+    pub fn stale_paths(&self) -> Vec<VirtualPath> {
+        let mut paths = vec![self.path.clone()];
+        paths.extend(self.path.parent());
+        if let ChangeKind::Renamed { from } = &self.kind {
+            paths.push(from.clone());
+            paths.extend(from.parent());
+        }
+        paths
+    }
+}
+
+/// Changes as they happen; the watch ends when the stream is dropped
+pub type ChangeStream = Pin<Box<dyn Stream<Item = CfkResult<ChangeEvent>> + Send>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("local", p)
+    }
+
+    #[test]
+    fn test_is_within() {
+        let root = path("/docs");
+        let created = |p: &str| ChangeEvent::new(path(p), ChangeKind::Created);
+
+        assert!(created("/docs").is_within(&root, false));
+        assert!(created("/docs/a.txt").is_within(&root, false));
+        assert!(!created("/docs/sub/a.txt").is_within(&root, false));
+        assert!(created("/docs/sub/a.txt").is_within(&root, true));
+        assert!(!created("/docsx/a.txt").is_within(&root, true));
+        assert!(!created("/other").is_within(&root, true));
+
+        let moved_out = ChangeEvent::new(
+            path("/other/a.txt"),
+            ChangeKind::Renamed {
+                from: path("/docs/a.txt"),
+            },
+        );
+        assert!(moved_out.is_within(&root, false));
+
+        let rescan = ChangeEvent::new(path("/"), ChangeKind::Rescan);
+        assert!(rescan.is_within(&root, false));
+    }
+}
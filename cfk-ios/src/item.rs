@@ -4,7 +4,6 @@
 //! Maps to NSFileProviderItem in iOS.
 
 use crate::domain::DomainIdentifier;
-use crate::error::{IosError, IosResult};
 use cfk_core::{Entry, EntryKind, VirtualPath};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -238,7 +237,7 @@ pub fn from_entry(
     }
 
     /// Create a root item
-    pub fn root(domain: &DomainIdentifier, display_name: &str) -> Self {
+    pub fn root(_domain: &DomainIdentifier, display_name: &str) -> Self {
         Self {
             identifier: ItemIdentifier::root(),
             parent_identifier: ItemIdentifier::root(),
@@ -47,8 +47,10 @@ fn capabilities(&self) -> &StorageCapabilities {
             sharing: false,
             offline: false,
             streaming: false,
+            range_reads: false,
             resumable_uploads: false,
             content_hashing: false,
+            watch: false,
         };
         &CAPS
     }
@@ -340,7 +342,7 @@ pub async fn fetch_contents(&self, identifier: &ItemIdentifier) -> IosResult<Pat
         }
 
         // Write to cache
-        let cache_path = self.cache_dir.join(&identifier.0.replace([':', '/'], "_"));
+        let cache_path = self.cache_dir.join(identifier.0.replace([':', '/'], "_"));
         tokio::fs::write(&cache_path, &data)
             .await
             .map_err(|e| IosError::Core(cfk_core::CfkError::Io(e)))?;
@@ -432,7 +434,7 @@ pub async fn delete_item(&self, identifier: &ItemIdentifier) -> IosResult<()> {
             .map_err(IosError::Core)?;
 
         // Remove from cache
-        let cache_path = self.cache_dir.join(&identifier.0.replace([':', '/'], "_"));
+        let cache_path = self.cache_dir.join(identifier.0.replace([':', '/'], "_"));
         let _ = tokio::fs::remove_file(&cache_path).await;
 
         Ok(())
@@ -490,7 +492,7 @@ pub async fn space_info(&self, domain_id: &DomainIdentifier) -> IosResult<(u64,
 
     /// Evict item from local cache
     pub async fn evict_item(&self, identifier: &ItemIdentifier) -> IosResult<()> {
-        let cache_path = self.cache_dir.join(&identifier.0.replace([':', '/'], "_"));
+        let cache_path = self.cache_dir.join(identifier.0.replace([':', '/'], "_"));
         tokio::fs::remove_file(&cache_path)
             .await
             .map_err(|e| IosError::Core(cfk_core::CfkError::Io(e)))?;
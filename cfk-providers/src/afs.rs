@@ -60,6 +60,7 @@ pub fn new(id: impl Into<String>, config: AfsConfig) -> Self {
                 versioning: false,
                 sharing: true, // ACLs
                 streaming: true,
+                range_reads: false,
                 resume: true,
                 watch: false,
                 metadata: true,
@@ -3,60 +3,151 @@
 //! Box API implementation with OAuth 2.0 authentication.
 
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    CfkError, CfkResult, Entry, EntryKind, Metadata, ResumableUpload, StorageBackend,
+    StorageCapabilities, UploadPart, UploadSession, VirtualPath,
 };
 use chrono::{DateTime, Utc};
 use oauth2::{
     basic::BasicClient, AuthUrl, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
     PkceCodeVerifier, RedirectUrl, Scope, TokenUrl,
 };
-use reqwest::Client;
+use reqwest::{multipart, Client};
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+use crate::common;
+use crate::http;
+use crate::tokens::{AuthRequest, OAuthBackend, OAuthTokens, TokenManager, TokenStore};
+
 const BOX_AUTH_URL: &str = "https://account.box.com/api/oauth2/authorize";
 const BOX_TOKEN_URL: &str = "https://api.box.com/oauth2/token";
 const BOX_API_URL: &str = "https://api.box.com/2.0";
 const BOX_UPLOAD_URL: &str = "https://upload.box.com/api/2.0";
 
+const ITEM_FIELDS: &str = "id,type,name,size,created_at,modified_at,sha1,etag";
+const VERSION_FIELDS: &str = "id,size,modified_at,modified_by";
+
+/// How many times to retry a commit Box is still processing
+const COMMIT_ATTEMPTS: u32 = 10;
+
+/// Largest page the folder items endpoint will return
+const LIST_PAGE_SIZE: usize = 1000;
+
 /// Box OAuth tokens
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BoxTokens {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
-    pub expires_at: Option<DateTime<Utc>>,
-}
+pub type BoxTokens = OAuthTokens;
 
 /// Box backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BoxConfig {
     pub client_id: String,
     pub client_secret: String,
+    #[serde(default = "default_redirect_uri")]
     pub redirect_uri: String,
+    /// Box API base URL
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// Box upload API base URL
+    #[serde(default = "default_upload_url")]
+    pub upload_url: String,
+    /// OAuth token endpoint
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_redirect_uri() -> String {
+    crate::tokens::LOOPBACK_REDIRECT_URI.to_string()
+}
+
+fn default_api_url() -> String {
+    BOX_API_URL.to_string()
+}
+
+fn default_upload_url() -> String {
+    BOX_UPLOAD_URL.to_string()
+}
+
+fn default_token_url() -> String {
+    BOX_TOKEN_URL.to_string()
+}
+
+impl BoxConfig {
+    /// Configuration pointing at the public Box API
+    pub fn new(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        redirect_uri: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            redirect_uri: redirect_uri.into(),
+            api_url: BOX_API_URL.to_string(),
+            upload_url: BOX_UPLOAD_URL.to_string(),
+            token_url: BOX_TOKEN_URL.to_string(),
+        }
+    }
+}
+
+/// A resolved Box item
+#[derive(Debug, Clone)]
+struct ItemRef {
+    id: String,
+    is_folder: bool,
+}
+
+impl ItemRef {
+    fn root() -> Self {
+        Self {
+            id: "0".to_string(),
+            is_folder: true,
+        }
+    }
+
+    /// API collection the item lives in
+    fn collection(&self) -> &'static str {
+        if self.is_folder {
+            "folders"
+        } else {
+            "files"
+        }
+    }
 }
 
 /// Box storage backend
 pub struct BoxBackend {
     id: String,
     config: BoxConfig,
-    tokens: Arc<RwLock<Option<BoxTokens>>>,
+    auth: TokenManager,
     http: Client,
     capabilities: StorageCapabilities,
-    /// Cache of path to folder ID
-    folder_cache: Arc<RwLock<HashMap<String, String>>>,
+    /// Cache of path to item ID
+    item_cache: Arc<RwLock<HashMap<String, ItemRef>>>,
 }
 
 impl BoxBackend {
     pub fn new(id: impl Into<String>, config: BoxConfig) -> Self {
+        let id = id.into();
+        let auth = TokenManager::new(
+            &id,
+            &config.token_url,
+            &config.client_id,
+            Some(&config.client_secret),
+        );
         Self {
-            id: id.into(),
+            id,
             config,
-            tokens: Arc::new(RwLock::new(None)),
+            auth,
             http: Client::new(),
             capabilities: StorageCapabilities {
                 read: true,
@@ -68,153 +159,167 @@ pub fn new(id: impl Into<String>, config: BoxConfig) -> Self {
                 search: true,
                 versioning: true,
                 sharing: true,
+                offline: false,
                 streaming: true,
-                resume: true,
-                watch: true,
-                metadata: true,
-                thumbnails: true,
-                max_file_size: Some(150 * 1024 * 1024 * 1024), // 150GB for enterprise
+                range_reads: true,
+                resumable_uploads: true,
+                content_hashing: true,
+                watch: false,
             },
-            folder_cache: Arc::new(RwLock::new(HashMap::new())),
+            item_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    /// Start OAuth 2.0 flow
-    pub fn start_auth(&self) -> (String, PkceCodeVerifier) {
-        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
-            .set_client_secret(ClientSecret::new(self.config.client_secret.clone()))
-            .set_auth_uri(AuthUrl::new(BOX_AUTH_URL.to_string()).unwrap())
-            .set_token_uri(TokenUrl::new(BOX_TOKEN_URL.to_string()).unwrap())
-            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
+    /// Set tokens directly, persisting them to the token store if any
+    pub async fn set_tokens(&self, tokens: BoxTokens) -> CfkResult<()> {
+        self.auth.set(tokens).await
+    }
 
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    /// Current access token, refreshed first when it is about to expire
+    async fn get_access_token(&self) -> CfkResult<String> {
+        self.auth.access_token(&self.http).await
+    }
 
-        let (auth_url, _csrf_token) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("root_readwrite".to_string()))
-            .set_pkce_challenge(pkce_challenge)
-            .url();
+    /// Send an authenticated request and return the checked response
+    async fn send(&self, request: reqwest::RequestBuilder) -> CfkResult<reqwest::Response> {
+        let response = self.send_unchecked(request).await?;
 
-        (auth_url.to_string(), pkce_verifier)
+        // Box signals a populated folder with 400 folder_not_empty
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let text = response.text().await.unwrap_or_default();
+            return Err(if text.contains("folder_not_empty") {
+                CfkError::DirectoryNotEmpty(text)
+            } else {
+                CfkError::ProviderApi {
+                    provider: "box".into(),
+                    message: format!("400 Bad Request: {}", text),
+                }
+            });
+        }
+
+        http::check_response("box", response).await
     }
 
-    /// Complete OAuth flow
-    pub async fn complete_auth(
+    /// Send an authenticated request, leaving the status to the caller
+    async fn send_unchecked(
         &self,
-        code: &str,
-        _verifier: PkceCodeVerifier,
-    ) -> CfkResult<BoxTokens> {
-        let params = [
-            ("grant_type", "authorization_code"),
-            ("code", code),
-            ("client_id", &self.config.client_id),
-            ("client_secret", &self.config.client_secret),
-        ];
-
-        let response = self
-            .http
-            .post(BOX_TOKEN_URL)
-            .form(&params)
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<reqwest::Response> {
+        request
+            .bearer_auth(self.get_access_token().await?)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::Auth(format!("Token exchange failed: {}", error_text)));
-        }
-
-        #[derive(Deserialize)]
-        struct TokenResponse {
-            access_token: String,
-            refresh_token: Option<String>,
-            expires_in: Option<i64>,
-        }
+            .map_err(http::network_error)
+    }
 
-        let token_resp: TokenResponse = response
+    /// Send an authenticated request and decode the JSON response
+    async fn send_json<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<T> {
+        self.send(request)
+            .await?
             .json()
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
-
-        let tokens = BoxTokens {
-            access_token: token_resp.access_token,
-            refresh_token: token_resp.refresh_token,
-            expires_at: token_resp
-                .expires_in
-                .map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
-        };
-
-        *self.tokens.write().await = Some(tokens.clone());
-        Ok(tokens)
+            .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
-    /// Set tokens directly
-    pub async fn set_tokens(&self, tokens: BoxTokens) {
-        *self.tokens.write().await = Some(tokens);
-    }
-
-    /// Get access token
-    async fn get_access_token(&self) -> CfkResult<String> {
-        let tokens = self.tokens.read().await;
-        tokens
-            .as_ref()
-            .map(|t| t.access_token.clone())
-            .ok_or_else(|| CfkError::Auth("Not authenticated".into()))
+    /// Fetch one page of a folder's items
+    async fn folder_items(
+        &self,
+        folder_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> CfkResult<ItemList> {
+        self.send_json(
+            self.http
+                .get(format!(
+                    "{}/folders/{}/items",
+                    self.config.api_url, folder_id
+                ))
+                .query(&[
+                    ("fields", ITEM_FIELDS.to_string()),
+                    ("limit", limit.to_string()),
+                    ("offset", offset.to_string()),
+                ]),
+        )
+        .await
     }
 
-    /// Resolve path to folder ID
-    async fn resolve_folder_id(&self, path: &VirtualPath) -> CfkResult<String> {
+    /// Resolve path to a Box item
+    async fn resolve_item(&self, path: &VirtualPath) -> CfkResult<ItemRef> {
         if path.segments.is_empty() {
-            return Ok("0".to_string()); // Root folder
+            return Ok(ItemRef::root());
         }
 
         let path_str = path.to_string();
         {
-            let cache = self.folder_cache.read().await;
-            if let Some(id) = cache.get(&path_str) {
-                return Ok(id.clone());
+            let cache = self.item_cache.read().await;
+            if let Some(item) = cache.get(&path_str) {
+                return Ok(item.clone());
             }
         }
 
         // Navigate path
-        let mut current_id = "0".to_string();
+        let mut current = ItemRef::root();
 
         for segment in &path.segments {
-            let response = self
-                .http
-                .get(format!("{}/folders/{}/items", BOX_API_URL, current_id))
-                .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-                .query(&[("fields", "id,name,type")])
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
-
-            #[derive(Deserialize)]
-            struct ItemList {
-                entries: Vec<BoxItem>,
+            if !current.is_folder {
+                return Err(CfkError::NotADirectory(path.to_string()));
             }
 
-            let list: ItemList = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            let mut offset = 0;
+            let found = loop {
+                let list = self
+                    .folder_items(&current.id, offset, LIST_PAGE_SIZE)
+                    .await?;
+                if let Some(item) = list.entries.iter().find(|e| e.name == *segment) {
+                    break item.clone();
+                }
+                offset += list.entries.len();
+                if list.entries.is_empty() || offset as u64 >= list.total_count {
+                    return Err(CfkError::NotFound(path.to_string()));
+                }
+            };
 
-            current_id = list
-                .entries
-                .iter()
-                .find(|e| e.name == *segment)
-                .map(|e| e.id.clone())
-                .ok_or_else(|| CfkError::NotFound(path.to_string()))?;
+            current = ItemRef {
+                id: found.id,
+                is_folder: found.item_type == "folder",
+            };
         }
 
         // Cache
         {
-            let mut cache = self.folder_cache.write().await;
-            cache.insert(path_str, current_id.clone());
+            let mut cache = self.item_cache.write().await;
+            cache.insert(path_str, current.clone());
         }
 
-        Ok(current_id)
+        Ok(current)
     }
+
+    /// Drop cached IDs for a path and everything below it
+    async fn invalidate(&self, path: &VirtualPath) {
+        let key = path.to_string();
+        let prefix = format!("{}/", key);
+        self.item_cache
+            .write()
+            .await
+            .retain(|k, _| k != &key && !k.starts_with(&prefix));
+    }
+
+    fn parent_and_name(path: &VirtualPath) -> CfkResult<(VirtualPath, String)> {
+        match (path.parent(), path.name()) {
+            (Some(parent), Some(name)) => Ok((parent, name.to_string())),
+            _ => Err(CfkError::InvalidPath(path.to_string())),
+        }
+    }
+}
+
+/// Page of folder items
+#[derive(Debug, Deserialize)]
+struct ItemList {
+    entries: Vec<BoxItem>,
+    total_count: u64,
 }
 
 /// Box item metadata
@@ -228,6 +333,7 @@ struct BoxItem {
     created_at: Option<String>,
     modified_at: Option<String>,
     sha1: Option<String>,
+    etag: Option<String>,
 }
 
 impl BoxItem {
@@ -245,22 +351,21 @@ fn to_entry(&self, backend_id: &str, base_path: &str) -> Entry {
             EntryKind::File
         };
 
-        let mut metadata = Metadata::default();
-        metadata.size = self.size;
+        let parse_time = |s: &Option<String>| {
+            s.as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|dt| dt.with_timezone(&Utc))
+        };
 
-        if let Some(ref modified) = self.modified_at {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(modified) {
-                metadata.modified = Some(dt.with_timezone(&Utc));
-            }
-        }
-        if let Some(ref created) = self.created_at {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(created) {
-                metadata.created = Some(dt.with_timezone(&Utc));
-            }
-        }
-        if let Some(ref sha1) = self.sha1 {
-            metadata.checksum = Some(sha1.clone());
-        }
+        let metadata = Metadata {
+            size: self.size,
+            modified: parse_time(&self.modified_at),
+            created: parse_time(&self.created_at),
+            content_hash: self.sha1.clone(),
+            provider_id: Some(self.id.clone()),
+            revision: self.etag.clone(),
+            ..Default::default()
+        };
 
         Entry {
             path: virtual_path,
@@ -270,210 +375,615 @@ fn to_entry(&self, backend_id: &str, base_path: &str) -> Entry {
     }
 }
 
-#[async_trait]
-impl StorageBackend for BoxBackend {
-    fn id(&self) -> &str {
-        &self.id
+/// A file version, or a file with its current version
+#[derive(Debug, Deserialize)]
+struct BoxVersion {
+    id: String,
+    size: Option<u64>,
+    modified_at: DateTime<Utc>,
+    modified_by: Option<BoxUser>,
+    /// Set on files only; versions are their own
+    file_version: Option<VersionRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BoxUser {
+    name: Option<String>,
+    login: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionRef {
+    id: String,
+}
+
+impl BoxVersion {
+    fn into_file_version(self) -> FileVersion {
+        FileVersion {
+            id: self.file_version.map(|v| v.id).unwrap_or(self.id),
+            modified: self.modified_at,
+            size: self.size,
+            author: self.modified_by.and_then(|user| user.name.or(user.login)),
+        }
     }
+}
 
-    fn display_name(&self) -> &str {
-        "Box"
+fn base_path_of(path: &VirtualPath) -> String {
+    path.parent()
+        .map(|p| p.segments.join("/"))
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Parent {
+    id: String,
+}
+
+/// SHA-1 of a whole upload, carried from chunk to chunk in the session
+///
+/// Box wants the digest of the entire file at commit time. Every chunk but
+/// the last is a whole number of 64-byte blocks, so the state in between is
+/// just the five hash words and the length hashed so far.
+struct RunningSha1 {
+    state: [u32; 5],
+    len: u64,
+}
+
+impl RunningSha1 {
+    const INIT: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    fn load(session: &UploadSession) -> Self {
+        let state = session
+            .state
+            .get("sha1_state")
+            .and_then(|hex| {
+                let words: Vec<u32> = hex
+                    .split(',')
+                    .filter_map(|w| u32::from_str_radix(w, 16).ok())
+                    .collect();
+                words.try_into().ok()
+            })
+            .unwrap_or(Self::INIT);
+        let len = session
+            .state
+            .get("sha1_len")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(0);
+        Self { state, len }
     }
 
-    fn capabilities(&self) -> &StorageCapabilities {
-        &self.capabilities
+    fn save(&self, session: &mut UploadSession) {
+        let words: Vec<String> = self.state.iter().map(|w| format!("{:08x}", w)).collect();
+        session.state.insert("sha1_state".into(), words.join(","));
+        session
+            .state
+            .insert("sha1_len".into(), self.len.to_string());
     }
 
-    async fn is_available(&self) -> bool {
-        self.tokens.read().await.is_some()
+    /// Hash whole blocks; `data.len()` must be a multiple of 64
+    fn update(&mut self, data: &[u8]) {
+        let blocks: Vec<_> = data
+            .chunks_exact(64)
+            .map(sha1::digest::generic_array::GenericArray::clone_from_slice)
+            .collect();
+        sha1::compress(&mut self.state, &blocks);
+        self.len += data.len() as u64;
     }
 
-    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let item_id = self.resolve_folder_id(path).await?;
+    /// Hash the final chunk and pad
+    fn finish(mut self, data: &[u8]) -> [u8; 20] {
+        let whole = data.len() / 64 * 64;
+        self.update(&data[..whole]);
 
-        // Try as folder first
-        let response = self
-            .http
-            .get(format!("{}/folders/{}", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let bits = (self.len + (data.len() - whole) as u64) * 8;
+        let mut tail = data[whole..].to_vec();
+        tail.push(0x80);
+        while tail.len() % 64 != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bits.to_be_bytes());
+        self.update(&tail);
 
-        if response.status().is_success() {
-            let item: BoxItem = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let mut digest = [0u8; 20];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
 
-            let base_path = if path.segments.len() > 1 {
-                path.segments[..path.segments.len() - 1].join("/")
-            } else {
-                String::new()
-            };
+/// A part as Box reports and commits it
+#[derive(Debug, Serialize, Deserialize)]
+struct BoxPart {
+    part_id: String,
+    offset: u64,
+    size: u64,
+    sha1: String,
+}
+
+/// Chunked upload sessions
+///
+/// Box only takes chunked uploads of files over 20 MB, and picks the part
+/// size itself.
+#[async_trait]
+impl ResumableUpload for BoxBackend {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        let (parent_path, name) = Self::parent_and_name(path)?;
+
+        let existing = match self.resolve_item(path).await {
+            Ok(item) => Some(item),
+            Err(CfkError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        let request = match existing {
+            Some(item) if item.is_folder => return Err(CfkError::NotAFile(path.to_string())),
+            Some(_) if !options.overwrite => {
+                return Err(CfkError::AlreadyExists(path.to_string()));
+            }
+            // A session on an existing file uploads a new version
+            Some(item) => self
+                .http
+                .post(format!(
+                    "{}/files/{}/upload_sessions",
+                    self.config.upload_url, item.id
+                ))
+                .json(&serde_json::json!({ "file_size": size })),
+            None => {
+                let parent = self.resolve_item(&parent_path).await?;
+                self.http
+                    .post(format!("{}/files/upload_sessions", self.config.upload_url))
+                    .json(&serde_json::json!({
+                        "folder_id": parent.id,
+                        "file_size": size,
+                        "file_name": name,
+                    }))
+            }
+        };
 
-            return Ok(item.to_entry(&self.id, &base_path));
+        #[derive(Deserialize)]
+        struct Session {
+            id: String,
+            part_size: u64,
         }
 
-        // Try as file
-        let response = self
-            .http
-            .get(format!("{}/files/{}", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let session: Session = self.send_json(request).await?;
+        Ok(UploadSession::new(
+            path.clone(),
+            size,
+            session.id,
+            session.part_size,
+            options.overwrite,
+        ))
+    }
 
-        let item: BoxItem = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        let size = data.len() as u64;
+        let last = session.offset + size >= session.size;
+        if !last && !size.is_multiple_of(64) {
+            return Err(CfkError::InvalidPath(format!(
+                "chunk of {} bytes is not block aligned",
+                size
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct Uploaded {
+            part: BoxPart,
+        }
 
-        let base_path = if path.segments.len() > 1 {
-            path.segments[..path.segments.len() - 1].join("/")
+        let digest = format!("sha={}", BASE64.encode(Sha1::digest(&data)));
+        let uploaded: Uploaded = self
+            .send_json(
+                self.http
+                    .put(format!(
+                        "{}/files/upload_sessions/{}",
+                        self.config.upload_url, session.id
+                    ))
+                    .header("Content-Range", session.content_range(size))
+                    .header("Digest", digest)
+                    .header("Content-Type", "application/octet-stream")
+                    .body(data.clone()),
+            )
+            .await?;
+
+        let mut sha1 = RunningSha1::load(session);
+        if last {
+            let whole = sha1.finish(&data);
+            session.state.insert("sha1".into(), BASE64.encode(whole));
         } else {
-            String::new()
-        };
+            sha1.update(&data);
+            sha1.save(session);
+        }
 
-        Ok(item.to_entry(&self.id, &base_path))
+        session.parts.push(UploadPart {
+            number: session.parts.len() as u32 + 1,
+            offset: uploaded.part.offset,
+            size: uploaded.part.size,
+            tag: uploaded.part.part_id,
+            checksum: Some(uploaded.part.sha1),
+        });
+        session.offset += size;
+        Ok(())
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let folder_id = self.resolve_folder_id(path).await?;
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+        #[derive(Deserialize)]
+        struct PartList {
+            entries: Vec<BoxPart>,
+            total_count: u64,
+        }
 
-        let mut entries = Vec::new();
+        let mut listed = Vec::new();
+        loop {
+            let page: PartList = self
+                .send_json(
+                    self.http
+                        .get(format!(
+                            "{}/files/upload_sessions/{}/parts",
+                            self.config.upload_url, session.id
+                        ))
+                        .query(&[
+                            ("offset", listed.len().to_string()),
+                            ("limit", LIST_PAGE_SIZE.to_string()),
+                        ]),
+                )
+                .await?;
+            let done = page.entries.is_empty();
+            listed.extend(page.entries);
+            if done || listed.len() as u64 >= page.total_count {
+                break;
+            }
+        }
+
+        // Parts count only up to what the running digest has seen, in an
+        // unbroken run from the start
+        let hashed = if session.state.contains_key("sha1") {
+            session.size
+        } else {
+            RunningSha1::load(session).len
+        };
+        listed.sort_by_key(|p| p.offset);
+        let mut parts = Vec::new();
         let mut offset = 0;
-        let limit = 1000;
+        for part in listed {
+            if part.offset != offset || offset + part.size > hashed {
+                break;
+            }
+            offset += part.size;
+            parts.push(UploadPart {
+                number: parts.len() as u32 + 1,
+                offset: part.offset,
+                size: part.size,
+                tag: part.part_id,
+                checksum: Some(part.sha1),
+            });
+        }
 
-        loop {
-            let response = self
-                .http
-                .get(format!("{}/folders/{}/items", BOX_API_URL, folder_id))
-                .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-                .query(&[
-                    ("fields", "id,type,name,size,created_at,modified_at,sha1"),
-                    ("limit", &limit.to_string()),
-                    ("offset", &offset.to_string()),
-                ])
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
+        session.parts = parts;
+        session.offset = offset;
+        Ok(offset)
+    }
 
-            #[derive(Deserialize)]
-            struct ItemList {
-                entries: Vec<BoxItem>,
-                total_count: u64,
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        let digest = session
+            .state
+            .get("sha1")
+            .ok_or_else(|| CfkError::ProviderApi {
+                provider: "box".into(),
+                message: format!(
+                    "upload incomplete: {} of {} bytes",
+                    session.offset, session.size
+                ),
+            })?;
+        let parts: Vec<BoxPart> = session
+            .parts
+            .iter()
+            .map(|p| BoxPart {
+                part_id: p.tag.clone(),
+                offset: p.offset,
+                size: p.size,
+                sha1: p.checksum.clone().unwrap_or_default(),
+            })
+            .collect();
+
+        #[derive(Deserialize)]
+        struct Committed {
+            entries: Vec<BoxItem>,
+        }
+
+        // 202 means Box is still assembling the parts
+        for _ in 0..COMMIT_ATTEMPTS {
+            let response = self
+                .send(
+                    self.http
+                        .post(format!(
+                            "{}/files/upload_sessions/{}/commit",
+                            self.config.upload_url, session.id
+                        ))
+                        .header("Digest", format!("sha={}", digest))
+                        .json(&serde_json::json!({ "parts": parts })),
+                )
+                .await?;
+
+            if response.status() == reqwest::StatusCode::ACCEPTED {
+                let wait = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                continue;
             }
 
-            let list: ItemList = response
+            let committed: Committed = response
                 .json()
                 .await
                 .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            let item = committed
+                .entries
+                .first()
+                .ok_or_else(|| CfkError::ProviderApi {
+                    provider: "box".into(),
+                    message: "No file returned".into(),
+                })?;
+            self.invalidate(&session.path).await;
+            return Ok(item.to_entry(&self.id, &base_path_of(&session.path)));
+        }
 
-            let base_path = path.segments.join("/");
-
-            for item in &list.entries {
-                entries.push(item.to_entry(&self.id, &base_path));
-            }
+        Err(CfkError::Timeout)
+    }
 
-            offset += list.entries.len();
-            if offset as u64 >= list.total_count {
-                break;
-            }
+    async fn abort(&self, session: &UploadSession) -> CfkResult<()> {
+        let result = self
+            .send(self.http.delete(format!(
+                "{}/files/upload_sessions/{}",
+                self.config.upload_url, session.id
+            )))
+            .await
+            .map(drop);
+        match result {
+            Err(CfkError::NotFound(_)) => Ok(()),
+            other => other,
         }
+    }
+}
 
-        Ok(entries)
+#[async_trait]
+impl OAuthBackend for BoxBackend {
+    fn with_token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.auth.attach_store(store);
+        self
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let file_id = self.resolve_folder_id(path).await?;
+    fn redirect_uri(&self) -> &str {
+        &self.config.redirect_uri
+    }
 
-        let response = self
-            .http
-            .get(format!("{}/files/{}/content", BOX_API_URL, file_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    fn start_auth(&self) -> AuthRequest {
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_client_secret(ClientSecret::new(self.config.client_secret.clone()))
+            .set_auth_uri(AuthUrl::new(BOX_AUTH_URL.to_string()).unwrap())
+            .set_token_uri(TokenUrl::new(self.config.token_url.clone()).unwrap())
+            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "box".into(),
-                message: format!("{}: {}", status, error_text),
-            });
+        let (pkce_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let (auth_url, state) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new("root_readwrite".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        AuthRequest {
+            url: auth_url.to_string(),
+            state,
+            verifier,
         }
+    }
 
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+    /// Box authenticates the exchange with the client secret rather than
+    /// the PKCE verifier
+    async fn complete_auth(
+        &self,
+        code: &str,
+        _verifier: PkceCodeVerifier,
+    ) -> CfkResult<OAuthTokens> {
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", &self.config.client_id),
+            ("client_secret", &self.config.client_secret),
+            ("redirect_uri", &self.config.redirect_uri),
+        ];
+        self.auth.exchange(&self.http, &params).await
     }
+}
 
-    async fn write_file(&self, path: &VirtualPath, data: Bytes) -> CfkResult<Entry> {
-        let parent_path = if path.segments.len() > 1 {
-            VirtualPath::new(&self.id, &path.segments[..path.segments.len() - 1].join("/"))
-        } else {
-            VirtualPath::new(&self.id, "")
-        };
+#[async_trait]
+impl StorageBackend for BoxBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
 
-        let parent_id = self.resolve_folder_id(&parent_path).await?;
-        let name = path.segments.last().cloned().unwrap_or_default();
+    fn display_name(&self) -> &str {
+        "Box"
+    }
 
-        // Use multipart upload
-        let boundary = "cfk_box_boundary";
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
 
-        #[derive(Serialize)]
-        struct FileAttributes {
-            name: String,
-            parent: Parent,
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.auth.refresh(&self.http).await
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        Some(self)
+    }
+
+    async fn is_available(&self) -> bool {
+        self.auth.is_authenticated().await
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let item = self.resolve_item(path).await?;
+
+        let item: BoxItem = self
+            .send_json(
+                self.http
+                    .get(format!(
+                        "{}/{}/{}",
+                        self.config.api_url,
+                        item.collection(),
+                        item.id
+                    ))
+                    .query(&[("fields", ITEM_FIELDS)]),
+            )
+            .await?;
+
+        if path.is_root() {
+            return Ok(Entry::directory(
+                path.clone(),
+                item.to_entry(&self.id, "").metadata,
+            ));
         }
+        Ok(item.to_entry(&self.id, &base_path_of(path)))
+    }
 
-        #[derive(Serialize)]
-        struct Parent {
-            id: String,
+    /// The cursor is the offset of the next page
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let folder = self.resolve_item(path).await?;
+        if !folder.is_folder {
+            return Err(CfkError::NotADirectory(path.to_string()));
         }
 
-        let attributes = FileAttributes {
-            name: name.clone(),
-            parent: Parent { id: parent_id },
+        let offset: usize = match &options.cursor {
+            Some(cursor) => cursor
+                .parse()
+                .map_err(|_| CfkError::Other(format!("Invalid Box cursor: {}", cursor)))?,
+            None => 0,
         };
+        let limit = options
+            .limit
+            .unwrap_or(LIST_PAGE_SIZE)
+            .clamp(1, LIST_PAGE_SIZE);
 
-        let attributes_json =
-            serde_json::to_string(&attributes).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let list = self.folder_items(&folder.id, offset, limit).await?;
+        let base_path = path.segments.join("/");
 
-        let body = format!(
-            "--{}\r\nContent-Disposition: form-data; name=\"attributes\"\r\n\r\n{}\r\n--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
-            boundary, attributes_json, boundary, name
-        );
+        let next_offset = offset + list.entries.len();
+        let has_more = !list.entries.is_empty() && (next_offset as u64) < list.total_count;
 
-        let mut full_body = body.into_bytes();
-        full_body.extend_from_slice(&data);
-        full_body.extend_from_slice(format!("\r\n--{}--", boundary).as_bytes());
+        let entries = list
+            .entries
+            .iter()
+            .filter(|item| options.include_hidden || !item.name.starts_with('.'))
+            .map(|item| item.to_entry(&self.id, &base_path))
+            .collect();
+
+        Ok(DirectoryListing {
+            path: path.clone(),
+            entries,
+            cursor: has_more.then(|| next_offset.to_string()),
+            has_more,
+        })
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let item = self.resolve_item(path).await?;
+        if item.is_folder {
+            return Err(CfkError::NotAFile(path.to_string()));
+        }
 
-        let response = self
+        let mut request = self
             .http
-            .post(format!("{}/files/content", BOX_UPLOAD_URL))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .header(
-                "Content-Type",
-                format!("multipart/form-data; boundary={}", boundary),
-            )
-            .body(full_body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .get(format!("{}/files/{}/content", self.config.api_url, item.id));
+        if let Some(range) = options.range {
+            request = request.header(reqwest::header::RANGE, http::range_header(range));
+        }
+        let response = self.send_unchecked(request).await?;
+
+        http::ranged_stream("box", response, options.range).await
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let (parent_path, name) = Self::parent_and_name(path)?;
+
+        let existing = match self.resolve_item(path).await {
+            Ok(item) => Some(item),
+            Err(CfkError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        #[derive(Serialize)]
+        struct FileAttributes {
+            name: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            parent: Option<Parent>,
+        }
+
+        let (url, attributes) = match existing {
+            Some(item) if item.is_folder => {
+                return Err(CfkError::NotAFile(path.to_string()));
+            }
+            Some(_) if !options.overwrite => {
+                return Err(CfkError::AlreadyExists(path.to_string()));
+            }
+            // Uploading to an existing file creates a new version
+            Some(item) => (
+                format!("{}/files/{}/content", self.config.upload_url, item.id),
+                FileAttributes {
+                    name: name.clone(),
+                    parent: None,
+                },
+            ),
+            None => {
+                let parent = self.resolve_item(&parent_path).await?;
+                (
+                    format!("{}/files/content", self.config.upload_url),
+                    FileAttributes {
+                        name: name.clone(),
+                        parent: Some(Parent { id: parent.id }),
+                    },
+                )
+            }
+        };
+
+        let attributes_json = serde_json::to_string(&attributes)
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+        let form = multipart::Form::new()
+            .text("attributes", attributes_json)
+            .part("file", multipart::Part::stream(data).file_name(name));
+
+        let mut request = self.http.post(url).multipart(form);
+        if let Some(hash) = &options.content_hash {
+            // Box rejects the upload if the SHA-1 does not match
+            request = request.header("Content-MD5", hash);
+        }
 
         #[derive(Deserialize)]
         struct UploadResponse {
             entries: Vec<BoxItem>,
         }
 
-        let upload_resp: UploadResponse = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let upload_resp: UploadResponse = self.send_json(request).await?;
 
         let item = upload_resp
             .entries
@@ -483,56 +993,44 @@ struct UploadResponse {
                 message: "No file returned".into(),
             })?;
 
-        let base_path = parent_path.segments.join("/");
-        Ok(item.to_entry(&self.id, &base_path))
+        Ok(item.to_entry(&self.id, &parent_path.segments.join("/")))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let item_id = self.resolve_folder_id(path).await?;
-
-        // Try as file first
-        let response = self
-            .http
-            .delete(format!("{}/files/{}", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if response.status().is_success() || response.status() == reqwest::StatusCode::NO_CONTENT {
-            return Ok(());
-        }
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let data = common::collect_stream(stream).await?;
+        self.write_file(path, data, options).await
+    }
 
-        // Try as folder
-        let response = self
-            .http
-            .delete(format!("{}/folders/{}?recursive=true", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        let item = match self.resolve_item(path).await {
+            Ok(item) => item,
+            Err(e) => return common::ignore_missing(Err(e), options),
+        };
 
-        if !response.status().is_success() && response.status() != reqwest::StatusCode::NO_CONTENT {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "box".into(),
-                message: format!("{}: {}", status, error_text),
-            });
+        let mut request = self.http.delete(format!(
+            "{}/{}/{}",
+            self.config.api_url,
+            item.collection(),
+            item.id
+        ));
+        if item.is_folder {
+            request = request.query(&[("recursive", options.recursive.to_string())]);
         }
 
-        Ok(())
+        let result = self.send(request).await.map(|_| ());
+        self.invalidate(path).await;
+        common::ignore_missing(result, options)
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let parent_path = if path.segments.len() > 1 {
-            VirtualPath::new(&self.id, &path.segments[..path.segments.len() - 1].join("/"))
-        } else {
-            VirtualPath::new(&self.id, "")
-        };
-
-        let parent_id = self.resolve_folder_id(&parent_path).await?;
-        let name = path.segments.last().cloned().unwrap_or_default();
+        let (parent_path, name) = Self::parent_and_name(path)?;
+        let parent = self.resolve_item(&parent_path).await?;
 
         #[derive(Serialize)]
         struct CreateFolder {
@@ -540,43 +1038,39 @@ struct CreateFolder {
             parent: Parent,
         }
 
-        #[derive(Serialize)]
-        struct Parent {
-            id: String,
-        }
-
         let body = CreateFolder {
             name,
-            parent: Parent { id: parent_id },
+            parent: Parent { id: parent.id },
         };
 
-        let response = self
-            .http
-            .post(format!("{}/folders", BOX_API_URL))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        let item: BoxItem = response
-            .json()
+        let item: BoxItem = self
+            .send_json(
+                self.http
+                    .post(format!("{}/folders", self.config.api_url))
+                    .query(&[("fields", ITEM_FIELDS)])
+                    .json(&body),
+            )
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .map_err(|e| match e {
+                CfkError::Conflict(_) => CfkError::AlreadyExists(path.to_string()),
+                e => e,
+            })?;
 
-        let base_path = parent_path.segments.join("/");
-        Ok(item.to_entry(&self.id, &base_path))
+        Ok(item.to_entry(&self.id, &parent_path.segments.join("/")))
     }
 
-    async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let item_id = self.resolve_folder_id(from).await?;
-        let parent_path = if to.segments.len() > 1 {
-            VirtualPath::new(&self.id, &to.segments[..to.segments.len() - 1].join("/"))
-        } else {
-            VirtualPath::new(&self.id, "")
-        };
-        let parent_id = self.resolve_folder_id(&parent_path).await?;
-        let name = to.segments.last().cloned().unwrap_or_default();
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+        self.invalidate(dest).await;
+
+        let item = self.resolve_item(source).await?;
+        let (parent_path, name) = Self::parent_and_name(dest)?;
+        let parent = self.resolve_item(&parent_path).await?;
 
         #[derive(Serialize)]
         struct CopyRequest {
@@ -584,138 +1078,435 @@ struct CopyRequest {
             name: String,
         }
 
-        #[derive(Serialize)]
-        struct Parent {
-            id: String,
-        }
-
         let body = CopyRequest {
-            parent: Parent { id: parent_id },
+            parent: Parent { id: parent.id },
             name,
         };
 
-        // Try as file
-        let response = self
-            .http
-            .post(format!("{}/files/{}/copy", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let copied: BoxItem = self
+            .send_json(
+                self.http
+                    .post(format!(
+                        "{}/{}/{}/copy",
+                        self.config.api_url,
+                        item.collection(),
+                        item.id
+                    ))
+                    .query(&[("fields", ITEM_FIELDS)])
+                    .json(&body),
+            )
+            .await?;
 
-        if response.status().is_success() {
-            let item: BoxItem = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        Ok(copied.to_entry(&self.id, &parent_path.segments.join("/")))
+    }
 
-            let base_path = parent_path.segments.join("/");
-            return Ok(item.to_entry(&self.id, &base_path));
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+        self.invalidate(dest).await;
+
+        let item = self.resolve_item(source).await?;
+        let (parent_path, name) = Self::parent_and_name(dest)?;
+
+        #[derive(Serialize)]
+        struct UpdateRequest {
+            name: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            parent: Option<Parent>,
         }
 
-        // Try as folder
-        let response = self
-            .http
-            .post(format!("{}/folders/{}/copy", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let parent = if source.parent().as_ref() != Some(&parent_path) {
+            Some(Parent {
+                id: self.resolve_item(&parent_path).await?.id,
+            })
+        } else {
+            None
+        };
 
-        let item: BoxItem = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let body = UpdateRequest { name, parent };
+
+        let updated: BoxItem = self
+            .send_json(
+                self.http
+                    .put(format!(
+                        "{}/{}/{}",
+                        self.config.api_url,
+                        item.collection(),
+                        item.id
+                    ))
+                    .query(&[("fields", ITEM_FIELDS)])
+                    .json(&body),
+            )
+            .await?;
 
-        let base_path = parent_path.segments.join("/");
-        Ok(item.to_entry(&self.id, &base_path))
+        self.invalidate(source).await;
+        Ok(updated.to_entry(&self.id, &parent_path.segments.join("/")))
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let item_id = self.resolve_folder_id(from).await?;
-        let name = to.segments.last().cloned().unwrap_or_default();
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        #[derive(Deserialize)]
+        struct VersionList {
+            entries: Vec<BoxVersion>,
+            total_count: u64,
+        }
 
-        #[derive(Serialize)]
-        struct RenameRequest {
-            name: String,
+        let item = self.resolve_item(path).await?;
+        if item.is_folder {
+            return Err(CfkError::NotAFile(path.to_string()));
         }
 
-        let body = RenameRequest { name };
+        // Box lists only the versions a file had before its current one
+        let current: BoxVersion = self
+            .send_json(
+                self.http
+                    .get(format!("{}/files/{}", self.config.api_url, item.id))
+                    .query(&[("fields", format!("{},file_version", VERSION_FIELDS))]),
+            )
+            .await?;
+        let mut versions = vec![current.into_file_version()];
 
-        // Try as file
-        let response = self
-            .http
-            .put(format!("{}/files/{}", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let mut offset = 0;
+        loop {
+            let list: VersionList = self
+                .send_json(
+                    self.http
+                        .get(format!("{}/files/{}/versions", self.config.api_url, item.id))
+                        .query(&[
+                            ("fields", VERSION_FIELDS.to_string()),
+                            ("limit", LIST_PAGE_SIZE.to_string()),
+                            ("offset", offset.to_string()),
+                        ]),
+                )
+                .await?;
+            offset += list.entries.len();
+            let done = list.entries.is_empty() || offset as u64 >= list.total_count;
+            versions.extend(list.entries.into_iter().map(BoxVersion::into_file_version));
+            if done {
+                break;
+            }
+        }
 
-        if response.status().is_success() {
-            let item: BoxItem = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        versions[1..].sort_by_key(|v| std::cmp::Reverse(v.modified));
+        Ok(versions)
+    }
 
-            let base_path = if to.segments.len() > 1 {
-                to.segments[..to.segments.len() - 1].join("/")
-            } else {
-                String::new()
-            };
-            return Ok(item.to_entry(&self.id, &base_path));
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        let item = self.resolve_item(path).await?;
+        if item.is_folder {
+            return Err(CfkError::NotAFile(path.to_string()));
         }
 
-        // Try as folder
-        let response = self
+        let request = self
             .http
-            .put(format!("{}/folders/{}", BOX_API_URL, item_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
+            .get(format!("{}/files/{}/content", self.config.api_url, item.id))
+            .query(&[("version", version_id)]);
+        Ok(http::body_stream(self.send(request).await?))
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        #[derive(Deserialize)]
+        struct User {
+            space_amount: Option<u64>,
+            space_used: Option<u64>,
+        }
+
+        let user: User = self
+            .send_json(
+                self.http
+                    .get(format!("{}/users/me", self.config.api_url))
+                    .query(&[("fields", "space_amount,space_used")]),
+            )
+            .await?;
+
+        Ok(SpaceInfo {
+            total: user.space_amount,
+            used: user.space_used,
+            available: user
+                .space_amount
+                .map(|total| total.saturating_sub(user.space_used.unwrap_or(0))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn backend(server: &MockServer) -> BoxBackend {
+        let mut config = BoxConfig::new("client", "secret", "http://localhost/callback");
+        config.api_url = server.uri();
+        config.upload_url = format!("{}/upload", server.uri());
+        let backend = BoxBackend::new("box", config);
+        backend
+            .set_tokens(BoxTokens {
+                access_token: "token".into(),
+                refresh_token: None,
+                expires_at: None,
+            })
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .unwrap();
+        backend
+    }
 
-        let item: BoxItem = response
-            .json()
+    #[tokio::test]
+    async fn test_list_uses_offset_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/folders/0/items"))
+            .and(query_param("offset", "0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 2,
+                "entries": [
+                    {"id": "11", "type": "file", "name": "a.txt", "size": 3,
+                     "sha1": "deadbeef", "etag": "0"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/folders/0/items"))
+            .and(query_param("offset", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 2,
+                "entries": [{"id": "12", "type": "folder", "name": "docs"}]
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let root = VirtualPath::root("box");
+
+        let first = backend
+            .list_directory(
+                &root,
+                &ListOptions {
+                    limit: Some(1),
+                    ..Default::default()
+                },
+            )
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .unwrap();
+        assert!(first.has_more);
+        assert_eq!(first.cursor.as_deref(), Some("1"));
+        assert_eq!(
+            first.entries[0].metadata.content_hash.as_deref(),
+            Some("deadbeef")
+        );
 
-        let base_path = if to.segments.len() > 1 {
-            to.segments[..to.segments.len() - 1].join("/")
-        } else {
-            String::new()
-        };
-        Ok(item.to_entry(&self.id, &base_path))
+        let second = backend
+            .list_directory(
+                &root,
+                &ListOptions {
+                    cursor: first.cursor,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(!second.has_more);
+        assert!(second.entries[0].is_directory());
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        let response = self
-            .http
-            .get(format!("{}/users/me", BOX_API_URL))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .query(&[("fields", "space_amount,space_used")])
-            .send()
+    #[tokio::test]
+    async fn test_versions_start_with_the_current_one() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/folders/0/items"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 1,
+                "entries": [{"id": "11", "type": "file", "name": "a.txt"}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/11"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "11", "size": 5, "modified_at": "2024-03-01T00:00:00-08:00",
+                "modified_by": {"name": "Ada", "login": "ada@example.com"},
+                "file_version": {"type": "file_version", "id": "v3"}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/11/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 2,
+                "entries": [
+                    {"id": "v1", "size": 1, "modified_at": "2024-01-01T00:00:00Z",
+                     "modified_by": {"login": "bo@example.com"}},
+                    {"id": "v2", "size": 2, "modified_at": "2024-02-01T00:00:00Z"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/11/content"))
+            .and(query_param("version", "v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"1".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let file = VirtualPath::new("box", "a.txt");
+        let versions = backend.get_versions(&file).await.unwrap();
+        let summary: Vec<_> = versions
+            .iter()
+            .map(|v| (v.id.as_str(), v.size, v.author.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("v3", Some(5), Some("Ada")),
+                ("v2", Some(2), None),
+                ("v1", Some(1), Some("bo@example.com"))
+            ]
+        );
+
+        let old = crate::common::collect_stream(backend.get_version(&file, "v1").await.unwrap())
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .unwrap();
+        assert_eq!(&old[..], b"1");
+    }
 
-        #[derive(Deserialize)]
-        struct User {
-            space_amount: Option<u64>,
-            space_used: Option<u64>,
-        }
+    #[tokio::test]
+    async fn test_non_recursive_folder_delete_maps_not_empty() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/folders/0/items"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 1,
+                "entries": [{"id": "12", "type": "folder", "name": "docs"}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/folders/12"))
+            .and(query_param("recursive", "false"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "type": "error", "code": "folder_not_empty"
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let err = backend
+            .delete(&VirtualPath::new("box", "docs"), &DeleteOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::DirectoryNotEmpty(_)));
+    }
 
-        let user: User = response
-            .json()
+    #[tokio::test]
+    async fn test_upload_new_file() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/folders/0/items"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_count": 0, "entries": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/upload/files/content"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "entries": [{"id": "99", "type": "file", "name": "new.txt", "size": 5}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let entry = backend
+            .write_file(
+                &VirtualPath::new("box", "new.txt"),
+                Bytes::from_static(b"hello"),
+                &WriteOptions::default(),
+            )
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .unwrap();
+        assert_eq!(entry.metadata.provider_id.as_deref(), Some("99"));
+    }
 
-        let total = user.space_amount.unwrap_or(0);
-        let used = user.space_used.unwrap_or(0);
-        let available = total.saturating_sub(used);
+    #[tokio::test]
+    async fn test_space_info() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/users/me"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "space_amount": 100, "space_used": 40
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let info = backend.get_space_info().await.unwrap();
+        assert_eq!(info.available, Some(60));
+    }
 
-        Ok((available, total))
+    #[test]
+    fn test_running_sha1_matches_one_shot() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let mut session = UploadSession::new(
+            VirtualPath::new("box", "f"),
+            data.len() as u64,
+            "s",
+            128,
+            false,
+        );
+
+        // Two aligned chunks, saved and reloaded in between, then the tail
+        for chunk in data[..768].chunks(384) {
+            let mut sha1 = RunningSha1::load(&session);
+            sha1.update(chunk);
+            sha1.save(&mut session);
+        }
+        let digest = RunningSha1::load(&session).finish(&data[768..]);
+        assert_eq!(digest.as_slice(), Sha1::digest(&data).as_slice());
+
+        let empty = RunningSha1::load(&UploadSession::new(
+            VirtualPath::new("box", "f"),
+            0,
+            "s",
+            128,
+            false,
+        ));
+        assert_eq!(empty.finish(&[]).as_slice(), Sha1::digest(b"").as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_commit_sends_whole_file_digest() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/upload/files/upload_sessions/s1"))
+            .and(wiremock::matchers::header("content-range", "bytes 0-2/3"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "part": {"part_id": "p1", "offset": 0, "size": 3, "sha1": "aa"}
+            })))
+            .mount(&server)
+            .await;
+        let digest = format!("sha={}", BASE64.encode(Sha1::digest(b"abc")));
+        Mock::given(method("POST"))
+            .and(path("/upload/files/upload_sessions/s1/commit"))
+            .and(wiremock::matchers::header("digest", digest.as_str()))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "entries": [{"id": "9", "type": "file", "name": "f.bin", "size": 3}]
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let mut session = UploadSession::new(VirtualPath::new("box", "f.bin"), 3, "s1", 64, false);
+        backend
+            .upload_chunk(&mut session, Bytes::from_static(b"abc"))
+            .await
+            .unwrap();
+        let entry = backend.finalize(&session).await.unwrap();
+        assert_eq!(entry.metadata.provider_id.as_deref(), Some("9"));
     }
 }
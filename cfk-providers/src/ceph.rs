@@ -1,16 +1,55 @@
 //! Ceph storage backend
 //!
-//! Distributed object storage via RADOS, CephFS, or S3/Swift gateway.
+//! Three ways into a cluster:
+//!
+//! - RGW, the RADOS Gateway, through its S3 API. Object operations go
+//!   through [`S3Backend`]; usage figures come from the RGW admin API,
+//!   signed with the same keys.
+//! - CephFS through libcephfs, as a POSIX filesystem.
+//! - RADOS through librados, as a flat pool of objects whose names are
+//!   split on `/` into directories, the way S3 keys are.
+//!
+//! The native modes need the `ceph-native` feature and the Ceph client
+//! libraries at link time. librados and libcephfs block, so their calls
+//! run on tokio's blocking pool.
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    CfkError, CfkResult, Entry, Metadata, ResumableUpload, StorageBackend, StorageCapabilities,
     VirtualPath,
 };
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[cfg(feature = "ceph-native")]
+use futures::StreamExt;
+#[cfg(feature = "ceph-native")]
+use std::sync::Arc;
+#[cfg(feature = "ceph-native")]
+use tokio::sync::Mutex;
+
+use crate::s3::{S3Backend, S3Config};
 
 /// Ceph access mode
-#[derive(Debug, Clone)]
+///
+/// In a remote's options, `mode` picks the variant:
+///
+/// ```toml
+/// [remotes.ceph]
+/// type = "ceph"
+/// mode = "rgw"
+/// endpoint = "http://rgw.example.com:7480"
+/// access_key = "..."
+/// secret_key = "..."
+/// bucket = "data"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase", deny_unknown_fields)]
 pub enum CephMode {
     /// Direct RADOS object access
     Rados {
@@ -42,18 +81,45 @@ pub struct CephConfig {
 }
 
 /// Ceph storage backend
-///
-/// Note: This is a stub implementation. Full implementation would use
-/// `ceph` or `rados` crate for RADOS, or the S3 backend for RGW.
 pub struct CephBackend {
     id: String,
     config: CephConfig,
     capabilities: StorageCapabilities,
+    /// The S3 client of RGW mode
+    rgw: Option<S3Backend>,
+    /// The cluster handle of the native modes, once connected
+    #[cfg(feature = "ceph-native")]
+    cluster: Mutex<Option<native::Cluster>>,
+}
+
+/// What an operation runs against
+enum Driver<'a> {
+    Rgw(&'a S3Backend),
+    #[cfg(feature = "ceph-native")]
+    Rados(Arc<native::Rados>),
+    #[cfg(feature = "ceph-native")]
+    CephFs(Arc<native::CephFs>),
 }
 
 impl CephBackend {
     pub fn new(id: impl Into<String>, config: CephConfig) -> Self {
-        let caps = match &config.mode {
+        let id = id.into();
+        let rgw = match &config.mode {
+            // RGW takes any region unless a zonegroup says otherwise, and
+            // bucket names need not resolve as host names, as with MinIO
+            CephMode::Rgw {
+                endpoint,
+                access_key,
+                secret_key,
+                bucket,
+            } => Some(S3Backend::new(
+                id.clone(),
+                S3Config::minio(endpoint, bucket, access_key, secret_key),
+            )),
+            _ => None,
+        };
+
+        let capabilities = match &config.mode {
             CephMode::Rados { .. } => StorageCapabilities {
                 read: true,
                 write: true,
@@ -64,53 +130,32 @@ pub fn new(id: impl Into<String>, config: CephConfig) -> Self {
                 search: false,
                 versioning: false,
                 sharing: false,
+                offline: false,
                 streaming: true,
-                resume: true, // Offset reads/writes
-                watch: true,  // RADOS watch/notify
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false,
+                watch: false,
             },
             CephMode::CephFs { .. } => StorageCapabilities {
                 read: true,
                 write: true,
                 delete: true,
                 rename: true,
-                copy: true,
-                list: true,
-                search: false,
+                copy: false,       // read + write
                 versioning: false, // CephFS has snapshots
-                sharing: true,     // POSIX ACLs
-                streaming: true,
-                resume: true,
-                watch: true, // inotify
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
-            },
-            CephMode::Rgw { .. } => StorageCapabilities {
-                read: true,
-                write: true,
-                delete: true,
-                rename: false, // S3-style
-                copy: true,
-                list: true,
-                search: false,
-                versioning: true,
-                sharing: true, // Presigned URLs
-                streaming: true,
-                resume: true,
-                watch: false,
-                metadata: true,
-                thumbnails: false,
-                max_file_size: Some(5 * 1024 * 1024 * 1024 * 1024), // 5TB
+                ..StorageCapabilities::local_filesystem()
             },
+            CephMode::Rgw { .. } => rgw.as_ref().unwrap().capabilities().clone(),
         };
 
         Self {
-            id: id.into(),
+            id,
             config,
-            capabilities: caps,
+            capabilities,
+            rgw,
+            #[cfg(feature = "ceph-native")]
+            cluster: Mutex::new(None),
         }
     }
 
@@ -178,32 +223,151 @@ pub fn rgw(
     }
 
     /// Connect to Ceph cluster
+    ///
+    /// RGW is plain HTTP and has nothing to set up.
     pub async fn connect(&self) -> CfkResult<()> {
+        self.driver().await.map(|_| ())
+    }
+
+    /// The client for this mode, connecting to the cluster first if needed
+    #[cfg(feature = "ceph-native")]
+    async fn driver(&self) -> CfkResult<Driver<'_>> {
+        if let Some(s3) = &self.rgw {
+            return Ok(Driver::Rgw(s3));
+        }
+
+        let mut cluster = self.cluster.lock().await;
+        if cluster.is_none() {
+            let id = self.id.clone();
+            let mode = self.config.mode.clone();
+            *cluster = Some(native::blocking(move || native::Cluster::connect(id, &mode)).await?);
+        }
+        Ok(match cluster.as_ref().unwrap() {
+            native::Cluster::Rados(rados) => Driver::Rados(rados.clone()),
+            native::Cluster::CephFs(fs) => Driver::CephFs(fs.clone()),
+        })
+    }
+
+    /// The client for this mode; only RGW without `ceph-native`
+    #[cfg(not(feature = "ceph-native"))]
+    async fn driver(&self) -> CfkResult<Driver<'_>> {
+        match &self.rgw {
+            Some(s3) => Ok(Driver::Rgw(s3)),
+            None => Err(CfkError::Unsupported(
+                "RADOS and CephFS need cfk built with the ceph-native feature; \
+                 use RGW mode or mount CephFS"
+                    .into(),
+            )),
+        }
+    }
+
+    /// `GET /admin/bucket` with statistics, for one bucket or all of them
+    async fn bucket_stats<T: serde::de::DeserializeOwned>(
+        &self,
+        s3: &S3Backend,
+        bucket: Option<&str>,
+    ) -> CfkResult<T> {
+        let mut query = vec![("format", "json"), ("stats", "true")];
+        if let Some(bucket) = bucket {
+            query.push(("bucket", bucket));
+        }
+        s3.endpoint_request(Method::GET, "/admin/bucket", &query)
+            .await?
+            .json()
+            .await
+            .map_err(|e| CfkError::ProviderApi {
+                provider: "ceph".into(),
+                message: format!("bad admin API reply: {}", e),
+            })
+    }
+
+    fn rgw_bucket(&self) -> &str {
         match &self.config.mode {
-            CephMode::Rados { monitors, user, key, pool } => {
-                // Would use rados_create(), rados_conf_set(), rados_connect()
-                // rados_ioctx_create() for pool access
-            }
-            CephMode::CephFs { monitors, user, key, mount_path } => {
-                // Would use ceph_mount(), ceph_conf_set(), etc.
-            }
-            CephMode::Rgw { .. } => {
-                // Use S3 backend (already implemented)
-                return Ok(());
+            CephMode::Rgw { bucket, .. } => bucket,
+            _ => "",
+        }
+    }
+
+    /// Capacity and usage of the whole cluster
+    ///
+    /// Through RGW this sums the usage of every bucket, which takes the
+    /// `buckets=read` admin capability; the gateway does not report raw
+    /// capacity, so `kb` and `kb_avail` stay zero.
+    pub async fn cluster_stat(&self) -> CfkResult<ClusterStat> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => {
+                let buckets: Vec<BucketStats> = self.bucket_stats(s3, None).await?;
+                let mut stat = ClusterStat::default();
+                for usage in buckets.iter().filter_map(BucketStats::main) {
+                    stat.kb_used += usage.size_kb_actual;
+                    stat.num_objects += usage.num_objects;
+                }
+                Ok(stat)
             }
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => native::blocking(move || rados.cluster_stat()).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(_) => Err(CfkError::Unsupported(
+                "cluster statistics need RADOS or RGW mode; use get_space_info on CephFS".into(),
+            )),
         }
+    }
 
-        Err(CfkError::Unsupported(
-            "Ceph backend is a stub. Use rados/ceph crate or S3 backend for RGW.".into(),
-        ))
+    /// Usage of the configured pool, or through RGW of the bucket
+    pub async fn pool_stat(&self) -> CfkResult<PoolStat> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => {
+                let stats: BucketStats = self.bucket_stats(s3, Some(self.rgw_bucket())).await?;
+                let usage = stats.main().cloned().unwrap_or_default();
+                Ok(PoolStat {
+                    num_bytes: usage.size,
+                    num_kb: usage.size_kb,
+                    num_objects: usage.num_objects,
+                    ..Default::default()
+                })
+            }
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => native::blocking(move || rados.pool_stat()).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(_) => Err(CfkError::Unsupported(
+                "pool statistics need RADOS or RGW mode".into(),
+            )),
+        }
     }
+}
 
-    /// Convert VirtualPath to object/path name
-    fn to_object_name(&self, path: &VirtualPath) -> String {
-        path.segments.join("/")
+/// A bucket as the RGW admin API describes it
+#[derive(Debug, Deserialize)]
+struct BucketStats {
+    #[serde(default)]
+    usage: HashMap<String, BucketUsage>,
+    bucket_quota: Option<BucketQuota>,
+}
+
+impl BucketStats {
+    /// Usage of object data, leaving out multipart bookkeeping
+    fn main(&self) -> Option<&BucketUsage> {
+        self.usage.get("rgw.main")
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct BucketUsage {
+    size: u64,
+    size_kb: u64,
+    size_kb_actual: u64,
+    num_objects: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct BucketQuota {
+    enabled: bool,
+    /// Bytes; negative for no limit
+    #[serde(default)]
+    max_size: i64,
+}
+
 #[async_trait]
 impl StorageBackend for CephBackend {
     fn id(&self) -> &str {
@@ -222,211 +386,330 @@ fn capabilities(&self) -> &StorageCapabilities {
         &self.capabilities
     }
 
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        self.rgw.as_ref().and_then(|s3| s3.resumable())
+    }
+
     async fn is_available(&self) -> bool {
-        false // Would check cluster connection
+        match self.driver().await {
+            Ok(Driver::Rgw(s3)) => s3.is_available().await,
+            #[cfg(feature = "ceph-native")]
+            Ok(_) => true,
+            Err(_) => false,
+        }
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let _name = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_stat() for object
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.get_metadata(path).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => {
+                let path = path.clone();
+                native::blocking(move || rados.entry(&path)).await
             }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_stat()
-            }
-            CephMode::Rgw { .. } => {
-                // Use S3 HEAD
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let path = path.clone();
+                native::blocking(move || fs.entry(&path)).await
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let _prefix = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_nobjects_list_open/next
-            }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_readdir()
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.list_directory(path, options).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => {
+                let (path, options) = (path.clone(), options.clone());
+                native::blocking(move || rados.list(&path, &options)).await
             }
-            CephMode::Rgw { .. } => {
-                // Use S3 LIST
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let (path, options) = (path.clone(), options.clone());
+                native::blocking(move || fs.list(&path, &options)).await
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let _name = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_read()
-            }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_read()
-            }
-            CephMode::Rgw { .. } => {
-                // Use S3 GET
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.read_file(path, options).await,
+            #[cfg(feature = "ceph-native")]
+            driver => {
+                let file: Arc<dyn native::ReadAt> = match driver {
+                    Driver::Rados(rados) => {
+                        let path = path.clone();
+                        Arc::new(native::blocking(move || rados.open(&path)).await?)
+                    }
+                    Driver::CephFs(fs) => {
+                        let path = path.clone();
+                        Arc::new(native::blocking(move || fs.open_read(&path)).await?)
+                    }
+                    Driver::Rgw(_) => unreachable!(),
+                };
+                let (start, end) = options.range.unwrap_or((0, u64::MAX));
+                Ok(native::read_stream(file, start, end))
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn write_file(&self, path: &VirtualPath, _data: Bytes) -> CfkResult<Entry> {
-        let _name = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_write_full() or rados_write()
-            }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_write()
-            }
-            CephMode::Rgw { .. } => {
-                // Use S3 PUT
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.write_file(path, data, options).await,
+            #[cfg(feature = "ceph-native")]
+            _ => {
+                let size = data.len() as u64;
+                let stream: ByteStream = Box::pin(futures::stream::once(async { Ok(data) }));
+                self.write_file_stream(path, stream, Some(size), options)
+                    .await
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let _name = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_remove()
-            }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_unlink()
-            }
-            CephMode::Rgw { .. } => {
-                // Use S3 DELETE
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.write_file_stream(path, stream, size_hint, options).await,
+            #[cfg(feature = "ceph-native")]
+            driver => {
+                let file: Arc<dyn native::WriteAt> = match &driver {
+                    Driver::Rados(rados) => {
+                        let (rados, path, options) = (rados.clone(), path.clone(), options.clone());
+                        Arc::new(native::blocking(move || rados.create(&path, &options)).await?)
+                    }
+                    Driver::CephFs(fs) => {
+                        let (fs, path, options) = (fs.clone(), path.clone(), options.clone());
+                        Arc::new(native::blocking(move || fs.open_write(&path, &options)).await?)
+                    }
+                    Driver::Rgw(_) => unreachable!(),
+                };
+                native::write_stream(file, stream).await?;
+                self.get_metadata(path).await
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let _name = self.to_object_name(path);
-
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // RADOS doesn't have directories
-                return Err(CfkError::Unsupported(
-                    "RADOS doesn't support directories".into(),
-                ));
-            }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_mkdir()
-            }
-            CephMode::Rgw { .. } => {
-                // Create zero-byte object with trailing /
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.create_directory(path).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(_) => Err(CfkError::Unsupported(
+                "RADOS doesn't support directories".into(),
+            )),
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let path = path.clone();
+                native::blocking(move || fs.mkdir(&path)).await
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn copy(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                return Err(CfkError::Unsupported("RADOS doesn't support copy".into()));
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.delete(path, options).await,
+            #[cfg(feature = "ceph-native")]
+            driver => {
+                if path.is_root() {
+                    return Err(CfkError::PermissionDenied(path.to_string()));
+                }
+                let (path, recursive) = (path.clone(), options.recursive);
+                let result = match driver {
+                    Driver::Rados(rados) => {
+                        native::blocking(move || rados.delete(&path, recursive)).await
+                    }
+                    Driver::CephFs(fs) => {
+                        native::blocking(move || fs.delete(&path, recursive)).await
+                    }
+                    Driver::Rgw(_) => unreachable!(),
+                };
+                crate::common::ignore_missing(result, options)
             }
-            CephMode::CephFs { .. } | CephMode::Rgw { .. } => {
-                // CephFS: read + write
-                // RGW: S3 COPY
+        }
+    }
+
+    /// RGW copies on the server. CephFS and RADOS have no copy call, so the
+    /// data passes through.
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.copy(source, dest, options).await,
+            #[cfg(feature = "ceph-native")]
+            driver => {
+                let entry = self.get_metadata(source).await?;
+                if entry.is_directory() {
+                    return Err(CfkError::NotAFile(source.to_string()));
+                }
+                let stream = self.read_file(source, &ReadOptions::default()).await?;
+                let write = WriteOptions {
+                    overwrite: options.overwrite,
+                    ..Default::default()
+                };
+                let copied = self
+                    .write_file_stream(dest, stream, entry.size(), &write)
+                    .await?;
+                if options.preserve_metadata && matches!(driver, Driver::CephFs(_)) {
+                    self.set_metadata(dest, &entry.metadata).await?;
+                    return self.get_metadata(dest).await;
+                }
+                Ok(copied)
             }
         }
+    }
 
-        Err(CfkError::Unsupported("Ceph stub".into()))
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => s3.rename(source, dest, options).await,
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(_) => Err(CfkError::Unsupported("RADOS doesn't support rename".into())),
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let (source, dest, overwrite) = (source.clone(), dest.clone(), options.overwrite);
+                native::blocking(move || fs.rename(&source, &dest, overwrite)).await
+            }
+        }
     }
 
-    async fn rename(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        match &self.config.mode {
-            CephMode::Rados { .. } | CephMode::Rgw { .. } => {
-                return Err(CfkError::Unsupported(
-                    "RADOS/RGW doesn't support rename".into(),
-                ));
+    /// RGW reports the bucket's usage against its quota, when the keys
+    /// carry the `buckets=read` admin capability
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        match self.driver().await? {
+            Driver::Rgw(s3) => {
+                let stats: BucketStats = match self.bucket_stats(s3, Some(self.rgw_bucket())).await
+                {
+                    Ok(stats) => stats,
+                    Err(CfkError::PermissionDenied(_) | CfkError::NotFound(_)) => {
+                        return Ok(SpaceInfo::unknown())
+                    }
+                    Err(e) => return Err(e),
+                };
+                let used = stats.main().map_or(0, |u| u.size);
+                let total = stats
+                    .bucket_quota
+                    .filter(|q| q.enabled && q.max_size > 0)
+                    .map(|q| q.max_size as u64);
+                Ok(SpaceInfo {
+                    total,
+                    used: Some(used),
+                    available: total.map(|t| t.saturating_sub(used)),
+                })
             }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_rename()
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => {
+                let stat = native::blocking(move || rados.cluster_stat()).await?;
+                Ok(SpaceInfo {
+                    total: Some(stat.kb * 1024),
+                    used: Some(stat.kb_used * 1024),
+                    available: Some(stat.kb_avail * 1024),
+                })
             }
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => native::blocking(move || fs.statfs()).await,
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_cluster_stat()
+    /// CephFS sets permissions and times; objects have neither
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        match self.driver().await? {
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let (path, metadata) = (path.clone(), metadata.clone());
+                native::blocking(move || fs.set_metadata(&path, &metadata)).await
             }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_statfs()
-            }
-            CephMode::Rgw { .. } => {
-                // RGW doesn't expose quota
-                return Ok((0, 0));
+            _ => {
+                let _ = (path, metadata);
+                Err(CfkError::Unsupported(
+                    "Setting metadata not supported".into(),
+                ))
             }
         }
-
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 }
 
 /// RADOS object extended attributes
 impl CephBackend {
     /// Get extended attribute
-    pub async fn getxattr(&self, _path: &VirtualPath, _name: &str) -> CfkResult<Vec<u8>> {
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_getxattr()
+    pub async fn getxattr(&self, path: &VirtualPath, name: &str) -> CfkResult<Vec<u8>> {
+        match self.driver().await? {
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => {
+                let (path, name) = (path.clone(), name.to_string());
+                native::blocking(move || rados.getxattr(&path, &name)).await
+            }
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let (path, name) = (path.clone(), name.to_string());
+                native::blocking(move || fs.getxattr(&path, &name)).await
             }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_getxattr()
+            _ => {
+                let _ = (path, name);
+                Err(CfkError::Unsupported(
+                    "Extended attributes need RADOS or CephFS mode".into(),
+                ))
             }
-            _ => {}
         }
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
     /// Set extended attribute
-    pub async fn setxattr(&self, _path: &VirtualPath, _name: &str, _value: &[u8]) -> CfkResult<()> {
-        match &self.config.mode {
-            CephMode::Rados { .. } => {
-                // Would use rados_setxattr()
+    pub async fn setxattr(&self, path: &VirtualPath, name: &str, value: &[u8]) -> CfkResult<()> {
+        match self.driver().await? {
+            #[cfg(feature = "ceph-native")]
+            Driver::Rados(rados) => {
+                let (path, name, value) = (path.clone(), name.to_string(), value.to_vec());
+                native::blocking(move || rados.setxattr(&path, &name, &value)).await
+            }
+            #[cfg(feature = "ceph-native")]
+            Driver::CephFs(fs) => {
+                let (path, name, value) = (path.clone(), name.to_string(), value.to_vec());
+                native::blocking(move || fs.setxattr(&path, &name, &value)).await
             }
-            CephMode::CephFs { .. } => {
-                // Would use ceph_setxattr()
+            _ => {
+                let _ = (path, name, value);
+                Err(CfkError::Unsupported(
+                    "Extended attributes need RADOS or CephFS mode".into(),
+                ))
             }
-            _ => {}
         }
-        Err(CfkError::Unsupported("Ceph stub".into()))
     }
 
     /// Create snapshot (CephFS only)
-    pub async fn create_snapshot(&self, _path: &VirtualPath, _name: &str) -> CfkResult<()> {
-        match &self.config.mode {
-            CephMode::CephFs { .. } => {
-                // Would create .snap/name directory
-            }
-            _ => {
-                return Err(CfkError::Unsupported(
-                    "Snapshots only supported on CephFS".into(),
-                ));
-            }
+    ///
+    /// A CephFS snapshot is a directory made in the hidden `.snap` of the
+    /// directory to snapshot.
+    pub async fn create_snapshot(&self, path: &VirtualPath, name: &str) -> CfkResult<()> {
+        if !matches!(self.config.mode, CephMode::CephFs { .. }) {
+            return Err(CfkError::Unsupported(
+                "Snapshots only supported on CephFS".into(),
+            ));
+        }
+        if name.is_empty() || name.contains('/') {
+            return Err(CfkError::InvalidPath(name.to_string()));
         }
-        Err(CfkError::Unsupported("Ceph stub".into()))
+        self.create_directory(&path.join(".snap").join(name))
+            .await
+            .map(|_| ())
     }
 }
 
@@ -452,3 +735,1420 @@ pub struct PoolStat {
     pub num_wr: u64,
     pub num_wr_kb: u64,
 }
+
+/// librados and libcephfs bindings
+#[cfg(feature = "ceph-native")]
+mod native {
+    use super::*;
+    use cfk_core::{metadata::Permissions, EntryKind};
+    use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString};
+    use std::ptr;
+
+    /// Bytes per read or write call
+    const CHUNK: usize = 4 * 1024 * 1024;
+
+    /// Give up on monitors and OSDs that do not answer
+    const TIMEOUT_SECS: &str = "30";
+
+    const CEPH_STATX_BASIC_STATS: c_uint = 0x7ff;
+
+    #[allow(non_camel_case_types)]
+    mod sys {
+        use super::*;
+
+        pub type rados_t = *mut c_void;
+        pub type rados_ioctx_t = *mut c_void;
+        pub type rados_list_ctx_t = *mut c_void;
+
+        pub enum ceph_mount_info {}
+        pub enum ceph_dir_result {}
+
+        #[repr(C)]
+        #[derive(Default)]
+        pub struct rados_cluster_stat_t {
+            pub kb: u64,
+            pub kb_used: u64,
+            pub kb_avail: u64,
+            pub num_objects: u64,
+        }
+
+        #[repr(C)]
+        #[derive(Default)]
+        pub struct rados_pool_stat_t {
+            pub num_bytes: u64,
+            pub num_kb: u64,
+            pub num_objects: u64,
+            pub num_object_clones: u64,
+            pub num_object_copies: u64,
+            pub num_objects_missing_on_primary: u64,
+            pub num_objects_unfound: u64,
+            pub num_objects_degraded: u64,
+            pub num_rd: u64,
+            pub num_rd_kb: u64,
+            pub num_wr: u64,
+            pub num_wr_kb: u64,
+            pub num_user_bytes: u64,
+            pub compressed_bytes_orig: u64,
+            pub compressed_bytes: u64,
+            pub compressed_bytes_alloc: u64,
+        }
+
+        #[repr(C)]
+        pub struct ceph_statx {
+            pub stx_mask: u32,
+            pub stx_blksize: u32,
+            pub stx_nlink: u32,
+            pub stx_uid: u32,
+            pub stx_gid: u32,
+            pub stx_mode: u16,
+            pub stx_ino: u64,
+            pub stx_size: u64,
+            pub stx_blocks: u64,
+            pub stx_dev: libc::dev_t,
+            pub stx_rdev: libc::dev_t,
+            pub stx_atime: libc::timespec,
+            pub stx_ctime: libc::timespec,
+            pub stx_mtime: libc::timespec,
+            pub stx_btime: libc::timespec,
+            pub stx_version: u64,
+        }
+
+        #[link(name = "rados")]
+        extern "C" {
+            pub fn rados_create(cluster: *mut rados_t, id: *const c_char) -> c_int;
+            pub fn rados_conf_set(
+                cluster: rados_t,
+                option: *const c_char,
+                value: *const c_char,
+            ) -> c_int;
+            pub fn rados_connect(cluster: rados_t) -> c_int;
+            pub fn rados_shutdown(cluster: rados_t);
+            pub fn rados_cluster_stat(cluster: rados_t, result: *mut rados_cluster_stat_t)
+                -> c_int;
+            pub fn rados_ioctx_create(
+                cluster: rados_t,
+                pool: *const c_char,
+                ioctx: *mut rados_ioctx_t,
+            ) -> c_int;
+            pub fn rados_ioctx_destroy(io: rados_ioctx_t);
+            pub fn rados_ioctx_pool_stat(io: rados_ioctx_t, stats: *mut rados_pool_stat_t)
+                -> c_int;
+            pub fn rados_stat(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                size: *mut u64,
+                mtime: *mut libc::time_t,
+            ) -> c_int;
+            pub fn rados_read(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                buf: *mut c_char,
+                len: usize,
+                off: u64,
+            ) -> c_int;
+            pub fn rados_write(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                buf: *const c_char,
+                len: usize,
+                off: u64,
+            ) -> c_int;
+            pub fn rados_write_full(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                buf: *const c_char,
+                len: usize,
+            ) -> c_int;
+            pub fn rados_remove(io: rados_ioctx_t, oid: *const c_char) -> c_int;
+            pub fn rados_getxattr(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                name: *const c_char,
+                buf: *mut c_char,
+                len: usize,
+            ) -> c_int;
+            pub fn rados_setxattr(
+                io: rados_ioctx_t,
+                oid: *const c_char,
+                name: *const c_char,
+                buf: *const c_char,
+                len: usize,
+            ) -> c_int;
+            pub fn rados_nobjects_list_open(io: rados_ioctx_t, ctx: *mut rados_list_ctx_t)
+                -> c_int;
+            pub fn rados_nobjects_list_next(
+                ctx: rados_list_ctx_t,
+                entry: *mut *const c_char,
+                key: *mut *const c_char,
+                nspace: *mut *const c_char,
+            ) -> c_int;
+            pub fn rados_nobjects_list_close(ctx: rados_list_ctx_t);
+        }
+
+        #[link(name = "cephfs")]
+        extern "C" {
+            pub fn ceph_create(cmount: *mut *mut ceph_mount_info, id: *const c_char) -> c_int;
+            pub fn ceph_conf_set(
+                cmount: *mut ceph_mount_info,
+                option: *const c_char,
+                value: *const c_char,
+            ) -> c_int;
+            pub fn ceph_mount(cmount: *mut ceph_mount_info, root: *const c_char) -> c_int;
+            pub fn ceph_unmount(cmount: *mut ceph_mount_info) -> c_int;
+            pub fn ceph_release(cmount: *mut ceph_mount_info) -> c_int;
+            pub fn ceph_statx(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                stx: *mut ceph_statx,
+                want: c_uint,
+                flags: c_uint,
+            ) -> c_int;
+            pub fn ceph_statfs(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                buf: *mut libc::statvfs,
+            ) -> c_int;
+            pub fn ceph_opendir(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                dir: *mut *mut ceph_dir_result,
+            ) -> c_int;
+            pub fn ceph_readdir(
+                cmount: *mut ceph_mount_info,
+                dir: *mut ceph_dir_result,
+            ) -> *mut libc::dirent;
+            pub fn ceph_closedir(cmount: *mut ceph_mount_info, dir: *mut ceph_dir_result) -> c_int;
+            pub fn ceph_mkdir(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                mode: libc::mode_t,
+            ) -> c_int;
+            pub fn ceph_mkdirs(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                mode: libc::mode_t,
+            ) -> c_int;
+            pub fn ceph_rmdir(cmount: *mut ceph_mount_info, path: *const c_char) -> c_int;
+            pub fn ceph_unlink(cmount: *mut ceph_mount_info, path: *const c_char) -> c_int;
+            pub fn ceph_rename(
+                cmount: *mut ceph_mount_info,
+                from: *const c_char,
+                to: *const c_char,
+            ) -> c_int;
+            pub fn ceph_open(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                flags: c_int,
+                mode: libc::mode_t,
+            ) -> c_int;
+            pub fn ceph_close(cmount: *mut ceph_mount_info, fd: c_int) -> c_int;
+            pub fn ceph_read(
+                cmount: *mut ceph_mount_info,
+                fd: c_int,
+                buf: *mut c_char,
+                size: i64,
+                offset: i64,
+            ) -> c_int;
+            pub fn ceph_write(
+                cmount: *mut ceph_mount_info,
+                fd: c_int,
+                buf: *const c_char,
+                size: i64,
+                offset: i64,
+            ) -> c_int;
+            pub fn ceph_chmod(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                mode: libc::mode_t,
+            ) -> c_int;
+            pub fn ceph_utimes(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                times: *mut libc::timeval,
+            ) -> c_int;
+            pub fn ceph_getxattr(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                name: *const c_char,
+                value: *mut c_void,
+                size: usize,
+            ) -> c_int;
+            pub fn ceph_setxattr(
+                cmount: *mut ceph_mount_info,
+                path: *const c_char,
+                name: *const c_char,
+                value: *const c_void,
+                size: usize,
+                flags: c_int,
+            ) -> c_int;
+        }
+    }
+
+    /// Run a blocking library call off the async runtime
+    pub(super) async fn blocking<T: Send + 'static>(
+        f: impl FnOnce() -> CfkResult<T> + Send + 'static,
+    ) -> CfkResult<T> {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|e| CfkError::Other(e.to_string()))?
+    }
+
+    /// A connected cluster handle
+    pub(super) enum Cluster {
+        Rados(Arc<Rados>),
+        CephFs(Arc<CephFs>),
+    }
+
+    impl Cluster {
+        pub fn connect(id: String, mode: &CephMode) -> CfkResult<Self> {
+            match mode {
+                CephMode::Rados {
+                    monitors,
+                    user,
+                    key,
+                    pool,
+                } => {
+                    Rados::connect(id, monitors, user, key, pool).map(|r| Self::Rados(Arc::new(r)))
+                }
+                CephMode::CephFs {
+                    monitors,
+                    user,
+                    key,
+                    mount_path,
+                } => CephFs::mount(id, monitors, user, key, mount_path)
+                    .map(|fs| Self::CephFs(Arc::new(fs))),
+                CephMode::Rgw { .. } => unreachable!("RGW has no cluster handle"),
+            }
+        }
+    }
+
+    /// Turn a negative errno from librados or libcephfs into an error about
+    /// `what`
+    fn check(ret: c_int, what: impl std::fmt::Display) -> CfkResult<c_int> {
+        if ret >= 0 {
+            return Ok(ret);
+        }
+        let what = what.to_string();
+        Err(match -ret {
+            libc::ENOENT | libc::ENODATA => CfkError::NotFound(what),
+            libc::EEXIST => CfkError::AlreadyExists(what),
+            libc::EACCES | libc::EPERM => CfkError::PermissionDenied(what),
+            libc::ENOTDIR => CfkError::NotADirectory(what),
+            libc::EISDIR => CfkError::NotAFile(what),
+            libc::ENOTEMPTY => CfkError::DirectoryNotEmpty(what),
+            libc::ENOSPC | libc::EDQUOT | libc::EFBIG => CfkError::QuotaExceeded(what),
+            libc::EINVAL | libc::ENAMETOOLONG => CfkError::InvalidPath(what),
+            libc::ETIMEDOUT => CfkError::Timeout,
+            libc::ENOTCONN | libc::ECONNREFUSED | libc::ESHUTDOWN => {
+                CfkError::Network(format!("{}: cluster unreachable", what))
+            }
+            libc::EOPNOTSUPP => CfkError::Unsupported(what),
+            errno => CfkError::ProviderApi {
+                provider: "ceph".into(),
+                message: format!("{}: {}", what, std::io::Error::from_raw_os_error(errno)),
+            },
+        })
+    }
+
+    fn cstring(s: &str) -> CfkResult<CString> {
+        CString::new(s).map_err(|_| CfkError::InvalidPath(s.to_string()))
+    }
+
+    /// Client name without the `client.` prefix, as the libraries want it
+    fn client_id(user: &str) -> CfkResult<CString> {
+        cstring(user.strip_prefix("client.").unwrap_or(user))
+    }
+
+    /// Monitor addresses, secret and timeouts, set through `set`
+    fn configure(
+        monitors: &[String],
+        key: &str,
+        mut set: impl FnMut(*const c_char, *const c_char) -> c_int,
+    ) -> CfkResult<()> {
+        let monitors = monitors.join(",");
+        let options = [
+            ("mon_host", monitors.as_str()),
+            ("key", key),
+            ("client_mount_timeout", TIMEOUT_SECS),
+            ("rados_mon_op_timeout", TIMEOUT_SECS),
+            ("rados_osd_op_timeout", TIMEOUT_SECS),
+        ];
+        for (option, value) in options {
+            let (name, value) = (cstring(option)?, cstring(value)?);
+            check(set(name.as_ptr(), value.as_ptr()), option)?;
+        }
+        Ok(())
+    }
+
+    fn time(secs: i64, nanos: i64) -> Option<chrono::DateTime<chrono::Utc>> {
+        (secs > 0)
+            .then(|| chrono::DateTime::from_timestamp(secs, nanos as u32))
+            .flatten()
+    }
+
+    /// Reading at offsets, from an object or an open file
+    pub(super) trait ReadAt: Send + Sync + 'static {
+        fn read_at(&self, offset: u64, len: usize) -> CfkResult<Bytes>;
+    }
+
+    /// Writing at offsets, to an object or an open file
+    pub(super) trait WriteAt: Send + Sync + 'static {
+        fn write_at(&self, offset: u64, data: &[u8]) -> CfkResult<()>;
+    }
+
+    /// Stream `start..end` of `file`, a chunk per blocking call
+    pub(super) fn read_stream(file: Arc<dyn ReadAt>, start: u64, end: u64) -> ByteStream {
+        Box::pin(futures::stream::try_unfold(
+            (file, start),
+            move |(file, offset)| async move {
+                if offset >= end {
+                    return Ok(None);
+                }
+                let len = (end - offset).min(CHUNK as u64) as usize;
+                let reader = file.clone();
+                let chunk = blocking(move || reader.read_at(offset, len)).await?;
+                if chunk.is_empty() {
+                    return Ok(None);
+                }
+                let next = offset + chunk.len() as u64;
+                Ok(Some((chunk, (file, next))))
+            },
+        ))
+    }
+
+    /// Write all of `stream` to `file` from the start, a chunk per
+    /// blocking call
+    pub(super) async fn write_stream(
+        file: Arc<dyn WriteAt>,
+        mut stream: ByteStream,
+    ) -> CfkResult<()> {
+        let mut offset = 0;
+        let mut pending = Vec::new();
+        loop {
+            let chunk = stream.next().await.transpose()?;
+            if let Some(chunk) = &chunk {
+                pending.extend_from_slice(chunk);
+                if pending.len() < CHUNK {
+                    continue;
+                }
+            }
+            if !pending.is_empty() {
+                let (writer, data) = (file.clone(), std::mem::take(&mut pending));
+                let len = data.len() as u64;
+                blocking(move || writer.write_at(offset, &data)).await?;
+                offset += len;
+            }
+            if chunk.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// An I/O context on one pool
+    pub(super) struct Rados {
+        id: String,
+        cluster: sys::rados_t,
+        ioctx: sys::rados_ioctx_t,
+    }
+
+    // librados handles may be shared between threads
+    unsafe impl Send for Rados {}
+    unsafe impl Sync for Rados {}
+
+    impl Drop for Rados {
+        fn drop(&mut self) {
+            unsafe {
+                if !self.ioctx.is_null() {
+                    sys::rados_ioctx_destroy(self.ioctx);
+                }
+                sys::rados_shutdown(self.cluster);
+            }
+        }
+    }
+
+    /// An object to read from or write to
+    pub(super) struct Object {
+        rados: Arc<Rados>,
+        oid: CString,
+        name: String,
+    }
+
+    impl ReadAt for Object {
+        fn read_at(&self, offset: u64, len: usize) -> CfkResult<Bytes> {
+            let mut buf = vec![0u8; len];
+            let n = check(
+                unsafe {
+                    sys::rados_read(
+                        self.rados.ioctx,
+                        self.oid.as_ptr(),
+                        buf.as_mut_ptr().cast(),
+                        len,
+                        offset,
+                    )
+                },
+                &self.name,
+            )?;
+            buf.truncate(n as usize);
+            Ok(Bytes::from(buf))
+        }
+    }
+
+    impl WriteAt for Object {
+        fn write_at(&self, offset: u64, data: &[u8]) -> CfkResult<()> {
+            check(
+                unsafe {
+                    sys::rados_write(
+                        self.rados.ioctx,
+                        self.oid.as_ptr(),
+                        data.as_ptr().cast(),
+                        data.len(),
+                        offset,
+                    )
+                },
+                &self.name,
+            )
+            .map(|_| ())
+        }
+    }
+
+    impl Rados {
+        fn connect(
+            id: String,
+            monitors: &[String],
+            user: &str,
+            key: &str,
+            pool: &str,
+        ) -> CfkResult<Self> {
+            let mut rados = Self {
+                id,
+                cluster: ptr::null_mut(),
+                ioctx: ptr::null_mut(),
+            };
+            let user = client_id(user)?;
+            check(
+                unsafe { sys::rados_create(&mut rados.cluster, user.as_ptr()) },
+                "rados_create",
+            )?;
+            configure(monitors, key, |name, value| unsafe {
+                sys::rados_conf_set(rados.cluster, name, value)
+            })?;
+            check(unsafe { sys::rados_connect(rados.cluster) }, "cluster").map_err(
+                |e| match e {
+                    CfkError::PermissionDenied(_) => {
+                        CfkError::AuthFailed("cephx authentication failed".into())
+                    }
+                    e => e,
+                },
+            )?;
+            let pool_name = cstring(pool)?;
+            check(
+                unsafe {
+                    sys::rados_ioctx_create(rados.cluster, pool_name.as_ptr(), &mut rados.ioctx)
+                },
+                format!("pool {}", pool),
+            )?;
+            Ok(rados)
+        }
+
+        pub fn cluster_stat(&self) -> CfkResult<ClusterStat> {
+            let mut stat = sys::rados_cluster_stat_t::default();
+            check(
+                unsafe { sys::rados_cluster_stat(self.cluster, &mut stat) },
+                "cluster",
+            )?;
+            Ok(ClusterStat {
+                kb: stat.kb,
+                kb_used: stat.kb_used,
+                kb_avail: stat.kb_avail,
+                num_objects: stat.num_objects,
+            })
+        }
+
+        pub fn pool_stat(&self) -> CfkResult<PoolStat> {
+            let mut stat = sys::rados_pool_stat_t::default();
+            check(
+                unsafe { sys::rados_ioctx_pool_stat(self.ioctx, &mut stat) },
+                "pool",
+            )?;
+            Ok(PoolStat {
+                num_bytes: stat.num_bytes,
+                num_kb: stat.num_kb,
+                num_objects: stat.num_objects,
+                num_object_clones: stat.num_object_clones,
+                num_object_copies: stat.num_object_copies,
+                num_rd: stat.num_rd,
+                num_rd_kb: stat.num_rd_kb,
+                num_wr: stat.num_wr,
+                num_wr_kb: stat.num_wr_kb,
+            })
+        }
+
+        fn oid(path: &VirtualPath) -> CfkResult<CString> {
+            cstring(&path.segments.join("/"))
+        }
+
+        /// Size and modification time of an object
+        fn stat(&self, path: &VirtualPath) -> CfkResult<(u64, i64)> {
+            let oid = Self::oid(path)?;
+            let (mut size, mut mtime) = (0u64, 0 as libc::time_t);
+            check(
+                unsafe { sys::rados_stat(self.ioctx, oid.as_ptr(), &mut size, &mut mtime) },
+                path,
+            )?;
+            Ok((size, mtime))
+        }
+
+        fn file_entry(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            let (size, mtime) = self.stat(path)?;
+            let mut metadata = Metadata::new().with_size(size);
+            metadata.modified = time(mtime, 0);
+            Ok(Entry::file(path.clone(), metadata))
+        }
+
+        /// Every object name in the pool
+        fn object_names(&self) -> CfkResult<Vec<String>> {
+            let mut ctx: sys::rados_list_ctx_t = ptr::null_mut();
+            check(
+                unsafe { sys::rados_nobjects_list_open(self.ioctx, &mut ctx) },
+                "pool",
+            )?;
+            let mut names = Vec::new();
+            let result = loop {
+                let mut entry: *const c_char = ptr::null();
+                let ret = unsafe {
+                    sys::rados_nobjects_list_next(ctx, &mut entry, ptr::null_mut(), ptr::null_mut())
+                };
+                if ret == -libc::ENOENT {
+                    break Ok(names);
+                }
+                if let Err(e) = check(ret, "pool") {
+                    break Err(e);
+                }
+                names.push(
+                    unsafe { CStr::from_ptr(entry) }
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            };
+            unsafe { sys::rados_nobjects_list_close(ctx) };
+            result
+        }
+
+        /// Names under the directory `path`, relative to it
+        fn names_under(&self, path: &VirtualPath) -> CfkResult<Vec<String>> {
+            let prefix = match path.segments.join("/") {
+                dir if dir.is_empty() => dir,
+                dir => dir + "/",
+            };
+            let mut names: Vec<String> = self
+                .object_names()?
+                .into_iter()
+                .filter_map(|name| name.strip_prefix(&prefix).map(str::to_string))
+                .filter(|rest| !rest.is_empty())
+                .collect();
+            names.sort();
+            Ok(names)
+        }
+
+        /// An object, or a directory when objects are named under it
+        pub fn entry(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            if path.is_root() {
+                return Ok(Entry::directory(path.clone(), Metadata::default()));
+            }
+            match self.file_entry(path) {
+                Err(CfkError::NotFound(_)) if !self.names_under(path)?.is_empty() => {
+                    Ok(Entry::directory(path.clone(), Metadata::default()))
+                }
+                result => result,
+            }
+        }
+
+        pub fn list(
+            &self,
+            path: &VirtualPath,
+            options: &ListOptions,
+        ) -> CfkResult<DirectoryListing> {
+            let names = self.names_under(path)?;
+            if names.is_empty() && !path.is_root() {
+                // An object, or nothing at all
+                return Err(match self.file_entry(path) {
+                    Ok(_) => CfkError::NotADirectory(path.to_string()),
+                    Err(e) => e,
+                });
+            }
+
+            let mut entries = Vec::new();
+            let mut dirs = std::collections::BTreeSet::new();
+            for name in names {
+                let segments: Vec<&str> = name.split('/').collect();
+                let parents = &segments[..segments.len() - 1];
+                let depth = if options.recursive {
+                    parents.len()
+                } else {
+                    parents.len().min(1)
+                };
+                for i in 1..=depth {
+                    dirs.insert(parents[..i].join("/"));
+                }
+                if options.recursive || parents.is_empty() {
+                    entries.push(self.file_entry(&path.join(&name))?);
+                }
+            }
+            entries.extend(
+                dirs.into_iter()
+                    .map(|dir| Entry::directory(path.join(dir), Metadata::default())),
+            );
+            entries.sort_by(|a, b| a.path.segments.cmp(&b.path.segments));
+            if !options.include_hidden {
+                // Hidden directories hide what is in them too
+                let base = path.segments.len();
+                entries.retain(|e| !e.path.segments[base..].iter().any(|s| s.starts_with('.')));
+            }
+            for entry in &mut entries {
+                entry.path.backend = self.id.clone();
+            }
+            Ok(crate::common::page_entries(path, entries, options))
+        }
+
+        pub fn open(self: &Arc<Self>, path: &VirtualPath) -> CfkResult<Object> {
+            if path.is_root() {
+                return Err(CfkError::NotAFile(path.to_string()));
+            }
+            match self.stat(path) {
+                Err(CfkError::NotFound(_)) if self.entry(path)?.is_directory() => {
+                    return Err(CfkError::NotAFile(path.to_string()))
+                }
+                result => result?,
+            };
+            Ok(Object {
+                rados: self.clone(),
+                oid: Self::oid(path)?,
+                name: path.to_string(),
+            })
+        }
+
+        /// Create or truncate an object for writing
+        pub fn create(
+            self: &Arc<Self>,
+            path: &VirtualPath,
+            options: &WriteOptions,
+        ) -> CfkResult<Object> {
+            if path.is_root() {
+                return Err(CfkError::NotAFile(path.to_string()));
+            }
+            match self.stat(path) {
+                Ok(_) if !options.overwrite => {
+                    return Err(CfkError::AlreadyExists(path.to_string()))
+                }
+                Ok(_) | Err(CfkError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            let oid = Self::oid(path)?;
+            check(
+                unsafe { sys::rados_write_full(self.ioctx, oid.as_ptr(), ptr::null(), 0) },
+                path,
+            )?;
+            Ok(Object {
+                rados: self.clone(),
+                oid,
+                name: path.to_string(),
+            })
+        }
+
+        fn remove(&self, path: &VirtualPath) -> CfkResult<()> {
+            let oid = Self::oid(path)?;
+            check(unsafe { sys::rados_remove(self.ioctx, oid.as_ptr()) }, path).map(|_| ())
+        }
+
+        pub fn delete(&self, path: &VirtualPath, recursive: bool) -> CfkResult<()> {
+            match self.remove(path) {
+                Err(CfkError::NotFound(_)) => {}
+                result => return result,
+            }
+            let names = self.names_under(path)?;
+            if names.is_empty() {
+                return Err(CfkError::NotFound(path.to_string()));
+            }
+            if !recursive {
+                return Err(CfkError::DirectoryNotEmpty(path.to_string()));
+            }
+            for name in names {
+                match self.remove(&path.join(name)) {
+                    Ok(()) | Err(CfkError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        pub fn getxattr(&self, path: &VirtualPath, name: &str) -> CfkResult<Vec<u8>> {
+            let (oid, attr) = (Self::oid(path)?, cstring(name)?);
+            let mut len = 4096;
+            loop {
+                let mut buf = vec![0u8; len];
+                let ret = unsafe {
+                    sys::rados_getxattr(
+                        self.ioctx,
+                        oid.as_ptr(),
+                        attr.as_ptr(),
+                        buf.as_mut_ptr().cast(),
+                        len,
+                    )
+                };
+                if ret == -libc::ERANGE && len < CHUNK {
+                    len *= 16;
+                    continue;
+                }
+                buf.truncate(check(ret, format!("{} ({})", path, name))? as usize);
+                return Ok(buf);
+            }
+        }
+
+        pub fn setxattr(&self, path: &VirtualPath, name: &str, value: &[u8]) -> CfkResult<()> {
+            let (oid, attr) = (Self::oid(path)?, cstring(name)?);
+            check(
+                unsafe {
+                    sys::rados_setxattr(
+                        self.ioctx,
+                        oid.as_ptr(),
+                        attr.as_ptr(),
+                        value.as_ptr().cast(),
+                        value.len(),
+                    )
+                },
+                path,
+            )
+            .map(|_| ())
+        }
+    }
+
+    /// A CephFS mount
+    pub(super) struct CephFs {
+        id: String,
+        cmount: *mut sys::ceph_mount_info,
+    }
+
+    // libcephfs mounts may be shared between threads
+    unsafe impl Send for CephFs {}
+    unsafe impl Sync for CephFs {}
+
+    impl Drop for CephFs {
+        fn drop(&mut self) {
+            unsafe {
+                sys::ceph_unmount(self.cmount);
+                sys::ceph_release(self.cmount);
+            }
+        }
+    }
+
+    /// An open CephFS file, closed on drop
+    pub(super) struct File {
+        fs: Arc<CephFs>,
+        fd: c_int,
+        name: String,
+    }
+
+    impl Drop for File {
+        fn drop(&mut self) {
+            unsafe { sys::ceph_close(self.fs.cmount, self.fd) };
+        }
+    }
+
+    impl ReadAt for File {
+        fn read_at(&self, offset: u64, len: usize) -> CfkResult<Bytes> {
+            let mut buf = vec![0u8; len];
+            let n = check(
+                unsafe {
+                    sys::ceph_read(
+                        self.fs.cmount,
+                        self.fd,
+                        buf.as_mut_ptr().cast(),
+                        len as i64,
+                        offset as i64,
+                    )
+                },
+                &self.name,
+            )?;
+            buf.truncate(n as usize);
+            Ok(Bytes::from(buf))
+        }
+    }
+
+    impl WriteAt for File {
+        fn write_at(&self, mut offset: u64, mut data: &[u8]) -> CfkResult<()> {
+            while !data.is_empty() {
+                let n = check(
+                    unsafe {
+                        sys::ceph_write(
+                            self.fs.cmount,
+                            self.fd,
+                            data.as_ptr().cast(),
+                            data.len() as i64,
+                            offset as i64,
+                        )
+                    },
+                    &self.name,
+                )? as usize;
+                if n == 0 {
+                    return Err(CfkError::QuotaExceeded(self.name.clone()));
+                }
+                offset += n as u64;
+                data = &data[n..];
+            }
+            Ok(())
+        }
+    }
+
+    impl CephFs {
+        fn mount(
+            id: String,
+            monitors: &[String],
+            user: &str,
+            key: &str,
+            mount_path: &str,
+        ) -> CfkResult<Self> {
+            let mut cmount = ptr::null_mut();
+            let user = client_id(user)?;
+            check(
+                unsafe { sys::ceph_create(&mut cmount, user.as_ptr()) },
+                "ceph_create",
+            )?;
+            // Released on any failure from here on
+            let fs = Self { id, cmount };
+            configure(monitors, key, |name, value| unsafe {
+                sys::ceph_conf_set(fs.cmount, name, value)
+            })?;
+            let root = cstring(if mount_path.is_empty() {
+                "/"
+            } else {
+                mount_path
+            })?;
+            check(
+                unsafe { sys::ceph_mount(fs.cmount, root.as_ptr()) },
+                mount_path,
+            )
+            .map_err(|e| match e {
+                CfkError::PermissionDenied(_) => {
+                    CfkError::AuthFailed("cephx authentication failed".into())
+                }
+                e => e,
+            })?;
+            Ok(fs)
+        }
+
+        fn cpath(path: &VirtualPath) -> CfkResult<CString> {
+            cstring(&path.to_path_string())
+        }
+
+        fn statx(&self, path: &VirtualPath) -> CfkResult<sys::ceph_statx> {
+            let cpath = Self::cpath(path)?;
+            let mut stx = std::mem::MaybeUninit::<sys::ceph_statx>::zeroed();
+            check(
+                unsafe {
+                    sys::ceph_statx(
+                        self.cmount,
+                        cpath.as_ptr(),
+                        stx.as_mut_ptr(),
+                        CEPH_STATX_BASIC_STATS,
+                        libc::AT_SYMLINK_NOFOLLOW as c_uint,
+                    )
+                },
+                path,
+            )?;
+            Ok(unsafe { stx.assume_init() })
+        }
+
+        fn to_entry(&self, path: &VirtualPath, stx: &sys::ceph_statx) -> Entry {
+            let mode = stx.stx_mode as u32;
+            let kind = match mode & libc::S_IFMT {
+                libc::S_IFREG => EntryKind::File,
+                libc::S_IFDIR => EntryKind::Directory,
+                libc::S_IFLNK => EntryKind::Symlink,
+                _ => EntryKind::Unknown,
+            };
+            let mut metadata = Metadata::new().with_size(stx.stx_size);
+            metadata.permissions = Some(Permissions::new(mode));
+            metadata.modified = time(stx.stx_mtime.tv_sec, stx.stx_mtime.tv_nsec);
+            metadata.accessed = time(stx.stx_atime.tv_sec, stx.stx_atime.tv_nsec);
+            metadata.created = time(stx.stx_btime.tv_sec, stx.stx_btime.tv_nsec);
+            metadata
+                .custom
+                .insert("uid".to_string(), stx.stx_uid.to_string());
+            metadata
+                .custom
+                .insert("gid".to_string(), stx.stx_gid.to_string());
+            Entry {
+                path: VirtualPath {
+                    backend: self.id.clone(),
+                    segments: path.segments.clone(),
+                },
+                kind,
+                metadata,
+            }
+        }
+
+        pub fn entry(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            let stx = self.statx(path)?;
+            Ok(self.to_entry(path, &stx))
+        }
+
+        /// Names in the directory `path`, without `.` and `..`
+        fn read_dir(&self, path: &VirtualPath) -> CfkResult<Vec<String>> {
+            let cpath = Self::cpath(path)?;
+            let mut dir = ptr::null_mut();
+            check(
+                unsafe { sys::ceph_opendir(self.cmount, cpath.as_ptr(), &mut dir) },
+                path,
+            )?;
+            let mut names = Vec::new();
+            loop {
+                let dirent = unsafe { sys::ceph_readdir(self.cmount, dir) };
+                if dirent.is_null() {
+                    break;
+                }
+                let name = unsafe { CStr::from_ptr((*dirent).d_name.as_ptr()) };
+                let name = name.to_string_lossy();
+                if name != "." && name != ".." {
+                    names.push(name.into_owned());
+                }
+            }
+            unsafe { sys::ceph_closedir(self.cmount, dir) };
+            Ok(names)
+        }
+
+        fn children(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+            let mut children = Vec::new();
+            for name in self.read_dir(path)? {
+                match self.entry(&path.join(&name)) {
+                    Ok(entry) => children.push(entry),
+                    // Removed since the directory was read
+                    Err(CfkError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            children.sort_by(|a, b| a.path.segments.cmp(&b.path.segments));
+            Ok(children)
+        }
+
+        pub fn list(
+            &self,
+            path: &VirtualPath,
+            options: &ListOptions,
+        ) -> CfkResult<DirectoryListing> {
+            let mut entries = Vec::new();
+            let mut pending = vec![path.clone()];
+            while let Some(dir) = pending.pop() {
+                let children = self.children(&dir)?;
+                if options.recursive {
+                    pending.extend(
+                        children
+                            .iter()
+                            .rev()
+                            .filter(|e| e.is_directory())
+                            .filter(|e| {
+                                options.include_hidden
+                                    || !e.name().is_some_and(|n| n.starts_with('.'))
+                            })
+                            .map(|e| e.path.clone()),
+                    );
+                }
+                entries.extend(children);
+            }
+            Ok(crate::common::page_entries(path, entries, options))
+        }
+
+        fn open(self: &Arc<Self>, path: &VirtualPath, flags: c_int) -> CfkResult<File> {
+            let cpath = Self::cpath(path)?;
+            let fd = check(
+                unsafe { sys::ceph_open(self.cmount, cpath.as_ptr(), flags, 0o644) },
+                path,
+            )?;
+            Ok(File {
+                fs: self.clone(),
+                fd,
+                name: path.to_string(),
+            })
+        }
+
+        pub fn open_read(self: &Arc<Self>, path: &VirtualPath) -> CfkResult<File> {
+            if self.entry(path)?.is_directory() {
+                return Err(CfkError::NotAFile(path.to_string()));
+            }
+            self.open(path, libc::O_RDONLY)
+        }
+
+        pub fn open_write(
+            self: &Arc<Self>,
+            path: &VirtualPath,
+            options: &WriteOptions,
+        ) -> CfkResult<File> {
+            if options.create_parents {
+                if let Some(parent) = path.parent().filter(|p| !p.is_root()) {
+                    let cpath = Self::cpath(&parent)?;
+                    match check(
+                        unsafe { sys::ceph_mkdirs(self.cmount, cpath.as_ptr(), 0o755) },
+                        &parent,
+                    ) {
+                        Ok(_) | Err(CfkError::AlreadyExists(_)) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            let flags = libc::O_WRONLY
+                | libc::O_CREAT
+                | if options.overwrite {
+                    libc::O_TRUNC
+                } else {
+                    libc::O_EXCL
+                };
+            self.open(path, flags)
+        }
+
+        pub fn mkdir(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            let cpath = Self::cpath(path)?;
+            check(
+                unsafe { sys::ceph_mkdir(self.cmount, cpath.as_ptr(), 0o755) },
+                path,
+            )?;
+            self.entry(path)
+        }
+
+        fn remove(&self, path: &VirtualPath, is_dir: bool) -> CfkResult<()> {
+            let cpath = Self::cpath(path)?;
+            let ret = unsafe {
+                if is_dir {
+                    sys::ceph_rmdir(self.cmount, cpath.as_ptr())
+                } else {
+                    sys::ceph_unlink(self.cmount, cpath.as_ptr())
+                }
+            };
+            check(ret, path).map(|_| ())
+        }
+
+        pub fn delete(&self, path: &VirtualPath, recursive: bool) -> CfkResult<()> {
+            let is_dir = self.entry(path)?.is_directory();
+            if is_dir && recursive {
+                // Parents come before their children, so removing in
+                // reverse empties each directory before it goes
+                let mut doomed = Vec::new();
+                let mut pending = vec![path.clone()];
+                while let Some(dir) = pending.pop() {
+                    for entry in self.children(&dir)? {
+                        if entry.is_directory() {
+                            pending.push(entry.path.clone());
+                        }
+                        doomed.push(entry);
+                    }
+                }
+                for entry in doomed.iter().rev() {
+                    self.remove(&entry.path, entry.is_directory())?;
+                }
+            }
+            self.remove(path, is_dir)
+        }
+
+        /// Like POSIX rename, but refusing to replace `dest` without
+        /// `overwrite`
+        pub fn rename(
+            &self,
+            source: &VirtualPath,
+            dest: &VirtualPath,
+            overwrite: bool,
+        ) -> CfkResult<Entry> {
+            if !overwrite {
+                match self.statx(dest) {
+                    Ok(_) => return Err(CfkError::AlreadyExists(dest.to_string())),
+                    Err(CfkError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            let (from, to) = (Self::cpath(source)?, Self::cpath(dest)?);
+            check(
+                unsafe { sys::ceph_rename(self.cmount, from.as_ptr(), to.as_ptr()) },
+                source,
+            )?;
+            self.entry(dest)
+        }
+
+        pub fn statfs(&self) -> CfkResult<SpaceInfo> {
+            let root = cstring("/")?;
+            let mut st = std::mem::MaybeUninit::<libc::statvfs>::zeroed();
+            check(
+                unsafe { sys::ceph_statfs(self.cmount, root.as_ptr(), st.as_mut_ptr()) },
+                "/",
+            )?;
+            let st = unsafe { st.assume_init() };
+            let block = st.f_frsize;
+            Ok(SpaceInfo {
+                total: Some(st.f_blocks * block),
+                used: Some((st.f_blocks - st.f_bfree) * block),
+                available: Some(st.f_bavail * block),
+            })
+        }
+
+        pub fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+            let cpath = Self::cpath(path)?;
+            if let Some(permissions) = metadata.permissions {
+                check(
+                    unsafe {
+                        sys::ceph_chmod(
+                            self.cmount,
+                            cpath.as_ptr(),
+                            (permissions.mode & 0o7777) as libc::mode_t,
+                        )
+                    },
+                    path,
+                )?;
+            }
+            if metadata.modified.is_some() || metadata.accessed.is_some() {
+                let current = self.entry(path)?.metadata;
+                let timeval = |t: Option<chrono::DateTime<chrono::Utc>>| {
+                    let t = t.unwrap_or_default();
+                    libc::timeval {
+                        tv_sec: t.timestamp() as libc::time_t,
+                        tv_usec: t.timestamp_subsec_micros() as libc::suseconds_t,
+                    }
+                };
+                let mut times = [
+                    timeval(metadata.accessed.or(current.accessed)),
+                    timeval(metadata.modified.or(current.modified)),
+                ];
+                check(
+                    unsafe { sys::ceph_utimes(self.cmount, cpath.as_ptr(), times.as_mut_ptr()) },
+                    path,
+                )?;
+            }
+            Ok(())
+        }
+
+        pub fn getxattr(&self, path: &VirtualPath, name: &str) -> CfkResult<Vec<u8>> {
+            let (cpath, attr) = (Self::cpath(path)?, cstring(name)?);
+            let what = format!("{} ({})", path, name);
+            // A zero size asks for the length
+            let len = check(
+                unsafe {
+                    sys::ceph_getxattr(
+                        self.cmount,
+                        cpath.as_ptr(),
+                        attr.as_ptr(),
+                        ptr::null_mut(),
+                        0,
+                    )
+                },
+                &what,
+            )? as usize;
+            let mut buf = vec![0u8; len];
+            let n = check(
+                unsafe {
+                    sys::ceph_getxattr(
+                        self.cmount,
+                        cpath.as_ptr(),
+                        attr.as_ptr(),
+                        buf.as_mut_ptr().cast(),
+                        len,
+                    )
+                },
+                &what,
+            )?;
+            buf.truncate(n as usize);
+            Ok(buf)
+        }
+
+        pub fn setxattr(&self, path: &VirtualPath, name: &str, value: &[u8]) -> CfkResult<()> {
+            let (cpath, attr) = (Self::cpath(path)?, cstring(name)?);
+            check(
+                unsafe {
+                    sys::ceph_setxattr(
+                        self.cmount,
+                        cpath.as_ptr(),
+                        attr.as_ptr(),
+                        value.as_ptr().cast(),
+                        value.len(),
+                        0,
+                    )
+                },
+                path,
+            )
+            .map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header_exists, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn backend(server: &MockServer) -> CephBackend {
+        CephBackend::rgw("ceph", &server.uri(), "AKID", "SECRET", "bucket")
+    }
+
+    fn bucket_stats(size: u64, objects: u64, quota: Option<i64>) -> serde_json::Value {
+        serde_json::json!({
+            "bucket": "bucket",
+            "usage": {
+                "rgw.main": {
+                    "size": size,
+                    "size_actual": size,
+                    "size_kb": size / 1024,
+                    "size_kb_actual": size / 1024,
+                    "num_objects": objects
+                }
+            },
+            "bucket_quota": {
+                "enabled": quota.is_some(),
+                "max_size": quota.unwrap_or(-1),
+                "max_objects": -1
+            }
+        })
+    }
+
+    #[test]
+    fn test_mode_from_remote_options() {
+        let mode: CephMode = toml::from_str(
+            r#"
+            mode = "cephfs"
+            monitors = ["mon1:6789", "mon2:6789"]
+            user = "client.cfk"
+            key = "AQD..."
+            mount_path = "/volumes/data"
+            "#,
+        )
+        .unwrap();
+        assert!(
+            matches!(mode, CephMode::CephFs { ref mount_path, .. } if mount_path == "/volumes/data")
+        );
+
+        let mode: CephMode = toml::from_str(
+            r#"
+            mode = "rgw"
+            endpoint = "http://rgw:7480"
+            access_key = "a"
+            secret_key = "s"
+            bucket = "data"
+            "#,
+        )
+        .unwrap();
+        let backend = CephBackend::new("ceph", CephConfig { mode });
+        assert_eq!(backend.display_name(), "Ceph RGW");
+        assert!(backend.capabilities().resumable_uploads);
+    }
+
+    #[tokio::test]
+    async fn test_rgw_reads_objects_path_style() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket/docs/a.txt"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&b"hello"[..]))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let stream = backend
+            .read_file(
+                &VirtualPath::new("ceph", "docs/a.txt"),
+                &ReadOptions::default(),
+            )
+            .await
+            .unwrap();
+        let data = crate::common::collect_stream(stream).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_pool_stat_and_space_from_bucket_stats() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/admin/bucket"))
+            .and(query_param("bucket", "bucket"))
+            .and(query_param("stats", "true"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(bucket_stats(
+                4096,
+                3,
+                Some(10240),
+            )))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let stat = backend.pool_stat().await.unwrap();
+        assert_eq!(stat.num_bytes, 4096);
+        assert_eq!(stat.num_kb, 4);
+        assert_eq!(stat.num_objects, 3);
+
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.used, Some(4096));
+        assert_eq!(space.total, Some(10240));
+        assert_eq!(space.available, Some(6144));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_stat_sums_buckets() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/admin/bucket"))
+            .and(query_param("stats", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                bucket_stats(2048, 1, None),
+                bucket_stats(8192, 5, None),
+                // A bucket nothing was ever written to has no usage
+                { "bucket": "empty", "usage": {} }
+            ])))
+            .mount(&server)
+            .await;
+
+        let stat = backend(&server).cluster_stat().await.unwrap();
+        assert_eq!(stat.kb_used, 10);
+        assert_eq!(stat.num_objects, 6);
+        assert_eq!(stat.kb, 0);
+    }
+
+    #[tokio::test]
+    async fn test_space_unknown_without_admin_caps() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/admin/bucket"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.total, None);
+        assert_eq!(space.used, None);
+        assert!(matches!(
+            backend.pool_stat().await,
+            Err(CfkError::PermissionDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rgw_has_no_xattrs_or_snapshots() {
+        let backend = CephBackend::rgw("ceph", "http://127.0.0.1:1", "a", "s", "bucket");
+        let path = VirtualPath::new("ceph", "a.txt");
+        assert!(matches!(
+            backend.getxattr(&path, "user.tag").await,
+            Err(CfkError::Unsupported(_))
+        ));
+        assert!(matches!(
+            backend
+                .create_snapshot(&VirtualPath::root("ceph"), "daily")
+                .await,
+            Err(CfkError::Unsupported(_))
+        ));
+    }
+
+    #[cfg(not(feature = "ceph-native"))]
+    #[tokio::test]
+    async fn test_native_modes_need_feature() {
+        let backend = CephBackend::rados("ceph", vec!["mon1:6789".into()], "admin", "key", "data");
+        assert!(!backend.is_available().await);
+        assert!(matches!(
+            backend.connect().await,
+            Err(CfkError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+//! Operation semantics shared by providers
+//!
+//! Paging, overwrite and delete rules that most remote protocols leave to
+//! the client, independent of how the provider talks to its server.
+
+use async_trait::async_trait;
+use cfk_core::{
+    backend::ByteStream,
+    entry::DirectoryListing,
+    operations::{DeleteOptions, ListOptions},
+    CfkError, CfkResult, ChangeEvent, ChangeStream, Entry, StorageBackend, VirtualPath,
+};
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// First delay before polling a failed change feed again; it doubles up to
+/// `FEED_RETRY_MAX` while the failures last
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "syncthing"
+    )),
+    allow(dead_code)
+)]
+const FEED_RETRY: Duration = Duration::from_secs(1);
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "syncthing"
+    )),
+    allow(dead_code)
+)]
+const FEED_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Drop the first `skip` bytes of a stream and stop after `take` more
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "ipfs",
+        feature = "webdav"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn slice_stream(stream: ByteStream, skip: u64, take: u64) -> ByteStream {
+    Box::pin(
+        stream
+            .scan((skip, take), |(skip, take), chunk| {
+                let item = match chunk {
+                    Ok(_) if *take == 0 => None,
+                    Ok(mut chunk) => {
+                        let drop = (*skip).min(chunk.len() as u64);
+                        *skip -= drop;
+                        let chunk = chunk.split_off(drop as usize);
+                        let keep = (*take).min(chunk.len() as u64);
+                        *take -= keep;
+                        Some(Ok(chunk.slice(..keep as usize)))
+                    }
+                    Err(e) => Some(Err(e)),
+                };
+                futures::future::ready(item)
+            })
+            .filter(|chunk| futures::future::ready(!matches!(chunk, Ok(c) if c.is_empty()))),
+    )
+}
+
+/// Enforce `overwrite` semantics for providers whose APIs refuse to
+/// replace an existing destination
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "ipfs",
+        feature = "sftp"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn prepare_destination(
+    backend: &dyn StorageBackend,
+    dest: &VirtualPath,
+    overwrite: bool,
+) -> CfkResult<()> {
+    match backend.get_metadata(dest).await {
+        Ok(_) if !overwrite => Err(CfkError::AlreadyExists(dest.to_string())),
+        Ok(existing) => {
+            let options = DeleteOptions {
+                recursive: existing.is_directory(),
+                force: true,
+            };
+            backend.delete(dest, &options).await
+        }
+        Err(CfkError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Refuse a non-recursive delete of a populated directory.
+///
+/// Cloud APIs delete folders recursively, so the check has to happen
+/// client-side.
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "ipfs",
+        feature = "webdav",
+        feature = "ninep"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn check_delete(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+    options: &DeleteOptions,
+) -> CfkResult<()> {
+    if options.recursive {
+        return Ok(());
+    }
+
+    let entry = match backend.get_metadata(path).await {
+        Ok(entry) => entry,
+        Err(CfkError::NotFound(_)) if options.force => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if entry.is_directory() {
+        let probe = ListOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let listing = backend.list_directory(path, &probe).await?;
+        if !listing.entries.is_empty() || listing.has_more {
+            return Err(CfkError::DirectoryNotEmpty(path.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Swallow `NotFound` when the caller asked for a forced delete
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "ipfs",
+        feature = "webdav",
+        feature = "ninep",
+        feature = "sftp",
+        feature = "nfs",
+        feature = "smb"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn ignore_missing(result: CfkResult<()>, options: &DeleteOptions) -> CfkResult<()> {
+    match result {
+        Err(CfkError::NotFound(_)) if options.force => Ok(()),
+        other => other,
+    }
+}
+
+/// Page a listing the API returned whole.
+///
+/// The cursor is the offset of the next entry; hidden entries are dropped
+/// unless `include_hidden` is set.
+#[cfg_attr(
+    not(any(
+        feature = "ipfs",
+        feature = "webdav",
+        feature = "ninep",
+        feature = "sftp",
+        feature = "nfs",
+        feature = "smb",
+        feature = "syncthing",
+        feature = "ceph-native"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn page_entries(
+    path: &VirtualPath,
+    mut entries: Vec<Entry>,
+    options: &ListOptions,
+) -> DirectoryListing {
+    if !options.include_hidden {
+        entries.retain(|e| !e.path.name().is_some_and(|n| n.starts_with('.')));
+    }
+
+    let offset: usize = options
+        .cursor
+        .as_deref()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(0);
+    let total = entries.len();
+    let end = options
+        .limit
+        .map(|l| offset.saturating_add(l).min(total))
+        .unwrap_or(total);
+    let page: Vec<Entry> = entries
+        .into_iter()
+        .skip(offset)
+        .take(end.saturating_sub(offset))
+        .collect();
+
+    let has_more = end < total;
+    DirectoryListing {
+        path: path.clone(),
+        entries: page,
+        cursor: has_more.then(|| end.to_string()),
+        has_more,
+    }
+}
+
+/// Collect a byte stream into memory
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "ipfs",
+        feature = "ninep"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn collect_stream(mut stream: ByteStream) -> CfkResult<bytes::Bytes> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(bytes::Bytes::from(data))
+}
+
+/// A provider's change feed: a cursor it advances one poll at a time
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "syncthing"
+    )),
+    allow(dead_code)
+)]
+#[async_trait]
+pub(crate) trait ChangeFeed: Send + 'static {
+    /// Wait for changes and return them; an empty batch is fine
+    async fn poll(&mut self) -> CfkResult<Vec<ChangeEvent>>;
+}
+
+/// Serve a watch on `root` from a change feed.
+///
+/// Feeds report everything they see; events outside the watch are dropped
+/// here. Errors are passed on and the feed is polled again after a backoff,
+/// so a consumer that keeps reading rides out outages.
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "syncthing"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn change_stream(
+    feed: impl ChangeFeed,
+    root: VirtualPath,
+    recursive: bool,
+) -> ChangeStream {
+    let ready = VecDeque::new();
+    let state = (feed, ready, None::<Duration>);
+    Box::pin(futures::stream::unfold(state, move |(mut feed, mut ready, mut delay)| {
+        let root = root.clone();
+        async move {
+            loop {
+                if let Some(event) = ready.pop_front() {
+                    return Some((Ok(event), (feed, ready, delay)));
+                }
+                if let Some(delay) = delay {
+                    tokio::time::sleep(delay).await;
+                }
+                match feed.poll().await {
+                    Ok(events) => {
+                        delay = None;
+                        ready.extend(events.into_iter().filter(|e| e.is_within(&root, recursive)));
+                    }
+                    Err(e) => {
+                        delay = Some(delay.map_or(FEED_RETRY, |d| (d * 2).min(FEED_RETRY_MAX)));
+                        return Some((Err(e), (feed, ready, delay)));
+                    }
+                }
+            }
+        }
+    }))
+}
+
+/// The folder whose changes cover a watch on `path`: the path itself, or
+/// the folder holding it when it is a file
+#[cfg_attr(not(feature = "dropbox"), allow(dead_code))]
+pub(crate) async fn watched_folder(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+) -> CfkResult<VirtualPath> {
+    if path.is_root() || backend.get_metadata(path).await?.is_directory() {
+        return Ok(path.clone());
+    }
+    Ok(path.parent().unwrap_or_else(|| path.clone()))
+}
@@ -0,0 +1,666 @@
+//! Named remotes
+//!
+//! Remotes are declared in a TOML file, one table per remote. The `type` key
+//! picks the provider and the other keys are the fields of its config
+//! struct ([`S3Config`](crate::S3Config), [`WebDavConfig`](crate::WebDavConfig)
+//! and so on):
+//!
+//! ```toml
+//! [remotes.work-s3]
+//! type = "s3"
+//! endpoint = "https://s3.eu-central-1.amazonaws.com"
+//! bucket = "work"
+//! region = "eu-central-1"
+//! access_key_id = "AKIA..."
+//! secret_access_key = "..."
+//!
+//! [remotes.nas]
+//! type = "webdav"
+//! base_url = "https://nas.example.com/remote.php/dav/files/me"
+//! auth = { basic = { username = "me", password = "..." } }
+//! ```
+//!
+//! Each remote becomes a backend of the same name, reachable as
+//! `cfk://work-s3/...`. Options are checked against the provider only when
+//! the backend is built, so one bad remote, or one for a provider this
+//! build lacks, does not keep the others from loading.
+//!
+//! Some remotes are layered over another one, and are built after it:
+//!
+//! ```toml
+//! [remotes.secret-s3]
+//! type = "crypt"
+//! remote = "cfk://work-s3/secret"
+//! passphrase = "..."
+//! ```
+
+use cfk_core::{CfkError, CfkResult, StorageBackend};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{BackendRegistry, LocalBackend, TokenStore};
+
+#[cfg(feature = "oauth2")]
+use crate::OAuthBackend;
+
+/// Every provider type a remote can name
+pub const PROVIDERS: &[&str] = &[
+    "local",
+    "s3",
+    "webdav",
+    "dropbox",
+    "gdrive",
+    "onedrive",
+    "box",
+    "ipfs",
+    "syncthing",
+    "ninep",
+    "sftp",
+    "nfs",
+    "smb",
+    "ceph",
+    "crypt",
+];
+
+/// Keys whose values `RemoteConfig::redacted` hides
+const SECRET_KEYS: &[&str] = &[
+    "secret_access_key",
+    "client_secret",
+    "password",
+    "passphrase",
+    "api_key",
+    "bearer",
+    "secret_key",
+    "key",
+];
+
+/// The cfk configuration file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CfkConfig {
+    #[serde(default)]
+    pub remotes: BTreeMap<String, RemoteConfig>,
+}
+
+/// One named remote: a provider type and that provider's options
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteConfig {
+    #[serde(rename = "type")]
+    pub provider: String,
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+/// Options of a `local` remote
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocalRemote {
+    root: PathBuf,
+}
+
+impl CfkConfig {
+    /// `$CFK_CONFIG`, or `config.toml` in the user config directory
+    pub fn default_path() -> PathBuf {
+        if let Some(path) = std::env::var_os("CFK_CONFIG") {
+            return PathBuf::from(path);
+        }
+        directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.config_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-config"))
+            .join("config.toml")
+    }
+
+    /// Load the configuration at `path`; a missing file is an empty one
+    pub fn load(path: impl AsRef<Path>) -> CfkResult<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|e| CfkError::Config(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> CfkResult<Self> {
+        toml::from_str(text).map_err(|e| CfkError::Config(e.to_string()))
+    }
+
+    /// Write the configuration to `path`, readable by the owner only
+    ///
+    /// The file is replaced atomically, so a crash never leaves it half
+    /// written.
+    pub fn save(&self, path: impl AsRef<Path>) -> CfkResult<()> {
+        let text = toml::to_string(self).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        write_private(path.as_ref(), text.as_bytes())
+    }
+
+    /// Add a remote, replacing one of the same name only with `replace`
+    pub fn add_remote(&mut self, name: &str, remote: RemoteConfig, replace: bool) -> CfkResult<()> {
+        check_remote_name(name)?;
+        if !replace && self.remotes.contains_key(name) {
+            return Err(CfkError::AlreadyExists(format!("remote {}", name)));
+        }
+        self.remotes.insert(name.to_string(), remote);
+        Ok(())
+    }
+
+    pub fn remove_remote(&mut self, name: &str) -> CfkResult<RemoteConfig> {
+        self.remotes
+            .remove(name)
+            .ok_or_else(|| CfkError::BackendNotFound(name.to_string()))
+    }
+
+    /// Build every remote into `registry`, OAuth remotes with their tokens
+    /// from `tokens`
+    ///
+    /// Remotes that fail to build are recorded in the registry, which
+    /// reports the reason when the remote is asked for.
+    pub fn register_remotes(
+        &self,
+        registry: &mut BackendRegistry,
+        tokens: Option<&Arc<dyn TokenStore>>,
+    ) {
+        let mut pending: BTreeMap<&String, &RemoteConfig> = self.remotes.iter().collect();
+        while !pending.is_empty() {
+            // A layered remote waits until the one under it is registered
+            let ready: Vec<&String> = pending
+                .iter()
+                .filter(|(_, remote)| {
+                    remote
+                        .wraps()
+                        .is_none_or(|under| !pending.keys().any(|name| **name == under))
+                })
+                .map(|(name, _)| *name)
+                .collect();
+            if ready.is_empty() {
+                for name in pending.keys() {
+                    tracing::warn!("remote {}: wraps itself through other remotes", name);
+                    registry.register_failed(name, "remotes wrap each other in a loop".to_string());
+                }
+                break;
+            }
+            for name in ready {
+                let remote = pending.remove(name).expect("ready remotes are pending");
+                match remote.build_with(name, tokens, registry) {
+                    Ok(backend) => registry.register(backend),
+                    Err(e) => {
+                        tracing::warn!("remote {}: {}", name, e);
+                        registry.register_failed(name, e.to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replace `path` with `data`, readable by the owner only
+pub(crate) fn write_private(path: &Path, data: &[u8]) -> CfkResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    std::io::Write::write_all(&mut file, data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Remote names end up as the host of `cfk://` URIs
+pub fn check_remote_name(name: &str) -> CfkResult<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(CfkError::Config(format!(
+            "invalid remote name {:?}: use letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    if name == "local" {
+        return Err(CfkError::Config(
+            "\"local\" is reserved for the local filesystem".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl RemoteConfig {
+    pub fn new(provider: impl Into<String>) -> Self {
+        Self {
+            provider: provider.into(),
+            options: toml::Table::new(),
+        }
+    }
+
+    /// Set an option from `key=value` style input
+    ///
+    /// Dotted keys reach into nested tables (`auth.basic.username`).
+    /// `true` and `false` become booleans, anything else a string.
+    pub fn set_option(&mut self, key: &str, value: &str) -> CfkResult<()> {
+        let value = match value {
+            "true" => toml::Value::Boolean(true),
+            "false" => toml::Value::Boolean(false),
+            _ => toml::Value::String(value.to_string()),
+        };
+
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+        if last.is_empty() || parts.iter().any(|p| p.is_empty()) {
+            return Err(CfkError::Config(format!("invalid option key {:?}", key)));
+        }
+        let mut table = &mut self.options;
+        for part in parts {
+            let entry = table
+                .entry(part.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            table = match entry {
+                toml::Value::Table(inner) => inner,
+                _ => return Err(CfkError::Config(format!("option {} is not a table", part))),
+            };
+        }
+        table.insert(last.to_string(), value);
+        Ok(())
+    }
+
+    /// A copy with passwords, keys and client secrets masked, for display
+    pub fn redacted(&self) -> Self {
+        let mut options = self.options.clone();
+        redact(&mut options);
+        Self {
+            provider: self.provider.clone(),
+            options,
+        }
+    }
+
+    /// Whether the provider authorizes through OAuth (`cfk auth`)
+    pub fn uses_oauth(&self) -> bool {
+        matches!(
+            self.provider.as_str(),
+            "dropbox" | "gdrive" | "onedrive" | "box"
+        )
+    }
+
+    /// The remote this one is layered over, which has to be built first
+    pub fn wraps(&self) -> Option<String> {
+        match self.provider.as_str() {
+            "crypt" => self
+                .options
+                .get("remote")
+                .and_then(|remote| remote.as_str())
+                .and_then(cfk_core::VirtualPath::parse_uri)
+                .map(|path| path.backend),
+            _ => None,
+        }
+    }
+
+    /// Build the backend for the remote called `name`
+    ///
+    /// Network backends come wrapped in a [`RetryBackend`](crate::RetryBackend).
+    /// Layered remotes fail to build here; see [`Self::build_with`].
+    pub fn build(
+        &self,
+        name: &str,
+        tokens: Option<&Arc<dyn TokenStore>>,
+    ) -> CfkResult<Arc<dyn StorageBackend>> {
+        self.build_with(name, tokens, &BackendRegistry::new())
+    }
+
+    /// Build the backend for the remote called `name`, taking the remote it
+    /// [wraps](Self::wraps), if any, from `registry`
+    pub fn build_with(
+        &self,
+        name: &str,
+        tokens: Option<&Arc<dyn TokenStore>>,
+        registry: &BackendRegistry,
+    ) -> CfkResult<Arc<dyn StorageBackend>> {
+        // Only OAuth providers keep tokens, and only layered ones look at
+        // other remotes
+        #[cfg(not(feature = "oauth2"))]
+        let _ = tokens;
+        #[cfg(not(feature = "crypt"))]
+        let _ = registry;
+
+        match self.provider.as_str() {
+            "local" => {
+                let local: LocalRemote = self.parse()?;
+                Ok(Arc::new(LocalBackend::new(name, local.root)))
+            }
+            #[cfg(feature = "s3")]
+            "s3" => Ok(retrying(Arc::new(crate::S3Backend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "webdav")]
+            "webdav" => Ok(retrying(Arc::new(crate::WebDavBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "dropbox")]
+            "dropbox" => Ok(retrying(with_tokens(
+                crate::DropboxBackend::new(name, self.parse()?),
+                tokens,
+            ))),
+            #[cfg(feature = "gdrive")]
+            "gdrive" => Ok(retrying(with_tokens(
+                crate::GoogleDriveBackend::new(name, self.parse()?),
+                tokens,
+            ))),
+            #[cfg(feature = "onedrive")]
+            "onedrive" => Ok(retrying(with_tokens(
+                crate::OneDriveBackend::new(name, self.parse()?),
+                tokens,
+            ))),
+            #[cfg(feature = "box")]
+            "box" => Ok(retrying(with_tokens(
+                crate::BoxBackend::new(name, self.parse()?),
+                tokens,
+            ))),
+            #[cfg(feature = "ipfs")]
+            "ipfs" => Ok(retrying(Arc::new(crate::IpfsBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "syncthing")]
+            "syncthing" => Ok(retrying(Arc::new(crate::SyncthingBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "sftp")]
+            "sftp" => Ok(retrying(Arc::new(crate::SftpBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "nfs")]
+            "nfs" => Ok(retrying(Arc::new(crate::NfsBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "smb")]
+            "smb" => Ok(retrying(Arc::new(crate::SmbBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "ceph")]
+            "ceph" => Ok(retrying(Arc::new(crate::CephBackend::new(
+                name,
+                crate::CephConfig {
+                    mode: self.parse()?,
+                },
+            )))),
+            #[cfg(feature = "ninep")]
+            "ninep" => Ok(retrying(Arc::new(crate::NinePBackend::new(
+                name,
+                self.parse()?,
+            )))),
+            #[cfg(feature = "crypt")]
+            "crypt" => {
+                let config: crate::CryptConfig = self.parse()?;
+                let inner = registry.get_or_err(&config.target()?.backend)?;
+                // The remote underneath retries on its own
+                Ok(Arc::new(crate::CryptBackend::new(name, inner, config)?))
+            }
+            other => Err(self.unavailable(other)),
+        }
+    }
+
+    /// Build an OAuth remote's backend for authorizing it
+    #[cfg(feature = "oauth2")]
+    pub fn build_oauth(
+        &self,
+        name: &str,
+        tokens: Option<&Arc<dyn TokenStore>>,
+    ) -> CfkResult<Arc<dyn OAuthBackend>> {
+        match self.provider.as_str() {
+            #[cfg(feature = "dropbox")]
+            "dropbox" => Ok(with_tokens(
+                crate::DropboxBackend::new(name, self.parse()?),
+                tokens,
+            )),
+            #[cfg(feature = "gdrive")]
+            "gdrive" => Ok(with_tokens(
+                crate::GoogleDriveBackend::new(name, self.parse()?),
+                tokens,
+            )),
+            #[cfg(feature = "onedrive")]
+            "onedrive" => Ok(with_tokens(
+                crate::OneDriveBackend::new(name, self.parse()?),
+                tokens,
+            )),
+            #[cfg(feature = "box")]
+            "box" => Ok(with_tokens(
+                crate::BoxBackend::new(name, self.parse()?),
+                tokens,
+            )),
+            other if !self.uses_oauth() => Err(CfkError::Unsupported(format!(
+                "{} remotes do not authorize through OAuth",
+                other
+            ))),
+            other => Err(self.unavailable(other)),
+        }
+    }
+
+    fn unavailable(&self, provider: &str) -> CfkError {
+        if PROVIDERS.contains(&provider) {
+            CfkError::Unsupported(format!(
+                "provider {} is not available in this build",
+                provider
+            ))
+        } else {
+            CfkError::Config(format!(
+                "unknown provider type {:?} (known: {})",
+                provider,
+                PROVIDERS.join(", ")
+            ))
+        }
+    }
+
+    /// The options as the provider's config struct
+    fn parse<T: DeserializeOwned>(&self) -> CfkResult<T> {
+        toml::Value::Table(self.options.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| {
+                CfkError::Config(format!("{} options: {}", self.provider, e.message()))
+            })
+    }
+}
+
+#[cfg(any(
+    feature = "reqwest",
+    feature = "sftp",
+    feature = "nfs",
+    feature = "smb",
+    feature = "ninep"
+))]
+fn retrying(backend: Arc<dyn StorageBackend>) -> Arc<dyn StorageBackend> {
+    Arc::new(crate::RetryBackend::new(backend))
+}
+
+#[cfg(feature = "oauth2")]
+fn with_tokens<B: OAuthBackend>(backend: B, tokens: Option<&Arc<dyn TokenStore>>) -> Arc<B> {
+    Arc::new(match tokens {
+        Some(store) => backend.with_token_store(store.clone()),
+        None => backend,
+    })
+}
+
+fn redact(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        match value {
+            toml::Value::Table(inner) => redact(inner),
+            _ if SECRET_KEYS.contains(&key.as_str()) => {
+                *value = toml::Value::String("********".to_string())
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+[remotes.work-s3]
+type = "s3"
+endpoint = "http://127.0.0.1:9000"
+bucket = "work"
+access_key_id = "minio"
+secret_access_key = "minio123"
+path_style = true
+
+[remotes.nas]
+type = "webdav"
+base_url = "https://nas.example.com/dav"
+auth = { basic = { username = "me", password = "hunter2" } }
+
+[remotes.scratch]
+type = "local"
+root = "/tmp"
+"#;
+
+    #[test]
+    fn test_parse_and_round_trip() {
+        let config = CfkConfig::parse(EXAMPLE).unwrap();
+        assert_eq!(config.remotes.len(), 3);
+        let s3 = &config.remotes["work-s3"];
+        assert_eq!(s3.provider, "s3");
+        assert_eq!(s3.options["bucket"].as_str(), Some("work"));
+        assert!(!s3.options.contains_key("type"));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cfk").join("config.toml");
+        config.save(&path).unwrap();
+        assert_eq!(CfkConfig::load(&path).unwrap(), config);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert_eq!(
+            CfkConfig::load(tmp.path().join("missing.toml")).unwrap(),
+            CfkConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_registry_builds_remotes_and_remembers_failures() {
+        let mut config = CfkConfig::parse(EXAMPLE).unwrap();
+        let mut broken = RemoteConfig::new("local");
+        broken.set_option("rooot", "/tmp").unwrap();
+        config.add_remote("typo", broken, false).unwrap();
+        config
+            .add_remote("what", RemoteConfig::new("floppy"), false)
+            .unwrap();
+
+        let mut registry = BackendRegistry::new();
+        config.register_remotes(&mut registry, None);
+
+        assert_eq!(registry.get("scratch").unwrap().id(), "scratch");
+        #[cfg(feature = "s3")]
+        assert_eq!(registry.get_or_err("work-s3").unwrap().id(), "work-s3");
+        #[cfg(feature = "webdav")]
+        assert!(registry.get("nas").is_some());
+
+        let err = registry.get_or_err("typo").err().unwrap().to_string();
+        assert!(err.contains("rooot"), "{}", err);
+        let err = registry.get_or_err("what").err().unwrap().to_string();
+        assert!(err.contains("unknown provider"), "{}", err);
+        assert!(matches!(
+            registry.get_or_err("nowhere"),
+            Err(CfkError::BackendNotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "crypt")]
+    #[tokio::test]
+    async fn test_layered_remotes_are_built_after_the_one_they_wrap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = CfkConfig::parse(&format!(
+            r#"
+[remotes.a-secret]
+type = "crypt"
+remote = "cfk://z-plain/vault"
+passphrase = "correct horse"
+
+[remotes.z-plain]
+type = "local"
+root = {:?}
+
+[remotes.loop-a]
+type = "crypt"
+remote = "cfk://loop-b/"
+passphrase = "x"
+
+[remotes.loop-b]
+type = "crypt"
+remote = "cfk://loop-a/"
+passphrase = "x"
+"#,
+            tmp.path()
+        ))
+        .unwrap();
+        assert_eq!(config.remotes["a-secret"].wraps().as_deref(), Some("z-plain"));
+
+        let mut registry = BackendRegistry::new();
+        config.register_remotes(&mut registry, None);
+
+        let secret = registry.get_or_err("a-secret").unwrap();
+        let path = cfk_core::VirtualPath::new("a-secret", "/notes.txt");
+        secret
+            .write_file(&path, bytes::Bytes::from_static(b"hidden"), &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(tmp.path().join("vault")).unwrap().count(), 1);
+        assert!(!tmp.path().join("vault").join("notes.txt").exists());
+
+        let err = registry.get_or_err("loop-a").err().unwrap().to_string();
+        assert!(err.contains("loop"), "{}", err);
+    }
+
+    #[test]
+    fn test_set_option_and_redaction() {
+        let mut remote = RemoteConfig::new("webdav");
+        remote
+            .set_option("base_url", "https://dav.example.com")
+            .unwrap();
+        remote.set_option("auth.basic.username", "me").unwrap();
+        remote.set_option("auth.basic.password", "hunter2").unwrap();
+        assert!(remote.set_option("auth.basic.username.first", "x").is_err());
+        assert!(remote.set_option("auth..x", "x").is_err());
+
+        #[cfg(feature = "webdav")]
+        assert!(remote.build("dav", None).is_ok());
+
+        let shown = toml::to_string(&remote.redacted()).unwrap();
+        assert!(shown.contains("username = \"me\""), "{}", shown);
+        assert!(!shown.contains("hunter2"), "{}", shown);
+
+        let mut config = CfkConfig::default();
+        assert!(config.add_remote("local", remote.clone(), false).is_err());
+        assert!(config.add_remote("a/b", remote.clone(), false).is_err());
+        config.add_remote("dav", remote.clone(), false).unwrap();
+        assert!(matches!(
+            config.add_remote("dav", remote.clone(), false),
+            Err(CfkError::AlreadyExists(_))
+        ));
+        config.add_remote("dav", remote, true).unwrap();
+        config.remove_remote("dav").unwrap();
+        assert!(config.remove_remote("dav").is_err());
+    }
+}
@@ -0,0 +1,1093 @@
+//! Client-side encryption over another remote
+//!
+//! [`CryptBackend`] keeps its files in a directory of another backend,
+//! encrypted with keys derived from a passphrase, so the provider only ever
+//! sees ciphertext:
+//!
+//! ```toml
+//! [remotes.secret-s3]
+//! type = "crypt"
+//! remote = "cfk://work-s3/secret"
+//! passphrase = "..."
+//! ```
+//!
+//! File contents are split into 64 KiB blocks, each sealed with
+//! XChaCha20-Poly1305 under a random per-file nonce, so a range is read by
+//! fetching and opening only the blocks it covers. The last block is marked
+//! in its associated data: a truncated file fails to decrypt rather than
+//! coming back short.
+//!
+//! Names are encrypted one path segment at a time, deterministically: the
+//! nonce is a keyed BLAKE3 hash of the name, which authenticates it again on
+//! the way back (the SIV construction). A name always encrypts the same way,
+//! so paths are looked up without listing, at the cost of showing which
+//! names are equal. Encrypted names are base32hex, which survives
+//! case-insensitive providers, and grow to 255 characters for a 143-byte
+//! name; longer names are refused.
+//!
+//! Keys come from Argon2id, derived on first use rather than when the
+//! remote is built.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use cfk_core::{
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::{DirectoryListing, EntryKind},
+    operations::*,
+    CfkError, CfkResult, ChangeEvent, ChangeKind, ChangeStream, Entry, Metadata, StorageBackend,
+    StorageCapabilities, VirtualPath,
+};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::XChaCha20;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+const MAGIC: &[u8; 8] = b"CFKCRYP1";
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: u64 = MAGIC.len() as u64 + NONCE_LEN as u64;
+const BLOCK_LEN: u64 = 64 * 1024;
+const TAG_LEN: u64 = 16;
+/// Bytes of the name hash kept as its nonce and checked on decryption
+const SIV_LEN: usize = 16;
+/// Longest name whose encryption fits the 255 characters most filesystems
+/// and providers allow in a name
+const MAX_NAME_LEN: usize = 143;
+
+/// Crypt remote configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CryptConfig {
+    /// Where the encrypted files go, as `cfk://remote/path`
+    pub remote: String,
+    /// What the keys are derived from; without it the files are lost
+    pub passphrase: String,
+    /// Mixed into the key derivation: the same passphrase with another salt
+    /// gives other keys
+    #[serde(default)]
+    pub salt: Option<String>,
+}
+
+impl CryptConfig {
+    /// The directory the encrypted files go to
+    pub fn target(&self) -> CfkResult<VirtualPath> {
+        VirtualPath::parse_uri(&self.remote).ok_or_else(|| {
+            CfkError::Config(format!(
+                "crypt remote must be a cfk://remote/path URI, not {:?}",
+                self.remote
+            ))
+        })
+    }
+}
+
+/// Size of the encrypted form of `size` bytes
+pub fn encrypted_size(size: u64) -> u64 {
+    HEADER_LEN + size + block_count(size) * TAG_LEN
+}
+
+/// Size of the content an encrypted file of `size` bytes holds, or `None`
+/// when no encryption produces that size
+pub fn decrypted_size(size: u64) -> Option<u64> {
+    let body = size.checked_sub(HEADER_LEN)?;
+    let full = body / (BLOCK_LEN + TAG_LEN);
+    match body % (BLOCK_LEN + TAG_LEN) {
+        0 if full > 0 => Some(full * BLOCK_LEN),
+        rest if rest >= TAG_LEN => Some(full * BLOCK_LEN + rest - TAG_LEN),
+        _ => None,
+    }
+}
+
+/// Blocks in a file of `size` bytes; an empty file still has one
+fn block_count(size: u64) -> u64 {
+    size.div_ceil(BLOCK_LEN).max(1)
+}
+
+/// The keys of one remote
+struct Keys {
+    content: XChaCha20Poly1305,
+    names: [u8; 32],
+    siv: [u8; 32],
+}
+
+impl Keys {
+    fn derive(passphrase: &str, salt: Option<&str>) -> CfkResult<Self> {
+        // Fixed rather than the crate's defaults, which may change
+        let params = Params::new(19 * 1024, 2, 1, Some(32))
+            .map_err(|e| CfkError::Config(format!("crypt key derivation: {}", e)))?;
+        let salt = format!("cfk-crypt/{}", salt.unwrap_or_default());
+        let mut master = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt.as_bytes(), &mut master)
+            .map_err(|e| CfkError::Config(format!("crypt key derivation: {}", e)))?;
+
+        let content = blake3::derive_key("cfk crypt content key", &master);
+        Ok(Self {
+            content: XChaCha20Poly1305::new(&content.into()),
+            names: blake3::derive_key("cfk crypt name key", &master),
+            siv: blake3::derive_key("cfk crypt name siv", &master),
+        })
+    }
+
+    fn encrypt_name(&self, name: &str) -> String {
+        let hash = blake3::keyed_hash(&self.siv, name.as_bytes());
+        let siv = &hash.as_bytes()[..SIV_LEN];
+        let mut token = siv.to_vec();
+        let start = token.len();
+        token.extend_from_slice(name.as_bytes());
+        self.name_cipher(siv).apply_keystream(&mut token[start..]);
+        base32::encode(&token)
+    }
+
+    /// The name `token` encrypts, or `None` when it is not one of ours
+    fn decrypt_name(&self, token: &str) -> Option<String> {
+        let data = base32::decode(token)?;
+        if data.len() < SIV_LEN {
+            return None;
+        }
+        let (siv, encrypted) = data.split_at(SIV_LEN);
+        let mut name = encrypted.to_vec();
+        self.name_cipher(siv).apply_keystream(&mut name);
+
+        let hash = blake3::keyed_hash(&self.siv, &name);
+        let mismatch = hash.as_bytes()[..SIV_LEN]
+            .iter()
+            .zip(siv)
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if mismatch != 0 {
+            return None;
+        }
+        String::from_utf8(name).ok()
+    }
+
+    fn name_cipher(&self, siv: &[u8]) -> XChaCha20 {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..SIV_LEN].copy_from_slice(siv);
+        XChaCha20::new(&self.names.into(), &nonce.into())
+    }
+}
+
+/// Seals the blocks of one file, in order
+struct Sealer {
+    keys: Arc<Keys>,
+    nonce: [u8; NONCE_LEN],
+    index: u64,
+}
+
+impl Sealer {
+    fn new(keys: Arc<Keys>) -> Self {
+        Self {
+            keys,
+            nonce: XChaCha20Poly1305::generate_nonce(&mut OsRng).into(),
+            index: 0,
+        }
+    }
+
+    fn header(&self) -> Bytes {
+        [&MAGIC[..], &self.nonce].concat().into()
+    }
+
+    fn seal(&mut self, block: &[u8], last: bool) -> CfkResult<Bytes> {
+        let nonce = block_nonce(&self.nonce, self.index);
+        self.index += 1;
+        let payload = Payload {
+            msg: block,
+            aad: &[last as u8],
+        };
+        self.keys
+            .content
+            .encrypt(&nonce, payload)
+            .map(Bytes::from)
+            .map_err(|_| CfkError::Other("encryption failed".into()))
+    }
+
+    /// Header and every block of `data`
+    fn seal_all(mut self, data: &[u8]) -> CfkResult<Bytes> {
+        let size = data.len() as u64;
+        let mut sealed = BytesMut::with_capacity(encrypted_size(size) as usize);
+        sealed.extend_from_slice(&self.header());
+        let blocks = block_count(size);
+        for index in 0..blocks {
+            let start = (index * BLOCK_LEN) as usize;
+            let end = ((index + 1) * BLOCK_LEN).min(size) as usize;
+            sealed.extend_from_slice(&self.seal(&data[start..end], index + 1 == blocks)?);
+        }
+        Ok(sealed.freeze())
+    }
+}
+
+/// Opens the blocks of one file, in order
+struct Opener {
+    keys: Arc<Keys>,
+    /// Read from the stream itself until known
+    nonce: Option<[u8; NONCE_LEN]>,
+    index: u64,
+    /// Index of the file's last block, when the stream stops before it
+    last: Option<u64>,
+    path: VirtualPath,
+}
+
+impl Opener {
+    fn header(&mut self, header: &[u8]) -> CfkResult<()> {
+        let nonce = header
+            .strip_prefix(MAGIC)
+            .and_then(|nonce| <[u8; NONCE_LEN]>::try_from(nonce).ok())
+            .ok_or_else(|| self.damaged())?;
+        self.nonce = Some(nonce);
+        Ok(())
+    }
+
+    fn open(&mut self, block: &[u8], at_end: bool) -> CfkResult<Bytes> {
+        let nonce = self.nonce.ok_or_else(|| self.damaged())?;
+        let last = match self.last {
+            Some(last) => self.index == last,
+            None => at_end,
+        };
+        let nonce = block_nonce(&nonce, self.index);
+        self.index += 1;
+        let payload = Payload {
+            msg: block,
+            aad: &[last as u8],
+        };
+        self.keys
+            .content
+            .decrypt(&nonce, payload)
+            .map(Bytes::from)
+            .map_err(|_| self.damaged())
+    }
+
+    fn damaged(&self) -> CfkError {
+        CfkError::Other(format!(
+            "cannot decrypt {}: wrong passphrase, or the file was changed",
+            self.path
+        ))
+    }
+}
+
+fn block_nonce(nonce: &[u8; NONCE_LEN], index: u64) -> XNonce {
+    let mut nonce = *nonce;
+    for (byte, i) in nonce.iter_mut().zip(index.to_le_bytes()) {
+        *byte ^= i;
+    }
+    nonce.into()
+}
+
+fn encrypt_stream(sealer: Sealer, data: ByteStream) -> ByteStream {
+    let header = sealer.header();
+    let state = Some((data, sealer, BytesMut::new()));
+    let blocks = stream::unfold(state, |state| async move {
+        let (mut data, mut sealer, mut buffer) = state?;
+        loop {
+            // Only a block with more after it is known not to be the last
+            if buffer.len() as u64 > BLOCK_LEN {
+                let block = buffer.split_to(BLOCK_LEN as usize);
+                let sealed = sealer.seal(&block, false);
+                return Some((sealed, Some((data, sealer, buffer))));
+            }
+            match data.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => return Some((sealer.seal(&buffer, true), None)),
+            }
+        }
+    });
+    Box::pin(stream::once(future::ready(Ok(header))).chain(blocks))
+}
+
+fn decrypt_stream(opener: Opener, data: ByteStream) -> ByteStream {
+    let state = Some((data, opener, BytesMut::new()));
+    Box::pin(stream::unfold(state, |state| async move {
+        let (mut data, mut opener, mut buffer) = state?;
+        loop {
+            if opener.nonce.is_none() && buffer.len() as u64 >= HEADER_LEN {
+                let header = buffer.split_to(HEADER_LEN as usize);
+                if let Err(e) = opener.header(&header) {
+                    return Some((Err(e), None));
+                }
+            }
+            if opener.nonce.is_some() && buffer.len() as u64 > BLOCK_LEN + TAG_LEN {
+                let block = buffer.split_to((BLOCK_LEN + TAG_LEN) as usize);
+                return match opener.open(&block, false) {
+                    Ok(plain) => Some((Ok(plain), Some((data, opener, buffer)))),
+                    Err(e) => Some((Err(e), None)),
+                };
+            }
+            match data.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => return Some((opener.open(&buffer, true), None)),
+            }
+        }
+    }))
+}
+
+/// The part of `data` from `skip` on, `take` bytes long
+fn trimmed(data: ByteStream, mut skip: u64, mut take: u64) -> ByteStream {
+    Box::pin(data.map_ok(move |chunk| {
+        let from = skip.min(chunk.len() as u64) as usize;
+        skip -= from as u64;
+        let chunk = chunk.slice(from..);
+        let to = take.min(chunk.len() as u64) as usize;
+        take -= to as u64;
+        chunk.slice(..to)
+    }))
+}
+
+/// A backend that encrypts everything it stores in a directory of another
+pub struct CryptBackend {
+    id: String,
+    inner: Arc<dyn StorageBackend>,
+    /// The directory on `inner` that holds the encrypted files
+    root: VirtualPath,
+    config: CryptConfig,
+    keys: OnceCell<Arc<Keys>>,
+    capabilities: StorageCapabilities,
+}
+
+impl CryptBackend {
+    /// Encrypt into `config.remote`, which must be a directory of `inner`
+    pub fn new(
+        id: impl Into<String>,
+        inner: Arc<dyn StorageBackend>,
+        config: CryptConfig,
+    ) -> CfkResult<Self> {
+        if config.passphrase.is_empty() {
+            return Err(CfkError::Config(
+                "crypt remote needs a passphrase".to_string(),
+            ));
+        }
+        let root = config.target()?;
+        if root.backend != inner.id() {
+            return Err(CfkError::Config(format!(
+                "crypt remote {} is not on backend {}",
+                config.remote,
+                inner.id()
+            )));
+        }
+        let capabilities = StorageCapabilities {
+            // Names, hashes and upload sessions would all be the ciphertext's
+            search: false,
+            sharing: false,
+            content_hashing: false,
+            resumable_uploads: false,
+            ..inner.capabilities().clone()
+        };
+        Ok(Self {
+            id: id.into(),
+            inner,
+            root,
+            config,
+            keys: OnceCell::new(),
+            capabilities,
+        })
+    }
+
+    pub fn inner(&self) -> &Arc<dyn StorageBackend> {
+        &self.inner
+    }
+
+    async fn keys(&self) -> CfkResult<Arc<Keys>> {
+        self.keys
+            .get_or_try_init(|| async {
+                let passphrase = self.config.passphrase.clone();
+                let salt = self.config.salt.clone();
+                // Memory-hard on purpose, so off the async workers
+                let keys =
+                    tokio::task::spawn_blocking(move || Keys::derive(&passphrase, salt.as_deref()))
+                        .await
+                        .map_err(|e| CfkError::Other(e.to_string()))??;
+                Ok(Arc::new(keys))
+            })
+            .await
+            .cloned()
+    }
+
+    /// Where `path` is stored on the inner backend
+    fn encrypt_path(&self, keys: &Keys, path: &VirtualPath) -> CfkResult<VirtualPath> {
+        if let Some(name) = path.segments.iter().find(|name| name.len() > MAX_NAME_LEN) {
+            return Err(CfkError::InvalidPath(format!(
+                "{}: the name {:?} is {} bytes, and crypt remotes take names of at most {}",
+                path,
+                name,
+                name.len(),
+                MAX_NAME_LEN
+            )));
+        }
+        let mut inner = self.root.clone();
+        inner
+            .segments
+            .extend(path.segments.iter().map(|name| keys.encrypt_name(name)));
+        Ok(inner)
+    }
+
+    /// The path an inner path stands for, or `None` for anything that is
+    /// not ours
+    fn decrypt_path(&self, keys: &Keys, inner: &VirtualPath) -> Option<VirtualPath> {
+        let names = inner.segments.strip_prefix(self.root.segments.as_slice())?;
+        let segments = names
+            .iter()
+            .map(|name| keys.decrypt_name(name))
+            .collect::<Option<Vec<_>>>()?;
+        Some(VirtualPath {
+            backend: self.id.clone(),
+            segments,
+        })
+    }
+
+    /// An inner entry as seen through the encryption
+    fn entry(&self, path: VirtualPath, inner: Entry) -> Entry {
+        let mut metadata = inner.metadata;
+        if inner.kind == EntryKind::File {
+            metadata.size = metadata.size.and_then(decrypted_size);
+        }
+        metadata.content_hash = None;
+        metadata.mime_type = None;
+        Entry {
+            path,
+            kind: inner.kind,
+            metadata,
+        }
+    }
+
+    fn opener(&self, keys: Arc<Keys>, path: &VirtualPath) -> Opener {
+        Opener {
+            keys,
+            nonce: None,
+            index: 0,
+            last: None,
+            path: path.clone(),
+        }
+    }
+
+    /// Read `start..end` by opening only the blocks that cover it
+    async fn read_range(
+        &self,
+        keys: Arc<Keys>,
+        path: &VirtualPath,
+        (start, end): (u64, u64),
+        options: &ReadOptions,
+    ) -> CfkResult<ByteStream> {
+        let inner_path = self.encrypt_path(&keys, path)?;
+        let mut opener = self.opener(keys, path);
+        let size = self
+            .inner
+            .get_metadata(&inner_path)
+            .await?
+            .metadata
+            .size
+            .and_then(decrypted_size)
+            .ok_or_else(|| opener.damaged())?;
+
+        let end = end.min(size);
+        if start >= end {
+            return Ok(Box::pin(stream::empty()));
+        }
+        let first = start / BLOCK_LEN;
+        let last = (end - 1) / BLOCK_LEN;
+        opener.index = first;
+        opener.last = Some(block_count(size) - 1);
+
+        let block_offset = |index: u64| HEADER_LEN + index * (BLOCK_LEN + TAG_LEN);
+        let from = if first == 0 {
+            0
+        } else {
+            let header = ReadOptions {
+                range: Some((0, HEADER_LEN)),
+                ..options.clone()
+            };
+            let header: Vec<Bytes> = self
+                .inner
+                .read_file(&inner_path, &header)
+                .await?
+                .try_collect()
+                .await?;
+            opener.header(&header.concat())?;
+            block_offset(first)
+        };
+
+        let body = ReadOptions {
+            range: Some((from, block_offset(last + 1))),
+            ..options.clone()
+        };
+        let blocks = self.inner.read_file(&inner_path, &body).await?;
+        Ok(trimmed(
+            decrypt_stream(opener, blocks),
+            start - first * BLOCK_LEN,
+            end - start,
+        ))
+    }
+
+    fn inner_write_options(path: &VirtualPath, options: &WriteOptions) -> WriteOptions {
+        WriteOptions {
+            // The directory holding the remote is created with its first file
+            create_parents: options.create_parents || path.segments.len() == 1,
+            // A hash of the content would be checked against the ciphertext
+            content_hash: None,
+            ..options.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for CryptBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn display_name(&self) -> &str {
+        "Crypt"
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
+
+    async fn is_available(&self) -> bool {
+        self.inner.is_available().await
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let entry = self
+            .inner
+            .get_metadata(&self.encrypt_path(&keys, path)?)
+            .await?;
+        Ok(self.entry(path.clone(), entry))
+    }
+
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let keys = self.keys().await?;
+        // Whether a name is hidden only shows once it is decrypted
+        let inner_options = ListOptions {
+            include_hidden: true,
+            ..options.clone()
+        };
+        let listing = self
+            .inner
+            .list_directory(&self.encrypt_path(&keys, path)?, &inner_options)
+            .await?;
+
+        let mut entries = Vec::with_capacity(listing.entries.len());
+        for entry in listing.entries {
+            let Some(plain) = self.decrypt_path(&keys, &entry.path) else {
+                tracing::debug!("crypt {}: skipping {}", self.id, entry.path);
+                continue;
+            };
+            let hidden = plain.name().is_some_and(|name| name.starts_with('.'));
+            if hidden && !options.include_hidden {
+                continue;
+            }
+            entries.push(self.entry(plain, entry));
+        }
+
+        Ok(DirectoryListing {
+            path: path.clone(),
+            entries,
+            cursor: listing.cursor,
+            has_more: listing.has_more,
+        })
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let keys = self.keys().await?;
+        if let Some(range) = options.range {
+            return self.read_range(keys, path, range, options).await;
+        }
+        let data = self
+            .inner
+            .read_file(&self.encrypt_path(&keys, path)?, options)
+            .await?;
+        Ok(decrypt_stream(self.opener(keys, path), data))
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let inner_path = self.encrypt_path(&keys, path)?;
+        let sealed = Sealer::new(keys).seal_all(&data)?;
+        let entry = self
+            .inner
+            .write_file(
+                &inner_path,
+                sealed,
+                &Self::inner_write_options(path, options),
+            )
+            .await?;
+        Ok(self.entry(path.clone(), entry))
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let inner_path = self.encrypt_path(&keys, path)?;
+        let sealed = encrypt_stream(Sealer::new(keys), stream);
+        let entry = self
+            .inner
+            .write_file_stream(
+                &inner_path,
+                sealed,
+                size_hint.map(encrypted_size),
+                &Self::inner_write_options(path, options),
+            )
+            .await?;
+        Ok(self.entry(path.clone(), entry))
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let entry = self
+            .inner
+            .create_directory(&self.encrypt_path(&keys, path)?)
+            .await?;
+        Ok(self.entry(path.clone(), entry))
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        let keys = self.keys().await?;
+        self.inner
+            .delete(&self.encrypt_path(&keys, path)?, options)
+            .await
+    }
+
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let entry = self
+            .inner
+            .copy(
+                &self.encrypt_path(&keys, source)?,
+                &self.encrypt_path(&keys, dest)?,
+                options,
+            )
+            .await?;
+        Ok(self.entry(dest.clone(), entry))
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        let keys = self.keys().await?;
+        let entry = self
+            .inner
+            .rename(
+                &self.encrypt_path(&keys, source)?,
+                &self.encrypt_path(&keys, dest)?,
+                options,
+            )
+            .await?;
+        Ok(self.entry(dest.clone(), entry))
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        self.inner.get_space_info().await
+    }
+
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        let keys = self.keys().await?;
+        let mut versions = self
+            .inner
+            .get_versions(&self.encrypt_path(&keys, path)?)
+            .await?;
+        for version in &mut versions {
+            version.size = version.size.and_then(decrypted_size);
+        }
+        Ok(versions)
+    }
+
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        let keys = self.keys().await?;
+        let data = self
+            .inner
+            .get_version(&self.encrypt_path(&keys, path)?, version_id)
+            .await?;
+        Ok(decrypt_stream(self.opener(keys, path), data))
+    }
+
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.inner.refresh_credentials().await
+    }
+
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        let keys = self.keys().await?;
+        self.inner
+            .set_metadata(&self.encrypt_path(&keys, path)?, metadata)
+            .await
+    }
+
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        let keys = self.keys().await?;
+        let changes = self
+            .inner
+            .watch(&self.encrypt_path(&keys, path)?, recursive)
+            .await?;
+        let (id, root) = (self.id.clone(), self.root.clone());
+        let decrypt = move |path: &VirtualPath| {
+            let names = path.segments.strip_prefix(root.segments.as_slice())?;
+            let segments = names
+                .iter()
+                .map(|name| keys.decrypt_name(name))
+                .collect::<Option<Vec<_>>>()?;
+            Some(VirtualPath {
+                backend: id.clone(),
+                segments,
+            })
+        };
+        Ok(Box::pin(changes.filter_map(move |change| {
+            let change = match change {
+                Ok(change) => decrypt(&change.path).map(|path| {
+                    let kind = match change.kind {
+                        ChangeKind::Renamed { from } => match decrypt(&from) {
+                            Some(from) => ChangeKind::Renamed { from },
+                            None => ChangeKind::Created,
+                        },
+                        kind => kind,
+                    };
+                    Ok(ChangeEvent::new(path, kind))
+                }),
+                Err(e) => Some(Err(e)),
+            };
+            future::ready(change)
+        })))
+    }
+}
+
+/// Lowercase base32hex without padding: valid in any file name on any
+/// provider, and sorts like the bytes it encodes
+mod base32 {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut text = String::with_capacity(data.len().div_ceil(5) * 8);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for &byte in data {
+            buffer = (buffer << 8 | u32::from(byte)) & 0xffff;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                text.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+            }
+        }
+        if bits > 0 {
+            text.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(text.len() * 5 / 8);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in text.bytes() {
+            let value = match c {
+                b'0'..=b'9' => c - b'0',
+                b'a'..=b'v' => c - b'a' + 10,
+                b'A'..=b'V' => c - b'A' + 10,
+                _ => return None,
+            };
+            buffer = (buffer << 5 | u32::from(value)) & 0xffff;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                data.push((buffer >> bits) as u8);
+            }
+        }
+        // Leftover bits are padding, and must be zero
+        (bits < 5 && buffer & ((1 << bits) - 1) == 0).then_some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalBackend;
+
+    fn crypt(dir: &std::path::Path, passphrase: &str) -> CryptBackend {
+        let inner: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("plain", dir));
+        let config = CryptConfig {
+            remote: "cfk://plain/vault".to_string(),
+            passphrase: passphrase.to_string(),
+            salt: None,
+        };
+        CryptBackend::new("secret", inner, config).unwrap()
+    }
+
+    async fn read(
+        backend: &dyn StorageBackend,
+        path: &VirtualPath,
+        range: Option<(u64, u64)>,
+    ) -> CfkResult<Vec<u8>> {
+        let options = ReadOptions {
+            range,
+            ..Default::default()
+        };
+        let chunks: Vec<Bytes> = backend
+            .read_file(path, &options)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(chunks.concat())
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_names_round_trip_and_reject_other_keys() {
+        let keys = Keys::derive("correct horse", None).unwrap();
+        let token = keys.encrypt_name("Tax Return 2025.pdf");
+        assert_eq!(keys.encrypt_name("Tax Return 2025.pdf"), token);
+        assert_ne!(keys.encrypt_name("Tax Return 2024.pdf"), token);
+        assert!(!token.contains("Tax"));
+        assert_eq!(
+            keys.decrypt_name(&token).as_deref(),
+            Some("Tax Return 2025.pdf")
+        );
+        assert_eq!(
+            keys.decrypt_name(&token.to_uppercase()).as_deref(),
+            Some("Tax Return 2025.pdf")
+        );
+
+        let salted = Keys::derive("correct horse", Some("other")).unwrap();
+        assert_eq!(salted.decrypt_name(&token), None);
+        assert_eq!(keys.decrypt_name("README.md"), None);
+        assert_eq!(keys.decrypt_name(""), None);
+
+        for len in 0..12 {
+            let data: Vec<u8> = (0..len).map(|i| 0xf0 ^ i as u8).collect();
+            assert_eq!(base32::decode(&base32::encode(&data)), Some(data));
+        }
+        // Five leftover bits of a one-byte encoding can't be padding
+        assert_eq!(base32::decode("000"), None);
+    }
+
+    #[test]
+    fn test_sizes_round_trip() {
+        for size in [0, 1, BLOCK_LEN - 1, BLOCK_LEN, BLOCK_LEN + 1, 3 * BLOCK_LEN] {
+            assert_eq!(decrypted_size(encrypted_size(size)), Some(size));
+        }
+        assert_eq!(decrypted_size(HEADER_LEN + TAG_LEN - 1), None);
+        assert_eq!(decrypted_size(HEADER_LEN + BLOCK_LEN + TAG_LEN + 3), None);
+    }
+
+    #[tokio::test]
+    async fn test_files_are_stored_encrypted_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = crypt(dir.path(), "correct horse");
+        let data = content(200_000);
+
+        let docs = VirtualPath::new("secret", "/Documents");
+        backend.create_directory(&docs).await.unwrap();
+        let whole = docs.join("whole.bin");
+        let entry = backend
+            .write_file(&whole, Bytes::from(data.clone()), &WriteOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(entry.metadata.size, Some(200_000));
+
+        // Odd chunk sizes, so blocks straddle them
+        let streamed = docs.join("streamed.bin");
+        let chunks: Vec<CfkResult<Bytes>> = data
+            .chunks(7919)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        backend
+            .write_file_stream(
+                &streamed,
+                Box::pin(stream::iter(chunks)),
+                Some(200_000),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        let empty = docs.join(".empty");
+        backend
+            .write_file(&empty, Bytes::new(), &WriteOptions::default())
+            .await
+            .unwrap();
+
+        for path in [&whole, &streamed] {
+            assert_eq!(read(&backend, path, None).await.unwrap(), data);
+        }
+        assert!(read(&backend, &empty, None).await.unwrap().is_empty());
+
+        let block = BLOCK_LEN as usize;
+        for (start, end) in [
+            (0, 10),
+            (block - 5, block + 5),
+            (2 * block, 2 * block + 1),
+            (150_000, 400_000),
+        ] {
+            let got = read(&backend, &streamed, Some((start as u64, end as u64)))
+                .await
+                .unwrap();
+            assert_eq!(got, data[start..end.min(data.len())], "{}..{}", start, end);
+        }
+        assert!(read(&backend, &whole, Some((300_000, 300_010)))
+            .await
+            .unwrap()
+            .is_empty());
+
+        let listing = backend
+            .list_directory(&docs, &ListOptions::default())
+            .await
+            .unwrap();
+        let mut names: Vec<_> = listing
+            .entries
+            .iter()
+            .map(|e| (e.path.name().unwrap().to_string(), e.metadata.size))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ("streamed.bin".to_string(), Some(200_000)),
+                ("whole.bin".to_string(), Some(200_000))
+            ]
+        );
+        let hidden = ListOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backend
+                .list_directory(&docs, &hidden)
+                .await
+                .unwrap()
+                .entries
+                .len(),
+            3
+        );
+
+        // Nothing readable reaches the inner backend
+        let vault = dir.path().join("vault");
+        let stored: Vec<_> = std::fs::read_dir(&vault).unwrap().collect();
+        assert_eq!(stored.len(), 1);
+        let stored_docs = stored[0].as_ref().unwrap().path();
+        assert!(!stored_docs.ends_with("Documents"));
+        for file in std::fs::read_dir(&stored_docs).unwrap() {
+            let file = file.unwrap();
+            assert!(!file.file_name().to_string_lossy().contains("bin"));
+            let bytes = std::fs::read(file.path()).unwrap();
+            assert!(
+                bytes.len() == encrypted_size(200_000) as usize
+                    || bytes.len() == encrypted_size(0) as usize
+            );
+            assert!(!bytes.windows(64).any(|w| w == &data[..64]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncation_and_wrong_passphrase_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = crypt(dir.path(), "correct horse");
+        let path = VirtualPath::new("secret", "/report.txt");
+        let data = content(3 * BLOCK_LEN as usize);
+        backend
+            .write_file(&path, Bytes::from(data), &WriteOptions::default())
+            .await
+            .unwrap();
+
+        let keys = backend.keys().await.unwrap();
+        let stored = backend.encrypt_path(&keys, &path).unwrap();
+        let file = dir
+            .path()
+            .join(stored.to_path_string().trim_start_matches('/'));
+
+        // Someone else's passphrase: the name means nothing, and neither
+        // does the content under the name it would pick
+        let wrong = crypt(dir.path(), "battery staple");
+        let root = VirtualPath::root("secret");
+        assert!(wrong
+            .list_directory(&root, &ListOptions::default())
+            .await
+            .unwrap()
+            .entries
+            .is_empty());
+        let wrong_keys = wrong.keys().await.unwrap();
+        let wrong_file = dir.path().join(
+            wrong
+                .encrypt_path(&wrong_keys, &path)
+                .unwrap()
+                .to_path_string()
+                .trim_start_matches('/'),
+        );
+        std::fs::copy(&file, &wrong_file).unwrap();
+        let err = read(&wrong, &path, None).await.unwrap_err().to_string();
+        assert!(err.contains("cannot decrypt"), "{}", err);
+
+        // Dropping whole blocks keeps a plausible size, but the new last
+        // block isn't marked as last
+        let sealed = std::fs::read(&file).unwrap();
+        std::fs::write(
+            &file,
+            &sealed[..(HEADER_LEN + 2 * (BLOCK_LEN + TAG_LEN)) as usize],
+        )
+        .unwrap();
+        assert!(read(&backend, &path, None).await.is_err());
+        assert!(read(&backend, &path, Some((BLOCK_LEN, BLOCK_LEN + 1)))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_empty_passphrase_and_long_names_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("plain", dir.path()));
+        let config = CryptConfig {
+            remote: "cfk://plain/vault".to_string(),
+            passphrase: String::new(),
+            salt: None,
+        };
+        assert!(matches!(
+            CryptBackend::new("secret", inner, config),
+            Err(CfkError::Config(_))
+        ));
+
+        let backend = crypt(dir.path(), "correct horse");
+        let keys = backend.keys().await.unwrap();
+        let longest = "n".repeat(MAX_NAME_LEN);
+        assert_eq!(keys.encrypt_name(&longest).len(), 255);
+
+        let fits = VirtualPath::new("secret", format!("/{}", longest));
+        backend
+            .write_file(&fits, Bytes::from_static(b"x"), &WriteOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(read(&backend, &fits, None).await.unwrap(), b"x");
+
+        let too_long = VirtualPath::new("secret", format!("/{}n/file", longest));
+        assert!(matches!(
+            backend
+                .write_file(
+                    &too_long,
+                    Bytes::from_static(b"x"),
+                    &WriteOptions::default()
+                )
+                .await,
+            Err(CfkError::InvalidPath(_))
+        ));
+    }
+}
@@ -5,8 +5,11 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeEvent, ChangeKind, ChangeStream, CfkError, CfkResult, Entry, EntryKind, Metadata,
+    ResumableUpload, StorageBackend, StorageCapabilities, UploadSession, VirtualPath,
 };
 use chrono::{DateTime, Utc};
 use oauth2::{
@@ -16,43 +19,109 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+
+use crate::common::{self, ChangeFeed};
+use crate::http;
+use crate::tokens::{AuthRequest, OAuthBackend, OAuthTokens, TokenManager, TokenStore};
 
 const DROPBOX_AUTH_URL: &str = "https://www.dropbox.com/oauth2/authorize";
 const DROPBOX_TOKEN_URL: &str = "https://api.dropboxapi.com/oauth2/token";
 const DROPBOX_API_URL: &str = "https://api.dropboxapi.com/2";
 const DROPBOX_CONTENT_URL: &str = "https://content.dropboxapi.com/2";
+const DROPBOX_NOTIFY_URL: &str = "https://notify.dropboxapi.com/2";
+
+/// Page size requested from `files/list_folder`
+const LIST_PAGE_SIZE: usize = 2000;
+
+/// How long `files/list_folder/longpoll` may hold a request; the most
+/// Dropbox allows
+const LONGPOLL_TIMEOUT_SECS: u64 = 480;
+
+/// Revisions asked of `files/list_revisions`; the most Dropbox returns
+const REVISION_LIMIT: u32 = 100;
+
+/// Upload session chunk size; Dropbox wants multiples of 4 MiB
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
 
 /// Dropbox OAuth tokens
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DropboxTokens {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
-    pub expires_at: Option<DateTime<Utc>>,
-}
+pub type DropboxTokens = OAuthTokens;
 
 /// Dropbox backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DropboxConfig {
     pub client_id: String,
+    #[serde(default = "default_redirect_uri")]
     pub redirect_uri: String,
+    /// RPC endpoint base (`api.dropboxapi.com`)
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// Content endpoint base (`content.dropboxapi.com`)
+    #[serde(default = "default_content_url")]
+    pub content_url: String,
+    /// Longpoll endpoint base (`notify.dropboxapi.com`)
+    #[serde(default = "default_notify_url")]
+    pub notify_url: String,
+    /// OAuth token endpoint
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_redirect_uri() -> String {
+    crate::tokens::LOOPBACK_REDIRECT_URI.to_string()
+}
+
+fn default_api_url() -> String {
+    DROPBOX_API_URL.to_string()
+}
+
+fn default_content_url() -> String {
+    DROPBOX_CONTENT_URL.to_string()
+}
+
+fn default_notify_url() -> String {
+    DROPBOX_NOTIFY_URL.to_string()
+}
+
+fn default_token_url() -> String {
+    DROPBOX_TOKEN_URL.to_string()
+}
+
+impl DropboxConfig {
+    /// Configuration pointing at the public Dropbox API
+    pub fn new(client_id: impl Into<String>, redirect_uri: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            redirect_uri: redirect_uri.into(),
+            api_url: DROPBOX_API_URL.to_string(),
+            content_url: DROPBOX_CONTENT_URL.to_string(),
+            notify_url: DROPBOX_NOTIFY_URL.to_string(),
+            token_url: DROPBOX_TOKEN_URL.to_string(),
+        }
+    }
 }
 
 /// Dropbox storage backend
+///
+/// Clones share tokens.
+#[derive(Clone)]
 pub struct DropboxBackend {
     id: String,
     config: DropboxConfig,
-    tokens: Arc<RwLock<Option<DropboxTokens>>>,
+    auth: Arc<TokenManager>,
     http: Client,
     capabilities: StorageCapabilities,
 }
 
 impl DropboxBackend {
     pub fn new(id: impl Into<String>, config: DropboxConfig) -> Self {
+        let id = id.into();
+        let auth = Arc::new(TokenManager::new(&id, &config.token_url, &config.client_id, None));
         Self {
-            id: id.into(),
+            id,
             config,
-            tokens: Arc::new(RwLock::new(None)),
+            auth,
             http: Client::new(),
             capabilities: StorageCapabilities {
                 read: true,
@@ -64,100 +133,24 @@ pub fn new(id: impl Into<String>, config: DropboxConfig) -> Self {
                 search: true,
                 versioning: true,
                 sharing: true,
+                offline: false,
                 streaming: true,
-                resume: true,
-                watch: false,
-                metadata: true,
-                thumbnails: true,
-                max_file_size: Some(350 * 1024 * 1024 * 1024), // 350GB
+                range_reads: true,
+                resumable_uploads: true,
+                content_hashing: true,
+                watch: true,
             },
         }
     }
 
-    /// Start OAuth 2.0 + PKCE flow
-    pub fn start_auth(&self) -> (String, PkceCodeVerifier) {
-        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
-            .set_auth_uri(AuthUrl::new(DROPBOX_AUTH_URL.to_string()).unwrap())
-            .set_token_uri(TokenUrl::new(DROPBOX_TOKEN_URL.to_string()).unwrap())
-            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
-
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-
-        let (auth_url, _csrf_token) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("files.metadata.read".to_string()))
-            .add_scope(Scope::new("files.metadata.write".to_string()))
-            .add_scope(Scope::new("files.content.read".to_string()))
-            .add_scope(Scope::new("files.content.write".to_string()))
-            .set_pkce_challenge(pkce_challenge)
-            .url();
-
-        (auth_url.to_string(), pkce_verifier)
+    /// Set tokens directly, persisting them to the token store if any
+    pub async fn set_tokens(&self, tokens: DropboxTokens) -> CfkResult<()> {
+        self.auth.set(tokens).await
     }
 
-    /// Complete OAuth flow with authorization code
-    pub async fn complete_auth(
-        &self,
-        code: &str,
-        verifier: PkceCodeVerifier,
-    ) -> CfkResult<DropboxTokens> {
-        let params = [
-            ("code", code),
-            ("grant_type", "authorization_code"),
-            ("client_id", &self.config.client_id),
-            ("redirect_uri", &self.config.redirect_uri),
-            ("code_verifier", verifier.secret()),
-        ];
-
-        let response = self
-            .http
-            .post(DROPBOX_TOKEN_URL)
-            .form(&params)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::Auth(format!("Token exchange failed: {}", error_text)));
-        }
-
-        #[derive(Deserialize)]
-        struct TokenResponse {
-            access_token: String,
-            refresh_token: Option<String>,
-            expires_in: Option<i64>,
-        }
-
-        let token_resp: TokenResponse = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
-
-        let tokens = DropboxTokens {
-            access_token: token_resp.access_token,
-            refresh_token: token_resp.refresh_token,
-            expires_at: token_resp
-                .expires_in
-                .map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
-        };
-
-        *self.tokens.write().await = Some(tokens.clone());
-        Ok(tokens)
-    }
-
-    /// Set tokens directly (for restoring from storage)
-    pub async fn set_tokens(&self, tokens: DropboxTokens) {
-        *self.tokens.write().await = Some(tokens);
-    }
-
-    /// Get current access token
+    /// Current access token, refreshed first when it is about to expire
     async fn get_access_token(&self) -> CfkResult<String> {
-        let tokens = self.tokens.read().await;
-        tokens
-            .as_ref()
-            .map(|t| t.access_token.clone())
-            .ok_or_else(|| CfkError::Auth("Not authenticated".into()))
+        self.auth.access_token(&self.http).await
     }
 
     /// Make authenticated API request
@@ -167,7 +160,7 @@ async fn api_request<T: for<'de> Deserialize<'de>>(
         body: impl Serialize,
     ) -> CfkResult<T> {
         let token = self.get_access_token().await?;
-        let url = format!("{}/{}", DROPBOX_API_URL, endpoint);
+        let url = format!("{}/{}", self.config.api_url, endpoint);
 
         let response = self
             .http
@@ -177,16 +170,9 @@ async fn api_request<T: for<'de> Deserialize<'de>>(
             .json(&body)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .map_err(http::network_error)?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "dropbox".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+        let response = self.check(response).await?;
 
         response
             .json()
@@ -194,10 +180,51 @@ async fn api_request<T: for<'de> Deserialize<'de>>(
             .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
-    /// Convert Dropbox path to VirtualPath
-    fn to_virtual_path(&self, dropbox_path: &str) -> VirtualPath {
-        let path = dropbox_path.trim_start_matches('/');
-        VirtualPath::new(&self.id, path)
+    /// Map Dropbox error responses.
+    ///
+    /// Dropbox reports missing paths as `409 path/not_found` rather than 404,
+    /// and existing destinations as `409 .../conflict`.
+    async fn check(&self, response: reqwest::Response) -> CfkResult<reqwest::Response> {
+        if response.status() != reqwest::StatusCode::CONFLICT {
+            return http::check_response("dropbox", response).await;
+        }
+
+        let text = response.text().await.unwrap_or_default();
+        if text.contains("not_found") {
+            Err(CfkError::NotFound(text))
+        } else if text.contains("conflict") {
+            Err(CfkError::AlreadyExists(text))
+        } else {
+            Err(CfkError::ProviderApi {
+                provider: "dropbox".into(),
+                message: format!("409 Conflict: {}", text),
+            })
+        }
+    }
+
+    /// Call a content endpoint, passing the argument in `Dropbox-API-Arg`
+    async fn content_request(
+        &self,
+        endpoint: &str,
+        arg: impl Serialize,
+        body: Bytes,
+    ) -> CfkResult<reqwest::Response> {
+        let token = self.get_access_token().await?;
+        let arg =
+            serde_json::to_string(&arg).map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+        let response = self
+            .http
+            .post(format!("{}/{}", self.config.content_url, endpoint))
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Dropbox-API-Arg", arg)
+            .header("Content-Type", "application/octet-stream")
+            .body(body)
+            .send()
+            .await
+            .map_err(http::network_error)?;
+
+        self.check(response).await
     }
 
     /// Convert VirtualPath to Dropbox path
@@ -208,6 +235,164 @@ fn to_dropbox_path(&self, path: &VirtualPath) -> String {
             format!("/{}", path.segments.join("/"))
         }
     }
+
+    /// Download `path`, which may also be `rev:<revision>`
+    async fn download(&self, path: String, range: Option<(u64, u64)>) -> CfkResult<ByteStream> {
+        let token = self.get_access_token().await?;
+
+        #[derive(Serialize)]
+        struct DownloadArg {
+            path: String,
+        }
+
+        let arg = serde_json::to_string(&DownloadArg { path })
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+        let mut request = self
+            .http
+            .post(format!("{}/files/download", self.config.content_url))
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Dropbox-API-Arg", arg);
+        if let Some(range) = range {
+            request = request.header(reqwest::header::RANGE, http::range_header(range));
+        }
+        let response = request.send().await.map_err(http::network_error)?;
+
+        if range.is_some() && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+        let response = self.check(response).await?;
+        Ok(http::range_body(response, range))
+    }
+
+    fn listing(&self, path: &VirtualPath, result: ListFolderResponse) -> DirectoryListing {
+        DirectoryListing {
+            path: path.clone(),
+            entries: result
+                .entries
+                .iter()
+                .filter(|m| m.tag != "deleted")
+                .map(|m| m.to_entry(&self.id))
+                .collect(),
+            cursor: result.has_more.then_some(result.cursor),
+            has_more: result.has_more,
+        }
+    }
+
+    /// A cursor for changes to `folder` from now on
+    async fn latest_cursor(&self, folder: &VirtualPath, recursive: bool) -> CfkResult<String> {
+        #[derive(Deserialize)]
+        struct LatestCursor {
+            cursor: String,
+        }
+
+        let result: LatestCursor = self
+            .api_request(
+                "files/list_folder/get_latest_cursor",
+                serde_json::json!({
+                    "path": self.to_dropbox_path(folder),
+                    "recursive": recursive,
+                    "include_deleted": true,
+                }),
+            )
+            .await?;
+        Ok(result.cursor)
+    }
+}
+
+/// Changes below a folder, from `files/list_folder/longpoll`
+struct DropboxChanges {
+    backend: DropboxBackend,
+    folder: VirtualPath,
+    recursive: bool,
+    cursor: String,
+    /// Seconds Dropbox asked us to wait before the next longpoll
+    backoff: Option<u64>,
+}
+
+impl DropboxChanges {
+    /// Wait until the cursor has changes; the notify endpoint takes no
+    /// token
+    async fn wait(&mut self) -> CfkResult<bool> {
+        #[derive(Deserialize)]
+        struct Longpoll {
+            changes: bool,
+            backoff: Option<u64>,
+        }
+
+        if let Some(secs) = self.backoff.take() {
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+        }
+        let response = self
+            .backend
+            .http
+            .post(format!("{}/files/list_folder/longpoll", self.backend.config.notify_url))
+            .json(&serde_json::json!({
+                "cursor": self.cursor,
+                "timeout": LONGPOLL_TIMEOUT_SECS,
+            }))
+            .send()
+            .await
+            .map_err(http::network_error)?;
+        let result: Longpoll = self
+            .backend
+            .check(response)
+            .await?
+            .json()
+            .await
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        self.backoff = result.backoff;
+        Ok(result.changes)
+    }
+
+    /// Page through what changed since the cursor.
+    ///
+    /// Entries carry their current state rather than what happened, so
+    /// anything not deleted is reported as modified.
+    async fn fetch(&mut self) -> CfkResult<Vec<ChangeEvent>> {
+        let mut events = Vec::new();
+        loop {
+            let result: ListFolderResponse = self
+                .backend
+                .api_request(
+                    "files/list_folder/continue",
+                    serde_json::json!({ "cursor": self.cursor }),
+                )
+                .await?;
+            events.extend(result.entries.iter().map(|m| {
+                let kind = if m.tag == "deleted" {
+                    ChangeKind::Deleted
+                } else {
+                    ChangeKind::Modified
+                };
+                ChangeEvent::new(m.to_entry(&self.backend.id).path, kind)
+            }));
+            self.cursor = result.cursor;
+            if !result.has_more {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChangeFeed for DropboxChanges {
+    async fn poll(&mut self) -> CfkResult<Vec<ChangeEvent>> {
+        let result = match self.wait().await {
+            Ok(true) => self.fetch().await,
+            Ok(false) => return Ok(Vec::new()),
+            Err(e) => Err(e),
+        };
+        match result {
+            // The cursor expired: start over and have everything looked at
+            // again
+            Err(CfkError::ProviderApi { message, .. }) if message.contains("reset") => {
+                self.cursor = self.backend.latest_cursor(&self.folder, self.recursive).await?;
+                Ok(vec![ChangeEvent::new(self.folder.clone(), ChangeKind::Rescan)])
+            }
+            other => other,
+        }
+    }
 }
 
 /// Dropbox file metadata response
@@ -240,16 +425,26 @@ fn to_entry(&self, backend_id: &str) -> Entry {
             EntryKind::File
         };
 
-        let mut metadata = Metadata::default();
-        metadata.size = self.size;
-        if let Some(ref modified) = self.server_modified {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(modified) {
-                metadata.modified = Some(dt.with_timezone(&Utc));
-            }
-        }
-        if let Some(ref hash) = self.content_hash {
-            metadata.checksum = Some(hash.clone());
-        }
+        let modified = self
+            .server_modified
+            .as_deref()
+            .and_then(|m| DateTime::parse_from_rfc3339(m).ok())
+            .map(|dt| dt.with_timezone(&Utc));
+        let created = self
+            .client_modified
+            .as_deref()
+            .and_then(|m| DateTime::parse_from_rfc3339(m).ok())
+            .map(|dt| dt.with_timezone(&Utc));
+
+        let metadata = Metadata {
+            size: self.size,
+            modified,
+            created,
+            content_hash: self.content_hash.clone(),
+            provider_id: self.id.clone(),
+            revision: self.rev.clone(),
+            ..Default::default()
+        };
 
         Entry {
             path: virtual_path,
@@ -267,6 +462,170 @@ struct ListFolderResponse {
     has_more: bool,
 }
 
+#[derive(Serialize)]
+struct UploadCursor<'a> {
+    session_id: &'a str,
+    offset: u64,
+}
+
+/// Offset Dropbox expected, from an `incorrect_offset` append error
+fn correct_offset(error: &CfkError) -> Option<u64> {
+    let CfkError::ProviderApi { message, .. } = error else {
+        return None;
+    };
+    let json = &message[message.find('{')?..];
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    value["error"]["correct_offset"].as_u64()
+}
+
+/// Upload sessions
+#[async_trait]
+impl ResumableUpload for DropboxBackend {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        #[derive(Deserialize)]
+        struct StartResult {
+            session_id: String,
+        }
+
+        let result: StartResult = self
+            .content_request(
+                "files/upload_session/start",
+                serde_json::json!({ "close": false }),
+                Bytes::new(),
+            )
+            .await?
+            .json()
+            .await
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+        Ok(UploadSession::new(
+            path.clone(),
+            size,
+            result.session_id,
+            UPLOAD_CHUNK_SIZE,
+            options.overwrite,
+        ))
+    }
+
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        let size = data.len() as u64;
+        let arg = serde_json::json!({
+            "cursor": UploadCursor { session_id: &session.id, offset: session.offset },
+            "close": false,
+        });
+        self.content_request("files/upload_session/append_v2", arg, data)
+            .await?;
+        session.offset += size;
+        Ok(())
+    }
+
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+        // There is no status call; an empty append at the offset we believe
+        // in either succeeds or reports the offset Dropbox has
+        let arg = serde_json::json!({
+            "cursor": UploadCursor { session_id: &session.id, offset: session.offset },
+            "close": false,
+        });
+        match self
+            .content_request("files/upload_session/append_v2", arg, Bytes::new())
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => match correct_offset(&e) {
+                Some(offset) => session.offset = offset,
+                None => return Err(e),
+            },
+        }
+        Ok(session.offset)
+    }
+
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        let mode = if session.overwrite {
+            "overwrite"
+        } else {
+            "add"
+        };
+        let arg = serde_json::json!({
+            "cursor": UploadCursor { session_id: &session.id, offset: session.offset },
+            "commit": {
+                "path": self.to_dropbox_path(&session.path),
+                "mode": mode,
+                "autorename": false,
+                "mute": false,
+            },
+        });
+
+        let metadata: DropboxMetadata = self
+            .content_request("files/upload_session/finish", arg, Bytes::new())
+            .await?
+            .json()
+            .await
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+        Ok(metadata.to_entry(&self.id))
+    }
+}
+
+#[async_trait]
+impl OAuthBackend for DropboxBackend {
+    fn with_token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        Arc::get_mut(&mut self.auth)
+            .expect("token store attached before the backend is cloned")
+            .attach_store(store);
+        self
+    }
+
+    fn redirect_uri(&self) -> &str {
+        &self.config.redirect_uri
+    }
+
+    fn start_auth(&self) -> AuthRequest {
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_auth_uri(AuthUrl::new(DROPBOX_AUTH_URL.to_string()).unwrap())
+            .set_token_uri(TokenUrl::new(self.config.token_url.clone()).unwrap())
+            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
+
+        let (pkce_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let (auth_url, state) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new("files.metadata.read".to_string()))
+            .add_scope(Scope::new("files.metadata.write".to_string()))
+            .add_scope(Scope::new("files.content.read".to_string()))
+            .add_scope(Scope::new("files.content.write".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            // Without offline access Dropbox hands out no refresh token
+            .add_extra_param("token_access_type", "offline")
+            .url();
+
+        AuthRequest {
+            url: auth_url.to_string(),
+            state,
+            verifier,
+        }
+    }
+
+    async fn complete_auth(
+        &self,
+        code: &str,
+        verifier: PkceCodeVerifier,
+    ) -> CfkResult<OAuthTokens> {
+        let params = [
+            ("code", code),
+            ("grant_type", "authorization_code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", verifier.secret()),
+        ];
+        self.auth.exchange(&self.http, &params).await
+    }
+}
+
 #[async_trait]
 impl StorageBackend for DropboxBackend {
     fn id(&self) -> &str {
@@ -281,8 +640,16 @@ fn capabilities(&self) -> &StorageCapabilities {
         &self.capabilities
     }
 
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.auth.refresh(&self.http).await
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        Some(self)
+    }
+
     async fn is_available(&self) -> bool {
-        self.tokens.read().await.is_some()
+        self.auth.is_authenticated().await
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
@@ -309,50 +676,18 @@ struct GetMetadataArg {
         Ok(result.to_entry(&self.id))
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let dropbox_path = self.to_dropbox_path(path);
-
-        #[derive(Serialize)]
-        struct ListFolderArg {
-            path: String,
-            recursive: bool,
-            include_deleted: bool,
-            limit: u32,
-        }
-
-        let result: ListFolderResponse = self
-            .api_request(
-                "files/list_folder",
-                ListFolderArg {
-                    path: if dropbox_path.is_empty() {
-                        String::new()
-                    } else {
-                        dropbox_path
-                    },
-                    recursive: false,
-                    include_deleted: false,
-                    limit: 2000,
-                },
-            )
-            .await?;
-
-        let mut entries: Vec<Entry> = result
-            .entries
-            .iter()
-            .map(|m| m.to_entry(&self.id))
-            .collect();
-
-        // Handle pagination
-        let mut cursor = result.cursor;
-        let mut has_more = result.has_more;
-
-        while has_more {
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        if let Some(cursor) = &options.cursor {
             #[derive(Serialize)]
             struct ListFolderContinueArg {
                 cursor: String,
             }
 
-            let continue_result: ListFolderResponse = self
+            let result: ListFolderResponse = self
                 .api_request(
                     "files/list_folder/continue",
                     ListFolderContinueArg {
@@ -361,51 +696,56 @@ struct ListFolderContinueArg {
                 )
                 .await?;
 
-            entries.extend(continue_result.entries.iter().map(|m| m.to_entry(&self.id)));
-            cursor = continue_result.cursor;
-            has_more = continue_result.has_more;
+            return Ok(self.listing(path, result));
         }
 
-        Ok(entries)
-    }
-
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let token = self.get_access_token().await?;
-        let dropbox_path = self.to_dropbox_path(path);
-
         #[derive(Serialize)]
-        struct DownloadArg {
+        struct ListFolderArg {
             path: String,
+            recursive: bool,
+            include_deleted: bool,
+            limit: u32,
         }
 
-        let arg = serde_json::to_string(&DownloadArg { path: dropbox_path })
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let limit = options
+            .limit
+            .unwrap_or(LIST_PAGE_SIZE)
+            .clamp(1, LIST_PAGE_SIZE);
 
-        let response = self
-            .http
-            .post(format!("{}/files/download", DROPBOX_CONTENT_URL))
-            .header("Authorization", format!("Bearer {}", token))
-            .header("Dropbox-API-Arg", arg)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let mut result: ListFolderResponse = self
+            .api_request(
+                "files/list_folder",
+                ListFolderArg {
+                    path: self.to_dropbox_path(path),
+                    recursive: options.recursive,
+                    include_deleted: false,
+                    limit: limit as u32,
+                },
+            )
+            .await?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "dropbox".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+        // A recursive listing includes the folder itself
+        let own_path = self.to_dropbox_path(path).to_lowercase();
+        result.entries.retain(|m| {
+            m.path_display
+                .as_deref()
+                .map(|p| p.to_lowercase() != own_path)
+                .unwrap_or(true)
+        });
 
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+        Ok(self.listing(path, result))
     }
 
-    async fn write_file(&self, path: &VirtualPath, data: Bytes) -> CfkResult<Entry> {
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        self.download(self.to_dropbox_path(path), options.range).await
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
         let token = self.get_access_token().await?;
         let dropbox_path = self.to_dropbox_path(path);
 
@@ -417,9 +757,17 @@ struct UploadArg {
             mute: bool,
         }
 
+        // "add" refuses to replace an existing file instead of renaming it
+        // because autorename is off
+        let mode = if options.overwrite {
+            "overwrite"
+        } else {
+            "add"
+        };
+
         let arg = serde_json::to_string(&UploadArg {
             path: dropbox_path,
-            mode: "overwrite".to_string(),
+            mode: mode.to_string(),
             autorename: false,
             mute: false,
         })
@@ -427,33 +775,45 @@ struct UploadArg {
 
         let response = self
             .http
-            .post(format!("{}/files/upload", DROPBOX_CONTENT_URL))
+            .post(format!("{}/files/upload", self.config.content_url))
             .header("Authorization", format!("Bearer {}", token))
             .header("Dropbox-API-Arg", arg)
             .header("Content-Type", "application/octet-stream")
-            .body(data.to_vec())
+            .body(data)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .map_err(http::network_error)?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "dropbox".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+        let response = self.check(response).await?;
 
         let metadata: DropboxMetadata = response
             .json()
             .await
             .map_err(|e| CfkError::Serialization(e.to_string()))?;
 
+        if let Some(expected) = &options.content_hash {
+            if metadata.content_hash.as_deref() != Some(expected.as_str()) {
+                return Err(CfkError::ChecksumMismatch);
+            }
+        }
+
         Ok(metadata.to_entry(&self.id))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let data = common::collect_stream(stream).await?;
+        self.write_file(path, data, options).await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        common::check_delete(self, path, options).await?;
+
         let dropbox_path = self.to_dropbox_path(path);
 
         #[derive(Serialize)]
@@ -461,11 +821,11 @@ struct DeleteArg {
             path: String,
         }
 
-        let _: serde_json::Value = self
+        let result: CfkResult<serde_json::Value> = self
             .api_request("files/delete_v2", DeleteArg { path: dropbox_path })
-            .await?;
+            .await;
 
-        Ok(())
+        common::ignore_missing(result.map(|_| ()), options)
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
@@ -495,9 +855,16 @@ struct CreateFolderResult {
         Ok(result.metadata.to_entry(&self.id))
     }
 
-    async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let from_path = self.to_dropbox_path(from);
-        let to_path = self.to_dropbox_path(to);
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+
+        let from_path = self.to_dropbox_path(source);
+        let to_path = self.to_dropbox_path(dest);
 
         #[derive(Serialize)]
         struct CopyArg {
@@ -525,9 +892,16 @@ struct CopyResult {
         Ok(result.metadata.to_entry(&self.id))
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let from_path = self.to_dropbox_path(from);
-        let to_path = self.to_dropbox_path(to);
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+
+        let from_path = self.to_dropbox_path(source);
+        let to_path = self.to_dropbox_path(dest);
 
         #[derive(Serialize)]
         struct MoveArg {
@@ -555,7 +929,66 @@ struct MoveResult {
         Ok(result.metadata.to_entry(&self.id))
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        #[derive(Serialize)]
+        struct ListRevisionsArg {
+            path: String,
+            limit: u32,
+        }
+
+        #[derive(Deserialize)]
+        struct ListRevisionsResponse {
+            entries: Vec<Revision>,
+        }
+
+        #[derive(Deserialize)]
+        struct Revision {
+            rev: String,
+            size: Option<u64>,
+            server_modified: DateTime<Utc>,
+        }
+
+        let result: ListRevisionsResponse = self
+            .api_request(
+                "files/list_revisions",
+                ListRevisionsArg {
+                    path: self.to_dropbox_path(path),
+                    limit: REVISION_LIMIT,
+                },
+            )
+            .await?;
+
+        Ok(result
+            .entries
+            .into_iter()
+            .map(|revision| FileVersion {
+                id: revision.rev,
+                modified: revision.server_modified,
+                size: revision.size,
+                author: None,
+            })
+            .collect())
+    }
+
+    async fn get_version(&self, _path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        // Revisions are addressed on their own, wherever the file is now
+        self.download(format!("rev:{}", version_id), None).await
+    }
+
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        let folder = common::watched_folder(self, path).await?;
+        let recursive = recursive && folder == *path;
+        let changes = DropboxChanges {
+            backend: self.clone(),
+            cursor: self.latest_cursor(&folder, recursive).await?,
+            folder,
+            recursive,
+            backoff: None,
+        };
+        Ok(common::change_stream(changes, path.clone(), recursive))
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         #[derive(Deserialize)]
         struct SpaceUsage {
             used: u64,
@@ -564,8 +997,6 @@ struct SpaceUsage {
 
         #[derive(Deserialize)]
         struct SpaceAllocation {
-            #[serde(rename = ".tag")]
-            tag: String,
             allocated: Option<u64>,
         }
 
@@ -573,10 +1004,392 @@ struct SpaceAllocation {
             .api_request("users/get_space_usage", serde_json::json!(null))
             .await?;
 
-        let total = result.allocation.allocated.unwrap_or(0);
+        let total = result.allocation.allocated;
         let used = result.used;
-        let available = total.saturating_sub(used);
 
-        Ok((available, total))
+        Ok(SpaceInfo {
+            total,
+            used: Some(used),
+            available: total.map(|t| t.saturating_sub(used)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use wiremock::matchers::{body_json, body_string_contains, header, header_regex, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn backend(server: &MockServer) -> DropboxBackend {
+        let mut config = DropboxConfig::new("client", "http://localhost/callback");
+        config.api_url = server.uri();
+        config.content_url = server.uri();
+        config.notify_url = server.uri();
+        let backend = DropboxBackend::new("dropbox", config);
+        backend
+            .set_tokens(DropboxTokens {
+                access_token: "token".into(),
+                refresh_token: None,
+                expires_at: None,
+            })
+            .await
+            .unwrap();
+        backend
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_pages_with_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/list_folder"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "entries": [
+                    {".tag": "file", "name": "a.txt", "path_display": "/docs/a.txt",
+                     "id": "id:a", "size": 3, "rev": "015", "content_hash": "abc",
+                     "server_modified": "2024-01-01T00:00:00Z"}
+                ],
+                "cursor": "c1",
+                "has_more": true
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/list_folder/continue"))
+            .and(body_json(serde_json::json!({"cursor": "c1"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "entries": [
+                    {".tag": "folder", "name": "sub", "path_display": "/docs/sub", "id": "id:s"}
+                ],
+                "cursor": "c2",
+                "has_more": false
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let dir = VirtualPath::new("dropbox", "docs");
+
+        let first = backend
+            .list_directory(&dir, &ListOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(first.entries.len(), 1);
+        assert!(first.has_more);
+        assert_eq!(first.cursor.as_deref(), Some("c1"));
+        let file = &first.entries[0];
+        assert_eq!(file.path.to_path_string(), "/docs/a.txt");
+        assert_eq!(file.metadata.revision.as_deref(), Some("015"));
+        assert_eq!(file.metadata.content_hash.as_deref(), Some("abc"));
+
+        let options = ListOptions {
+            cursor: first.cursor.clone(),
+            ..Default::default()
+        };
+        let second = backend.list_directory(&dir, &options).await.unwrap();
+        assert_eq!(second.entries.len(), 1);
+        assert!(second.entries[0].is_directory());
+        assert!(!second.has_more);
+        assert!(second.cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_file_streams_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/download"))
+            .and(header("Dropbox-API-Arg", r#"{"path":"/hello.txt"}"#))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let mut stream = backend
+            .read_file(
+                &VirtualPath::new("dropbox", "hello.txt"),
+                &ReadOptions::default(),
+            )
+            .await
+            .unwrap();
+
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_write_without_overwrite_uses_add_mode() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/upload"))
+            .and(header_regex("Dropbox-API-Arg", r#""mode":"add""#))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                ".tag": "file", "name": "new.txt", "path_display": "/new.txt", "size": 4
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let entry = backend
+            .write_file(
+                &VirtualPath::new("dropbox", "new.txt"),
+                Bytes::from_static(b"data"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(entry.size(), Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_versions_list_revisions_and_download_by_rev() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/list_revisions"))
+            .and(body_json(serde_json::json!({"path": "/notes.txt", "limit": 100})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "is_deleted": false,
+                "entries": [
+                    {"name": "notes.txt", "rev": "a2", "size": 7,
+                     "server_modified": "2024-02-01T00:00:00Z"},
+                    {"name": "notes.txt", "rev": "a1", "size": 3,
+                     "server_modified": "2024-01-01T00:00:00Z"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/download"))
+            .and(header("Dropbox-API-Arg", r#"{"path":"rev:a1"}"#))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"old".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let file = VirtualPath::new("dropbox", "notes.txt");
+        let versions = backend.get_versions(&file).await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].id, "a1");
+        assert_eq!(versions[1].size, Some(3));
+        assert_eq!(versions[0].modified.to_rfc3339(), "2024-02-01T00:00:00+00:00");
+
+        let old = common::collect_stream(backend.get_version(&file, "a1").await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&old[..], b"old");
+    }
+
+    #[tokio::test]
+    async fn test_path_not_found_maps_to_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/get_metadata"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(serde_json::json!({
+                "error_summary": "path/not_found/..",
+                "error": {".tag": "path", "path": {".tag": "not_found"}}
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let err = backend
+            .get_metadata(&VirtualPath::new("dropbox", "missing"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_watch_longpolls_then_continues() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/get_metadata"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                ".tag": "folder", "name": "docs", "path_display": "/docs", "id": "id:d"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/list_folder/get_latest_cursor"))
+            .and(body_string_contains("\"recursive\":true"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"cursor": "c1"})),
+            )
+            .mount(&server)
+            .await;
+        // The notify endpoint is called without a token
+        Mock::given(method("POST"))
+            .and(path("/files/list_folder/longpoll"))
+            .and(body_string_contains("\"cursor\":\"c1\""))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"changes": true})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/list_folder/continue"))
+            .and(body_json(serde_json::json!({"cursor": "c1"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "entries": [
+                    {".tag": "file", "name": "a.txt", "path_display": "/docs/a.txt", "id": "id:a"},
+                    {".tag": "deleted", "name": "old.txt", "path_display": "/docs/sub/old.txt"}
+                ],
+                "cursor": "c2",
+                "has_more": false
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let docs = VirtualPath::new("dropbox", "docs");
+        let mut changes = backend.watch(&docs, true).await.unwrap();
+
+        let first = changes.next().await.unwrap().unwrap();
+        assert_eq!(first.path.to_path_string(), "/docs/a.txt");
+        assert_eq!(first.kind, ChangeKind::Modified);
+        let second = changes.next().await.unwrap().unwrap();
+        assert_eq!(second.path.to_path_string(), "/docs/sub/old.txt");
+        assert_eq!(second.kind, ChangeKind::Deleted);
+
+        let requests = server.received_requests().await.unwrap();
+        let longpoll = requests
+            .iter()
+            .find(|r| r.url.path() == "/files/list_folder/longpoll")
+            .unwrap();
+        assert!(!longpoll.headers.contains_key("authorization"));
+    }
+
+    #[tokio::test]
+    async fn test_space_info() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/users/get_space_usage"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "used": 100,
+                "allocation": {".tag": "individual", "allocated": 1000}
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let info = backend.get_space_info().await.unwrap();
+        assert_eq!(info.total, Some(1000));
+        assert_eq!(info.used, Some(100));
+        assert_eq!(info.available, Some(900));
+    }
+
+    #[tokio::test]
+    async fn test_upload_session_recovers_offset() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/upload_session/start"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"session_id": "s1"})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/upload_session/append_v2"))
+            .and(header_regex("Dropbox-API-Arg", r#""offset":0"#))
+            .respond_with(ResponseTemplate::new(409).set_body_json(serde_json::json!({
+                "error_summary": "incorrect_offset/..",
+                "error": {".tag": "incorrect_offset", "correct_offset": 8}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/upload_session/finish"))
+            .and(header_regex("Dropbox-API-Arg", r#""mode":"add""#))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                ".tag": "file", "name": "big.bin", "path_display": "/big.bin", "size": 8
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let path = VirtualPath::new("dropbox", "big.bin");
+        let mut session = backend
+            .create_session(&path, 8, &WriteOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(backend.query_offset(&mut session).await.unwrap(), 8);
+        assert!(session.is_complete());
+
+        let entry = backend.finalize(&session).await.unwrap();
+        assert_eq!(entry.path, path);
+    }
+
+    #[tokio::test]
+    async fn test_retry_refreshes_expired_token_and_rides_out_503() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/oauth2/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .and(body_string_contains("refresh_token=r1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "fresh", "expires_in": 14400
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/get_metadata"))
+            .and(header("Authorization", "Bearer token"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_string(r#"{"error": {".tag": "expired_access_token"}}"#),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/get_metadata"))
+            .and(header("Authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/files/get_metadata"))
+            .and(header("Authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                ".tag": "file", "name": "a.txt", "path_display": "/a.txt",
+                "id": "id:a", "size": 3, "rev": "015"
+            })))
+            .mount(&server)
+            .await;
+
+        let mut config = DropboxConfig::new("client", "http://localhost/callback");
+        config.api_url = server.uri();
+        config.token_url = format!("{}/oauth2/token", server.uri());
+        let dropbox = DropboxBackend::new("dropbox", config);
+        dropbox
+            .set_tokens(DropboxTokens {
+                access_token: "token".into(),
+                refresh_token: Some("r1".into()),
+                expires_at: None,
+            })
+            .await
+            .unwrap();
+        let policy = crate::RetryPolicy {
+            base_delay: std::time::Duration::from_millis(1),
+            ..Default::default()
+        };
+        let backend = crate::RetryBackend::with_policy(Arc::new(dropbox), policy);
+
+        let entry = backend
+            .get_metadata(&VirtualPath::new("dropbox", "a.txt"))
+            .await
+            .unwrap();
+        assert_eq!(entry.metadata.size, Some(3));
+        let stats = backend.stats();
+        assert_eq!(stats.token_refreshes, 1);
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.exhausted, 0);
+        // What the registry sees, behind `dyn StorageBackend`
+        let registered: Arc<dyn StorageBackend> = Arc::new(backend);
+        assert_eq!(registered.retry_stats(), Some(stats));
     }
 }
@@ -5,8 +5,11 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeEvent, ChangeKind, ChangeStream, CfkError, CfkResult, Entry, EntryKind, Metadata,
+    ResumableUpload, StorageBackend, StorageCapabilities, UploadSession, VirtualPath,
 };
 use chrono::{DateTime, Utc};
 use oauth2::{
@@ -17,46 +20,119 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+use crate::common::{self, ChangeFeed};
+use crate::http;
+use crate::tokens::{AuthRequest, OAuthBackend, OAuthTokens, TokenManager, TokenStore};
+
 const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
 const DRIVE_API_URL: &str = "https://www.googleapis.com/drive/v3";
 const DRIVE_UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3";
 
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+const FILE_FIELDS: &str =
+    "id,name,mimeType,size,createdTime,modifiedTime,md5Checksum,headRevisionId";
+
+const REVISION_FIELDS: &str =
+    "nextPageToken,revisions(id,modifiedTime,size,lastModifyingUser(displayName,emailAddress))";
+
+/// Largest page `files.list` will return
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// How often to ask `changes.list` for news; Drive has no long poll
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const CHANGE_FIELDS: &str =
+    "nextPageToken,newStartPageToken,changes(fileId,removed,file(id,name,mimeType,parents,trashed))";
+
+/// Deepest folder chain followed when placing a changed file
+const MAX_FOLDER_DEPTH: usize = 64;
+
+/// Resumable upload chunk size; Drive wants multiples of 256 KiB
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
 /// Google OAuth tokens
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GoogleTokens {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
-    pub expires_at: Option<DateTime<Utc>>,
-}
+pub type GoogleTokens = OAuthTokens;
 
 /// Google Drive backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GoogleDriveConfig {
     pub client_id: String,
+    #[serde(default)]
     pub client_secret: Option<String>,
+    #[serde(default = "default_redirect_uri")]
     pub redirect_uri: String,
+    /// Drive API v3 base URL
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// Drive upload endpoint base URL
+    #[serde(default = "default_upload_url")]
+    pub upload_url: String,
+    /// OAuth token endpoint
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_redirect_uri() -> String {
+    crate::tokens::LOOPBACK_REDIRECT_URI.to_string()
+}
+
+fn default_api_url() -> String {
+    DRIVE_API_URL.to_string()
+}
+
+fn default_upload_url() -> String {
+    DRIVE_UPLOAD_URL.to_string()
+}
+
+fn default_token_url() -> String {
+    GOOGLE_TOKEN_URL.to_string()
+}
+
+impl GoogleDriveConfig {
+    /// Configuration pointing at the public Drive API
+    pub fn new(client_id: impl Into<String>, redirect_uri: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: None,
+            redirect_uri: redirect_uri.into(),
+            api_url: DRIVE_API_URL.to_string(),
+            upload_url: DRIVE_UPLOAD_URL.to_string(),
+            token_url: GOOGLE_TOKEN_URL.to_string(),
+        }
+    }
 }
 
 /// Google Drive storage backend
+///
+/// Clones share tokens and cached IDs.
+#[derive(Clone)]
 pub struct GoogleDriveBackend {
     id: String,
     config: GoogleDriveConfig,
-    tokens: Arc<RwLock<Option<GoogleTokens>>>,
+    auth: Arc<TokenManager>,
     http: Client,
     capabilities: StorageCapabilities,
-    /// Cache of file ID to path mapping
+    /// Cache of path to file ID mapping
     path_cache: Arc<RwLock<HashMap<String, String>>>,
 }
 
 impl GoogleDriveBackend {
     pub fn new(id: impl Into<String>, config: GoogleDriveConfig) -> Self {
+        let id = id.into();
+        let auth = Arc::new(TokenManager::new(
+            &id,
+            &config.token_url,
+            &config.client_id,
+            config.client_secret.as_deref(),
+        ));
         Self {
-            id: id.into(),
+            id,
             config,
-            tokens: Arc::new(RwLock::new(None)),
+            auth,
             http: Client::new(),
             capabilities: StorageCapabilities {
                 read: true,
@@ -68,109 +144,87 @@ pub fn new(id: impl Into<String>, config: GoogleDriveConfig) -> Self {
                 search: true,
                 versioning: true,
                 sharing: true,
+                offline: false,
                 streaming: true,
-                resume: true,
+                range_reads: true,
+                resumable_uploads: true,
+                content_hashing: true,
                 watch: true,
-                metadata: true,
-                thumbnails: true,
-                max_file_size: Some(5 * 1024 * 1024 * 1024 * 1024), // 5TB
             },
             path_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    /// Start OAuth 2.0 + PKCE flow
-    pub fn start_auth(&self) -> (String, PkceCodeVerifier) {
-        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
-            .set_auth_uri(AuthUrl::new(GOOGLE_AUTH_URL.to_string()).unwrap())
-            .set_token_uri(TokenUrl::new(GOOGLE_TOKEN_URL.to_string()).unwrap())
-            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
-
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-
-        let (auth_url, _csrf_token) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new(
-                "https://www.googleapis.com/auth/drive".to_string(),
-            ))
-            .add_scope(Scope::new(
-                "https://www.googleapis.com/auth/drive.metadata.readonly".to_string(),
-            ))
-            .set_pkce_challenge(pkce_challenge)
-            .add_extra_param("access_type", "offline")
-            .add_extra_param("prompt", "consent")
-            .url();
+    /// Set tokens directly, persisting them to the token store if any
+    pub async fn set_tokens(&self, tokens: GoogleTokens) -> CfkResult<()> {
+        self.auth.set(tokens).await
+    }
 
-        (auth_url.to_string(), pkce_verifier)
+    /// Current access token, refreshed first when it is about to expire
+    async fn get_access_token(&self) -> CfkResult<String> {
+        self.auth.access_token(&self.http).await
     }
 
-    /// Complete OAuth flow with authorization code
-    pub async fn complete_auth(
+    /// Send a request and decode a JSON response body
+    async fn send_json<T: for<'de> Deserialize<'de>>(
         &self,
-        code: &str,
-        verifier: PkceCodeVerifier,
-    ) -> CfkResult<GoogleTokens> {
-        let mut params = vec![
-            ("code", code.to_string()),
-            ("grant_type", "authorization_code".to_string()),
-            ("client_id", self.config.client_id.clone()),
-            ("redirect_uri", self.config.redirect_uri.clone()),
-            ("code_verifier", verifier.secret().to_string()),
-        ];
-
-        if let Some(ref secret) = self.config.client_secret {
-            params.push(("client_secret", secret.clone()));
-        }
-
-        let response = self
-            .http
-            .post(GOOGLE_TOKEN_URL)
-            .form(&params)
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<T> {
+        let response = request
+            .bearer_auth(self.get_access_token().await?)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::Auth(format!("Token exchange failed: {}", error_text)));
-        }
-
-        #[derive(Deserialize)]
-        struct TokenResponse {
-            access_token: String,
-            refresh_token: Option<String>,
-            expires_in: Option<i64>,
-        }
+            .map_err(http::network_error)?;
 
-        let token_resp: TokenResponse = response
+        http::check_response("gdrive", response)
+            .await?
             .json()
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
-
-        let tokens = GoogleTokens {
-            access_token: token_resp.access_token,
-            refresh_token: token_resp.refresh_token,
-            expires_at: token_resp
-                .expires_in
-                .map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
-        };
-
-        *self.tokens.write().await = Some(tokens.clone());
-        Ok(tokens)
+            .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
-    /// Set tokens directly
-    pub async fn set_tokens(&self, tokens: GoogleTokens) {
-        *self.tokens.write().await = Some(tokens);
+    /// Send an authenticated request, leaving the status to the caller
+    async fn send_unchecked(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<reqwest::Response> {
+        request
+            .bearer_auth(self.get_access_token().await?)
+            .send()
+            .await
+            .map_err(http::network_error)
     }
 
-    /// Get current access token
-    async fn get_access_token(&self) -> CfkResult<String> {
-        let tokens = self.tokens.read().await;
-        tokens
-            .as_ref()
-            .map(|t| t.access_token.clone())
-            .ok_or_else(|| CfkError::Auth("Not authenticated".into()))
+    /// Record what a resumable session reported: `308` carries the range
+    /// received so far, `200`/`201` the finished file
+    async fn session_status(
+        &self,
+        session: &mut UploadSession,
+        response: reqwest::Response,
+    ) -> CfkResult<()> {
+        match response.status().as_u16() {
+            308 => {
+                // "bytes=0-N", absent when nothing has arrived yet
+                session.offset = response
+                    .headers()
+                    .get("range")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit('-').next()?.parse::<u64>().ok())
+                    .map(|last| last + 1)
+                    .unwrap_or(0);
+                Ok(())
+            }
+            200 | 201 => {
+                let file: DriveFile = response
+                    .json()
+                    .await
+                    .map_err(|e| CfkError::Serialization(e.to_string()))?;
+                session.offset = session.size;
+                session.state.insert("file_id".into(), file.id);
+                Ok(())
+            }
+            _ => Err(http::error_from_response("gdrive", response).await),
+        }
     }
 
     /// Resolve path to file ID
@@ -194,27 +248,22 @@ async fn resolve_file_id(&self, path: &VirtualPath) -> CfkResult<String> {
         for segment in &path.segments {
             let query = format!(
                 "'{}' in parents and name = '{}' and trashed = false",
-                current_id, segment
+                current_id,
+                escape_query(segment)
             );
 
-            let response = self
-                .http
-                .get(format!("{}/files", DRIVE_API_URL))
-                .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-                .query(&[("q", &query), ("fields", &"files(id,name)".to_string())])
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
-
             #[derive(Deserialize)]
             struct FileList {
                 files: Vec<DriveFile>,
             }
 
-            let list: FileList = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            let list: FileList = self
+                .send_json(
+                    self.http
+                        .get(format!("{}/files", self.config.api_url))
+                        .query(&[("q", query.as_str()), ("fields", "files(id,name,mimeType)")]),
+                )
+                .await?;
 
             current_id = list
                 .files
@@ -232,19 +281,34 @@ struct FileList {
         Ok(current_id)
     }
 
-    /// Convert VirtualPath to parent folder ID and filename
-    fn path_to_parent_and_name(&self, path: &VirtualPath) -> (String, String) {
-        if path.segments.is_empty() {
-            ("root".to_string(), String::new())
-        } else if path.segments.len() == 1 {
-            ("root".to_string(), path.segments[0].clone())
-        } else {
-            let parent_segments = &path.segments[..path.segments.len() - 1];
-            let parent_path = parent_segments.join("/");
-            let name = path.segments.last().unwrap().clone();
-            (parent_path, name)
+    /// Resolve the folder ID a path lives in
+    async fn resolve_parent_id(&self, path: &VirtualPath) -> CfkResult<String> {
+        match path.parent() {
+            Some(parent) => self.resolve_file_id(&parent).await,
+            None => Err(CfkError::InvalidPath(path.to_string())),
         }
     }
+
+    /// Drop cached IDs for a path and everything below it
+    async fn invalidate(&self, path: &VirtualPath) {
+        let key = path.to_string();
+        let prefix = format!("{}/", key);
+        self.path_cache
+            .write()
+            .await
+            .retain(|k, _| k != &key && !k.starts_with(&prefix));
+    }
+
+    fn file_name(path: &VirtualPath) -> CfkResult<String> {
+        path.name()
+            .map(String::from)
+            .ok_or_else(|| CfkError::InvalidPath(path.to_string()))
+    }
+}
+
+/// Escape a value for use inside a quoted Drive query string
+fn escape_query(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
 }
 
 /// Google Drive file metadata
@@ -253,54 +317,395 @@ fn path_to_parent_and_name(&self, path: &VirtualPath) -> (String, String) {
 struct DriveFile {
     id: String,
     name: String,
+    #[serde(default)]
     mime_type: String,
     #[serde(default)]
     size: Option<String>,
     created_time: Option<String>,
     modified_time: Option<String>,
+    md5_checksum: Option<String>,
+    head_revision_id: Option<String>,
     #[serde(default)]
     parents: Vec<String>,
     #[serde(default)]
     trashed: bool,
-    md5_checksum: Option<String>,
 }
 
 impl DriveFile {
     fn to_entry(&self, backend_id: &str, path: &str) -> Entry {
         let virtual_path = VirtualPath::new(backend_id, path);
 
-        let kind = if self.mime_type == "application/vnd.google-apps.folder" {
+        let kind = if self.mime_type == FOLDER_MIME_TYPE {
             EntryKind::Directory
         } else {
             EntryKind::File
         };
 
-        let mut metadata = Metadata::default();
-        metadata.size = self.size.as_ref().and_then(|s| s.parse().ok());
-        metadata.mime_type = Some(self.mime_type.clone());
+        let parse_time = |s: &Option<String>| {
+            s.as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|dt| dt.with_timezone(&Utc))
+        };
+
+        let metadata = Metadata {
+            size: self.size.as_ref().and_then(|s| s.parse().ok()),
+            mime_type: Some(self.mime_type.clone()),
+            modified: parse_time(&self.modified_time),
+            created: parse_time(&self.created_time),
+            content_hash: self.md5_checksum.clone(),
+            provider_id: Some(self.id.clone()),
+            revision: self.head_revision_id.clone(),
+            ..Default::default()
+        };
+
+        Entry {
+            path: virtual_path,
+            kind,
+            metadata,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    file_id: String,
+    #[serde(default)]
+    removed: bool,
+    file: Option<DriveFile>,
+}
+
+/// Changes anywhere in My Drive, from `changes.list`.
+///
+/// Changes name files by ID, so each is placed by walking its parents up to
+/// the root. Files that were seen before are remembered, which is what lets
+/// a move be told apart from an edit and a removal be placed at all.
+struct DriveChanges {
+    backend: GoogleDriveBackend,
+    page_token: String,
+    root_id: String,
+    /// Where each file seen so far was, by ID
+    paths: HashMap<String, VirtualPath>,
+    /// Reported as rescanned when a file nobody saw is removed
+    watched: VirtualPath,
+    polled: bool,
+}
+
+impl DriveChanges {
+    /// Where `file` is now, or `None` when it is outside My Drive
+    async fn path_of(&mut self, file: &DriveFile) -> CfkResult<Option<VirtualPath>> {
+        // The file and its ancestors, nearest first
+        let mut chain = vec![(file.id.clone(), file.name.clone())];
+        let mut parent = file.parents.first().cloned();
+        let mut base = None;
+        while let Some(id) = parent.take() {
+            if id == self.root_id {
+                base = Some(VirtualPath::root(&self.backend.id));
+                break;
+            }
+            if let Some(known) = self.paths.get(&id) {
+                base = Some(known.clone());
+                break;
+            }
+            if chain.len() > MAX_FOLDER_DEPTH {
+                break;
+            }
+            let request = self
+                .backend
+                .http
+                .get(format!("{}/files/{}", self.backend.config.api_url, id))
+                .query(&[("fields", "id,name,parents")]);
+            let folder: DriveFile = match self.backend.send_json(request).await {
+                Ok(folder) => folder,
+                Err(CfkError::NotFound(_)) => break,
+                Err(e) => return Err(e),
+            };
+            parent = folder.parents.first().cloned();
+            chain.push((folder.id, folder.name));
+        }
+
+        let Some(mut path) = base else {
+            return Ok(None);
+        };
+        for (id, name) in chain.into_iter().rev() {
+            path = path.join(&name);
+            self.paths.insert(id, path.clone());
+        }
+        Ok(Some(path))
+    }
+
+    async fn event(&mut self, change: Change) -> CfkResult<Option<ChangeEvent>> {
+        let old = self.paths.get(&change.file_id).cloned();
+        let (new, gone) = match change.file.filter(|_| !change.removed) {
+            Some(file) => (self.path_of(&file).await?, file.trashed),
+            None => (None, true),
+        };
+
+        // Cached paths below a moved or removed folder are stale
+        if let Some(old) = old.as_ref().filter(|old| new.as_ref() != Some(*old)) {
+            self.paths.retain(|_, path| !path.starts_with(old));
+        }
+        if gone {
+            self.paths.remove(&change.file_id);
+            return Ok(Some(match new.or(old) {
+                Some(path) => ChangeEvent::new(path, ChangeKind::Deleted),
+                None => ChangeEvent::new(self.watched.clone(), ChangeKind::Rescan),
+            }));
+        }
 
-        if let Some(ref modified) = self.modified_time {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(modified) {
-                metadata.modified = Some(dt.with_timezone(&Utc));
+        Ok(match (old, new) {
+            (Some(from), Some(path)) if from != path => {
+                Some(ChangeEvent::new(path, ChangeKind::Renamed { from }))
             }
+            (_, Some(path)) => Some(ChangeEvent::new(path, ChangeKind::Modified)),
+            // Moved out of My Drive
+            (Some(path), None) => Some(ChangeEvent::new(path, ChangeKind::Deleted)),
+            (None, None) => None,
+        })
+    }
+}
+
+#[async_trait]
+impl ChangeFeed for DriveChanges {
+    async fn poll(&mut self) -> CfkResult<Vec<ChangeEvent>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ChangeList {
+            #[serde(default)]
+            changes: Vec<Change>,
+            next_page_token: Option<String>,
+            new_start_page_token: Option<String>,
+        }
+
+        if self.polled {
+            tokio::time::sleep(CHANGES_POLL_INTERVAL).await;
         }
-        if let Some(ref created) = self.created_time {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(created) {
-                metadata.created = Some(dt.with_timezone(&Utc));
+        self.polled = true;
+
+        let mut events = Vec::new();
+        let mut token = self.page_token.clone();
+        loop {
+            let request = self
+                .backend
+                .http
+                .get(format!("{}/changes", self.backend.config.api_url))
+                .query(&[
+                    ("pageToken", token.as_str()),
+                    ("fields", CHANGE_FIELDS),
+                    ("includeRemoved", "true"),
+                    ("pageSize", "1000"),
+                ]);
+            let list: ChangeList = self.backend.send_json(request).await?;
+            for change in list.changes {
+                let Some(event) = self.event(change).await? else {
+                    continue;
+                };
+                // One look at everything covers any number of unplaced
+                // removals
+                if event.kind != ChangeKind::Rescan || !events.contains(&event) {
+                    events.push(event);
+                }
+            }
+            match (list.next_page_token, list.new_start_page_token) {
+                (Some(next), _) => token = next,
+                (None, start) => {
+                    self.page_token = start.unwrap_or(token);
+                    break;
+                }
             }
         }
-        if let Some(ref checksum) = self.md5_checksum {
-            metadata.checksum = Some(checksum.clone());
+
+        for event in &events {
+            self.backend.invalidate(&event.path).await;
+            if let ChangeKind::Renamed { from } = &event.kind {
+                self.backend.invalidate(from).await;
+            }
         }
+        Ok(events)
+    }
+}
 
-        Entry {
-            path: virtual_path,
-            kind,
-            metadata,
+/// Resumable upload sessions
+#[async_trait]
+impl ResumableUpload for GoogleDriveBackend {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        let existing_id = match self.resolve_file_id(path).await {
+            Ok(id) => Some(id),
+            Err(CfkError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        let request = match existing_id {
+            Some(_) if !options.overwrite => {
+                return Err(CfkError::AlreadyExists(path.to_string()));
+            }
+            Some(file_id) => self
+                .http
+                .patch(format!("{}/files/{}", self.config.upload_url, file_id))
+                .json(&serde_json::json!({})),
+            None => self
+                .http
+                .post(format!("{}/files", self.config.upload_url))
+                .json(&serde_json::json!({
+                    "name": Self::file_name(path)?,
+                    "parents": [self.resolve_parent_id(path).await?],
+                })),
+        };
+
+        let response = self
+            .send_unchecked(
+                request
+                    .query(&[("uploadType", "resumable"), ("fields", FILE_FIELDS)])
+                    .header("X-Upload-Content-Length", size),
+            )
+            .await?;
+        let response = http::check_response("gdrive", response).await?;
+        let session_uri = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| CfkError::ProviderApi {
+                provider: "gdrive".into(),
+                message: "resumable upload returned no session URI".into(),
+            })?;
+
+        Ok(UploadSession::new(
+            path.clone(),
+            size,
+            session_uri,
+            UPLOAD_CHUNK_SIZE,
+            options.overwrite,
+        ))
+    }
+
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        let response = self
+            .send_unchecked(
+                self.http
+                    .put(&session.id)
+                    .header("Content-Range", session.content_range(data.len() as u64))
+                    .body(data),
+            )
+            .await?;
+        self.session_status(session, response).await
+    }
+
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+        let response = self
+            .send_unchecked(
+                self.http
+                    .put(&session.id)
+                    .header("Content-Range", format!("bytes */{}", session.size))
+                    .header("Content-Length", 0),
+            )
+            .await?;
+        self.session_status(session, response).await?;
+        Ok(session.offset)
+    }
+
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        // Drive commits with the last chunk; all that is left is the result
+        let file_id = session
+            .state
+            .get("file_id")
+            .ok_or_else(|| CfkError::ProviderApi {
+                provider: "gdrive".into(),
+                message: format!(
+                    "upload incomplete: {} of {} bytes",
+                    session.offset, session.size
+                ),
+            })?;
+
+        let file: DriveFile = self
+            .send_json(
+                self.http
+                    .get(format!("{}/files/{}", self.config.api_url, file_id))
+                    .query(&[("fields", FILE_FIELDS)]),
+            )
+            .await?;
+
+        self.path_cache
+            .write()
+            .await
+            .insert(session.path.to_string(), file.id.clone());
+        Ok(file.to_entry(&self.id, &session.path.segments.join("/")))
+    }
+
+    async fn abort(&self, session: &UploadSession) -> CfkResult<()> {
+        let response = self.send_unchecked(self.http.delete(&session.id)).await?;
+        // Drive answers a cancelled session with 499
+        match response.status().as_u16() {
+            499 | 404 => Ok(()),
+            _ => http::check_response("gdrive", response).await.map(drop),
         }
     }
 }
 
+#[async_trait]
+impl OAuthBackend for GoogleDriveBackend {
+    fn with_token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        Arc::get_mut(&mut self.auth)
+            .expect("token store attached before the backend is cloned")
+            .attach_store(store);
+        self
+    }
+
+    fn redirect_uri(&self) -> &str {
+        &self.config.redirect_uri
+    }
+
+    fn start_auth(&self) -> AuthRequest {
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_auth_uri(AuthUrl::new(GOOGLE_AUTH_URL.to_string()).unwrap())
+            .set_token_uri(TokenUrl::new(self.config.token_url.clone()).unwrap())
+            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
+
+        let (pkce_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let (auth_url, state) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new(
+                "https://www.googleapis.com/auth/drive".to_string(),
+            ))
+            .add_scope(Scope::new(
+                "https://www.googleapis.com/auth/drive.metadata.readonly".to_string(),
+            ))
+            .set_pkce_challenge(pkce_challenge)
+            .add_extra_param("access_type", "offline")
+            .add_extra_param("prompt", "consent")
+            .url();
+
+        AuthRequest {
+            url: auth_url.to_string(),
+            state,
+            verifier,
+        }
+    }
+
+    async fn complete_auth(
+        &self,
+        code: &str,
+        verifier: PkceCodeVerifier,
+    ) -> CfkResult<OAuthTokens> {
+        let mut params = vec![
+            ("code", code),
+            ("grant_type", "authorization_code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", verifier.secret()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            params.push(("client_secret", secret));
+        }
+        self.auth.exchange(&self.http, &params).await
+    }
+}
+
 #[async_trait]
 impl StorageBackend for GoogleDriveBackend {
     fn id(&self) -> &str {
@@ -315,168 +720,138 @@ fn capabilities(&self) -> &StorageCapabilities {
         &self.capabilities
     }
 
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.auth.refresh(&self.http).await
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        Some(self)
+    }
+
     async fn is_available(&self) -> bool {
-        self.tokens.read().await.is_some()
+        self.auth.is_authenticated().await
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
         let file_id = self.resolve_file_id(path).await?;
 
-        let response = self
-            .http
-            .get(format!("{}/files/{}", DRIVE_API_URL, file_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .query(&[(
-                "fields",
-                "id,name,mimeType,size,createdTime,modifiedTime,parents,trashed,md5Checksum",
-            )])
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            if status == reqwest::StatusCode::NOT_FOUND {
-                return Err(CfkError::NotFound(path.to_string()));
-            }
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "gdrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
-
-        let file: DriveFile = response
-            .json()
+        let file: DriveFile = self
+            .send_json(
+                self.http
+                    .get(format!("{}/files/{}", self.config.api_url, file_id))
+                    .query(&[("fields", FILE_FIELDS)]),
+            )
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+                e => e,
+            })?;
 
         let path_str = path.segments.join("/");
         Ok(file.to_entry(&self.id, &path_str))
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+    /// Drive has no recursive listing; `options.recursive` is ignored and
+    /// callers walk subfolders themselves.
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
         let folder_id = self.resolve_file_id(path).await?;
 
-        let mut entries = Vec::new();
-        let mut page_token: Option<String> = None;
-
-        loop {
-            let query = format!("'{}' in parents and trashed = false", folder_id);
-
-            let mut request = self
-                .http
-                .get(format!("{}/files", DRIVE_API_URL))
-                .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-                .query(&[
-                    ("q", query.as_str()),
-                    (
-                        "fields",
-                        "nextPageToken,files(id,name,mimeType,size,createdTime,modifiedTime,md5Checksum)",
-                    ),
-                    ("pageSize", "1000"),
-                ]);
-
-            if let Some(ref token) = page_token {
-                request = request.query(&[("pageToken", token.as_str())]);
-            }
-
-            let response = request
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
-
-            #[derive(Deserialize)]
-            #[serde(rename_all = "camelCase")]
-            struct FileList {
-                files: Vec<DriveFile>,
-                next_page_token: Option<String>,
-            }
+        let query = format!("'{}' in parents and trashed = false", folder_id);
+        let fields = format!("nextPageToken,files({})", FILE_FIELDS);
+        let page_size = options
+            .limit
+            .unwrap_or(LIST_PAGE_SIZE)
+            .clamp(1, LIST_PAGE_SIZE)
+            .to_string();
 
-            let list: FileList = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let mut request = self
+            .http
+            .get(format!("{}/files", self.config.api_url))
+            .query(&[
+                ("q", query.as_str()),
+                ("fields", fields.as_str()),
+                ("pageSize", page_size.as_str()),
+            ]);
+
+        if let Some(ref token) = options.cursor {
+            request = request.query(&[("pageToken", token.as_str())]);
+        }
 
-            let base_path = if path.segments.is_empty() {
-                String::new()
-            } else {
-                format!("{}/", path.segments.join("/"))
-            };
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct FileList {
+            files: Vec<DriveFile>,
+            next_page_token: Option<String>,
+        }
 
-            for file in list.files {
-                let file_path = format!("{}{}", base_path, file.name);
-                entries.push(file.to_entry(&self.id, &file_path));
-            }
+        let list: FileList = self.send_json(request).await?;
 
-            page_token = list.next_page_token;
-            if page_token.is_none() {
-                break;
-            }
-        }
+        let base_path = if path.segments.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path.segments.join("/"))
+        };
 
-        Ok(entries)
+        let entries = list
+            .files
+            .iter()
+            .filter(|f| options.include_hidden || !f.name.starts_with('.'))
+            .map(|file| file.to_entry(&self.id, &format!("{}{}", base_path, file.name)))
+            .collect();
+
+        Ok(DirectoryListing {
+            path: path.clone(),
+            entries,
+            has_more: list.next_page_token.is_some(),
+            cursor: list.next_page_token,
+        })
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
         let file_id = self.resolve_file_id(path).await?;
 
-        let response = self
+        let mut request = self
             .http
-            .get(format!("{}/files/{}?alt=media", DRIVE_API_URL, file_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "gdrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
+            .get(format!("{}/files/{}", self.config.api_url, file_id))
+            .query(&[("alt", "media")]);
+        if let Some(range) = options.range {
+            request = request.header(reqwest::header::RANGE, http::range_header(range));
         }
+        let response = self.send_unchecked(request).await?;
 
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+        http::ranged_stream("gdrive", response, options.range).await
     }
 
-    async fn write_file(&self, path: &VirtualPath, data: Bytes) -> CfkResult<Entry> {
-        let token = self.get_access_token().await?;
-
-        // Check if file exists
-        let existing_id = self.resolve_file_id(path).await.ok();
-
-        let (parent_path, name) = self.path_to_parent_and_name(path);
-        let parent_id = if parent_path == "root" {
-            "root".to_string()
-        } else {
-            let parent_virtual = VirtualPath::new(&self.id, &parent_path);
-            self.resolve_file_id(&parent_virtual).await?
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let existing_id = match self.resolve_file_id(path).await {
+            Ok(id) => Some(id),
+            Err(CfkError::NotFound(_)) => None,
+            Err(e) => return Err(e),
         };
 
         let file: DriveFile = if let Some(file_id) = existing_id {
+            if !options.overwrite {
+                return Err(CfkError::AlreadyExists(path.to_string()));
+            }
+
             // Update existing file
-            let response = self
-                .http
-                .patch(format!(
-                    "{}/files/{}?uploadType=media",
-                    DRIVE_UPLOAD_URL, file_id
-                ))
-                .header("Authorization", format!("Bearer {}", token))
-                .header("Content-Type", "application/octet-stream")
-                .body(data.to_vec())
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
-
-            response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?
+            self.send_json(
+                self.http
+                    .patch(format!("{}/files/{}", self.config.upload_url, file_id))
+                    .query(&[("uploadType", "media"), ("fields", FILE_FIELDS)])
+                    .header("Content-Type", "application/octet-stream")
+                    .body(data),
+            )
+            .await?
         } else {
             // Create new file
             #[derive(Serialize)]
@@ -486,12 +861,12 @@ struct FileMetadata {
             }
 
             let metadata = FileMetadata {
-                name: name.clone(),
-                parents: vec![parent_id],
+                name: Self::file_name(path)?,
+                parents: vec![self.resolve_parent_id(path).await?],
             };
 
-            let metadata_json =
-                serde_json::to_string(&metadata).map_err(|e| CfkError::Serialization(e.to_string()))?;
+            let metadata_json = serde_json::to_string(&metadata)
+                .map_err(|e| CfkError::Serialization(e.to_string()))?;
 
             // Use multipart upload
             let boundary = "cfk_boundary_12345";
@@ -504,69 +879,62 @@ struct FileMetadata {
             full_body.extend_from_slice(&data);
             full_body.extend_from_slice(format!("\r\n--{}--", boundary).as_bytes());
 
-            let response = self
-                .http
-                .post(format!("{}?uploadType=multipart", DRIVE_UPLOAD_URL))
-                .header("Authorization", format!("Bearer {}", token))
-                .header(
-                    "Content-Type",
-                    format!("multipart/related; boundary={}", boundary),
-                )
-                .body(full_body)
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
-
-            response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?
+            self.send_json(
+                self.http
+                    .post(format!("{}/files", self.config.upload_url))
+                    .query(&[("uploadType", "multipart"), ("fields", FILE_FIELDS)])
+                    .header(
+                        "Content-Type",
+                        format!("multipart/related; boundary={}", boundary),
+                    )
+                    .body(full_body),
+            )
+            .await?
         };
 
+        if let Some(expected) = &options.content_hash {
+            if file.md5_checksum.as_deref() != Some(expected.as_str()) {
+                return Err(CfkError::ChecksumMismatch);
+            }
+        }
+
         let path_str = path.segments.join("/");
         Ok(file.to_entry(&self.id, &path_str))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let file_id = self.resolve_file_id(path).await?;
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let data = common::collect_stream(stream).await?;
+        self.write_file(path, data, options).await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        common::check_delete(self, path, options).await?;
+
+        let file_id = match self.resolve_file_id(path).await {
+            Ok(id) => id,
+            Err(e) => return common::ignore_missing(Err(e), options),
+        };
 
         let response = self
             .http
-            .delete(format!("{}/files/{}", DRIVE_API_URL, file_id))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
+            .delete(format!("{}/files/{}", self.config.api_url, file_id))
+            .bearer_auth(self.get_access_token().await?)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .map_err(http::network_error)?;
 
-        if !response.status().is_success() && response.status() != reqwest::StatusCode::NO_CONTENT {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "gdrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
-
-        // Invalidate cache
-        {
-            let mut cache = self.path_cache.write().await;
-            cache.remove(&path.to_string());
-        }
-
-        Ok(())
+        let result = http::check_response("gdrive", response).await.map(|_| ());
+        self.invalidate(path).await;
+        common::ignore_missing(result, options)
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let token = self.get_access_token().await?;
-        let (parent_path, name) = self.path_to_parent_and_name(path);
-
-        let parent_id = if parent_path == "root" {
-            "root".to_string()
-        } else {
-            let parent_virtual = VirtualPath::new(&self.id, &parent_path);
-            self.resolve_file_id(&parent_virtual).await?
-        };
-
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
         struct FolderMetadata {
@@ -576,40 +944,40 @@ struct FolderMetadata {
         }
 
         let metadata = FolderMetadata {
-            name,
-            mime_type: "application/vnd.google-apps.folder".to_string(),
-            parents: vec![parent_id],
+            name: Self::file_name(path)?,
+            mime_type: FOLDER_MIME_TYPE.to_string(),
+            parents: vec![self.resolve_parent_id(path).await?],
         };
 
-        let response = self
-            .http
-            .post(format!("{}/files", DRIVE_API_URL))
-            .header("Authorization", format!("Bearer {}", token))
-            .json(&metadata)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        let file: DriveFile = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let file: DriveFile = self
+            .send_json(
+                self.http
+                    .post(format!("{}/files", self.config.api_url))
+                    .query(&[("fields", FILE_FIELDS)])
+                    .json(&metadata),
+            )
+            .await?;
 
         let path_str = path.segments.join("/");
         Ok(file.to_entry(&self.id, &path_str))
     }
 
-    async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let token = self.get_access_token().await?;
-        let file_id = self.resolve_file_id(from).await?;
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        if self.get_metadata(source).await?.is_directory() {
+            return Err(CfkError::Unsupported(
+                "Google Drive cannot copy folders".into(),
+            ));
+        }
+
+        common::prepare_destination(self, dest, options.overwrite).await?;
+        self.invalidate(dest).await;
 
-        let (parent_path, name) = self.path_to_parent_and_name(to);
-        let parent_id = if parent_path == "root" {
-            "root".to_string()
-        } else {
-            let parent_virtual = VirtualPath::new(&self.id, &parent_path);
-            self.resolve_file_id(&parent_virtual).await?
-        };
+        let file_id = self.resolve_file_id(source).await?;
 
         #[derive(Serialize)]
         struct CopyMetadata {
@@ -618,84 +986,176 @@ struct CopyMetadata {
         }
 
         let metadata = CopyMetadata {
-            name,
-            parents: vec![parent_id],
+            name: Self::file_name(dest)?,
+            parents: vec![self.resolve_parent_id(dest).await?],
         };
 
-        let response = self
-            .http
-            .post(format!("{}/files/{}/copy", DRIVE_API_URL, file_id))
-            .header("Authorization", format!("Bearer {}", token))
-            .json(&metadata)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        let file: DriveFile = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let file: DriveFile = self
+            .send_json(
+                self.http
+                    .post(format!("{}/files/{}/copy", self.config.api_url, file_id))
+                    .query(&[("fields", FILE_FIELDS)])
+                    .json(&metadata),
+            )
+            .await?;
 
-        let path_str = to.segments.join("/");
+        let path_str = dest.segments.join("/");
         Ok(file.to_entry(&self.id, &path_str))
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let token = self.get_access_token().await?;
-        let file_id = self.resolve_file_id(from).await?;
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+        self.invalidate(dest).await;
 
-        let (parent_path, name) = self.path_to_parent_and_name(to);
-        let parent_id = if parent_path == "root" {
-            "root".to_string()
-        } else {
-            let parent_virtual = VirtualPath::new(&self.id, &parent_path);
-            self.resolve_file_id(&parent_virtual).await?
-        };
+        let file_id = self.resolve_file_id(source).await?;
+        let old_parent_id = self.resolve_parent_id(source).await?;
+        let new_parent_id = self.resolve_parent_id(dest).await?;
 
         #[derive(Serialize)]
         struct UpdateMetadata {
             name: String,
         }
 
-        let metadata = UpdateMetadata { name };
+        let metadata = UpdateMetadata {
+            name: Self::file_name(dest)?,
+        };
 
-        let response = self
+        let mut request = self
             .http
-            .patch(format!(
-                "{}/files/{}?addParents={}&removeParents={}",
-                DRIVE_API_URL, file_id, parent_id, "root"
-            ))
-            .header("Authorization", format!("Bearer {}", token))
-            .json(&metadata)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .patch(format!("{}/files/{}", self.config.api_url, file_id))
+            .query(&[("fields", FILE_FIELDS)])
+            .json(&metadata);
+
+        if old_parent_id != new_parent_id {
+            request = request.query(&[
+                ("addParents", new_parent_id.as_str()),
+                ("removeParents", old_parent_id.as_str()),
+            ]);
+        }
 
-        let file: DriveFile = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let file: DriveFile = self.send_json(request).await?;
 
-        // Invalidate cache
-        {
-            let mut cache = self.path_cache.write().await;
-            cache.remove(&from.to_string());
-        }
+        self.invalidate(source).await;
 
-        let path_str = to.segments.join("/");
+        let path_str = dest.segments.join("/");
         Ok(file.to_entry(&self.id, &path_str))
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        let response = self
+    /// Drive reports changes for the whole drive; those outside `path` are
+    /// dropped here
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct StartPageToken {
+            start_page_token: String,
+        }
+
+        #[derive(Deserialize)]
+        struct Root {
+            id: String,
+        }
+
+        self.get_metadata(path).await?;
+        let start: StartPageToken = self
+            .send_json(
+                self.http
+                    .get(format!("{}/changes/startPageToken", self.config.api_url)),
+            )
+            .await?;
+        let root: Root = self
+            .send_json(
+                self.http
+                    .get(format!("{}/files/root", self.config.api_url))
+                    .query(&[("fields", "id")]),
+            )
+            .await?;
+
+        let changes = DriveChanges {
+            backend: self.clone(),
+            page_token: start.start_page_token,
+            root_id: root.id,
+            paths: HashMap::new(),
+            watched: path.clone(),
+            polled: false,
+        };
+        Ok(common::change_stream(changes, path.clone(), recursive))
+    }
+
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RevisionList {
+            next_page_token: Option<String>,
+            #[serde(default)]
+            revisions: Vec<Revision>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Revision {
+            id: String,
+            modified_time: DateTime<Utc>,
+            size: Option<String>,
+            last_modifying_user: Option<User>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct User {
+            display_name: Option<String>,
+            email_address: Option<String>,
+        }
+
+        let file_id = self.resolve_file_id(path).await?;
+        let url = format!("{}/files/{}/revisions", self.config.api_url, file_id);
+        let mut versions = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self.http.get(&url).query(&[("fields", REVISION_FIELDS)]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+            let list: RevisionList = self.send_json(request).await?;
+
+            versions.extend(list.revisions.into_iter().map(|revision| FileVersion {
+                id: revision.id,
+                modified: revision.modified_time,
+                size: revision.size.and_then(|s| s.parse().ok()),
+                author: revision
+                    .last_modifying_user
+                    .and_then(|user| user.display_name.or(user.email_address)),
+            }));
+            match list.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+
+        // Drive lists the oldest first
+        versions.reverse();
+        Ok(versions)
+    }
+
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        let file_id = self.resolve_file_id(path).await?;
+        let request = self
             .http
-            .get(format!("{}/about", DRIVE_API_URL))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .query(&[("fields", "storageQuota")])
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .get(format!(
+                "{}/files/{}/revisions/{}",
+                self.config.api_url, file_id, version_id
+            ))
+            .query(&[("alt", "media")]);
+        let response = self.send_unchecked(request).await?;
+
+        http::ranged_stream("gdrive", response, None).await
+    }
 
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         #[derive(Deserialize)]
         #[serde(rename_all = "camelCase")]
         struct About {
@@ -709,23 +1169,329 @@ struct StorageQuota {
             usage: Option<String>,
         }
 
-        let about: About = response
-            .json()
+        let about: About = self
+            .send_json(
+                self.http
+                    .get(format!("{}/about", self.config.api_url))
+                    .query(&[("fields", "storageQuota")]),
+            )
+            .await?;
+
+        // Accounts with unlimited storage report no limit
+        let total: Option<u64> = about.storage_quota.limit.and_then(|s| s.parse().ok());
+        let used: Option<u64> = about.storage_quota.usage.and_then(|s| s.parse().ok());
+
+        Ok(SpaceInfo {
+            total,
+            used,
+            available: total.map(|t| t.saturating_sub(used.unwrap_or(0))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn backend(server: &MockServer) -> GoogleDriveBackend {
+        let mut config = GoogleDriveConfig::new("client", "http://localhost/callback");
+        config.api_url = server.uri();
+        config.upload_url = format!("{}/upload", server.uri());
+        let backend = GoogleDriveBackend::new("gdrive", config);
+        backend
+            .set_tokens(GoogleTokens {
+                access_token: "token".into(),
+                refresh_token: None,
+                expires_at: None,
+            })
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .unwrap();
+        backend
+    }
 
-        let total: u64 = about
-            .storage_quota
-            .limit
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0);
-        let used: u64 = about
-            .storage_quota
-            .usage
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0);
-        let available = total.saturating_sub(used);
-
-        Ok((available, total))
+    #[tokio::test]
+    async fn test_list_root_returns_page_token_as_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .and(query_param("q", "'root' in parents and trashed = false"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "nextPageToken": "page2",
+                "files": [
+                    {"id": "f1", "name": "report.pdf", "mimeType": "application/pdf",
+                     "size": "2048", "md5Checksum": "d41d8", "headRevisionId": "r1"},
+                    {"id": "d1", "name": "Photos", "mimeType": FOLDER_MIME_TYPE}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let listing = backend
+            .list_directory(&VirtualPath::root("gdrive"), &ListOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 2);
+        assert!(listing.has_more);
+        assert_eq!(listing.cursor.as_deref(), Some("page2"));
+
+        let file = &listing.entries[0];
+        assert_eq!(file.size(), Some(2048));
+        assert_eq!(file.metadata.content_hash.as_deref(), Some("d41d8"));
+        assert_eq!(file.metadata.provider_id.as_deref(), Some("f1"));
+        assert!(listing.entries[1].is_directory());
+    }
+
+    #[tokio::test]
+    async fn test_write_existing_without_overwrite_fails() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": [{"id": "f1", "name": "a.txt"}]
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let err = backend
+            .write_file(
+                &VirtualPath::new("gdrive", "a.txt"),
+                Bytes::from_static(b"x"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)));
+    }
+
+    #[tokio::test]
+    async fn test_create_file_uses_multipart_upload() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/upload/files"))
+            .and(query_param("uploadType", "multipart"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "new", "name": "new.txt", "mimeType": "text/plain", "size": "5"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let entry = backend
+            .write_file(
+                &VirtualPath::new("gdrive", "new.txt"),
+                Bytes::from_static(b"hello"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(entry.path.to_path_string(), "/new.txt");
+        assert_eq!(entry.size(), Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_watch_places_changes_by_parent() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": [{"id": "d1", "name": "docs", "mimeType": FOLDER_MIME_TYPE}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/d1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "d1", "name": "docs", "mimeType": FOLDER_MIME_TYPE, "parents": ["r0"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/root"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"id": "r0"})))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/changes/startPageToken"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"startPageToken": "41"})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/changes"))
+            .and(query_param("pageToken", "41"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "changes": [
+                    {"fileId": "a", "removed": false,
+                     "file": {"id": "a", "name": "a.txt", "parents": ["d1"]}},
+                    {"fileId": "x", "removed": false,
+                     "file": {"id": "x", "name": "elsewhere.txt", "parents": ["r0"]}},
+                    {"fileId": "gone1", "removed": true},
+                    {"fileId": "gone2", "removed": true}
+                ],
+                "newStartPageToken": "42"
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let docs = VirtualPath::new("gdrive", "docs");
+        let mut changes = backend.watch(&docs, true).await.unwrap();
+
+        let edit = changes.next().await.unwrap().unwrap();
+        assert_eq!(edit.path.to_path_string(), "/docs/a.txt");
+        assert_eq!(edit.kind, ChangeKind::Modified);
+        // Removals of files never seen cannot be placed
+        let rescan = changes.next().await.unwrap().unwrap();
+        assert_eq!(rescan, ChangeEvent::new(docs, ChangeKind::Rescan));
+    }
+
+    #[tokio::test]
+    async fn test_space_info() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/about"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "storageQuota": {"limit": "1000", "usage": "250"}
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let info = backend.get_space_info().await.unwrap();
+        assert_eq!(info.total, Some(1000));
+        assert_eq!(info.available, Some(750));
+    }
+
+    #[tokio::test]
+    async fn test_versions_come_from_revisions_newest_first() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": [{"id": "f1", "name": "plan.txt", "mimeType": "text/plain"}]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/f1/revisions"))
+            .and(query_param("pageToken", "p2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "revisions": [
+                    {"id": "r2", "modifiedTime": "2024-02-01T00:00:00Z", "size": "9",
+                     "lastModifyingUser": {"emailAddress": "bo@example.com"}}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/f1/revisions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "nextPageToken": "p2",
+                "revisions": [
+                    {"id": "r1", "modifiedTime": "2024-01-01T00:00:00Z", "size": "4",
+                     "lastModifyingUser": {"displayName": "Ada", "emailAddress": "ada@example.com"}}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/f1/revisions/r1"))
+            .and(query_param("alt", "media"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"plan".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let file = VirtualPath::new("gdrive", "plan.txt");
+        let versions = backend.get_versions(&file).await.unwrap();
+        let summary: Vec<_> = versions
+            .iter()
+            .map(|v| (v.id.as_str(), v.size, v.author.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [("r2", Some(9), Some("bo@example.com")), ("r1", Some(4), Some("Ada"))]
+        );
+
+        let old = common::collect_stream(backend.get_version(&file, "r1").await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&old[..], b"plan");
+    }
+
+    #[test]
+    fn test_escape_query() {
+        assert_eq!(escape_query("it's"), "it\\'s");
+        assert_eq!(escape_query("a\\b"), "a\\\\b");
+    }
+
+    #[tokio::test]
+    async fn test_resumable_session_tracks_received_range() {
+        let server = MockServer::start().await;
+        let session_uri = format!("{}/session/abc", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "files": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/upload/files"))
+            .and(query_param("uploadType", "resumable"))
+            .respond_with(
+                ResponseTemplate::new(200).insert_header("location", session_uri.as_str()),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/session/abc"))
+            .and(wiremock::matchers::header("content-range", "bytes */10"))
+            .respond_with(ResponseTemplate::new(308).insert_header("range", "bytes=0-3"))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/session/abc"))
+            .and(wiremock::matchers::header("content-range", "bytes 4-9/10"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "f1", "name": "big.bin", "size": "10"
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let mut session = backend
+            .create_session(
+                &VirtualPath::new("gdrive", "big.bin"),
+                10,
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(session.id, session_uri);
+
+        assert_eq!(backend.query_offset(&mut session).await.unwrap(), 4);
+        backend
+            .upload_chunk(&mut session, Bytes::from_static(b"456789"))
+            .await
+            .unwrap();
+        assert!(session.is_complete());
+        assert_eq!(session.state.get("file_id").map(String::as_str), Some("f1"));
     }
 }
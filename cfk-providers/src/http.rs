@@ -0,0 +1,237 @@
+//! Shared helpers for HTTP-based providers
+//!
+//! Status-code mapping and response streaming. The overwrite and delete
+//! rules every REST backend emulates live in `common`.
+
+use cfk_core::{backend::ByteStream, CfkError, CfkResult};
+use futures::StreamExt;
+use reqwest::{header, Response, StatusCode};
+
+/// Pass a successful response through, or convert it into a `CfkError`
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "webdav",
+        feature = "syncthing"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn check_response(provider: &str, response: Response) -> CfkResult<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    Err(error_from_response(provider, response).await)
+}
+
+/// Map an unsuccessful HTTP response onto the closest `CfkError` variant
+pub(crate) async fn error_from_response(provider: &str, response: Response) -> CfkError {
+    let status = response.status();
+    let path = response.url().path().to_string();
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    let bearer_rejected = response
+        .headers()
+        .get(header::WWW_AUTHENTICATE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("invalid_token"))
+        .unwrap_or(false);
+    let body = response.text().await.unwrap_or_default();
+
+    match status {
+        StatusCode::NOT_FOUND => CfkError::NotFound(path),
+        StatusCode::UNAUTHORIZED
+            if bearer_rejected
+                || body.contains("expired_access_token")
+                || body.contains("invalid_access_token") =>
+        {
+            CfkError::TokenExpired
+        }
+        StatusCode::UNAUTHORIZED => CfkError::AuthFailed(body),
+        StatusCode::FORBIDDEN => CfkError::PermissionDenied(path),
+        StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => CfkError::Conflict(path),
+        StatusCode::TOO_MANY_REQUESTS => CfkError::RateLimited {
+            retry_after_secs: retry_after,
+        },
+        StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => CfkError::RateLimited {
+            retry_after_secs: retry_after,
+        },
+        StatusCode::INSUFFICIENT_STORAGE => CfkError::QuotaExceeded(body),
+        StatusCode::REQUEST_TIMEOUT => CfkError::Timeout,
+        // Transient server trouble; worth another try
+        StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => CfkError::Network(format!("{}: {}", status, body)),
+        _ => CfkError::ProviderApi {
+            provider: provider.to_string(),
+            message: format!("{}: {}", status, body),
+        },
+    }
+}
+
+/// Convert a response body into a `ByteStream`
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "ipfs",
+        feature = "webdav"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn body_stream(response: Response) -> ByteStream {
+    Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(|e| CfkError::Network(e.to_string()))),
+    )
+}
+
+/// `Range` header value for a `start..end` byte range
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "webdav"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn range_header((start, end): (u64, u64)) -> String {
+    // An empty range still asks for one byte; `ranged_stream` drops it
+    format!("bytes={}-{}", start, end.max(start + 1) - 1)
+}
+
+/// Body of a ranged GET, cut down to the range
+///
+/// Servers may ignore `Range` and send the whole file with a 200, or answer
+/// 416 for a range starting past the end. Either way the caller gets what a
+/// local read of the range would return.
+#[cfg_attr(
+    not(any(
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "webdav"
+    )),
+    allow(dead_code)
+)]
+pub(crate) async fn ranged_stream(
+    provider: &str,
+    response: Response,
+    range: Option<(u64, u64)>,
+) -> CfkResult<ByteStream> {
+    if range.is_some() && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(Box::pin(futures::stream::empty()));
+    }
+    let response = check_response(provider, response).await?;
+    Ok(range_body(response, range))
+}
+
+/// Like [`ranged_stream`], for a response the provider has already checked
+#[cfg_attr(
+    not(any(
+        feature = "dropbox",
+        feature = "gdrive",
+        feature = "onedrive",
+        feature = "box",
+        feature = "s3",
+        feature = "webdav"
+    )),
+    allow(dead_code)
+)]
+pub(crate) fn range_body(response: Response, range: Option<(u64, u64)>) -> ByteStream {
+    let Some((start, end)) = range else {
+        return body_stream(response);
+    };
+    let skip = match response.status() {
+        StatusCode::PARTIAL_CONTENT => 0,
+        _ => start,
+    };
+    crate::common::slice_stream(body_stream(response), skip, end.saturating_sub(start))
+}
+
+/// Tokens from an OAuth token endpoint
+#[cfg_attr(not(feature = "oauth2"), allow(dead_code))]
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct TokenGrant {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+}
+
+#[cfg_attr(not(feature = "oauth2"), allow(dead_code))]
+impl TokenGrant {
+    pub fn expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.expires_in
+            .map(|secs| chrono::Utc::now() + chrono::Duration::seconds(secs))
+    }
+}
+
+/// Trade a refresh token for a new access token
+#[cfg_attr(not(feature = "oauth2"), allow(dead_code))]
+pub(crate) async fn refresh_grant(
+    http: &reqwest::Client,
+    token_url: &str,
+    refresh_token: &str,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> CfkResult<TokenGrant> {
+    let mut params = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", client_id),
+    ];
+    if let Some(secret) = client_secret {
+        params.push(("client_secret", secret));
+    }
+    token_grant(http, token_url, &params, "Token refresh failed").await
+}
+
+/// Post a form to an OAuth token endpoint; a refusal is `AuthFailed`
+/// prefixed with `failure`
+#[cfg_attr(not(feature = "oauth2"), allow(dead_code))]
+pub(crate) async fn token_grant(
+    http: &reqwest::Client,
+    token_url: &str,
+    params: &[(&str, &str)],
+    failure: &str,
+) -> CfkResult<TokenGrant> {
+    let response = http
+        .post(token_url)
+        .form(params)
+        .send()
+        .await
+        .map_err(network_error)?;
+    if !response.status().is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(CfkError::AuthFailed(format!("{}: {}", failure, text)));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| CfkError::Serialization(e.to_string()))
+}
+
+/// Convert a reqwest transport error
+pub(crate) fn network_error(e: reqwest::Error) -> CfkError {
+    if e.is_timeout() {
+        CfkError::Timeout
+    } else {
+        CfkError::Network(e.to_string())
+    }
+}
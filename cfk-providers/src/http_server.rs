@@ -0,0 +1,156 @@
+//! Shared helpers for the HTTP servers
+//!
+//! The accept loop, bodies in both directions and the header handling the
+//! WebDAV server and the S3 gateway have in common.
+
+// Not every server feature uses every helper
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use cfk_core::{backend::ByteStream, CfkError, CfkResult};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use http_body_util::{
+    combinators::UnsyncBoxBody, BodyExt, BodyStream, Empty, Full, Limited, StreamBody,
+};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderMap, HeaderValue, IntoHeaderName};
+use hyper::{Request, Response, StatusCode};
+use std::convert::Infallible;
+use std::future::Future;
+use tokio::net::TcpListener;
+
+/// Response body: empty, buffered, or streamed from a backend
+pub(crate) type Body = UnsyncBoxBody<Bytes, CfkError>;
+
+/// Accept HTTP/1.1 clients until the listener fails, answering each
+/// request with `handler`
+pub(crate) async fn serve<H, F>(listener: TcpListener, name: &'static str, handler: H) -> CfkResult<()>
+where
+    H: Fn(Request<Incoming>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+{
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let response = handler(request);
+                async move { Ok::<_, Infallible>(response.await) }
+            });
+            let io = hyper_util::rt::TokioIo::new(stream);
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(io, service)
+                .await
+            {
+                tracing::debug!("{} client {}: {}", name, peer, e);
+            }
+        });
+    }
+}
+
+pub(crate) fn empty() -> Body {
+    Empty::new().map_err(|never| match never {}).boxed_unsync()
+}
+
+pub(crate) fn full(data: impl Into<Bytes>) -> Body {
+    Full::new(data.into())
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+pub(crate) fn stream_body(stream: ByteStream) -> Body {
+    StreamBody::new(stream.map_ok(Frame::data)).boxed_unsync()
+}
+
+/// A request body as the stream backends write from
+pub(crate) fn body_stream(body: Incoming) -> ByteStream {
+    let frames = TryStreamExt::map_err(BodyStream::new(body), |e| {
+        CfkError::Network(e.to_string())
+    });
+    Box::pin(frames.try_filter_map(|frame| async move { Ok(frame.into_data().ok()) }))
+}
+
+/// A whole request body of at most `limit` bytes
+pub(crate) async fn read_body(body: Incoming, limit: usize) -> CfkResult<Bytes> {
+    Ok(Limited::new(body, limit)
+        .collect()
+        .await
+        .map_err(|e| CfkError::Serialization(format!("request body: {}", e)))?
+        .to_bytes())
+}
+
+/// An empty response with `code`
+pub(crate) fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(empty());
+    *response.status_mut() = code;
+    response
+}
+
+/// Set a header, skipping values HTTP cannot carry
+pub(crate) fn set<K: IntoHeaderName>(response: &mut Response<Body>, name: K, value: impl AsRef<str>) {
+    if let Ok(value) = HeaderValue::from_str(value.as_ref()) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+pub(crate) fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// A single `bytes=` range as `(start, end)` with `end` exclusive.
+///
+/// `None` means serve the whole file: no range, a malformed one, or several
+/// (which may be answered with everything). `Some(Err)` is unsatisfiable.
+pub(crate) fn parse_range(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (size.saturating_sub(suffix), size)
+        }
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.saturating_add(1).min(size))
+        }
+    };
+    if start >= size || start >= end {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+/// IMF-fixdate, as `Last-Modified` carries
+pub(crate) fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Compare secrets without leaking where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(Ok((0, 5))));
+        assert_eq!(parse_range("bytes=5-", 10), Some(Ok((5, 10))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Ok((7, 10))));
+        assert_eq!(parse_range("bytes=8-100", 10), Some(Ok((8, 10))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+        assert_eq!(parse_range("bytes=5-2", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+}
@@ -6,19 +6,25 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    CfkError, CfkResult, Entry, Metadata, StorageBackend, StorageCapabilities, VirtualPath,
 };
-use reqwest::{multipart, Client};
+use reqwest::{multipart, Body, Client};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::common;
+use crate::http;
+
 const DEFAULT_API_URL: &str = "http://127.0.0.1:5001/api/v0";
 const DEFAULT_GATEWAY_URL: &str = "http://127.0.0.1:8080";
 
 /// IPFS backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct IpfsConfig {
     /// IPFS API URL (default: http://127.0.0.1:5001/api/v0)
     pub api_url: String,
@@ -65,45 +71,71 @@ pub fn new(id: impl Into<String>, config: IpfsConfig) -> Self {
                 search: false,
                 versioning: true, // Content-addressed = immutable versions
                 sharing: true,
+                offline: false,
                 streaming: true,
-                resume: false,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: true,
                 watch: false,
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
             },
         }
     }
 
-    /// Make API POST request
-    async fn api_post(&self, endpoint: &str, params: &[(&str, &str)]) -> CfkResult<String> {
-        let config = self.config.read().await;
-        let url = format!("{}/{}", config.api_url, endpoint);
+    async fn use_mfs(&self) -> bool {
+        self.config.read().await.use_mfs
+    }
 
-        let mut request = self.http.post(&url);
+    async fn endpoint_url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.config.read().await.api_url, endpoint)
+    }
+
+    /// Send an RPC request and map daemon errors
+    async fn send(&self, request: reqwest::RequestBuilder) -> CfkResult<reqwest::Response> {
+        let response = request.send().await.map_err(http::network_error)?;
 
-        for (key, value) in params {
-            request = request.query(&[(key, value)]);
+        if response.status().is_success() {
+            return Ok(response);
         }
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "ipfs".into(),
-                message: format!("{}: {}", status, error_text),
-            });
+        // Kubo reports most failures as 500 with a JSON `Message`
+        if response.status() == reqwest::StatusCode::INTERNAL_SERVER_ERROR {
+            #[derive(Deserialize)]
+            struct ApiError {
+                #[serde(rename = "Message")]
+                message: String,
+            }
+
+            let path = response.url().query().unwrap_or_default().to_string();
+            let text = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<ApiError>(&text)
+                .map(|e| e.message)
+                .unwrap_or(text);
+
+            return Err(
+                if message.contains("does not exist") || message.contains("no link named") {
+                    CfkError::NotFound(path)
+                } else if message.contains("already exists") {
+                    CfkError::AlreadyExists(path)
+                } else if message.contains("not a directory") {
+                    CfkError::NotADirectory(path)
+                } else {
+                    CfkError::ProviderApi {
+                        provider: "ipfs".into(),
+                        message,
+                    }
+                },
+            );
         }
 
-        response
-            .text()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+        Err(http::error_from_response("ipfs", response).await)
+    }
+
+    /// Make API POST request
+    async fn api_post(&self, endpoint: &str, params: &[(&str, &str)]) -> CfkResult<String> {
+        let url = self.endpoint_url(endpoint).await;
+        let response = self.send(self.http.post(&url).query(params)).await?;
+
+        response.text().await.map_err(http::network_error)
     }
 
     /// Make API POST request with JSON response
@@ -118,42 +150,23 @@ async fn api_post_json<T: for<'de> Deserialize<'de>>(
 
     /// Add content to IPFS
     pub async fn add(&self, data: Bytes, name: Option<&str>) -> CfkResult<AddResponse> {
-        let config = self.config.read().await;
-        let url = format!("{}/add", config.api_url);
+        let auto_pin = self.config.read().await.auto_pin;
+        let url = self.endpoint_url("add").await;
 
-        let mut params = vec![("pin", if config.auto_pin { "true" } else { "false" })];
+        let mut params = vec![("pin", if auto_pin { "true" } else { "false" })];
         if let Some(n) = name {
             params.push(("path", n));
         }
 
-        let part = multipart::Part::bytes(data.to_vec())
-            .file_name(name.unwrap_or("file").to_string());
+        let part =
+            multipart::Part::bytes(data.to_vec()).file_name(name.unwrap_or("file").to_string());
         let form = multipart::Form::new().part("file", part);
 
-        let mut request = self.http.post(&url);
-        for (key, value) in &params {
-            request = request.query(&[(key, value)]);
-        }
-
-        let response = request
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "ipfs".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+        let response = self
+            .send(self.http.post(&url).query(&params).multipart(form))
+            .await?;
 
-        let text = response
-            .text()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let text = response.text().await.map_err(http::network_error)?;
 
         // IPFS returns newline-delimited JSON for directories
         let last_line = text.lines().last().unwrap_or(&text);
@@ -161,31 +174,35 @@ pub async fn add(&self, data: Bytes, name: Option<&str>) -> CfkResult<AddRespons
     }
 
     /// Get content by CID
-    pub async fn cat(&self, cid: &str) -> CfkResult<Bytes> {
-        let config = self.config.read().await;
-        let url = format!("{}/cat", config.api_url);
-
-        let response = self
-            .http
-            .post(&url)
-            .query(&[("arg", cid)])
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "ipfs".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+    pub async fn cat(&self, cid: &str) -> CfkResult<ByteStream> {
+        self.read_range("cat", "length", cid, None).await
+    }
 
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+    /// `cat` or `files/read`, limited to a byte range
+    ///
+    /// The two endpoints take an `offset` and differ only in the name of
+    /// the length parameter.
+    async fn read_range(
+        &self,
+        endpoint: &str,
+        length_param: &str,
+        arg: &str,
+        range: Option<(u64, u64)>,
+    ) -> CfkResult<ByteStream> {
+        let url = self.endpoint_url(endpoint).await;
+        let mut params = vec![("arg", arg.to_string())];
+        if let Some((start, end)) = range {
+            // A zero length means "to the end", so ask for at least a byte
+            params.push(("offset", start.to_string()));
+            params.push((length_param, end.saturating_sub(start).max(1).to_string()));
+        }
+        let response = self.send(self.http.post(&url).query(&params)).await?;
+
+        let body = http::body_stream(response);
+        Ok(match range {
+            Some((start, end)) => common::slice_stream(body, 0, end.saturating_sub(start)),
+            None => body,
+        })
     }
 
     /// Pin a CID
@@ -227,65 +244,29 @@ struct PinType {
     }
 
     /// MFS: Write file to path
-    async fn mfs_write(&self, path: &str, data: Bytes) -> CfkResult<()> {
-        let config = self.config.read().await;
-        let url = format!("{}/files/write", config.api_url);
-
-        let part = multipart::Part::bytes(data.to_vec()).file_name("file");
-        let form = multipart::Form::new().part("file", part);
-
-        let response = self
-            .http
-            .post(&url)
-            .query(&[
-                ("arg", path),
-                ("create", "true"),
-                ("parents", "true"),
-                ("truncate", "true"),
-            ])
-            .multipart(form)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "ipfs".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+    async fn mfs_write(&self, path: &str, part: multipart::Part, parents: bool) -> CfkResult<()> {
+        let url = self.endpoint_url("files/write").await;
+        let form = multipart::Form::new().part("file", part.file_name("file"));
+
+        self.send(
+            self.http
+                .post(&url)
+                .query(&[
+                    ("arg", path),
+                    ("create", "true"),
+                    ("parents", if parents { "true" } else { "false" }),
+                    ("truncate", "true"),
+                ])
+                .multipart(form),
+        )
+        .await?;
 
         Ok(())
     }
 
     /// MFS: Read file from path
-    async fn mfs_read(&self, path: &str) -> CfkResult<Bytes> {
-        let config = self.config.read().await;
-        let url = format!("{}/files/read", config.api_url);
-
-        let response = self
-            .http
-            .post(&url)
-            .query(&[("arg", path)])
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "ipfs".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
-
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+    async fn mfs_read(&self, path: &str, range: Option<(u64, u64)>) -> CfkResult<ByteStream> {
+        self.read_range("files/read", "count", path, range).await
     }
 
     /// MFS: List directory
@@ -319,7 +300,10 @@ async fn mfs_mkdir(&self, path: &str) -> CfkResult<()> {
     async fn mfs_rm(&self, path: &str, recursive: bool) -> CfkResult<()> {
         self.api_post(
             "files/rm",
-            &[("arg", path), ("recursive", if recursive { "true" } else { "false" })],
+            &[
+                ("arg", path),
+                ("recursive", if recursive { "true" } else { "false" }),
+            ],
         )
         .await?;
         Ok(())
@@ -347,6 +331,60 @@ fn to_mfs_path(&self, path: &VirtualPath) -> String {
             format!("/{}", path.segments.join("/"))
         }
     }
+
+    /// Stat an MFS path and convert it to an entry
+    async fn mfs_entry(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let stat = self.mfs_stat(&self.to_mfs_path(path)).await?;
+        let metadata = cid_metadata(stat.size, stat.hash);
+
+        Ok(if stat.entry_type == "directory" {
+            Entry::directory(path.clone(), metadata)
+        } else {
+            Entry::file(path.clone(), metadata)
+        })
+    }
+
+    /// Write to MFS, honouring `overwrite` and `content_hash`
+    async fn write_part(
+        &self,
+        path: &VirtualPath,
+        part: multipart::Part,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let mfs_path = self.to_mfs_path(path);
+
+        if !options.overwrite {
+            match self.mfs_stat(&mfs_path).await {
+                Ok(_) => return Err(CfkError::AlreadyExists(path.to_string())),
+                Err(CfkError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.mfs_write(&mfs_path, part, options.create_parents)
+            .await?;
+
+        // Get updated metadata
+        let entry = self.mfs_entry(path).await?;
+        if let Some(expected) = &options.content_hash {
+            if entry.metadata.content_hash.as_ref() != Some(expected) {
+                return Err(CfkError::ChecksumMismatch);
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
+/// Metadata for a content-addressed node: the CID is both hash and revision
+fn cid_metadata(size: u64, cid: String) -> Metadata {
+    Metadata {
+        size: Some(size),
+        content_hash: Some(cid.clone()),
+        provider_id: Some(cid.clone()),
+        revision: Some(cid),
+        ..Default::default()
+    }
 }
 
 /// IPFS add response
@@ -418,40 +456,17 @@ struct IdResponse {
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            let stat = self.mfs_stat(&mfs_path).await?;
-
-            let kind = if stat.entry_type == "directory" {
-                EntryKind::Directory
-            } else {
-                EntryKind::File
-            };
-
-            let mut metadata = Metadata::default();
-            metadata.size = Some(stat.size);
-            metadata.checksum = Some(stat.hash);
-
-            return Ok(Entry {
-                path: path.clone(),
-                kind,
-                metadata,
-            });
+        if self.use_mfs().await {
+            return self.mfs_entry(path).await;
         }
 
         // For CID-based paths
         if path.segments.is_empty() {
-            return Ok(Entry {
-                path: path.clone(),
-                kind: EntryKind::Directory,
-                metadata: Metadata::default(),
-            });
+            return Ok(Entry::directory(path.clone(), Metadata::default()));
         }
 
         // Assume first segment is CID
-        let cid = &path.segments[0];
+        let cid = path.segments.join("/");
 
         #[derive(Deserialize)]
         struct ObjectStat {
@@ -459,151 +474,95 @@ struct ObjectStat {
             hash: String,
             #[serde(rename = "NumLinks")]
             num_links: u64,
-            #[serde(rename = "DataSize")]
-            data_size: u64,
             #[serde(rename = "CumulativeSize")]
             cumulative_size: u64,
         }
 
-        let stat: ObjectStat = self.api_post_json("object/stat", &[("arg", cid)]).await?;
+        let stat: ObjectStat = self.api_post_json("object/stat", &[("arg", &cid)]).await?;
+        let metadata = cid_metadata(stat.cumulative_size, stat.hash);
 
-        let kind = if stat.num_links > 0 {
-            EntryKind::Directory
+        Ok(if stat.num_links > 0 {
+            Entry::directory(path.clone(), metadata)
         } else {
-            EntryKind::File
-        };
-
-        let mut metadata = Metadata::default();
-        metadata.size = Some(stat.cumulative_size);
-        metadata.checksum = Some(stat.hash);
-
-        Ok(Entry {
-            path: path.clone(),
-            kind,
-            metadata,
+            Entry::file(path.clone(), metadata)
         })
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            let entries = self.mfs_ls(&mfs_path).await?;
-
-            let base_path = if path.segments.is_empty() {
-                String::new()
-            } else {
-                format!("{}/", path.segments.join("/"))
-            };
-
-            return Ok(entries
-                .iter()
-                .map(|e| {
-                    let kind = if e.entry_type == 1 {
-                        EntryKind::Directory
-                    } else {
-                        EntryKind::File
-                    };
-
-                    let mut metadata = Metadata::default();
-                    metadata.size = Some(e.size);
-                    metadata.checksum = Some(e.hash.clone());
-
-                    Entry {
-                        path: VirtualPath::new(&self.id, &format!("{}{}", base_path, e.name)),
-                        kind,
-                        metadata,
-                    }
-                })
-                .collect());
-        }
-
-        // For non-MFS, list pins at root
-        if path.segments.is_empty() {
-            let pins = self.list_pins().await?;
-
-            return Ok(pins
-                .iter()
-                .map(|p| {
-                    let mut metadata = Metadata::default();
-                    metadata.custom.insert("pin_type".to_string(), p.pin_type.clone());
-
-                    Entry {
-                        path: VirtualPath::new(&self.id, &p.cid),
-                        kind: EntryKind::File, // Assume file, could be directory
-                        metadata,
-                    }
-                })
-                .collect());
-        }
-
-        // List IPFS directory by CID
-        let cid = &path.segments[0];
-
-        #[derive(Deserialize)]
-        struct LsResponse {
-            #[serde(rename = "Objects")]
-            objects: Vec<LsObject>,
-        }
-
-        #[derive(Deserialize)]
-        struct LsObject {
-            #[serde(rename = "Links")]
-            links: Vec<LsLink>,
-        }
-
-        #[derive(Deserialize)]
-        struct LsLink {
-            #[serde(rename = "Name")]
-            name: String,
-            #[serde(rename = "Hash")]
-            hash: String,
-            #[serde(rename = "Size")]
-            size: u64,
-            #[serde(rename = "Type")]
-            link_type: u64,
+    /// IPFS listings are returned whole, so paging happens client-side with
+    /// the cursor holding the offset. `recursive` is not supported.
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let mut entries = Vec::new();
+
+        if self.use_mfs().await {
+            for e in self.mfs_ls(&self.to_mfs_path(path)).await? {
+                let child = path.join(&e.name);
+                let metadata = cid_metadata(e.size, e.hash);
+                entries.push(if e.entry_type == 1 {
+                    Entry::directory(child, metadata)
+                } else {
+                    Entry::file(child, metadata)
+                });
+            }
+        } else if path.segments.is_empty() {
+            // For non-MFS, list pins at root
+            for p in self.list_pins().await? {
+                let mut metadata = cid_metadata(0, p.cid.clone());
+                metadata.size = None;
+                metadata.custom.insert("pin_type".to_string(), p.pin_type);
+
+                // Assume file, could be directory
+                entries.push(Entry::file(VirtualPath::new(&self.id, &p.cid), metadata));
+            }
+        } else {
+            // List IPFS directory by CID
+            #[derive(Deserialize)]
+            struct LsResponse {
+                #[serde(rename = "Objects")]
+                objects: Vec<LsObject>,
+            }
+
+            #[derive(Deserialize)]
+            struct LsObject {
+                #[serde(rename = "Links")]
+                links: Vec<LsLink>,
+            }
+
+            #[derive(Deserialize)]
+            struct LsLink {
+                #[serde(rename = "Name")]
+                name: String,
+                #[serde(rename = "Hash")]
+                hash: String,
+                #[serde(rename = "Size")]
+                size: u64,
+                #[serde(rename = "Type")]
+                link_type: u64,
+            }
+
+            let cid = path.segments.join("/");
+            let resp: LsResponse = self.api_post_json("ls", &[("arg", &cid)]).await?;
+
+            for l in resp.objects.into_iter().flat_map(|o| o.links) {
+                let child = path.join(&l.name);
+                let metadata = cid_metadata(l.size, l.hash);
+                entries.push(if l.link_type == 1 {
+                    Entry::directory(child, metadata)
+                } else {
+                    Entry::file(child, metadata)
+                });
+            }
         }
 
-        let resp: LsResponse = self.api_post_json("ls", &[("arg", cid)]).await?;
-
-        let links = resp
-            .objects
-            .first()
-            .map(|o| &o.links)
-            .cloned()
-            .unwrap_or_default();
-
-        let base_path = path.segments.join("/");
-
-        Ok(links
-            .iter()
-            .map(|l| {
-                let kind = if l.link_type == 1 {
-                    EntryKind::Directory
-                } else {
-                    EntryKind::File
-                };
-
-                let mut metadata = Metadata::default();
-                metadata.size = Some(l.size);
-                metadata.checksum = Some(l.hash.clone());
-
-                Entry {
-                    path: VirtualPath::new(&self.id, &format!("{}/{}", base_path, l.name)),
-                    kind,
-                    metadata,
-                }
-            })
-            .collect())
+        Ok(common::page_entries(path, entries, options))
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            return self.mfs_read(&mfs_path).await;
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        if self.use_mfs().await {
+            return self.mfs_read(&self.to_mfs_path(path), options.range).await;
         }
 
         // Read by CID
@@ -611,52 +570,62 @@ async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
             return Err(CfkError::InvalidPath("No CID specified".into()));
         }
 
-        let cid = path.segments.join("/");
-        self.cat(&cid).await
+        self.read_range("cat", "length", &path.segments.join("/"), options.range)
+            .await
     }
 
-    async fn write_file(&self, path: &VirtualPath, data: Bytes) -> CfkResult<Entry> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            self.mfs_write(&mfs_path, data).await?;
-
-            // Get updated metadata
-            let stat = self.mfs_stat(&mfs_path).await?;
-
-            let mut metadata = Metadata::default();
-            metadata.size = Some(stat.size);
-            metadata.checksum = Some(stat.hash);
-
-            return Ok(Entry {
-                path: path.clone(),
-                kind: EntryKind::File,
-                metadata,
-            });
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if self.use_mfs().await {
+            let part = multipart::Part::bytes(data.to_vec());
+            return self.write_part(path, part, options).await;
         }
 
         // Add to IPFS and return the CID path
         let name = path.segments.last().map(String::as_str);
         let add_resp = self.add(data, name).await?;
 
-        let mut metadata = Metadata::default();
-        metadata.size = Some(add_resp.size.parse().unwrap_or(0));
-        metadata.checksum = Some(add_resp.hash.clone());
+        let size = add_resp.size.parse().unwrap_or(0);
+        Ok(Entry::file(
+            VirtualPath::new(&self.id, &add_resp.hash),
+            cid_metadata(size, add_resp.hash),
+        ))
+    }
 
-        Ok(Entry {
-            path: VirtualPath::new(&self.id, &add_resp.hash),
-            kind: EntryKind::File,
-            metadata,
-        })
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if !self.use_mfs().await {
+            let data = common::collect_stream(stream).await?;
+            return self.write_file(path, data, options).await;
+        }
+
+        let body = Body::wrap_stream(stream);
+        let part = match size_hint {
+            Some(len) => multipart::Part::stream_with_length(body, len),
+            None => multipart::Part::stream(body),
+        };
+        self.write_part(path, part, options).await
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let config = self.config.read().await;
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if self.use_mfs().await {
+            if path.is_root() {
+                return Err(CfkError::PermissionDenied(path.to_string()));
+            }
 
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            return self.mfs_rm(&mfs_path, true).await;
+            // MFS only removes directories with `recursive`, even empty ones
+            common::check_delete(self, path, options).await?;
+            let result = self.mfs_rm(&self.to_mfs_path(path), true).await;
+            return common::ignore_missing(result, options);
         }
 
         // For CID paths, unpin
@@ -669,22 +638,9 @@ async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let mfs_path = self.to_mfs_path(path);
-            self.mfs_mkdir(&mfs_path).await?;
-
-            let stat = self.mfs_stat(&mfs_path).await?;
-
-            let mut metadata = Metadata::default();
-            metadata.checksum = Some(stat.hash);
-
-            return Ok(Entry {
-                path: path.clone(),
-                kind: EntryKind::Directory,
-                metadata,
-            });
+        if self.use_mfs().await {
+            self.mfs_mkdir(&self.to_mfs_path(path)).await?;
+            return self.mfs_entry(path).await;
         }
 
         Err(CfkError::Unsupported(
@@ -692,35 +648,41 @@ async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
         ))
     }
 
-    async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let from_path = self.to_mfs_path(from);
-            let to_path = self.to_mfs_path(to);
-            self.mfs_cp(&from_path, &to_path).await?;
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        if self.use_mfs().await {
+            common::prepare_destination(self, dest, options.overwrite).await?;
+            self.mfs_cp(&self.to_mfs_path(source), &self.to_mfs_path(dest))
+                .await?;
 
-            return self.get_metadata(to).await;
+            return self.mfs_entry(dest).await;
         }
 
         Err(CfkError::Unsupported("Copy requires MFS enabled".into()))
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let config = self.config.read().await;
-
-        if config.use_mfs {
-            let from_path = self.to_mfs_path(from);
-            let to_path = self.to_mfs_path(to);
-            self.mfs_mv(&from_path, &to_path).await?;
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        if self.use_mfs().await {
+            common::prepare_destination(self, dest, options.overwrite).await?;
+            self.mfs_mv(&self.to_mfs_path(source), &self.to_mfs_path(dest))
+                .await?;
 
-            return self.get_metadata(to).await;
+            return self.mfs_entry(dest).await;
         }
 
         Err(CfkError::Unsupported("Rename requires MFS enabled".into()))
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         #[derive(Deserialize)]
         struct RepoStat {
             #[serde(rename = "RepoSize")]
@@ -731,8 +693,11 @@ struct RepoStat {
 
         let stat: RepoStat = self.api_post_json("repo/stat", &[]).await?;
 
-        let available = stat.storage_max.saturating_sub(stat.repo_size);
-        Ok((available, stat.storage_max))
+        Ok(SpaceInfo {
+            total: Some(stat.storage_max),
+            used: Some(stat.repo_size),
+            available: Some(stat.storage_max.saturating_sub(stat.repo_size)),
+        })
     }
 }
 
@@ -746,9 +711,7 @@ struct ResolveResponse {
             path: String,
         }
 
-        let resp: ResolveResponse = self
-            .api_post_json("name/resolve", &[("arg", name)])
-            .await?;
+        let resp: ResolveResponse = self.api_post_json("name/resolve", &[("arg", name)]).await?;
 
         Ok(resp.path)
     }
@@ -759,13 +722,9 @@ pub async fn publish_ipns(&self, cid: &str) -> CfkResult<String> {
         struct PublishResponse {
             #[serde(rename = "Name")]
             name: String,
-            #[serde(rename = "Value")]
-            value: String,
         }
 
-        let resp: PublishResponse = self
-            .api_post_json("name/publish", &[("arg", cid)])
-            .await?;
+        let resp: PublishResponse = self.api_post_json("name/publish", &[("arg", cid)]).await?;
 
         Ok(resp.name)
     }
@@ -800,3 +759,118 @@ pub async fn repo_gc(&self) -> CfkResult<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn backend(server: &MockServer) -> IpfsBackend {
+        IpfsBackend::new(
+            "ipfs",
+            IpfsConfig {
+                api_url: server.uri(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_list_mfs_directory_pages_by_offset() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/ls"))
+            .and(query_param("arg", "/docs"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"Entries":[
+                    {"Name":"a.txt","Type":0,"Size":3,"Hash":"QmA"},
+                    {"Name":"img","Type":1,"Size":0,"Hash":"QmB"},
+                    {"Name":"b.txt","Type":0,"Size":4,"Hash":"QmC"}
+                ]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let dir = VirtualPath::new("ipfs", "docs");
+        let options = ListOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let first = backend.list_directory(&dir, &options).await.unwrap();
+        assert_eq!(first.entries.len(), 2);
+        assert!(first.entries[1].is_directory());
+        assert_eq!(first.cursor.as_deref(), Some("2"));
+
+        let options = ListOptions {
+            cursor: first.cursor,
+            ..options
+        };
+        let second = backend.list_directory(&dir, &options).await.unwrap();
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(
+            second.entries[0].metadata.content_hash.as_deref(),
+            Some("QmC")
+        );
+        assert!(!second.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_read_file_streams_mfs_content() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/read"))
+            .and(query_param("arg", "/a.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let stream = backend
+            .read_file(&VirtualPath::new("ipfs", "a.txt"), &ReadOptions::default())
+            .await
+            .unwrap();
+        let data = common::collect_stream(stream).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_missing_file_maps_to_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files/stat"))
+            .respond_with(
+                ResponseTemplate::new(500).set_body_string(
+                    r#"{"Message":"file does not exist","Code":0,"Type":"error"}"#,
+                ),
+            )
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let err = backend
+            .get_metadata(&VirtualPath::new("ipfs", "nope"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_space_info_from_repo_stat() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/repo/stat"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"RepoSize":400,"StorageMax":1000,"NumObjects":2}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.used, Some(400));
+        assert_eq!(space.available, Some(600));
+    }
+}
@@ -4,8 +4,18 @@
 //! Plus exotic protocols: Gopher, Gemini, NNTP, RTSP, BitTorrent, etc.
 //! Transport layers: TCP, QUIC, UDP, Unix sockets.
 
+mod common;
+pub mod config;
 mod local;
+#[cfg(feature = "reqwest")]
+mod http;
+#[cfg(feature = "hyper")]
+mod http_server;
+#[cfg(feature = "smb")]
+mod ntlm;
 pub mod protocols;
+pub mod retry;
+pub mod tokens;
 pub mod transport;
 
 #[cfg(feature = "dropbox")]
@@ -50,7 +60,16 @@
 #[cfg(feature = "ceph")]
 pub mod ceph;
 
+#[cfg(feature = "crypt")]
+pub mod crypt;
+
+pub use config::{CfkConfig, RemoteConfig};
 pub use local::LocalBackend;
+pub use retry::{RetryBackend, RetryPolicy, RetryStats};
+pub use tokens::{FileTokenStore, OAuthTokens, TokenStore};
+
+#[cfg(feature = "oauth2")]
+pub use tokens::{AuthRequest, LoopbackListener, OAuthBackend};
 
 // Re-export provider types when features are enabled
 #[cfg(feature = "dropbox")]
@@ -66,34 +85,37 @@
 pub use box_com::{BoxBackend, BoxConfig, BoxTokens};
 
 #[cfg(feature = "s3")]
-pub use s3::{S3Backend, S3Config};
+pub use s3::{S3Backend, S3Config, S3Gateway, S3GatewayOptions};
 
 #[cfg(feature = "ipfs")]
 pub use ipfs::{IpfsBackend, IpfsConfig};
 
 #[cfg(feature = "webdav")]
-pub use webdav::{WebDavBackend, WebDavConfig, WebDavAuth};
+pub use webdav::{WebDavAuth, WebDavBackend, WebDavConfig, WebDavServer, WebDavServerOptions};
 
 #[cfg(feature = "afs")]
 pub use afs::{AfsBackend, AfsConfig};
 
 #[cfg(feature = "ninep")]
-pub use ninep::{NinePBackend, NinePConfig};
+pub use ninep::{NinePBackend, NinePConfig, NinePServer, NinePServerOptions};
 
 #[cfg(feature = "sftp")]
 pub use sftp::{SftpBackend, SftpConfig, SftpAuth};
 
 #[cfg(feature = "nfs")]
-pub use nfs::{NfsBackend, NfsConfig, NfsVersion};
+pub use nfs::{NfsAuth, NfsBackend, NfsConfig, NfsExport, NfsVersion};
 
 #[cfg(feature = "smb")]
-pub use smb::{SmbBackend, SmbConfig, SmbVersion, SmbAuth};
+pub use smb::{SmbAuth, SmbBackend, SmbConfig, SmbShare, SmbShareKind, SmbVersion};
 
 #[cfg(feature = "syncthing")]
 pub use syncthing::{SyncthingBackend, SyncthingConfig};
 
 #[cfg(feature = "ceph")]
-pub use ceph::{CephBackend, CephConfig, CephMode};
+pub use ceph::{CephBackend, CephConfig, CephMode, ClusterStat, PoolStat};
+
+#[cfg(feature = "crypt")]
+pub use crypt::{CryptBackend, CryptConfig};
 
 use cfk_core::{StorageBackend, CfkResult, CfkError};
 use std::collections::HashMap;
@@ -102,23 +124,42 @@
 /// Registry of storage backends
 pub struct BackendRegistry {
     backends: HashMap<String, Arc<dyn StorageBackend>>,
+    /// Configured backends that could not be built, with the reason
+    failed: HashMap<String, String>,
 }
 
 impl BackendRegistry {
     pub fn new() -> Self {
-        Self { backends: HashMap::new() }
+        Self { backends: HashMap::new(), failed: HashMap::new() }
     }
 
     pub fn register(&mut self, backend: Arc<dyn StorageBackend>) {
+        self.failed.remove(backend.id());
         self.backends.insert(backend.id().to_string(), backend);
     }
 
+    /// Remember why backend `id` is missing, for `get_or_err` to report
+    pub fn register_failed(&mut self, id: &str, reason: impl Into<String>) {
+        self.failed.insert(id.to_string(), reason.into());
+    }
+
     pub fn get(&self, id: &str) -> Option<Arc<dyn StorageBackend>> {
         self.backends.get(id).cloned()
     }
 
     pub fn get_or_err(&self, id: &str) -> CfkResult<Arc<dyn StorageBackend>> {
-        self.get(id).ok_or_else(|| CfkError::BackendNotFound(id.to_string()))
+        if let Some(backend) = self.get(id) {
+            return Ok(backend);
+        }
+        match self.failed.get(id) {
+            Some(reason) => Err(CfkError::Config(format!("remote {}: {}", id, reason))),
+            None => Err(CfkError::BackendNotFound(id.to_string())),
+        }
+    }
+
+    /// Backends that failed to build, with the reason
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.failed.iter().map(|(id, reason)| (id.as_str(), reason.as_str()))
     }
 
     pub fn list(&self) -> Vec<&str> {
@@ -10,6 +10,8 @@
     operations::*,
     VirtualPath,
 };
+#[cfg(target_os = "linux")]
+use cfk_core::{ChangeEvent, ChangeStream};
 use std::path::{Path, PathBuf};
 use tokio::fs;
 use tokio::io::AsyncReadExt;
@@ -26,7 +28,10 @@ pub fn new(id: impl Into<String>, root: impl AsRef<Path>) -> Self {
         Self {
             id: id.into(),
             root: root.as_ref().to_path_buf(),
-            capabilities: StorageCapabilities::local_filesystem(),
+            capabilities: StorageCapabilities {
+                watch: cfg!(target_os = "linux"),
+                ..StorageCapabilities::local_filesystem()
+            },
         }
     }
 
@@ -135,9 +140,8 @@ async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResul
         if let Some((start, end)) = options.range {
             use tokio::io::AsyncSeekExt;
             file.seek(std::io::SeekFrom::Start(start)).await?;
-            let len = (end - start) as usize;
-            buffer.resize(len, 0);
-            file.read_exact(&mut buffer).await?;
+            // Like an HTTP range, a range running past EOF is clamped
+            file.take(end.saturating_sub(start)).read_to_end(&mut buffer).await?;
         } else {
             file.read_to_end(&mut buffer).await?;
         }
@@ -213,6 +217,10 @@ async fn copy(&self, source: &VirtualPath, dest: &VirtualPath, options: &CopyOpt
         }
 
         fs::copy(&src_real, &dst_real).await?;
+        if options.preserve_metadata {
+            let (_, metadata) = self.metadata_from_path(&src_real).await?;
+            self.set_metadata(dest, &metadata).await?;
+        }
         self.get_metadata(dest).await
     }
 
@@ -231,6 +239,33 @@ async fn rename(&self, source: &VirtualPath, dest: &VirtualPath, options: &MoveO
         self.get_metadata(dest).await
     }
 
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        let real = self.to_real_path(path);
+        if !real.exists() {
+            return Err(CfkError::NotFound(path.to_string()));
+        }
+
+        #[cfg(unix)]
+        if let Some(permissions) = metadata.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::Permissions::from_mode(permissions.mode & 0o7777);
+            fs::set_permissions(&real, mode).await?;
+        }
+
+        if let Some(modified) = metadata.modified {
+            let mut times = std::fs::FileTimes::new().set_modified(modified.into());
+            if let Some(accessed) = metadata.accessed {
+                times = times.set_accessed(accessed.into());
+            }
+            let file = std::fs::File::open(&real)?;
+            tokio::task::spawn_blocking(move || file.set_times(times))
+                .await
+                .map_err(|e| CfkError::Other(e.to_string()))??;
+        }
+
+        Ok(())
+    }
+
     async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         #[cfg(unix)]
         {
@@ -243,6 +278,8 @@ async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
             let mut stat: MaybeUninit<libc::statvfs> = MaybeUninit::uninit();
             let result = unsafe { libc::statvfs(path_cstr.as_ptr(), stat.as_mut_ptr()) };
 
+            // statvfs field widths differ between platforms
+            #[allow(clippy::unnecessary_cast)]
             if result == 0 {
                 let stat = unsafe { stat.assume_init() };
                 let block_size = stat.f_frsize as u64;
@@ -266,6 +303,267 @@ async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
             Ok(SpaceInfo::unknown())
         }
     }
+
+    #[cfg(target_os = "linux")]
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        let real = self.to_real_path(path);
+        if !real.exists() {
+            return Err(CfkError::NotFound(path.to_string()));
+        }
+        watcher::Watcher::start(&self.id, &self.root, &real, path.clone(), recursive)
+    }
+}
+
+/// inotify watches
+///
+/// inotify is per directory, so a recursive watch adds one for every
+/// directory below the root, and for each new one as it appears. Watches
+/// follow inodes: a renamed directory keeps reporting, under its new name
+/// once the rename has been seen.
+#[cfg(target_os = "linux")]
+mod watcher {
+    use super::*;
+    use cfk_core::ChangeKind;
+    use futures::StreamExt;
+    use inotify::{EventMask, EventOwned, EventStream, Inotify, WatchDescriptor, WatchMask, Watches};
+    use std::collections::{HashMap, VecDeque};
+    use std::time::Duration;
+
+    /// How long a move out of a watched directory waits for its arrival
+    /// before it counts as a delete
+    const MOVE_PAIRING: Duration = Duration::from_millis(50);
+
+    pub(super) struct Watcher {
+        id: String,
+        root: PathBuf,
+        /// What was asked for; events elsewhere are dropped
+        watched: VirtualPath,
+        /// The directory watched first, whose own deletion is reported
+        top: PathBuf,
+        recursive: bool,
+        events: EventStream<Vec<u8>>,
+        watches: Watches,
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+        /// A move seen leaving: cookie, old path, whether a directory
+        moved_from: Option<(u32, PathBuf, bool)>,
+        ready: VecDeque<ChangeEvent>,
+    }
+
+    impl Watcher {
+        pub fn start(
+            id: &str,
+            root: &Path,
+            real: &Path,
+            watched: VirtualPath,
+            recursive: bool,
+        ) -> CfkResult<ChangeStream> {
+            // A file is watched through its directory, which also sees it
+            // replaced by a rename the way editors save
+            let top = match real.parent() {
+                Some(parent) if !real.is_dir() => parent,
+                _ => real,
+            };
+            let events = Inotify::init()?.into_event_stream(vec![0u8; 64 * 1024])?;
+            let mut watcher = Self {
+                id: id.to_string(),
+                root: root.to_path_buf(),
+                watched,
+                top: top.to_path_buf(),
+                recursive,
+                watches: events.watches(),
+                events,
+                dirs: HashMap::new(),
+                moved_from: None,
+                ready: VecDeque::new(),
+            };
+            let wd = watcher.watches.add(top, Self::mask())?;
+            watcher.dirs.insert(wd, top.to_path_buf());
+            if recursive && top == real {
+                watcher.add_below(top);
+            }
+
+            Ok(Box::pin(futures::stream::unfold(watcher, |mut watcher| async move {
+                watcher.next().await.map(|item| (item, watcher))
+            })))
+        }
+
+        fn mask() -> WatchMask {
+            WatchMask::CREATE
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::ATTRIB
+                | WatchMask::DELETE
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO
+        }
+
+        /// Watch every directory below `dir`. Best effort: directories that
+        /// vanish or cannot be read are skipped.
+        fn add_below(&mut self, dir: &Path) {
+            let mut pending = vec![dir.to_path_buf()];
+            while let Some(dir) = pending.pop() {
+                let Ok(read_dir) = std::fs::read_dir(&dir) else {
+                    continue;
+                };
+                for entry in read_dir.flatten() {
+                    if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        continue;
+                    }
+                    let path = entry.path();
+                    match self.watches.add(&path, Self::mask()) {
+                        Ok(wd) => {
+                            self.dirs.insert(wd, path.clone());
+                            pending.push(path);
+                        }
+                        Err(e) => tracing::debug!("not watching {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+
+        fn add_tree(&mut self, dir: &Path) {
+            if let Ok(wd) = self.watches.add(dir, Self::mask()) {
+                self.dirs.insert(wd, dir.to_path_buf());
+                self.add_below(dir);
+            }
+        }
+
+        /// Stop watching `dir` and everything below it
+        fn forget(&mut self, dir: &Path) {
+            let gone: Vec<WatchDescriptor> = self
+                .dirs
+                .iter()
+                .filter(|(_, path)| path.starts_with(dir))
+                .map(|(wd, _)| wd.clone())
+                .collect();
+            for wd in gone {
+                self.dirs.remove(&wd);
+                let _ = self.watches.remove(wd);
+            }
+        }
+
+        /// Point the watches of a renamed directory at its new name
+        fn moved(&mut self, from: &Path, to: &Path) {
+            for path in self.dirs.values_mut() {
+                if let Ok(rest) = path.strip_prefix(from) {
+                    *path = if rest.as_os_str().is_empty() {
+                        to.to_path_buf()
+                    } else {
+                        to.join(rest)
+                    };
+                }
+            }
+        }
+
+        fn virtual_path(&self, real: &Path) -> Option<VirtualPath> {
+            let relative = real.strip_prefix(&self.root).ok()?;
+            Some(VirtualPath::new(&self.id, relative.to_string_lossy()))
+        }
+
+        fn push(&mut self, real: &Path, kind: ChangeKind) {
+            if let Some(path) = self.virtual_path(real) {
+                let event = ChangeEvent::new(path, kind);
+                if event.is_within(&self.watched, self.recursive) {
+                    self.ready.push_back(event);
+                }
+            }
+        }
+
+        /// A move out with no arrival left the watched tree
+        fn flush_move(&mut self) {
+            if let Some((_, from, is_dir)) = self.moved_from.take() {
+                if is_dir {
+                    self.forget(&from);
+                }
+                self.push(&from, ChangeKind::Deleted);
+            }
+        }
+
+        async fn next(&mut self) -> Option<CfkResult<ChangeEvent>> {
+            loop {
+                if let Some(event) = self.ready.pop_front() {
+                    return Some(Ok(event));
+                }
+                let next = if self.moved_from.is_some() {
+                    match tokio::time::timeout(MOVE_PAIRING, self.events.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            self.flush_move();
+                            continue;
+                        }
+                    }
+                } else {
+                    self.events.next().await
+                };
+                match next? {
+                    Ok(event) => self.handle(event),
+                    Err(e) => return Some(Err(e.into())),
+                }
+            }
+        }
+
+        fn handle(&mut self, event: EventOwned) {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                self.flush_move();
+                let watched = self.watched.clone();
+                self.ready.push_back(ChangeEvent::new(watched, ChangeKind::Rescan));
+                return;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                self.dirs.remove(&event.wd);
+                return;
+            }
+            let Some(dir) = self.dirs.get(&event.wd) else {
+                return;
+            };
+            let real = match &event.name {
+                Some(name) => dir.join(name),
+                None => dir.clone(),
+            };
+            let is_dir = event.mask.contains(EventMask::ISDIR);
+
+            if event.mask.contains(EventMask::MOVED_TO) {
+                match self.moved_from.take() {
+                    Some((cookie, from, _)) if cookie == event.cookie => {
+                        self.moved(&from, &real);
+                        let from = self.virtual_path(&from);
+                        if let Some(from) = from {
+                            self.push(&real, ChangeKind::Renamed { from });
+                        }
+                    }
+                    unpaired => {
+                        self.moved_from = unpaired;
+                        self.flush_move();
+                        if is_dir && self.recursive {
+                            self.add_tree(&real);
+                        }
+                        self.push(&real, ChangeKind::Created);
+                    }
+                }
+                return;
+            }
+
+            self.flush_move();
+            if event.mask.contains(EventMask::MOVED_FROM) {
+                self.moved_from = Some((event.cookie, real, is_dir));
+            } else if event.mask.contains(EventMask::CREATE) {
+                if is_dir && self.recursive {
+                    self.add_tree(&real);
+                }
+                self.push(&real, ChangeKind::Created);
+            } else if event.mask.contains(EventMask::DELETE_SELF) {
+                // Directories below the top also show up as deleted in
+                // their parent
+                if real == self.top {
+                    self.push(&real, ChangeKind::Deleted);
+                }
+            } else if event.mask.contains(EventMask::DELETE) {
+                self.push(&real, ChangeKind::Deleted);
+            } else {
+                self.push(&real, ChangeKind::Modified);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -430,6 +728,23 @@ async fn test_copy_file() {
         assert_eq!(content, b"original content");
     }
 
+    #[tokio::test]
+    async fn test_copy_preserves_metadata() {
+        let tmp = TempDir::new().unwrap();
+        let backend = make_backend(&tmp);
+        let src = make_path(&backend, "/original.txt");
+        let dst = make_path(&backend, "/copied.txt");
+
+        backend.write_file(&src, Bytes::from("content"), &WriteOptions::default()).await.unwrap();
+        let past = chrono::DateTime::from_timestamp(1_000_000_000, 0).unwrap();
+        let metadata = Metadata { modified: Some(past), ..Default::default() };
+        backend.set_metadata(&src, &metadata).await.unwrap();
+
+        let options = CopyOptions { preserve_metadata: true, ..Default::default() };
+        let entry = backend.copy(&src, &dst, &options).await.unwrap();
+        assert_eq!(entry.metadata.modified, Some(past));
+    }
+
     #[tokio::test]
     async fn test_rename_file() {
         let tmp = TempDir::new().unwrap();
@@ -481,4 +796,48 @@ async fn test_get_metadata() {
         assert_eq!(entry.size(), Some(content.len() as u64));
         assert!(entry.metadata.modified.is_some());
     }
+
+    #[cfg(target_os = "linux")]
+    async fn next_change(changes: &mut ChangeStream) -> ChangeEvent {
+        let next = tokio::time::timeout(std::time::Duration::from_secs(5), changes.next());
+        next.await.expect("no event").unwrap().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_watch_reports_changes() {
+        use cfk_core::ChangeKind;
+
+        let dir = TempDir::new().unwrap();
+        let backend = make_backend(&dir);
+        assert!(backend.capabilities().watch);
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("outside.txt"), b"x").unwrap();
+
+        let docs = make_path(&backend, "docs");
+        let mut changes = backend.watch(&docs, true).await.unwrap();
+        let event = |p: &str, kind| ChangeEvent::new(make_path(&backend, p), kind);
+
+        std::fs::write(dir.path().join("docs/a.txt"), b"hello").unwrap();
+        assert_eq!(next_change(&mut changes).await, event("docs/a.txt", ChangeKind::Created));
+        assert_eq!(next_change(&mut changes).await, event("docs/a.txt", ChangeKind::Modified));
+
+        std::fs::rename(dir.path().join("docs/a.txt"), dir.path().join("docs/b.txt")).unwrap();
+        let from = make_path(&backend, "docs/a.txt");
+        assert_eq!(next_change(&mut changes).await, event("docs/b.txt", ChangeKind::Renamed { from }));
+
+        // New directories are watched as they appear
+        std::fs::create_dir(dir.path().join("docs/sub")).unwrap();
+        assert_eq!(next_change(&mut changes).await, event("docs/sub", ChangeKind::Created));
+        std::fs::write(dir.path().join("outside.txt"), b"y").unwrap();
+        std::fs::write(dir.path().join("docs/sub/c.txt"), b"").unwrap();
+        assert_eq!(next_change(&mut changes).await, event("docs/sub/c.txt", ChangeKind::Created));
+        assert_eq!(next_change(&mut changes).await, event("docs/sub/c.txt", ChangeKind::Modified));
+
+        // Moving out of the watched tree is a delete
+        std::fs::rename(dir.path().join("docs/b.txt"), dir.path().join("b.txt")).unwrap();
+        assert_eq!(next_change(&mut changes).await, event("docs/b.txt", ChangeKind::Deleted));
+        std::fs::remove_file(dir.path().join("docs/sub/c.txt")).unwrap();
+        assert_eq!(next_change(&mut changes).await, event("docs/sub/c.txt", ChangeKind::Deleted));
+    }
 }
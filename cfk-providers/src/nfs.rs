@@ -1,38 +1,154 @@
 //! NFS storage backend
 //!
-//! Network File System client implementation.
-//! Supports NFSv3 and NFSv4 protocols.
+//! A userspace NFSv3 client over ONC RPC, so exports can be browsed
+//! without root or a kernel mount. The export's root handle comes from the
+//! MOUNT protocol, and requests carry AUTH_SYS credentials.
+//!
+//! Without a privileged source port, Linux servers only answer exports
+//! carrying the `insecure` option.
+//!
+//! One connection is shared by all operations, one request at a time, and
+//! re-established on the next call after it drops.
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    metadata::Permissions,
+    operations::*,
     CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
     VirtualPath,
 };
-use std::path::PathBuf;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::common;
+
+/// Give up on a server that has not mounted the export by then
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Give up on a request the server has not answered by then
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest RPC record accepted from the server
+const MAX_RECORD: usize = 16 * 1024 * 1024;
+
+/// Bytes of names and cookies, and of everything, per READDIRPLUS reply
+const DIR_COUNT: u32 = 16 * 1024;
+const DIR_MAX_COUNT: u32 = 64 * 1024;
+
+/// Permissions of files and directories the client creates
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+
+const RPC_VERSION: u32 = 2;
+const PORTMAP_PORT: u16 = 111;
+
+/// RPC programs and the procedures used of each
+mod prog {
+    pub const PORTMAP: u32 = 100000;
+    pub const PORTMAP_VERSION: u32 = 2;
+    pub const PMAPPROC_GETPORT: u32 = 3;
+
+    pub const MOUNT: u32 = 100005;
+    pub const MOUNT_VERSION: u32 = 3;
+    pub const MOUNTPROC_MNT: u32 = 1;
+    pub const MOUNTPROC_UMNT: u32 = 3;
+    pub const MOUNTPROC_EXPORT: u32 = 5;
+
+    pub const NFS: u32 = 100003;
+    pub const NFS_VERSION: u32 = 3;
+}
+
+/// NFSv3 procedures
+mod proc3 {
+    pub const GETATTR: u32 = 1;
+    pub const SETATTR: u32 = 2;
+    pub const LOOKUP: u32 = 3;
+    pub const READ: u32 = 6;
+    pub const WRITE: u32 = 7;
+    pub const CREATE: u32 = 8;
+    pub const MKDIR: u32 = 9;
+    pub const REMOVE: u32 = 12;
+    pub const RMDIR: u32 = 13;
+    pub const RENAME: u32 = 14;
+    pub const READDIRPLUS: u32 = 17;
+    pub const FSSTAT: u32 = 18;
+    pub const FSINFO: u32 = 19;
+}
+
+/// nfsstat3 codes the client tells apart
+mod nfsstat {
+    pub const OK: u32 = 0;
+    pub const PERM: u32 = 1;
+    pub const NOENT: u32 = 2;
+    pub const ACCES: u32 = 13;
+    pub const EXIST: u32 = 17;
+    pub const XDEV: u32 = 18;
+    pub const NOTDIR: u32 = 20;
+    pub const ISDIR: u32 = 21;
+    pub const INVAL: u32 = 22;
+    pub const FBIG: u32 = 27;
+    pub const NOSPC: u32 = 28;
+    pub const ROFS: u32 = 30;
+    pub const NAMETOOLONG: u32 = 63;
+    pub const NOTEMPTY: u32 = 66;
+    pub const DQUOT: u32 = 69;
+    pub const STALE: u32 = 70;
+    pub const BADHANDLE: u32 = 10001;
+    pub const NOTSUPP: u32 = 10004;
+    pub const JUKEBOX: u32 = 10008;
+}
+
+const AUTH_NONE: u32 = 0;
+const AUTH_SYS: u32 = 1;
+
+/// stable_how: data reaches stable storage before WRITE returns
+const FILE_SYNC: u32 = 2;
+
+/// createmode3
+const UNCHECKED: u32 = 0;
+const GUARDED: u32 = 1;
+
+/// time_how of sattr3
+const SET_TO_CLIENT_TIME: u32 = 2;
 
 /// NFS version
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum NfsVersion {
+    /// The version the userspace client speaks
+    #[default]
     V3,
+    /// Only through `mount_system` for now
     V4,
+    /// Only through `mount_system` for now
     V41,
 }
 
-impl Default for NfsVersion {
-    fn default() -> Self {
-        Self::V4
-    }
-}
-
 /// NFS authentication flavor
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NfsAuth {
     /// AUTH_SYS (Unix authentication)
-    Sys { uid: u32, gid: u32, gids: Vec<u32> },
+    Sys {
+        uid: u32,
+        gid: u32,
+        #[serde(default)]
+        gids: Vec<u32>,
+    },
     /// AUTH_NONE
     None,
-    /// RPCSEC_GSS (Kerberos)
+    /// RPCSEC_GSS (Kerberos; not supported yet)
     Gss { principal: String },
 }
 
@@ -47,24 +163,46 @@ fn default() -> Self {
 }
 
 /// NFS backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NfsConfig {
     /// Server hostname or IP
     pub server: String,
     /// Export path
     pub export: String,
     /// NFS version
+    #[serde(default)]
     pub version: NfsVersion,
     /// Authentication
+    #[serde(default)]
     pub auth: NfsAuth,
-    /// Read size (NFSv3: 65536, NFSv4: 1MB)
+    /// Largest read, further capped by the server (default: 1MB)
+    #[serde(default = "default_io_size")]
     pub rsize: u32,
-    /// Write size
+    /// Largest write, further capped by the server (default: 1MB)
+    #[serde(default = "default_io_size")]
     pub wsize: u32,
-    /// Use TCP (vs UDP for NFSv3)
+    /// Use TCP; the userspace client does not speak UDP
+    #[serde(default = "default_true")]
     pub tcp: bool,
-    /// Port (0 = use portmapper/rpcbind)
+    /// NFS port (0 = ask portmapper/rpcbind)
+    #[serde(default = "default_port")]
     pub port: u16,
+    /// MOUNT port (0 = ask portmapper/rpcbind)
+    #[serde(default)]
+    pub mount_port: u16,
+}
+
+fn default_io_size() -> u32 {
+    1048576 // 1MB
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_port() -> u16 {
+    2049
 }
 
 impl Default for NfsConfig {
@@ -72,33 +210,608 @@ fn default() -> Self {
         Self {
             server: "localhost".to_string(),
             export: "/".to_string(),
-            version: NfsVersion::V4,
+            version: NfsVersion::default(),
             auth: NfsAuth::default(),
-            rsize: 1048576,  // 1MB
-            wsize: 1048576,
+            rsize: default_io_size(),
+            wsize: default_io_size(),
             tcp: true,
-            port: 2049,
+            port: default_port(),
+            mount_port: 0,
         }
     }
 }
 
-/// NFS file handle
-#[derive(Debug, Clone, Default)]
-struct NfsFileHandle {
-    data: Vec<u8>,
+/// An export the server offers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NfsExport {
+    pub path: String,
+    /// Hosts or groups allowed to mount it; empty for everyone
+    pub groups: Vec<String>,
+}
+
+/// Append XDR items: big-endian, padded to four bytes
+trait PutXdr {
+    fn put_u32(&mut self, v: u32);
+    fn put_u64(&mut self, v: u64);
+    fn put_bool(&mut self, v: bool);
+    fn put_opaque(&mut self, data: &[u8]);
+}
+
+impl PutXdr for Vec<u8> {
+    fn put_u32(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn put_u64(&mut self, v: u64) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn put_bool(&mut self, v: bool) {
+        self.put_u32(v as u32);
+    }
+
+    /// Variable-length opaque data or string
+    fn put_opaque(&mut self, data: &[u8]) {
+        self.put_u32(data.len() as u32);
+        self.extend_from_slice(data);
+        self.resize(self.len().next_multiple_of(4), 0);
+    }
+}
+
+/// Bounds-checked XDR decoding
+struct Xdr<'a> {
+    buf: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Xdr<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, at: 0 }
+    }
+
+    fn fixed(&mut self, len: usize) -> CfkResult<&'a [u8]> {
+        let end = self.at.checked_add(len).ok_or_else(malformed)?;
+        let data = self.buf.get(self.at..end).ok_or_else(malformed)?;
+        self.at = end.next_multiple_of(4);
+        Ok(data)
+    }
+
+    fn u32(&mut self) -> CfkResult<u32> {
+        Ok(u32::from_be_bytes(self.fixed(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> CfkResult<u64> {
+        Ok(u64::from_be_bytes(self.fixed(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> CfkResult<bool> {
+        Ok(self.u32()? != 0)
+    }
+
+    fn opaque(&mut self) -> CfkResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.fixed(len)
+    }
+
+    fn string(&mut self) -> CfkResult<String> {
+        Ok(String::from_utf8_lossy(self.opaque()?).into_owned())
+    }
+
+    /// An optional item, such as post_op_attr, decoded by `item`
+    fn optional<T>(
+        &mut self,
+        item: impl FnOnce(&mut Self) -> CfkResult<T>,
+    ) -> CfkResult<Option<T>> {
+        if self.bool()? {
+            item(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn malformed() -> CfkError {
+    CfkError::ProviderApi {
+        provider: "nfs".into(),
+        message: "malformed reply from server".into(),
+    }
+}
+
+/// A directory handle with a name in it, as most NFS procedures take
+fn put_where(args: &mut Vec<u8>, dir: &[u8], name: &str) {
+    args.put_opaque(dir);
+    args.put_opaque(name.as_bytes());
+}
+
+/// Attributes to set, as sattr3
+#[derive(Default)]
+struct SetAttrs {
+    mode: Option<u32>,
+    size: Option<u64>,
+    atime: Option<(u32, u32)>,
+    mtime: Option<(u32, u32)>,
+}
+
+impl SetAttrs {
+    fn mode(mode: u32) -> Self {
+        Self {
+            mode: Some(mode),
+            ..Default::default()
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.put_bool(self.mode.is_some());
+        if let Some(mode) = self.mode {
+            out.put_u32(mode);
+        }
+        out.put_bool(false); // uid
+        out.put_bool(false); // gid
+        out.put_bool(self.size.is_some());
+        if let Some(size) = self.size {
+            out.put_u64(size);
+        }
+        for time in [self.atime, self.mtime] {
+            match time {
+                Some((secs, nanos)) => {
+                    out.put_u32(SET_TO_CLIENT_TIME);
+                    out.put_u32(secs);
+                    out.put_u32(nanos);
+                }
+                None => out.put_u32(0),
+            }
+        }
+    }
+}
+
+/// An RPC client on one TCP connection
+struct Rpc {
+    stream: TcpStream,
+    xid: u32,
+    /// Encoded credential sent with every call
+    credential: Vec<u8>,
+}
+
+impl Rpc {
+    async fn connect(server: &str, port: u16, credential: Vec<u8>) -> CfkResult<Self> {
+        let stream = TcpStream::connect((server, port)).await.map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+        Ok(Self {
+            stream,
+            xid: fastrand::u32(..),
+            credential,
+        })
+    }
+
+    /// Call `procedure` and return the procedure's results
+    async fn call(
+        &mut self,
+        program: u32,
+        version: u32,
+        procedure: u32,
+        args: &[u8],
+    ) -> CfkResult<Vec<u8>> {
+        self.xid = self.xid.wrapping_add(1);
+        let mut record = vec![0; 4];
+        record.put_u32(self.xid);
+        record.put_u32(0); // CALL
+        record.put_u32(RPC_VERSION);
+        record.put_u32(program);
+        record.put_u32(version);
+        record.put_u32(procedure);
+        record.extend_from_slice(&self.credential);
+        record.put_u32(AUTH_NONE); // verifier
+        record.put_u32(0);
+        record.extend_from_slice(args);
+        // One fragment, marked last
+        let len = (record.len() - 4) as u32 | 0x8000_0000;
+        record[..4].copy_from_slice(&len.to_be_bytes());
+        self.stream.write_all(&record).await.map_err(io_error)?;
+
+        let reply = tokio::time::timeout(REQUEST_TIMEOUT, self.read_record())
+            .await
+            .map_err(|_| CfkError::Timeout)??;
+        reply_results(&reply, self.xid, program, version, procedure)
+    }
+
+    /// One RPC record, reassembled from its fragments
+    async fn read_record(&mut self) -> CfkResult<Vec<u8>> {
+        let mut record = Vec::new();
+        loop {
+            let mut header = [0; 4];
+            self.stream
+                .read_exact(&mut header)
+                .await
+                .map_err(io_error)?;
+            let header = u32::from_be_bytes(header);
+            let len = (header & 0x7fff_ffff) as usize;
+            if record.len() + len > MAX_RECORD {
+                return Err(malformed());
+            }
+            let start = record.len();
+            record.resize(start + len, 0);
+            self.stream
+                .read_exact(&mut record[start..])
+                .await
+                .map_err(io_error)?;
+            if header & 0x8000_0000 != 0 {
+                return Ok(record);
+            }
+        }
+    }
+}
+
+/// The results of an accepted reply to call `xid`, or the error a denied or
+/// unsuccessful call stands for
+fn reply_results(
+    reply: &[u8],
+    xid: u32,
+    program: u32,
+    version: u32,
+    procedure: u32,
+) -> CfkResult<Vec<u8>> {
+    let mut xdr = Xdr::new(reply);
+    if xdr.u32()? != xid || xdr.u32()? != 1 {
+        return Err(malformed());
+    }
+
+    let name = program_name(program);
+    if xdr.u32()? != 0 {
+        // MSG_DENIED
+        return Err(match xdr.u32()? {
+            0 => {
+                CfkError::Unsupported(format!("server does not speak RPC version {}", RPC_VERSION))
+            }
+            _ => auth_error(xdr.u32()?, name),
+        });
+    }
+    let _verifier_flavor = xdr.u32()?;
+    xdr.opaque()?;
+    match xdr.u32()? {
+        0 => Ok(reply[xdr.at..].to_vec()),
+        1 => Err(CfkError::Unsupported(format!(
+            "server does not offer {}",
+            name
+        ))),
+        2 => Err(CfkError::Unsupported(format!(
+            "server offers {} versions {} to {}, not {}",
+            name,
+            xdr.u32()?,
+            xdr.u32()?,
+            version
+        ))),
+        3 => Err(CfkError::Unsupported(format!(
+            "{} procedure {} is not available",
+            name, procedure
+        ))),
+        status => Err(CfkError::ProviderApi {
+            provider: "nfs".into(),
+            message: format!("{} call failed with accept status {}", name, status),
+        }),
+    }
+}
+
+fn program_name(program: u32) -> &'static str {
+    match program {
+        prog::PORTMAP => "portmapper",
+        prog::MOUNT => "MOUNT v3",
+        prog::NFS => "NFS v3",
+        _ => "RPC program",
+    }
+}
+
+/// Convert a rejected credential
+fn auth_error(status: u32, name: &str) -> CfkError {
+    let reason = match status {
+        1 => "bad credential",
+        2 => "credential rejected",
+        3 | 4 => "bad verifier",
+        5 => "authentication too weak",
+        _ => "authentication failed",
+    };
+    CfkError::AuthFailed(format!("{}: {}", name, reason))
+}
+
+/// An export mounted and its NFS connection
+struct Connection {
+    rpc: Mutex<Rpc>,
+    root: Vec<u8>,
+    rsize: u32,
+    wsize: u32,
+    broken: AtomicBool,
+}
+
+impl Connection {
+    /// Call an NFS procedure about `what`, returning the results after a
+    /// successful status. A transport failure, or a handle the server no
+    /// longer knows, leaves the connection to be replaced.
+    async fn call(&self, procedure: u32, args: &[u8], what: impl Display) -> CfkResult<Vec<u8>> {
+        let result = self
+            .rpc
+            .lock()
+            .await
+            .call(prog::NFS, prog::NFS_VERSION, procedure, args)
+            .await;
+        let mut results = match result {
+            Ok(results) => results,
+            Err(e) => {
+                self.broken.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        let status = Xdr::new(&results).u32()?;
+        if matches!(status, nfsstat::STALE | nfsstat::BADHANDLE) {
+            self.broken.store(true, Ordering::Relaxed);
+        }
+        if status != nfsstat::OK {
+            return Err(status_error(status, what));
+        }
+        results.drain(..4);
+        Ok(results)
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+    }
+
+    async fn getattr(&self, fh: &[u8], what: impl Display) -> CfkResult<NfsAttributes> {
+        let mut args = Vec::new();
+        args.put_opaque(fh);
+        let results = self.call(proc3::GETATTR, &args, what).await?;
+        NfsAttributes::decode(&mut Xdr::new(&results))
+    }
+
+    /// The handle and attributes of `name` in directory `dir`
+    async fn lookup(&self, dir: &[u8], name: &str, what: impl Display) -> CfkResult<Found> {
+        let mut args = Vec::new();
+        put_where(&mut args, dir, name);
+        let what = what.to_string();
+        let results = self.call(proc3::LOOKUP, &args, &what).await?;
+        let mut xdr = Xdr::new(&results);
+        let fh = xdr.opaque()?.to_vec();
+        let attrs = match xdr.optional(NfsAttributes::decode)? {
+            Some(attrs) => attrs,
+            None => self.getattr(&fh, &what).await?,
+        };
+        Ok(Found { fh, attrs })
+    }
+
+    /// Up to `count` bytes at `offset`, and whether the file ends there
+    async fn read(
+        &self,
+        fh: &[u8],
+        offset: u64,
+        count: u32,
+        what: impl Display,
+    ) -> CfkResult<(Bytes, bool)> {
+        let mut args = Vec::new();
+        args.put_opaque(fh);
+        args.put_u64(offset);
+        args.put_u32(count.min(self.rsize));
+        let results = self.call(proc3::READ, &args, what).await?;
+        decode_read(&results)
+    }
+
+    /// Write all of `data` at `offset`, in as many requests as it takes
+    async fn write_all(
+        &self,
+        fh: &[u8],
+        mut offset: u64,
+        mut data: &[u8],
+        what: impl Display,
+    ) -> CfkResult<()> {
+        let what = what.to_string();
+        while !data.is_empty() {
+            let len = data.len().min(self.wsize as usize);
+            let mut args = Vec::with_capacity(len + 64);
+            args.put_opaque(fh);
+            args.put_u64(offset);
+            args.put_u32(len as u32);
+            args.put_u32(FILE_SYNC);
+            args.put_opaque(&data[..len]);
+
+            let results = self.call(proc3::WRITE, &args, &what).await?;
+            let mut xdr = Xdr::new(&results);
+            skip_wcc(&mut xdr)?;
+            let written = xdr.u32()? as usize;
+            if written == 0 || written > len {
+                return Err(malformed());
+            }
+            offset += written as u64;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
+    /// Every entry of directory `dir` but `.` and `..`, with handles where
+    /// the server includes them
+    async fn read_dir(
+        &self,
+        dir: &[u8],
+        what: impl Display,
+    ) -> CfkResult<Vec<(String, Option<Found>)>> {
+        let what = what.to_string();
+        let mut entries = Vec::new();
+        let mut cookie = 0;
+        let mut verifier = [0; 8];
+        loop {
+            let mut args = Vec::new();
+            args.put_opaque(dir);
+            args.put_u64(cookie);
+            args.extend_from_slice(&verifier);
+            args.put_u32(DIR_COUNT);
+            args.put_u32(DIR_MAX_COUNT.min(self.rsize));
+
+            let results = self.call(proc3::READDIRPLUS, &args, &what).await?;
+            let page = decode_dir_page(&results)?;
+            entries.extend(page.entries);
+            if page.eof {
+                return Ok(entries);
+            }
+            // Asking again from the same cookie would get the same page
+            cookie = page.cookie.ok_or_else(malformed)?;
+            verifier = page.verifier;
+        }
+    }
+
+    /// Create a file or directory `name` in `dir`
+    async fn create(
+        &self,
+        procedure: u32,
+        dir: &[u8],
+        name: &str,
+        how: &[u8],
+        what: impl Display,
+    ) -> CfkResult<Found> {
+        let mut args = Vec::new();
+        put_where(&mut args, dir, name);
+        args.extend_from_slice(how);
+        let what = what.to_string();
+        let results = self.call(procedure, &args, &what).await?;
+        let mut xdr = Xdr::new(&results);
+        let fh = xdr.optional(|x| x.opaque().map(<[u8]>::to_vec))?;
+        let attrs = xdr.optional(NfsAttributes::decode)?;
+        match (fh, attrs) {
+            (Some(fh), Some(attrs)) => Ok(Found { fh, attrs }),
+            (Some(fh), None) => {
+                let attrs = self.getattr(&fh, &what).await?;
+                Ok(Found { fh, attrs })
+            }
+            (None, _) => self.lookup(dir, name, &what).await,
+        }
+    }
+
+    async fn remove(
+        &self,
+        dir: &[u8],
+        name: &str,
+        is_dir: bool,
+        what: impl Display,
+    ) -> CfkResult<()> {
+        let mut args = Vec::new();
+        put_where(&mut args, dir, name);
+        let procedure = if is_dir { proc3::RMDIR } else { proc3::REMOVE };
+        self.call(procedure, &args, what).await.map(|_| ())
+    }
+
+    async fn setattr(&self, fh: &[u8], attrs: &SetAttrs, what: impl Display) -> CfkResult<()> {
+        let mut args = Vec::new();
+        args.put_opaque(fh);
+        attrs.encode(&mut args);
+        args.put_bool(false); // no ctime guard
+        self.call(proc3::SETATTR, &args, what).await.map(|_| ())
+    }
+}
+
+/// Skip a wcc_data: attributes before and after an update
+fn skip_wcc(xdr: &mut Xdr) -> CfkResult<()> {
+    xdr.optional(|x| x.fixed(24).map(|_| ()))?;
+    xdr.optional(NfsAttributes::decode)?;
+    Ok(())
+}
+
+/// A file handle with the attributes of its file
+struct Found {
+    fh: Vec<u8>,
+    attrs: NfsAttributes,
+}
+
+/// Decode READ3resok: the data read, and whether the file ends after it.
+/// The server may return less than was asked for without reaching the end.
+fn decode_read(results: &[u8]) -> CfkResult<(Bytes, bool)> {
+    let mut xdr = Xdr::new(results);
+    xdr.optional(NfsAttributes::decode)?;
+    let count = xdr.u32()?;
+    let eof = xdr.bool()?;
+    let data = xdr.opaque()?;
+    if count as usize != data.len() {
+        return Err(malformed());
+    }
+    Ok((Bytes::copy_from_slice(data), eof))
+}
+
+/// One READDIRPLUS reply
+struct DirPage {
+    /// Every entry but `.` and `..`, with handles where the server
+    /// included them
+    entries: Vec<(String, Option<Found>)>,
+    /// Where the next request continues, if the page had any entries
+    cookie: Option<u64>,
+    verifier: [u8; 8],
+    eof: bool,
+}
+
+/// Decode READDIRPLUS3resok
+fn decode_dir_page(results: &[u8]) -> CfkResult<DirPage> {
+    let mut xdr = Xdr::new(results);
+    xdr.optional(NfsAttributes::decode)?;
+    let verifier = xdr.fixed(8)?.try_into().unwrap();
+    let mut entries = Vec::new();
+    let mut cookie = None;
+    while xdr.bool()? {
+        let _fileid = xdr.u64()?;
+        let name = xdr.string()?;
+        cookie = Some(xdr.u64()?);
+        let attrs = xdr.optional(NfsAttributes::decode)?;
+        let fh = xdr.optional(|x| x.opaque().map(<[u8]>::to_vec))?;
+        if name == "." || name == ".." {
+            continue;
+        }
+        let found = match (fh, attrs) {
+            (Some(fh), Some(attrs)) => Some(Found { fh, attrs }),
+            _ => None,
+        };
+        entries.push((name, found));
+    }
+    Ok(DirPage {
+        entries,
+        cookie,
+        verifier,
+        eof: xdr.bool()?,
+    })
+}
+
+/// Convert an nfsstat3 for an operation on `what`
+fn status_error(status: u32, what: impl Display) -> CfkError {
+    let what = what.to_string();
+    match status {
+        nfsstat::NOENT => CfkError::NotFound(what),
+        nfsstat::PERM | nfsstat::ACCES | nfsstat::ROFS => CfkError::PermissionDenied(what),
+        nfsstat::EXIST => CfkError::AlreadyExists(what),
+        nfsstat::NOTDIR => CfkError::NotADirectory(what),
+        nfsstat::ISDIR => CfkError::NotAFile(what),
+        nfsstat::NOTEMPTY => CfkError::DirectoryNotEmpty(what),
+        nfsstat::INVAL | nfsstat::NAMETOOLONG => CfkError::InvalidPath(what),
+        nfsstat::NOSPC | nfsstat::DQUOT | nfsstat::FBIG => CfkError::QuotaExceeded(what),
+        nfsstat::XDEV => CfkError::Unsupported(format!("{}: crosses a filesystem boundary", what)),
+        nfsstat::NOTSUPP => CfkError::Unsupported(format!("{}: not supported by the server", what)),
+        nfsstat::STALE | nfsstat::BADHANDLE => {
+            CfkError::Network(format!("{}: file handle is no longer valid", what))
+        }
+        nfsstat::JUKEBOX => CfkError::RateLimited {
+            retry_after_secs: Some(1),
+        },
+        _ => CfkError::ProviderApi {
+            provider: "nfs".into(),
+            message: format!("{}: NFS error {}", what, status),
+        },
+    }
+}
+
+/// Convert an error from the TCP stream
+fn io_error(e: std::io::Error) -> CfkError {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => CfkError::Timeout,
+        _ => CfkError::Network(e.to_string()),
+    }
 }
 
 /// NFS storage backend
-///
-/// Note: This is a stub implementation. Full implementation would require
-/// ONC RPC and XDR encoding, which is complex. Consider using `nfs` crate
-/// or system mount.
 pub struct NfsBackend {
     id: String,
     config: NfsConfig,
     capabilities: StorageCapabilities,
-    /// Root file handle (obtained from MOUNT/PUTROOTFH)
-    root_fh: Option<NfsFileHandle>,
+    connection: Mutex<Option<Arc<Connection>>>,
 }
 
 impl NfsBackend {
@@ -111,23 +824,23 @@ pub fn new(id: impl Into<String>, config: NfsConfig) -> Self {
                 write: true,
                 delete: true,
                 rename: true,
-                copy: false,
+                copy: false, // NFSv3 has no server-side copy
                 list: true,
                 search: false,
                 versioning: false,
-                sharing: true, // ACLs
+                sharing: false,
+                offline: false,
                 streaming: true,
-                resume: true,
-                watch: false, // NFSv4.1 has callbacks
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false,
+                watch: false,
             },
-            root_fh: None,
+            connection: Mutex::new(None),
         }
     }
 
-    /// Create from NFS URL: nfs://server/export
+    /// Create from NFS URL: nfs://server[:port]/export
     pub fn from_url(id: impl Into<String>, url: &str) -> CfkResult<Self> {
         let parsed = url::Url::parse(url)
             .map_err(|e| CfkError::InvalidPath(format!("Invalid URL: {}", e)))?;
@@ -141,7 +854,10 @@ pub fn from_url(id: impl Into<String>, url: &str) -> CfkResult<Self> {
             .ok_or_else(|| CfkError::InvalidPath("Missing server".into()))?
             .to_string();
 
-        let export = parsed.path().to_string();
+        let export = match parsed.path() {
+            "" => "/".to_string(),
+            path => path.to_string(),
+        };
         let port = parsed.port().unwrap_or(2049);
 
         Ok(Self::new(
@@ -155,38 +871,376 @@ pub fn from_url(id: impl Into<String>, url: &str) -> CfkResult<Self> {
         ))
     }
 
-    /// Mount the NFS export
-    pub async fn mount(&mut self) -> CfkResult<()> {
-        // In a full implementation:
-        // 1. For NFSv3: Contact portmapper, get MOUNT port, call MOUNT
-        // 2. For NFSv4: Use PUTROOTFH compound operation
+    /// Open an NFS URL naming a path inside an export, returning the backend
+    /// for the export and the path within it. The export is the longest
+    /// one in the server's export list that the URL path starts with.
+    pub async fn open_url(id: impl Into<String>, url: &str) -> CfkResult<(Self, VirtualPath)> {
+        let mut backend = Self::from_url(id, url)?;
+        let full = VirtualPath::new(&backend.id, &backend.config.export);
 
-        match self.config.version {
-            NfsVersion::V3 => {
-                // NFSv3 mount protocol
-                // 1. RPC call to rpcbind to get mount daemon port
-                // 2. RPC MOUNT call to get root file handle
+        // Servers may keep their export list to themselves; then the whole
+        // path is taken to be the export
+        let exports = backend.list_exports().await.unwrap_or_default();
+        let export = exports
+            .iter()
+            .map(|e| VirtualPath::new(&backend.id, &e.path))
+            .filter(|e| full.segments.starts_with(&e.segments))
+            .max_by_key(|e| e.segments.len());
+
+        let path = match export {
+            Some(export) => {
+                backend.config.export = export.to_path_string();
+                VirtualPath {
+                    backend: backend.id.clone(),
+                    segments: full.segments[export.segments.len()..].to_vec(),
+                }
             }
-            NfsVersion::V4 | NfsVersion::V41 => {
-                // NFSv4 uses COMPOUND operations
-                // PUTROOTFH + GETFH to get root handle
+            None => VirtualPath::root(&backend.id),
+        };
+        Ok((backend, path))
+    }
+
+    /// Mount the export, unless already mounted
+    pub async fn connect(&self) -> CfkResult<()> {
+        self.session().await.map(|_| ())
+    }
+
+    /// Drop the connection and tell the server the export is unmounted
+    pub async fn disconnect(&self) -> CfkResult<()> {
+        if self.connection.lock().await.take().is_none() {
+            return Ok(());
+        }
+        let mut args = Vec::new();
+        args.put_opaque(self.config.export.as_bytes());
+        self.mount_call(prog::MOUNTPROC_UMNT, &args)
+            .await
+            .map(|_| ())
+    }
+
+    /// The exports the server offers
+    pub async fn list_exports(&self) -> CfkResult<Vec<NfsExport>> {
+        let results = self.mount_call(prog::MOUNTPROC_EXPORT, &[]).await?;
+        let mut xdr = Xdr::new(&results);
+        let mut exports = Vec::new();
+        while xdr.bool()? {
+            let path = xdr.string()?;
+            let mut groups = Vec::new();
+            while xdr.bool()? {
+                groups.push(xdr.string()?);
             }
+            exports.push(NfsExport { path, groups });
         }
+        Ok(exports)
+    }
 
-        Err(CfkError::Unsupported(
-            "NFS backend is a stub. Use system mount or nfs crate.".into(),
-        ))
+    /// The credential every call carries
+    fn credential(&self) -> CfkResult<Vec<u8>> {
+        let mut credential = Vec::new();
+        match &self.config.auth {
+            NfsAuth::Sys { uid, gid, gids } => {
+                let mut body = Vec::new();
+                body.put_u32(0); // stamp
+                body.put_opaque(b"cfk");
+                body.put_u32(*uid);
+                body.put_u32(*gid);
+                // AUTH_SYS carries at most 16 groups
+                let gids = &gids[..gids.len().min(16)];
+                body.put_u32(gids.len() as u32);
+                for gid in gids {
+                    body.put_u32(*gid);
+                }
+                credential.put_u32(AUTH_SYS);
+                credential.put_opaque(&body);
+            }
+            NfsAuth::None => {
+                credential.put_u32(AUTH_NONE);
+                credential.put_u32(0);
+            }
+            NfsAuth::Gss { .. } => {
+                return Err(CfkError::Unsupported(
+                    "RPCSEC_GSS (Kerberos) is not implemented; use sys".into(),
+                ))
+            }
+        }
+        Ok(credential)
+    }
+
+    /// The port of `program`: configured, or from the portmapper
+    async fn port(&self, configured: u16, program: u32, version: u32) -> CfkResult<u16> {
+        if configured != 0 {
+            return Ok(configured);
+        }
+        let mut portmap = Rpc::connect(&self.config.server, PORTMAP_PORT, {
+            let mut none = Vec::new();
+            none.put_u32(AUTH_NONE);
+            none.put_u32(0);
+            none
+        })
+        .await?;
+        let mut args = Vec::new();
+        args.put_u32(program);
+        args.put_u32(version);
+        args.put_u32(6); // TCP
+        args.put_u32(0);
+        let results = portmap
+            .call(
+                prog::PORTMAP,
+                prog::PORTMAP_VERSION,
+                prog::PMAPPROC_GETPORT,
+                &args,
+            )
+            .await?;
+        match Xdr::new(&results).u32()? {
+            0 => Err(CfkError::Unsupported(format!(
+                "{} is not registered with the portmapper on {}",
+                program_name(program),
+                self.config.server
+            ))),
+            port => u16::try_from(port).map_err(|_| malformed()),
+        }
     }
 
-    /// Convert VirtualPath to NFS path components
-    fn to_path_components(&self, path: &VirtualPath) -> Vec<String> {
-        path.segments.clone()
+    /// Call a MOUNT procedure over a connection of its own
+    async fn mount_call(&self, procedure: u32, args: &[u8]) -> CfkResult<Vec<u8>> {
+        let port = self
+            .port(self.config.mount_port, prog::MOUNT, prog::MOUNT_VERSION)
+            .await?;
+        let mut rpc = Rpc::connect(&self.config.server, port, self.credential()?).await?;
+        rpc.call(prog::MOUNT, prog::MOUNT_VERSION, procedure, args)
+            .await
     }
 
-    /// Lookup a path and return file handle
-    async fn lookup(&self, _path: &VirtualPath) -> CfkResult<NfsFileHandle> {
-        // Would use LOOKUP (v3) or LOOKUP in COMPOUND (v4)
-        Err(CfkError::Unsupported("NFS stub".into()))
+    /// The live connection, mounting the export again if there is none
+    async fn session(&self) -> CfkResult<Arc<Connection>> {
+        let mut connection = self.connection.lock().await;
+        if let Some(conn) = connection.as_ref() {
+            if !conn.is_broken() {
+                return Ok(conn.clone());
+            }
+            tracing::debug!(
+                "nfs connection to {} dropped, reconnecting",
+                self.config.server
+            );
+        }
+
+        let conn = Arc::new(
+            tokio::time::timeout(CONNECT_TIMEOUT, self.open_connection())
+                .await
+                .map_err(|_| CfkError::Timeout)??,
+        );
+        *connection = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Mount the export and connect to the NFS service
+    async fn open_connection(&self) -> CfkResult<Connection> {
+        if self.config.version != NfsVersion::V3 {
+            return Err(CfkError::Unsupported(
+                "the userspace NFS client speaks NFSv3 only; set version = \"v3\" \
+                 or use mount_system for NFSv4"
+                    .into(),
+            ));
+        }
+        if !self.config.tcp {
+            return Err(CfkError::Unsupported(
+                "the userspace NFS client does not speak UDP".into(),
+            ));
+        }
+        let credential = self.credential()?;
+
+        let mut args = Vec::new();
+        args.put_opaque(self.config.export.as_bytes());
+        let results = self.mount_call(prog::MOUNTPROC_MNT, &args).await?;
+        let mut xdr = Xdr::new(&results);
+        let export = format!("{}:{}", self.config.server, self.config.export);
+        match xdr.u32()? {
+            0 => {}
+            nfsstat::PERM | nfsstat::ACCES => return Err(CfkError::PermissionDenied(export)),
+            status => return Err(status_error(status, export)),
+        }
+        let root = xdr.opaque()?.to_vec();
+        let flavor = credential[..4].try_into().map(u32::from_be_bytes).unwrap();
+        let flavors = (0..xdr.u32()?)
+            .map(|_| xdr.u32())
+            .collect::<CfkResult<Vec<u32>>>()?;
+        if !flavors.is_empty() && !flavors.contains(&flavor) {
+            return Err(CfkError::AuthFailed(format!(
+                "{} does not accept {} credentials (it takes flavors {:?})",
+                export,
+                if flavor == AUTH_SYS { "sys" } else { "none" },
+                flavors
+            )));
+        }
+
+        let port = self
+            .port(self.config.port, prog::NFS, prog::NFS_VERSION)
+            .await?;
+        let mut conn = Connection {
+            rpc: Mutex::new(Rpc::connect(&self.config.server, port, credential).await?),
+            root,
+            rsize: self.config.rsize.max(4096),
+            wsize: self.config.wsize.max(4096),
+            broken: AtomicBool::new(false),
+        };
+
+        // The server's transfer limits
+        let mut args = Vec::new();
+        args.put_opaque(&conn.root);
+        let results = conn.call(proc3::FSINFO, &args, &export).await?;
+        let mut xdr = Xdr::new(&results);
+        xdr.optional(NfsAttributes::decode)?;
+        let rtmax = xdr.u32()?;
+        let _rtpref = xdr.u32()?;
+        let _rtmult = xdr.u32()?;
+        let wtmax = xdr.u32()?;
+        if rtmax > 0 {
+            conn.rsize = conn.rsize.min(rtmax);
+        }
+        if wtmax > 0 {
+            conn.wsize = conn.wsize.min(wtmax);
+        }
+        Ok(conn)
+    }
+
+    /// Walk `path` down from the export root, one LOOKUP per segment
+    async fn lookup(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<Found> {
+        let mut found = Found {
+            fh: conn.root.clone(),
+            attrs: conn.getattr(&conn.root, path).await?,
+        };
+        for segment in &path.segments {
+            if segment == "." || segment == ".." {
+                return Err(CfkError::InvalidPath(path.to_string()));
+            }
+            found = conn.lookup(&found.fh, segment, path).await?;
+        }
+        Ok(found)
+    }
+
+    /// The handle of the directory holding `path`, and its name there
+    async fn parent<'p>(
+        &self,
+        conn: &Connection,
+        path: &'p VirtualPath,
+    ) -> CfkResult<(Found, &'p str)> {
+        let (Some(parent), Some(name)) = (path.parent(), path.name()) else {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        };
+        let dir = self.lookup(conn, &parent).await.map_err(|e| match e {
+            CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+            e => e,
+        })?;
+        Ok((dir, name))
+    }
+
+    fn entry(&self, path: &VirtualPath, attrs: &NfsAttributes) -> Entry {
+        attrs.to_entry(&self.id, &path.to_path_string())
+    }
+
+    /// The entries of one directory, with their handles
+    async fn children(
+        &self,
+        conn: &Connection,
+        dir: &VirtualPath,
+        fh: &[u8],
+    ) -> CfkResult<Vec<(Entry, Vec<u8>)>> {
+        let mut children = Vec::new();
+        for (name, found) in conn.read_dir(fh, dir).await? {
+            let path = dir.join(&name);
+            let found = match found {
+                Some(found) => found,
+                None => conn.lookup(fh, &name, &path).await?,
+            };
+            children.push((self.entry(&path, &found.attrs), found.fh));
+        }
+        Ok(children)
+    }
+
+    /// Create every missing ancestor of `path`, returning the handle of its
+    /// parent
+    async fn ensure_parents(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<Found> {
+        let mut dir = Found {
+            fh: conn.root.clone(),
+            attrs: conn.getattr(&conn.root, path).await?,
+        };
+        let Some(parent) = path.parent() else {
+            return Ok(dir);
+        };
+        let mut how = Vec::new();
+        SetAttrs::mode(DIR_MODE).encode(&mut how);
+        for (i, segment) in parent.segments.iter().enumerate() {
+            let at = VirtualPath {
+                backend: path.backend.clone(),
+                segments: parent.segments[..=i].to_vec(),
+            };
+            dir = match conn.lookup(&dir.fh, segment, &at).await {
+                Ok(found) => found,
+                Err(CfkError::NotFound(_)) => {
+                    match conn.create(proc3::MKDIR, &dir.fh, segment, &how, &at).await {
+                        // Created concurrently
+                        Err(CfkError::AlreadyExists(_)) => {
+                            conn.lookup(&dir.fh, segment, &at).await?
+                        }
+                        result => result?,
+                    }
+                }
+                Err(e) => return Err(e),
+            };
+        }
+        Ok(dir)
+    }
+
+    /// Stream `stream` into `path`, honouring `overwrite` and `create_parents`
+    async fn put(
+        &self,
+        path: &VirtualPath,
+        mut stream: ByteStream,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        let name = path
+            .name()
+            .ok_or_else(|| CfkError::NotAFile(path.to_string()))?;
+        let dir = if options.create_parents {
+            self.ensure_parents(&conn, path).await?
+        } else {
+            self.parent(&conn, path).await?.0
+        };
+
+        // UNCHECKED with a zero size truncates a file that is already there
+        let mut how = Vec::new();
+        let attrs = SetAttrs {
+            mode: Some(FILE_MODE),
+            size: options.overwrite.then_some(0),
+            ..Default::default()
+        };
+        how.put_u32(if options.overwrite {
+            UNCHECKED
+        } else {
+            GUARDED
+        });
+        attrs.encode(&mut how);
+        let file = conn
+            .create(proc3::CREATE, &dir.fh, name, &how, path)
+            .await?;
+        if file.attrs.file_type == NfsFileType::Directory as u32 {
+            return Err(CfkError::NotAFile(path.to_string()));
+        }
+
+        let mut offset = 0;
+        let mut pending = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            pending.extend_from_slice(&chunk?);
+            if pending.len() >= conn.wsize as usize {
+                conn.write_all(&file.fh, offset, &pending, path).await?;
+                offset += pending.len() as u64;
+                pending.clear();
+            }
+        }
+        conn.write_all(&file.fh, offset, &pending, path).await?;
+
+        let attrs = conn.getattr(&file.fh, path).await?;
+        Ok(self.entry(path, &attrs))
     }
 }
 
@@ -209,71 +1263,267 @@ fn capabilities(&self) -> &StorageCapabilities {
     }
 
     async fn is_available(&self) -> bool {
-        self.root_fh.is_some()
+        self.session().await.is_ok()
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let _fh = self.lookup(path).await?;
-        // Would use GETATTR operation
-
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+        let conn = self.session().await?;
+        let found = self.lookup(&conn, path).await?;
+        Ok(self.entry(path, &found.attrs))
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let _fh = self.lookup(path).await?;
-        // Would use READDIR/READDIRPLUS (v3) or READDIR in COMPOUND (v4)
+    /// READDIRPLUS brings attributes and handles along with the names, so
+    /// a listing costs no LOOKUPs. The cursor is an offset into the full
+    /// listing. Symlinks are listed, not followed.
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let conn = self.session().await?;
+        let root = self.lookup(&conn, path).await?;
+        let mut entries = Vec::new();
+        let mut pending = vec![(path.clone(), root.fh)];
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
-    }
+        while let Some((dir, fh)) = pending.pop() {
+            // Servers return entries in no particular order; sort so that
+            // offsets stay stable between pages
+            let mut children = self.children(&conn, &dir, &fh).await?;
+            children.sort_by(|a, b| a.0.path.segments.cmp(&b.0.path.segments));
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let _fh = self.lookup(path).await?;
-        // Would use READ operation with offset/count
+            if options.recursive {
+                pending.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .filter(|(e, _)| e.is_directory())
+                        .filter(|(e, _)| {
+                            options.include_hidden
+                                || !e.path.name().is_some_and(|n| n.starts_with('.'))
+                        })
+                        .map(|(e, fh)| (e.path.clone(), fh.clone())),
+                );
+            }
+            entries.extend(children.into_iter().map(|(e, _)| e));
+        }
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+        Ok(common::page_entries(path, entries, options))
     }
 
-    async fn write_file(&self, path: &VirtualPath, _data: Bytes) -> CfkResult<Entry> {
-        // Would use CREATE + WRITE operations
-        let _components = self.to_path_components(path);
-
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let conn = self.session().await?;
+        let file = self.lookup(&conn, path).await?;
+        if file.attrs.file_type == NfsFileType::Directory as u32 {
+            return Err(CfkError::NotAFile(path.to_string()));
+        }
+        let (start, limit) = match options.range {
+            Some((start, end)) => (start, end.saturating_sub(start)),
+            None => (0, u64::MAX),
+        };
+        Ok(file_stream(conn, file.fh, path.clone(), start, limit))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let _fh = self.lookup(path).await?;
-        // Would use REMOVE (file) or RMDIR (directory)
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let stream: ByteStream = Box::pin(futures::stream::once(async { Ok(data) }));
+        self.put(path, stream, options).await
+    }
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.put(path, stream, options).await
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        // Would use MKDIR operation
-        let _components = self.to_path_components(path);
+        let conn = self.session().await?;
+        let (dir, name) = self.parent(&conn, path).await?;
+        let mut how = Vec::new();
+        SetAttrs::mode(DIR_MODE).encode(&mut how);
+        let found = conn.create(proc3::MKDIR, &dir.fh, name, &how, path).await?;
+        Ok(self.entry(path, &found.attrs))
+    }
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if path.is_root() {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        }
+
+        let conn = self.session().await?;
+        let result = async {
+            let (dir, name) = self.parent(&conn, path).await?;
+            // LOOKUP does not follow links, so a link is removed itself
+            let target = conn.lookup(&dir.fh, name, path).await?;
+            let is_dir = target.attrs.file_type == NfsFileType::Directory as u32;
+
+            if is_dir && options.recursive {
+                // Parents are listed before their children, so removing in
+                // reverse empties each directory before it goes
+                let mut doomed = Vec::new();
+                let mut pending = vec![(path.clone(), target.fh)];
+                while let Some((dir, fh)) = pending.pop() {
+                    for (entry, child) in self.children(&conn, &dir, &fh).await? {
+                        if entry.is_directory() {
+                            pending.push((entry.path.clone(), child));
+                        }
+                        doomed.push((fh.clone(), entry));
+                    }
+                }
+                for (parent, entry) in doomed.iter().rev() {
+                    let name = entry.path.name().unwrap_or_default();
+                    conn.remove(parent, name, entry.is_directory(), &entry.path)
+                        .await?;
+                }
+            }
+            conn.remove(&dir.fh, name, is_dir, path).await
+        }
+        .await;
+
+        common::ignore_missing(result, options)
     }
 
-    async fn copy(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        // NFS doesn't have native copy (until NFSv4.2 COPY operation)
-        Err(CfkError::Unsupported("NFS doesn't support native copy".into()))
+    /// There is no server-side copy in NFSv3, so the data passes through
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        let entry = self.get_metadata(source).await?;
+        if entry.is_directory() {
+            return Err(CfkError::NotAFile(source.to_string()));
+        }
+
+        let stream = self.read_file(source, &ReadOptions::default()).await?;
+        let write = WriteOptions {
+            overwrite: options.overwrite,
+            ..Default::default()
+        };
+        let copied = self.put(dest, stream, &write).await?;
+
+        if options.preserve_metadata {
+            self.set_metadata(dest, &entry.metadata).await?;
+            return self.get_metadata(dest).await;
+        }
+        Ok(copied)
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        // Would use RENAME operation
-        let _from_components = self.to_path_components(from);
-        let _to_components = self.to_path_components(to);
+    /// NFS rename replaces an existing target, so without `overwrite` the
+    /// target is checked for first
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        let (from, from_name) = self.parent(&conn, source).await?;
+        let (to, to_name) = self.parent(&conn, dest).await?;
+        if !options.overwrite {
+            match conn.lookup(&to.fh, to_name, dest).await {
+                Ok(_) => return Err(CfkError::AlreadyExists(dest.to_string())),
+                Err(CfkError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut args = Vec::new();
+        put_where(&mut args, &from.fh, from_name);
+        put_where(&mut args, &to.fh, to_name);
+        conn.call(proc3::RENAME, &args, source).await?;
+
+        let found = conn.lookup(&to.fh, to_name, dest).await?;
+        Ok(self.entry(dest, &found.attrs))
+    }
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        let conn = self.session().await?;
+        let mut args = Vec::new();
+        args.put_opaque(&conn.root);
+        let results = conn.call(proc3::FSSTAT, &args, &self.config.export).await?;
+        let mut xdr = Xdr::new(&results);
+        xdr.optional(NfsAttributes::decode)?;
+        let total = xdr.u64()?;
+        let free = xdr.u64()?;
+        let available = xdr.u64()?;
+        Ok(SpaceInfo {
+            total: Some(total),
+            used: Some(total.saturating_sub(free)),
+            available: Some(available),
+        })
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        // Would use FSSTAT (v3) or GETATTR with fsinfo (v4)
+    /// Sets permissions and access and modification times
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        let time = |t: Option<chrono::DateTime<chrono::Utc>>| {
+            t.map(|t| {
+                (
+                    t.timestamp().clamp(0, u32::MAX as i64) as u32,
+                    t.timestamp_subsec_nanos(),
+                )
+            })
+        };
+        let attrs = SetAttrs {
+            mode: metadata.permissions.map(|p| p.mode & 0o7777),
+            size: None,
+            atime: time(metadata.accessed),
+            mtime: time(metadata.modified),
+        };
+        if attrs.mode.is_none() && attrs.atime.is_none() && attrs.mtime.is_none() {
+            return Ok(());
+        }
 
-        Err(CfkError::Unsupported("NFS stub - use system mount".into()))
+        let conn = self.session().await?;
+        let found = self.lookup(&conn, path).await?;
+        conn.setattr(&found.fh, &attrs, path).await
     }
 }
 
+/// Stream up to `limit` bytes of a file from `offset`, holding on to the
+/// connection
+fn file_stream(
+    conn: Arc<Connection>,
+    fh: Vec<u8>,
+    path: VirtualPath,
+    offset: u64,
+    limit: u64,
+) -> ByteStream {
+    Box::pin(futures::stream::try_unfold(
+        (conn, fh, path, offset, limit, false),
+        |(conn, fh, path, offset, remaining, eof)| async move {
+            if remaining == 0 || eof {
+                return Ok(None);
+            }
+            let count = remaining.min(u32::MAX as u64) as u32;
+            let (chunk, eof) = conn.read(&fh, offset, count, &path).await?;
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+            let len = chunk.len() as u64;
+            let chunk = chunk.slice(..len.min(remaining) as usize);
+            Ok(Some((
+                chunk,
+                (
+                    conn,
+                    fh,
+                    path,
+                    offset + len,
+                    remaining.saturating_sub(len),
+                    eof,
+                ),
+            )))
+        },
+    ))
+}
+
 /// NFS file types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NfsFileType {
@@ -307,25 +1557,62 @@ pub struct NfsAttributes {
 }
 
 impl NfsAttributes {
+    /// Decode an fattr3
+    fn decode(xdr: &mut Xdr) -> CfkResult<Self> {
+        let file_type = xdr.u32()?;
+        let mode = xdr.u32()?;
+        let nlink = xdr.u32()?;
+        let uid = xdr.u32()?;
+        let gid = xdr.u32()?;
+        let size = xdr.u64()?;
+        let used = xdr.u64()?;
+        let _rdev = xdr.u64()?;
+        Ok(Self {
+            file_type,
+            mode,
+            nlink,
+            uid,
+            gid,
+            size,
+            used,
+            fsid: xdr.u64()?,
+            fileid: xdr.u64()?,
+            atime_sec: xdr.u32()?,
+            atime_nsec: xdr.u32()?,
+            mtime_sec: xdr.u32()?,
+            mtime_nsec: xdr.u32()?,
+            ctime_sec: xdr.u32()?,
+            ctime_nsec: xdr.u32()?,
+        })
+    }
+
+    /// Owner and group go into `custom` as `uid` and `gid`
     pub fn to_entry(&self, backend_id: &str, path: &str) -> Entry {
         let kind = match self.file_type {
+            1 => EntryKind::File,
             2 => EntryKind::Directory,
             5 => EntryKind::Symlink,
-            _ => EntryKind::File,
+            _ => EntryKind::Unknown,
         };
 
-        let mut metadata = Metadata::default();
-        metadata.size = Some(self.size);
-        metadata.permissions = Some(self.mode);
-        metadata.uid = Some(self.uid);
-        metadata.gid = Some(self.gid);
-
-        if self.mtime_sec > 0 {
-            metadata.modified = chrono::DateTime::from_timestamp(
-                self.mtime_sec as i64,
-                self.mtime_nsec,
-            );
-        }
+        let timestamp = |secs: u32, nanos: u32| {
+            (secs > 0)
+                .then(|| chrono::DateTime::from_timestamp(secs as i64, nanos))
+                .flatten()
+        };
+        let mut metadata = Metadata {
+            size: Some(self.size),
+            permissions: Some(Permissions::new(self.mode)),
+            modified: timestamp(self.mtime_sec, self.mtime_nsec),
+            accessed: timestamp(self.atime_sec, self.atime_nsec),
+            ..Default::default()
+        };
+        metadata
+            .custom
+            .insert("uid".to_string(), self.uid.to_string());
+        metadata
+            .custom
+            .insert("gid".to_string(), self.gid.to_string());
 
         Entry {
             path: VirtualPath::new(backend_id, path),
@@ -338,7 +1625,7 @@ pub fn to_entry(&self, backend_id: &str, path: &str) -> Entry {
 /// Helper function to use system NFS mount
 impl NfsBackend {
     /// Mount using system mount command (requires root or fuse-nfs)
-    pub fn mount_system(&self, mount_point: &PathBuf) -> CfkResult<()> {
+    pub fn mount_system(&self, mount_point: &Path) -> CfkResult<()> {
         use std::process::Command;
 
         let source = format!("{}:{}", self.config.server, self.config.export);
@@ -350,13 +1637,15 @@ pub fn mount_system(&self, mount_point: &PathBuf) -> CfkResult<()> {
 
         let status = Command::new("mount")
             .args([
-                "-t", "nfs",
-                "-o", &format!("vers={}", version),
+                "-t",
+                "nfs",
+                "-o",
+                &format!("vers={}", version),
                 &source,
                 mount_point.to_str().unwrap_or("/mnt"),
             ])
             .status()
-            .map_err(|e| CfkError::Io(e.to_string()))?;
+            .map_err(CfkError::Io)?;
 
         if !status.success() {
             return Err(CfkError::ProviderApi {
@@ -369,13 +1658,13 @@ pub fn mount_system(&self, mount_point: &PathBuf) -> CfkResult<()> {
     }
 
     /// Unmount system mount
-    pub fn unmount_system(&self, mount_point: &PathBuf) -> CfkResult<()> {
+    pub fn unmount_system(&self, mount_point: &Path) -> CfkResult<()> {
         use std::process::Command;
 
         let status = Command::new("umount")
             .arg(mount_point.to_str().unwrap_or("/mnt"))
             .status()
-            .map_err(|e| CfkError::Io(e.to_string()))?;
+            .map_err(CfkError::Io)?;
 
         if !status.success() {
             return Err(CfkError::ProviderApi {
@@ -387,3 +1676,911 @@ pub fn unmount_system(&self, mount_point: &PathBuf) -> CfkResult<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    const UID: u32 = 1000;
+    const EXPORT: &str = "/data";
+    const ROOT_HANDLE: u64 = 1;
+
+    /// File handles the fake server has handed out, shared by its
+    /// connections the way a server's handles outlive them
+    #[derive(Default)]
+    struct Handles {
+        paths: HashMap<u64, PathBuf>,
+        ids: HashMap<PathBuf, u64>,
+    }
+
+    impl Handles {
+        fn handle(&mut self, path: PathBuf) -> Vec<u8> {
+            let next = self.paths.len() as u64 + ROOT_HANDLE;
+            let id = *self.ids.entry(path.clone()).or_insert(next);
+            self.paths.insert(id, path);
+            id.to_be_bytes().to_vec()
+        }
+    }
+
+    /// MOUNT and NFSv3 on one connection, serving `root` as `/data` to
+    /// AUTH_SYS callers with uid 1000. Directory listings come two entries
+    /// per reply, writes stop at 50 000 bytes and every other listed entry
+    /// lacks its handle, so the client's loops all get exercised.
+    struct FakeServer {
+        handles: Arc<std::sync::Mutex<Handles>>,
+    }
+
+    impl FakeServer {
+        async fn run(self, mut socket: TcpStream) {
+            loop {
+                let mut header = [0; 4];
+                if socket.read_exact(&mut header).await.is_err() {
+                    return;
+                }
+                let len = (u32::from_be_bytes(header) & 0x7fff_ffff) as usize;
+                let mut call = vec![0; len];
+                socket.read_exact(&mut call).await.unwrap();
+
+                let reply = self.reply(&call);
+                let mut record = ((reply.len() as u32) | 0x8000_0000).to_be_bytes().to_vec();
+                record.extend_from_slice(&reply);
+                socket.write_all(&record).await.unwrap();
+            }
+        }
+
+        fn reply(&self, call: &[u8]) -> Vec<u8> {
+            let mut xdr = Xdr::new(call);
+            let xid = xdr.u32().unwrap();
+            assert_eq!(xdr.u32().unwrap(), 0);
+            assert_eq!(xdr.u32().unwrap(), RPC_VERSION);
+            let program = xdr.u32().unwrap();
+            let version = xdr.u32().unwrap();
+            let procedure = xdr.u32().unwrap();
+            let flavor = xdr.u32().unwrap();
+            let credential = xdr.opaque().unwrap();
+            xdr.u32().unwrap();
+            xdr.opaque().unwrap();
+
+            let mut reply = Vec::new();
+            reply.put_u32(xid);
+            reply.put_u32(1);
+            let uid = (flavor == AUTH_SYS).then(|| {
+                let mut cred = Xdr::new(credential);
+                cred.u32().unwrap();
+                assert_eq!(cred.string().unwrap(), "cfk");
+                cred.u32().unwrap()
+            });
+            if uid != Some(UID) {
+                reply.put_u32(1); // MSG_DENIED
+                reply.put_u32(1); // AUTH_ERROR
+                reply.put_u32(if uid.is_some() { 2 } else { 5 });
+                return reply;
+            }
+            reply.put_u32(0);
+            reply.put_u32(AUTH_NONE);
+            reply.put_u32(0);
+            match (program, version) {
+                (prog::MOUNT, 3) => {
+                    reply.put_u32(0);
+                    self.mount(procedure, &mut xdr, &mut reply);
+                }
+                (prog::NFS, 3) => {
+                    reply.put_u32(0);
+                    if let Err(e) = self.nfs(procedure, &mut xdr, &mut reply) {
+                        reply.truncate(24);
+                        reply.put_u32(match e.raw_os_error() {
+                            Some(39) => nfsstat::NOTEMPTY,
+                            Some(errno) => errno as u32,
+                            None => nfsstat::INVAL,
+                        });
+                    }
+                }
+                _ => reply.put_u32(1), // PROG_UNAVAIL
+            }
+            reply
+        }
+
+        fn mount(&self, procedure: u32, args: &mut Xdr, reply: &mut Vec<u8>) {
+            match procedure {
+                prog::MOUNTPROC_MNT => {
+                    if args.string().unwrap() != EXPORT {
+                        reply.put_u32(nfsstat::NOENT);
+                        return;
+                    }
+                    reply.put_u32(0);
+                    reply.put_opaque(&ROOT_HANDLE.to_be_bytes());
+                    reply.put_u32(1);
+                    reply.put_u32(AUTH_SYS);
+                }
+                prog::MOUNTPROC_UMNT => {}
+                prog::MOUNTPROC_EXPORT => {
+                    for (path, groups) in [(EXPORT, &["*"][..]), ("/data/archive", &[])] {
+                        reply.put_bool(true);
+                        reply.put_opaque(path.as_bytes());
+                        for group in groups {
+                            reply.put_bool(true);
+                            reply.put_opaque(group.as_bytes());
+                        }
+                        reply.put_bool(false);
+                    }
+                    reply.put_bool(false);
+                }
+                other => panic!("unexpected MOUNT procedure {other}"),
+            }
+        }
+
+        fn path(&self, args: &mut Xdr) -> std::io::Result<PathBuf> {
+            let fh = args.opaque().unwrap();
+            let id = u64::from_be_bytes(fh.try_into().unwrap());
+            let handles = self.handles.lock().unwrap();
+            let path = handles.paths.get(&id).cloned();
+            path.filter(|p| p.symlink_metadata().is_ok())
+                .ok_or_else(|| std::io::Error::from_raw_os_error(nfsstat::STALE as i32))
+        }
+
+        fn child(&self, args: &mut Xdr) -> std::io::Result<PathBuf> {
+            let dir = self.path(args)?;
+            Ok(dir.join(args.string().unwrap()))
+        }
+
+        fn put_attrs(reply: &mut Vec<u8>, path: &Path) -> std::io::Result<()> {
+            let meta = path.symlink_metadata()?;
+            let file_type = if meta.is_dir() {
+                2
+            } else if meta.file_type().is_symlink() {
+                5
+            } else {
+                1
+            };
+            reply.put_u32(file_type);
+            reply.put_u32(meta.mode() & 0o7777);
+            reply.put_u32(meta.nlink() as u32);
+            reply.put_u32(meta.uid());
+            reply.put_u32(meta.gid());
+            reply.put_u64(meta.size());
+            reply.put_u64(meta.blocks() * 512);
+            reply.put_u64(0);
+            reply.put_u64(1);
+            reply.put_u64(meta.ino());
+            for (secs, nanos) in [
+                (meta.atime(), meta.atime_nsec()),
+                (meta.mtime(), meta.mtime_nsec()),
+                (meta.ctime(), meta.ctime_nsec()),
+            ] {
+                reply.put_u32(secs as u32);
+                reply.put_u32(nanos as u32);
+            }
+            Ok(())
+        }
+
+        /// A handle and attributes, as CREATE and MKDIR give
+        fn put_found(&self, reply: &mut Vec<u8>, path: PathBuf) -> std::io::Result<()> {
+            reply.put_bool(true);
+            reply.put_opaque(&self.handles.lock().unwrap().handle(path.clone()));
+            reply.put_bool(true);
+            Self::put_attrs(reply, &path)
+        }
+
+        fn read_sattr(args: &mut Xdr) -> SetAttrs {
+            let mut attrs = SetAttrs {
+                mode: args.optional(|x| x.u32()).unwrap(),
+                ..Default::default()
+            };
+            args.optional(|x| x.u32()).unwrap();
+            args.optional(|x| x.u32()).unwrap();
+            attrs.size = args.optional(|x| x.u64()).unwrap();
+            let mut time = || match args.u32().unwrap() {
+                SET_TO_CLIENT_TIME => Some((args.u32().unwrap(), args.u32().unwrap())),
+                _ => None,
+            };
+            attrs.atime = time();
+            attrs.mtime = time();
+            attrs
+        }
+
+        fn nfs(&self, procedure: u32, args: &mut Xdr, reply: &mut Vec<u8>) -> std::io::Result<()> {
+            let wcc = |reply: &mut Vec<u8>| {
+                reply.put_bool(false);
+                reply.put_bool(false);
+            };
+            match procedure {
+                proc3::GETATTR => {
+                    let path = self.path(args)?;
+                    reply.put_u32(0);
+                    Self::put_attrs(reply, &path)?;
+                }
+                proc3::SETATTR => {
+                    let path = self.path(args)?;
+                    let attrs = Self::read_sattr(args);
+                    if let Some(mode) = attrs.mode {
+                        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+                    }
+                    let time = |t: Option<(u32, u32)>| {
+                        t.map(|(s, n)| std::time::UNIX_EPOCH + Duration::new(s as u64, n))
+                    };
+                    let mut times = std::fs::FileTimes::new();
+                    if let Some(t) = time(attrs.atime) {
+                        times = times.set_accessed(t);
+                    }
+                    if let Some(t) = time(attrs.mtime) {
+                        times = times.set_modified(t);
+                    }
+                    std::fs::File::options()
+                        .write(true)
+                        .open(&path)?
+                        .set_times(times)?;
+                    reply.put_u32(0);
+                    wcc(reply);
+                }
+                proc3::LOOKUP => {
+                    let path = self.child(args)?;
+                    path.symlink_metadata()?;
+                    reply.put_u32(0);
+                    reply.put_opaque(&self.handles.lock().unwrap().handle(path.clone()));
+                    reply.put_bool(true);
+                    Self::put_attrs(reply, &path)?;
+                    reply.put_bool(false);
+                }
+                proc3::READ => {
+                    let path = self.path(args)?;
+                    let offset = args.u64().unwrap() as usize;
+                    let count = args.u32().unwrap() as usize;
+                    let data = std::fs::read(&path)?;
+                    let start = offset.min(data.len());
+                    let end = (start + count).min(data.len());
+                    reply.put_u32(0);
+                    reply.put_bool(false);
+                    reply.put_u32((end - start) as u32);
+                    reply.put_bool(end == data.len());
+                    reply.put_opaque(&data[start..end]);
+                }
+                proc3::WRITE => {
+                    let path = self.path(args)?;
+                    let offset = args.u64().unwrap() as usize;
+                    args.u32().unwrap();
+                    assert_eq!(args.u32().unwrap(), FILE_SYNC);
+                    let data = args.opaque().unwrap();
+                    let data = &data[..data.len().min(50_000)];
+                    let mut contents = std::fs::read(&path)?;
+                    if contents.len() < offset + data.len() {
+                        contents.resize(offset + data.len(), 0);
+                    }
+                    contents[offset..offset + data.len()].copy_from_slice(data);
+                    std::fs::write(&path, contents)?;
+                    reply.put_u32(0);
+                    wcc(reply);
+                    reply.put_u32(data.len() as u32);
+                    reply.put_u32(FILE_SYNC);
+                    reply.extend_from_slice(&[0; 8]);
+                }
+                proc3::CREATE => {
+                    let path = self.child(args)?;
+                    let how = args.u32().unwrap();
+                    let attrs = Self::read_sattr(args);
+                    if how == GUARDED && path.symlink_metadata().is_ok() {
+                        return Err(std::io::Error::from_raw_os_error(nfsstat::EXIST as i32));
+                    }
+                    let file = std::fs::File::options()
+                        .create(true)
+                        .append(true)
+                        .open(&path)?;
+                    if let Some(size) = attrs.size {
+                        file.set_len(size)?;
+                    }
+                    reply.put_u32(0);
+                    self.put_found(reply, path)?;
+                    wcc(reply);
+                }
+                proc3::MKDIR => {
+                    let path = self.child(args)?;
+                    assert_eq!(Self::read_sattr(args).mode, Some(DIR_MODE));
+                    std::fs::create_dir(&path)?;
+                    reply.put_u32(0);
+                    self.put_found(reply, path)?;
+                    wcc(reply);
+                }
+                proc3::REMOVE | proc3::RMDIR => {
+                    let path = self.child(args)?;
+                    if procedure == proc3::RMDIR {
+                        std::fs::remove_dir(&path)?;
+                    } else {
+                        std::fs::remove_file(&path)?;
+                    }
+                    reply.put_u32(0);
+                    wcc(reply);
+                }
+                proc3::RENAME => {
+                    let from = self.child(args)?;
+                    let to = self.child(args)?;
+                    std::fs::rename(from, to)?;
+                    reply.put_u32(0);
+                    wcc(reply);
+                    wcc(reply);
+                }
+                proc3::READDIRPLUS => {
+                    let dir = self.path(args)?;
+                    let cookie = args.u64().unwrap() as usize;
+                    let mut names: Vec<String> = std::fs::read_dir(&dir)?
+                        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                        .collect();
+                    names.sort();
+                    names.splice(0..0, [".".to_string(), "..".to_string()]);
+
+                    reply.put_u32(0);
+                    reply.put_bool(false);
+                    reply.extend_from_slice(&[7; 8]);
+                    let end = (cookie + 2).min(names.len());
+                    for (i, name) in names.iter().enumerate().take(end).skip(cookie) {
+                        let path = match name.as_str() {
+                            "." | ".." => dir.clone(),
+                            _ => dir.join(name),
+                        };
+                        reply.put_bool(true);
+                        reply.put_u64(path.symlink_metadata()?.ino());
+                        reply.put_opaque(name.as_bytes());
+                        reply.put_u64(i as u64 + 1);
+                        reply.put_bool(true);
+                        Self::put_attrs(reply, &path)?;
+                        reply.put_bool(i % 2 == 1);
+                        if i % 2 == 1 {
+                            reply.put_opaque(&self.handles.lock().unwrap().handle(path));
+                        }
+                    }
+                    reply.put_bool(false);
+                    reply.put_bool(end == names.len());
+                }
+                proc3::FSSTAT => {
+                    reply.put_u32(0);
+                    reply.put_bool(false);
+                    reply.put_u64(1000 * 4096);
+                    reply.put_u64(300 * 4096);
+                    reply.put_u64(250 * 4096);
+                    reply.put_u64(100);
+                    reply.put_u64(30);
+                    reply.put_u64(25);
+                    reply.put_u32(0);
+                }
+                proc3::FSINFO => {
+                    reply.put_u32(0);
+                    reply.put_bool(false);
+                    for limit in [64 * 1024, 64 * 1024, 1, 64 * 1024, 64 * 1024, 1, 4096] {
+                        reply.put_u32(limit);
+                    }
+                    reply.put_u64(u64::MAX);
+                    reply.put_u32(0);
+                    reply.put_u32(1);
+                    reply.put_u32(0x1b);
+                }
+                other => panic!("unexpected NFS procedure {other}"),
+            }
+            Ok(())
+        }
+    }
+
+    /// A server on a loopback port serving `root`
+    async fn serve(root: &Path) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut handles = Handles::default();
+        handles.handle(root.to_path_buf());
+        let handles = Arc::new(std::sync::Mutex::new(handles));
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let server = FakeServer {
+                    handles: handles.clone(),
+                };
+                tokio::spawn(server.run(socket));
+            }
+        });
+        port
+    }
+
+    fn backend(port: u16, auth: NfsAuth) -> NfsBackend {
+        NfsBackend::new(
+            "nfs",
+            NfsConfig {
+                server: "127.0.0.1".into(),
+                export: EXPORT.into(),
+                auth,
+                port,
+                mount_port: port,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn sys(uid: u32) -> NfsAuth {
+        NfsAuth::Sys {
+            uid,
+            gid: 100,
+            gids: vec![100, 27],
+        }
+    }
+
+    #[test]
+    fn test_from_url_and_attributes() {
+        let backend = NfsBackend::from_url("nas", "nfs://fileserver:20049/srv/share").unwrap();
+        assert_eq!(backend.config.server, "fileserver");
+        assert_eq!(backend.config.export, "/srv/share");
+        assert_eq!(backend.config.port, 20049);
+        assert_eq!(backend.config.version, NfsVersion::V3);
+        assert!(NfsBackend::from_url("nas", "smb://fileserver/share").is_err());
+
+        let config: NfsConfig = toml::from_str(
+            "server = \"nas\"\nexport = \"/tank\"\nversion = \"v41\"\n\
+             auth = { sys = { uid = 1000, gid = 1000 } }",
+        )
+        .unwrap();
+        assert_eq!(config.version, NfsVersion::V41);
+        assert!(matches!(config.auth, NfsAuth::Sys { uid: 1000, ref gids, .. } if gids.is_empty()));
+        assert_eq!(config.mount_port, 0);
+
+        let attrs = NfsAttributes {
+            file_type: NfsFileType::Symlink as u32,
+            mode: 0o777,
+            uid: 1000,
+            gid: 100,
+            size: 12,
+            mtime_sec: 1_700_000_000,
+            mtime_nsec: 5,
+            ..Default::default()
+        };
+        let entry = attrs.to_entry("nfs", "/docs/link");
+        assert_eq!(entry.kind, EntryKind::Symlink);
+        assert_eq!(entry.path.to_path_string(), "/docs/link");
+        assert_eq!(entry.metadata.permissions.unwrap().mode, 0o777);
+        assert_eq!(entry.metadata.modified.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!(entry.metadata.accessed, None);
+        assert_eq!(entry.metadata.custom["uid"], "1000");
+        assert_eq!(entry.metadata.custom["gid"], "100");
+    }
+
+    #[tokio::test]
+    async fn test_file_operations_over_nfsv3() {
+        let tmp = tempfile::tempdir().unwrap();
+        let port = serve(tmp.path()).await;
+        let backend = backend(port, sys(UID));
+        let path = |p: &str| VirtualPath::new("nfs", p);
+
+        let exports = backend.list_exports().await.unwrap();
+        assert_eq!(exports[0].path, EXPORT);
+        assert_eq!(exports[0].groups, ["*"]);
+        assert!(exports[1].groups.is_empty());
+
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let create = WriteOptions {
+            create_parents: true,
+            ..Default::default()
+        };
+        let entry = backend
+            .write_file(&path("docs/sub/a.bin"), Bytes::from(data.clone()), &create)
+            .await
+            .unwrap();
+        assert!(entry.is_file());
+        assert_eq!(entry.size(), Some(200_000));
+        assert_eq!(
+            std::fs::read(tmp.path().join("docs/sub/a.bin")).unwrap(),
+            data
+        );
+
+        let err = backend
+            .write_file(&path("docs/sub/a.bin"), Bytes::from_static(b"x"), &create)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)), "{err}");
+        let overwrite = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        backend
+            .write_file(
+                &path("docs/short.txt"),
+                Bytes::from_static(b"a longer text"),
+                &overwrite,
+            )
+            .await
+            .unwrap();
+        let entry = backend
+            .write_file(
+                &path("docs/short.txt"),
+                Bytes::from_static(b"short"),
+                &overwrite,
+            )
+            .await
+            .unwrap();
+        assert_eq!(entry.size(), Some(5));
+
+        let range = ReadOptions {
+            range: Some((70_000, 140_005)),
+            ..Default::default()
+        };
+        let stream = backend
+            .read_file(&path("docs/sub/a.bin"), &range)
+            .await
+            .unwrap();
+        assert_eq!(
+            common::collect_stream(stream).await.unwrap(),
+            data[70_000..140_005]
+        );
+        let stream = backend
+            .read_file(&path("docs/sub/a.bin"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(common::collect_stream(stream).await.unwrap(), data);
+
+        let mut metadata = Metadata {
+            permissions: Some(Permissions::new(0o100600)),
+            ..Default::default()
+        };
+        metadata.modified = chrono::DateTime::from_timestamp(1_600_000_000, 0);
+        backend
+            .set_metadata(&path("docs/sub/a.bin"), &metadata)
+            .await
+            .unwrap();
+        let copy = CopyOptions {
+            preserve_metadata: true,
+            ..Default::default()
+        };
+        let copied = backend
+            .copy(&path("docs/sub/a.bin"), &path("docs/b.bin"), &copy)
+            .await
+            .unwrap();
+        assert_eq!(copied.size(), Some(200_000));
+        assert_eq!(copied.metadata.permissions.unwrap().mode, 0o600);
+        assert_eq!(copied.metadata.modified.unwrap().timestamp(), 1_600_000_000);
+        assert_eq!(std::fs::read(tmp.path().join("docs/b.bin")).unwrap(), data);
+
+        backend
+            .create_directory(&path("docs/.hidden"))
+            .await
+            .unwrap();
+        let recursive = ListOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let listing = backend
+            .list_directory(&path("docs"), &recursive)
+            .await
+            .unwrap();
+        let names: Vec<String> = listing
+            .entries
+            .iter()
+            .map(|e| e.path.to_path_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "/docs/b.bin",
+                "/docs/short.txt",
+                "/docs/sub",
+                "/docs/sub/a.bin"
+            ]
+        );
+        assert_eq!(
+            listing.entries[0].metadata.custom["uid"],
+            std::fs::metadata(tmp.path()).unwrap().uid().to_string()
+        );
+
+        let err = backend
+            .rename(
+                &path("docs/b.bin"),
+                &path("docs/sub/a.bin"),
+                &MoveOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, CfkError::AlreadyExists(p) if p.ends_with("docs/sub/a.bin")),
+            "{err}"
+        );
+        let renamed = backend
+            .rename(
+                &path("docs/b.bin"),
+                &path("docs/c.bin"),
+                &MoveOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.path.to_path_string(), "/docs/c.bin");
+        assert!(!tmp.path().join("docs/b.bin").exists());
+
+        let err = backend
+            .get_metadata(&path("docs/../etc"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::InvalidPath(_)), "{err}");
+        let err = backend
+            .delete(&path("docs"), &DeleteOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::DirectoryNotEmpty(_)), "{err}");
+        let recursive = DeleteOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        backend.delete(&path("docs"), &recursive).await.unwrap();
+        assert!(!tmp.path().join("docs").exists());
+        let err = backend.get_metadata(&path("docs")).await.unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)), "{err}");
+        let force = DeleteOptions {
+            force: true,
+            ..Default::default()
+        };
+        backend.delete(&path("docs"), &force).await.unwrap();
+
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.total, Some(1000 * 4096));
+        assert_eq!(space.available, Some(250 * 4096));
+        assert_eq!(space.used, Some(700 * 4096));
+
+        backend.disconnect().await.unwrap();
+        assert!(backend.is_available().await);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_credentials_and_unknown_exports() {
+        let tmp = tempfile::tempdir().unwrap();
+        let port = serve(tmp.path()).await;
+        let root = VirtualPath::root("nfs");
+
+        for auth in [NfsAuth::None, sys(0)] {
+            let err = backend(port, auth).get_metadata(&root).await.unwrap_err();
+            assert!(matches!(err, CfkError::AuthFailed(_)), "{err}");
+        }
+
+        let mut missing = backend(port, sys(UID));
+        missing.config.export = "/nope".into();
+        let err = missing.get_metadata(&root).await.unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)), "{err}");
+
+        let mut v4 = backend(port, sys(UID));
+        v4.config.version = NfsVersion::V4;
+        let err = v4.get_metadata(&root).await.unwrap_err();
+        assert!(matches!(err, CfkError::Unsupported(_)), "{err}");
+    }
+
+    const XID: u32 = 0x5eb0_0007;
+
+    /// A READ reply with five bytes, well short of the 4096 asked for, that
+    /// does not reach the end of the file
+    const READ_REPLY: &[u8] = &[
+        0x5e, 0xb0, 0x00, 0x07, // xid
+        0, 0, 0, 1, // REPLY
+        0, 0, 0, 0, // MSG_ACCEPTED
+        0, 0, 0, 0, 0, 0, 0, 0, // AUTH_NONE verifier
+        0, 0, 0, 0, // SUCCESS
+        0, 0, 0, 0, // NFS3_OK
+        0, 0, 0, 0, // no file attributes
+        0, 0, 0, 5, // count
+        0, 0, 0, 0, // not eof
+        0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o', 0, 0, 0,
+    ];
+
+    /// A LOOKUP of a missing name
+    const LOOKUP_NOENT: &[u8] = &[
+        0x5e, 0xb0, 0x00, 0x07, // xid
+        0, 0, 0, 1, // REPLY
+        0, 0, 0, 0, // MSG_ACCEPTED
+        0, 0, 0, 0, 0, 0, 0, 0, // AUTH_NONE verifier
+        0, 0, 0, 0, // SUCCESS
+        0, 0, 0, 2, // NFS3ERR_NOENT
+        0, 0, 0, 0, // no directory attributes
+    ];
+
+    /// The first READDIRPLUS page of a directory holding a.txt, b and c:
+    /// `.` and two entries, the second without attributes or a handle
+    const DIR_PAGE_1: &[u8] = &[
+        0x5e, 0xb0, 0x00, 0x07, // xid
+        0, 0, 0, 1, // REPLY
+        0, 0, 0, 0, // MSG_ACCEPTED
+        0, 0, 0, 0, 0, 0, 0, 0, // AUTH_NONE verifier
+        0, 0, 0, 0, // SUCCESS
+        0, 0, 0, 0, // NFS3_OK
+        0, 0, 0, 0, // no directory attributes
+        1, 2, 3, 4, 5, 6, 7, 8, // cookie verifier
+        0, 0, 0, 1, // entry
+        0, 0, 0, 0, 0, 0, 0, 2, // fileid
+        0, 0, 0, 1, b'.', 0, 0, 0, // name
+        0, 0, 0, 0, 0, 0, 0, 1, // cookie
+        0, 0, 0, 0, // no attributes
+        0, 0, 0, 0, // no handle
+        0, 0, 0, 1, // entry
+        0, 0, 0, 0, 0, 0, 0, 3, // fileid
+        0, 0, 0, 5, b'a', b'.', b't', b'x', b't', 0, 0, 0, // name
+        0, 0, 0, 0, 0, 0, 0, 2, // cookie
+        0, 0, 0, 1, // attributes
+        0, 0, 0, 1, // NF3REG
+        0, 0, 0x01, 0xa4, // mode 0644
+        0, 0, 0, 1, // nlink
+        0, 0, 0x03, 0xe8, // uid
+        0, 0, 0x03, 0xe8, // gid
+        0, 0, 0, 0, 0, 0, 0, 11, // size
+        0, 0, 0, 0, 0, 0, 0x10, 0, // used
+        0, 0, 0, 0, 0, 0, 0, 0, // rdev
+        0, 0, 0, 0, 0, 0, 0, 1, // fsid
+        0, 0, 0, 0, 0, 0, 0, 3, // fileid
+        0x65, 0x53, 0xf1, 0x00, 0, 0, 0, 0, // atime
+        0x65, 0x53, 0xf1, 0x00, 0, 0, 0, 0, // mtime
+        0x65, 0x53, 0xf1, 0x00, 0, 0, 0, 0, // ctime
+        0, 0, 0, 1, // handle
+        0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 3, // fh
+        0, 0, 0, 1, // entry
+        0, 0, 0, 0, 0, 0, 0, 4, // fileid
+        0, 0, 0, 1, b'b', 0, 0, 0, // name
+        0, 0, 0, 0, 0, 0, 0, 3, // cookie
+        0, 0, 0, 0, // no attributes
+        0, 0, 0, 0, // no handle
+        0, 0, 0, 0, // no more entries
+        0, 0, 0, 0, // not eof
+    ];
+
+    /// The page after DIR_PAGE_1, ending the listing
+    const DIR_PAGE_2: &[u8] = &[
+        0x5e, 0xb0, 0x00, 0x07, // xid
+        0, 0, 0, 1, // REPLY
+        0, 0, 0, 0, // MSG_ACCEPTED
+        0, 0, 0, 0, 0, 0, 0, 0, // AUTH_NONE verifier
+        0, 0, 0, 0, // SUCCESS
+        0, 0, 0, 0, // NFS3_OK
+        0, 0, 0, 0, // no directory attributes
+        1, 2, 3, 4, 5, 6, 7, 8, // cookie verifier
+        0, 0, 0, 1, // entry
+        0, 0, 0, 0, 0, 0, 0, 5, // fileid
+        0, 0, 0, 1, b'c', 0, 0, 0, // name
+        0, 0, 0, 0, 0, 0, 0, 4, // cookie
+        0, 0, 0, 0, // no attributes
+        0, 0, 0, 0, // no handle
+        0, 0, 0, 0, // no more entries
+        0, 0, 0, 1, // eof
+    ];
+
+    /// The NFS results of a captured reply, as `Connection::call` checks them
+    fn results(reply: &[u8], procedure: u32) -> CfkResult<Vec<u8>> {
+        let mut results = reply_results(reply, XID, prog::NFS, 3, procedure)?;
+        let status = Xdr::new(&results).u32()?;
+        if status != nfsstat::OK {
+            return Err(status_error(status, "/data/x"));
+        }
+        results.drain(..4);
+        Ok(results)
+    }
+
+    /// `reply` with its nfsstat3 replaced
+    fn with_status(reply: &[u8], status: u32) -> Vec<u8> {
+        let mut reply = reply.to_vec();
+        reply[24..28].copy_from_slice(&status.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn test_rpc_reply_headers() {
+        assert!(reply_results(READ_REPLY, XID, prog::NFS, 3, proc3::READ).is_ok());
+        // A reply to some other call
+        assert!(matches!(
+            reply_results(READ_REPLY, XID + 1, prog::NFS, 3, proc3::READ),
+            Err(CfkError::ProviderApi { .. })
+        ));
+
+        let denied = [
+            0x5e, 0xb0, 0x00, 0x07, // xid
+            0, 0, 0, 1, // REPLY
+            0, 0, 0, 1, // MSG_DENIED
+            0, 0, 0, 1, // AUTH_ERROR
+            0, 0, 0, 2, // AUTH_REJECTEDCRED
+        ];
+        match reply_results(&denied, XID, prog::MOUNT, 3, 1) {
+            Err(CfkError::AuthFailed(message)) => {
+                assert_eq!(message, "MOUNT v3: credential rejected")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mismatch = [
+            0x5e, 0xb0, 0x00, 0x07, // xid
+            0, 0, 0, 1, // REPLY
+            0, 0, 0, 0, // MSG_ACCEPTED
+            0, 0, 0, 0, 0, 0, 0, 0, // AUTH_NONE verifier
+            0, 0, 0, 2, // PROG_MISMATCH
+            0, 0, 0, 4, // lowest
+            0, 0, 0, 4, // highest
+        ];
+        match reply_results(&mismatch, XID, prog::NFS, 3, proc3::GETATTR) {
+            Err(CfkError::Unsupported(message)) => {
+                assert_eq!(message, "server offers NFS v3 versions 4 to 4, not 3")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_status_mapping() {
+        assert!(matches!(
+            results(LOOKUP_NOENT, proc3::LOOKUP),
+            Err(CfkError::NotFound(path)) if path == "/data/x"
+        ));
+        let mapped = |status| results(&with_status(LOOKUP_NOENT, status), proc3::LOOKUP);
+        assert!(matches!(
+            mapped(nfsstat::ACCES),
+            Err(CfkError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            mapped(nfsstat::ROFS),
+            Err(CfkError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            mapped(nfsstat::EXIST),
+            Err(CfkError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            mapped(nfsstat::NOTEMPTY),
+            Err(CfkError::DirectoryNotEmpty(_))
+        ));
+        assert!(matches!(
+            mapped(nfsstat::DQUOT),
+            Err(CfkError::QuotaExceeded(_))
+        ));
+        assert!(matches!(mapped(nfsstat::STALE), Err(CfkError::Network(_))));
+        assert!(matches!(
+            mapped(nfsstat::JUKEBOX),
+            Err(CfkError::RateLimited {
+                retry_after_secs: Some(1)
+            })
+        ));
+        match mapped(10_006) {
+            Err(CfkError::ProviderApi { message, .. }) => {
+                assert_eq!(message, "/data/x: NFS error 10006")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_short_and_truncated_reads() {
+        let (data, eof) = decode_read(&results(READ_REPLY, proc3::READ).unwrap()).unwrap();
+        assert_eq!(&data[..], b"hello");
+        assert!(!eof);
+
+        // Cut off in the middle of the data
+        let truncated = &READ_REPLY[..READ_REPLY.len() - 4];
+        let results_of_truncated = results(truncated, proc3::READ).unwrap();
+        assert!(decode_read(&results_of_truncated).is_err());
+
+        // A count that disagrees with the data that came
+        let mut miscounted = READ_REPLY.to_vec();
+        miscounted[35] = 6;
+        assert!(decode_read(&results(&miscounted, proc3::READ).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_read_dir_continuation() {
+        let first = decode_dir_page(&results(DIR_PAGE_1, proc3::READDIRPLUS).unwrap()).unwrap();
+        assert!(!first.eof);
+        assert_eq!(first.cookie, Some(3));
+        assert_eq!(first.verifier, [1, 2, 3, 4, 5, 6, 7, 8]);
+        let names: Vec<_> = first
+            .entries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["a.txt", "b"]);
+        let found = first.entries[0].1.as_ref().unwrap();
+        assert_eq!(found.fh, 3u64.to_be_bytes());
+        assert_eq!(found.attrs.size, 11);
+        assert_eq!(found.attrs.mode, 0o644);
+        assert_eq!(found.attrs.mtime_sec, 1_700_000_000);
+        assert!(first.entries[1].1.is_none());
+
+        let second = decode_dir_page(&results(DIR_PAGE_2, proc3::READDIRPLUS).unwrap()).unwrap();
+        assert!(second.eof);
+        assert_eq!(second.cookie, Some(4));
+        assert_eq!(second.entries.len(), 1);
+
+        // A page that ends before its end-of-listing flag
+        let truncated = &DIR_PAGE_2[..DIR_PAGE_2.len() - 4];
+        assert!(decode_dir_page(&results(truncated, proc3::READDIRPLUS).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,874 @@
+//! 9P/Plan 9 filesystem protocol backend
+//!
+//! Used in WSL2 (drvfs), QEMU/KVM (virtio-9p), and Plan 9/Inferno systems.
+//! Implements 9P2000.L (Linux extensions) protocol, as a client here and as
+//! a server exporting any backend in [`server`].
+
+mod proto;
+pub mod server;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cfk_core::{
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    metadata::Permissions,
+    operations::*,
+    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
+    VirtualPath,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use proto::{Attr, DirEntry, Rmessage, SetAttr, Tmessage};
+
+pub use server::{NinePServer, NinePServerOptions};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+
+/// 9P backend configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NinePConfig {
+    /// Server address: `host:port`, or `unix:/path/to/socket`
+    pub address: String,
+    /// Attach name (usually empty or a mount tag)
+    pub aname: String,
+    /// Username for authentication
+    pub uname: String,
+    /// Numeric user id sent with the attach
+    pub uid: Option<u32>,
+    /// Maximum message size
+    pub msize: u32,
+}
+
+impl Default for NinePConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:564".to_string(),
+            aname: String::new(),
+            uname: "nobody".to_string(),
+            uid: None,
+            msize: 128 * 1024,
+        }
+    }
+}
+
+/// A byte stream to the server
+trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// An attached 9P session
+///
+/// One request is in flight at a time.
+struct Connection {
+    transport: Mutex<Box<dyn Transport>>,
+    msize: u32,
+    root: u32,
+    next_fid: AtomicU32,
+    next_tag: AtomicU16,
+    broken: AtomicBool,
+}
+
+impl Connection {
+    /// Negotiate the version and attach to `aname`
+    async fn open(transport: Box<dyn Transport>, config: &NinePConfig) -> CfkResult<Self> {
+        let mut conn = Self {
+            transport: Mutex::new(transport),
+            msize: config.msize.max(4096),
+            root: 0,
+            next_fid: AtomicU32::new(1),
+            next_tag: AtomicU16::new(0),
+            broken: AtomicBool::new(false),
+        };
+
+        let version = Tmessage::Version {
+            msize: conn.msize,
+            version: proto::VERSION.to_string(),
+        };
+        match conn.send(proto::NOTAG, version).await? {
+            Rmessage::Version { msize, version } if version == proto::VERSION => {
+                conn.msize = conn.msize.min(msize);
+            }
+            Rmessage::Version { version, .. } => {
+                return Err(CfkError::Unsupported(format!(
+                    "9P server speaks {}, not {}",
+                    version,
+                    proto::VERSION
+                )))
+            }
+            other => return Err(unexpected(other)),
+        }
+
+        let attach = Tmessage::Attach {
+            fid: conn.root,
+            afid: proto::NOFID,
+            uname: config.uname.clone(),
+            aname: config.aname.clone(),
+            n_uname: config.uid.unwrap_or(proto::NOFID),
+        };
+        match conn
+            .call(attach, format!("attach {}", config.aname))
+            .await?
+        {
+            Rmessage::Attach { .. } => Ok(conn),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Relaxed)
+    }
+
+    /// Send one request and wait for its reply
+    async fn send(&self, tag: u16, msg: Tmessage) -> CfkResult<Rmessage> {
+        let mut transport = self.transport.lock().await;
+        let result = async {
+            proto::write_frame(&mut *transport, &msg.encode(tag)).await?;
+            let frame = proto::read_frame(&mut *transport, self.msize).await?;
+            let (reply_tag, reply) = Rmessage::decode(&frame)?;
+            if reply_tag != tag {
+                return Err(CfkError::Serialization(format!(
+                    "9P reply for tag {} while waiting for {}",
+                    reply_tag, tag
+                )));
+            }
+            Ok(reply)
+        }
+        .await;
+        if result.is_err() {
+            self.broken.store(true, Ordering::Relaxed);
+        }
+        result.map_err(|e| match e {
+            CfkError::Io(e) => CfkError::Network(format!("9p: {}", e)),
+            e => e,
+        })
+    }
+
+    /// Send a request about `what`, turning Rlerror into an error
+    async fn call(&self, msg: Tmessage, what: impl Display) -> CfkResult<Rmessage> {
+        let tag = match self.next_tag.fetch_add(1, Ordering::Relaxed) {
+            proto::NOTAG => self.next_tag.fetch_add(1, Ordering::Relaxed),
+            tag => tag,
+        };
+        match self.send(tag, msg).await? {
+            Rmessage::Lerror { ecode } => Err(proto::error_of(ecode, what)),
+            reply => Ok(reply),
+        }
+    }
+
+    fn alloc_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// A new fid for `names` below `from`
+    async fn walk_from(&self, from: u32, names: &[String], what: impl Display) -> CfkResult<u32> {
+        let fid = self.alloc_fid();
+        let mut chunks = names.chunks(proto::MAXWELEM);
+        let first = chunks.next().unwrap_or_default();
+        self.walk_step(from, fid, first, &what).await?;
+        for chunk in chunks {
+            if let Err(e) = self.walk_step(fid, fid, chunk, &what).await {
+                self.clunk(fid).await;
+                return Err(e);
+            }
+        }
+        Ok(fid)
+    }
+
+    async fn walk_step(
+        &self,
+        fid: u32,
+        newfid: u32,
+        names: &[String],
+        what: &impl Display,
+    ) -> CfkResult<()> {
+        let msg = Tmessage::Walk {
+            fid,
+            newfid,
+            names: names.to_vec(),
+        };
+        match self.call(msg, what).await? {
+            Rmessage::Walk { qids } if qids.len() == names.len() => Ok(()),
+            // Stopped part way: newfid was not set up
+            Rmessage::Walk { .. } => Err(CfkError::NotFound(what.to_string())),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// A new fid for `path`
+    async fn walk(&self, path: &VirtualPath) -> CfkResult<u32> {
+        self.walk_from(self.root, &path.segments, path).await
+    }
+
+    /// Release a fid; the server forgets it even if the reply is an error
+    async fn clunk(&self, fid: u32) {
+        let _ = self.call(Tmessage::Clunk { fid }, "clunk").await;
+    }
+
+    async fn getattr(&self, fid: u32, what: impl Display) -> CfkResult<Attr> {
+        let msg = Tmessage::Getattr {
+            fid,
+            request_mask: proto::getattr::BASIC,
+        };
+        match self.call(msg, what).await? {
+            Rmessage::Getattr(attr) => Ok(attr),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Open `fid`, returning the most to ask for in one read or write
+    async fn lopen(&self, fid: u32, flags: u32, what: impl Display) -> CfkResult<u32> {
+        match self.call(Tmessage::Lopen { fid, flags }, what).await? {
+            Rmessage::Lopen { iounit, .. } => Ok(self.iounit(iounit)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn iounit(&self, iounit: u32) -> u32 {
+        let max = self.msize - proto::IOHDRSZ;
+        if iounit == 0 {
+            max
+        } else {
+            iounit.min(max)
+        }
+    }
+
+    async fn read(
+        &self,
+        fid: u32,
+        offset: u64,
+        count: u32,
+        what: impl Display,
+    ) -> CfkResult<Bytes> {
+        match self
+            .call(Tmessage::Read { fid, offset, count }, what)
+            .await?
+        {
+            Rmessage::Read { data } => Ok(data),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn write_all(
+        &self,
+        fid: u32,
+        mut offset: u64,
+        data: Bytes,
+        iounit: u32,
+        what: impl Display,
+    ) -> CfkResult<()> {
+        let mut written = 0;
+        while written < data.len() {
+            let end = data.len().min(written + iounit as usize);
+            let msg = Tmessage::Write {
+                fid,
+                offset,
+                data: data.slice(written..end),
+            };
+            // A short write leaves the rest to send again
+            let count = match self.call(msg, &what).await? {
+                Rmessage::Write { count } => count as usize,
+                other => return Err(unexpected(other)),
+            };
+            if count == 0 {
+                return Err(CfkError::QuotaExceeded(what.to_string()));
+            }
+            written += count;
+            offset += count as u64;
+        }
+        Ok(())
+    }
+
+    /// Every entry of an open directory fid, without `.` and `..`
+    async fn readdir(&self, fid: u32, what: impl Display) -> CfkResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        loop {
+            let msg = Tmessage::Readdir {
+                fid,
+                offset,
+                count: self.msize - proto::IOHDRSZ,
+            };
+            let data = match self.call(msg, &what).await? {
+                Rmessage::Readdir { data } => data,
+                other => return Err(unexpected(other)),
+            };
+            let batch = DirEntry::decode_all(&data)?;
+            let Some(last) = batch.last() else {
+                return Ok(entries);
+            };
+            offset = last.offset;
+            entries.extend(
+                batch
+                    .into_iter()
+                    .filter(|e| e.name != "." && e.name != ".."),
+            );
+        }
+    }
+}
+
+fn unexpected(reply: Rmessage) -> CfkError {
+    CfkError::Serialization(format!("unexpected 9P reply {:?}", reply))
+}
+
+/// A fid clunked when dropped, for streams that may be abandoned
+struct OpenFid {
+    conn: Arc<Connection>,
+    fid: u32,
+}
+
+impl OpenFid {
+    /// Clunk now rather than in the background. Servers may only store what
+    /// was written once the fid is clunked, and may fail doing so.
+    async fn close(mut self, what: impl Display) -> CfkResult<()> {
+        let fid = std::mem::replace(&mut self.fid, proto::NOFID);
+        match self.conn.call(Tmessage::Clunk { fid }, what).await? {
+            Rmessage::Clunk => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+impl Drop for OpenFid {
+    fn drop(&mut self) {
+        let (conn, fid) = (self.conn.clone(), self.fid);
+        if fid == proto::NOFID {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { conn.clunk(fid).await });
+        }
+    }
+}
+
+/// 9P storage backend
+pub struct NinePBackend {
+    id: String,
+    config: NinePConfig,
+    capabilities: StorageCapabilities,
+    connection: Mutex<Option<Arc<Connection>>>,
+}
+
+impl NinePBackend {
+    pub fn new(id: impl Into<String>, config: NinePConfig) -> Self {
+        Self {
+            id: id.into(),
+            config,
+            capabilities: StorageCapabilities {
+                read: true,
+                write: true,
+                delete: true,
+                rename: true,
+                copy: false, // 9P doesn't have native copy
+                list: true,
+                search: false,
+                versioning: false,
+                sharing: false,
+                offline: false,
+                streaming: true,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false,
+                watch: false,
+            },
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Connect to 9P server
+    pub async fn connect(&self) -> CfkResult<()> {
+        self.session().await.map(|_| ())
+    }
+
+    /// The attached session, connecting first if there is none or the last
+    /// one failed
+    async fn session(&self) -> CfkResult<Arc<Connection>> {
+        let mut connection = self.connection.lock().await;
+        if let Some(conn) = connection.as_ref() {
+            if !conn.is_broken() {
+                return Ok(conn.clone());
+            }
+            tracing::debug!(
+                "9p connection to {} dropped, reconnecting",
+                self.config.address
+            );
+        }
+
+        let conn = Arc::new(
+            tokio::time::timeout(CONNECT_TIMEOUT, async {
+                Connection::open(self.dial().await?, &self.config).await
+            })
+            .await
+            .map_err(|_| CfkError::Timeout)??,
+        );
+        *connection = Some(conn.clone());
+        Ok(conn)
+    }
+
+    async fn dial(&self) -> CfkResult<Box<dyn Transport>> {
+        let network =
+            |e: std::io::Error| CfkError::Network(format!("{}: {}", self.config.address, e));
+        if let Some(path) = self.config.address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Box::new(
+                tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(network)?,
+            ));
+            #[cfg(not(unix))]
+            return Err(CfkError::Unsupported(format!("Unix socket {}", path)));
+        }
+        let stream = TcpStream::connect(&self.config.address)
+            .await
+            .map_err(network)?;
+        stream.set_nodelay(true).map_err(network)?;
+        Ok(Box::new(stream))
+    }
+
+    fn entry(&self, path: &VirtualPath, attr: &Attr) -> Entry {
+        let kind = match attr.mode & 0o170000 {
+            0o040000 => EntryKind::Directory,
+            0o120000 => EntryKind::Symlink,
+            0o100000 => EntryKind::File,
+            _ if attr.qid.is_dir() => EntryKind::Directory,
+            _ => EntryKind::Unknown,
+        };
+        let time = |(sec, nsec): (u64, u64)| {
+            (sec > 0)
+                .then(|| chrono::DateTime::from_timestamp(sec as i64, nsec as u32))
+                .flatten()
+        };
+        let mut metadata = Metadata::new().with_size(attr.size);
+        metadata.permissions = Some(Permissions::new(attr.mode));
+        metadata.modified = time(attr.mtime);
+        metadata.accessed = time(attr.atime);
+        metadata.created = time(attr.btime);
+        metadata
+            .custom
+            .insert("uid".to_string(), attr.uid.to_string());
+        metadata
+            .custom
+            .insert("gid".to_string(), attr.gid.to_string());
+        Entry {
+            path: VirtualPath {
+                backend: self.id.clone(),
+                segments: path.segments.clone(),
+            },
+            kind,
+            metadata,
+        }
+    }
+
+    async fn stat(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<Entry> {
+        let fid = conn.walk(path).await?;
+        let attr = conn.getattr(fid, path).await;
+        conn.clunk(fid).await;
+        Ok(self.entry(path, &attr?))
+    }
+
+    /// A fid for the directory holding `path`, and the name within it
+    async fn parent<'p>(
+        &self,
+        conn: &Connection,
+        path: &'p VirtualPath,
+    ) -> CfkResult<(u32, &'p str)> {
+        let name = path
+            .name()
+            .ok_or_else(|| CfkError::InvalidPath(path.to_string()))?;
+        let dir = &path.segments[..path.segments.len() - 1];
+        Ok((conn.walk_from(conn.root, dir, path).await?, name))
+    }
+
+    async fn mkdir(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<()> {
+        let (dfid, name) = self.parent(conn, path).await?;
+        let msg = Tmessage::Mkdir {
+            dfid,
+            name: name.to_string(),
+            mode: DIR_MODE,
+            gid: proto::NOFID,
+        };
+        let result = conn.call(msg, path).await;
+        conn.clunk(dfid).await;
+        result.map(|_| ())
+    }
+
+    /// Create every missing directory above `path`
+    async fn ensure_parents(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<()> {
+        let mut dir = VirtualPath::root(&self.id);
+        for segment in &path.segments[..path.segments.len().saturating_sub(1)] {
+            dir = dir.join(segment);
+            match self.mkdir(conn, &dir).await {
+                Ok(()) | Err(CfkError::AlreadyExists(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn put(
+        &self,
+        path: &VirtualPath,
+        mut stream: ByteStream,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        if options.create_parents {
+            self.ensure_parents(&conn, path).await?;
+        }
+        let (fid, name) = self.parent(&conn, path).await?;
+        let file = OpenFid {
+            conn: conn.clone(),
+            fid,
+        };
+        let flags = proto::open::WRONLY
+            | proto::open::CREAT
+            | if options.overwrite {
+                proto::open::TRUNC
+            } else {
+                proto::open::EXCL
+            };
+        let msg = Tmessage::Lcreate {
+            fid,
+            name: name.to_string(),
+            flags,
+            mode: FILE_MODE,
+            gid: proto::NOFID,
+        };
+        // The directory fid now stands for the created file
+        let iounit = match conn.call(msg, path).await? {
+            Rmessage::Lcreate { qid, .. } if qid.is_dir() => {
+                return Err(CfkError::NotAFile(path.to_string()))
+            }
+            Rmessage::Lcreate { iounit, .. } => conn.iounit(iounit),
+            other => return Err(unexpected(other)),
+        };
+
+        let mut offset = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            let len = chunk.len() as u64;
+            conn.write_all(file.fid, offset, chunk, iounit, &path)
+                .await?;
+            offset += len;
+        }
+        let attr = conn.getattr(file.fid, path).await?;
+        file.close(path).await?;
+        Ok(self.entry(path, &attr))
+    }
+
+    async fn remove(&self, conn: &Connection, path: &VirtualPath, dir: bool) -> CfkResult<()> {
+        let (dirfid, name) = self.parent(conn, path).await?;
+        let msg = Tmessage::Unlinkat {
+            dirfid,
+            name: name.to_string(),
+            flags: if dir { proto::AT_REMOVEDIR } else { 0 },
+        };
+        let result = conn.call(msg, path).await;
+        conn.clunk(dirfid).await;
+        result.map(|_| ())
+    }
+
+    /// Remove `path` and, for a directory, everything in it
+    async fn remove_tree(&self, conn: &Connection, entry: &Entry) -> CfkResult<()> {
+        let mut dirs = vec![entry.clone()];
+        let mut order = Vec::new();
+        while let Some(dir) = dirs.pop() {
+            if dir.is_directory() {
+                for child in self.children(conn, &dir.path).await? {
+                    dirs.push(child);
+                }
+            }
+            order.push(dir);
+        }
+        // Children were found after their parents
+        for entry in order.iter().rev() {
+            self.remove(conn, &entry.path, entry.is_directory()).await?;
+        }
+        Ok(())
+    }
+
+    /// The entries of directory `path`, sorted by name
+    async fn children(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        // An open fid cannot be walked from, so the listing gets a clone
+        let dir = conn.walk(path).await?;
+        let result = async {
+            let listing = conn.walk_from(dir, &[], path).await?;
+            let names = async {
+                conn.lopen(listing, proto::open::RDONLY, path).await?;
+                conn.readdir(listing, path).await
+            }
+            .await;
+            conn.clunk(listing).await;
+
+            let mut entries = Vec::new();
+            for dirent in names? {
+                let child = path.join(&dirent.name);
+                let fid = match conn.walk_from(dir, &[dirent.name], &child).await {
+                    Ok(fid) => fid,
+                    // Removed since the directory was read
+                    Err(CfkError::NotFound(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                let attr = conn.getattr(fid, &child).await;
+                conn.clunk(fid).await;
+                entries.push(self.entry(&child, &attr?));
+            }
+            entries.sort_by(|a, b| a.path.segments.cmp(&b.path.segments));
+            Ok(entries)
+        }
+        .await;
+        conn.clunk(dir).await;
+        result
+    }
+}
+
+#[async_trait]
+impl StorageBackend for NinePBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn display_name(&self) -> &str {
+        "9P"
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
+
+    async fn is_available(&self) -> bool {
+        self.session().await.is_ok()
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        self.stat(&conn, path).await
+    }
+
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let conn = self.session().await?;
+        if !self.stat(&conn, path).await?.is_directory() {
+            return Err(CfkError::NotADirectory(path.to_string()));
+        }
+
+        let mut entries = Vec::new();
+        let mut pending = vec![path.clone()];
+        while let Some(dir) = pending.pop() {
+            let children = self.children(&conn, &dir).await?;
+            if options.recursive {
+                pending.extend(
+                    children
+                        .iter()
+                        .filter(|e| e.is_directory())
+                        .filter(|e| {
+                            options.include_hidden || !e.name().is_some_and(|n| n.starts_with('.'))
+                        })
+                        .map(|e| e.path.clone()),
+                );
+            }
+            entries.extend(children);
+        }
+        Ok(crate::common::page_entries(path, entries, options))
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let conn = self.session().await?;
+        let file = OpenFid {
+            conn: conn.clone(),
+            fid: conn.walk(path).await?,
+        };
+        let attr = conn.getattr(file.fid, path).await?;
+        if attr.qid.is_dir() {
+            return Err(CfkError::NotAFile(path.to_string()));
+        }
+        let iounit = conn.lopen(file.fid, proto::open::RDONLY, path).await?;
+
+        let (start, end) = options.range.unwrap_or((0, u64::MAX));
+        let path = path.clone();
+        Ok(Box::pin(futures::stream::try_unfold(
+            (file, start),
+            move |(file, offset)| {
+                let path = path.clone();
+                async move {
+                    if offset >= end {
+                        return Ok(None);
+                    }
+                    let count = (end - offset).min(iounit as u64) as u32;
+                    let chunk = file.conn.read(file.fid, offset, count, &path).await?;
+                    if chunk.is_empty() {
+                        return Ok(None);
+                    }
+                    let next = offset + chunk.len() as u64;
+                    Ok(Some((chunk, (file, next))))
+                }
+            },
+        )))
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let stream: ByteStream = Box::pin(futures::stream::once(async { Ok(data) }));
+        self.put(path, stream, options).await
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.put(path, stream, options).await
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        self.mkdir(&conn, path).await?;
+        self.stat(&conn, path).await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if path.is_root() {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        }
+        let conn = self.session().await?;
+        let result = async {
+            let entry = self.stat(&conn, path).await?;
+            if options.recursive {
+                self.remove_tree(&conn, &entry).await
+            } else {
+                self.remove(&conn, path, entry.is_directory()).await
+            }
+        }
+        .await;
+        crate::common::ignore_missing(result, options)
+    }
+
+    /// 9P has no copy, so the data passes through
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        let entry = self.get_metadata(source).await?;
+        if entry.is_directory() {
+            return Err(CfkError::NotAFile(source.to_string()));
+        }
+        let stream = self.read_file(source, &ReadOptions::default()).await?;
+        let write = WriteOptions {
+            overwrite: options.overwrite,
+            ..Default::default()
+        };
+        let copied = self
+            .write_file_stream(dest, stream, entry.size(), &write)
+            .await?;
+        if options.preserve_metadata {
+            self.set_metadata(dest, &entry.metadata).await?;
+            return self.get_metadata(dest).await;
+        }
+        Ok(copied)
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        if !options.overwrite {
+            match self.stat(&conn, dest).await {
+                Ok(_) => return Err(CfkError::AlreadyExists(dest.to_string())),
+                Err(CfkError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let (olddirfid, oldname) = self.parent(&conn, source).await?;
+        let result = async {
+            let (newdirfid, newname) = self.parent(&conn, dest).await?;
+            let msg = Tmessage::Renameat {
+                olddirfid,
+                oldname: oldname.to_string(),
+                newdirfid,
+                newname: newname.to_string(),
+            };
+            let result = conn.call(msg, source).await;
+            conn.clunk(newdirfid).await;
+            result
+        }
+        .await;
+        conn.clunk(olddirfid).await;
+        result?;
+        self.stat(&conn, dest).await
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        let conn = self.session().await?;
+        let reply = conn
+            .call(Tmessage::Statfs { fid: conn.root }, "statfs")
+            .await;
+        match reply {
+            Ok(Rmessage::Statfs(st)) => {
+                let bsize = st.bsize as u64;
+                Ok(SpaceInfo {
+                    total: Some(st.blocks * bsize),
+                    used: Some(st.blocks.saturating_sub(st.bfree) * bsize),
+                    available: Some(st.bavail * bsize),
+                })
+            }
+            Ok(other) => Err(unexpected(other)),
+            Err(CfkError::Unsupported(_)) => Ok(SpaceInfo::unknown()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        let mut attr = SetAttr::default();
+        if let Some(permissions) = metadata.permissions {
+            attr.valid |= proto::setattr::MODE;
+            attr.mode = permissions.mode & 0o7777;
+        }
+        let time = |t: chrono::DateTime<chrono::Utc>| {
+            (
+                t.timestamp().max(0) as u64,
+                t.timestamp_subsec_nanos() as u64,
+            )
+        };
+        if let Some(modified) = metadata.modified {
+            attr.valid |= proto::setattr::MTIME | proto::setattr::MTIME_SET;
+            attr.mtime = time(modified);
+        }
+        if let Some(accessed) = metadata.accessed {
+            attr.valid |= proto::setattr::ATIME | proto::setattr::ATIME_SET;
+            attr.atime = time(accessed);
+        }
+        if attr.valid == 0 {
+            return Ok(());
+        }
+
+        let conn = self.session().await?;
+        let fid = conn.walk(path).await?;
+        let result = conn.call(Tmessage::Setattr { fid, attr }, path).await;
+        conn.clunk(fid).await;
+        result.map(|_| ())
+    }
+}
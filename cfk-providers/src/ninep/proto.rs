@@ -0,0 +1,1039 @@
+//! 9P2000.L message codec
+//!
+//! Shared by the client backend and the server. A message on the wire is
+//! `size[4] type[1] tag[2]` followed by the body, little-endian throughout;
+//! strings carry a 2-byte length.
+
+#![allow(dead_code)] // The protocol tables are kept complete
+
+use bytes::{BufMut, Bytes, BytesMut};
+use cfk_core::{CfkError, CfkResult};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The only dialect spoken
+pub const VERSION: &str = "9P2000.L";
+/// Tag of Tversion
+pub const NOTAG: u16 = 0xffff;
+/// "No fid", as the afid of an unauthenticated Tattach
+pub const NOFID: u32 = 0xffff_ffff;
+/// Bytes of a Tread/Twrite header, subtracted from msize for the iounit
+pub const IOHDRSZ: u32 = 24;
+/// Most names in one Twalk
+pub const MAXWELEM: usize = 16;
+
+/// Qid types
+pub mod qt {
+    pub const DIR: u8 = 0x80;
+    pub const SYMLINK: u8 = 0x02;
+    pub const FILE: u8 = 0x00;
+}
+
+/// Tgetattr request mask and Rgetattr valid bits
+pub mod getattr {
+    pub const MODE: u64 = 0x0001;
+    pub const NLINK: u64 = 0x0002;
+    pub const UID: u64 = 0x0004;
+    pub const GID: u64 = 0x0008;
+    pub const RDEV: u64 = 0x0010;
+    pub const ATIME: u64 = 0x0020;
+    pub const MTIME: u64 = 0x0040;
+    pub const CTIME: u64 = 0x0080;
+    pub const INO: u64 = 0x0100;
+    pub const SIZE: u64 = 0x0200;
+    pub const BLOCKS: u64 = 0x0400;
+    /// Everything but btime, gen and data_version
+    pub const BASIC: u64 = 0x07ff;
+}
+
+/// Tsetattr valid bits
+pub mod setattr {
+    pub const MODE: u32 = 0x0001;
+    pub const UID: u32 = 0x0002;
+    pub const GID: u32 = 0x0004;
+    pub const SIZE: u32 = 0x0008;
+    pub const ATIME: u32 = 0x0010;
+    pub const MTIME: u32 = 0x0020;
+    pub const CTIME: u32 = 0x0040;
+    /// The given atime rather than now
+    pub const ATIME_SET: u32 = 0x0080;
+    /// The given mtime rather than now
+    pub const MTIME_SET: u32 = 0x0100;
+}
+
+/// Linux open flags, as Tlopen and Tlcreate carry them
+pub mod open {
+    pub const RDONLY: u32 = 0o0;
+    pub const WRONLY: u32 = 0o1;
+    pub const RDWR: u32 = 0o2;
+    pub const ACCMODE: u32 = 0o3;
+    pub const CREAT: u32 = 0o100;
+    pub const EXCL: u32 = 0o200;
+    pub const TRUNC: u32 = 0o1000;
+    pub const APPEND: u32 = 0o2000;
+}
+
+/// Tunlinkat flag for removing a directory
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// Linux errno values, which Rlerror carries whatever the host
+pub mod errno {
+    pub const EPERM: u32 = 1;
+    pub const ENOENT: u32 = 2;
+    pub const EINTR: u32 = 4;
+    pub const EIO: u32 = 5;
+    pub const EBADF: u32 = 9;
+    pub const EAGAIN: u32 = 11;
+    pub const EACCES: u32 = 13;
+    pub const EEXIST: u32 = 17;
+    pub const EXDEV: u32 = 18;
+    pub const ENOTDIR: u32 = 20;
+    pub const EISDIR: u32 = 21;
+    pub const EINVAL: u32 = 22;
+    pub const EFBIG: u32 = 27;
+    pub const ENOSPC: u32 = 28;
+    pub const EROFS: u32 = 30;
+    pub const ERANGE: u32 = 34;
+    pub const ENAMETOOLONG: u32 = 36;
+    pub const ENOSYS: u32 = 38;
+    pub const ENOTEMPTY: u32 = 39;
+    pub const ENODATA: u32 = 61;
+    pub const EPROTO: u32 = 71;
+    pub const EOPNOTSUPP: u32 = 95;
+    pub const ECONNRESET: u32 = 104;
+    pub const ETIMEDOUT: u32 = 110;
+    pub const EDQUOT: u32 = 122;
+}
+
+mod ty {
+    pub const TLERROR: u8 = 6;
+    pub const RLERROR: u8 = 7;
+    pub const TSTATFS: u8 = 8;
+    pub const RSTATFS: u8 = 9;
+    pub const TLOPEN: u8 = 12;
+    pub const RLOPEN: u8 = 13;
+    pub const TLCREATE: u8 = 14;
+    pub const RLCREATE: u8 = 15;
+    pub const TRENAME: u8 = 20;
+    pub const RRENAME: u8 = 21;
+    pub const TGETATTR: u8 = 24;
+    pub const RGETATTR: u8 = 25;
+    pub const TSETATTR: u8 = 26;
+    pub const RSETATTR: u8 = 27;
+    pub const TREADDIR: u8 = 40;
+    pub const RREADDIR: u8 = 41;
+    pub const TFSYNC: u8 = 50;
+    pub const RFSYNC: u8 = 51;
+    pub const TMKDIR: u8 = 72;
+    pub const RMKDIR: u8 = 73;
+    pub const TRENAMEAT: u8 = 74;
+    pub const RRENAMEAT: u8 = 75;
+    pub const TUNLINKAT: u8 = 76;
+    pub const RUNLINKAT: u8 = 77;
+    pub const TVERSION: u8 = 100;
+    pub const RVERSION: u8 = 101;
+    pub const TAUTH: u8 = 102;
+    pub const TATTACH: u8 = 104;
+    pub const RATTACH: u8 = 105;
+    pub const TFLUSH: u8 = 108;
+    pub const RFLUSH: u8 = 109;
+    pub const TWALK: u8 = 110;
+    pub const RWALK: u8 = 111;
+    pub const TREAD: u8 = 116;
+    pub const RREAD: u8 = 117;
+    pub const TWRITE: u8 = 118;
+    pub const RWRITE: u8 = 119;
+    pub const TCLUNK: u8 = 120;
+    pub const RCLUNK: u8 = 121;
+    pub const TREMOVE: u8 = 122;
+    pub const RREMOVE: u8 = 123;
+}
+
+/// Server-side identity of a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Qid {
+    pub ty: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+impl Qid {
+    pub fn is_dir(&self) -> bool {
+        self.ty & qt::DIR != 0
+    }
+}
+
+/// Rgetattr body
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attr {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime: (u64, u64),
+    pub mtime: (u64, u64),
+    pub ctime: (u64, u64),
+    pub btime: (u64, u64),
+    pub gen: u64,
+    pub data_version: u64,
+}
+
+/// Tsetattr body; `valid` says which fields apply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetAttr {
+    pub valid: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: (u64, u64),
+    pub mtime: (u64, u64),
+}
+
+/// Rstatfs body
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatFs {
+    pub fs_type: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+/// One entry of an Rreaddir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub qid: Qid,
+    /// Offset to pass to the next Treaddir to continue after this entry
+    pub offset: u64,
+    /// `d_type` of the entry
+    pub ty: u8,
+    pub name: String,
+}
+
+impl DirEntry {
+    /// Bytes the entry takes in an Rreaddir
+    pub fn size(&self) -> usize {
+        13 + 8 + 1 + 2 + self.name.len()
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_qid(&self.qid);
+        buf.put_u64_le(self.offset);
+        buf.put_u8(self.ty);
+        buf.put_str(&self.name);
+    }
+
+    /// Split the data of an Rreaddir into entries
+    pub fn decode_all(data: &[u8]) -> CfkResult<Vec<DirEntry>> {
+        let mut r = Reader(data);
+        let mut entries = Vec::new();
+        while !r.0.is_empty() {
+            entries.push(DirEntry {
+                qid: r.qid()?,
+                offset: r.u64()?,
+                ty: r.u8()?,
+                name: r.string()?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Requests, client to server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tmessage {
+    Version {
+        msize: u32,
+        version: String,
+    },
+    Auth {
+        afid: u32,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    },
+    Attach {
+        fid: u32,
+        afid: u32,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    },
+    Flush {
+        oldtag: u16,
+    },
+    Walk {
+        fid: u32,
+        newfid: u32,
+        names: Vec<String>,
+    },
+    Lopen {
+        fid: u32,
+        flags: u32,
+    },
+    Lcreate {
+        fid: u32,
+        name: String,
+        flags: u32,
+        mode: u32,
+        gid: u32,
+    },
+    Read {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        fid: u32,
+        offset: u64,
+        data: Bytes,
+    },
+    Clunk {
+        fid: u32,
+    },
+    Remove {
+        fid: u32,
+    },
+    Readdir {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Getattr {
+        fid: u32,
+        request_mask: u64,
+    },
+    Setattr {
+        fid: u32,
+        attr: SetAttr,
+    },
+    Statfs {
+        fid: u32,
+    },
+    Fsync {
+        fid: u32,
+    },
+    Mkdir {
+        dfid: u32,
+        name: String,
+        mode: u32,
+        gid: u32,
+    },
+    Rename {
+        fid: u32,
+        dfid: u32,
+        name: String,
+    },
+    Renameat {
+        olddirfid: u32,
+        oldname: String,
+        newdirfid: u32,
+        newname: String,
+    },
+    Unlinkat {
+        dirfid: u32,
+        name: String,
+        flags: u32,
+    },
+    /// A request this codec does not know, by type
+    Unknown(u8),
+}
+
+/// Replies, server to client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rmessage {
+    Lerror { ecode: u32 },
+    Version { msize: u32, version: String },
+    Attach { qid: Qid },
+    Flush,
+    Walk { qids: Vec<Qid> },
+    Lopen { qid: Qid, iounit: u32 },
+    Lcreate { qid: Qid, iounit: u32 },
+    Read { data: Bytes },
+    Write { count: u32 },
+    Clunk,
+    Remove,
+    Readdir { data: Bytes },
+    Getattr(Attr),
+    Setattr,
+    Statfs(StatFs),
+    Fsync,
+    Mkdir { qid: Qid },
+    Rename,
+    Renameat,
+    Unlinkat,
+}
+
+trait Put9p {
+    fn put_str(&mut self, s: &str);
+    fn put_qid(&mut self, qid: &Qid);
+}
+
+impl Put9p for BytesMut {
+    fn put_str(&mut self, s: &str) {
+        self.put_u16_le(s.len() as u16);
+        self.put_slice(s.as_bytes());
+    }
+
+    fn put_qid(&mut self, qid: &Qid) {
+        self.put_u8(qid.ty);
+        self.put_u32_le(qid.version);
+        self.put_u64_le(qid.path);
+    }
+}
+
+/// A frame with its size filled in once the body is written
+fn frame(ty: u8, tag: u16, body: impl FnOnce(&mut BytesMut)) -> Bytes {
+    let mut buf = BytesMut::with_capacity(64);
+    buf.put_u32_le(0);
+    buf.put_u8(ty);
+    buf.put_u16_le(tag);
+    body(&mut buf);
+    let size = buf.len() as u32;
+    buf[..4].copy_from_slice(&size.to_le_bytes());
+    buf.freeze()
+}
+
+/// Cursor over a message body
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> CfkResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(CfkError::Serialization("9P message truncated".into()));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> CfkResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> CfkResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> CfkResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> CfkResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn time(&mut self) -> CfkResult<(u64, u64)> {
+        Ok((self.u64()?, self.u64()?))
+    }
+
+    fn string(&mut self) -> CfkResult<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| CfkError::Serialization("9P string is not UTF-8".into()))
+    }
+
+    fn qid(&mut self) -> CfkResult<Qid> {
+        Ok(Qid {
+            ty: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+
+    /// A `count[4]`-prefixed run of bytes
+    fn data(&mut self) -> CfkResult<Bytes> {
+        let len = self.u32()? as usize;
+        Ok(Bytes::copy_from_slice(self.take(len)?))
+    }
+}
+
+/// Split a frame into type, tag and body
+fn header(frame: &[u8]) -> CfkResult<(u8, u16, Reader<'_>)> {
+    let mut r = Reader(frame);
+    r.u32()?;
+    Ok((r.u8()?, r.u16()?, r))
+}
+
+impl Tmessage {
+    pub fn encode(&self, tag: u16) -> Bytes {
+        match self {
+            Self::Version { msize, version } => frame(ty::TVERSION, tag, |b| {
+                b.put_u32_le(*msize);
+                b.put_str(version);
+            }),
+            Self::Auth {
+                afid,
+                uname,
+                aname,
+                n_uname,
+            } => frame(ty::TAUTH, tag, |b| {
+                b.put_u32_le(*afid);
+                b.put_str(uname);
+                b.put_str(aname);
+                b.put_u32_le(*n_uname);
+            }),
+            Self::Attach {
+                fid,
+                afid,
+                uname,
+                aname,
+                n_uname,
+            } => frame(ty::TATTACH, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u32_le(*afid);
+                b.put_str(uname);
+                b.put_str(aname);
+                b.put_u32_le(*n_uname);
+            }),
+            Self::Flush { oldtag } => frame(ty::TFLUSH, tag, |b| b.put_u16_le(*oldtag)),
+            Self::Walk { fid, newfid, names } => frame(ty::TWALK, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u32_le(*newfid);
+                b.put_u16_le(names.len() as u16);
+                for name in names {
+                    b.put_str(name);
+                }
+            }),
+            Self::Lopen { fid, flags } => frame(ty::TLOPEN, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u32_le(*flags);
+            }),
+            Self::Lcreate {
+                fid,
+                name,
+                flags,
+                mode,
+                gid,
+            } => frame(ty::TLCREATE, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_str(name);
+                b.put_u32_le(*flags);
+                b.put_u32_le(*mode);
+                b.put_u32_le(*gid);
+            }),
+            Self::Read { fid, offset, count } => frame(ty::TREAD, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u64_le(*offset);
+                b.put_u32_le(*count);
+            }),
+            Self::Write { fid, offset, data } => frame(ty::TWRITE, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u64_le(*offset);
+                b.put_u32_le(data.len() as u32);
+                b.put_slice(data);
+            }),
+            Self::Clunk { fid } => frame(ty::TCLUNK, tag, |b| b.put_u32_le(*fid)),
+            Self::Remove { fid } => frame(ty::TREMOVE, tag, |b| b.put_u32_le(*fid)),
+            Self::Readdir { fid, offset, count } => frame(ty::TREADDIR, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u64_le(*offset);
+                b.put_u32_le(*count);
+            }),
+            Self::Getattr { fid, request_mask } => frame(ty::TGETATTR, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u64_le(*request_mask);
+            }),
+            Self::Setattr { fid, attr } => frame(ty::TSETATTR, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u32_le(attr.valid);
+                b.put_u32_le(attr.mode);
+                b.put_u32_le(attr.uid);
+                b.put_u32_le(attr.gid);
+                b.put_u64_le(attr.size);
+                b.put_u64_le(attr.atime.0);
+                b.put_u64_le(attr.atime.1);
+                b.put_u64_le(attr.mtime.0);
+                b.put_u64_le(attr.mtime.1);
+            }),
+            Self::Statfs { fid } => frame(ty::TSTATFS, tag, |b| b.put_u32_le(*fid)),
+            Self::Fsync { fid } => frame(ty::TFSYNC, tag, |b| b.put_u32_le(*fid)),
+            Self::Mkdir {
+                dfid,
+                name,
+                mode,
+                gid,
+            } => frame(ty::TMKDIR, tag, |b| {
+                b.put_u32_le(*dfid);
+                b.put_str(name);
+                b.put_u32_le(*mode);
+                b.put_u32_le(*gid);
+            }),
+            Self::Rename { fid, dfid, name } => frame(ty::TRENAME, tag, |b| {
+                b.put_u32_le(*fid);
+                b.put_u32_le(*dfid);
+                b.put_str(name);
+            }),
+            Self::Renameat {
+                olddirfid,
+                oldname,
+                newdirfid,
+                newname,
+            } => frame(ty::TRENAMEAT, tag, |b| {
+                b.put_u32_le(*olddirfid);
+                b.put_str(oldname);
+                b.put_u32_le(*newdirfid);
+                b.put_str(newname);
+            }),
+            Self::Unlinkat {
+                dirfid,
+                name,
+                flags,
+            } => frame(ty::TUNLINKAT, tag, |b| {
+                b.put_u32_le(*dirfid);
+                b.put_str(name);
+                b.put_u32_le(*flags);
+            }),
+            Self::Unknown(t) => frame(*t, tag, |_| {}),
+        }
+    }
+
+    /// Decode a whole frame, size included, into its tag and message
+    pub fn decode(frame: &[u8]) -> CfkResult<(u16, Self)> {
+        let (t, tag, mut r) = header(frame)?;
+        let msg = match t {
+            ty::TVERSION => Self::Version {
+                msize: r.u32()?,
+                version: r.string()?,
+            },
+            ty::TAUTH => Self::Auth {
+                afid: r.u32()?,
+                uname: r.string()?,
+                aname: r.string()?,
+                n_uname: r.u32()?,
+            },
+            ty::TATTACH => Self::Attach {
+                fid: r.u32()?,
+                afid: r.u32()?,
+                uname: r.string()?,
+                aname: r.string()?,
+                n_uname: r.u32()?,
+            },
+            ty::TFLUSH => Self::Flush { oldtag: r.u16()? },
+            ty::TWALK => {
+                let (fid, newfid) = (r.u32()?, r.u32()?);
+                let n = r.u16()? as usize;
+                if n > MAXWELEM {
+                    return Err(CfkError::Serialization("Twalk of too many names".into()));
+                }
+                let names = (0..n).map(|_| r.string()).collect::<CfkResult<_>>()?;
+                Self::Walk { fid, newfid, names }
+            }
+            ty::TLOPEN => Self::Lopen {
+                fid: r.u32()?,
+                flags: r.u32()?,
+            },
+            ty::TLCREATE => Self::Lcreate {
+                fid: r.u32()?,
+                name: r.string()?,
+                flags: r.u32()?,
+                mode: r.u32()?,
+                gid: r.u32()?,
+            },
+            ty::TREAD => Self::Read {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+            ty::TWRITE => Self::Write {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                data: r.data()?,
+            },
+            ty::TCLUNK => Self::Clunk { fid: r.u32()? },
+            ty::TREMOVE => Self::Remove { fid: r.u32()? },
+            ty::TREADDIR => Self::Readdir {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+            ty::TGETATTR => Self::Getattr {
+                fid: r.u32()?,
+                request_mask: r.u64()?,
+            },
+            ty::TSETATTR => Self::Setattr {
+                fid: r.u32()?,
+                attr: SetAttr {
+                    valid: r.u32()?,
+                    mode: r.u32()?,
+                    uid: r.u32()?,
+                    gid: r.u32()?,
+                    size: r.u64()?,
+                    atime: r.time()?,
+                    mtime: r.time()?,
+                },
+            },
+            ty::TSTATFS => Self::Statfs { fid: r.u32()? },
+            ty::TFSYNC => Self::Fsync { fid: r.u32()? },
+            ty::TMKDIR => Self::Mkdir {
+                dfid: r.u32()?,
+                name: r.string()?,
+                mode: r.u32()?,
+                gid: r.u32()?,
+            },
+            ty::TRENAME => Self::Rename {
+                fid: r.u32()?,
+                dfid: r.u32()?,
+                name: r.string()?,
+            },
+            ty::TRENAMEAT => Self::Renameat {
+                olddirfid: r.u32()?,
+                oldname: r.string()?,
+                newdirfid: r.u32()?,
+                newname: r.string()?,
+            },
+            ty::TUNLINKAT => Self::Unlinkat {
+                dirfid: r.u32()?,
+                name: r.string()?,
+                flags: r.u32()?,
+            },
+            other => Self::Unknown(other),
+        };
+        Ok((tag, msg))
+    }
+}
+
+impl Rmessage {
+    pub fn encode(&self, tag: u16) -> Bytes {
+        match self {
+            Self::Lerror { ecode } => frame(ty::RLERROR, tag, |b| b.put_u32_le(*ecode)),
+            Self::Version { msize, version } => frame(ty::RVERSION, tag, |b| {
+                b.put_u32_le(*msize);
+                b.put_str(version);
+            }),
+            Self::Attach { qid } => frame(ty::RATTACH, tag, |b| b.put_qid(qid)),
+            Self::Flush => frame(ty::RFLUSH, tag, |_| {}),
+            Self::Walk { qids } => frame(ty::RWALK, tag, |b| {
+                b.put_u16_le(qids.len() as u16);
+                for qid in qids {
+                    b.put_qid(qid);
+                }
+            }),
+            Self::Lopen { qid, iounit } => frame(ty::RLOPEN, tag, |b| {
+                b.put_qid(qid);
+                b.put_u32_le(*iounit);
+            }),
+            Self::Lcreate { qid, iounit } => frame(ty::RLCREATE, tag, |b| {
+                b.put_qid(qid);
+                b.put_u32_le(*iounit);
+            }),
+            Self::Read { data } => frame(ty::RREAD, tag, |b| {
+                b.put_u32_le(data.len() as u32);
+                b.put_slice(data);
+            }),
+            Self::Write { count } => frame(ty::RWRITE, tag, |b| b.put_u32_le(*count)),
+            Self::Clunk => frame(ty::RCLUNK, tag, |_| {}),
+            Self::Remove => frame(ty::RREMOVE, tag, |_| {}),
+            Self::Readdir { data } => frame(ty::RREADDIR, tag, |b| {
+                b.put_u32_le(data.len() as u32);
+                b.put_slice(data);
+            }),
+            Self::Getattr(a) => frame(ty::RGETATTR, tag, |b| {
+                b.put_u64_le(a.valid);
+                b.put_qid(&a.qid);
+                b.put_u32_le(a.mode);
+                b.put_u32_le(a.uid);
+                b.put_u32_le(a.gid);
+                b.put_u64_le(a.nlink);
+                b.put_u64_le(a.rdev);
+                b.put_u64_le(a.size);
+                b.put_u64_le(a.blksize);
+                b.put_u64_le(a.blocks);
+                for (sec, nsec) in [a.atime, a.mtime, a.ctime, a.btime] {
+                    b.put_u64_le(sec);
+                    b.put_u64_le(nsec);
+                }
+                b.put_u64_le(a.gen);
+                b.put_u64_le(a.data_version);
+            }),
+            Self::Setattr => frame(ty::RSETATTR, tag, |_| {}),
+            Self::Statfs(s) => frame(ty::RSTATFS, tag, |b| {
+                b.put_u32_le(s.fs_type);
+                b.put_u32_le(s.bsize);
+                b.put_u64_le(s.blocks);
+                b.put_u64_le(s.bfree);
+                b.put_u64_le(s.bavail);
+                b.put_u64_le(s.files);
+                b.put_u64_le(s.ffree);
+                b.put_u64_le(s.fsid);
+                b.put_u32_le(s.namelen);
+            }),
+            Self::Fsync => frame(ty::RFSYNC, tag, |_| {}),
+            Self::Mkdir { qid } => frame(ty::RMKDIR, tag, |b| b.put_qid(qid)),
+            Self::Rename => frame(ty::RRENAME, tag, |_| {}),
+            Self::Renameat => frame(ty::RRENAMEAT, tag, |_| {}),
+            Self::Unlinkat => frame(ty::RUNLINKAT, tag, |_| {}),
+        }
+    }
+
+    /// Decode a whole frame, size included, into its tag and message
+    pub fn decode(frame: &[u8]) -> CfkResult<(u16, Self)> {
+        let (t, tag, mut r) = header(frame)?;
+        let msg = match t {
+            ty::RLERROR => Self::Lerror { ecode: r.u32()? },
+            ty::RVERSION => Self::Version {
+                msize: r.u32()?,
+                version: r.string()?,
+            },
+            ty::RATTACH => Self::Attach { qid: r.qid()? },
+            ty::RFLUSH => Self::Flush,
+            ty::RWALK => {
+                let n = r.u16()? as usize;
+                let qids = (0..n).map(|_| r.qid()).collect::<CfkResult<_>>()?;
+                Self::Walk { qids }
+            }
+            ty::RLOPEN => Self::Lopen {
+                qid: r.qid()?,
+                iounit: r.u32()?,
+            },
+            ty::RLCREATE => Self::Lcreate {
+                qid: r.qid()?,
+                iounit: r.u32()?,
+            },
+            ty::RREAD => Self::Read { data: r.data()? },
+            ty::RWRITE => Self::Write { count: r.u32()? },
+            ty::RCLUNK => Self::Clunk,
+            ty::RREMOVE => Self::Remove,
+            ty::RREADDIR => Self::Readdir { data: r.data()? },
+            ty::RGETATTR => Self::Getattr(Attr {
+                valid: r.u64()?,
+                qid: r.qid()?,
+                mode: r.u32()?,
+                uid: r.u32()?,
+                gid: r.u32()?,
+                nlink: r.u64()?,
+                rdev: r.u64()?,
+                size: r.u64()?,
+                blksize: r.u64()?,
+                blocks: r.u64()?,
+                atime: r.time()?,
+                mtime: r.time()?,
+                ctime: r.time()?,
+                btime: r.time()?,
+                gen: r.u64()?,
+                data_version: r.u64()?,
+            }),
+            ty::RSETATTR => Self::Setattr,
+            ty::RSTATFS => Self::Statfs(StatFs {
+                fs_type: r.u32()?,
+                bsize: r.u32()?,
+                blocks: r.u64()?,
+                bfree: r.u64()?,
+                bavail: r.u64()?,
+                files: r.u64()?,
+                ffree: r.u64()?,
+                fsid: r.u64()?,
+                namelen: r.u32()?,
+            }),
+            ty::RFSYNC => Self::Fsync,
+            ty::RMKDIR => Self::Mkdir { qid: r.qid()? },
+            ty::RRENAME => Self::Rename,
+            ty::RRENAMEAT => Self::Renameat,
+            ty::RUNLINKAT => Self::Unlinkat,
+            other => {
+                return Err(CfkError::Serialization(format!(
+                    "unexpected 9P reply type {}",
+                    other
+                )))
+            }
+        };
+        Ok((tag, msg))
+    }
+}
+
+/// Read one frame, refusing any larger than `msize`
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, msize: u32) -> CfkResult<Vec<u8>> {
+    let mut size = [0u8; 4];
+    reader.read_exact(&mut size).await?;
+    let len = u32::from_le_bytes(size);
+    if !(7..=msize).contains(&len) {
+        return Err(CfkError::Serialization(format!(
+            "9P message of {} bytes",
+            len
+        )));
+    }
+    let mut frame = vec![0u8; len as usize];
+    frame[..4].copy_from_slice(&size);
+    reader.read_exact(&mut frame[4..]).await?;
+    Ok(frame)
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> CfkResult<()> {
+    writer.write_all(frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The errno an Rlerror reports for `error`
+pub fn errno_of(error: &CfkError) -> u32 {
+    match error {
+        CfkError::NotFound(_) | CfkError::OfflineNoCache => errno::ENOENT,
+        CfkError::AlreadyExists(_) => errno::EEXIST,
+        CfkError::PermissionDenied(_)
+        | CfkError::AuthRequired(_)
+        | CfkError::AuthFailed(_)
+        | CfkError::TokenExpired => errno::EACCES,
+        CfkError::NotADirectory(_) => errno::ENOTDIR,
+        CfkError::NotAFile(_) => errno::EISDIR,
+        CfkError::DirectoryNotEmpty(_) => errno::ENOTEMPTY,
+        CfkError::InvalidPath(_) => errno::EINVAL,
+        CfkError::QuotaExceeded(_) => errno::ENOSPC,
+        CfkError::Unsupported(_) => errno::EOPNOTSUPP,
+        CfkError::Timeout => errno::ETIMEDOUT,
+        CfkError::RateLimited { .. } => errno::EAGAIN,
+        CfkError::Cancelled => errno::EINTR,
+        CfkError::Io(e) => e.raw_os_error().map_or(errno::EIO, |e| e as u32),
+        _ => errno::EIO,
+    }
+}
+
+/// The error an Rlerror stands for, about `what`
+pub fn error_of(ecode: u32, what: impl std::fmt::Display) -> CfkError {
+    let what = what.to_string();
+    match ecode {
+        errno::ENOENT | errno::ENODATA => CfkError::NotFound(what),
+        errno::EEXIST => CfkError::AlreadyExists(what),
+        errno::EACCES | errno::EPERM | errno::EROFS => CfkError::PermissionDenied(what),
+        errno::ENOTDIR => CfkError::NotADirectory(what),
+        errno::EISDIR => CfkError::NotAFile(what),
+        errno::ENOTEMPTY => CfkError::DirectoryNotEmpty(what),
+        errno::EINVAL | errno::ENAMETOOLONG => CfkError::InvalidPath(what),
+        errno::ENOSPC | errno::EDQUOT | errno::EFBIG => CfkError::QuotaExceeded(what),
+        errno::EOPNOTSUPP | errno::ENOSYS => CfkError::Unsupported(what),
+        errno::ETIMEDOUT => CfkError::Timeout,
+        ecode => CfkError::ProviderApi {
+            provider: "9p".into(),
+            message: format!("{}: errno {}", what, ecode),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_t(message: Tmessage) {
+        let frame = message.encode(7);
+        let (tag, decoded) = Tmessage::decode(&frame).unwrap();
+        assert_eq!(tag, 7);
+        assert_eq!(decoded, message);
+    }
+
+    fn round_trip_r(message: Rmessage) {
+        let frame = message.encode(9);
+        let (tag, decoded) = Rmessage::decode(&frame).unwrap();
+        assert_eq!(tag, 9);
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_requests_round_trip() {
+        round_trip_t(Tmessage::Version {
+            msize: 8192,
+            version: VERSION.to_string(),
+        });
+        round_trip_t(Tmessage::Walk {
+            fid: 1,
+            newfid: 2,
+            names: vec!["a".into(), "b c".into()],
+        });
+        round_trip_t(Tmessage::Write {
+            fid: 3,
+            offset: 1 << 40,
+            data: Bytes::from_static(b"payload"),
+        });
+        round_trip_t(Tmessage::Setattr {
+            fid: 4,
+            attr: SetAttr {
+                valid: setattr::MODE | setattr::SIZE,
+                mode: 0o600,
+                size: 12,
+                ..Default::default()
+            },
+        });
+        round_trip_t(Tmessage::Unlinkat {
+            dirfid: 5,
+            name: "gone".into(),
+            flags: AT_REMOVEDIR,
+        });
+    }
+
+    #[test]
+    fn test_replies_round_trip() {
+        round_trip_r(Rmessage::Lerror {
+            ecode: errno::ENOENT,
+        });
+        round_trip_r(Rmessage::Walk {
+            qids: vec![
+                Qid {
+                    ty: qt::DIR,
+                    version: 1,
+                    path: 2,
+                },
+                Qid::default(),
+            ],
+        });
+        round_trip_r(Rmessage::Getattr(Attr {
+            valid: getattr::BASIC,
+            mode: 0o100644,
+            size: 42,
+            mtime: (1_700_000_000, 5),
+            ..Default::default()
+        }));
+        round_trip_r(Rmessage::Statfs(StatFs {
+            bsize: 4096,
+            blocks: 10,
+            namelen: 255,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_dir_entries() {
+        let entries = vec![
+            DirEntry {
+                qid: Qid::default(),
+                offset: 1,
+                ty: 8,
+                name: "file".into(),
+            },
+            DirEntry {
+                qid: Qid {
+                    ty: qt::DIR,
+                    ..Default::default()
+                },
+                offset: 2,
+                ty: 4,
+                name: "dir".into(),
+            },
+        ];
+        let mut buf = BytesMut::new();
+        for entry in &entries {
+            entry.encode(&mut buf);
+        }
+        assert_eq!(buf.len(), entries.iter().map(DirEntry::size).sum::<usize>());
+        let decoded = DirEntry::decode_all(&buf).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].name, "dir");
+        assert!(decoded[1].qid.is_dir());
+    }
+
+    #[test]
+    fn test_errno_mapping() {
+        let e = error_of(errno::ENOENT, "/x");
+        assert!(matches!(e, CfkError::NotFound(_)));
+        assert_eq!(errno_of(&e), errno::ENOENT);
+        let e = CfkError::AlreadyExists("/x".into());
+        assert!(matches!(error_of(errno_of(&e), "/x"), CfkError::AlreadyExists(_)));
+    }
+}
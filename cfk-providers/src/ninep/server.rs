@@ -0,0 +1,1133 @@
+//! 9P2000.L server exporting a [`StorageBackend`]
+//!
+//! Lets a Linux kernel (`mount -t 9p`), a QEMU guest over virtio-9p or any
+//! other 9P2000.L client reach whatever a backend holds. Requests on one
+//! connection are served in order.
+//!
+//! Backends have no random-access writes, so a file opened for writing is
+//! spooled in memory and stored whole when the fid is clunked or fsynced.
+//! Reads stream from the backend and only reopen on a seek.
+
+use bytes::{Bytes, BytesMut};
+use cfk_core::{
+    backend::ByteStream, operations::*, CfkError, CfkResult, Entry, EntryKind, Metadata,
+    StorageBackend, VirtualPath,
+};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+use super::proto::{self, errno, Attr, DirEntry, Qid, Rmessage, SetAttr, StatFs, Tmessage};
+
+/// What 9P filesystems report as `f_type`
+const V9FS_MAGIC: u32 = 0x0102_1997;
+/// Block size reported by getattr and statfs
+const BLOCK_SIZE: u64 = 4096;
+
+/// `d_type` values in Rreaddir
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+/// Options for [`NinePServer`]
+#[derive(Debug, Clone)]
+pub struct NinePServerOptions {
+    /// Refuse every change with EROFS
+    pub read_only: bool,
+    /// Largest message offered in Tversion negotiation
+    pub msize: u32,
+    /// Largest file clients may write or truncate to; beyond it they get
+    /// EFBIG. Files being written are held in memory.
+    pub max_file_size: u64,
+}
+
+impl Default for NinePServerOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            msize: 512 * 1024,
+            max_file_size: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A 9P2000.L server for one backend directory
+pub struct NinePServer {
+    backend: Arc<dyn StorageBackend>,
+    root: VirtualPath,
+    options: NinePServerOptions,
+    /// Qid paths handed out so far, so a file keeps its qid while served
+    qids: std::sync::Mutex<HashMap<Vec<String>, u64>>,
+}
+
+/// Per-connection state of a fid
+struct Fid {
+    path: VirtualPath,
+    qid: Qid,
+    open: Option<Open>,
+}
+
+enum Open {
+    /// Directory entries, read once at offset 0
+    Dir(Option<Vec<DirEntry>>),
+    File(OpenFile),
+}
+
+struct OpenFile {
+    flags: u32,
+    /// The content being written, for a file opened for writing
+    spool: Option<Spool>,
+    /// The backend stream sequential reads continue from
+    reader: Option<Reader>,
+}
+
+struct Spool {
+    data: Vec<u8>,
+    /// Changed since last stored
+    dirty: bool,
+}
+
+struct Reader {
+    stream: ByteStream,
+    /// Offset of the first byte of `pending`
+    offset: u64,
+    pending: Bytes,
+}
+
+type Reply = CfkResult<Rmessage>;
+
+impl NinePServer {
+    /// Serve `root` and everything below it
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        root: VirtualPath,
+        options: NinePServerOptions,
+    ) -> Self {
+        Self {
+            backend,
+            root,
+            options,
+            qids: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accept TCP clients until the listener fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> CfkResult<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            stream.set_nodelay(true)?;
+            tracing::debug!("9p client {} connected", peer);
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    tracing::debug!("9p client {}: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Accept clients on a Unix socket until the listener fails
+    #[cfg(unix)]
+    pub async fn serve_unix(self: Arc<Self>, listener: tokio::net::UnixListener) -> CfkResult<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    tracing::debug!("9p client: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Serve one client until it hangs up
+    pub async fn serve_connection<S>(&self, stream: S) -> CfkResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let mut session = Session {
+            server: self,
+            msize: self.options.msize,
+            fids: HashMap::new(),
+        };
+        let result = loop {
+            let frame = match proto::read_frame(&mut reader, session.msize).await {
+                Ok(frame) => frame,
+                Err(CfkError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    break Ok(())
+                }
+                Err(e) => break Err(e),
+            };
+            let (tag, request) = Tmessage::decode(&frame)?;
+            let reply = session.handle(request).await.unwrap_or_else(|e| {
+                tracing::debug!("9p: {}", e);
+                Rmessage::Lerror {
+                    ecode: proto::errno_of(&e),
+                }
+            });
+            proto::write_frame(&mut writer, &reply.encode(tag)).await?;
+        };
+        // Whatever was written but never clunked is still worth keeping
+        session.clunk_all().await;
+        result
+    }
+
+    fn qid(&self, entry: &Entry) -> Qid {
+        let mut qids = self.qids.lock().unwrap();
+        let next = qids.len() as u64 + 1;
+        let path = *qids.entry(entry.path.segments.clone()).or_insert(next);
+        let ty = match entry.kind {
+            EntryKind::Directory => proto::qt::DIR,
+            EntryKind::Symlink => proto::qt::SYMLINK,
+            _ => proto::qt::FILE,
+        };
+        // Clients compare versions to see a file changed
+        let version = entry.metadata.modified.map_or(0, |t| {
+            t.timestamp() as u32 ^ entry.size().unwrap_or(0) as u32
+        });
+        Qid { ty, version, path }
+    }
+
+    /// Metadata of `path`; the export root is a directory whatever the
+    /// backend says about it
+    async fn stat(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        match self.backend.get_metadata(path).await {
+            Err(_) if *path == self.root => Ok(Entry::directory(path.clone(), Metadata::default())),
+            result => result,
+        }
+    }
+
+    fn attr(&self, entry: &Entry) -> Attr {
+        let is_dir = entry.is_directory();
+        let kind = match entry.kind {
+            EntryKind::Directory => 0o040000,
+            EntryKind::Symlink => 0o120000,
+            _ => 0o100000,
+        };
+        let permissions = entry
+            .metadata
+            .permissions
+            .map(|p| p.mode & 0o7777)
+            .unwrap_or(if is_dir { 0o755 } else { 0o644 });
+        let size = entry.size().unwrap_or(0);
+        let time = |t: Option<chrono::DateTime<chrono::Utc>>| {
+            t.map_or((0, 0), |t| {
+                (
+                    t.timestamp().max(0) as u64,
+                    t.timestamp_subsec_nanos() as u64,
+                )
+            })
+        };
+        let mtime = time(entry.metadata.modified);
+        let id = |key: &str| {
+            entry
+                .metadata
+                .custom
+                .get(key)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0)
+        };
+        Attr {
+            valid: proto::getattr::BASIC,
+            qid: self.qid(entry),
+            mode: kind | permissions,
+            uid: id("uid"),
+            gid: id("gid"),
+            nlink: if is_dir { 2 } else { 1 },
+            size,
+            blksize: BLOCK_SIZE,
+            blocks: size.div_ceil(512),
+            atime: entry.metadata.accessed.map_or(mtime, |t| time(Some(t))),
+            mtime,
+            ctime: mtime,
+            btime: time(entry.metadata.created),
+            ..Default::default()
+        }
+    }
+}
+
+/// One client connection
+struct Session<'a> {
+    server: &'a NinePServer,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Session<'_> {
+    fn backend(&self) -> &dyn StorageBackend {
+        self.server.backend.as_ref()
+    }
+
+    fn fid(&mut self, fid: u32) -> CfkResult<&mut Fid> {
+        self.fids
+            .get_mut(&fid)
+            .ok_or_else(|| CfkError::Other(format!("unknown fid {}", fid)))
+    }
+
+    fn writable(&self) -> CfkResult<()> {
+        if self.server.options.read_only {
+            return Err(CfkError::PermissionDenied("read-only export".into()));
+        }
+        Ok(())
+    }
+
+    fn iounit(&self) -> u32 {
+        self.msize - proto::IOHDRSZ
+    }
+
+    /// `name` inside the directory of `fid`
+    fn child(&mut self, fid: u32, name: &str) -> CfkResult<VirtualPath> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(CfkError::InvalidPath(name.to_string()));
+        }
+        let dir = self.fid(fid)?;
+        if !dir.qid.is_dir() {
+            return Err(CfkError::NotADirectory(dir.path.to_string()));
+        }
+        Ok(dir.path.join(name))
+    }
+
+    async fn handle(&mut self, request: Tmessage) -> Reply {
+        if self.server.options.read_only {
+            if let Tmessage::Lcreate { .. }
+            | Tmessage::Write { .. }
+            | Tmessage::Mkdir { .. }
+            | Tmessage::Rename { .. }
+            | Tmessage::Renameat { .. }
+            | Tmessage::Unlinkat { .. }
+            | Tmessage::Remove { .. }
+            | Tmessage::Setattr { .. } = request
+            {
+                return Ok(Rmessage::Lerror {
+                    ecode: errno::EROFS,
+                });
+            }
+        }
+
+        match request {
+            Tmessage::Version { msize, version } => Ok(self.version(msize, &version).await),
+            Tmessage::Auth { .. } => Ok(Rmessage::Lerror {
+                ecode: errno::EOPNOTSUPP,
+            }),
+            Tmessage::Attach {
+                fid, afid, aname, ..
+            } => self.attach(fid, afid, &aname).await,
+            // Requests are answered in order, so there is nothing to cancel
+            Tmessage::Flush { .. } => Ok(Rmessage::Flush),
+            Tmessage::Walk { fid, newfid, names } => self.walk(fid, newfid, &names).await,
+            Tmessage::Lopen { fid, flags } => self.lopen(fid, flags).await,
+            Tmessage::Lcreate {
+                fid, name, flags, ..
+            } => self.lcreate(fid, &name, flags).await,
+            Tmessage::Read { fid, offset, count } => self.read(fid, offset, count).await,
+            Tmessage::Write { fid, offset, data } => self.write(fid, offset, &data),
+            Tmessage::Clunk { fid } => {
+                let fid = self
+                    .fids
+                    .remove(&fid)
+                    .ok_or(CfkError::Other("unknown fid".into()))?;
+                self.release(fid).await.map(|_| Rmessage::Clunk)
+            }
+            Tmessage::Remove { fid } => {
+                let fid = self
+                    .fids
+                    .remove(&fid)
+                    .ok_or(CfkError::Other("unknown fid".into()))?;
+                let options = DeleteOptions::default();
+                self.backend().delete(&fid.path, &options).await?;
+                Ok(Rmessage::Remove)
+            }
+            Tmessage::Readdir { fid, offset, count } => self.readdir(fid, offset, count).await,
+            Tmessage::Getattr { fid, .. } => {
+                let path = self.fid(fid)?.path.clone();
+                let entry = self.server.stat(&path).await?;
+                let mut attr = self.server.attr(&entry);
+                // Unstored writes show in the size
+                if let Some(Open::File(OpenFile {
+                    spool: Some(spool), ..
+                })) = &self.fid(fid)?.open
+                {
+                    attr.size = spool.data.len() as u64;
+                }
+                Ok(Rmessage::Getattr(attr))
+            }
+            Tmessage::Setattr { fid, attr } => self.setattr(fid, attr).await,
+            Tmessage::Statfs { .. } => self.statfs().await,
+            Tmessage::Fsync { fid } => {
+                let server = self.server;
+                let fid = self.fid(fid)?;
+                if let Some(Open::File(OpenFile {
+                    spool: Some(spool), ..
+                })) = &mut fid.open
+                {
+                    store(server.backend.as_ref(), &fid.path, spool).await?;
+                }
+                Ok(Rmessage::Fsync)
+            }
+            Tmessage::Mkdir { dfid, name, .. } => {
+                let path = self.child(dfid, &name)?;
+                let entry = self.backend().create_directory(&path).await?;
+                Ok(Rmessage::Mkdir {
+                    qid: self.server.qid(&entry),
+                })
+            }
+            Tmessage::Rename { fid, dfid, name } => {
+                let from = self.fid(fid)?.path.clone();
+                let to = self.child(dfid, &name)?;
+                self.rename(&from, &to).await.map(|_| Rmessage::Rename)
+            }
+            Tmessage::Renameat {
+                olddirfid,
+                oldname,
+                newdirfid,
+                newname,
+            } => {
+                let from = self.child(olddirfid, &oldname)?;
+                let to = self.child(newdirfid, &newname)?;
+                self.rename(&from, &to).await.map(|_| Rmessage::Renameat)
+            }
+            Tmessage::Unlinkat {
+                dirfid,
+                name,
+                flags,
+            } => {
+                let path = self.child(dirfid, &name)?;
+                let entry = self.backend().get_metadata(&path).await?;
+                match (entry.is_directory(), flags & proto::AT_REMOVEDIR != 0) {
+                    (true, false) => return Err(CfkError::NotAFile(path.to_string())),
+                    (false, true) => return Err(CfkError::NotADirectory(path.to_string())),
+                    _ => {}
+                }
+                let options = DeleteOptions::default();
+                crate::common::check_delete(self.backend(), &path, &options).await?;
+                self.backend().delete(&path, &options).await?;
+                Ok(Rmessage::Unlinkat)
+            }
+            Tmessage::Unknown(ty) => {
+                tracing::debug!("9p: unsupported request type {}", ty);
+                Ok(Rmessage::Lerror {
+                    ecode: errno::EOPNOTSUPP,
+                })
+            }
+        }
+    }
+
+    async fn version(&mut self, msize: u32, version: &str) -> Rmessage {
+        // A new version starts the session over
+        self.clunk_all().await;
+        self.msize = msize.clamp(4096, self.server.options.msize);
+        let version = if version.starts_with(proto::VERSION) {
+            proto::VERSION
+        } else {
+            "unknown"
+        };
+        Rmessage::Version {
+            msize: self.msize,
+            version: version.to_string(),
+        }
+    }
+
+    async fn attach(&mut self, fid: u32, afid: u32, aname: &str) -> Reply {
+        if afid != proto::NOFID {
+            return Err(CfkError::PermissionDenied("no authentication".into()));
+        }
+        if self.fids.contains_key(&fid) {
+            return Err(CfkError::Other(format!("fid {} in use", fid)));
+        }
+        // The attach name picks a directory inside the export
+        let mut path = self.server.root.clone();
+        for segment in aname.split('/').filter(|s| !s.is_empty()) {
+            if segment == ".." {
+                return Err(CfkError::InvalidPath(aname.to_string()));
+            }
+            path = path.join(segment);
+        }
+        let entry = self.server.stat(&path).await?;
+        if !entry.is_directory() {
+            return Err(CfkError::NotADirectory(aname.to_string()));
+        }
+        let qid = self.server.qid(&entry);
+        self.fids.insert(
+            fid,
+            Fid {
+                path,
+                qid,
+                open: None,
+            },
+        );
+        Ok(Rmessage::Attach { qid })
+    }
+
+    async fn walk(&mut self, fid: u32, newfid: u32, names: &[String]) -> Reply {
+        let start = self.fid(fid)?;
+        let (mut path, mut qid) = (start.path.clone(), start.qid);
+        if newfid != fid && self.fids.contains_key(&newfid) {
+            return Err(CfkError::Other(format!("fid {} in use", newfid)));
+        }
+
+        let mut qids = Vec::new();
+        for name in names {
+            let next = match name.as_str() {
+                // Never above the export
+                ".." if path.segments.len() > self.server.root.segments.len() => {
+                    path.parent().unwrap()
+                }
+                ".." => path.clone(),
+                name if name.is_empty() || name == "." || name.contains('/') => {
+                    return Err(CfkError::InvalidPath(name.to_string()))
+                }
+                name if !qid.is_dir() => {
+                    return Err(CfkError::NotADirectory(format!("{}/{}", path, name)))
+                }
+                name => path.join(name),
+            };
+            match self.server.stat(&next).await {
+                Ok(entry) => {
+                    qid = self.server.qid(&entry);
+                    qids.push(qid);
+                    path = next;
+                }
+                Err(e) if qids.is_empty() => return Err(e),
+                // Partial walk: report how far it got, newfid stays unused
+                Err(_) => return Ok(Rmessage::Walk { qids }),
+            }
+        }
+
+        let walked = Fid {
+            path,
+            qid,
+            open: None,
+        };
+        if let Some(old) = self.fids.insert(newfid, walked) {
+            self.release(old).await?;
+        }
+        Ok(Rmessage::Walk { qids })
+    }
+
+    async fn lopen(&mut self, fid: u32, flags: u32) -> Reply {
+        let iounit = self.iounit();
+        let path = self.fid(fid)?.path.clone();
+        let entry = self.server.stat(&path).await?;
+        let qid = self.server.qid(&entry);
+        let access = flags & proto::open::ACCMODE;
+
+        let open = if entry.is_directory() {
+            if access != proto::open::RDONLY {
+                return Err(CfkError::NotAFile(path.to_string()));
+            }
+            Open::Dir(None)
+        } else if access == proto::open::RDONLY {
+            Open::File(OpenFile {
+                flags,
+                spool: None,
+                reader: None,
+            })
+        } else {
+            self.writable()?;
+            let spool = if flags & proto::open::TRUNC != 0 {
+                Spool {
+                    data: Vec::new(),
+                    dirty: true,
+                }
+            } else {
+                let stream = self
+                    .backend()
+                    .read_file(&path, &ReadOptions::default())
+                    .await?;
+                Spool {
+                    data: crate::common::collect_stream(stream).await?.to_vec(),
+                    dirty: false,
+                }
+            };
+            Open::File(OpenFile {
+                flags,
+                spool: Some(spool),
+                reader: None,
+            })
+        };
+
+        let fid = self.fid(fid)?;
+        fid.qid = qid;
+        fid.open = Some(open);
+        Ok(Rmessage::Lopen { qid, iounit })
+    }
+
+    async fn lcreate(&mut self, fid: u32, name: &str, flags: u32) -> Reply {
+        self.writable()?;
+        let path = self.child(fid, name)?;
+        let options = WriteOptions {
+            overwrite: false,
+            ..Default::default()
+        };
+        // Created at once, so it can be looked up while still being written
+        let (entry, data) = match self
+            .backend()
+            .write_file(&path, Bytes::new(), &options)
+            .await
+        {
+            Ok(entry) => (entry, Vec::new()),
+            Err(CfkError::AlreadyExists(_)) if flags & proto::open::EXCL == 0 => {
+                let entry = self.backend().get_metadata(&path).await?;
+                if entry.is_directory() {
+                    return Err(CfkError::NotAFile(path.to_string()));
+                }
+                let data = if flags & proto::open::TRUNC != 0 {
+                    Vec::new()
+                } else {
+                    let stream = self
+                        .backend()
+                        .read_file(&path, &ReadOptions::default())
+                        .await?;
+                    crate::common::collect_stream(stream).await?.to_vec()
+                };
+                (entry, data)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let qid = self.server.qid(&entry);
+        let iounit = self.iounit();
+        let dirty = flags & proto::open::TRUNC != 0 && entry.size().unwrap_or(0) > 0;
+        let fid = self.fid(fid)?;
+        // The directory fid now stands for the new file
+        *fid = Fid {
+            path,
+            qid,
+            open: Some(Open::File(OpenFile {
+                flags,
+                spool: Some(Spool { data, dirty }),
+                reader: None,
+            })),
+        };
+        Ok(Rmessage::Lcreate { qid, iounit })
+    }
+
+    async fn read(&mut self, fid: u32, offset: u64, count: u32) -> Reply {
+        let count = count.min(self.iounit()) as usize;
+        let backend = self.server.backend.clone();
+        let fid = self.fid(fid)?;
+        let path = fid.path.clone();
+        let file = match &mut fid.open {
+            Some(Open::File(file)) => file,
+            Some(Open::Dir(_)) => return Err(CfkError::NotAFile(path.to_string())),
+            None => return Err(CfkError::Other("read of unopened fid".into())),
+        };
+
+        if let Some(spool) = &file.spool {
+            let start = (offset as usize).min(spool.data.len());
+            let end = spool.data.len().min(start + count);
+            return Ok(Rmessage::Read {
+                data: Bytes::copy_from_slice(&spool.data[start..end]),
+            });
+        }
+
+        // Continue the stream for a sequential read, otherwise start anew
+        let reader = match file.reader.take() {
+            Some(reader) if reader.offset == offset => reader,
+            _ => {
+                let options = ReadOptions {
+                    range: Some((offset, u64::MAX)),
+                    ..Default::default()
+                };
+                Reader {
+                    stream: backend.read_file(&path, &options).await?,
+                    offset,
+                    pending: Bytes::new(),
+                }
+            }
+        };
+        let mut reader = reader;
+        let mut data = BytesMut::new();
+        while data.len() < count {
+            if reader.pending.is_empty() {
+                match reader.stream.next().await {
+                    Some(chunk) => reader.pending = chunk?,
+                    None => break,
+                }
+            }
+            let take = reader.pending.len().min(count - data.len());
+            data.extend_from_slice(&reader.pending.split_to(take));
+        }
+        reader.offset += data.len() as u64;
+        file.reader = Some(reader);
+        Ok(Rmessage::Read {
+            data: data.freeze(),
+        })
+    }
+
+    fn write(&mut self, fid: u32, offset: u64, data: &[u8]) -> Reply {
+        let max_file_size = self.server.options.max_file_size;
+        let fid = self.fid(fid)?;
+        let spool = match &mut fid.open {
+            Some(Open::File(OpenFile {
+                spool: Some(spool),
+                flags,
+                ..
+            })) => {
+                let offset = if *flags & proto::open::APPEND != 0 {
+                    spool.data.len() as u64
+                } else {
+                    offset
+                };
+                (spool, offset)
+            }
+            _ => {
+                return Ok(Rmessage::Lerror {
+                    ecode: errno::EBADF,
+                })
+            }
+        };
+        let (spool, offset) = spool;
+        let end = offset
+            .checked_add(data.len() as u64)
+            .filter(|&end| end <= max_file_size)
+            .and_then(|end| usize::try_from(end).ok());
+        let Some(end) = end else {
+            return Ok(Rmessage::Lerror {
+                ecode: errno::EFBIG,
+            });
+        };
+        let offset = end - data.len();
+        if spool.data.len() < end {
+            spool.data.resize(end, 0);
+        }
+        spool.data[offset..end].copy_from_slice(data);
+        spool.dirty = true;
+        Ok(Rmessage::Write {
+            count: data.len() as u32,
+        })
+    }
+
+    async fn readdir(&mut self, fid: u32, offset: u64, count: u32) -> Reply {
+        let server = self.server;
+        let dir = self.fid(fid)?;
+        let path = dir.path.clone();
+        let cached = match &mut dir.open {
+            Some(Open::Dir(entries)) => entries,
+            _ => return Err(CfkError::NotADirectory(path.to_string())),
+        };
+
+        // Listing again at offset 0 is a rewinddir
+        if cached.is_none() || offset == 0 {
+            let mut options = ListOptions {
+                include_hidden: true,
+                ..Default::default()
+            };
+            let mut listed = Vec::new();
+            loop {
+                let listing = server.backend.list_directory(&path, &options).await?;
+                listed.extend(listing.entries);
+                match listing.cursor {
+                    Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                    _ => break,
+                }
+            }
+            let entries = listed
+                .iter()
+                .enumerate()
+                .filter_map(|(i, entry)| {
+                    Some(DirEntry {
+                        qid: server.qid(entry),
+                        offset: i as u64 + 1,
+                        ty: match entry.kind {
+                            EntryKind::Directory => DT_DIR,
+                            EntryKind::Symlink => DT_LNK,
+                            _ => DT_REG,
+                        },
+                        name: entry.name()?.to_string(),
+                    })
+                })
+                .collect();
+            *cached = Some(entries);
+        }
+
+        let mut data = BytesMut::new();
+        for entry in cached.iter().flatten().skip(offset as usize) {
+            if data.len() + entry.size() > count as usize {
+                break;
+            }
+            entry.encode(&mut data);
+        }
+        Ok(Rmessage::Readdir {
+            data: data.freeze(),
+        })
+    }
+
+    async fn setattr(&mut self, fid: u32, attr: SetAttr) -> Reply {
+        let backend = self.server.backend.clone();
+        let max_file_size = self.server.options.max_file_size;
+        let fid = self.fid(fid)?;
+        let path = fid.path.clone();
+
+        if attr.valid & proto::setattr::SIZE != 0 {
+            let size = match usize::try_from(attr.size) {
+                Ok(size) if attr.size <= max_file_size => size,
+                _ => {
+                    return Ok(Rmessage::Lerror {
+                        ecode: errno::EFBIG,
+                    })
+                }
+            };
+            match &mut fid.open {
+                Some(Open::File(OpenFile {
+                    spool: Some(spool), ..
+                })) => {
+                    spool.data.resize(size, 0);
+                    spool.dirty = true;
+                }
+                _ => {
+                    let data = if size == 0 {
+                        Vec::new()
+                    } else {
+                        let stream = backend.read_file(&path, &ReadOptions::default()).await?;
+                        let mut data = crate::common::collect_stream(stream).await?.to_vec();
+                        data.resize(size, 0);
+                        data
+                    };
+                    let options = WriteOptions {
+                        overwrite: true,
+                        ..Default::default()
+                    };
+                    backend.write_file(&path, data.into(), &options).await?;
+                }
+            }
+        }
+
+        let mut metadata = Metadata::default();
+        if attr.valid & proto::setattr::MODE != 0 {
+            metadata.permissions = Some(cfk_core::metadata::Permissions::new(attr.mode & 0o7777));
+        }
+        let time = |(sec, nsec): (u64, u64), set: bool| {
+            if set {
+                chrono::DateTime::from_timestamp(sec as i64, nsec as u32)
+            } else {
+                Some(chrono::Utc::now())
+            }
+        };
+        if attr.valid & proto::setattr::MTIME != 0 {
+            metadata.modified = time(attr.mtime, attr.valid & proto::setattr::MTIME_SET != 0);
+        }
+        if attr.valid & proto::setattr::ATIME != 0 {
+            metadata.accessed = time(attr.atime, attr.valid & proto::setattr::ATIME_SET != 0);
+        }
+        if metadata.permissions.is_some()
+            || metadata.modified.is_some()
+            || metadata.accessed.is_some()
+        {
+            // Most backends keep no modes or times; like a FAT mount, going
+            // without them beats failing chmod and touch
+            match backend.set_metadata(&path, &metadata).await {
+                Ok(()) | Err(CfkError::Unsupported(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Rmessage::Setattr)
+    }
+
+    async fn statfs(&mut self) -> Reply {
+        let space = self.backend().get_space_info().await?;
+        let blocks = |bytes: Option<u64>| bytes.unwrap_or(0) / BLOCK_SIZE;
+        let total = space.total.or_else(|| Some(space.used? + space.available?));
+        Ok(Rmessage::Statfs(StatFs {
+            fs_type: V9FS_MAGIC,
+            bsize: BLOCK_SIZE as u32,
+            blocks: blocks(total),
+            bfree: blocks(space.available),
+            bavail: blocks(space.available),
+            namelen: 255,
+            ..Default::default()
+        }))
+    }
+
+    async fn rename(&mut self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<()> {
+        // POSIX rename replaces the target
+        let options = MoveOptions { overwrite: true };
+        self.backend().rename(from, to, &options).await?;
+
+        // Fids inside the renamed tree follow it
+        let depth = from.segments.len();
+        for fid in self.fids.values_mut() {
+            if fid.path.segments.starts_with(&from.segments) {
+                let mut segments = to.segments.clone();
+                segments.extend_from_slice(&fid.path.segments[depth..]);
+                fid.path.segments = segments;
+            }
+        }
+        Ok(())
+    }
+
+    /// Store what a fid wrote and forget it
+    async fn release(&mut self, fid: Fid) -> CfkResult<()> {
+        if let Some(Open::File(OpenFile {
+            spool: Some(mut spool),
+            ..
+        })) = fid.open
+        {
+            store(self.backend(), &fid.path, &mut spool).await?;
+        }
+        Ok(())
+    }
+
+    async fn clunk_all(&mut self) {
+        for (_, fid) in std::mem::take(&mut self.fids) {
+            let path = fid.path.clone();
+            if let Err(e) = self.release(fid).await {
+                tracing::warn!("9p: writing {} failed: {}", path, e);
+            }
+        }
+    }
+}
+
+async fn store(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+    spool: &mut Spool,
+) -> CfkResult<()> {
+    if !spool.dirty {
+        return Ok(());
+    }
+    let options = WriteOptions {
+        overwrite: true,
+        ..Default::default()
+    };
+    backend
+        .write_file(path, Bytes::copy_from_slice(&spool.data), &options)
+        .await?;
+    spool.dirty = false;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ninep::{NinePBackend, NinePConfig};
+    use crate::LocalBackend;
+    use tempfile::TempDir;
+
+    /// A client attached to a server exporting `tmp/export`
+    async fn serve(tmp: &TempDir, options: NinePServerOptions) -> NinePBackend {
+        std::fs::create_dir_all(tmp.path().join("export")).unwrap();
+        let local: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let root = VirtualPath::new("local", "/export");
+        let server = Arc::new(NinePServer::new(local, root, options));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(server.serve(listener));
+        NinePBackend::new(
+            "nine",
+            NinePConfig {
+                address,
+                msize: 8192,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("nine", p)
+    }
+
+    #[tokio::test]
+    async fn test_write_read_list() {
+        let tmp = TempDir::new().unwrap();
+        let client = serve(&tmp, NinePServerOptions::default()).await;
+
+        client.create_directory(&path("/docs")).await.unwrap();
+        // Larger than one iounit, so it takes several Twrite and Tread
+        let data: Bytes = (0..20_000u32).map(|i| i as u8).collect::<Vec<_>>().into();
+        let options = WriteOptions::default();
+        client
+            .write_file(&path("/docs/a.bin"), data.clone(), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(tmp.path().join("export/docs/a.bin")).unwrap(),
+            data
+        );
+
+        let stream = client
+            .read_file(&path("/docs/a.bin"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(crate::common::collect_stream(stream).await.unwrap(), data);
+
+        let options = ReadOptions {
+            range: Some((10_000, 10_010)),
+            ..Default::default()
+        };
+        let stream = client.read_file(&path("/docs/a.bin"), &options).await.unwrap();
+        assert_eq!(
+            crate::common::collect_stream(stream).await.unwrap(),
+            data.slice(10_000..10_010)
+        );
+
+        let entry = client.get_metadata(&path("/docs/a.bin")).await.unwrap();
+        assert_eq!(entry.size(), Some(20_000));
+
+        let listing = client
+            .list_directory(&path("/"), &ListOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].name(), Some("docs"));
+        assert!(listing.entries[0].is_directory());
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_delete() {
+        let tmp = TempDir::new().unwrap();
+        let client = serve(&tmp, NinePServerOptions::default()).await;
+        let options = WriteOptions::default();
+        client
+            .write_file(&path("/old.txt"), Bytes::from("hello"), &options)
+            .await
+            .unwrap();
+
+        client
+            .rename(&path("/old.txt"), &path("/new.txt"), &MoveOptions::default())
+            .await
+            .unwrap();
+        assert!(!tmp.path().join("export/old.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("export/new.txt")).unwrap(),
+            "hello"
+        );
+
+        client
+            .delete(&path("/new.txt"), &DeleteOptions::default())
+            .await
+            .unwrap();
+        assert!(!tmp.path().join("export/new.txt").exists());
+        assert!(matches!(
+            client.get_metadata(&path("/new.txt")).await,
+            Err(CfkError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_walk_stays_in_export() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("secret"), "outside").unwrap();
+        let client = serve(&tmp, NinePServerOptions::default()).await;
+
+        let listing = client
+            .list_directory(&path("/.."), &ListOptions::default())
+            .await
+            .unwrap();
+        assert!(listing.entries.is_empty());
+        assert!(client.get_metadata(&path("/../secret")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir(tmp.path().join("export")).unwrap();
+        std::fs::write(tmp.path().join("export/file"), "data").unwrap();
+        let options = NinePServerOptions {
+            read_only: true,
+            ..Default::default()
+        };
+        let client = serve(&tmp, options).await;
+
+        let stream = client
+            .read_file(&path("/file"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(crate::common::collect_stream(stream).await.unwrap(), "data");
+
+        let result = client
+            .write_file(&path("/other"), Bytes::from("x"), &WriteOptions::default())
+            .await;
+        assert!(matches!(result, Err(CfkError::PermissionDenied(_))));
+        assert!(client.create_directory(&path("/dir")).await.is_err());
+        assert!(client
+            .delete(&path("/file"), &DeleteOptions::default())
+            .await
+            .is_err());
+        assert!(tmp.path().join("export/file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sizes_past_the_limit_fail_with_efbig() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir(tmp.path().join("export")).unwrap();
+        std::fs::write(tmp.path().join("export/small"), "data").unwrap();
+        let local: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let options = NinePServerOptions {
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        let server = NinePServer::new(local, VirtualPath::new("local", "/export"), options);
+        // Straight to the session, to send what a well-behaved client would not
+        let mut session = Session {
+            server: &server,
+            msize: server.options.msize,
+            fids: HashMap::new(),
+        };
+        let attach = Tmessage::Attach {
+            fid: 0,
+            afid: proto::NOFID,
+            uname: String::new(),
+            aname: String::new(),
+            n_uname: 0,
+        };
+        session.handle(attach).await.unwrap();
+        let walk = Tmessage::Walk {
+            fid: 0,
+            newfid: 1,
+            names: vec!["small".into()],
+        };
+        session.handle(walk).await.unwrap();
+        let create = Tmessage::Lcreate {
+            fid: 0,
+            name: "big".into(),
+            flags: proto::open::RDWR,
+            mode: 0o644,
+            gid: 0,
+        };
+        session.handle(create).await.unwrap();
+
+        let efbig = Rmessage::Lerror {
+            ecode: errno::EFBIG,
+        };
+        let write = |offset| Tmessage::Write {
+            fid: 0,
+            offset,
+            data: Bytes::from_static(b"data"),
+        };
+        assert_eq!(session.handle(write(u64::MAX)).await.unwrap(), efbig);
+        assert_eq!(session.handle(write(1021)).await.unwrap(), efbig);
+        assert_eq!(
+            session.handle(write(1020)).await.unwrap(),
+            Rmessage::Write { count: 4 }
+        );
+
+        let truncate = |fid, size| Tmessage::Setattr {
+            fid,
+            attr: SetAttr {
+                valid: proto::setattr::SIZE,
+                size,
+                ..Default::default()
+            },
+        };
+        // The file being written, then one that is not open
+        for fid in [0, 1] {
+            assert_eq!(session.handle(truncate(fid, 1 << 40)).await.unwrap(), efbig);
+            assert_eq!(
+                session.handle(truncate(fid, u64::MAX)).await.unwrap(),
+                efbig
+            );
+            assert_eq!(
+                session.handle(truncate(fid, 10)).await.unwrap(),
+                Rmessage::Setattr
+            );
+        }
+        assert_eq!(
+            std::fs::read(tmp.path().join("export/small")).unwrap(),
+            b"data\0\0\0\0\0\0"
+        );
+    }
+}
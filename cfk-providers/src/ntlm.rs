@@ -0,0 +1,466 @@
+//! NTLMv2 authentication wrapped in SPNEGO
+//!
+//! Only the initiator side that an SMB session setup needs: the NEGOTIATE
+//! message, the AUTHENTICATE answer to the server's CHALLENGE, and the
+//! session key both ends derive from it. No key exchange is negotiated, so
+//! the session key is the NTLMv2 session base key.
+
+use cfk_core::{CfkError, CfkResult};
+use hmac::{Hmac, Mac};
+use md4::{Digest, Md4};
+use md5::Md5;
+
+type HmacMd5 = Hmac<Md5>;
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_SIGN: u32 = 0x0000_0010;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ANONYMOUS: u32 = 0x0000_0800;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const NEGOTIATE_TARGET_INFO: u32 = 0x0080_0000;
+const NEGOTIATE_128: u32 = 0x2000_0000;
+const NEGOTIATE_56: u32 = 0x8000_0000;
+
+/// Everything the client offers; the server answers with a subset
+const FLAGS: u32 = NEGOTIATE_UNICODE
+    | REQUEST_TARGET
+    | NEGOTIATE_SIGN
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY
+    | NEGOTIATE_TARGET_INFO
+    | NEGOTIATE_128
+    | NEGOTIATE_56;
+
+/// Target info pair carrying the server's clock as a FILETIME
+const AV_TIMESTAMP: u16 = 7;
+const AV_EOL: u16 = 0;
+
+/// 1601-01-01 to 1970-01-01 in 100ns intervals
+const FILETIME_UNIX_DIFF: u64 = 116_444_736_000_000_000;
+
+/// OID 1.3.6.1.5.5.2, SPNEGO
+const SPNEGO_OID: &[u8] = &[0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+/// OID 1.3.6.1.4.1.311.2.2.10, NTLMSSP
+const NTLMSSP_OID: &[u8] = &[
+    0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a,
+];
+
+/// negState value a server sends when it refuses the credentials
+const NEG_STATE_REJECT: u8 = 2;
+
+/// A user's NTLM credentials
+pub(crate) struct Credentials<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub domain: &'a str,
+}
+
+/// The NEGOTIATE message opening the exchange
+pub(crate) fn negotiate(anonymous: bool) -> Vec<u8> {
+    let flags = if anonymous {
+        FLAGS | NEGOTIATE_ANONYMOUS
+    } else {
+        FLAGS
+    };
+    let mut msg = SIGNATURE.to_vec();
+    msg.extend_from_slice(&1u32.to_le_bytes());
+    msg.extend_from_slice(&flags.to_le_bytes());
+    // Empty domain and workstation fields
+    msg.extend_from_slice(&[0; 16]);
+    msg
+}
+
+/// The server's CHALLENGE message
+pub(crate) struct Challenge {
+    pub flags: u32,
+    pub server_challenge: [u8; 8],
+    pub target_info: Vec<u8>,
+}
+
+impl Challenge {
+    pub(crate) fn parse(msg: &[u8]) -> CfkResult<Self> {
+        let malformed = || CfkError::AuthFailed("malformed NTLM challenge".into());
+        if msg.len() < 48 || &msg[..8] != SIGNATURE || le32(msg, 8) != 2 {
+            return Err(malformed());
+        }
+
+        let info_len = le16(msg, 40) as usize;
+        let info_offset = le32(msg, 44) as usize;
+        let target_info = msg
+            .get(info_offset..info_offset + info_len)
+            .ok_or_else(malformed)?
+            .to_vec();
+
+        let mut server_challenge = [0; 8];
+        server_challenge.copy_from_slice(&msg[24..32]);
+        Ok(Self {
+            flags: le32(msg, 20),
+            server_challenge,
+            target_info,
+        })
+    }
+
+    /// The server's clock, when it sent it
+    fn timestamp(&self) -> Option<u64> {
+        let mut pairs = self.target_info.as_slice();
+        while pairs.len() >= 4 {
+            let id = le16(pairs, 0);
+            let len = le16(pairs, 2) as usize;
+            let value = pairs.get(4..4 + len)?;
+            match id {
+                AV_EOL => return None,
+                AV_TIMESTAMP if len == 8 => return Some(le64(value, 0)),
+                _ => pairs = &pairs[4 + len..],
+            }
+        }
+        None
+    }
+}
+
+/// The AUTHENTICATE message and the session key it establishes
+pub(crate) struct Authenticate {
+    pub message: Vec<u8>,
+    /// `None` for an anonymous session
+    pub session_key: Option<[u8; 16]>,
+}
+
+/// Answer `challenge`, anonymously when there are no credentials
+pub(crate) fn authenticate(
+    challenge: &Challenge,
+    credentials: Option<&Credentials>,
+    client_challenge: [u8; 8],
+) -> Authenticate {
+    let Some(credentials) = credentials else {
+        let flags = (challenge.flags & FLAGS) | NEGOTIATE_ANONYMOUS;
+        return Authenticate {
+            message: authenticate_message(flags, "", "", &[0], &[]),
+            session_key: None,
+        };
+    };
+
+    let key = response_key_nt(
+        credentials.username,
+        credentials.domain,
+        credentials.password,
+    );
+    let (lm, timestamp) = match challenge.timestamp() {
+        // With a server timestamp the LMv2 response is left out
+        Some(timestamp) => (vec![0; 24], timestamp),
+        None => (
+            lmv2_response(&key, &challenge.server_challenge, &client_challenge),
+            now_filetime(),
+        ),
+    };
+    let (nt, proof) = ntlmv2_response(
+        &key,
+        &challenge.server_challenge,
+        &client_challenge,
+        timestamp,
+        &challenge.target_info,
+    );
+
+    Authenticate {
+        message: authenticate_message(
+            challenge.flags & FLAGS,
+            credentials.domain,
+            credentials.username,
+            &lm,
+            &nt,
+        ),
+        session_key: Some(hmac_md5(&key, &[&proof])),
+    }
+}
+
+fn authenticate_message(flags: u32, domain: &str, user: &str, lm: &[u8], nt: &[u8]) -> Vec<u8> {
+    let domain = utf16(domain);
+    let user = utf16(user);
+    // Lm, Nt, Domain, User, Workstation and session key fields, then flags
+    let mut offset = 64u32;
+    let mut field = |len: usize| {
+        let mut out = Vec::with_capacity(8);
+        out.extend_from_slice(&(len as u16).to_le_bytes());
+        out.extend_from_slice(&(len as u16).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        offset += len as u32;
+        out
+    };
+
+    let mut msg = SIGNATURE.to_vec();
+    msg.extend_from_slice(&3u32.to_le_bytes());
+    msg.extend(field(lm.len()));
+    msg.extend(field(nt.len()));
+    msg.extend(field(domain.len()));
+    msg.extend(field(user.len()));
+    msg.extend(field(0));
+    msg.extend(field(0));
+    msg.extend_from_slice(&flags.to_le_bytes());
+    for part in [lm, nt, &domain, &user] {
+        msg.extend_from_slice(part);
+    }
+    msg
+}
+
+/// NTOWFv2: the key every NTLMv2 response is computed with
+pub(crate) fn response_key_nt(username: &str, domain: &str, password: &str) -> [u8; 16] {
+    let nt_hash: [u8; 16] = Md4::digest(utf16(password)).into();
+    let identity = utf16(&format!("{}{}", username.to_uppercase(), domain));
+    hmac_md5(&nt_hash, &[&identity])
+}
+
+pub(crate) fn lmv2_response(
+    key: &[u8; 16],
+    server_challenge: &[u8; 8],
+    client_challenge: &[u8; 8],
+) -> Vec<u8> {
+    let mut response = hmac_md5(key, &[server_challenge, client_challenge]).to_vec();
+    response.extend_from_slice(client_challenge);
+    response
+}
+
+/// The NTLMv2 response and the NTProofStr at its start
+pub(crate) fn ntlmv2_response(
+    key: &[u8; 16],
+    server_challenge: &[u8; 8],
+    client_challenge: &[u8; 8],
+    timestamp: u64,
+    target_info: &[u8],
+) -> (Vec<u8>, [u8; 16]) {
+    let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    blob.extend_from_slice(&timestamp.to_le_bytes());
+    blob.extend_from_slice(client_challenge);
+    blob.extend_from_slice(&[0; 4]);
+    blob.extend_from_slice(target_info);
+    blob.extend_from_slice(&[0; 4]);
+
+    let proof = hmac_md5(key, &[server_challenge, &blob]);
+    let mut response = proof.to_vec();
+    response.extend_from_slice(&blob);
+    (response, proof)
+}
+
+fn hmac_md5(key: &[u8], parts: &[&[u8]]) -> [u8; 16] {
+    let mut mac = HmacMd5::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn now_filetime() -> u64 {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    FILETIME_UNIX_DIFF + since_epoch.as_nanos() as u64 / 100
+}
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn le16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn le32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+/// SPNEGO NegTokenInit offering NTLMSSP, carrying its first token
+pub(crate) fn spnego_init(token: &[u8]) -> Vec<u8> {
+    let mech_types = der(0xa0, &der(0x30, NTLMSSP_OID));
+    let mech_token = der(0xa2, &der(0x04, token));
+    let init = der(0xa0, &der(0x30, &[mech_types, mech_token].concat()));
+    der(0x60, &[SPNEGO_OID, &init].concat())
+}
+
+/// SPNEGO NegTokenResp carrying a follow-up token
+pub(crate) fn spnego_response(token: &[u8]) -> Vec<u8> {
+    der(0xa1, &der(0x30, &der(0xa2, &der(0x04, token))))
+}
+
+/// The mechanism token inside a server's NegTokenResp, or the blob itself
+/// if the server answered with bare NTLMSSP
+pub(crate) fn spnego_token(blob: &[u8]) -> CfkResult<Vec<u8>> {
+    if blob.starts_with(SIGNATURE) {
+        return Ok(blob.to_vec());
+    }
+    let malformed = || CfkError::AuthFailed("malformed SPNEGO response".into());
+
+    let (tag, resp, _) = der_next(blob).ok_or_else(malformed)?;
+    let (seq_tag, mut fields, _) = der_next(resp).ok_or_else(malformed)?;
+    if tag != 0xa1 || seq_tag != 0x30 {
+        return Err(malformed());
+    }
+
+    let mut token = None;
+    while let Some((tag, value, rest)) = der_next(fields) {
+        match (tag, der_next(value)) {
+            (0xa0, Some((0x0a, [NEG_STATE_REJECT], _))) => {
+                return Err(CfkError::AuthFailed(
+                    "server rejected the credentials".into(),
+                ))
+            }
+            (0xa2, Some((0x04, inner, _))) => token = Some(inner.to_vec()),
+            _ => {}
+        }
+        fields = rest;
+    }
+    token.ok_or_else(malformed)
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Split off the first DER element: its tag, its content, and what follows
+fn der_next(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = buf.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > std::mem::size_of::<usize>() || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        s.split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).unwrap())
+            .collect()
+    }
+
+    /// The NTLMv2 example of MS-NLMP section 4.2.4
+    #[test]
+    fn test_ntlmv2_matches_specification_example() {
+        let key = response_key_nt("User", "Domain", "Password");
+        assert_eq!(
+            key.to_vec(),
+            hex("0c 86 8a 40 3b fd 7a 93 a3 00 1e f2 2e f0 2e 3f")
+        );
+
+        let server_challenge = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        let client_challenge = [0xaa; 8];
+        assert_eq!(
+            lmv2_response(&key, &server_challenge, &client_challenge),
+            hex("86 c3 50 97 ac 9c ec 10 25 54 76 4a 57 cc cc 19 aa aa aa aa aa aa aa aa")
+        );
+
+        let mut target_info = vec![0x02, 0x00, 0x0c, 0x00];
+        target_info.extend(utf16("Domain"));
+        target_info.extend([0x01, 0x00, 0x0c, 0x00]);
+        target_info.extend(utf16("Server"));
+        target_info.extend([0; 4]);
+        let (response, proof) =
+            ntlmv2_response(&key, &server_challenge, &client_challenge, 0, &target_info);
+        assert_eq!(
+            proof.to_vec(),
+            hex("68 cd 0a b8 51 e5 1c 96 aa bc 92 7b eb ef 6a 1c")
+        );
+        assert_eq!(&response[16..18], &[1, 1]);
+        assert_eq!(
+            hmac_md5(&key, &[&proof]).to_vec(),
+            hex("8d e4 0c ca db c1 4a 82 f1 5c b0 ad 0d e9 5c a3")
+        );
+    }
+
+    #[test]
+    fn test_spnego_round_trip() {
+        let token = negotiate(false);
+        let init = spnego_init(&token);
+        assert_eq!(init[0], 0x60);
+        assert!(init.windows(NTLMSSP_OID.len()).any(|w| w == NTLMSSP_OID));
+
+        // Long enough to need a multi-byte DER length
+        let long = vec![7; 300];
+        assert_eq!(spnego_token(&spnego_response(&long)).unwrap(), long);
+        assert_eq!(spnego_token(&token).unwrap(), token);
+
+        let reject = der(
+            0xa1,
+            &der(0x30, &der(0xa0, &der(0x0a, &[NEG_STATE_REJECT]))),
+        );
+        assert!(matches!(
+            spnego_token(&reject),
+            Err(CfkError::AuthFailed(_))
+        ));
+        assert!(spnego_token(&[0xa1, 0x05, 0x30]).is_err());
+    }
+
+    /// A CHALLENGE message laid out like the MS-NLMP section 4.2.4
+    /// example: target name "Server", then the Domain and Server AV pairs
+    const CHALLENGE: &str = concat!(
+        "4e 54 4c 4d 53 53 50 00 02 00 00 00 0c 00 0c 00 ",
+        "38 00 00 00 33 82 8a e2 01 23 45 67 89 ab cd ef ",
+        "00 00 00 00 00 00 00 00 24 00 24 00 44 00 00 00 ",
+        "06 00 70 17 00 00 00 0f 53 00 65 00 72 00 76 00 ",
+        "65 00 72 00 02 00 0c 00 44 00 6f 00 6d 00 61 00 ",
+        "69 00 6e 00 01 00 0c 00 53 00 65 00 72 00 76 00 ",
+        "65 00 72 00 00 00 00 00"
+    );
+
+    #[test]
+    fn test_challenge_parses() {
+        let msg = hex(CHALLENGE);
+        let challenge = Challenge::parse(&msg).unwrap();
+        assert_eq!(challenge.flags, 0xe28a_8233);
+        assert_eq!(
+            challenge.server_challenge,
+            [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]
+        );
+        assert_eq!(challenge.target_info.len(), 36);
+        assert_eq!(&challenge.target_info[4..16], utf16("Domain").as_slice());
+        assert_eq!(challenge.timestamp(), None);
+
+        // Windows servers add their clock as an AV pair before the end
+        let mut stamped = msg.clone();
+        stamped.truncate(stamped.len() - 4);
+        stamped.extend([0x07, 0x00, 0x08, 0x00]);
+        stamped.extend(133_444_736_000_000_000u64.to_le_bytes());
+        stamped.extend([0; 4]);
+        stamped[40..42].copy_from_slice(&48u16.to_le_bytes());
+        let challenge = Challenge::parse(&stamped).unwrap();
+        assert_eq!(challenge.timestamp(), Some(133_444_736_000_000_000));
+
+        // Target info that runs past the message, and a NEGOTIATE message
+        assert!(matches!(
+            Challenge::parse(&msg[..msg.len() - 1]),
+            Err(CfkError::AuthFailed(_))
+        ));
+        assert!(Challenge::parse(&negotiate(false)).is_err());
+    }
+}
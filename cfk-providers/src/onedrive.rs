@@ -5,8 +5,11 @@
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeEvent, ChangeKind, ChangeStream, CfkError, CfkResult, Entry, EntryKind, Metadata,
+    ResumableUpload, StorageBackend, StorageCapabilities, UploadSession, VirtualPath,
 };
 use chrono::{DateTime, Utc};
 use oauth2::{
@@ -15,45 +18,93 @@
 };
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+
+use crate::common::{self, ChangeFeed};
+use crate::http;
+use crate::tokens::{AuthRequest, OAuthBackend, OAuthTokens, TokenManager, TokenStore};
 
 const MS_AUTH_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize";
 const MS_TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
 const GRAPH_API_URL: &str = "https://graph.microsoft.com/v1.0";
 
+/// How many times to poll for the result of an asynchronous copy
+const COPY_POLL_ATTEMPTS: u32 = 10;
+
+/// How often to follow the delta link; Graph has webhooks but no long poll
+const DELTA_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Upload session chunk size; Graph wants multiples of 320 KiB
+const UPLOAD_CHUNK_SIZE: u64 = 32 * 320 * 1024;
+
 /// Microsoft OAuth tokens
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OneDriveTokens {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
-    pub expires_at: Option<DateTime<Utc>>,
-}
+pub type OneDriveTokens = OAuthTokens;
 
 /// OneDrive backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct OneDriveConfig {
     pub client_id: String,
+    #[serde(default = "default_redirect_uri")]
     pub redirect_uri: String,
     /// Use OneDrive for Business (SharePoint) instead of personal
+    #[serde(default)]
     pub business: bool,
+    /// Microsoft Graph base URL
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// OAuth token endpoint
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+}
+
+fn default_redirect_uri() -> String {
+    crate::tokens::LOOPBACK_REDIRECT_URI.to_string()
+}
+
+fn default_api_url() -> String {
+    GRAPH_API_URL.to_string()
+}
+
+fn default_token_url() -> String {
+    MS_TOKEN_URL.to_string()
+}
+
+impl OneDriveConfig {
+    /// Configuration for a personal OneDrive
+    pub fn new(client_id: impl Into<String>, redirect_uri: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            redirect_uri: redirect_uri.into(),
+            business: false,
+            api_url: GRAPH_API_URL.to_string(),
+            token_url: MS_TOKEN_URL.to_string(),
+        }
+    }
 }
 
 /// OneDrive storage backend
+///
+/// Clones share tokens.
+#[derive(Clone)]
 pub struct OneDriveBackend {
     id: String,
     config: OneDriveConfig,
-    tokens: Arc<RwLock<Option<OneDriveTokens>>>,
+    auth: Arc<TokenManager>,
     http: Client,
     capabilities: StorageCapabilities,
 }
 
 impl OneDriveBackend {
     pub fn new(id: impl Into<String>, config: OneDriveConfig) -> Self {
+        let id = id.into();
+        let auth = Arc::new(TokenManager::new(&id, &config.token_url, &config.client_id, None));
         Self {
-            id: id.into(),
+            id,
             config,
-            tokens: Arc::new(RwLock::new(None)),
+            auth,
             http: Client::new(),
             capabilities: StorageCapabilities {
                 read: true,
@@ -65,117 +116,240 @@ pub fn new(id: impl Into<String>, config: OneDriveConfig) -> Self {
                 search: true,
                 versioning: true,
                 sharing: true,
+                offline: false,
                 streaming: true,
-                resume: true,
+                range_reads: true,
+                resumable_uploads: true,
+                content_hashing: true,
                 watch: true,
-                metadata: true,
-                thumbnails: true,
-                max_file_size: Some(250 * 1024 * 1024 * 1024), // 250GB
             },
         }
     }
 
-    /// Start OAuth 2.0 + PKCE flow
-    pub fn start_auth(&self) -> (String, PkceCodeVerifier) {
-        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
-            .set_auth_uri(AuthUrl::new(MS_AUTH_URL.to_string()).unwrap())
-            .set_token_uri(TokenUrl::new(MS_TOKEN_URL.to_string()).unwrap())
-            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
-
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    /// Set tokens directly, persisting them to the token store if any
+    pub async fn set_tokens(&self, tokens: OneDriveTokens) -> CfkResult<()> {
+        self.auth.set(tokens).await
+    }
 
-        let (auth_url, _csrf_token) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("Files.ReadWrite.All".to_string()))
-            .add_scope(Scope::new("offline_access".to_string()))
-            .set_pkce_challenge(pkce_challenge)
-            .url();
+    /// Current access token, refreshed first when it is about to expire
+    async fn get_access_token(&self) -> CfkResult<String> {
+        self.auth.access_token(&self.http).await
+    }
 
-        (auth_url.to_string(), pkce_verifier)
+    /// Send an authenticated request and return the checked response
+    async fn send(&self, request: reqwest::RequestBuilder) -> CfkResult<reqwest::Response> {
+        let response = self.send_unchecked(request).await?;
+        http::check_response("onedrive", response).await
     }
 
-    /// Complete OAuth flow with authorization code
-    pub async fn complete_auth(
+    /// Send an authenticated request, leaving the status to the caller
+    async fn send_unchecked(
         &self,
-        code: &str,
-        verifier: PkceCodeVerifier,
-    ) -> CfkResult<OneDriveTokens> {
-        let params = [
-            ("code", code.to_string()),
-            ("grant_type", "authorization_code".to_string()),
-            ("client_id", self.config.client_id.clone()),
-            ("redirect_uri", self.config.redirect_uri.clone()),
-            ("code_verifier", verifier.secret().to_string()),
-        ];
-
-        let response = self
-            .http
-            .post(MS_TOKEN_URL)
-            .form(&params)
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<reqwest::Response> {
+        request
+            .bearer_auth(self.get_access_token().await?)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::Auth(format!("Token exchange failed: {}", error_text)));
-        }
-
-        #[derive(Deserialize)]
-        struct TokenResponse {
-            access_token: String,
-            refresh_token: Option<String>,
-            expires_in: Option<i64>,
-        }
+            .map_err(http::network_error)
+    }
 
-        let token_resp: TokenResponse = response
+    /// Send an authenticated request and decode the JSON response
+    async fn send_json<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> CfkResult<T> {
+        self.send(request)
+            .await?
             .json()
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
-
-        let tokens = OneDriveTokens {
-            access_token: token_resp.access_token,
-            refresh_token: token_resp.refresh_token,
-            expires_at: token_resp
-                .expires_in
-                .map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
-        };
-
-        *self.tokens.write().await = Some(tokens.clone());
-        Ok(tokens)
+            .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
-    /// Set tokens directly
-    pub async fn set_tokens(&self, tokens: OneDriveTokens) {
-        *self.tokens.write().await = Some(tokens);
-    }
-
-    /// Get current access token
-    async fn get_access_token(&self) -> CfkResult<String> {
-        let tokens = self.tokens.read().await;
-        tokens
-            .as_ref()
-            .map(|t| t.access_token.clone())
-            .ok_or_else(|| CfkError::Auth("Not authenticated".into()))
+    /// Percent-encode path segments for a Graph path-based address
+    fn encoded_path(path: &VirtualPath) -> String {
+        path.segments
+            .iter()
+            .map(|s| urlencoding::encode(s).into_owned())
+            .collect::<Vec<_>>()
+            .join("/")
     }
 
     /// Build API path for OneDrive
     fn api_path(&self, path: &VirtualPath) -> String {
         if path.segments.is_empty() {
-            format!("{}/me/drive/root", GRAPH_API_URL)
+            format!("{}/me/drive/root", self.config.api_url)
         } else {
-            let path_str = path.segments.join("/");
-            format!("{}/me/drive/root:/{}", GRAPH_API_URL, path_str)
+            format!(
+                "{}/me/drive/root:/{}:",
+                self.config.api_url,
+                Self::encoded_path(path)
+            )
         }
     }
 
     /// Build children API path
     fn children_path(&self, path: &VirtualPath) -> String {
-        if path.segments.is_empty() {
-            format!("{}/me/drive/root/children", GRAPH_API_URL)
-        } else {
-            let path_str = path.segments.join("/");
-            format!("{}/me/drive/root:/{}:/children", GRAPH_API_URL, path_str)
+        format!("{}/children", self.api_path(path))
+    }
+
+    /// Build content API path
+    fn content_path(&self, path: &VirtualPath) -> String {
+        format!("{}/content", self.api_path(path))
+    }
+
+    async fn get_item(&self, path: &VirtualPath) -> CfkResult<DriveItem> {
+        self.send_json(self.http.get(self.api_path(path)))
+            .await
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+                e => e,
+            })
+    }
+
+    fn parent_and_name(path: &VirtualPath) -> CfkResult<(VirtualPath, String)> {
+        match (path.parent(), path.name()) {
+            (Some(parent), Some(name)) => Ok((parent, name.to_string())),
+            _ => Err(CfkError::InvalidPath(path.to_string())),
+        }
+    }
+
+    /// A delta link that reports changes from now on
+    async fn latest_delta_link(&self) -> CfkResult<String> {
+        let page: DeltaPage = self
+            .send_json(
+                self.http
+                    .get(format!("{}/me/drive/root/delta", self.config.api_url))
+                    .query(&[("token", "latest")]),
+            )
+            .await?;
+        page.delta_link
+            .ok_or_else(|| CfkError::Serialization("delta response without a delta link".into()))
+    }
+}
+
+/// One page of `/delta`
+#[derive(Debug, Deserialize)]
+struct DeltaPage {
+    #[serde(default)]
+    value: Vec<DeltaItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+    #[serde(rename = "@odata.deltaLink")]
+    delta_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeltaItem {
+    id: String,
+    name: Option<String>,
+    parent_reference: Option<ParentReference>,
+    deleted: Option<serde_json::Value>,
+    root: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParentReference {
+    /// `/drive/root:/Documents`, absent for deleted items on personal drives
+    path: Option<String>,
+}
+
+/// Changes anywhere in the drive, from the `/delta` feed.
+///
+/// Delta reports items as they are now, so moves are told apart from edits
+/// by remembering where each item seen so far was.
+struct DeltaChanges {
+    backend: OneDriveBackend,
+    link: String,
+    /// Where each item seen so far was, by ID
+    paths: HashMap<String, VirtualPath>,
+    /// Reported as rescanned when the feed restarts or an item nobody saw
+    /// is deleted
+    watched: VirtualPath,
+    polled: bool,
+}
+
+impl DeltaChanges {
+    fn path_of(&self, item: &DeltaItem) -> Option<VirtualPath> {
+        let parent = item.parent_reference.as_ref()?.path.as_deref()?;
+        let (_, below) = parent.split_once("root:")?;
+        let below = urlencoding::decode(below)
+            .map(|b| b.into_owned())
+            .unwrap_or_else(|_| below.to_string());
+        Some(VirtualPath::new(
+            &self.backend.id,
+            format!("{}/{}", below, item.name.as_deref()?),
+        ))
+    }
+
+    fn event(&mut self, item: DeltaItem) -> Option<ChangeEvent> {
+        let old = self.paths.get(&item.id).cloned();
+        let new = self.path_of(&item);
+
+        // Cached paths below a moved or removed folder are stale
+        if let Some(old) = old.as_ref().filter(|old| new.as_ref() != Some(*old)) {
+            self.paths.retain(|_, path| !path.starts_with(old));
+        }
+        if item.deleted.is_some() {
+            self.paths.remove(&item.id);
+            return Some(match old.or(new) {
+                Some(path) => ChangeEvent::new(path, ChangeKind::Deleted),
+                None => ChangeEvent::new(self.watched.clone(), ChangeKind::Rescan),
+            });
+        }
+
+        let path = new?;
+        self.paths.insert(item.id, path.clone());
+        Some(match old {
+            Some(from) if from != path => ChangeEvent::new(path, ChangeKind::Renamed { from }),
+            _ => ChangeEvent::new(path, ChangeKind::Modified),
+        })
+    }
+}
+
+#[async_trait]
+impl ChangeFeed for DeltaChanges {
+    async fn poll(&mut self) -> CfkResult<Vec<ChangeEvent>> {
+        if self.polled {
+            tokio::time::sleep(DELTA_POLL_INTERVAL).await;
+        }
+        self.polled = true;
+
+        let mut events = Vec::new();
+        let mut url = self.link.clone();
+        loop {
+            let response = self.backend.send_unchecked(self.backend.http.get(&url)).await?;
+            // The delta token expired: start over and have everything
+            // looked at again
+            if response.status() == reqwest::StatusCode::GONE {
+                self.link = self.backend.latest_delta_link().await?;
+                self.paths.clear();
+                return Ok(vec![ChangeEvent::new(self.watched.clone(), ChangeKind::Rescan)]);
+            }
+            let page: DeltaPage = http::check_response("onedrive", response)
+                .await?
+                .json()
+                .await
+                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+
+            for item in page.value.into_iter().filter(|item| item.root.is_none()) {
+                let Some(event) = self.event(item) else {
+                    continue;
+                };
+                // One look at everything covers any number of unplaced
+                // deletes
+                if event.kind != ChangeKind::Rescan || !events.contains(&event) {
+                    events.push(event);
+                }
+            }
+            match (page.next_link, page.delta_link) {
+                (Some(next), _) => url = next,
+                (None, link) => {
+                    self.link = link.unwrap_or(url);
+                    return Ok(events);
+                }
+            }
         }
     }
 }
@@ -187,11 +361,11 @@ struct DriveItem {
     id: String,
     name: String,
     size: Option<u64>,
+    e_tag: Option<String>,
     created_date_time: Option<String>,
     last_modified_date_time: Option<String>,
     folder: Option<FolderFacet>,
     file: Option<FileFacet>,
-    parent_reference: Option<ParentReference>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -215,12 +389,6 @@ struct FileHashes {
     sha256_hash: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ParentReference {
-    path: Option<String>,
-}
-
 impl DriveItem {
     fn to_entry(&self, backend_id: &str, base_path: &str) -> Entry {
         let path_str = if base_path.is_empty() {
@@ -236,28 +404,37 @@ fn to_entry(&self, backend_id: &str, base_path: &str) -> Entry {
             EntryKind::File
         };
 
-        let mut metadata = Metadata::default();
-        metadata.size = self.size;
+        let parse_time = |s: &Option<String>| {
+            s.as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|dt| dt.with_timezone(&Utc))
+        };
+
+        let mut metadata = Metadata {
+            size: self.size,
+            modified: parse_time(&self.last_modified_date_time),
+            created: parse_time(&self.created_date_time),
+            provider_id: Some(self.id.clone()),
+            revision: self.e_tag.clone(),
+            ..Default::default()
+        };
 
         if let Some(ref file) = self.file {
             metadata.mime_type = file.mime_type.clone();
             if let Some(ref hashes) = file.hashes {
-                metadata.checksum = hashes
+                // Personal accounts only expose SHA-1, Business only QuickXorHash
+                metadata.content_hash = hashes
                     .sha256_hash
                     .clone()
-                    .or_else(|| hashes.sha1_hash.clone());
+                    .or_else(|| hashes.sha1_hash.clone())
+                    .or_else(|| hashes.quick_xor_hash.clone());
             }
         }
 
-        if let Some(ref modified) = self.last_modified_date_time {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(modified) {
-                metadata.modified = Some(dt.with_timezone(&Utc));
-            }
-        }
-        if let Some(ref created) = self.created_date_time {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(created) {
-                metadata.created = Some(dt.with_timezone(&Utc));
-            }
+        if let Some(count) = self.folder.as_ref().and_then(|f| f.child_count) {
+            metadata
+                .custom
+                .insert("child_count".to_string(), count.to_string());
         }
 
         Entry {
@@ -268,198 +445,331 @@ fn to_entry(&self, backend_id: &str, base_path: &str) -> Entry {
     }
 }
 
-#[async_trait]
-impl StorageBackend for OneDriveBackend {
-    fn id(&self) -> &str {
-        &self.id
-    }
+fn base_path_of(path: &VirtualPath) -> String {
+    path.parent()
+        .map(|p| p.segments.join("/"))
+        .unwrap_or_default()
+}
 
-    fn display_name(&self) -> &str {
-        if self.config.business {
-            "OneDrive for Business"
-        } else {
-            "OneDrive"
+/// Start of the first range Graph still expects, from `nextExpectedRanges`
+fn next_expected_offset(status: &serde_json::Value) -> Option<u64> {
+    status["nextExpectedRanges"]
+        .as_array()?
+        .iter()
+        .filter_map(|r| r.as_str()?.split('-').next()?.parse().ok())
+        .min()
+}
+
+/// Upload sessions
+///
+/// The session URL is pre-authenticated; Graph rejects requests to it that
+/// carry a bearer token.
+#[async_trait]
+impl ResumableUpload for OneDriveBackend {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Session {
+            upload_url: String,
         }
-    }
 
-    fn capabilities(&self) -> &StorageCapabilities {
-        &self.capabilities
+        let conflict = if options.overwrite { "replace" } else { "fail" };
+        let session: Session = self
+            .send_json(
+                self.http
+                    .post(format!("{}/createUploadSession", self.api_path(path)))
+                    .json(&serde_json::json!({
+                        "item": { "@microsoft.graph.conflictBehavior": conflict }
+                    })),
+            )
+            .await?;
+
+        Ok(UploadSession::new(
+            path.clone(),
+            size,
+            session.upload_url,
+            UPLOAD_CHUNK_SIZE,
+            options.overwrite,
+        ))
     }
 
-    async fn is_available(&self) -> bool {
-        self.tokens.read().await.is_some()
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        let size = data.len() as u64;
+        let response = self
+            .http
+            .put(&session.id)
+            .header("Content-Range", session.content_range(size))
+            .body(data)
+            .send()
+            .await
+            .map_err(http::network_error)?;
+        let response = http::check_response("onedrive", response)
+            .await
+            .map_err(|e| match e {
+                CfkError::Conflict(_) => CfkError::AlreadyExists(session.path.to_string()),
+                e => e,
+            })?;
+
+        // 202 while more is expected, 200/201 with the item once complete
+        if response.status() == reqwest::StatusCode::ACCEPTED {
+            let status: serde_json::Value = response
+                .json()
+                .await
+                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            session.offset = next_expected_offset(&status).unwrap_or(session.offset + size);
+        } else {
+            session.offset = session.size;
+        }
+        Ok(())
     }
 
-    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let url = self.api_path(path);
-
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
         let response = self
             .http
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
+            .get(&session.id)
             .send()
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+            .map_err(http::network_error)?;
+        let status: serde_json::Value = http::check_response("onedrive", response)
+            .await?
+            .json()
+            .await
+            .map_err(|e| CfkError::Serialization(e.to_string()))?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            if status == reqwest::StatusCode::NOT_FOUND {
-                return Err(CfkError::NotFound(path.to_string()));
-            }
-            let error_text = response.text().await.unwrap_or_default();
+        session.offset = next_expected_offset(&status).unwrap_or(session.size);
+        Ok(session.offset)
+    }
+
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        // Graph commits with the last chunk
+        if !session.is_complete() {
             return Err(CfkError::ProviderApi {
                 provider: "onedrive".into(),
-                message: format!("{}: {}", status, error_text),
+                message: format!(
+                    "upload incomplete: {} of {} bytes",
+                    session.offset, session.size
+                ),
             });
         }
+        let item = self.get_item(&session.path).await?;
+        Ok(item.to_entry(&self.id, &base_path_of(&session.path)))
+    }
 
-        let item: DriveItem = response
-            .json()
+    async fn abort(&self, session: &UploadSession) -> CfkResult<()> {
+        let response = self
+            .http
+            .delete(&session.id)
+            .send()
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
-
-        let base_path = if path.segments.len() > 1 {
-            path.segments[..path.segments.len() - 1].join("/")
-        } else {
-            String::new()
-        };
+            .map_err(http::network_error)?;
+        common::ignore_missing(
+            http::check_response("onedrive", response).await.map(drop),
+            &DeleteOptions {
+                force: true,
+                ..Default::default()
+            },
+        )
+    }
+}
 
-        Ok(item.to_entry(&self.id, &base_path))
+#[async_trait]
+impl OAuthBackend for OneDriveBackend {
+    fn with_token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        Arc::get_mut(&mut self.auth)
+            .expect("token store attached before the backend is cloned")
+            .attach_store(store);
+        self
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let url = self.children_path(path);
+    fn redirect_uri(&self) -> &str {
+        &self.config.redirect_uri
+    }
 
-        let mut entries = Vec::new();
-        let mut next_link: Option<String> = Some(url);
+    fn start_auth(&self) -> AuthRequest {
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_auth_uri(AuthUrl::new(MS_AUTH_URL.to_string()).unwrap())
+            .set_token_uri(TokenUrl::new(self.config.token_url.clone()).unwrap())
+            .set_redirect_uri(RedirectUrl::new(self.config.redirect_uri.clone()).unwrap());
 
-        while let Some(url) = next_link.take() {
-            let response = self
-                .http
-                .get(&url)
-                .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-                .send()
-                .await
-                .map_err(|e| CfkError::Network(e.to_string()))?;
+        let (pkce_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
 
-            #[derive(Deserialize)]
-            struct ItemList {
-                value: Vec<DriveItem>,
-                #[serde(rename = "@odata.nextLink")]
-                next_link: Option<String>,
-            }
+        let (auth_url, state) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new("Files.ReadWrite.All".to_string()))
+            .add_scope(Scope::new("offline_access".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
 
-            let list: ItemList = response
-                .json()
-                .await
-                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        AuthRequest {
+            url: auth_url.to_string(),
+            state,
+            verifier,
+        }
+    }
 
-            let base_path = path.segments.join("/");
+    async fn complete_auth(
+        &self,
+        code: &str,
+        verifier: PkceCodeVerifier,
+    ) -> CfkResult<OAuthTokens> {
+        let params = [
+            ("code", code),
+            ("grant_type", "authorization_code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", verifier.secret()),
+        ];
+        self.auth.exchange(&self.http, &params).await
+    }
+}
 
-            for item in list.value {
-                entries.push(item.to_entry(&self.id, &base_path));
-            }
+#[async_trait]
+impl StorageBackend for OneDriveBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
 
-            next_link = list.next_link;
+    fn display_name(&self) -> &str {
+        if self.config.business {
+            "OneDrive for Business"
+        } else {
+            "OneDrive"
         }
+    }
 
-        Ok(entries)
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let url = format!("{}:/content", self.api_path(path));
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.auth.refresh(&self.http).await
+    }
 
-        let response = self
-            .http
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        Some(self)
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "onedrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+    async fn is_available(&self) -> bool {
+        self.auth.is_authenticated().await
+    }
 
-        response
-            .bytes()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let item = self.get_item(path).await?;
+        if path.is_root() {
+            return Ok(Entry::directory(
+                path.clone(),
+                item.to_entry(&self.id, "").metadata,
+            ));
+        }
+        Ok(item.to_entry(&self.id, &base_path_of(path)))
     }
 
-    async fn write_file(&self, path: &VirtualPath, data: Bytes) -> CfkResult<Entry> {
-        let url = format!("{}:/content", self.api_path(path));
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        // The cursor is the opaque `@odata.nextLink` from the previous page
+        let request = match &options.cursor {
+            Some(next_link) => self.http.get(next_link),
+            None => {
+                let mut request = self.http.get(self.children_path(path));
+                if let Some(limit) = options.limit {
+                    request = request.query(&[("$top", limit.to_string())]);
+                }
+                request
+            }
+        };
 
-        let response = self
-            .http
-            .put(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .header("Content-Type", "application/octet-stream")
-            .body(data.to_vec())
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        #[derive(Deserialize)]
+        struct ItemList {
+            value: Vec<DriveItem>,
+            #[serde(rename = "@odata.nextLink")]
+            next_link: Option<String>,
+        }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "onedrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
+        let list: ItemList = self.send_json(request).await?;
+        let base_path = path.segments.join("/");
+
+        let entries = list
+            .value
+            .iter()
+            .filter(|item| options.include_hidden || !item.name.starts_with('.'))
+            .map(|item| item.to_entry(&self.id, &base_path))
+            .collect();
+
+        Ok(DirectoryListing {
+            path: path.clone(),
+            entries,
+            has_more: list.next_link.is_some(),
+            cursor: list.next_link,
+        })
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let mut request = self.http.get(self.content_path(path));
+        if let Some(range) = options.range {
+            request = request.header(reqwest::header::RANGE, http::range_header(range));
         }
+        let response = self.send_unchecked(request).await?;
+        http::ranged_stream("onedrive", response, options.range).await
+    }
 
-        let item: DriveItem = response
-            .json()
+    /// Simple upload; Graph caps this at 4 MB per request
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let conflict = if options.overwrite { "replace" } else { "fail" };
+
+        let item: DriveItem = self
+            .send_json(
+                self.http
+                    .put(self.content_path(path))
+                    .query(&[("@microsoft.graph.conflictBehavior", conflict)])
+                    .header("Content-Type", "application/octet-stream")
+                    .body(data),
+            )
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .map_err(|e| match e {
+                CfkError::Conflict(_) => CfkError::AlreadyExists(path.to_string()),
+                e => e,
+            })?;
 
-        let base_path = if path.segments.len() > 1 {
-            path.segments[..path.segments.len() - 1].join("/")
-        } else {
-            String::new()
-        };
+        Ok(item.to_entry(&self.id, &base_path_of(path)))
+    }
 
-        Ok(item.to_entry(&self.id, &base_path))
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let data = common::collect_stream(stream).await?;
+        self.write_file(path, data, options).await
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let url = self.api_path(path);
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        common::check_delete(self, path, options).await?;
 
-        let response = self
-            .http
-            .delete(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
+        let result = self
+            .send(self.http.delete(self.api_path(path)))
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() && response.status() != reqwest::StatusCode::NO_CONTENT {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CfkError::ProviderApi {
-                provider: "onedrive".into(),
-                message: format!("{}: {}", status, error_text),
-            });
-        }
+            .map(|_| ());
 
-        Ok(())
+        common::ignore_missing(result, options)
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let parent_path = if path.segments.len() > 1 {
-            VirtualPath::new(&self.id, &path.segments[..path.segments.len() - 1].join("/"))
-        } else {
-            VirtualPath::new(&self.id, "")
-        };
-
-        let name = path.segments.last().cloned().unwrap_or_default();
-        let url = self.children_path(&parent_path);
+        let (parent_path, name) = Self::parent_and_name(path)?;
 
         #[derive(Serialize)]
         struct CreateFolder {
@@ -475,46 +785,28 @@ struct CreateFolder {
             conflict_behavior: "fail".to_string(),
         };
 
-        let response = self
-            .http
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        let item: DriveItem = response
-            .json()
+        let item: DriveItem = self
+            .send_json(self.http.post(self.children_path(&parent_path)).json(&body))
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .map_err(|e| match e {
+                CfkError::Conflict(_) => CfkError::AlreadyExists(path.to_string()),
+                e => e,
+            })?;
 
         let base_path = parent_path.segments.join("/");
         Ok(item.to_entry(&self.id, &base_path))
     }
 
-    async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let from_url = self.api_path(from);
-        let to_parent = if to.segments.len() > 1 {
-            VirtualPath::new(&self.id, &to.segments[..to.segments.len() - 1].join("/"))
-        } else {
-            VirtualPath::new(&self.id, "")
-        };
-        let to_name = to.segments.last().cloned().unwrap_or_default();
-
-        // Get the parent folder's drive item id
-        let parent_response = self
-            .http
-            .get(&self.api_path(&to_parent))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
 
-        let parent_item: DriveItem = parent_response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let (to_parent, to_name) = Self::parent_and_name(dest)?;
+        let parent_item = self.get_item(&to_parent).await?;
 
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
@@ -533,62 +825,144 @@ struct ParentRef {
             name: to_name,
         };
 
-        let _response = self
-            .http
-            .post(format!("{}:/copy", from_url))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        self.send(
+            self.http
+                .post(format!("{}/copy", self.api_path(source)))
+                .json(&body),
+        )
+        .await?;
+
+        // Copy is asynchronous in OneDrive; wait for the destination to appear
+        let mut delay = Duration::from_millis(250);
+        for _ in 0..COPY_POLL_ATTEMPTS {
+            match self.get_metadata(dest).await {
+                Err(CfkError::NotFound(_)) => {
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_secs(5));
+                }
+                result => return result,
+            }
+        }
 
-        // Copy is async in OneDrive, return metadata of destination
-        self.get_metadata(to).await
+        Err(CfkError::Timeout)
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let url = self.api_path(from);
-        let to_name = to.segments.last().cloned().unwrap_or_default();
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+
+        let (to_parent, to_name) = Self::parent_and_name(dest)?;
 
         #[derive(Serialize)]
-        struct RenameRequest {
+        #[serde(rename_all = "camelCase")]
+        struct MoveRequest {
             name: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            parent_reference: Option<ParentRef>,
         }
 
-        let body = RenameRequest { name: to_name };
+        #[derive(Serialize)]
+        struct ParentRef {
+            id: String,
+        }
 
-        let response = self
-            .http
-            .patch(&url)
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+        let parent_reference = if source.parent().as_ref() != Some(&to_parent) {
+            Some(ParentRef {
+                id: self.get_item(&to_parent).await?.id,
+            })
+        } else {
+            None
+        };
 
-        let item: DriveItem = response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let body = MoveRequest {
+            name: to_name,
+            parent_reference,
+        };
 
-        let base_path = if to.segments.len() > 1 {
-            to.segments[..to.segments.len() - 1].join("/")
-        } else {
-            String::new()
+        let item: DriveItem = self
+            .send_json(self.http.patch(self.api_path(source)).json(&body))
+            .await?;
+
+        Ok(item.to_entry(&self.id, &base_path_of(dest)))
+    }
+
+    /// Delta on a folder only works on personal drives, so the whole drive
+    /// is followed and changes outside `path` are dropped here
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        self.get_item(path).await?;
+        let changes = DeltaChanges {
+            backend: self.clone(),
+            link: self.latest_delta_link().await?,
+            paths: HashMap::new(),
+            watched: path.clone(),
+            polled: false,
         };
+        Ok(common::change_stream(changes, path.clone(), recursive))
+    }
 
-        Ok(item.to_entry(&self.id, &base_path))
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        #[derive(Deserialize)]
+        struct VersionList {
+            value: Vec<DriveItemVersion>,
+            #[serde(rename = "@odata.nextLink")]
+            next_link: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DriveItemVersion {
+            id: String,
+            last_modified_date_time: DateTime<Utc>,
+            size: Option<u64>,
+            last_modified_by: Option<IdentitySet>,
+        }
+
+        #[derive(Deserialize)]
+        struct IdentitySet {
+            user: Option<Identity>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Identity {
+            display_name: Option<String>,
+        }
+
+        let mut versions = Vec::new();
+        let mut url = format!("{}/versions", self.api_path(path));
+        loop {
+            let list: VersionList = self.send_json(self.http.get(&url)).await?;
+            versions.extend(list.value.into_iter().map(|version| FileVersion {
+                id: version.id,
+                modified: version.last_modified_date_time,
+                size: version.size,
+                author: version
+                    .last_modified_by
+                    .and_then(|by| by.user)
+                    .and_then(|user| user.display_name),
+            }));
+            match list.next_link {
+                Some(next_link) => url = next_link,
+                None => break,
+            }
+        }
+        Ok(versions)
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        let response = self
-            .http
-            .get(format!("{}/me/drive", GRAPH_API_URL))
-            .header("Authorization", format!("Bearer {}", self.get_access_token().await?))
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        let url = format!(
+            "{}/versions/{}/content",
+            self.api_path(path),
+            urlencoding::encode(version_id)
+        );
+        Ok(http::body_stream(self.send(self.http.get(url)).await?))
+    }
 
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         #[derive(Deserialize)]
         struct Drive {
             quota: Option<DriveQuota>,
@@ -601,20 +975,275 @@ struct DriveQuota {
             remaining: Option<u64>,
         }
 
-        let drive: Drive = response
-            .json()
+        let drive: Drive = self
+            .send_json(self.http.get(format!("{}/me/drive", self.config.api_url)))
+            .await?;
+
+        Ok(drive
+            .quota
+            .map(|q| SpaceInfo {
+                total: q.total,
+                used: q.used,
+                available: q.remaining,
+            })
+            .unwrap_or_else(SpaceInfo::unknown))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn backend(server: &MockServer) -> OneDriveBackend {
+        let mut config = OneDriveConfig::new("client", "http://localhost/callback");
+        config.api_url = server.uri();
+        let backend = OneDriveBackend::new("onedrive", config);
+        backend
+            .set_tokens(OneDriveTokens {
+                access_token: "token".into(),
+                refresh_token: None,
+                expires_at: None,
+            })
             .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            .unwrap();
+        backend
+    }
 
-        let quota = drive.quota.unwrap_or(DriveQuota {
-            total: None,
-            used: None,
-            remaining: None,
-        });
+    #[tokio::test]
+    async fn test_list_follows_next_link_cursor() {
+        let server = MockServer::start().await;
+        let next_link = format!("{}/page2", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/Documents:/children"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    {"id": "1", "name": "notes.md", "size": 12, "eTag": "e1",
+                     "file": {"mimeType": "text/markdown", "hashes": {"sha1Hash": "ABC"}}}
+                ],
+                "@odata.nextLink": next_link
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    {"id": "2", "name": "Archive", "folder": {"childCount": 3}}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let dir = VirtualPath::new("onedrive", "Documents");
+
+        let first = backend
+            .list_directory(&dir, &ListOptions::default())
+            .await
+            .unwrap();
+        assert!(first.has_more);
+        let file = &first.entries[0];
+        assert_eq!(file.path.to_path_string(), "/Documents/notes.md");
+        assert_eq!(file.metadata.content_hash.as_deref(), Some("ABC"));
+        assert_eq!(file.metadata.revision.as_deref(), Some("e1"));
+
+        let options = ListOptions {
+            cursor: first.cursor,
+            ..Default::default()
+        };
+        let second = backend.list_directory(&dir, &options).await.unwrap();
+        assert!(!second.has_more);
+        assert!(second.entries[0].is_directory());
+    }
 
-        let total = quota.total.unwrap_or(0);
-        let available = quota.remaining.unwrap_or(0);
+    #[tokio::test]
+    async fn test_write_without_overwrite_sets_fail_behavior() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/me/drive/root:/a.txt:/content"))
+            .and(query_param("@microsoft.graph.conflictBehavior", "fail"))
+            .respond_with(ResponseTemplate::new(409))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let err = backend
+            .write_file(
+                &VirtualPath::new("onedrive", "a.txt"),
+                Bytes::from_static(b"x"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)));
+    }
+
+    #[tokio::test]
+    async fn test_segments_are_percent_encoded() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/My%20Files/a%231.txt:"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "x", "name": "a#1.txt", "size": 1, "file": {}
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let entry = backend
+            .get_metadata(&VirtualPath::new("onedrive", "My Files/a#1.txt"))
+            .await
+            .unwrap();
+        assert_eq!(entry.path.to_path_string(), "/My Files/a#1.txt");
+    }
 
-        Ok((available, total))
+    #[tokio::test]
+    async fn test_versions_and_version_content() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/budget.xlsx:/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    {"id": "2.0", "lastModifiedDateTime": "2024-02-01T00:00:00Z", "size": 12,
+                     "lastModifiedBy": {"user": {"displayName": "Ada"}}},
+                    {"id": "1.0", "lastModifiedDateTime": "2024-01-01T00:00:00Z", "size": 10}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/budget.xlsx:/versions/1.0/content"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"first".to_vec()))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let file = VirtualPath::new("onedrive", "budget.xlsx");
+        let versions = backend.get_versions(&file).await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].author.as_deref(), Some("Ada"));
+        assert_eq!(versions[1].id, "1.0");
+        assert_eq!(versions[1].author, None);
+
+        let old = common::collect_stream(backend.get_version(&file, "1.0").await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&old[..], b"first");
+    }
+
+    #[tokio::test]
+    async fn test_watch_follows_delta_link() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root:/docs:"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "d1", "name": "docs", "folder": {"childCount": 2}
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive/root/delta"))
+            .and(query_param("token", "latest"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [],
+                "@odata.deltaLink": format!("{}/delta/1", server.uri())
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/delta/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "value": [
+                    {"id": "r", "name": "root", "root": {}},
+                    {"id": "a", "name": "a.txt",
+                     "parentReference": {"path": "/drive/root:/docs"}},
+                    {"id": "b", "name": "b c.txt",
+                     "parentReference": {"path": "/drive/root:/docs/sub%20dir"}},
+                    {"id": "o", "name": "other.txt",
+                     "parentReference": {"path": "/drive/root:"}},
+                    {"id": "g", "name": "gone.txt", "deleted": {}}
+                ],
+                "@odata.deltaLink": format!("{}/delta/2", server.uri())
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let docs = VirtualPath::new("onedrive", "docs");
+        let mut changes = backend.watch(&docs, true).await.unwrap();
+
+        let mut next = Vec::new();
+        for _ in 0..3 {
+            next.push(changes.next().await.unwrap().unwrap());
+        }
+        assert_eq!(next[0].path.to_path_string(), "/docs/a.txt");
+        assert_eq!(next[0].kind, ChangeKind::Modified);
+        assert_eq!(next[1].path.to_path_string(), "/docs/sub dir/b c.txt");
+        // A delete of an item never seen cannot be placed
+        assert_eq!(next[2], ChangeEvent::new(docs, ChangeKind::Rescan));
+    }
+
+    #[tokio::test]
+    async fn test_space_info() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me/drive"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "quota": {"total": 500, "used": 200, "remaining": 300}
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let info = backend.get_space_info().await.unwrap();
+        assert_eq!(info.total, Some(500));
+        assert_eq!(info.available, Some(300));
+    }
+
+    #[tokio::test]
+    async fn test_upload_session_follows_expected_ranges() {
+        let server = MockServer::start().await;
+        let upload_url = format!("{}/upload/sess", server.uri());
+        Mock::given(method("POST"))
+            .and(path("/me/drive/root:/big.bin:/createUploadSession"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "uploadUrl": upload_url
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/upload/sess"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "nextExpectedRanges": ["6-9"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/upload/sess"))
+            .and(wiremock::matchers::header("content-range", "bytes 6-9/10"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "i1", "name": "big.bin", "size": 10
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server).await;
+        let mut session = backend
+            .create_session(
+                &VirtualPath::new("onedrive", "big.bin"),
+                10,
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(backend.query_offset(&mut session).await.unwrap(), 6);
+        backend
+            .upload_chunk(&mut session, Bytes::from_static(b"6789"))
+            .await
+            .unwrap();
+        assert!(session.is_complete());
     }
 }
@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Retries for transient failures
+//!
+//! [`RetryBackend`] wraps any backend and retries operations that fail with
+//! a retryable error: dropped connections, timeouts, server trouble and rate
+//! limiting. Delays grow exponentially with full jitter, unless the server
+//! said how long to wait. A rejected token triggers one credential refresh
+//! and an immediate retry.
+//!
+//! Only idempotent operations are retried after a failure. Copies and
+//! renames are retried only when the server turned them away for the token,
+//! since then nothing happened; streamed writes are never retried because
+//! the stream is gone.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cfk_core::{
+    backend::{ByteStream, FileVersion, SearchOptions, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeStream, CfkError, CfkResult, Entry, Metadata, ResumableUpload, StorageBackend,
+    StorageCapabilities, VirtualPath,
+};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use cfk_core::backend::RetryStats;
+
+/// When and how often to retry
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Upper bound of the first delay; doubles with every retry
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt`, counting from zero
+    ///
+    /// A `Retry-After` from the server wins; otherwise the delay is drawn
+    /// uniformly below the exponential bound.
+    pub fn delay(&self, attempt: u32, error: &CfkError) -> Duration {
+        if let CfkError::RateLimited {
+            retry_after_secs: Some(secs),
+        } = error
+        {
+            return Duration::from_secs(*secs);
+        }
+        let bound = self
+            .base_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+        bound.mul_f64(fastrand::f64())
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    retries: AtomicU64,
+    rate_limited: AtomicU64,
+    token_refreshes: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+/// A backend that retries transient failures of the backend it wraps
+pub struct RetryBackend {
+    inner: Arc<dyn StorageBackend>,
+    policy: RetryPolicy,
+    counters: Counters,
+}
+
+impl RetryBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        Self::with_policy(inner, RetryPolicy::default())
+    }
+
+    pub fn with_policy(inner: Arc<dyn StorageBackend>, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &Arc<dyn StorageBackend> {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            retries: self.counters.retries.load(Ordering::Relaxed),
+            rate_limited: self.counters.rate_limited.load(Ordering::Relaxed),
+            token_refreshes: self.counters.token_refreshes.load(Ordering::Relaxed),
+            exhausted: self.counters.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `f` until it succeeds, fails for good or runs out of retries
+    ///
+    /// Without `idempotent`, only a rejected token leads to another attempt.
+    async fn retry<T, F, Fut>(&self, op: &str, idempotent: bool, mut f: F) -> CfkResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = CfkResult<T>>,
+    {
+        let mut attempt = 0;
+        let mut refreshed = false;
+        loop {
+            let error = match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            if let CfkError::TokenExpired = error {
+                if refreshed || !self.inner.refresh_credentials().await? {
+                    return Err(error);
+                }
+                tracing::debug!("{} on {}: token refreshed", op, self.inner.id());
+                refreshed = true;
+                self.counters
+                    .token_refreshes
+                    .fetch_add(1, Ordering::Relaxed);
+                self.counters.retries.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            if !idempotent || !error.is_retryable() {
+                return Err(error);
+            }
+            if let CfkError::RateLimited { .. } = error {
+                self.counters.rate_limited.fetch_add(1, Ordering::Relaxed);
+            }
+            if attempt >= self.policy.max_retries {
+                self.counters.exhausted.fetch_add(1, Ordering::Relaxed);
+                return Err(error);
+            }
+
+            let delay = self.policy.delay(attempt, &error);
+            tracing::debug!(
+                "{} on {} failed, retry {} in {:?}: {}",
+                op,
+                self.inner.id(),
+                attempt + 1,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+            self.counters.retries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RetryBackend {
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    fn display_name(&self) -> &str {
+        self.inner.display_name()
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn is_available(&self) -> bool {
+        self.inner.is_available().await
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        self.retry("stat", true, || self.inner.get_metadata(path))
+            .await
+    }
+
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        self.retry("list", true, || self.inner.list_directory(path, options))
+            .await
+    }
+
+    /// Retries opening the stream; a stream that breaks later is the
+    /// reader's to deal with
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        self.retry("read", true, || self.inner.read_file(path, options))
+            .await
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        // A lost reply to a create-only write would make the retry fail
+        // with AlreadyExists
+        self.retry("write", options.overwrite, || {
+            self.inner.write_file(path, data.clone(), options)
+        })
+        .await
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.inner
+            .write_file_stream(path, stream, size_hint, options)
+            .await
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let mut first = true;
+        self.retry("mkdir", true, || {
+            let retried = !std::mem::replace(&mut first, false);
+            async move {
+                match self.inner.create_directory(path).await {
+                    // An earlier attempt got through
+                    Err(CfkError::AlreadyExists(_)) if retried => {
+                        self.inner.get_metadata(path).await
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        let mut first = true;
+        self.retry("delete", true, || {
+            let retried = !std::mem::replace(&mut first, false);
+            async move {
+                match self.inner.delete(path, options).await {
+                    Err(CfkError::NotFound(_)) if retried => Ok(()),
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        self.retry("copy", false, || self.inner.copy(source, dest, options))
+            .await
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        self.retry("rename", false, || self.inner.rename(source, dest, options))
+            .await
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        self.retry("df", true, || self.inner.get_space_info()).await
+    }
+
+    async fn search(&self, options: &SearchOptions) -> CfkResult<Vec<Entry>> {
+        self.retry("search", true, || self.inner.search(options))
+            .await
+    }
+
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        self.retry("versions", true, || self.inner.get_versions(path))
+            .await
+    }
+
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        self.retry("read version", true, || {
+            self.inner.get_version(path, version_id)
+        })
+        .await
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        self.inner.resumable()
+    }
+
+    async fn refresh_credentials(&self) -> CfkResult<bool> {
+        self.inner.refresh_credentials().await
+    }
+
+    fn retry_stats(&self) -> Option<RetryStats> {
+        Some(self.stats())
+    }
+
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        self.retry("set metadata", true, || {
+            self.inner.set_metadata(path, metadata)
+        })
+        .await
+    }
+
+    /// Only starting the watch is retried; an established one rides out
+    /// errors on its own
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        self.retry("watch", true, || self.inner.watch(path, recursive))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_bounds_and_retry_after() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        let network = CfkError::Network("reset".into());
+        for _ in 0..100 {
+            assert!(policy.delay(0, &network) <= Duration::from_millis(100));
+            assert!(policy.delay(2, &network) <= Duration::from_millis(400));
+            assert!(policy.delay(20, &network) <= Duration::from_secs(1));
+        }
+
+        let limited = CfkError::RateLimited {
+            retry_after_secs: Some(7),
+        };
+        assert_eq!(policy.delay(0, &limited), Duration::from_secs(7));
+    }
+}
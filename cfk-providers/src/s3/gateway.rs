@@ -0,0 +1,2011 @@
+//! S3-compatible gateway exporting a [`StorageBackend`]
+//!
+//! Serves one directory of any remote as a single bucket, so S3 tools and
+//! SDKs can reach storage that does not speak S3. Requests are checked
+//! with the SigV4 code [`S3Backend`](super::S3Backend) signs with, and both
+//! path-style and virtual-hosted addressing are accepted.
+//!
+//! Keys map onto paths. Directories exist as prefixes: an empty one lists
+//! as a `dir/` marker object, and deleting the last object in a directory
+//! removes it. Multipart parts are spooled to local temporary files until
+//! the upload completes.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::{Buf, Bytes, BytesMut};
+use cfk_core::{
+    backend::ByteStream, operations::*, CfkError, CfkResult, Entry, StorageBackend, VirtualPath,
+};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use futures::{StreamExt, TryStreamExt};
+use hyper::body::Incoming;
+use hyper::header::{self, HeaderMap};
+use hyper::{Method, Request, Response, StatusCode};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use super::{
+    canonical_query, canonical_request, credential_scope, encode_key, extract_xml_value,
+    hmac_sha256, sha256_hex, signature, signing_key, xml_blocks,
+};
+use crate::http_server::{
+    self, body_stream, constant_time_eq, empty, full, header_str, http_date, parse_range, set,
+    status, stream_body, Body,
+};
+
+/// Namespace of every S3 response document
+const S3_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// Most keys a listing or a DeleteObjects request may carry
+const MAX_KEYS: usize = 1000;
+
+/// Largest DeleteObjects or CompleteMultipartUpload body read
+const MAX_XML_BODY: usize = 1024 * 1024;
+
+/// Highest part number S3 allows
+const MAX_PART_NUMBER: u32 = 10_000;
+
+/// How far a request's `x-amz-date` may be from the server clock
+const MAX_CLOCK_SKEW_SECS: i64 = 15 * 60;
+
+/// ETag of an empty object: the MD5 of nothing
+const EMPTY_ETAG: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+/// Options for [`S3Gateway`]
+#[derive(Debug, Clone)]
+pub struct S3GatewayOptions {
+    /// Name of the one bucket served
+    pub bucket: String,
+    /// Region reported by GetBucketLocation; requests may be signed for any
+    pub region: String,
+    /// Access key and secret requests must be signed with; `None` serves
+    /// anyone
+    pub credentials: Option<(String, String)>,
+    /// Answer every change with AccessDenied
+    pub read_only: bool,
+}
+
+impl Default for S3GatewayOptions {
+    fn default() -> Self {
+        Self {
+            bucket: "cfk".to_string(),
+            region: "us-east-1".to_string(),
+            credentials: None,
+            read_only: false,
+        }
+    }
+}
+
+/// S3 API over a directory of a [`StorageBackend`]
+pub struct S3Gateway {
+    backend: Arc<dyn StorageBackend>,
+    root: VirtualPath,
+    options: S3GatewayOptions,
+    created: DateTime<Utc>,
+    /// Where multipart parts wait for CompleteMultipartUpload
+    staging: PathBuf,
+    uploads: Mutex<HashMap<String, Upload>>,
+}
+
+/// A multipart upload in progress
+#[derive(Debug, Clone)]
+struct Upload {
+    key: String,
+    initiated: DateTime<Utc>,
+    parts: BTreeMap<u32, Part>,
+}
+
+#[derive(Debug, Clone)]
+struct Part {
+    size: u64,
+    /// Hex MD5 of the part, unquoted
+    etag: String,
+    modified: DateTime<Utc>,
+}
+
+/// A request, once it has been resolved against the bucket
+struct Target {
+    /// `None` for the service itself (ListBuckets)
+    bucket: Option<String>,
+    /// Decoded key; empty for bucket operations
+    key: String,
+    query: HashMap<String, String>,
+}
+
+/// How the body of an authenticated request is checked
+enum Payload {
+    /// Nothing promised beyond the length
+    Unsigned,
+    /// The hex SHA-256 the body must have
+    Sha256(String),
+    /// `aws-chunked` framing, with per-chunk signatures when `signer` is set
+    Chunked(Option<ChunkSigner>),
+}
+
+/// State for checking `STREAMING-AWS4-HMAC-SHA256-PAYLOAD` chunks, each
+/// signed over the one before
+#[derive(Clone)]
+struct ChunkSigner {
+    key: Vec<u8>,
+    amz_date: String,
+    scope: String,
+    previous: String,
+}
+
+/// One item of a listing, in key order
+enum Listed {
+    Object(String, Box<Entry>),
+    Prefix(String),
+}
+
+/// Parameters shared by both ListObjects versions
+struct ListQuery {
+    prefix: String,
+    delimiter: Option<String>,
+    /// Only keys and prefixes after this are returned
+    after: Option<String>,
+    max_keys: usize,
+}
+
+impl ListQuery {
+    /// The common prefix `key` rolls up into, if any
+    fn common_prefix(&self, key: &str) -> Option<String> {
+        let delimiter = self.delimiter.as_deref()?;
+        let relative = key.strip_prefix(&self.prefix)?;
+        let end = relative.find(delimiter)? + delimiter.len();
+        Some(format!("{}{}", self.prefix, &relative[..end]))
+    }
+
+    fn is_after(&self, key: &str) -> bool {
+        self.after.as_deref().is_none_or(|after| key > after)
+    }
+}
+
+impl S3Gateway {
+    /// Serve `root` and everything below it as one bucket
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        root: VirtualPath,
+        options: S3GatewayOptions,
+    ) -> Self {
+        let staging = std::env::temp_dir().join(format!(
+            "cfk-s3-{}-{:016x}",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        Self {
+            backend,
+            root,
+            options,
+            created: Utc::now(),
+            staging,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accept clients until the listener fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> CfkResult<()> {
+        http_server::serve(listener, "s3", move |request| {
+            let gateway = self.clone();
+            async move { gateway.handle(request).await }
+        })
+        .await
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<Body> {
+        let method = request.method().clone();
+        let resource = request.uri().path().to_string();
+
+        let mut response = match self.authenticate(&request) {
+            Ok(payload) => self.dispatch(request, payload).await.unwrap_or_else(|e| {
+                tracing::debug!("s3 {} {}: {}", method, resource, e);
+                let (code, name) = error_code(&e);
+                error(code, name, &e.to_string(), &resource)
+            }),
+            Err((code, name, message)) => error(code, name, message, &resource),
+        };
+        set(
+            &mut response,
+            "x-amz-request-id",
+            format!("{:016X}", fastrand::u64(..)),
+        );
+        set(&mut response, header::SERVER, "cfk");
+        tracing::debug!("s3 {} {} -> {}", method, resource, response.status());
+        response
+    }
+
+    /// Check the SigV4 `Authorization` header and work out how the body is
+    /// to be verified
+    fn authenticate(
+        &self,
+        request: &Request<Incoming>,
+    ) -> Result<Payload, (StatusCode, &'static str, &'static str)> {
+        let headers = request.headers();
+        let content_sha256 = header_str(headers, "x-amz-content-sha256");
+        let payload = |signer: Option<ChunkSigner>| match content_sha256 {
+            None | Some("UNSIGNED-PAYLOAD") => Ok(Payload::Unsigned),
+            Some("STREAMING-UNSIGNED-PAYLOAD-TRAILER") => Ok(Payload::Chunked(None)),
+            Some(
+                "STREAMING-AWS4-HMAC-SHA256-PAYLOAD" | "STREAMING-AWS4-HMAC-SHA256-PAYLOAD-TRAILER",
+            ) => Ok(Payload::Chunked(signer)),
+            Some(hash) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                Ok(Payload::Sha256(hash.to_ascii_lowercase()))
+            }
+            Some(_) => Err((
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "unrecognized x-amz-content-sha256",
+            )),
+        };
+
+        let Some((access_key, secret)) = &self.options.credentials else {
+            return payload(None);
+        };
+
+        let denied = (StatusCode::FORBIDDEN, "AccessDenied", "access denied");
+        let malformed = (
+            StatusCode::BAD_REQUEST,
+            "AuthorizationHeaderMalformed",
+            "the authorization header is malformed",
+        );
+        let mismatch = (
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch",
+            "the request signature does not match",
+        );
+
+        let authorization = header_str(headers, "authorization").ok_or(denied)?;
+        let fields = authorization
+            .strip_prefix("AWS4-HMAC-SHA256 ")
+            .ok_or(malformed)?;
+        let mut credential = None;
+        let mut signed_headers = None;
+        let mut given = None;
+        for field in fields.split(',') {
+            match field.trim().split_once('=') {
+                Some(("Credential", value)) => credential = Some(value),
+                Some(("SignedHeaders", value)) => signed_headers = Some(value),
+                Some(("Signature", value)) => given = Some(value),
+                _ => {}
+            }
+        }
+        let (credential, signed_headers, given) = match (credential, signed_headers, given) {
+            (Some(c), Some(h), Some(s)) => (c, h, s),
+            _ => return Err(malformed),
+        };
+
+        // AKID/date/region/s3/aws4_request
+        let scope_parts: Vec<&str> = credential.split('/').collect();
+        let [key_id, date_stamp, region, "s3", "aws4_request"] = scope_parts[..] else {
+            return Err(malformed);
+        };
+        if key_id != access_key {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "InvalidAccessKeyId",
+                "the access key does not exist",
+            ));
+        }
+
+        let amz_date = header_str(headers, "x-amz-date").ok_or(denied)?;
+        let time = NaiveDateTime::parse_from_str(amz_date, "%Y%m%dT%H%M%SZ")
+            .map_err(|_| malformed)?
+            .and_utc();
+        if !amz_date.starts_with(date_stamp) {
+            return Err(malformed);
+        }
+        if (Utc::now() - time).num_seconds().abs() > MAX_CLOCK_SKEW_SECS {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "RequestTimeTooSkewed",
+                "the request time is too far from the server time",
+            ));
+        }
+
+        let mut canonical_headers = BTreeMap::new();
+        for name in signed_headers.split(';') {
+            let values: Vec<String> = headers
+                .get_all(name)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .map(|v| v.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect();
+            if values.is_empty() {
+                return Err(mismatch);
+            }
+            canonical_headers.insert(name.to_string(), values.join(","));
+        }
+
+        let uri = request.uri();
+        let (canonical, _) = canonical_request(
+            request.method().as_str(),
+            uri.path(),
+            &canonical_query(uri.query().unwrap_or("")),
+            &canonical_headers,
+            content_sha256.ok_or(malformed)?,
+        );
+        let scope = credential_scope(date_stamp, region);
+        let key = signing_key(secret, date_stamp, region);
+        let expected = signature(&key, amz_date, &scope, &canonical);
+        if !constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+            return Err(mismatch);
+        }
+
+        payload(Some(ChunkSigner {
+            key,
+            amz_date: amz_date.to_string(),
+            scope,
+            previous: expected,
+        }))
+    }
+
+    /// Split a request into bucket, key and query, for path-style and
+    /// virtual-hosted addressing alike
+    fn target(&self, request: &Request<Incoming>) -> CfkResult<Target> {
+        let uri = request.uri();
+        let query = uri
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|pair| {
+                let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((decode(k)?, decode(v)?))
+            })
+            .collect::<CfkResult<_>>()?;
+
+        let host = header_str(request.headers(), "host").unwrap_or("");
+        let host = host.rsplit_once(':').map_or(host, |(name, _)| name);
+        let path = uri.path().strip_prefix('/').unwrap_or(uri.path());
+
+        let virtual_hosted = host
+            .strip_prefix(self.options.bucket.as_str())
+            .is_some_and(|rest| rest.starts_with('.'));
+        let (bucket, key) = if virtual_hosted {
+            (Some(self.options.bucket.clone()), path)
+        } else if path.is_empty() {
+            (None, "")
+        } else {
+            let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+            (Some(decode(bucket)?), key)
+        };
+
+        Ok(Target {
+            bucket,
+            key: decode(key)?,
+            query,
+        })
+    }
+
+    async fn dispatch(
+        &self,
+        request: Request<Incoming>,
+        payload: Payload,
+    ) -> CfkResult<Response<Body>> {
+        let target = self.target(&request)?;
+        let Some(bucket) = &target.bucket else {
+            return match *request.method() {
+                Method::GET => Ok(self.list_buckets()),
+                _ => Ok(not_implemented()),
+            };
+        };
+        if *bucket != self.options.bucket {
+            return Ok(error(
+                StatusCode::NOT_FOUND,
+                "NoSuchBucket",
+                "the specified bucket does not exist",
+                bucket,
+            ));
+        }
+
+        let method = request.method().clone();
+        let writes = !matches!(method, Method::GET | Method::HEAD);
+        if writes && self.options.read_only {
+            return Ok(error(
+                StatusCode::FORBIDDEN,
+                "AccessDenied",
+                "the gateway is read-only",
+                request.uri().path(),
+            ));
+        }
+
+        let query = &target.query;
+        let key = target.key.as_str();
+        if key.is_empty() {
+            return match method {
+                Method::HEAD => {
+                    let mut response = status(StatusCode::OK);
+                    set(&mut response, "x-amz-bucket-region", &self.options.region);
+                    Ok(response)
+                }
+                Method::GET if query.contains_key("location") => Ok(self.location()),
+                Method::GET if query.contains_key("uploads") => Ok(self.list_uploads()),
+                Method::GET if query.get("list-type").map(String::as_str) == Some("2") => {
+                    self.list_objects_v2(query).await
+                }
+                Method::GET => self.list_objects_v1(query).await,
+                Method::POST if query.contains_key("delete") => {
+                    let body = self.payload(request, payload)?;
+                    self.delete_objects(body).await
+                }
+                Method::PUT => Ok(error(
+                    StatusCode::CONFLICT,
+                    "BucketAlreadyOwnedByYou",
+                    "the bucket already exists",
+                    bucket,
+                )),
+                _ => Ok(not_implemented()),
+            };
+        }
+
+        let path = self.path(key)?;
+        let upload_id = query.get("uploadId").cloned();
+        match (method, upload_id) {
+            (Method::GET, Some(id)) => self.list_parts(key, &id, query),
+            (Method::GET | Method::HEAD, None) => self.get(&request, key, &path).await,
+            (Method::PUT, Some(id)) => {
+                if request.headers().contains_key("x-amz-copy-source") {
+                    return Ok(not_implemented());
+                }
+                let number = query.get("partNumber").and_then(|n| n.parse().ok());
+                let body = self.payload(request, payload)?;
+                self.upload_part(key, &id, number, body).await
+            }
+            (Method::PUT, None) if request.headers().contains_key("x-amz-copy-source") => {
+                self.copy(request.headers(), &path).await
+            }
+            (Method::PUT, None) => self.put(request, payload, key, &path).await,
+            (Method::POST, None) if query.contains_key("uploads") => Ok(self.create_upload(key)),
+            (Method::POST, Some(id)) => {
+                let body = self.payload(request, payload)?;
+                self.complete_upload(key, &id, &path, body).await
+            }
+            (Method::DELETE, Some(id)) => self.abort_upload(key, &id).await,
+            (Method::DELETE, None) => {
+                self.delete_key(key, &path).await?;
+                Ok(status(StatusCode::NO_CONTENT))
+            }
+            _ => Ok(not_implemented()),
+        }
+    }
+
+    /// The backend path a key names; `dir/` and `dir` name the same one
+    fn path(&self, key: &str) -> CfkResult<VirtualPath> {
+        let trimmed = key.strip_suffix('/').unwrap_or(key);
+        let mut path = self.root.clone();
+        for segment in trimmed.split('/') {
+            if segment.is_empty() || segment == "." || segment == ".." {
+                return Err(CfkError::InvalidPath(key.to_string()));
+            }
+            path = path.join(segment);
+        }
+        Ok(path)
+    }
+
+    /// The request body with its framing removed and its digests checked
+    fn payload(&self, request: Request<Incoming>, payload: Payload) -> CfkResult<ByteStream> {
+        let expected_md5 = match header_str(request.headers(), "content-md5") {
+            Some(md5) => Some(
+                BASE64
+                    .decode(md5.trim())
+                    .map_err(|_| CfkError::Serialization("invalid Content-MD5".into()))?,
+            ),
+            None => None,
+        };
+        let decoded_length = header_str(request.headers(), "x-amz-decoded-content-length")
+            .and_then(|n| n.parse::<u64>().ok());
+        let body = body_stream(request.into_body());
+        let (body, sha256) = match payload {
+            Payload::Unsigned => (body, None),
+            Payload::Sha256(hash) => (body, Some(hash)),
+            Payload::Chunked(signer) => (dechunk(body, signer, decoded_length), None),
+        };
+        Ok(verify(body, sha256, expected_md5, None))
+    }
+
+    fn list_buckets(&self) -> Response<Body> {
+        xml_response(
+            StatusCode::OK,
+            format!(
+                "<ListAllMyBucketsResult xmlns=\"{}\"><Owner><ID>cfk</ID><DisplayName>cfk</DisplayName></Owner>\
+                 <Buckets><Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket></Buckets>\
+                 </ListAllMyBucketsResult>",
+                S3_NAMESPACE,
+                escape(&self.options.bucket),
+                timestamp(self.created)
+            ),
+        )
+    }
+
+    fn location(&self) -> Response<Body> {
+        // us-east-1 is reported as no constraint at all
+        let region = match self.options.region.as_str() {
+            "us-east-1" => "",
+            region => region,
+        };
+        xml_response(
+            StatusCode::OK,
+            format!(
+                "<LocationConstraint xmlns=\"{}\">{}</LocationConstraint>",
+                S3_NAMESPACE,
+                escape(region)
+            ),
+        )
+    }
+
+    async fn children(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        let mut options = ListOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let mut entries = Vec::new();
+        loop {
+            let listing = self.backend.list_directory(path, &options).await?;
+            entries.extend(listing.entries);
+            match listing.cursor {
+                Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                _ => return Ok(entries),
+            }
+        }
+    }
+
+    /// Children of the directory at `key` with their keys, in key order.
+    ///
+    /// Directory keys end in `/`, so `a.txt` sorts before everything under
+    /// `a/` just as S3 would have it.
+    async fn keyed_children(
+        &self,
+        path: &VirtualPath,
+        key: &str,
+    ) -> CfkResult<Vec<(String, Entry)>> {
+        let mut children: Vec<_> = self
+            .children(path)
+            .await?
+            .into_iter()
+            .map(|entry| {
+                let slash = if entry.is_directory() { "/" } else { "" };
+                (
+                    format!("{}{}{}", key, entry.name().unwrap_or_default(), slash),
+                    entry,
+                )
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(children)
+    }
+
+    /// Up to `max_keys` objects and common prefixes, and whether more follow.
+    ///
+    /// Walks depth-first from the directory holding the prefix, skipping
+    /// subtrees that end before `after` and stopping at a delimiter rather
+    /// than descending.
+    async fn list(&self, query: &ListQuery) -> CfkResult<(Vec<Listed>, bool)> {
+        let mut items: Vec<Listed> = Vec::new();
+        let dir_key = &query.prefix[..query.prefix.rfind('/').map_or(0, |i| i + 1)];
+        let dir = match dir_key {
+            "" => self.root.clone(),
+            key => match self.path(key) {
+                Ok(path) => path,
+                Err(_) => return Ok((items, false)),
+            },
+        };
+        match self.backend.get_metadata(&dir).await {
+            Ok(entry) if entry.is_directory() => {}
+            Ok(_) | Err(CfkError::NotFound(_)) => return Ok((items, false)),
+            Err(e) => return Err(e),
+        }
+
+        let children = self.keyed_children(&dir, dir_key).await?;
+        if children.is_empty() && !dir_key.is_empty() && dir_key == query.prefix {
+            if query.is_after(dir_key) && query.max_keys > 0 {
+                let entry = self.backend.get_metadata(&dir).await?;
+                items.push(Listed::Object(dir_key.to_string(), Box::new(entry)));
+            }
+            return Ok((items, false));
+        }
+
+        let mut stack = vec![children.into_iter()];
+        while items.len() <= query.max_keys {
+            let Some(level) = stack.last_mut() else {
+                break;
+            };
+            let Some((key, entry)) = level.next() else {
+                stack.pop();
+                continue;
+            };
+            if !key.starts_with(&query.prefix) {
+                continue;
+            }
+
+            if let Some(prefix) = query.common_prefix(&key) {
+                let repeated =
+                    matches!(items.last(), Some(Listed::Prefix(last)) if *last == prefix);
+                if query.is_after(&prefix) && !repeated {
+                    items.push(Listed::Prefix(prefix));
+                }
+                // Everything below rolls up into the same prefix
+                continue;
+            }
+
+            if entry.is_directory() {
+                let ends_before = query
+                    .after
+                    .as_deref()
+                    .is_some_and(|after| after >= key.as_str() && !after.starts_with(key.as_str()));
+                if ends_before {
+                    continue;
+                }
+                let children = self.keyed_children(&entry.path, &key).await?;
+                if children.is_empty() {
+                    if query.is_after(&key) {
+                        items.push(Listed::Object(key, Box::new(entry)));
+                    }
+                } else {
+                    stack.push(children.into_iter());
+                }
+            } else if query.is_after(&key) {
+                items.push(Listed::Object(key, Box::new(entry)));
+            }
+        }
+
+        let truncated = items.len() > query.max_keys;
+        items.truncate(query.max_keys);
+        Ok((items, truncated))
+    }
+
+    async fn list_objects_v2(&self, params: &HashMap<String, String>) -> CfkResult<Response<Body>> {
+        let token = params.get("continuation-token");
+        let after = match token {
+            Some(token) => Some(
+                BASE64
+                    .decode(token)
+                    .ok()
+                    .and_then(|key| String::from_utf8(key).ok())
+                    .ok_or_else(|| CfkError::InvalidPath("invalid continuation token".into()))?,
+            ),
+            None => params.get("start-after").cloned(),
+        };
+        let query = list_query(params, after)?;
+        let (items, truncated) = self.list(&query).await?;
+        let url = params.get("encoding-type").map(String::as_str) == Some("url");
+
+        let mut xml = format!(
+            "<ListBucketResult xmlns=\"{}\"><Name>{}</Name><Prefix>{}</Prefix><MaxKeys>{}</MaxKeys>\
+             <KeyCount>{}</KeyCount><IsTruncated>{}</IsTruncated>",
+            S3_NAMESPACE,
+            escape(&self.options.bucket),
+            encode_listed(&query.prefix, url),
+            query.max_keys,
+            items.len(),
+            truncated
+        );
+        if let Some(delimiter) = &query.delimiter {
+            xml.push_str(&format!(
+                "<Delimiter>{}</Delimiter>",
+                encode_listed(delimiter, url)
+            ));
+        }
+        if let Some(token) = token {
+            xml.push_str(&format!(
+                "<ContinuationToken>{}</ContinuationToken>",
+                escape(token)
+            ));
+        }
+        if let Some(start_after) = params.get("start-after") {
+            xml.push_str(&format!(
+                "<StartAfter>{}</StartAfter>",
+                encode_listed(start_after, url)
+            ));
+        }
+        if url {
+            xml.push_str("<EncodingType>url</EncodingType>");
+        }
+        if truncated {
+            if let Some(last) = items.last() {
+                xml.push_str(&format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    BASE64.encode(listed_key(last))
+                ));
+            }
+        }
+        push_listed(&mut xml, &items, url);
+        xml.push_str("</ListBucketResult>");
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    async fn list_objects_v1(&self, params: &HashMap<String, String>) -> CfkResult<Response<Body>> {
+        let marker = params.get("marker").cloned();
+        let query = list_query(params, marker.clone())?;
+        let (items, truncated) = self.list(&query).await?;
+        let url = params.get("encoding-type").map(String::as_str) == Some("url");
+
+        let mut xml = format!(
+            "<ListBucketResult xmlns=\"{}\"><Name>{}</Name><Prefix>{}</Prefix><Marker>{}</Marker>\
+             <MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+            S3_NAMESPACE,
+            escape(&self.options.bucket),
+            encode_listed(&query.prefix, url),
+            encode_listed(marker.as_deref().unwrap_or(""), url),
+            query.max_keys,
+            truncated
+        );
+        if let Some(delimiter) = &query.delimiter {
+            xml.push_str(&format!(
+                "<Delimiter>{}</Delimiter>",
+                encode_listed(delimiter, url)
+            ));
+        }
+        if url {
+            xml.push_str("<EncodingType>url</EncodingType>");
+        }
+        // Without a delimiter clients continue from the last key themselves
+        if let (true, Some(_), Some(last)) = (truncated, &query.delimiter, items.last()) {
+            xml.push_str(&format!(
+                "<NextMarker>{}</NextMarker>",
+                encode_listed(listed_key(last), url)
+            ));
+        }
+        push_listed(&mut xml, &items, url);
+        xml.push_str("</ListBucketResult>");
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// GetObject and HeadObject
+    async fn get(
+        &self,
+        request: &Request<Incoming>,
+        key: &str,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        let entry = self.backend.get_metadata(path).await?;
+        let head = request.method() == Method::HEAD;
+
+        // A directory only answers as its `dir/` marker
+        if entry.is_directory() != key.ends_with('/') {
+            return Err(CfkError::NotFound(key.to_string()));
+        }
+        if entry.is_directory() {
+            let mut response = status(StatusCode::OK);
+            object_headers(&mut response, &entry);
+            set(&mut response, header::CONTENT_LENGTH, "0");
+            return Ok(response);
+        }
+
+        let size = entry.size().unwrap_or(0);
+        let range =
+            header_str(request.headers(), "range").and_then(|range| parse_range(range, size));
+        let (code, range) = match range {
+            None => (StatusCode::OK, None),
+            Some(Ok(range)) => (StatusCode::PARTIAL_CONTENT, Some(range)),
+            Some(Err(())) => {
+                let mut response = error(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "InvalidRange",
+                    "the requested range is not satisfiable",
+                    key,
+                );
+                set(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    format!("bytes */{}", size),
+                );
+                return Ok(response);
+            }
+        };
+
+        let body = if head {
+            empty()
+        } else {
+            let options = ReadOptions {
+                range,
+                use_cache: true,
+            };
+            stream_body(self.backend.read_file(path, &options).await?)
+        };
+        let mut response = Response::new(body);
+        *response.status_mut() = code;
+        object_headers(&mut response, &entry);
+        let length = match range {
+            Some((start, end)) => {
+                set(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end - 1, size),
+                );
+                end - start
+            }
+            None => size,
+        };
+        set(&mut response, header::CONTENT_LENGTH, length.to_string());
+        Ok(response)
+    }
+
+    /// PutObject, or a `dir/` marker creating a directory
+    async fn put(
+        &self,
+        request: Request<Incoming>,
+        payload: Payload,
+        key: &str,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        let headers = request.headers();
+        let if_none_match = header_str(headers, "if-none-match").is_some_and(|v| v.trim() == "*");
+        let size = header_str(headers, "x-amz-decoded-content-length")
+            .or_else(|| header_str(headers, header::CONTENT_LENGTH.as_str()))
+            .and_then(|n| n.parse::<u64>().ok());
+        let body = self.payload(request, payload)?;
+
+        if key.ends_with('/') {
+            let body = collect(body, 0).await?;
+            if !body.is_empty() {
+                return Err(CfkError::InvalidPath(format!(
+                    "{}: a directory marker must be empty",
+                    key
+                )));
+            }
+            self.backend.create_directory(path).await?;
+            let mut response = status(StatusCode::OK);
+            set(&mut response, header::ETAG, format!("\"{}\"", EMPTY_ETAG));
+            return Ok(response);
+        }
+
+        let md5 = Arc::new(Mutex::new(None));
+        let body = verify(body, None, None, Some(md5.clone()));
+        let options = WriteOptions {
+            overwrite: !if_none_match,
+            create_parents: true,
+            content_hash: None,
+        };
+        match self
+            .backend
+            .write_file_stream(path, body, size, &options)
+            .await
+        {
+            Err(CfkError::AlreadyExists(_)) if if_none_match => {
+                return Ok(error(
+                    StatusCode::PRECONDITION_FAILED,
+                    "PreconditionFailed",
+                    "the object already exists",
+                    key,
+                ));
+            }
+            result => result?,
+        };
+
+        let mut response = status(StatusCode::OK);
+        if let Some(md5) = md5.lock().unwrap().take() {
+            set(&mut response, header::ETAG, format!("\"{}\"", md5));
+        }
+        Ok(response)
+    }
+
+    /// CopyObject, server-side on the backend
+    async fn copy(&self, headers: &HeaderMap, dest: &VirtualPath) -> CfkResult<Response<Body>> {
+        let source = header_str(headers, "x-amz-copy-source").unwrap_or("");
+        // Version IDs are meaningless here; the current object is the only one
+        let source = source.split_once('?').map_or(source, |(source, _)| source);
+        let source = decode(source.trim_start_matches('/'))?;
+        let (bucket, source_key) = source
+            .split_once('/')
+            .ok_or_else(|| CfkError::InvalidPath(source.clone()))?;
+        if bucket != self.options.bucket {
+            return Err(CfkError::NotFound(bucket.to_string()));
+        }
+
+        let source = self.path(source_key)?;
+        let entry = self.backend.get_metadata(&source).await?;
+        if entry.is_directory() {
+            return Err(CfkError::NotFound(source_key.to_string()));
+        }
+        if source != *dest {
+            self.ensure_parent(dest).await?;
+            let options = CopyOptions {
+                overwrite: true,
+                preserve_metadata: true,
+            };
+            self.backend.copy(&source, dest, &options).await?;
+        }
+
+        let entry = self.backend.get_metadata(dest).await?;
+        let modified = entry.metadata.modified.unwrap_or(self.created);
+        Ok(xml_response(
+            StatusCode::OK,
+            format!(
+                "<CopyObjectResult><LastModified>{}</LastModified><ETag>{}</ETag></CopyObjectResult>",
+                timestamp(modified),
+                escape(&etag(&entry))
+            ),
+        ))
+    }
+
+    /// Create the directory `path` lives in when the backend will not
+    async fn ensure_parent(&self, path: &VirtualPath) -> CfkResult<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+        if parent == self.root {
+            return Ok(());
+        }
+        match self.backend.get_metadata(&parent).await {
+            Err(CfkError::NotFound(_)) => self.backend.create_directory(&parent).await.map(|_| ()),
+            other => other.map(|_| ()),
+        }
+    }
+
+    /// DeleteObject. Deleting a missing key succeeds, as it does on S3.
+    async fn delete_key(&self, key: &str, path: &VirtualPath) -> CfkResult<()> {
+        let entry = match self.backend.get_metadata(path).await {
+            Ok(entry) => entry,
+            Err(CfkError::NotFound(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if entry.is_directory() != key.ends_with('/') {
+            return Ok(());
+        }
+
+        let options = DeleteOptions {
+            recursive: false,
+            force: true,
+        };
+        match self.backend.delete(path, &options).await {
+            // Removing the marker of a directory with objects in it leaves
+            // the objects, and with them the directory
+            Err(CfkError::DirectoryNotEmpty(_)) => return Ok(()),
+            result => result?,
+        }
+        self.prune(path).await
+    }
+
+    /// Remove directories left empty above a deleted key, as no prefix
+    /// would remain for them on S3
+    async fn prune(&self, path: &VirtualPath) -> CfkResult<()> {
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == self.root || dir.segments.len() <= self.root.segments.len() {
+                break;
+            }
+            if !self.children(&dir).await?.is_empty() {
+                break;
+            }
+            let options = DeleteOptions {
+                recursive: false,
+                force: true,
+            };
+            self.backend.delete(&dir, &options).await?;
+            current = dir.parent();
+        }
+        Ok(())
+    }
+
+    /// DeleteObjects
+    async fn delete_objects(&self, body: ByteStream) -> CfkResult<Response<Body>> {
+        let body = collect(body, MAX_XML_BODY).await?;
+        let body = String::from_utf8(body.to_vec())
+            .map_err(|_| CfkError::Serialization("request body is not UTF-8".into()))?;
+        let quiet = extract_xml_value(&body, "Quiet").as_deref() == Some("true");
+        let keys: Vec<String> = xml_blocks(&body, "Object")
+            .into_iter()
+            .filter_map(|object| extract_xml_value(object, "Key"))
+            .collect();
+        if keys.len() > MAX_KEYS {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                "MalformedXML",
+                "at most 1000 keys may be deleted at once",
+                "",
+            ));
+        }
+
+        let mut xml = format!("<DeleteResult xmlns=\"{}\">", S3_NAMESPACE);
+        for key in keys {
+            let result = match self.path(&key) {
+                Ok(path) => self.delete_key(&key, &path).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) if quiet => {}
+                Ok(()) => xml.push_str(&format!("<Deleted><Key>{}</Key></Deleted>", escape(&key))),
+                Err(e) => {
+                    let (_, code) = error_code(&e);
+                    xml.push_str(&format!(
+                        "<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>",
+                        escape(&key),
+                        code,
+                        escape(&e.to_string())
+                    ));
+                }
+            }
+        }
+        xml.push_str("</DeleteResult>");
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// CreateMultipartUpload
+    fn create_upload(&self, key: &str) -> Response<Body> {
+        let id = format!("{:032x}", fastrand::u128(..));
+        let upload = Upload {
+            key: key.to_string(),
+            initiated: Utc::now(),
+            parts: BTreeMap::new(),
+        };
+        self.uploads.lock().unwrap().insert(id.clone(), upload);
+        xml_response(
+            StatusCode::OK,
+            format!(
+                "<InitiateMultipartUploadResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key>\
+                 <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                S3_NAMESPACE,
+                escape(&self.options.bucket),
+                escape(key),
+                id
+            ),
+        )
+    }
+
+    /// The upload `id` if it exists and belongs to `key`
+    fn upload(&self, key: &str, id: &str) -> Option<Upload> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|upload| upload.key == key)
+            .cloned()
+    }
+
+    /// UploadPart, spooling the part to the staging directory
+    async fn upload_part(
+        &self,
+        key: &str,
+        id: &str,
+        number: Option<u32>,
+        body: ByteStream,
+    ) -> CfkResult<Response<Body>> {
+        if self.upload(key, id).is_none() {
+            return Ok(no_such_upload(key));
+        }
+        let Some(number) = number.filter(|n| (1..=MAX_PART_NUMBER).contains(n)) else {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "part numbers run from 1 to 10000",
+                key,
+            ));
+        };
+
+        let dir = self.staging.join(id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let md5 = Arc::new(Mutex::new(None));
+        let mut body = verify(body, None, None, Some(md5.clone()));
+        let mut file = tokio::fs::File::create(dir.join(number.to_string())).await?;
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        let etag = md5.lock().unwrap().take().unwrap_or_default();
+
+        let part = Part {
+            size,
+            etag: etag.clone(),
+            modified: Utc::now(),
+        };
+        match self.uploads.lock().unwrap().get_mut(id) {
+            Some(upload) => upload.parts.insert(number, part),
+            // Aborted while the part was arriving
+            None => return Err(CfkError::Cancelled),
+        };
+
+        let mut response = status(StatusCode::OK);
+        set(&mut response, header::ETAG, format!("\"{}\"", etag));
+        Ok(response)
+    }
+
+    /// ListParts
+    fn list_parts(
+        &self,
+        key: &str,
+        id: &str,
+        params: &HashMap<String, String>,
+    ) -> CfkResult<Response<Body>> {
+        let Some(upload) = self.upload(key, id) else {
+            return Ok(no_such_upload(key));
+        };
+        let marker: u32 = params
+            .get("part-number-marker")
+            .and_then(|m| m.parse().ok())
+            .unwrap_or(0);
+        let max_parts = params
+            .get("max-parts")
+            .and_then(|m| m.parse().ok())
+            .unwrap_or(MAX_KEYS)
+            .min(MAX_KEYS);
+
+        let mut parts = upload.parts.range(marker + 1..).peekable();
+        let mut listed = String::new();
+        let mut last = marker;
+        for (number, part) in parts.by_ref().take(max_parts) {
+            listed.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><LastModified>{}</LastModified>\
+                 <ETag>&quot;{}&quot;</ETag><Size>{}</Size></Part>",
+                number,
+                timestamp(part.modified),
+                part.etag,
+                part.size
+            ));
+            last = *number;
+        }
+        let truncated = parts.peek().is_some();
+
+        Ok(xml_response(
+            StatusCode::OK,
+            format!(
+                "<ListPartsResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId>\
+                 <PartNumberMarker>{}</PartNumberMarker><NextPartNumberMarker>{}</NextPartNumberMarker>\
+                 <MaxParts>{}</MaxParts><IsTruncated>{}</IsTruncated>{}</ListPartsResult>",
+                S3_NAMESPACE,
+                escape(&self.options.bucket),
+                escape(key),
+                id,
+                marker,
+                last,
+                max_parts,
+                truncated,
+                listed
+            ),
+        ))
+    }
+
+    /// ListMultipartUploads, all of them at once
+    fn list_uploads(&self) -> Response<Body> {
+        let uploads = self.uploads.lock().unwrap();
+        let mut listed: Vec<_> = uploads.iter().collect();
+        listed.sort_by(|a, b| (&a.1.key, a.1.initiated).cmp(&(&b.1.key, b.1.initiated)));
+
+        let mut xml = format!(
+            "<ListMultipartUploadsResult xmlns=\"{}\"><Bucket>{}</Bucket><IsTruncated>false</IsTruncated>",
+            S3_NAMESPACE,
+            escape(&self.options.bucket)
+        );
+        for (id, upload) in listed {
+            xml.push_str(&format!(
+                "<Upload><Key>{}</Key><UploadId>{}</UploadId><Initiated>{}</Initiated></Upload>",
+                escape(&upload.key),
+                id,
+                timestamp(upload.initiated)
+            ));
+        }
+        xml.push_str("</ListMultipartUploadsResult>");
+        xml_response(StatusCode::OK, xml)
+    }
+
+    /// CompleteMultipartUpload: stream the listed parts, in order, into
+    /// one write
+    async fn complete_upload(
+        &self,
+        key: &str,
+        id: &str,
+        path: &VirtualPath,
+        body: ByteStream,
+    ) -> CfkResult<Response<Body>> {
+        let Some(upload) = self.upload(key, id) else {
+            return Ok(no_such_upload(key));
+        };
+        let body = collect(body, MAX_XML_BODY).await?;
+        let body = String::from_utf8(body.to_vec())
+            .map_err(|_| CfkError::Serialization("request body is not UTF-8".into()))?;
+
+        let invalid = |code, message| Ok(error(StatusCode::BAD_REQUEST, code, message, key));
+        let mut files = Vec::new();
+        let mut digests = Md5::new();
+        let mut size = 0;
+        let mut previous = 0;
+        for block in xml_blocks(&body, "Part") {
+            let number: Option<u32> =
+                extract_xml_value(block, "PartNumber").and_then(|n| n.parse().ok());
+            let Some(number) = number else {
+                return invalid("MalformedXML", "a part has no PartNumber");
+            };
+            if number <= previous {
+                return invalid(
+                    "InvalidPartOrder",
+                    "parts must be listed in ascending order",
+                );
+            }
+            previous = number;
+
+            let tag = extract_xml_value(block, "ETag").unwrap_or_default();
+            let part = match upload.parts.get(&number) {
+                Some(part) if part.etag == tag.trim_matches('"') => part,
+                _ => return invalid("InvalidPart", "a listed part was not uploaded"),
+            };
+            digests.update(hex::decode(&part.etag).unwrap_or_default());
+            size += part.size;
+            files.push(self.staging.join(id).join(number.to_string()));
+        }
+        if files.is_empty() {
+            return invalid("MalformedXML", "no parts were listed");
+        }
+
+        let options = WriteOptions {
+            overwrite: true,
+            create_parents: true,
+            content_hash: None,
+        };
+        self.backend
+            .write_file_stream(path, concatenate(files.clone()), Some(size), &options)
+            .await?;
+
+        self.uploads.lock().unwrap().remove(id);
+        let _ = tokio::fs::remove_dir_all(self.staging.join(id)).await;
+
+        let etag = format!("\"{}-{}\"", hex::encode(digests.finalize()), files.len());
+        Ok(xml_response(
+            StatusCode::OK,
+            format!(
+                "<CompleteMultipartUploadResult xmlns=\"{}\"><Location>/{}/{}</Location>\
+                 <Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+                S3_NAMESPACE,
+                escape(&self.options.bucket),
+                escape(&encode_key(key)),
+                escape(&self.options.bucket),
+                escape(key),
+                escape(&etag)
+            ),
+        ))
+    }
+
+    /// AbortMultipartUpload
+    async fn abort_upload(&self, key: &str, id: &str) -> CfkResult<Response<Body>> {
+        if self.upload(key, id).is_none() {
+            return Ok(no_such_upload(key));
+        }
+        self.uploads.lock().unwrap().remove(id);
+        let _ = tokio::fs::remove_dir_all(self.staging.join(id)).await;
+        Ok(status(StatusCode::NO_CONTENT))
+    }
+}
+
+impl Drop for S3Gateway {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.staging);
+    }
+}
+
+/// Prefix, delimiter and page size of a listing request
+fn list_query(params: &HashMap<String, String>, after: Option<String>) -> CfkResult<ListQuery> {
+    let max_keys = match params.get("max-keys") {
+        Some(n) => n
+            .parse::<usize>()
+            .map_err(|_| CfkError::InvalidPath(format!("max-keys: {}", n)))?
+            .min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    Ok(ListQuery {
+        prefix: params.get("prefix").cloned().unwrap_or_default(),
+        delimiter: params.get("delimiter").filter(|d| !d.is_empty()).cloned(),
+        after: after.filter(|a| !a.is_empty()),
+        max_keys,
+    })
+}
+
+fn listed_key(item: &Listed) -> &str {
+    match item {
+        Listed::Object(key, _) | Listed::Prefix(key) => key,
+    }
+}
+
+/// `Contents` and `CommonPrefixes` elements for a listing
+fn push_listed(xml: &mut String, items: &[Listed], url: bool) {
+    for item in items {
+        if let Listed::Object(key, entry) = item {
+            xml.push_str(&format!(
+                "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag>\
+                 <Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                encode_listed(key, url),
+                timestamp(entry.metadata.modified.unwrap_or(DateTime::UNIX_EPOCH)),
+                escape(&etag(entry)),
+                if entry.is_directory() {
+                    0
+                } else {
+                    entry.size().unwrap_or(0)
+                }
+            ));
+        }
+    }
+    for item in items {
+        if let Listed::Prefix(prefix) = item {
+            xml.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                encode_listed(prefix, url)
+            ));
+        }
+    }
+}
+
+fn encode_listed(key: &str, url: bool) -> String {
+    if url {
+        encode_key(key)
+    } else {
+        escape(key)
+    }
+}
+
+/// ETag, Last-Modified and Content-Type of an object
+fn object_headers(response: &mut Response<Body>, entry: &Entry) {
+    set(response, header::ETAG, etag(entry));
+    set(response, header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = entry.metadata.modified {
+        set(response, header::LAST_MODIFIED, http_date(modified));
+    }
+    let mime = match &entry.metadata.mime_type {
+        Some(mime) => mime.as_str(),
+        None if entry.is_directory() => "application/x-directory",
+        None => "application/octet-stream",
+    };
+    set(response, header::CONTENT_TYPE, mime);
+}
+
+/// Quoted ETag for a stored object.
+///
+/// An MD5 content hash is used as is. Anything else gets a dashed tag, which
+/// S3 clients read as opaque rather than comparing it with their own MD5.
+fn etag(entry: &Entry) -> String {
+    let metadata = &entry.metadata;
+    if entry.is_directory() {
+        return format!("\"{}\"", EMPTY_ETAG);
+    }
+    match &metadata.content_hash {
+        Some(hash) if hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            format!("\"{}\"", hash.to_ascii_lowercase())
+        }
+        _ => format!(
+            "\"{:x}-{:x}\"",
+            metadata.modified.map_or(0, |t| t.timestamp_micros()),
+            entry.size().unwrap_or(0)
+        ),
+    }
+}
+
+/// Pass `stream` through, failing at its end if it does not hash to the
+/// SHA-256 or MD5 the request promised, and record its hex MD5 in `md5`
+fn verify(
+    stream: ByteStream,
+    sha256: Option<String>,
+    expected_md5: Option<Vec<u8>>,
+    md5: Option<Arc<Mutex<Option<String>>>>,
+) -> ByteStream {
+    if sha256.is_none() && expected_md5.is_none() && md5.is_none() {
+        return stream;
+    }
+    let state = (stream, Sha256::new(), Md5::new());
+    Box::pin(futures::stream::unfold(Some(state), move |state| {
+        let (sha256, expected_md5, md5) = (sha256.clone(), expected_md5.clone(), md5.clone());
+        async move {
+            let (mut stream, mut sha_hasher, mut md5_hasher) = state?;
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    if sha256.is_some() {
+                        sha_hasher.update(&chunk);
+                    }
+                    md5_hasher.update(&chunk);
+                    Some((Ok(chunk), Some((stream, sha_hasher, md5_hasher))))
+                }
+                Some(Err(e)) => Some((Err(e), None)),
+                None => {
+                    let digest = md5_hasher.finalize();
+                    let sha_ok = sha256.is_none_or(|h| hex::encode(sha_hasher.finalize()) == h);
+                    let md5_ok = expected_md5.is_none_or(|m| m == digest.as_slice());
+                    if !(sha_ok && md5_ok) {
+                        return Some((Err(CfkError::ChecksumMismatch), None));
+                    }
+                    if let Some(md5) = md5 {
+                        *md5.lock().unwrap() = Some(hex::encode(digest));
+                    }
+                    None
+                }
+            }
+        }
+    }))
+}
+
+/// Strip `aws-chunked` framing, checking each chunk's signature when the
+/// request was signed chunk by chunk. Trailers after the last chunk are
+/// not checked.
+///
+/// Chunks add up to `decoded_length` when the client declared it. A chunk
+/// is buffered whole before its signature can be checked, so larger ones
+/// are refused before reading them.
+fn dechunk(
+    stream: ByteStream,
+    signer: Option<ChunkSigner>,
+    decoded_length: Option<u64>,
+) -> ByteStream {
+    /// Longest chunk header line: size, extensions and signature
+    const MAX_HEADER: usize = 4096;
+    /// Largest chunk; SDKs send 64 KiB to a few MiB
+    const MAX_CHUNK: u64 = 16 * 1024 * 1024;
+
+    let malformed = |what: &str| CfkError::Serialization(format!("aws-chunked body: {}", what));
+    let state = (stream, BytesMut::new(), signer, decoded_length);
+    Box::pin(futures::stream::try_unfold(
+        Some(state),
+        move |state| async move {
+            let Some((mut stream, mut buf, mut signer, mut remaining)) = state else {
+                return Ok(None);
+            };
+
+            // Fill until the buffer holds `n` bytes
+            async fn fill(
+                stream: &mut ByteStream,
+                buf: &mut BytesMut,
+                n: usize,
+            ) -> CfkResult<bool> {
+                while buf.len() < n {
+                    match stream.next().await {
+                        Some(chunk) => buf.extend_from_slice(&chunk?),
+                        None => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+
+            let line_end = loop {
+                if let Some(end) = buf.windows(2).position(|w| w == b"\r\n") {
+                    break end;
+                }
+                if buf.len() > MAX_HEADER || {
+                    let wanted = buf.len() + 1;
+                    !fill(&mut stream, &mut buf, wanted).await?
+                } {
+                    return Err(malformed("truncated chunk header"));
+                }
+            };
+            let line = String::from_utf8_lossy(&buf[..line_end]).into_owned();
+            buf.advance(line_end + 2);
+
+            let (size, extensions) = line.split_once(';').unwrap_or((&line, ""));
+            let size =
+                u64::from_str_radix(size.trim(), 16).map_err(|_| malformed("bad chunk size"))?;
+            if size > MAX_CHUNK || remaining.is_some_and(|left| size > left) {
+                return Err(malformed("chunk too large"));
+            }
+            if let Some(left) = &mut remaining {
+                *left -= size;
+            }
+            let size = size as usize;
+            let framed = size
+                .checked_add(2)
+                .ok_or_else(|| malformed("chunk too large"))?;
+            if !fill(&mut stream, &mut buf, framed).await? && size > 0 {
+                return Err(malformed("truncated chunk"));
+            }
+            let data = buf.split_to(size).freeze();
+
+            if let Some(signer) = &mut signer {
+                let given = extensions
+                    .split(';')
+                    .find_map(|e| e.trim().strip_prefix("chunk-signature="))
+                    .ok_or_else(|| malformed("missing chunk signature"))?;
+                let string_to_sign = format!(
+                    "AWS4-HMAC-SHA256-PAYLOAD\n{}\n{}\n{}\n{}\n{}",
+                    signer.amz_date,
+                    signer.scope,
+                    signer.previous,
+                    sha256_hex(b""),
+                    sha256_hex(&data)
+                );
+                let expected = hex::encode(hmac_sha256(&signer.key, string_to_sign.as_bytes()));
+                if !constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+                    return Err(CfkError::AuthFailed(
+                        "chunk signature does not match".into(),
+                    ));
+                }
+                signer.previous = expected;
+            }
+
+            if size == 0 {
+                if remaining.is_some_and(|left| left > 0) {
+                    return Err(malformed("shorter than x-amz-decoded-content-length"));
+                }
+                return Ok(None);
+            }
+            if buf.len() < 2 || &buf[..2] != b"\r\n" {
+                return Err(malformed("chunk not followed by CRLF"));
+            }
+            buf.advance(2);
+            Ok(Some((data, Some((stream, buf, signer, remaining)))))
+        },
+    ))
+}
+
+/// The spooled parts of an upload as one stream
+fn concatenate(files: Vec<PathBuf>) -> ByteStream {
+    const READ_SIZE: usize = 256 * 1024;
+
+    let chunks = futures::stream::iter(files)
+        .then(|path| async move { tokio::fs::File::open(path).await.map_err(CfkError::from) })
+        .map_ok(|file| {
+            futures::stream::try_unfold(file, |mut file| async move {
+                let mut buf = vec![0; READ_SIZE];
+                let n = file.read(&mut buf).await?;
+                buf.truncate(n);
+                Ok((n > 0).then(|| (Bytes::from(buf), file)))
+            })
+        })
+        .try_flatten();
+    Box::pin(chunks)
+}
+
+/// A whole body of at most `limit` bytes
+async fn collect(mut stream: ByteStream, limit: usize) -> CfkResult<Bytes> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > limit {
+            return Err(CfkError::Serialization("request body too large".into()));
+        }
+    }
+    Ok(body.freeze())
+}
+
+fn decode(text: &str) -> CfkResult<String> {
+    urlencoding::decode(text)
+        .map(|s| s.into_owned())
+        .map_err(|_| CfkError::InvalidPath(text.to_string()))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// ISO 8601 with milliseconds, as S3 documents carry
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The S3 error code a backend error is reported as
+fn error_code(error: &CfkError) -> (StatusCode, &'static str) {
+    match error {
+        CfkError::NotFound(_) | CfkError::OfflineNoCache => (StatusCode::NOT_FOUND, "NoSuchKey"),
+        CfkError::PermissionDenied(_) => (StatusCode::FORBIDDEN, "AccessDenied"),
+        CfkError::AuthFailed(_) => (StatusCode::FORBIDDEN, "SignatureDoesNotMatch"),
+        CfkError::InvalidPath(_) => (StatusCode::BAD_REQUEST, "InvalidArgument"),
+        CfkError::Serialization(_) => (StatusCode::BAD_REQUEST, "MalformedXML"),
+        CfkError::ChecksumMismatch => (StatusCode::BAD_REQUEST, "BadDigest"),
+        CfkError::AlreadyExists(_)
+        | CfkError::NotADirectory(_)
+        | CfkError::NotAFile(_)
+        | CfkError::DirectoryNotEmpty(_)
+        | CfkError::Conflict(_) => (StatusCode::CONFLICT, "OperationAborted"),
+        CfkError::QuotaExceeded(_) => (StatusCode::INSUFFICIENT_STORAGE, "StorageFull"),
+        CfkError::Unsupported(_) => (StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+        // The remote behind us failed or is busy; clients retry these
+        CfkError::RateLimited { .. } => (StatusCode::SERVICE_UNAVAILABLE, "SlowDown"),
+        CfkError::Network(_)
+        | CfkError::Timeout
+        | CfkError::AuthRequired(_)
+        | CfkError::TokenExpired
+        | CfkError::ProviderApi { .. } => (StatusCode::SERVICE_UNAVAILABLE, "ServiceUnavailable"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "InternalError"),
+    }
+}
+
+fn xml_response(code: StatusCode, xml: String) -> Response<Body> {
+    let mut response = Response::new(full(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}",
+        xml
+    )));
+    *response.status_mut() = code;
+    set(&mut response, header::CONTENT_TYPE, "application/xml");
+    response
+}
+
+fn error(code: StatusCode, name: &str, message: &str, resource: &str) -> Response<Body> {
+    xml_response(
+        code,
+        format!(
+            "<Error><Code>{}</Code><Message>{}</Message><Resource>{}</Resource></Error>",
+            name,
+            escape(message),
+            escape(resource)
+        ),
+    )
+}
+
+fn no_such_upload(key: &str) -> Response<Body> {
+    error(
+        StatusCode::NOT_FOUND,
+        "NoSuchUpload",
+        "the specified multipart upload does not exist",
+        key,
+    )
+}
+
+fn not_implemented() -> Response<Body> {
+    error(
+        StatusCode::NOT_IMPLEMENTED,
+        "NotImplemented",
+        "the gateway does not implement this request",
+        "",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::{S3Backend, S3Config};
+    use crate::LocalBackend;
+    use cfk_core::ResumableUpload;
+    use tempfile::TempDir;
+
+    /// Base URL of a gateway exporting `tmp/export` as `bucket`
+    async fn serve(tmp: &TempDir, options: S3GatewayOptions) -> String {
+        std::fs::create_dir_all(tmp.path().join("export")).unwrap();
+        let local: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let root = VirtualPath::new("local", "/export");
+        let gateway = Arc::new(S3Gateway::new(local, root, options));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(gateway.serve(listener));
+        url
+    }
+
+    fn signed() -> S3GatewayOptions {
+        S3GatewayOptions {
+            bucket: "bucket".to_string(),
+            credentials: Some(("AKID".to_string(), "SECRET".to_string())),
+            ..Default::default()
+        }
+    }
+
+    fn client(url: &str, secret: &str) -> S3Backend {
+        S3Backend::new("s3", S3Config::minio(url, "bucket", "AKID", secret))
+    }
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("s3", p)
+    }
+
+    async fn names(s3: &S3Backend, dir: &str) -> Vec<String> {
+        let listing = s3
+            .list_directory(&path(dir), &ListOptions::default())
+            .await
+            .unwrap();
+        let mut names: Vec<_> = listing
+            .entries
+            .iter()
+            .filter_map(|e| e.name().map(String::from))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_backend_round_trip() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        let s3 = client(&url, "SECRET");
+
+        let data = Bytes::from("0123456789");
+        s3.write_file(
+            &path("/My Docs/a&b.txt"),
+            data.clone(),
+            &WriteOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(tmp.path().join("export/My Docs/a&b.txt")).unwrap(),
+            data
+        );
+        let err = s3
+            .write_file(
+                &path("/My Docs/a&b.txt"),
+                data.clone(),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)), "{:?}", err);
+
+        let options = ReadOptions {
+            range: Some((2, 5)),
+            ..Default::default()
+        };
+        let stream = s3
+            .read_file(&path("/My Docs/a&b.txt"), &options)
+            .await
+            .unwrap();
+        assert_eq!(crate::common::collect_stream(stream).await.unwrap(), "234");
+
+        let entry = s3.get_metadata(&path("/My Docs/a&b.txt")).await.unwrap();
+        assert!(entry.is_file());
+        assert_eq!(entry.size(), Some(10));
+        assert!(s3
+            .get_metadata(&path("/My Docs"))
+            .await
+            .unwrap()
+            .is_directory());
+        assert!(matches!(
+            s3.get_metadata(&path("/missing")).await,
+            Err(CfkError::NotFound(_))
+        ));
+
+        s3.copy(&path("/My Docs"), &path("/Copy"), &CopyOptions::default())
+            .await
+            .unwrap();
+        s3.rename(
+            &path("/Copy/a&b.txt"),
+            &path("/b.txt"),
+            &MoveOptions::default(),
+        )
+        .await
+        .unwrap();
+        // The emptied directory goes with its last object
+        assert!(!tmp.path().join("export/Copy").exists());
+        assert_eq!(names(&s3, "/").await, ["My Docs", "b.txt"]);
+
+        let options = DeleteOptions {
+            recursive: true,
+            force: false,
+        };
+        s3.delete(&path("/My Docs"), &options).await.unwrap();
+        assert!(!tmp.path().join("export/My Docs").exists());
+        assert!(tmp.path().join("export/b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_directory_markers() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        let s3 = client(&url, "SECRET");
+
+        s3.create_directory(&path("/empty")).await.unwrap();
+        assert!(tmp.path().join("export/empty").is_dir());
+        assert!(s3
+            .get_metadata(&path("/empty"))
+            .await
+            .unwrap()
+            .is_directory());
+        assert_eq!(names(&s3, "/").await, ["empty"]);
+        assert!(names(&s3, "/empty").await.is_empty());
+
+        s3.delete(&path("/empty"), &DeleteOptions::default())
+            .await
+            .unwrap();
+        assert!(!tmp.path().join("export/empty").exists());
+    }
+
+    #[tokio::test]
+    async fn test_listing_pages_in_key_order() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        let s3 = client(&url, "SECRET");
+
+        for name in ["a/x", "a.txt", "b", "c/d/e", "c/f"] {
+            std::fs::create_dir_all(tmp.path().join("export").join(name).parent().unwrap())
+                .unwrap();
+            std::fs::write(tmp.path().join("export").join(name), name).unwrap();
+        }
+        std::fs::create_dir_all(tmp.path().join("export/c/g")).unwrap();
+
+        let mut keys = Vec::new();
+        let mut options = ListOptions {
+            recursive: true,
+            limit: Some(2),
+            ..Default::default()
+        };
+        loop {
+            let listing = s3.list_directory(&path("/"), &options).await.unwrap();
+            assert!(listing.entries.len() <= 2);
+            keys.extend(listing.entries.iter().map(|e| e.path.segments.join("/")));
+            match listing.cursor {
+                Some(cursor) => options.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(keys, ["a.txt", "a/x", "b", "c/d/e", "c/f", "c/g"]);
+        assert_eq!(names(&s3, "/c").await, ["d", "f", "g"]);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        let s3 = client(&url, "SECRET");
+
+        let target = path("/big/file.bin");
+        let mut session = s3
+            .create_session(&target, 12, &WriteOptions::default())
+            .await
+            .unwrap();
+        s3.upload_chunk(&mut session, Bytes::from("first-"))
+            .await
+            .unwrap();
+        s3.upload_chunk(&mut session, Bytes::from("second"))
+            .await
+            .unwrap();
+
+        // A resumed session learns its parts from the server
+        session.parts.clear();
+        assert_eq!(s3.query_offset(&mut session).await.unwrap(), 12);
+        assert_eq!(session.parts.len(), 2);
+
+        let entry = s3.finalize(&session).await.unwrap();
+        assert_eq!(entry.size(), Some(12));
+        assert_eq!(
+            std::fs::read(tmp.path().join("export/big/file.bin")).unwrap(),
+            b"first-second"
+        );
+
+        let session = s3
+            .create_session(&path("/gone"), 1, &WriteOptions::default())
+            .await
+            .unwrap();
+        s3.abort(&session).await.unwrap();
+        assert!(matches!(
+            s3.abort(&session).await,
+            Err(CfkError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_objects() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        let s3 = client(&url, "SECRET");
+        for name in ["one", "two"] {
+            std::fs::write(tmp.path().join("export").join(name), name).unwrap();
+        }
+
+        let body = "<Delete><Object><Key>one</Key></Object><Object><Key>two</Key></Object>\
+                    <Object><Key>never</Key></Object></Delete>";
+        let response = s3
+            .request(
+                reqwest::Method::POST,
+                "",
+                &[("delete", "")],
+                &[],
+                Some(Bytes::from(body)),
+            )
+            .await
+            .unwrap();
+        let xml = response.text().await.unwrap();
+        assert_eq!(xml_blocks(&xml, "Deleted").len(), 3, "{}", xml);
+        assert!(!tmp.path().join("export/one").exists());
+        assert!(!tmp.path().join("export/two").exists());
+    }
+
+    #[tokio::test]
+    async fn test_signature_and_read_only() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, signed()).await;
+        std::fs::write(tmp.path().join("export/file"), "data").unwrap();
+
+        let wrong = client(&url, "WRONG");
+        let err = wrong.get_metadata(&path("/file")).await.unwrap_err();
+        assert!(matches!(err, CfkError::PermissionDenied(_)), "{:?}", err);
+        let anonymous = reqwest::get(format!("{}/bucket/file", url)).await.unwrap();
+        assert_eq!(anonymous.status(), 403);
+
+        let options = S3GatewayOptions {
+            read_only: true,
+            ..signed()
+        };
+        let url = serve(&tmp, options).await;
+        let s3 = client(&url, "SECRET");
+        let stream = s3
+            .read_file(&path("/file"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(crate::common::collect_stream(stream).await.unwrap(), "data");
+        let err = s3
+            .write_file(&path("/new"), Bytes::from("x"), &WriteOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::PermissionDenied(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_dechunk_checks_chunk_signatures() {
+        let key = signing_key("SECRET", "20240101", "us-east-1");
+        let signer = ChunkSigner {
+            key: key.clone(),
+            amz_date: "20240101T000000Z".to_string(),
+            scope: credential_scope("20240101", "us-east-1"),
+            previous: "seed".to_string(),
+        };
+
+        // Frame `chunks` the way SDKs do, chaining each signature
+        let frame = |chunks: &[&str]| {
+            let mut previous = signer.previous.clone();
+            let mut body = String::new();
+            for chunk in chunks.iter().chain([&""]) {
+                let string_to_sign = format!(
+                    "AWS4-HMAC-SHA256-PAYLOAD\n{}\n{}\n{}\n{}\n{}",
+                    signer.amz_date,
+                    signer.scope,
+                    previous,
+                    sha256_hex(b""),
+                    sha256_hex(chunk.as_bytes())
+                );
+                previous = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+                body.push_str(&format!(
+                    "{:x};chunk-signature={}\r\n{}\r\n",
+                    chunk.len(),
+                    previous,
+                    chunk
+                ));
+            }
+            body
+        };
+        let stream = |body: String| -> ByteStream {
+            // Split mid-header to exercise buffering
+            let (a, b) = body.split_at(3);
+            let parts = vec![
+                Ok(Bytes::from(a.to_string())),
+                Ok(Bytes::from(b.to_string())),
+            ];
+            Box::pin(futures::stream::iter(parts))
+        };
+
+        let body = frame(&["hello ", "world"]);
+        let decoded = dechunk(stream(body.clone()), Some(signer.clone()), Some(11));
+        assert_eq!(
+            crate::common::collect_stream(decoded).await.unwrap(),
+            "hello world"
+        );
+
+        let decoded = dechunk(
+            stream(body.replace("world", "WORLD")),
+            Some(signer.clone()),
+            None,
+        );
+        assert!(crate::common::collect_stream(decoded).await.is_err());
+
+        let unsigned = "5\r\nhello\r\n0\r\nx-amz-checksum-crc32:AAAAAA==\r\n\r\n".to_string();
+        let decoded = dechunk(stream(unsigned), None, None);
+        assert_eq!(
+            crate::common::collect_stream(decoded).await.unwrap(),
+            "hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_chunks_are_refused_before_buffering() {
+        // A body that never ends, so buffering the chunk would never finish
+        let endless = |header: &'static str| -> ByteStream {
+            let filler = futures::stream::repeat_with(|| Ok(Bytes::from_static(&[0; 1024])));
+            Box::pin(futures::stream::once(async move { Ok(Bytes::from(header)) }).chain(filler))
+        };
+        let cases = [
+            ("ffffffffffffffff\r\n", None),
+            ("1000001;chunk-signature=00\r\n", None),
+            ("400\r\n", Some(100)),
+        ];
+        for (header, decoded_length) in cases {
+            let mut decoded = dechunk(endless(header), None, decoded_length);
+            let err = decoded.next().await.unwrap().unwrap_err();
+            assert!(
+                matches!(err, CfkError::Serialization(_)),
+                "{}: {}",
+                header,
+                err
+            );
+        }
+
+        let short = Bytes::from_static(b"5\r\nhello\r\n0\r\n\r\n");
+        let decoded = dechunk(Box::pin(futures::stream::iter([Ok(short)])), None, Some(6));
+        assert!(crate::common::collect_stream(decoded).await.is_err());
+    }
+}
@@ -0,0 +1,1490 @@
+//! S3-compatible storage backend
+//!
+//! Works with AWS S3, MinIO, Wasabi, DigitalOcean Spaces, Backblaze B2,
+//! Cloudflare R2, and any S3-compatible object storage.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cfk_core::{
+    backend::{ByteStream, FileVersion, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    CfkError, CfkResult, Entry, Metadata, ResumableUpload, StorageBackend, StorageCapabilities,
+    UploadPart, UploadSession, VirtualPath,
+};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Client, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::common;
+use crate::http;
+
+pub mod gateway;
+pub use gateway::{S3Gateway, S3GatewayOptions};
+
+/// Largest page ListObjectsV2 will return
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Default multipart part size; S3 wants at least 5 MiB for all but the last
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Most parts a multipart upload may have
+const MAX_PARTS: u64 = 10_000;
+
+/// S3 backend configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    /// S3 endpoint URL (e.g., "https://s3.amazonaws.com" or "https://minio.example.com")
+    pub endpoint: String,
+    /// Bucket name
+    pub bucket: String,
+    /// AWS region
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Access key ID
+    pub access_key_id: String,
+    /// Secret access key
+    pub secret_access_key: String,
+    /// Use path-style URLs (required for MinIO and some providers)
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl S3Config {
+    /// Create AWS S3 configuration
+    pub fn aws(bucket: &str, region: &str, access_key: &str, secret_key: &str) -> Self {
+        Self {
+            endpoint: format!("https://s3.{}.amazonaws.com", region),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key_id: access_key.to_string(),
+            secret_access_key: secret_key.to_string(),
+            path_style: false,
+        }
+    }
+
+    /// Create MinIO configuration
+    pub fn minio(endpoint: &str, bucket: &str, access_key: &str, secret_key: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            bucket: bucket.to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: access_key.to_string(),
+            secret_access_key: secret_key.to_string(),
+            path_style: true,
+        }
+    }
+
+    /// Create Cloudflare R2 configuration
+    pub fn r2(account_id: &str, bucket: &str, access_key: &str, secret_key: &str) -> Self {
+        Self {
+            endpoint: format!("https://{}.r2.cloudflarestorage.com", account_id),
+            bucket: bucket.to_string(),
+            region: "auto".to_string(),
+            access_key_id: access_key.to_string(),
+            secret_access_key: secret_key.to_string(),
+            path_style: true,
+        }
+    }
+
+    /// Create Backblaze B2 configuration
+    pub fn b2(bucket: &str, region: &str, key_id: &str, app_key: &str) -> Self {
+        Self {
+            endpoint: format!("https://s3.{}.backblazeb2.com", region),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key_id: key_id.to_string(),
+            secret_access_key: app_key.to_string(),
+            path_style: false,
+        }
+    }
+
+    /// Create DigitalOcean Spaces configuration
+    pub fn digitalocean(region: &str, space: &str, key: &str, secret: &str) -> Self {
+        Self {
+            endpoint: format!("https://{}.digitaloceanspaces.com", region),
+            bucket: space.to_string(),
+            region: region.to_string(),
+            access_key_id: key.to_string(),
+            secret_access_key: secret.to_string(),
+            path_style: false,
+        }
+    }
+
+    /// Create Wasabi configuration
+    pub fn wasabi(bucket: &str, region: &str, access_key: &str, secret_key: &str) -> Self {
+        Self {
+            endpoint: format!("https://s3.{}.wasabisys.com", region),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key_id: access_key.to_string(),
+            secret_access_key: secret_key.to_string(),
+            path_style: false,
+        }
+    }
+}
+
+/// S3 storage backend
+pub struct S3Backend {
+    id: String,
+    config: Arc<RwLock<S3Config>>,
+    http: Client,
+    capabilities: StorageCapabilities,
+}
+
+impl S3Backend {
+    pub fn new(id: impl Into<String>, config: S3Config) -> Self {
+        Self {
+            id: id.into(),
+            config: Arc::new(RwLock::new(config)),
+            http: Client::new(),
+            capabilities: StorageCapabilities {
+                read: true,
+                write: true,
+                delete: true,
+                rename: false, // S3 doesn't support rename, need copy+delete
+                copy: true,
+                list: true,
+                search: false,
+                versioning: true,
+                sharing: true, // Presigned URLs
+                offline: false,
+                streaming: true,
+                range_reads: true,
+                resumable_uploads: true, // Multipart upload
+                content_hashing: true,
+                watch: false,
+            },
+        }
+    }
+
+    /// Build URL for bucket operations
+    async fn bucket_url(&self) -> String {
+        let config = self.config.read().await;
+        let endpoint = config.endpoint.trim_end_matches('/');
+
+        if config.path_style {
+            format!("{}/{}", endpoint, config.bucket)
+        } else {
+            // Virtual-hosted style
+            match endpoint.split_once("://") {
+                Some((scheme, host)) => format!("{}://{}.{}", scheme, config.bucket, host),
+                None => format!("{}.{}", config.bucket, endpoint),
+            }
+        }
+    }
+
+    /// Build URL for an object, percent-encoding each key segment
+    async fn object_url(&self, key: &str) -> String {
+        format!("{}/{}", self.bucket_url().await, encode_key(key))
+    }
+
+    /// Sign request with AWS Signature Version 4
+    async fn sign_request(
+        &self,
+        method: &Method,
+        url: &str,
+        headers: &mut BTreeMap<String, String>,
+        payload_hash: &str,
+    ) -> CfkResult<String> {
+        let config = self.config.read().await;
+        let now = Utc::now();
+        let date_stamp = now.format("%Y%m%d").to_string();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+        headers.insert("x-amz-date".to_string(), amz_date.clone());
+        headers.insert("x-amz-content-sha256".to_string(), payload_hash.to_string());
+
+        // Parse URL
+        let parsed = url::Url::parse(url).map_err(|e| CfkError::InvalidPath(e.to_string()))?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => String::new(),
+        };
+        let path = parsed.path();
+        let query = canonical_query(parsed.query().unwrap_or(""));
+
+        headers.insert("host".to_string(), host);
+
+        let (canonical, signed_headers) =
+            canonical_request(method.as_str(), path, &query, headers, payload_hash);
+        let scope = credential_scope(&date_stamp, &config.region);
+        let key = signing_key(&config.secret_access_key, &date_stamp, &config.region);
+        let signature = signature(&key, &amz_date, &scope, &canonical);
+
+        // Build authorization header
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            config.access_key_id, scope, signed_headers, signature
+        );
+
+        Ok(authorization)
+    }
+
+    /// Make a signed request against the bucket (empty key) or an object.
+    ///
+    /// Query values are percent-encoded here; `extra_headers` are signed.
+    async fn request(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Option<Bytes>,
+    ) -> CfkResult<reqwest::Response> {
+        let response = self
+            .request_unchecked(method, key, query, extra_headers, body)
+            .await?;
+        http::check_response("s3", response).await
+    }
+
+    /// [`Self::request`] without mapping error statuses
+    async fn request_unchecked(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Option<Bytes>,
+    ) -> CfkResult<reqwest::Response> {
+        let url = if key.is_empty() {
+            self.bucket_url().await
+        } else {
+            self.object_url(key).await
+        };
+        self.send_signed(method, url, query, extra_headers, body).await
+    }
+
+    /// Make a signed request to `path` under the endpoint rather than the
+    /// bucket, for services such as the Ceph RGW admin API that share the
+    /// S3 credentials
+    #[cfg(feature = "ceph")]
+    pub(crate) async fn endpoint_request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> CfkResult<reqwest::Response> {
+        let url = format!(
+            "{}{}",
+            self.config.read().await.endpoint.trim_end_matches('/'),
+            path
+        );
+        let response = self.send_signed(method, url, query, &[], None).await?;
+        http::check_response("s3", response).await
+    }
+
+    /// Percent-encode `query` onto `url`, sign and send
+    async fn send_signed(
+        &self,
+        method: Method,
+        mut url: String,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Option<Bytes>,
+    ) -> CfkResult<reqwest::Response> {
+        if !query.is_empty() {
+            let encoded: Vec<String> = query
+                .iter()
+                .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+                .collect();
+            url.push('?');
+            url.push_str(&encoded.join("&"));
+        }
+
+        let payload_hash = if let Some(ref data) = body {
+            sha256_hex(data)
+        } else {
+            sha256_hex(b"")
+        };
+
+        let mut headers: BTreeMap<String, String> = extra_headers
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), v.clone()))
+            .collect();
+        let auth = self
+            .sign_request(&method, &url, &mut headers, &payload_hash)
+            .await?;
+
+        let mut request = self.http.request(method, &url);
+
+        for (k, v) in &headers {
+            request = request.header(k, v);
+        }
+        request = request.header(header::AUTHORIZATION, auth);
+
+        if let Some(data) = body {
+            request = request.body(data);
+        }
+
+        request.send().await.map_err(http::network_error)
+    }
+
+    /// List objects with prefix
+    async fn list_objects(
+        &self,
+        prefix: &str,
+        delimiter: Option<&str>,
+        continuation_token: Option<&str>,
+        max_keys: usize,
+    ) -> CfkResult<ListObjectsResult> {
+        let max_keys = max_keys.to_string();
+        let mut query = vec![("list-type", "2"), ("max-keys", max_keys.as_str())];
+
+        if !prefix.is_empty() {
+            query.push(("prefix", prefix));
+        }
+        if let Some(d) = delimiter {
+            query.push(("delimiter", d));
+        }
+        if let Some(token) = continuation_token {
+            query.push(("continuation-token", token));
+        }
+
+        let response = self.request(Method::GET, "", &query, &[], None).await?;
+        let text = response.text().await.map_err(http::network_error)?;
+        parse_list_objects_v2(&text)
+    }
+
+    /// All object keys under a prefix, following continuation tokens
+    async fn keys_with_prefix(&self, prefix: &str) -> CfkResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+
+        loop {
+            let result = self
+                .list_objects(prefix, None, token.as_deref(), LIST_PAGE_SIZE)
+                .await?;
+            keys.extend(result.objects.into_iter().map(|o| o.key));
+
+            if !result.is_truncated || result.continuation_token.is_none() {
+                return Ok(keys);
+            }
+            token = result.continuation_token;
+        }
+    }
+
+    /// Server-side copy of a single object
+    async fn copy_object(&self, from_key: &str, to_key: &str) -> CfkResult<()> {
+        let bucket = self.config.read().await.bucket.clone();
+        let copy_source = format!("/{}/{}", bucket, encode_key(from_key));
+
+        self.request(
+            Method::PUT,
+            to_key,
+            &[],
+            &[("x-amz-copy-source", copy_source)],
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// HEAD an object, returning `None` if it does not exist
+    async fn head_object(&self, path: &VirtualPath) -> CfkResult<Option<Entry>> {
+        let key = self.to_key(path);
+        let response = match self.request(Method::HEAD, &key, &[], &[], None).await {
+            Ok(response) => response,
+            Err(CfkError::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let headers = response.headers();
+        let header_str = |name: header::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+
+        let metadata = Metadata {
+            size: header_str(header::CONTENT_LENGTH).and_then(|s| s.parse().ok()),
+            modified: header_str(header::LAST_MODIFIED)
+                .and_then(|s| DateTime::parse_from_rfc2822(&s).ok())
+                .map(|dt| dt.with_timezone(&Utc)),
+            content_hash: header_str(header::ETAG).map(|s| s.trim_matches('"').to_string()),
+            mime_type: header_str(header::CONTENT_TYPE),
+            revision: headers
+                .get("x-amz-version-id")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            ..Default::default()
+        };
+
+        Ok(Some(Entry::file(path.clone(), metadata)))
+    }
+
+    /// Convert VirtualPath to S3 key
+    fn to_key(&self, path: &VirtualPath) -> String {
+        path.segments.join("/")
+    }
+
+    /// Key prefix for the "directory" at a path
+    fn to_prefix(&self, path: &VirtualPath) -> String {
+        let mut prefix = self.to_key(path);
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        prefix
+    }
+}
+
+/// Percent-encode an object key, leaving the `/` separators intact
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|s| urlencoding::encode(s).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Build the SigV4 canonical query string from an already-encoded query.
+///
+/// Each name and value is re-encoded the way SigV4 wants, so a query that
+/// left `/` or `:` bare canonicalizes the same as one that escaped them.
+fn canonical_query(query: &str) -> String {
+    let encode = |s: &str| {
+        let decoded = urlencoding::decode(s).map(|d| d.into_owned());
+        urlencoding::encode(&decoded.unwrap_or_else(|_| s.to_string())).into_owned()
+    };
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (encode(k), encode(v))
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// SigV4 canonical request for lowercase `headers`, with the
+/// `SignedHeaders` list that goes with it
+fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &BTreeMap<String, String>,
+    payload_hash: &str,
+) -> (String, String) {
+    let signed_headers = headers.keys().map(String::as_str).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect();
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, path, query, canonical_headers, signed_headers, payload_hash
+    );
+    (canonical, signed_headers)
+}
+
+/// `date/region/s3/aws4_request`
+fn credential_scope(date_stamp: &str, region: &str) -> String {
+    format!("{}/{}/s3/aws4_request", date_stamp, region)
+}
+
+/// Key derived from the secret for one day and region
+fn signing_key(secret: &str, date_stamp: &str, region: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date_stamp.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, b"s3");
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// Hex signature over a canonical request
+fn signature(key: &[u8], amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    hex::encode(hmac_sha256(key, string_to_sign.as_bytes()))
+}
+
+/// S3 object metadata
+#[derive(Debug, Clone, Default)]
+struct S3Object {
+    key: String,
+    size: u64,
+    last_modified: Option<DateTime<Utc>>,
+    etag: Option<String>,
+    storage_class: Option<String>,
+}
+
+/// Common prefix (directory) in listing
+#[derive(Debug, Clone)]
+struct CommonPrefix {
+    prefix: String,
+}
+
+/// List objects result
+#[derive(Debug, Clone, Default)]
+struct ListObjectsResult {
+    objects: Vec<S3Object>,
+    common_prefixes: Vec<CommonPrefix>,
+    is_truncated: bool,
+    continuation_token: Option<String>,
+}
+
+/// Parse ListObjectsV2 XML response
+fn parse_list_objects_v2(xml: &str) -> CfkResult<ListObjectsResult> {
+    let mut result = ListObjectsResult::default();
+
+    for block in xml_blocks(xml, "Contents") {
+        result.objects.push(S3Object {
+            key: extract_xml_value(block, "Key").unwrap_or_default(),
+            size: extract_xml_value(block, "Size")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            last_modified: extract_xml_value(block, "LastModified")
+                .and_then(|m| DateTime::parse_from_rfc3339(&m).ok())
+                .map(|dt| dt.with_timezone(&Utc)),
+            etag: extract_xml_value(block, "ETag").map(|e| e.trim_matches('"').to_string()),
+            storage_class: extract_xml_value(block, "StorageClass"),
+        });
+    }
+
+    for block in xml_blocks(xml, "CommonPrefixes") {
+        if let Some(prefix) = extract_xml_value(block, "Prefix") {
+            result.common_prefixes.push(CommonPrefix { prefix });
+        }
+    }
+
+    if let Some(truncated) = extract_xml_value(xml, "IsTruncated") {
+        result.is_truncated = truncated == "true";
+    }
+    result.continuation_token = extract_xml_value(xml, "NextContinuationToken");
+
+    Ok(result)
+}
+
+/// ListObjectVersions result, delete markers left out
+#[derive(Debug, Clone, Default)]
+struct ListVersionsResult {
+    versions: Vec<(String, FileVersion)>,
+    is_truncated: bool,
+    next_key_marker: Option<String>,
+    next_version_id_marker: Option<String>,
+}
+
+/// Parse ListObjectVersions XML response
+fn parse_list_object_versions(xml: &str) -> CfkResult<ListVersionsResult> {
+    let mut result = ListVersionsResult::default();
+
+    for block in xml_blocks(xml, "Version") {
+        let key = extract_xml_value(block, "Key").unwrap_or_default();
+        let id = extract_xml_value(block, "VersionId").unwrap_or_else(|| "null".to_string());
+        let modified = extract_xml_value(block, "LastModified")
+            .and_then(|m| DateTime::parse_from_rfc3339(&m).ok())
+            .map(|dt| dt.with_timezone(&Utc))
+            .ok_or_else(|| CfkError::Serialization(format!("version {} of {} has no date", id, key)))?;
+        let owner = xml_blocks(block, "Owner").first().copied().unwrap_or_default();
+        result.versions.push((
+            key,
+            FileVersion {
+                id,
+                modified,
+                size: extract_xml_value(block, "Size").and_then(|s| s.parse().ok()),
+                author: extract_xml_value(owner, "DisplayName").or_else(|| extract_xml_value(owner, "ID")),
+            },
+        ));
+    }
+
+    if let Some(truncated) = extract_xml_value(xml, "IsTruncated") {
+        result.is_truncated = truncated == "true";
+    }
+    result.next_key_marker = extract_xml_value(xml, "NextKeyMarker");
+    result.next_version_id_marker = extract_xml_value(xml, "NextVersionIdMarker");
+
+    Ok(result)
+}
+
+/// Inner text of every `<tag>...</tag>` element, in document order
+fn xml_blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let start_tag = format!("<{}>", tag);
+    let end_tag = format!("</{}>", tag);
+    let mut blocks = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&start_tag) {
+        let content_start = start + start_tag.len();
+        match rest[content_start..].find(&end_tag) {
+            Some(end) => {
+                blocks.push(&rest[content_start..content_start + end]);
+                rest = &rest[content_start + end + end_tag.len()..];
+            }
+            None => break,
+        }
+    }
+
+    blocks
+}
+
+/// Extract the unescaped value of the first `<tag>` element
+fn extract_xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_blocks(xml, tag).first().map(|v| xml_unescape(v))
+}
+
+/// Decode the predefined XML entities
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#34;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// SHA-256 hash as hex string
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// HMAC-SHA256
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    type HmacSha256 = Hmac<Sha256>;
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Part size for a `size`-byte upload, grown so it fits in `MAX_PARTS`
+fn part_size(size: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    let needed = size.div_ceil(MAX_PARTS).div_ceil(MIB) * MIB;
+    needed.max(PART_SIZE)
+}
+
+/// Parts listed by ListParts, with the marker of the next page if truncated
+fn parse_list_parts(xml: &str) -> (Vec<UploadPart>, Option<String>) {
+    let parts = xml_blocks(xml, "Part")
+        .into_iter()
+        .filter_map(|block| {
+            Some(UploadPart {
+                number: extract_xml_value(block, "PartNumber")?.parse().ok()?,
+                offset: 0,
+                size: extract_xml_value(block, "Size")?.parse().ok()?,
+                tag: extract_xml_value(block, "ETag")?,
+                checksum: None,
+            })
+        })
+        .collect();
+    let next = match extract_xml_value(xml, "IsTruncated").as_deref() {
+        Some("true") => extract_xml_value(xml, "NextPartNumberMarker"),
+        _ => None,
+    };
+    (parts, next)
+}
+
+/// Multipart upload
+#[async_trait]
+impl ResumableUpload for S3Backend {
+    async fn create_session(
+        &self,
+        path: &VirtualPath,
+        size: u64,
+        options: &WriteOptions,
+    ) -> CfkResult<UploadSession> {
+        if !options.overwrite && self.head_object(path).await?.is_some() {
+            return Err(CfkError::AlreadyExists(path.to_string()));
+        }
+
+        let key = self.to_key(path);
+        let response = self
+            .request(Method::POST, &key, &[("uploads", "")], &[], None)
+            .await?;
+        let xml = response.text().await.map_err(http::network_error)?;
+        let upload_id =
+            extract_xml_value(&xml, "UploadId").ok_or_else(|| CfkError::ProviderApi {
+                provider: "s3".into(),
+                message: "CreateMultipartUpload returned no UploadId".into(),
+            })?;
+
+        Ok(UploadSession::new(
+            path.clone(),
+            size,
+            upload_id,
+            part_size(size),
+            options.overwrite,
+        ))
+    }
+
+    async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+        let number = session.parts.len() as u32 + 1;
+        let size = data.len() as u64;
+        let key = self.to_key(&session.path);
+        let number_str = number.to_string();
+        let query = [
+            ("partNumber", number_str.as_str()),
+            ("uploadId", session.id.as_str()),
+        ];
+
+        let response = self
+            .request(Method::PUT, &key, &query, &[], Some(data))
+            .await?;
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| CfkError::ProviderApi {
+                provider: "s3".into(),
+                message: format!("UploadPart {} returned no ETag", number),
+            })?
+            .to_string();
+
+        session.parts.push(UploadPart {
+            number,
+            offset: session.offset,
+            size,
+            tag: etag,
+            checksum: None,
+        });
+        session.offset += size;
+        Ok(())
+    }
+
+    async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+        let key = self.to_key(&session.path);
+        let mut listed = Vec::new();
+        let mut marker: Option<String> = None;
+
+        loop {
+            let mut query = vec![("uploadId", session.id.as_str())];
+            if let Some(marker) = &marker {
+                query.push(("part-number-marker", marker.as_str()));
+            }
+            let response = self.request(Method::GET, &key, &query, &[], None).await?;
+            let xml = response.text().await.map_err(http::network_error)?;
+            let (parts, next) = parse_list_parts(&xml);
+            listed.extend(parts);
+            match next {
+                Some(next) => marker = Some(next),
+                None => break,
+            }
+        }
+
+        // Only an unbroken run of parts from the first one counts
+        listed.sort_by_key(|p| p.number);
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        for (expected, mut part) in (1..).zip(listed) {
+            if part.number != expected {
+                break;
+            }
+            part.offset = offset;
+            offset += part.size;
+            parts.push(part);
+        }
+
+        session.parts = parts;
+        session.offset = offset;
+        Ok(offset)
+    }
+
+    async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+        let key = self.to_key(&session.path);
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for part in &session.parts {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part.number, part.tag
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+
+        let response = self
+            .request(
+                Method::POST,
+                &key,
+                &[("uploadId", session.id.as_str())],
+                &[],
+                Some(Bytes::from(body)),
+            )
+            .await?;
+
+        // A failed completion can still come back as 200 with an error body
+        let xml = response.text().await.map_err(http::network_error)?;
+        if xml.contains("<Error>") {
+            return Err(CfkError::ProviderApi {
+                provider: "s3".into(),
+                message: extract_xml_value(&xml, "Message").unwrap_or(xml),
+            });
+        }
+
+        self.get_metadata(&session.path).await
+    }
+
+    async fn abort(&self, session: &UploadSession) -> CfkResult<()> {
+        let key = self.to_key(&session.path);
+        self.request(
+            Method::DELETE,
+            &key,
+            &[("uploadId", session.id.as_str())],
+            &[],
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn display_name(&self) -> &str {
+        "S3"
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
+
+    fn resumable(&self) -> Option<&dyn ResumableUpload> {
+        Some(self)
+    }
+
+    async fn is_available(&self) -> bool {
+        self.list_objects("", Some("/"), None, 1).await.is_ok()
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        if path.is_root() {
+            return Ok(Entry::directory(path.clone(), Metadata::default()));
+        }
+
+        if let Some(entry) = self.head_object(path).await? {
+            return Ok(entry);
+        }
+
+        // Check if it's a directory (prefix)
+        let list = self
+            .list_objects(&self.to_prefix(path), Some("/"), None, 1)
+            .await?;
+        if !list.objects.is_empty() || !list.common_prefixes.is_empty() {
+            return Ok(Entry::directory(path.clone(), Metadata::default()));
+        }
+
+        Err(CfkError::NotFound(path.to_string()))
+    }
+
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let prefix = self.to_prefix(path);
+        let delimiter = if options.recursive { None } else { Some("/") };
+        let max_keys = options
+            .limit
+            .unwrap_or(LIST_PAGE_SIZE)
+            .clamp(1, LIST_PAGE_SIZE);
+
+        let result = self
+            .list_objects(&prefix, delimiter, options.cursor.as_deref(), max_keys)
+            .await?;
+
+        let visible = |key: &str| {
+            options.include_hidden
+                || !key
+                    .trim_start_matches(&prefix)
+                    .split('/')
+                    .any(|s| s.starts_with('.'))
+        };
+
+        let mut entries = Vec::new();
+
+        // Add objects
+        for obj in result.objects {
+            let relative = obj.key.trim_start_matches(&prefix);
+            if relative.is_empty() || !visible(&obj.key) {
+                continue;
+            }
+
+            // Zero-byte "dir/" markers created by create_directory
+            if obj.key.ends_with('/') {
+                entries.push(Entry::directory(
+                    VirtualPath::new(&self.id, &obj.key),
+                    Metadata::default(),
+                ));
+                continue;
+            }
+
+            let mut metadata = Metadata {
+                size: Some(obj.size),
+                modified: obj.last_modified,
+                content_hash: obj.etag,
+                ..Default::default()
+            };
+            if let Some(class) = obj.storage_class {
+                metadata.custom.insert("storage_class".to_string(), class);
+            }
+
+            entries.push(Entry::file(VirtualPath::new(&self.id, &obj.key), metadata));
+        }
+
+        // Add directories (common prefixes)
+        for cp in result.common_prefixes {
+            if !visible(&cp.prefix) {
+                continue;
+            }
+            entries.push(Entry::directory(
+                VirtualPath::new(&self.id, cp.prefix.trim_end_matches('/')),
+                Metadata::default(),
+            ));
+        }
+
+        let has_more = result.is_truncated && result.continuation_token.is_some();
+        Ok(DirectoryListing {
+            path: path.clone(),
+            entries,
+            cursor: if has_more {
+                result.continuation_token
+            } else {
+                None
+            },
+            has_more,
+        })
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let key = self.to_key(path);
+        let headers: Vec<_> = options
+            .range
+            .map(|range| ("range", http::range_header(range)))
+            .into_iter()
+            .collect();
+        let response = self
+            .request_unchecked(Method::GET, &key, &[], &headers, None)
+            .await?;
+
+        http::ranged_stream("s3", response, options.range)
+            .await
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+                e => e,
+            })
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let key = self.to_key(path);
+
+        // Conditional write; servers without support ignore the header
+        let mut headers = Vec::new();
+        if !options.overwrite {
+            headers.push(("if-none-match", "*".to_string()));
+        }
+
+        let response = self
+            .request(Method::PUT, &key, &[], &headers, Some(data))
+            .await
+            .map_err(|e| match e {
+                CfkError::Conflict(_) => CfkError::AlreadyExists(path.to_string()),
+                e => e,
+            })?;
+
+        if let Some(expected) = &options.content_hash {
+            let etag = response
+                .headers()
+                .get(header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.trim_matches('"'));
+            if etag != Some(expected.as_str()) {
+                return Err(CfkError::ChecksumMismatch);
+            }
+        }
+
+        self.get_metadata(path).await
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let data = common::collect_stream(stream).await?;
+        self.write_file(path, data, options).await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if path.is_root() {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        }
+
+        if self.head_object(path).await?.is_some() {
+            self.request(Method::DELETE, &self.to_key(path), &[], &[], None)
+                .await?;
+            return Ok(());
+        }
+
+        // Not an object: treat as a prefix
+        let prefix = self.to_prefix(path);
+        let keys = self.keys_with_prefix(&prefix).await?;
+
+        if keys.is_empty() {
+            return common::ignore_missing(Err(CfkError::NotFound(path.to_string())), options);
+        }
+        if !options.recursive && keys.iter().any(|k| k != &prefix) {
+            return Err(CfkError::DirectoryNotEmpty(path.to_string()));
+        }
+
+        for key in keys {
+            self.request(Method::DELETE, &key, &[], &[], None).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        // S3 doesn't have real directories, create a zero-byte object with trailing slash
+        let key = self.to_prefix(path);
+
+        self.request(Method::PUT, &key, &[], &[], Some(Bytes::new()))
+            .await?;
+
+        Ok(Entry::directory(path.clone(), Metadata::default()))
+    }
+
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        common::prepare_destination(self, dest, options.overwrite).await?;
+
+        let from_key = self.to_key(source);
+        let to_key = self.to_key(dest);
+
+        if self.head_object(source).await?.is_some() {
+            self.copy_object(&from_key, &to_key).await?;
+            return self.get_metadata(dest).await;
+        }
+
+        // Copy every object under the source prefix
+        let from_prefix = self.to_prefix(source);
+        let to_prefix = self.to_prefix(dest);
+        let keys = self.keys_with_prefix(&from_prefix).await?;
+        if keys.is_empty() {
+            return Err(CfkError::NotFound(source.to_string()));
+        }
+
+        for key in keys {
+            let target = format!("{}{}", to_prefix, &key[from_prefix.len()..]);
+            self.copy_object(&key, &target).await?;
+        }
+
+        Ok(Entry::directory(dest.clone(), Metadata::default()))
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        // S3 doesn't support rename, use copy + delete
+        let copy_options = CopyOptions {
+            overwrite: options.overwrite,
+            preserve_metadata: true,
+        };
+        let entry = self.copy(source, dest, &copy_options).await?;
+
+        let delete_options = DeleteOptions {
+            recursive: true,
+            force: false,
+        };
+        self.delete(source, &delete_options).await?;
+
+        Ok(entry)
+    }
+
+    async fn get_versions(&self, path: &VirtualPath) -> CfkResult<Vec<FileVersion>> {
+        let key = self.to_key(path);
+        let max_keys = LIST_PAGE_SIZE.to_string();
+        let mut versions = Vec::new();
+        let mut markers: Option<(String, String)> = None;
+
+        loop {
+            let mut query = vec![
+                ("versions", ""),
+                ("prefix", key.as_str()),
+                ("max-keys", max_keys.as_str()),
+            ];
+            if let Some((key_marker, version_marker)) = &markers {
+                query.push(("key-marker", key_marker));
+                query.push(("version-id-marker", version_marker));
+            }
+            let response = self.request(Method::GET, "", &query, &[], None).await?;
+            let text = response.text().await.map_err(http::network_error)?;
+            let result = parse_list_object_versions(&text)?;
+
+            // The prefix also matches longer keys, which sort after this one
+            let past = result.versions.iter().any(|(k, _)| *k > key);
+            versions.extend(
+                result
+                    .versions
+                    .into_iter()
+                    .filter(|(k, _)| *k == key)
+                    .map(|(_, version)| version),
+            );
+            match (result.is_truncated && !past, result.next_key_marker, result.next_version_id_marker) {
+                (true, Some(key_marker), Some(version_marker)) => {
+                    markers = Some((key_marker, version_marker))
+                }
+                _ => break,
+            }
+        }
+
+        if versions.is_empty() {
+            return Err(CfkError::NotFound(path.to_string()));
+        }
+        Ok(versions)
+    }
+
+    async fn get_version(&self, path: &VirtualPath, version_id: &str) -> CfkResult<ByteStream> {
+        let key = self.to_key(path);
+        let response = self
+            .request(Method::GET, &key, &[("versionId", version_id)], &[], None)
+            .await
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(format!("{} version {}", path, version_id)),
+                e => e,
+            })?;
+        Ok(http::body_stream(response))
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        // S3 buckets have no quota
+        Ok(SpaceInfo::unknown())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header_exists, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn backend(server: &MockServer) -> S3Backend {
+        S3Backend::new(
+            "s3",
+            S3Config::minio(&server.uri(), "bucket", "AKID", "SECRET"),
+        )
+    }
+
+    #[test]
+    fn test_parse_single_line_listing() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult><Name>bucket</Name><Prefix>docs/</Prefix><IsTruncated>true</IsTruncated><NextContinuationToken>tok</NextContinuationToken><Contents><Key>docs/a &amp; b.txt</Key><Size>5</Size><ETag>&quot;abc&quot;</ETag><LastModified>2024-01-01T00:00:00.000Z</LastModified></Contents><CommonPrefixes><Prefix>docs/sub/</Prefix></CommonPrefixes></ListBucketResult>"#;
+
+        let result = parse_list_objects_v2(xml).unwrap();
+        assert_eq!(result.objects.len(), 1);
+        assert_eq!(result.objects[0].key, "docs/a & b.txt");
+        assert_eq!(result.objects[0].etag.as_deref(), Some("abc"));
+        assert_eq!(result.common_prefixes.len(), 1);
+        assert_eq!(result.common_prefixes[0].prefix, "docs/sub/");
+        assert!(result.is_truncated);
+        assert_eq!(result.continuation_token.as_deref(), Some("tok"));
+    }
+
+    #[test]
+    fn test_canonical_query_is_sorted() {
+        assert_eq!(
+            canonical_query("prefix=a%2Fb&list-type=2&delimiter=%2F"),
+            "delimiter=%2F&list-type=2&prefix=a%2Fb"
+        );
+        assert_eq!(canonical_query("uploads"), "uploads=");
+        assert_eq!(canonical_query("prefix=a/b"), "prefix=a%2Fb");
+    }
+
+    #[test]
+    fn test_signature_matches_aws_example() {
+        // GET Object example from the SigV4 documentation
+        let headers: BTreeMap<String, String> = [
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"),
+            ("x-amz-content-sha256", EMPTY_SHA256),
+            ("x-amz-date", "20130524T000000Z"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let (canonical, signed) = canonical_request("GET", "/test.txt", "", &headers, EMPTY_SHA256);
+        assert_eq!(signed, "host;range;x-amz-content-sha256;x-amz-date");
+
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY", "20130524", "us-east-1");
+        let scope = credential_scope("20130524", "us-east-1");
+        assert_eq!(
+            signature(&key, "20130524T000000Z", &scope, &canonical),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_virtual_hosted_url() {
+        let backend = S3Backend::new("s3", S3Config::aws("photos", "eu-west-1", "a", "b"));
+        assert_eq!(
+            backend.object_url("2024/my photo.jpg").await,
+            "https://photos.s3.eu-west-1.amazonaws.com/2024/my%20photo.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_with_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket"))
+            .and(query_param("prefix", "docs/"))
+            .and(query_param("delimiter", "/"))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                 <NextContinuationToken>next</NextContinuationToken>\
+                 <Contents><Key>docs/</Key><Size>0</Size></Contents>\
+                 <Contents><Key>docs/a.txt</Key><Size>3</Size><ETag>\"e\"</ETag></Contents>\
+                 <CommonPrefixes><Prefix>docs/img/</Prefix></CommonPrefixes>\
+                 </ListBucketResult>",
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let listing = backend
+            .list_directory(&VirtualPath::new("s3", "docs"), &ListOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 2);
+        assert_eq!(listing.entries[0].path.to_path_string(), "/docs/a.txt");
+        assert_eq!(
+            listing.entries[0].metadata.content_hash.as_deref(),
+            Some("e")
+        );
+        assert!(listing.entries[1].is_directory());
+        assert!(listing.has_more);
+        assert_eq!(listing.cursor.as_deref(), Some("next"));
+    }
+
+    #[tokio::test]
+    async fn test_write_without_overwrite_is_conditional() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/bucket/a.txt"))
+            .and(wiremock::matchers::header("if-none-match", "*"))
+            .respond_with(ResponseTemplate::new(412))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let err = backend
+            .write_file(
+                &VirtualPath::new("s3", "a.txt"),
+                Bytes::from_static(b"x"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)));
+    }
+
+    #[tokio::test]
+    async fn test_head_missing_key_checks_prefix() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/bucket/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bucket"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>",
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let err = backend
+            .get_metadata(&VirtualPath::new("s3", "missing"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)));
+    }
+
+    #[test]
+    fn test_part_size_fits_part_limit() {
+        assert_eq!(part_size(1), PART_SIZE);
+        let size = 200 * 1024 * 1024 * 1024;
+        assert!(part_size(size) * MAX_PARTS >= size);
+        assert_eq!(part_size(size) % (1024 * 1024), 0);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_resumes_from_listed_parts() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/bucket/big.bin"))
+            .and(query_param("uploads", ""))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/bucket/big.bin"))
+            .and(query_param("partNumber", "1"))
+            .and(query_param("uploadId", "up-1"))
+            .respond_with(ResponseTemplate::new(200).insert_header("etag", "\"p1\""))
+            .mount(&server)
+            .await;
+        // Parts 1 and 3 made it, 2 did not
+        Mock::given(method("GET"))
+            .and(path("/bucket/big.bin"))
+            .and(query_param("uploadId", "up-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListPartsResult><IsTruncated>false</IsTruncated>\
+                 <Part><PartNumber>3</PartNumber><ETag>&quot;p3&quot;</ETag><Size>4</Size></Part>\
+                 <Part><PartNumber>1</PartNumber><ETag>&quot;p1&quot;</ETag><Size>4</Size></Part>\
+                 </ListPartsResult>",
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let options = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        let mut session = backend
+            .create_session(&VirtualPath::new("s3", "big.bin"), 12, &options)
+            .await
+            .unwrap();
+        assert_eq!(session.id, "up-1");
+
+        backend
+            .upload_chunk(&mut session, Bytes::from_static(b"abcd"))
+            .await
+            .unwrap();
+        assert_eq!(session.offset, 4);
+        assert_eq!(session.parts[0].tag, "\"p1\"");
+
+        session.offset = 0;
+        session.parts.clear();
+        assert_eq!(backend.query_offset(&mut session).await.unwrap(), 4);
+        assert_eq!(session.parts.len(), 1);
+        assert_eq!(session.parts[0].tag, "\"p1\"");
+    }
+
+    #[tokio::test]
+    async fn test_versions_of_one_key_across_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket"))
+            .and(query_param("versions", ""))
+            .and(query_param("key-marker", "notes.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListVersionsResult><IsTruncated>true</IsTruncated>\
+                 <NextKeyMarker>notes.txt.bak</NextKeyMarker><NextVersionIdMarker>v9</NextVersionIdMarker>\
+                 <Version><Key>notes.txt</Key><VersionId>v1</VersionId><IsLatest>false</IsLatest>\
+                 <LastModified>2024-01-01T00:00:00.000Z</LastModified><Size>3</Size></Version>\
+                 <Version><Key>notes.txt.bak</Key><VersionId>v9</VersionId>\
+                 <LastModified>2024-01-01T00:00:00.000Z</LastModified><Size>1</Size></Version>\
+                 </ListVersionsResult>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bucket"))
+            .and(query_param("versions", ""))
+            .and(query_param("prefix", "notes.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<ListVersionsResult><IsTruncated>true</IsTruncated>\
+                 <NextKeyMarker>notes.txt</NextKeyMarker><NextVersionIdMarker>v2</NextVersionIdMarker>\
+                 <DeleteMarker><Key>notes.txt</Key><VersionId>d1</VersionId>\
+                 <LastModified>2024-03-01T00:00:00.000Z</LastModified></DeleteMarker>\
+                 <Version><Key>notes.txt</Key><VersionId>v2</VersionId><IsLatest>false</IsLatest>\
+                 <LastModified>2024-02-01T00:00:00.000Z</LastModified><Size>5</Size>\
+                 <Owner><ID>abc</ID><DisplayName>ada</DisplayName></Owner></Version>\
+                 </ListVersionsResult>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/bucket/notes.txt"))
+            .and(query_param("versionId", "v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(&b"one"[..]))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let file = VirtualPath::new("s3", "notes.txt");
+        let versions = backend.get_versions(&file).await.unwrap();
+        let summary: Vec<_> = versions
+            .iter()
+            .map(|v| (v.id.as_str(), v.size, v.author.as_deref()))
+            .collect();
+        assert_eq!(summary, [("v2", Some(5), Some("ada")), ("v1", Some(3), None)]);
+
+        let old = common::collect_stream(backend.get_version(&file, "v1").await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&old[..], b"one");
+    }
+
+    #[tokio::test]
+    async fn test_ranged_read_sends_range_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket/big.bin"))
+            .and(wiremock::matchers::header("range", "bytes=10-19"))
+            .respond_with(ResponseTemplate::new(206).set_body_bytes(&b"0123456789"[..]))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let options = ReadOptions {
+            range: Some((10, 20)),
+            ..Default::default()
+        };
+        let stream = backend
+            .read_file(&VirtualPath::new("s3", "big.bin"), &options)
+            .await
+            .unwrap();
+        let data = common::collect_stream(stream).await.unwrap();
+        assert_eq!(&data[..], b"0123456789");
+    }
+}
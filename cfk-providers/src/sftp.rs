@@ -1,18 +1,47 @@
 //! SFTP storage backend
 //!
-//! SSH File Transfer Protocol implementation.
-//! Supports password, key-based, and agent authentication.
+//! SSH File Transfer Protocol over a pure-Rust SSH client.
+//! Supports password, key-based, and agent authentication. The server's
+//! host key is checked against a known_hosts file before anything is sent.
+//!
+//! One SSH connection is shared by all operations and re-established on
+//! the next call after it drops.
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    metadata::Permissions,
+    operations::*,
     CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
     VirtualPath,
 };
+use futures::StreamExt;
+use russh::client;
+use russh::keys::{self, PrivateKeyWithHashAlg};
+use russh_sftp::client::{error::Error as SftpError, fs::File, SftpSession};
+use russh_sftp::protocol::{FileAttributes as SftpAttributes, OpenFlags, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io::SeekFrom;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::common;
+
+/// Give up on a server that has not finished the handshake by then
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bytes requested per read of a streamed download
+const READ_CHUNK: usize = 256 * 1024;
 
 /// SFTP authentication method
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SftpAuth {
     /// Password authentication
     Password { username: String, password: String },
@@ -20,55 +49,112 @@ pub enum SftpAuth {
     PrivateKey {
         username: String,
         private_key_path: PathBuf,
+        #[serde(default)]
         passphrase: Option<String>,
     },
     /// SSH agent authentication
     Agent { username: String },
 }
 
+impl SftpAuth {
+    pub fn username(&self) -> &str {
+        match self {
+            SftpAuth::Password { username, .. }
+            | SftpAuth::PrivateKey { username, .. }
+            | SftpAuth::Agent { username } => username,
+        }
+    }
+}
+
 /// SFTP backend configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SftpConfig {
     /// Host address
     pub host: String,
     /// Port (default: 22)
+    #[serde(default = "default_port")]
     pub port: u16,
     /// Authentication method
+    #[serde(default = "default_auth")]
     pub auth: SftpAuth,
-    /// Known hosts file path
+    /// Known hosts file path (default: ~/.ssh/known_hosts)
+    #[serde(default)]
     pub known_hosts: Option<PathBuf>,
     /// Skip host key verification (insecure!)
+    #[serde(default)]
     pub skip_host_key_check: bool,
     /// Remote base path
+    #[serde(default = "default_base_path")]
     pub base_path: String,
 }
 
+fn default_port() -> u16 {
+    22
+}
+
+fn default_auth() -> SftpAuth {
+    SftpAuth::Agent {
+        username: whoami::username(),
+    }
+}
+
+fn default_base_path() -> String {
+    "/".to_string()
+}
+
 impl Default for SftpConfig {
     fn default() -> Self {
         Self {
             host: "localhost".to_string(),
-            port: 22,
-            auth: SftpAuth::Agent {
-                username: whoami::username(),
-            },
+            port: default_port(),
+            auth: default_auth(),
             known_hosts: None,
             skip_host_key_check: false,
-            base_path: "/".to_string(),
+            base_path: default_base_path(),
+        }
+    }
+}
+
+/// An authenticated SSH connection with its SFTP channel
+struct Connection {
+    ssh: client::Handle<HostKeyCheck>,
+    sftp: SftpSession,
+}
+
+/// Accepts the server only if its key is recorded in known_hosts
+struct HostKeyCheck {
+    host: String,
+    port: u16,
+    known_hosts: Option<PathBuf>,
+    skip: bool,
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, key: &keys::PublicKey) -> Result<bool, Self::Error> {
+        if self.skip {
+            return Ok(true);
+        }
+        let known = match &self.known_hosts {
+            Some(path) => keys::check_known_hosts_path(&self.host, self.port, key, path),
+            None => keys::check_known_hosts(&self.host, self.port, key),
+        };
+        match known {
+            Ok(known) => Ok(known),
+            Err(keys::Error::KeyChanged { line }) => Err(russh::Error::KeyChanged { line }),
+            Err(e) => Err(e.into()),
         }
     }
 }
 
 /// SFTP storage backend
-///
-/// Note: This is a stub implementation. Full implementation would use
-/// the `ssh2` or `russh` crate for SSH/SFTP protocol support.
 pub struct SftpBackend {
     id: String,
     config: SftpConfig,
     capabilities: StorageCapabilities,
-    // In a full implementation:
-    // session: Option<ssh2::Session>,
-    // sftp: Option<ssh2::Sftp>,
+    connection: Mutex<Option<Arc<Connection>>>,
 }
 
 impl SftpBackend {
@@ -86,13 +172,14 @@ pub fn new(id: impl Into<String>, config: SftpConfig) -> Self {
                 search: false,
                 versioning: false,
                 sharing: false,
+                offline: false,
                 streaming: true,
-                resume: true, // With SEEK
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false,
                 watch: false,
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
             },
+            connection: Mutex::new(None),
         }
     }
 
@@ -148,24 +235,337 @@ pub fn from_url(id: impl Into<String>, url: &str) -> CfkResult<Self> {
     fn to_remote_path(&self, path: &VirtualPath) -> String {
         let base = self.config.base_path.trim_end_matches('/');
         if path.segments.is_empty() {
-            base.to_string()
+            if base.is_empty() {
+                "/".to_string()
+            } else {
+                base.to_string()
+            }
         } else {
             format!("{}/{}", base, path.segments.join("/"))
         }
     }
 
-    /// Connect to SFTP server
+    /// Connect to the SFTP server, unless already connected
     pub async fn connect(&self) -> CfkResult<()> {
-        // In a full implementation, this would:
-        // 1. Create TCP connection
-        // 2. Perform SSH handshake
-        // 3. Authenticate
-        // 4. Initialize SFTP subsystem
+        self.session().await.map(|_| ())
+    }
+
+    /// The live connection, opening a new one if there is none
+    async fn session(&self) -> CfkResult<Arc<Connection>> {
+        let mut connection = self.connection.lock().await;
+        if let Some(conn) = connection.as_ref() {
+            if !conn.ssh.is_closed() {
+                return Ok(conn.clone());
+            }
+            tracing::debug!(
+                "sftp connection to {} dropped, reconnecting",
+                self.config.host
+            );
+        }
+
+        let conn = Arc::new(
+            tokio::time::timeout(CONNECT_TIMEOUT, self.open_connection())
+                .await
+                .map_err(|_| CfkError::Timeout)??,
+        );
+        *connection = Some(conn.clone());
+        Ok(conn)
+    }
+
+    /// Handshake, authenticate and start the sftp subsystem
+    async fn open_connection(&self) -> CfkResult<Connection> {
+        let config = Arc::new(client::Config {
+            keepalive_interval: Some(Duration::from_secs(30)),
+            ..Default::default()
+        });
+        let handler = HostKeyCheck {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            known_hosts: self.config.known_hosts.clone(),
+            skip: self.config.skip_host_key_check,
+        };
+
+        let mut ssh = client::connect(
+            config,
+            (self.config.host.as_str(), self.config.port),
+            handler,
+        )
+        .await
+        .map_err(|e| self.ssh_error(e))?;
+
+        if !self.authenticate(&mut ssh).await? {
+            return Err(CfkError::AuthFailed(format!(
+                "{}@{} rejected {} authentication",
+                self.config.auth.username(),
+                self.config.host,
+                match self.config.auth {
+                    SftpAuth::Password { .. } => "password",
+                    SftpAuth::PrivateKey { .. } => "key",
+                    SftpAuth::Agent { .. } => "agent",
+                }
+            )));
+        }
+
+        let channel = ssh
+            .channel_open_session()
+            .await
+            .map_err(|e| self.ssh_error(e))?;
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(|e| self.ssh_error(e))?;
+        let sftp = SftpSession::new(channel.into_stream())
+            .await
+            .map_err(|e| sftp_error(e, "sftp subsystem"))?;
+
+        Ok(Connection { ssh, sftp })
+    }
+
+    /// Whether the server accepted the configured credentials
+    async fn authenticate(&self, ssh: &mut client::Handle<HostKeyCheck>) -> CfkResult<bool> {
+        let result = match &self.config.auth {
+            SftpAuth::Password { username, password } => ssh
+                .authenticate_password(username, password)
+                .await
+                .map_err(|e| self.ssh_error(e))?,
+            SftpAuth::PrivateKey {
+                username,
+                private_key_path,
+                passphrase,
+            } => {
+                let key = keys::load_secret_key(private_key_path, passphrase.as_deref()).map_err(
+                    |e| {
+                        CfkError::Config(format!(
+                            "private key {}: {}",
+                            private_key_path.display(),
+                            e
+                        ))
+                    },
+                )?;
+                let hash_alg = self.rsa_hash(ssh, key.algorithm().is_rsa()).await?;
+                ssh.authenticate_publickey(
+                    username,
+                    PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg),
+                )
+                .await
+                .map_err(|e| self.ssh_error(e))?
+            }
+            SftpAuth::Agent { username } => return self.authenticate_agent(ssh, username).await,
+        };
+        Ok(result.success())
+    }
+
+    /// Try every identity the agent holds until one is accepted
+    #[cfg(unix)]
+    async fn authenticate_agent(
+        &self,
+        ssh: &mut client::Handle<HostKeyCheck>,
+        username: &str,
+    ) -> CfkResult<bool> {
+        let mut agent = keys::agent::client::AgentClient::connect_env()
+            .await
+            .map_err(|e| CfkError::AuthRequired(format!("ssh agent: {}", e)))?;
+        let identities = agent
+            .request_identities()
+            .await
+            .map_err(|e| CfkError::AuthRequired(format!("ssh agent: {}", e)))?;
+
+        for key in identities {
+            let hash_alg = self.rsa_hash(ssh, key.algorithm().is_rsa()).await?;
+            let result = ssh
+                .authenticate_publickey_with(username, key, hash_alg, &mut agent)
+                .await
+                .map_err(|e| CfkError::AuthFailed(format!("ssh agent: {}", e)))?;
+            if result.success() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
+    #[cfg(not(unix))]
+    async fn authenticate_agent(
+        &self,
+        _ssh: &mut client::Handle<HostKeyCheck>,
+        _username: &str,
+    ) -> CfkResult<bool> {
         Err(CfkError::Unsupported(
-            "SFTP backend requires ssh2 or russh crate. Stub implementation.".into(),
+            "ssh agent authentication is only available on Unix".into(),
         ))
     }
+
+    /// The signature hash the server prefers for RSA keys
+    async fn rsa_hash(
+        &self,
+        ssh: &client::Handle<HostKeyCheck>,
+        is_rsa: bool,
+    ) -> CfkResult<Option<keys::HashAlg>> {
+        if !is_rsa {
+            return Ok(None);
+        }
+        Ok(ssh
+            .best_supported_rsa_hash()
+            .await
+            .map_err(|e| self.ssh_error(e))?
+            .flatten())
+    }
+
+    /// Convert an SSH transport error
+    fn ssh_error(&self, e: russh::Error) -> CfkError {
+        let known_hosts = || {
+            self.config
+                .known_hosts
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "~/.ssh/known_hosts".to_string())
+        };
+        match e {
+            russh::Error::UnknownKey => CfkError::AuthFailed(format!(
+                "host key of {} is not in {}",
+                self.config.host,
+                known_hosts()
+            )),
+            russh::Error::KeyChanged { line } => CfkError::AuthFailed(format!(
+                "host key of {} does not match {} line {}",
+                self.config.host,
+                known_hosts(),
+                line
+            )),
+            russh::Error::ConnectionTimeout
+            | russh::Error::KeepaliveTimeout
+            | russh::Error::InactivityTimeout
+            | russh::Error::Elapsed(_) => CfkError::Timeout,
+            russh::Error::IO(e) => CfkError::Network(e.to_string()),
+            e @ (russh::Error::Disconnect | russh::Error::HUP | russh::Error::SendError) => {
+                CfkError::Network(e.to_string())
+            }
+            e => CfkError::ProviderApi {
+                provider: "sftp".into(),
+                message: e.to_string(),
+            },
+        }
+    }
+
+    /// Stat `path`, following symlinks
+    async fn stat(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<Entry> {
+        let attrs = conn
+            .sftp
+            .metadata(self.to_remote_path(path))
+            .await
+            .map_err(|e| sftp_error(e, path))?;
+        Ok(to_entry(path.clone(), &attrs))
+    }
+
+    /// Whether `path` exists, for telling apart the bare failures servers
+    /// answer to an existing target
+    async fn exists(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<bool> {
+        match self.stat(conn, path).await {
+            Ok(_) => Ok(true),
+            Err(CfkError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Map an error creating `path`, which may have failed because it exists
+    async fn create_error(&self, conn: &Connection, e: SftpError, path: &VirtualPath) -> CfkError {
+        if is_failure(&e) && matches!(self.exists(conn, path).await, Ok(true)) {
+            return CfkError::AlreadyExists(path.to_string());
+        }
+        sftp_error(e, path)
+    }
+
+    /// Create every missing ancestor of `path`
+    async fn ensure_parents(&self, conn: &Connection, path: &VirtualPath) -> CfkResult<()> {
+        let mut missing = Vec::new();
+        let mut current = path.parent();
+
+        while let Some(dir) = current {
+            if dir.is_root() || self.exists(conn, &dir).await? {
+                break;
+            }
+            current = dir.parent();
+            missing.push(dir);
+        }
+
+        for dir in missing.into_iter().rev() {
+            if let Err(e) = conn.sftp.create_dir(self.to_remote_path(&dir)).await {
+                match self.create_error(conn, e, &dir).await {
+                    CfkError::AlreadyExists(_) => {}
+                    e => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stream `stream` into `path`, honouring `overwrite` and `create_parents`
+    async fn put(
+        &self,
+        path: &VirtualPath,
+        mut stream: ByteStream,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        if options.create_parents {
+            self.ensure_parents(&conn, path).await?;
+        }
+
+        let mut flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        if !options.overwrite {
+            flags |= OpenFlags::EXCLUDE;
+        }
+        let mut file = match conn
+            .sftp
+            .open_with_flags(self.to_remote_path(path), flags)
+            .await
+        {
+            Ok(file) => file,
+            Err(e) => return Err(self.create_error(&conn, e, path).await),
+        };
+
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk?).await.map_err(io_error)?;
+        }
+        // Waits for outstanding writes and closes the handle
+        file.shutdown().await.map_err(io_error)?;
+
+        self.stat(&conn, path).await
+    }
+
+    /// Delete a directory and everything below it
+    async fn remove_tree(&self, conn: &Connection, root: &str) -> CfkResult<()> {
+        let mut dirs = vec![root.to_string()];
+        let mut next = 0;
+
+        while next < dirs.len() {
+            let dir = dirs[next].clone();
+            let children = conn
+                .sftp
+                .read_dir(dir.as_str())
+                .await
+                .map_err(|e| sftp_error(e, &dir))?;
+            for child in children {
+                let child_path = child.path();
+                if FileAttributes::from(&child.metadata()).is_dir() {
+                    dirs.push(child_path);
+                } else {
+                    conn.sftp
+                        .remove_file(child_path.as_str())
+                        .await
+                        .map_err(|e| sftp_error(e, &child_path))?;
+                }
+            }
+            next += 1;
+        }
+
+        for dir in dirs.iter().rev() {
+            conn.sftp
+                .remove_dir(dir.as_str())
+                .await
+                .map_err(|e| sftp_error(e, dir))?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -183,90 +583,311 @@ fn capabilities(&self) -> &StorageCapabilities {
     }
 
     async fn is_available(&self) -> bool {
-        // Would check SSH connection
-        false
+        self.session().await.is_ok()
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let _remote_path = self.to_remote_path(path);
+        let conn = self.session().await?;
+        self.stat(&conn, path).await
+    }
 
-        // Would use SFTP stat() call
-        // let attrs = sftp.stat(&remote_path)?;
+    /// SFTP reads a directory in one go, so the cursor is an offset into
+    /// the full listing. Symlinks are listed as such, not followed.
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let conn = self.session().await?;
+        let mut entries = Vec::new();
+        let mut pending = vec![path.clone()];
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
-    }
+        while let Some(dir) = pending.pop() {
+            let read = conn
+                .sftp
+                .read_dir(self.to_remote_path(&dir))
+                .await
+                .map_err(|e| sftp_error(e, &dir))?;
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
-        let _remote_path = self.to_remote_path(path);
+            // Servers return entries in no particular order; sort so that
+            // offsets stay stable between pages
+            let mut children: Vec<Entry> = read
+                .map(|child| to_entry(dir.join(child.file_name()), &child.metadata()))
+                .collect();
+            children.sort_by(|a, b| a.path.segments.cmp(&b.path.segments));
 
-        // Would use SFTP readdir() call
-        // let entries = sftp.readdir(&remote_path)?;
+            if options.recursive {
+                pending.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .filter(|e| e.is_directory())
+                        .filter(|e| {
+                            options.include_hidden
+                                || !e.path.name().is_some_and(|n| n.starts_with('.'))
+                        })
+                        .map(|e| e.path.clone()),
+                );
+            }
+            entries.extend(children);
+        }
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+        Ok(common::page_entries(path, entries, options))
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
-        let _remote_path = self.to_remote_path(path);
-
-        // Would open file and read:
-        // let mut file = sftp.open(&remote_path)?;
-        // let mut data = Vec::new();
-        // file.read_to_end(&mut data)?;
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let conn = self.session().await?;
+        let mut file = conn
+            .sftp
+            .open(self.to_remote_path(path))
+            .await
+            .map_err(|e| sftp_error(e, path))?;
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+        let limit = match options.range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start)).await.map_err(io_error)?;
+                end.saturating_sub(start)
+            }
+            None => u64::MAX,
+        };
+        Ok(file_stream(conn, file, limit))
     }
 
-    async fn write_file(&self, path: &VirtualPath, _data: Bytes) -> CfkResult<Entry> {
-        let _remote_path = self.to_remote_path(path);
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        let stream: ByteStream = Box::pin(futures::stream::once(async { Ok(data) }));
+        self.put(path, stream, options).await
+    }
 
-        // Would create/open file and write:
-        // let mut file = sftp.create(&remote_path)?;
-        // file.write_all(&data)?;
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.put(path, stream, options).await
+    }
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        if let Err(e) = conn.sftp.create_dir(self.to_remote_path(path)).await {
+            return Err(self.create_error(&conn, e, path).await);
+        }
+        self.stat(&conn, path).await
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
-        let _remote_path = self.to_remote_path(path);
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if path.is_root() {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        }
+
+        let conn = self.session().await?;
+        let remote = self.to_remote_path(path);
+        let result = async {
+            // lstat, so a link to a directory is removed rather than followed
+            let attrs = conn
+                .sftp
+                .symlink_metadata(remote.as_str())
+                .await
+                .map_err(|e| sftp_error(e, path))?;
 
-        // Would use SFTP unlink() or rmdir():
-        // sftp.unlink(&remote_path)?;
+            if !FileAttributes::from(&attrs).is_dir() {
+                return conn
+                    .sftp
+                    .remove_file(remote.as_str())
+                    .await
+                    .map_err(|e| sftp_error(e, path));
+            }
+            if options.recursive {
+                return self.remove_tree(&conn, &remote).await;
+            }
+            conn.sftp
+                .remove_dir(remote.as_str())
+                .await
+                .map_err(|e| match e {
+                    // The only reason left for refusing to remove a directory
+                    e if is_failure(&e) => CfkError::DirectoryNotEmpty(path.to_string()),
+                    e => sftp_error(e, path),
+                })
+        }
+        .await;
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+        common::ignore_missing(result, options)
     }
 
-    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
-        let _remote_path = self.to_remote_path(path);
+    /// There is no server-side copy in SFTP, so the data passes through
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        let entry = self.get_metadata(source).await?;
+        if entry.is_directory() {
+            return Err(CfkError::NotAFile(source.to_string()));
+        }
 
-        // Would use SFTP mkdir():
-        // sftp.mkdir(&remote_path, 0o755)?;
+        let stream = self.read_file(source, &ReadOptions::default()).await?;
+        let write = WriteOptions {
+            overwrite: options.overwrite,
+            ..Default::default()
+        };
+        let copied = self.put(dest, stream, &write).await?;
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+        if options.preserve_metadata {
+            self.set_metadata(dest, &entry.metadata).await?;
+            return self.get_metadata(dest).await;
+        }
+        Ok(copied)
     }
 
-    async fn copy(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        // SFTP doesn't support server-side copy
-        // Would need to read + write
-        Err(CfkError::Unsupported(
-            "SFTP doesn't support native copy".into(),
-        ))
+    /// SFTP v3 rename refuses an existing target, so with `overwrite` the
+    /// target is removed first
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        let conn = self.session().await?;
+        self.stat(&conn, source).await?;
+        common::prepare_destination(self, dest, options.overwrite).await?;
+
+        conn.sftp
+            .rename(self.to_remote_path(source), self.to_remote_path(dest))
+            .await
+            .map_err(|e| sftp_error(e, source))?;
+        self.stat(&conn, dest).await
     }
 
-    async fn rename(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<Entry> {
-        let _from_path = self.to_remote_path(from);
-        let _to_path = self.to_remote_path(to);
+    /// Needs the `statvfs@openssh.com` extension; other servers report
+    /// unknown space
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        let conn = self.session().await?;
+        let root = self.to_remote_path(&VirtualPath::root(&self.id));
+        let stat = conn
+            .sftp
+            .fs_info(root.as_str())
+            .await
+            .map_err(|e| sftp_error(e, &root))?;
 
-        // Would use SFTP rename():
-        // sftp.rename(&from_path, &to_path, None)?;
+        Ok(match stat {
+            Some(stat) => {
+                let block = stat.fragment_size;
+                SpaceInfo {
+                    total: Some(stat.blocks * block),
+                    used: Some(stat.blocks.saturating_sub(stat.blocks_free) * block),
+                    available: Some(stat.blocks_avail * block),
+                }
+            }
+            None => SpaceInfo::unknown(),
+        })
+    }
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+    /// Sets permissions and times; SFTP sets access and modification time
+    /// together, so a missing access time is taken to be the modification
+    /// time
+    async fn set_metadata(&self, path: &VirtualPath, metadata: &Metadata) -> CfkResult<()> {
+        let mut attrs = SftpAttributes::empty();
+        attrs.permissions = metadata.permissions.map(|p| p.mode & 0o7777);
+        if let Some(modified) = metadata.modified {
+            let accessed = metadata.accessed.unwrap_or(modified);
+            attrs.mtime = Some(modified.timestamp().clamp(0, u32::MAX as i64) as u32);
+            attrs.atime = Some(accessed.timestamp().clamp(0, u32::MAX as i64) as u32);
+        }
+        if attrs.permissions.is_none() && attrs.mtime.is_none() {
+            return Ok(());
+        }
+
+        let conn = self.session().await?;
+        conn.sftp
+            .set_metadata(self.to_remote_path(path), attrs)
+            .await
+            .map_err(|e| sftp_error(e, path))
     }
+}
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
-        // SFTP has statvfs extension (OpenSSH)
-        // Would use sftp.statvfs()
+/// Stream up to `limit` bytes of an open file, holding on to the connection
+fn file_stream(conn: Arc<Connection>, file: File, limit: u64) -> ByteStream {
+    Box::pin(futures::stream::try_unfold(
+        (conn, file, limit),
+        |(conn, mut file, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let mut buf = vec![0; (READ_CHUNK as u64).min(remaining) as usize];
+            let read = file.read(&mut buf).await.map_err(io_error)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            buf.truncate(read);
+            Ok(Some((
+                Bytes::from(buf),
+                (conn, file, remaining - read as u64),
+            )))
+        },
+    ))
+}
 
-        Err(CfkError::Unsupported("SFTP stub - use ssh2 crate".into()))
+fn to_entry(path: VirtualPath, attrs: &SftpAttributes) -> Entry {
+    let attributes = FileAttributes::from(attrs);
+    let kind = if attributes.is_dir() {
+        EntryKind::Directory
+    } else if attributes.is_symlink() {
+        EntryKind::Symlink
+    } else if attributes.is_file() {
+        EntryKind::File
+    } else {
+        EntryKind::Unknown
+    };
+    Entry {
+        path,
+        kind,
+        metadata: attributes.to_metadata(),
+    }
+}
+
+/// SSH_FX_FAILURE, the status servers send when nothing more specific fits
+fn is_failure(e: &SftpError) -> bool {
+    matches!(e, SftpError::Status(status) if status.status_code == StatusCode::Failure)
+}
+
+/// Convert an SFTP error for an operation on `path`
+fn sftp_error(e: SftpError, path: impl Display) -> CfkError {
+    match e {
+        SftpError::Status(status) => match status.status_code {
+            StatusCode::NoSuchFile => CfkError::NotFound(path.to_string()),
+            StatusCode::PermissionDenied => CfkError::PermissionDenied(path.to_string()),
+            StatusCode::NoConnection | StatusCode::ConnectionLost => {
+                CfkError::Network(status.error_message)
+            }
+            StatusCode::OpUnsupported => {
+                CfkError::Unsupported(format!("sftp server: {}", status.error_message))
+            }
+            _ => CfkError::ProviderApi {
+                provider: "sftp".into(),
+                message: format!("{}: {}", path, status.error_message),
+            },
+        },
+        SftpError::Timeout => CfkError::Timeout,
+        SftpError::IO(message) => CfkError::Network(message),
+        e => CfkError::ProviderApi {
+            provider: "sftp".into(),
+            message: format!("{}: {}", path, e),
+        },
+    }
+}
+
+/// Convert an error from reading or writing an open file
+fn io_error(e: std::io::Error) -> CfkError {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => CfkError::Timeout,
+        _ => CfkError::Network(e.to_string()),
     }
 }
 
@@ -279,7 +900,13 @@ pub fn username() -> String {
     }
 }
 
-/// SFTP file attributes (mirrors ssh2::FileStat)
+/// File type bits of a Unix mode
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// SFTP file attributes
 #[derive(Debug, Clone, Default)]
 pub struct FileAttributes {
     pub size: Option<u64>,
@@ -290,36 +917,732 @@ pub struct FileAttributes {
     pub mtime: Option<u64>,
 }
 
+impl From<&SftpAttributes> for FileAttributes {
+    fn from(attrs: &SftpAttributes) -> Self {
+        Self {
+            size: attrs.size,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            permissions: attrs.permissions,
+            atime: attrs.atime.map(u64::from),
+            mtime: attrs.mtime.map(u64::from),
+        }
+    }
+}
+
 impl FileAttributes {
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|p| p & S_IFMT)
+    }
+
     pub fn is_dir(&self) -> bool {
-        self.permissions
-            .map(|p| (p & 0o40000) != 0)
-            .unwrap_or(false)
+        self.file_type() == Some(S_IFDIR)
     }
 
     pub fn is_symlink(&self) -> bool {
-        self.permissions
-            .map(|p| (p & 0o120000) == 0o120000)
-            .unwrap_or(false)
+        self.file_type() == Some(S_IFLNK)
     }
 
     pub fn is_file(&self) -> bool {
-        self.permissions
-            .map(|p| (p & 0o100000) != 0)
-            .unwrap_or(false)
+        self.file_type() == Some(S_IFREG)
     }
 
+    /// Owner and group go into `custom` as `uid` and `gid`
     pub fn to_metadata(&self) -> Metadata {
-        let mut meta = Metadata::default();
-        meta.size = self.size;
-        meta.permissions = self.permissions;
-        meta.uid = self.uid;
-        meta.gid = self.gid;
+        let timestamp = |secs: u64| chrono::DateTime::from_timestamp(secs as i64, 0);
 
-        if let Some(mtime) = self.mtime {
-            meta.modified = chrono::DateTime::from_timestamp(mtime as i64, 0);
+        let mut meta = Metadata {
+            size: self.size,
+            permissions: self.permissions.map(Permissions::new),
+            modified: self.mtime.and_then(timestamp),
+            accessed: self.atime.and_then(timestamp),
+            ..Default::default()
+        };
+        if let Some(uid) = self.uid {
+            meta.custom.insert("uid".to_string(), uid.to_string());
+        }
+        if let Some(gid) = self.gid {
+            meta.custom.insert("gid".to_string(), gid.to_string());
         }
-
         meta
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::ssh_key::{private::Ed25519Keypair, LineEnding};
+    use russh::server::{Auth, Msg, Session};
+    use russh::{Channel, ChannelId};
+    use russh_sftp::protocol::{Attrs, Data, File as DirEntry, Handle, Name, Status};
+    use std::collections::HashMap;
+    use std::io::{Read, Seek, Write};
+    use std::path::Path;
+
+    /// SFTP subsystem serving a local directory, with SFTP v3 rename rules.
+    /// Reads return at most 1000 bytes and directories come two entries per
+    /// reply, the way real servers split large answers.
+    struct DirServer {
+        root: PathBuf,
+        handles: HashMap<String, Open>,
+        next: u32,
+    }
+
+    enum Open {
+        File(std::fs::File),
+        Dir(Vec<DirEntry>),
+    }
+
+    impl DirServer {
+        fn real(&self, path: &str) -> PathBuf {
+            self.root.join(path.trim_start_matches('/'))
+        }
+
+        fn insert(&mut self, open: Open) -> String {
+            self.next += 1;
+            self.handles.insert(self.next.to_string(), open);
+            self.next.to_string()
+        }
+
+        fn file(&mut self, handle: &str) -> Result<&mut std::fs::File, StatusCode> {
+            match self.handles.get_mut(handle) {
+                Some(Open::File(file)) => Ok(file),
+                _ => Err(StatusCode::Failure),
+            }
+        }
+    }
+
+    fn status(e: std::io::Error) -> StatusCode {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+            std::io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+            _ => StatusCode::Failure,
+        }
+    }
+
+    fn ok(id: u32) -> Status {
+        Status {
+            id,
+            status_code: StatusCode::Ok,
+            error_message: "Ok".into(),
+            language_tag: "en-US".into(),
+        }
+    }
+
+    impl russh_sftp::server::Handler for DirServer {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> StatusCode {
+            StatusCode::OpUnsupported
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            filename: String,
+            pflags: OpenFlags,
+            _attrs: SftpAttributes,
+        ) -> Result<Handle, StatusCode> {
+            let file = std::fs::OpenOptions::from(pflags)
+                .open(self.real(&filename))
+                .map_err(status)?;
+            let handle = self.insert(Open::File(file));
+            Ok(Handle { id, handle })
+        }
+
+        async fn close(&mut self, id: u32, handle: String) -> Result<Status, StatusCode> {
+            self.handles.remove(&handle);
+            Ok(ok(id))
+        }
+
+        async fn read(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            len: u32,
+        ) -> Result<Data, StatusCode> {
+            let file = self.file(&handle)?;
+            file.seek(SeekFrom::Start(offset)).map_err(status)?;
+            let mut data = vec![0; len.min(1000) as usize];
+            let read = file.read(&mut data).map_err(status)?;
+            if read == 0 {
+                return Err(StatusCode::Eof);
+            }
+            data.truncate(read);
+            Ok(Data { id, data })
+        }
+
+        async fn write(
+            &mut self,
+            id: u32,
+            handle: String,
+            offset: u64,
+            data: Vec<u8>,
+        ) -> Result<Status, StatusCode> {
+            let file = self.file(&handle)?;
+            file.seek(SeekFrom::Start(offset)).map_err(status)?;
+            file.write_all(&data).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+            let meta = std::fs::metadata(self.real(&path)).map_err(status)?;
+            Ok(Attrs {
+                id,
+                attrs: SftpAttributes::from(&meta),
+            })
+        }
+
+        async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, StatusCode> {
+            let meta = std::fs::symlink_metadata(self.real(&path)).map_err(status)?;
+            Ok(Attrs {
+                id,
+                attrs: SftpAttributes::from(&meta),
+            })
+        }
+
+        async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, StatusCode> {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(self.real(&path)).map_err(status)? {
+                let entry = entry.map_err(status)?;
+                let meta = entry.metadata().map_err(status)?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                files.push(DirEntry::new(name, SftpAttributes::from(&meta)));
+            }
+            let handle = self.insert(Open::Dir(files));
+            Ok(Handle { id, handle })
+        }
+
+        async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, StatusCode> {
+            match self.handles.get_mut(&handle) {
+                Some(Open::Dir(files)) if files.is_empty() => Err(StatusCode::Eof),
+                Some(Open::Dir(files)) => {
+                    let files = files.drain(..files.len().min(2)).collect();
+                    Ok(Name { id, files })
+                }
+                _ => Err(StatusCode::Failure),
+            }
+        }
+
+        async fn remove(&mut self, id: u32, filename: String) -> Result<Status, StatusCode> {
+            std::fs::remove_file(self.real(&filename)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn mkdir(
+            &mut self,
+            id: u32,
+            path: String,
+            _attrs: SftpAttributes,
+        ) -> Result<Status, StatusCode> {
+            std::fs::create_dir(self.real(&path)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, StatusCode> {
+            std::fs::remove_dir(self.real(&path)).map_err(status)?;
+            Ok(ok(id))
+        }
+
+        async fn rename(
+            &mut self,
+            id: u32,
+            oldpath: String,
+            newpath: String,
+        ) -> Result<Status, StatusCode> {
+            if self.real(&newpath).exists() {
+                return Err(StatusCode::Failure);
+            }
+            std::fs::rename(self.real(&oldpath), self.real(&newpath)).map_err(status)?;
+            Ok(ok(id))
+        }
+    }
+
+    /// SSH server accepting alice by password "secret" or by `client_key`
+    struct SshServer {
+        root: PathBuf,
+        client_key: keys::PublicKey,
+        channels: HashMap<ChannelId, Channel<Msg>>,
+    }
+
+    impl russh::server::Handler for SshServer {
+        type Error = russh::Error;
+
+        async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+            Ok(match (user, password) {
+                ("alice", "secret") => Auth::Accept,
+                _ => Auth::reject(),
+            })
+        }
+
+        async fn auth_publickey(
+            &mut self,
+            user: &str,
+            key: &keys::PublicKey,
+        ) -> Result<Auth, Self::Error> {
+            Ok(if user == "alice" && *key == self.client_key {
+                Auth::Accept
+            } else {
+                Auth::reject()
+            })
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<Msg>,
+            _session: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            self.channels.insert(channel.id(), channel);
+            Ok(true)
+        }
+
+        async fn subsystem_request(
+            &mut self,
+            channel: ChannelId,
+            name: &str,
+            session: &mut Session,
+        ) -> Result<(), Self::Error> {
+            match self.channels.remove(&channel) {
+                Some(stream) if name == "sftp" => {
+                    session.channel_success(channel)?;
+                    let server = DirServer {
+                        root: self.root.clone(),
+                        handles: HashMap::new(),
+                        next: 0,
+                    };
+                    russh_sftp::server::run(stream.into_stream(), server).await;
+                }
+                _ => session.channel_failure(channel)?,
+            }
+            Ok(())
+        }
+    }
+
+    fn key(seed: u8) -> keys::PrivateKey {
+        keys::PrivateKey::from(Ed25519Keypair::from_seed(&[seed; 32]))
+    }
+
+    /// A server on a loopback port serving `root/data`, with a known_hosts
+    /// file and a client key file in `root`
+    struct Fixture {
+        port: u16,
+        data: PathBuf,
+        known_hosts: PathBuf,
+        client_key: PathBuf,
+    }
+
+    async fn serve(root: &Path) -> Fixture {
+        let host_key = key(1);
+        let client_key = key(2);
+        let data = root.join("data");
+        std::fs::create_dir(&data).unwrap();
+
+        let known_hosts = root.join("known_hosts");
+        let client_key_path = root.join("id_ed25519");
+        let config = Arc::new(russh::server::Config {
+            keys: vec![host_key.clone()],
+            auth_rejection_time: Duration::ZERO,
+            auth_rejection_time_initial: Some(Duration::ZERO),
+            ..Default::default()
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::fs::write(
+            &known_hosts,
+            format!(
+                "[127.0.0.1]:{} {}\n",
+                port,
+                host_key.public_key().to_openssh().unwrap()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            &client_key_path,
+            client_key.to_openssh(LineEnding::LF).unwrap().as_bytes(),
+        )
+        .unwrap();
+
+        let served = data.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let handler = SshServer {
+                    root: served.clone(),
+                    client_key: client_key.public_key().clone(),
+                    channels: HashMap::new(),
+                };
+                let config = config.clone();
+                tokio::spawn(async move {
+                    if let Ok(session) = russh::server::run_stream(config, socket, handler).await {
+                        let _ = session.await;
+                    }
+                });
+            }
+        });
+
+        Fixture {
+            port,
+            data,
+            known_hosts,
+            client_key: client_key_path,
+        }
+    }
+
+    impl Fixture {
+        fn backend(&self, auth: SftpAuth) -> SftpBackend {
+            SftpBackend::new(
+                "sftp",
+                SftpConfig {
+                    host: "127.0.0.1".into(),
+                    port: self.port,
+                    auth,
+                    known_hosts: Some(self.known_hosts.clone()),
+                    skip_host_key_check: false,
+                    base_path: "/".into(),
+                },
+            )
+        }
+    }
+
+    fn password(password: &str) -> SftpAuth {
+        SftpAuth::Password {
+            username: "alice".into(),
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn test_file_attributes_type_bits_and_metadata() {
+        let attrs = |mode| FileAttributes {
+            permissions: Some(mode),
+            ..Default::default()
+        };
+        assert!(attrs(0o040755).is_dir());
+        assert!(attrs(0o100644).is_file());
+        let link = attrs(0o120777);
+        assert!(link.is_symlink() && !link.is_file() && !link.is_dir());
+        let socket = attrs(0o140755);
+        assert!(!socket.is_dir() && !socket.is_file());
+
+        let meta = FileAttributes {
+            size: Some(42),
+            uid: Some(1000),
+            gid: Some(100),
+            permissions: Some(0o100640),
+            atime: Some(1_700_000_100),
+            mtime: Some(1_700_000_000),
+        }
+        .to_metadata();
+        assert_eq!(meta.size, Some(42));
+        assert_eq!(meta.permissions.map(|p| p.mode), Some(0o100640));
+        assert_eq!(meta.modified.map(|t| t.timestamp()), Some(1_700_000_000));
+        assert_eq!(meta.accessed.map(|t| t.timestamp()), Some(1_700_000_100));
+        assert_eq!(meta.custom.get("uid").map(String::as_str), Some("1000"));
+        assert_eq!(meta.custom.get("gid").map(String::as_str), Some("100"));
+    }
+
+    #[tokio::test]
+    async fn test_file_operations_over_password_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fixture = serve(tmp.path()).await;
+        let backend = fixture.backend(password("secret"));
+        let path = |p: &str| VirtualPath::new("sftp", p);
+
+        let create = WriteOptions {
+            create_parents: true,
+            ..Default::default()
+        };
+        let entry = backend
+            .write_file(
+                &path("docs/sub/a.txt"),
+                Bytes::from_static(b"abcdefgh"),
+                &create,
+            )
+            .await
+            .unwrap();
+        assert!(entry.is_file());
+        assert_eq!(entry.size(), Some(8));
+        assert_eq!(
+            std::fs::read(fixture.data.join("docs/sub/a.txt")).unwrap(),
+            b"abcdefgh"
+        );
+
+        let err = backend
+            .write_file(&path("docs/sub/a.txt"), Bytes::from_static(b"x"), &create)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)), "{err}");
+
+        let range = ReadOptions {
+            range: Some((2, 5)),
+            ..Default::default()
+        };
+        let stream = backend
+            .read_file(&path("docs/sub/a.txt"), &range)
+            .await
+            .unwrap();
+        assert_eq!(&common::collect_stream(stream).await.unwrap()[..], b"cde");
+
+        backend
+            .write_file(&path("docs/b.txt"), Bytes::from_static(b"b"), &create)
+            .await
+            .unwrap();
+        let recursive = ListOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let listing = backend
+            .list_directory(&path("docs"), &recursive)
+            .await
+            .unwrap();
+        let names: Vec<String> = listing
+            .entries
+            .iter()
+            .map(|e| e.path.to_path_string())
+            .collect();
+        assert_eq!(names, ["/docs/b.txt", "/docs/sub", "/docs/sub/a.txt"]);
+
+        // Rename refuses an existing target unless asked to overwrite
+        let err = backend
+            .rename(
+                &path("docs/b.txt"),
+                &path("docs/sub/a.txt"),
+                &MoveOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)), "{err}");
+        let overwrite = MoveOptions { overwrite: true };
+        backend
+            .rename(&path("docs/b.txt"), &path("docs/sub/a.txt"), &overwrite)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(fixture.data.join("docs/sub/a.txt")).unwrap(),
+            b"b"
+        );
+
+        backend
+            .copy(
+                &path("docs/sub/a.txt"),
+                &path("c.txt"),
+                &CopyOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(fixture.data.join("c.txt")).unwrap(), b"b");
+
+        let err = backend
+            .delete(&path("docs"), &DeleteOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::DirectoryNotEmpty(_)), "{err}");
+        let recursive = DeleteOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        backend.delete(&path("docs"), &recursive).await.unwrap();
+        assert!(!fixture.data.join("docs").exists());
+
+        let err = backend.get_metadata(&path("docs")).await.unwrap_err();
+        assert!(matches!(err, CfkError::NotFound(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_key_auth_and_host_key_verification() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fixture = serve(tmp.path()).await;
+
+        let config: SftpConfig = toml::from_str(&format!(
+            r#"
+            host = "127.0.0.1"
+            port = {}
+            known_hosts = "{}"
+
+            [auth.private_key]
+            username = "alice"
+            private_key_path = "{}"
+            "#,
+            fixture.port,
+            fixture.known_hosts.display(),
+            fixture.client_key.display()
+        ))
+        .unwrap();
+        let backend = SftpBackend::new("sftp", config);
+        let root = backend
+            .get_metadata(&VirtualPath::root("sftp"))
+            .await
+            .unwrap();
+        assert!(root.is_directory());
+
+        let err = fixture
+            .backend(password("wrong"))
+            .connect()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AuthFailed(_)), "{err}");
+
+        // A different key on record for the host
+        std::fs::write(
+            &fixture.known_hosts,
+            format!(
+                "[127.0.0.1]:{} {}\n",
+                fixture.port,
+                key(3).public_key().to_openssh().unwrap()
+            ),
+        )
+        .unwrap();
+        let err = fixture
+            .backend(password("secret"))
+            .connect()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+
+        // No key on record at all
+        std::fs::write(&fixture.known_hosts, "").unwrap();
+        let err = fixture
+            .backend(password("secret"))
+            .connect()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not in"), "{err}");
+    }
+
+    /// An SSH_FXP_STATUS packet, without its length, for request 7
+    fn status_packet(code: u8, message: &str) -> Bytes {
+        let mut packet = vec![
+            101, // SSH_FXP_STATUS
+            0, 0, 0, 7, // id
+            0, 0, 0, code,
+        ];
+        packet.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        packet.extend_from_slice(message.as_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 2, b'e', b'n']);
+        Bytes::from(packet)
+    }
+
+    fn decoded_error(mut packet: Bytes) -> SftpError {
+        match russh_sftp::protocol::Packet::try_from(&mut packet).unwrap() {
+            russh_sftp::protocol::Packet::Status(status) => SftpError::Status(status),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_status_mapping() {
+        let mapped = |code, message| sftp_error(decoded_error(status_packet(code, message)), "/x");
+        assert!(matches!(mapped(2, "No such file"), CfkError::NotFound(path) if path == "/x"));
+        assert!(matches!(
+            mapped(3, "Permission denied"),
+            CfkError::PermissionDenied(_)
+        ));
+        assert!(matches!(mapped(7, "Connection lost"), CfkError::Network(_)));
+        assert!(matches!(
+            mapped(8, "Operation unsupported"),
+            CfkError::Unsupported(_)
+        ));
+        match mapped(4, "Failure") {
+            CfkError::ProviderApi { message, .. } => assert_eq!(message, "/x: Failure"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(is_failure(&decoded_error(status_packet(4, "Failure"))));
+        assert!(!is_failure(&decoded_error(status_packet(
+            2,
+            "No such file"
+        ))));
+    }
+
+    #[test]
+    fn test_name_reply_entries() {
+        // A reply to SSH_FXP_READDIR with a directory and a file
+        let mut packet = Bytes::from_static(&[
+            104, // SSH_FXP_NAME
+            0, 0, 0, 7, // id
+            0, 0, 0, 2, // count
+            0, 0, 0, 3, b's', b'u', b'b', // filename
+            0, 0, 0, 0, // longname
+            0, 0, 0, 0x0c, // permissions and times
+            0, 0, 0x41, 0xed, // 040755
+            0x65, 0x53, 0xf1, 0x00, // atime
+            0x65, 0x53, 0xf1, 0x00, // mtime
+            0, 0, 0, 5, b'a', b'.', b't', b'x', b't', // filename
+            0, 0, 0, 0, // longname
+            0, 0, 0, 0x0f, // size, owner, permissions and times
+            0, 0, 0, 0, 0, 0, 0x30, 0x39, // size
+            0, 0, 0x03, 0xe8, // uid
+            0, 0, 0, 100, // gid
+            0, 0, 0x81, 0xa4, // 0100644
+            0x65, 0x53, 0xf1, 0x00, // atime
+            0x65, 0x53, 0xf1, 0x64, // mtime
+        ]);
+        let name = match russh_sftp::protocol::Packet::try_from(&mut packet).unwrap() {
+            russh_sftp::protocol::Packet::Name(name) => name,
+            other => panic!("unexpected {:?}", other),
+        };
+        let dir = VirtualPath::new("sftp", "/docs");
+        let entries: Vec<Entry> = name
+            .files
+            .iter()
+            .map(|file| to_entry(dir.join(&file.filename), &file.attrs))
+            .collect();
+
+        assert_eq!(entries[0].path, dir.join("sub"));
+        assert!(entries[0].is_directory());
+        assert_eq!(entries[1].kind, EntryKind::File);
+        let meta = &entries[1].metadata;
+        assert_eq!(meta.size, Some(12345));
+        assert_eq!(meta.modified.map(|t| t.timestamp()), Some(1_700_000_100));
+        assert_eq!(meta.custom.get("uid").map(String::as_str), Some("1000"));
+    }
+
+    #[tokio::test]
+    async fn test_short_reads_and_listing_in_batches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let fixture = serve(tmp.path()).await;
+        let backend = fixture.backend(password("secret"));
+
+        let many = fixture.data.join("many");
+        std::fs::create_dir(&many).unwrap();
+        for i in 0..5 {
+            std::fs::write(many.join(format!("{}.txt", i)), b"x").unwrap();
+        }
+        let large: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+        std::fs::write(many.join("large.bin"), &large).unwrap();
+
+        let path = VirtualPath::new("sftp", "/many");
+        let listing = backend
+            .list_directory(&path, &ListOptions::default())
+            .await
+            .unwrap();
+        let names: Vec<_> = listing
+            .entries
+            .iter()
+            .filter_map(|e| e.path.name())
+            .collect();
+        assert_eq!(
+            names,
+            ["0.txt", "1.txt", "2.txt", "3.txt", "4.txt", "large.bin"]
+        );
+
+        let stream = backend
+            .read_file(&path.join("large.bin"), &ReadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(common::collect_stream(stream).await.unwrap(), large);
+
+        let range = ReadOptions {
+            range: Some((1500, 2600)),
+            ..Default::default()
+        };
+        let stream = backend
+            .read_file(&path.join("large.bin"), &range)
+            .await
+            .unwrap();
+        assert_eq!(
+            common::collect_stream(stream).await.unwrap(),
+            &large[1500..2600]
+        );
+    }
+}
@@ -1,14 +1,21 @@
 //! Syncthing storage backend
 //!
 //! Connects to Syncthing's REST API to expose synced folders.
-//! Note: Syncthing folders are local, this backend provides folder discovery and sync status.
+//! Syncthing folders are plain directories on the daemon's host: when that
+//! directory is reachable from here, file operations go straight to disk and a
+//! rescan is requested afterwards. Otherwise the backend is limited to what the
+//! REST API offers (folder discovery, the database browse view and status).
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use cfk_core::{
-    CfkError, CfkResult, Entry, EntryKind, Metadata, StorageBackend, StorageCapabilities,
-    VirtualPath,
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    ChangeEvent, ChangeKind, ChangeStream, CfkError, CfkResult, Entry, Metadata, StorageBackend,
+    StorageCapabilities, VirtualPath,
 };
+use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -16,13 +23,30 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::common::{self, ChangeFeed};
+use crate::http;
+use crate::LocalBackend;
+
+/// Events a watch follows: edits made on the daemon's host, and remote
+/// changes once they have been written there
+const WATCH_EVENTS: &str = "LocalChangeDetected,ItemFinished";
+
+/// How long `/rest/events` may hold a request
+const EVENTS_TIMEOUT_SECS: &str = "60";
+
 /// Syncthing connection configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SyncthingConfig {
+    #[serde(default = "default_api_url")]
     pub api_url: String,
     pub api_key: String,
 }
 
+fn default_api_url() -> String {
+    "http://127.0.0.1:8384".to_string()
+}
+
 /// Syncthing folder information
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -49,6 +73,9 @@ pub struct FolderStatus {
 }
 
 /// Syncthing storage backend
+///
+/// Clones share configuration and the folder cache.
+#[derive(Clone)]
 pub struct SyncthingBackend {
     id: String,
     http: Client,
@@ -74,67 +101,36 @@ pub fn new(id: impl Into<String>, config: SyncthingConfig) -> Self {
                 search: false,
                 versioning: true,
                 sharing: false,
+                offline: true, // Folders are full local replicas
                 streaming: true,
-                resume: true,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false,
                 watch: true,
-                metadata: true,
-                thumbnails: false,
-                max_file_size: None,
             },
             folders: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    /// Make API GET request
-    async fn api_get<T: for<'de> Deserialize<'de>>(&self, endpoint: &str) -> CfkResult<T> {
+    /// Build an authenticated request against `/rest/{endpoint}`
+    async fn rest(&self, method: reqwest::Method, endpoint: &str) -> reqwest::RequestBuilder {
         let config = self.config.read().await;
-        let url = format!("{}/rest/{}", config.api_url, endpoint);
+        let url = format!("{}/rest/{}", config.api_url.trim_end_matches('/'), endpoint);
 
-        let response = self
-            .http
-            .get(&url)
+        self.http
+            .request(method, &url)
             .header("X-API-Key", &config.api_key)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(CfkError::ProviderApi {
-                provider: "syncthing".into(),
-                message: response.text().await.unwrap_or_default(),
-            });
-        }
-
-        response
-            .json()
-            .await
-            .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
-    /// Make API POST request
-    async fn api_post<T: for<'de> Deserialize<'de>>(
+    /// Make API GET request
+    async fn api_get<T: for<'de> Deserialize<'de>>(
         &self,
         endpoint: &str,
-        body: impl Serialize,
+        query: &[(&str, &str)],
     ) -> CfkResult<T> {
-        let config = self.config.read().await;
-        let url = format!("{}/rest/{}", config.api_url, endpoint);
-
-        let response = self
-            .http
-            .post(&url)
-            .header("X-API-Key", &config.api_key)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(CfkError::ProviderApi {
-                provider: "syncthing".into(),
-                message: response.text().await.unwrap_or_default(),
-            });
-        }
+        let request = self.rest(reqwest::Method::GET, endpoint).await.query(query);
+        let response = request.send().await.map_err(http::network_error)?;
+        let response = http::check_response("syncthing", response).await?;
 
         response
             .json()
@@ -142,6 +138,18 @@ async fn api_post<T: for<'de> Deserialize<'de>>(
             .map_err(|e| CfkError::Serialization(e.to_string()))
     }
 
+    /// Make API POST request without a body
+    async fn api_post(&self, endpoint: &str, query: &[(&str, &str)]) -> CfkResult<()> {
+        let request = self
+            .rest(reqwest::Method::POST, endpoint)
+            .await
+            .query(query);
+        let response = request.send().await.map_err(http::network_error)?;
+        http::check_response("syncthing", response).await?;
+
+        Ok(())
+    }
+
     /// Refresh folder list
     pub async fn refresh_folders(&self) -> CfkResult<()> {
         #[derive(Deserialize)]
@@ -149,7 +157,7 @@ struct ConfigResponse {
             folders: Vec<FolderConfig>,
         }
 
-        let config: ConfigResponse = self.api_get("config").await?;
+        let config: ConfigResponse = self.api_get("config", &[]).await?;
 
         let mut folders = self.folders.write().await;
         folders.clear();
@@ -162,44 +170,83 @@ struct ConfigResponse {
 
     /// Get folder status
     pub async fn get_folder_status(&self, folder_id: &str) -> CfkResult<FolderStatus> {
-        self.api_get(&format!("db/status?folder={}", folder_id))
-            .await
+        self.api_get("db/status", &[("folder", folder_id)]).await
     }
 
     /// Trigger rescan of a folder
     pub async fn rescan_folder(&self, folder_id: &str) -> CfkResult<()> {
-        let config = self.config.read().await;
-        let url = format!(
-            "{}/rest/db/scan?folder={}",
-            config.api_url, folder_id
-        );
+        self.api_post("db/scan", &[("folder", folder_id)]).await
+    }
 
-        let response = self
-            .http
-            .post(&url)
-            .header("X-API-Key", &config.api_key)
-            .send()
+    /// Trigger rescan of a single path within a folder
+    pub async fn rescan_path(&self, folder_id: &str, subpath: &str) -> CfkResult<()> {
+        self.api_post("db/scan", &[("folder", folder_id), ("sub", subpath)])
             .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
+    }
 
-        if !response.status().is_success() {
-            return Err(CfkError::ProviderApi {
-                provider: "syncthing".into(),
-                message: response.text().await.unwrap_or_default(),
-            });
+    /// Folder config, refreshing the cache on a miss
+    async fn folder(&self, folder_id: &str) -> CfkResult<FolderConfig> {
+        if let Some(folder) = self.folders.read().await.get(folder_id) {
+            return Ok(folder.clone());
         }
 
-        Ok(())
+        self.refresh_folders().await?;
+        self.folders
+            .read()
+            .await
+            .get(folder_id)
+            .cloned()
+            .ok_or_else(|| CfkError::NotFound(folder_id.to_string()))
     }
 
-    /// Get local path for a virtual path
-    fn get_local_path(&self, folder_id: &str, subpath: &str) -> CfkResult<PathBuf> {
-        // This would need the folder cache to be populated
-        // For now, return an error indicating the need for local backend
-        Err(CfkError::Unsupported(format!(
-            "Use LocalBackend for actual file operations on folder {} subpath {}",
-            folder_id, subpath
-        )))
+    /// Get local path for a folder, if it exists on this machine
+    async fn get_local_path(&self, folder_id: &str) -> CfkResult<Option<PathBuf>> {
+        let folder = self.folder(folder_id).await?;
+
+        // Syncthing stores `~` unexpanded in its config
+        let path = match folder.path.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(rest))
+                .unwrap_or_else(|| PathBuf::from(&folder.path)),
+            None => PathBuf::from(&folder.path),
+        };
+
+        Ok(path.is_dir().then_some(path))
+    }
+
+    /// Local backend for the folder holding `path`, plus the folder-relative path
+    async fn local(&self, path: &VirtualPath) -> CfkResult<(String, LocalBackend, VirtualPath)> {
+        let (folder_id, subpath) = self.parse_path(path);
+        let folder_id = folder_id
+            .ok_or_else(|| CfkError::InvalidPath("Syncthing root only contains folders".into()))?;
+
+        let root = self.get_local_path(&folder_id).await?.ok_or_else(|| {
+            CfkError::Unsupported(format!(
+                "Syncthing folder {} is not on this machine",
+                folder_id
+            ))
+        })?;
+
+        let local = LocalBackend::new(&self.id, root);
+        Ok((folder_id, local, VirtualPath::new(&self.id, subpath)))
+    }
+
+    /// Re-root an entry from a folder-local backend under its folder ID
+    fn remap(&self, folder_id: &str, mut entry: Entry) -> Entry {
+        let mut segments = vec![folder_id.to_string()];
+        segments.append(&mut entry.path.segments);
+        entry.path.segments = segments;
+        entry
+    }
+
+    /// Ask Syncthing to pick up a local change right away.
+    ///
+    /// Best effort: the filesystem watcher notices the change anyway.
+    async fn notify_changed(&self, folder_id: &str, local_path: &VirtualPath) {
+        let sub = local_path.segments.join("/");
+        if let Err(e) = self.rescan_path(folder_id, &sub).await {
+            tracing::debug!("Syncthing rescan of {}/{} failed: {}", folder_id, sub, e);
+        }
     }
 
     /// Parse path into folder ID and subpath
@@ -216,6 +263,197 @@ fn parse_path(&self, path: &VirtualPath) -> (Option<String>, String) {
             (Some(folder_id), subpath)
         }
     }
+
+    /// Entry for a folder itself
+    fn folder_entry(&self, folder: &FolderConfig) -> Entry {
+        let mut metadata = Metadata::default();
+        // Use label as display name in custom metadata
+        metadata
+            .custom
+            .insert("label".to_string(), folder.label.clone());
+        metadata
+            .custom
+            .insert("type".to_string(), folder.folder_type.clone());
+        metadata.custom.insert(
+            "paused".to_string(),
+            if folder.paused { "true" } else { "false" }.to_string(),
+        );
+
+        Entry::directory(VirtualPath::new(&self.id, &folder.id), metadata)
+    }
+
+    /// List a folder through the database browse API
+    async fn browse(
+        &self,
+        path: &VirtualPath,
+        folder_id: &str,
+        subpath: &str,
+    ) -> CfkResult<Vec<Entry>> {
+        #[derive(Deserialize)]
+        struct BrowseEntry {
+            name: String,
+            #[serde(rename = "type")]
+            entry_type: String,
+            size: Option<u64>,
+            #[serde(rename = "modTime")]
+            mod_time: Option<DateTime<Utc>>,
+        }
+
+        let mut query = vec![("folder", folder_id), ("levels", "0")];
+        if !subpath.is_empty() {
+            query.push(("prefix", subpath));
+        }
+        let entries: Vec<BrowseEntry> = self.api_get("db/browse", &query).await?;
+
+        Ok(entries
+            .into_iter()
+            .map(|e| {
+                let metadata = Metadata {
+                    size: e.size,
+                    modified: e.mod_time,
+                    ..Default::default()
+                };
+
+                let child = path.join(&e.name);
+                if e.entry_type.contains("DIRECTORY") || e.entry_type == "d" {
+                    Entry::directory(child, metadata)
+                } else {
+                    Entry::file(child, metadata)
+                }
+            })
+            .collect())
+    }
+
+    /// Metadata for a file from the global database view
+    async fn db_file(
+        &self,
+        path: &VirtualPath,
+        folder_id: &str,
+        subpath: &str,
+    ) -> CfkResult<Entry> {
+        #[derive(Deserialize)]
+        struct FileResponse {
+            global: FileInfo,
+        }
+
+        #[derive(Deserialize)]
+        struct FileInfo {
+            #[serde(rename = "type")]
+            entry_type: String,
+            size: u64,
+            modified: Option<DateTime<Utc>>,
+            sequence: Option<u64>,
+            #[serde(default)]
+            deleted: bool,
+        }
+
+        let resp: FileResponse = self
+            .api_get("db/file", &[("folder", folder_id), ("file", subpath)])
+            .await?;
+        if resp.global.deleted {
+            return Err(CfkError::NotFound(path.to_string()));
+        }
+
+        let metadata = Metadata {
+            size: Some(resp.global.size),
+            modified: resp.global.modified,
+            revision: resp.global.sequence.map(|s| s.to_string()),
+            ..Default::default()
+        };
+
+        Ok(if resp.global.entry_type.contains("DIRECTORY") {
+            Entry::directory(path.clone(), metadata)
+        } else {
+            Entry::file(path.clone(), metadata)
+        })
+    }
+}
+
+/// An entry of `/rest/events`
+#[derive(Debug, Deserialize)]
+struct Event {
+    id: u64,
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+/// Changes to all folders, from the `/rest/events` long poll
+struct SyncthingChanges {
+    backend: SyncthingBackend,
+    since: u64,
+    /// Reported as rescanned after the daemon was unreachable
+    watched: VirtualPath,
+    /// Event IDs restart with the daemon, so after an error the position is
+    /// taken again
+    resync: bool,
+}
+
+impl SyncthingChanges {
+    /// ID of the latest event, which the next poll starts after
+    async fn latest(backend: &SyncthingBackend) -> CfkResult<u64> {
+        let events: Vec<Event> = backend
+            .api_get(
+                "events",
+                &[("events", WATCH_EVENTS), ("limit", "1"), ("timeout", "0")],
+            )
+            .await?;
+        Ok(events.last().map(|e| e.id).unwrap_or(0))
+    }
+
+    fn event(&self, event: &Event) -> Option<ChangeEvent> {
+        let data = &event.data;
+        let folder = data["folder"].as_str().or(data["folderID"].as_str())?;
+        let (path, kind) = match event.event_type.as_str() {
+            "LocalChangeDetected" => {
+                let kind = match data["action"].as_str()? {
+                    "added" => ChangeKind::Created,
+                    "deleted" => ChangeKind::Deleted,
+                    _ => ChangeKind::Modified,
+                };
+                (data["path"].as_str()?, kind)
+            }
+            "ItemFinished" if data["error"].is_null() => {
+                let kind = match data["action"].as_str()? {
+                    "delete" => ChangeKind::Deleted,
+                    _ => ChangeKind::Modified,
+                };
+                (data["item"].as_str()?, kind)
+            }
+            _ => return None,
+        };
+        let path = VirtualPath::new(&self.backend.id, format!("{}/{}", folder, path));
+        Some(ChangeEvent::new(path, kind))
+    }
+}
+
+#[async_trait]
+impl ChangeFeed for SyncthingChanges {
+    async fn poll(&mut self) -> CfkResult<Vec<ChangeEvent>> {
+        if self.resync {
+            self.since = Self::latest(&self.backend).await?;
+            self.resync = false;
+            return Ok(vec![ChangeEvent::new(self.watched.clone(), ChangeKind::Rescan)]);
+        }
+
+        let since = self.since.to_string();
+        let query = [
+            ("since", since.as_str()),
+            ("events", WATCH_EVENTS),
+            ("timeout", EVENTS_TIMEOUT_SECS),
+        ];
+        let events: Vec<Event> = match self.backend.api_get("events", &query).await {
+            Ok(events) => events,
+            Err(e) => {
+                self.resync = true;
+                return Err(e);
+            }
+        };
+
+        self.since = events.iter().map(|e| e.id).fold(self.since, u64::max);
+        Ok(events.iter().filter_map(|e| self.event(e)).collect())
+    }
 }
 
 #[async_trait]
@@ -239,155 +477,127 @@ struct SystemStatus {
             _my_id: String,
         }
 
-        self.api_get::<SystemStatus>("system/status").await.is_ok()
+        self.api_get::<SystemStatus>("system/status", &[])
+            .await
+            .is_ok()
     }
 
     async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
         let (folder_id, subpath) = self.parse_path(path);
 
-        if folder_id.is_none() {
+        let Some(folder_id) = folder_id else {
             // Root - return as directory
-            return Ok(Entry {
-                path: path.clone(),
-                kind: EntryKind::Directory,
-                metadata: Metadata::default(),
-            });
-        }
-
-        let folder_id = folder_id.unwrap();
+            return Ok(Entry::directory(path.clone(), Metadata::default()));
+        };
 
         if subpath.is_empty() {
             // Folder root
-            let status = self.get_folder_status(&folder_id).await?;
-
-            let mut metadata = Metadata::default();
-            metadata.size = Some(status.local_bytes);
-
-            return Ok(Entry {
-                path: path.clone(),
-                kind: EntryKind::Directory,
-                metadata,
-            });
+            let folder = self.folder(&folder_id).await?;
+            let mut entry = self.folder_entry(&folder);
+            entry.metadata.size = Some(self.get_folder_status(&folder_id).await?.local_bytes);
+            return Ok(entry);
         }
 
-        // For subpaths, delegate to local backend
-        Err(CfkError::Unsupported(
-            "Use LocalBackend for file metadata in Syncthing folders".into(),
-        ))
+        match self.local(path).await {
+            Ok((folder_id, local, local_path)) => {
+                let entry = local.get_metadata(&local_path).await?;
+                Ok(self.remap(&folder_id, entry))
+            }
+            Err(CfkError::Unsupported(_)) => self.db_file(path, &folder_id, &subpath).await,
+            Err(e) => Err(e),
+        }
     }
 
-    async fn list_directory(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
         let (folder_id, subpath) = self.parse_path(path);
 
-        if folder_id.is_none() {
+        let Some(folder_id) = folder_id else {
             // List all synced folders
             self.refresh_folders().await?;
 
             let folders = self.folders.read().await;
-            let entries: Vec<Entry> = folders
-                .values()
-                .map(|f| {
-                    let mut metadata = Metadata::default();
-                    // Use label as display name in custom metadata
-                    metadata.custom
-                        .insert("label".to_string(), f.label.clone());
-                    metadata
-                        .custom
-                        .insert("type".to_string(), f.folder_type.clone());
-                    metadata.custom.insert(
-                        "paused".to_string(),
-                        if f.paused { "true" } else { "false" }.to_string(),
-                    );
-
-                    Entry {
-                        path: VirtualPath::new(&self.id, &f.id),
-                        kind: EntryKind::Directory,
-                        metadata,
-                    }
-                })
-                .collect();
-
-            return Ok(entries);
+            let entries = folders.values().map(|f| self.folder_entry(f)).collect();
+            return Ok(common::page_entries(path, entries, options));
+        };
+
+        match self.local(path).await {
+            Ok((folder_id, local, local_path)) => {
+                let mut listing = local.list_directory(&local_path, options).await?;
+                listing.path = path.clone();
+                listing.entries = listing
+                    .entries
+                    .into_iter()
+                    .map(|e| self.remap(&folder_id, e))
+                    .collect();
+                Ok(listing)
+            }
+            Err(CfkError::Unsupported(_)) => {
+                // Folder lives elsewhere; fall back to the database view
+                let entries = self.browse(path, &folder_id, &subpath).await?;
+                Ok(common::page_entries(path, entries, options))
+            }
+            Err(e) => Err(e),
         }
+    }
 
-        if !subpath.is_empty() {
-            return Err(CfkError::Unsupported(
-                "Use LocalBackend to list files in Syncthing folders".into(),
-            ));
-        }
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let (folder_id, subpath) = self.parse_path(path);
 
-        // For folder contents, we need to use the database browse API
-        #[derive(Deserialize)]
-        struct BrowseEntry {
-            name: String,
-            #[serde(rename = "type")]
-            entry_type: String,
-            size: Option<u64>,
-            #[serde(rename = "modTime")]
-            mod_time: Option<String>,
+        if folder_id.is_none() || subpath.is_empty() {
+            return Err(CfkError::NotAFile(path.to_string()));
         }
 
-        let folder_id = folder_id.unwrap();
-        let entries: Vec<BrowseEntry> = self
-            .api_get(&format!("db/browse?folder={}&levels=1", folder_id))
-            .await?;
-
-        let base_path = path.segments.join("/");
-
-        Ok(entries
-            .iter()
-            .map(|e| {
-                let path_str = if base_path.is_empty() {
-                    e.name.clone()
-                } else {
-                    format!("{}/{}", base_path, e.name)
-                };
-
-                let kind = if e.entry_type == "d" {
-                    EntryKind::Directory
-                } else {
-                    EntryKind::File
-                };
-
-                let mut metadata = Metadata::default();
-                metadata.size = e.size;
-
-                Entry {
-                    path: VirtualPath::new(&self.id, &path_str),
-                    kind,
-                    metadata,
-                }
-            })
-            .collect())
+        // Syncthing doesn't provide file content via API
+        let (_, local, local_path) = self.local(path).await?;
+        local.read_file(&local_path, options).await
     }
 
-    async fn read_file(&self, path: &VirtualPath) -> CfkResult<Bytes> {
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
         let (folder_id, subpath) = self.parse_path(path);
 
         if folder_id.is_none() || subpath.is_empty() {
-            return Err(CfkError::InvalidPath("Cannot read folder as file".into()));
+            return Err(CfkError::InvalidPath("Cannot write to folder".into()));
         }
 
-        // Syncthing doesn't provide file content via API
-        // Files must be accessed through local filesystem
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to read files in Syncthing folders".into(),
-        ))
+        let (folder_id, local, local_path) = self.local(path).await?;
+        let entry = local.write_file(&local_path, data, options).await?;
+        self.notify_changed(&folder_id, &local_path).await;
+
+        Ok(self.remap(&folder_id, entry))
     }
 
-    async fn write_file(&self, path: &VirtualPath, _data: Bytes) -> CfkResult<Entry> {
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
         let (folder_id, subpath) = self.parse_path(path);
 
         if folder_id.is_none() || subpath.is_empty() {
             return Err(CfkError::InvalidPath("Cannot write to folder".into()));
         }
 
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to write files in Syncthing folders, then trigger rescan".into(),
-        ))
+        let (folder_id, local, local_path) = self.local(path).await?;
+        let entry = local
+            .write_file_stream(&local_path, stream, size_hint, options)
+            .await?;
+        self.notify_changed(&folder_id, &local_path).await;
+
+        Ok(self.remap(&folder_id, entry))
     }
 
-    async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
         let (folder_id, subpath) = self.parse_path(path);
 
         if folder_id.is_none() {
@@ -400,9 +610,11 @@ async fn delete(&self, path: &VirtualPath) -> CfkResult<()> {
             ));
         }
 
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to delete files in Syncthing folders".into(),
-        ))
+        let (folder_id, local, local_path) = self.local(path).await?;
+        local.delete(&local_path, options).await?;
+        self.notify_changed(&folder_id, &local_path).await;
+
+        Ok(())
     }
 
     async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
@@ -418,38 +630,86 @@ async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
             return Err(CfkError::AlreadyExists(path.to_string()));
         }
 
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to create directories in Syncthing folders".into(),
-        ))
+        let (folder_id, local, local_path) = self.local(path).await?;
+        let entry = local.create_directory(&local_path).await?;
+        self.notify_changed(&folder_id, &local_path).await;
+
+        Ok(self.remap(&folder_id, entry))
     }
 
-    async fn copy(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to copy files in Syncthing folders".into(),
-        ))
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        if source.segments.first() != dest.segments.first() {
+            return Err(CfkError::Unsupported(
+                "Copy between Syncthing folders".into(),
+            ));
+        }
+
+        let (folder_id, local, local_source) = self.local(source).await?;
+        let local_dest = VirtualPath::new(&self.id, self.parse_path(dest).1);
+        let entry = local.copy(&local_source, &local_dest, options).await?;
+        self.notify_changed(&folder_id, &local_dest).await;
+
+        Ok(self.remap(&folder_id, entry))
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        if source.segments.first() != dest.segments.first() {
+            return Err(CfkError::Unsupported(
+                "Rename between Syncthing folders".into(),
+            ));
+        }
+
+        let (folder_id, local, local_source) = self.local(source).await?;
+        let local_dest = VirtualPath::new(&self.id, self.parse_path(dest).1);
+        let entry = local.rename(&local_source, &local_dest, options).await?;
+        self.notify_changed(&folder_id, &local_source).await;
+        self.notify_changed(&folder_id, &local_dest).await;
+
+        Ok(self.remap(&folder_id, entry))
     }
 
-    async fn rename(&self, _from: &VirtualPath, _to: &VirtualPath) -> CfkResult<Entry> {
-        Err(CfkError::Unsupported(
-            "Use LocalBackend to rename files in Syncthing folders".into(),
-        ))
+    async fn watch(&self, path: &VirtualPath, recursive: bool) -> CfkResult<ChangeStream> {
+        if let (Some(folder_id), _) = self.parse_path(path) {
+            self.folder(&folder_id).await?;
+        }
+        let changes = SyncthingChanges {
+            since: SyncthingChanges::latest(self).await?,
+            backend: self.clone(),
+            watched: path.clone(),
+            resync: false,
+        };
+        Ok(common::change_stream(changes, path.clone(), recursive))
     }
 
-    async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
         // Sum up space from all folders
         self.refresh_folders().await?;
 
-        let folders = self.folders.read().await;
-        let mut total_bytes = 0u64;
+        let folder_ids: Vec<String> = self.folders.read().await.keys().cloned().collect();
+        let mut used = 0u64;
 
-        for folder in folders.values() {
-            if let Ok(status) = self.get_folder_status(&folder.id).await {
-                total_bytes += status.local_bytes;
+        for folder_id in folder_ids {
+            if let Ok(status) = self.get_folder_status(&folder_id).await {
+                used += status.local_bytes;
             }
         }
 
-        // Syncthing doesn't track quota, return local bytes as both
-        Ok((total_bytes, total_bytes))
+        // Syncthing doesn't track quota
+        Ok(SpaceInfo {
+            total: None,
+            used: Some(used),
+            available: None,
+        })
     }
 }
 
@@ -457,6 +717,7 @@ async fn get_space_info(&self) -> CfkResult<(u64, u64)> {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceConfig {
+    #[serde(rename = "deviceID")]
     pub device_id: String,
     pub name: String,
     pub addresses: Vec<String>,
@@ -472,7 +733,7 @@ struct ConfigResponse {
             devices: Vec<DeviceConfig>,
         }
 
-        let config: ConfigResponse = self.api_get("config").await?;
+        let config: ConfigResponse = self.api_get("config", &[]).await?;
         Ok(config.devices)
     }
 
@@ -483,38 +744,18 @@ struct ConnectionsResponse {
             connections: HashMap<String, ConnectionInfo>,
         }
 
-        let resp: ConnectionsResponse = self.api_get("system/connections").await?;
+        let resp: ConnectionsResponse = self.api_get("system/connections", &[]).await?;
         Ok(resp.connections)
     }
 
     /// Pause syncing
     pub async fn pause(&self) -> CfkResult<()> {
-        let config = self.config.read().await;
-        let url = format!("{}/rest/system/pause", config.api_url);
-
-        self.http
-            .post(&url)
-            .header("X-API-Key", &config.api_key)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        Ok(())
+        self.api_post("system/pause", &[]).await
     }
 
     /// Resume syncing
     pub async fn resume(&self) -> CfkResult<()> {
-        let config = self.config.read().await;
-        let url = format!("{}/rest/system/resume", config.api_url);
-
-        self.http
-            .post(&url)
-            .header("X-API-Key", &config.api_key)
-            .send()
-            .await
-            .map_err(|e| CfkError::Network(e.to_string()))?;
-
-        Ok(())
+        self.api_post("system/resume", &[]).await
     }
 }
 
@@ -529,3 +770,184 @@ pub struct ConnectionInfo {
     #[serde(rename = "type")]
     pub connection_type: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use futures::StreamExt;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn backend(server: &MockServer) -> SyncthingBackend {
+        SyncthingBackend::new(
+            "st",
+            SyncthingConfig {
+                api_url: server.uri(),
+                api_key: "key".into(),
+            },
+        )
+    }
+
+    async fn mount_config(server: &MockServer, folder_path: &str) {
+        Mock::given(method("GET"))
+            .and(path("/rest/config"))
+            .and(header("X-API-Key", "key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "folders": [{
+                    "id": "docs", "label": "Documents", "path": folder_path,
+                    "type": "sendreceive", "paused": false
+                }],
+                "devices": []
+            })))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_watch_long_polls_events() {
+        let server = MockServer::start().await;
+        mount_config(&server, "/nonexistent/cfk-syncthing").await;
+        Mock::given(method("GET"))
+            .and(path("/rest/events"))
+            .and(query_param("limit", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"id": 5, "type": "ItemFinished", "data": {}}
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/rest/events"))
+            .and(query_param("since", "5"))
+            .and(query_param("events", WATCH_EVENTS))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"id": 6, "type": "LocalChangeDetected", "data": {
+                    "folder": "docs", "path": "a.txt", "action": "added", "type": "file"}},
+                {"id": 7, "type": "ItemFinished", "data": {
+                    "folder": "music", "item": "b.mp3", "error": null, "action": "update"}},
+                {"id": 8, "type": "ItemFinished", "data": {
+                    "folder": "docs", "item": "sub/c.txt", "error": "disk full",
+                    "action": "update"}},
+                {"id": 9, "type": "ItemFinished", "data": {
+                    "folder": "docs", "item": "sub/d.txt", "error": null, "action": "delete"}}
+            ])))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let docs = VirtualPath::new("st", "docs");
+        let mut changes = backend.watch(&docs, true).await.unwrap();
+
+        let added = changes.next().await.unwrap().unwrap();
+        assert_eq!(added, ChangeEvent::new(docs.join("a.txt"), ChangeKind::Created));
+        let deleted = changes.next().await.unwrap().unwrap();
+        assert_eq!(
+            deleted,
+            ChangeEvent::new(VirtualPath::new("st", "docs/sub/d.txt"), ChangeKind::Deleted)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_root_lists_folders() {
+        let server = MockServer::start().await;
+        mount_config(&server, "/nonexistent/cfk-syncthing").await;
+
+        let backend = backend(&server);
+        let listing = backend
+            .list_directory(&VirtualPath::root("st"), &ListOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 1);
+        assert!(listing.entries[0].is_directory());
+        assert_eq!(
+            listing.entries[0]
+                .metadata
+                .custom
+                .get("label")
+                .map(String::as_str),
+            Some("Documents")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_folder_uses_browse_api() {
+        let server = MockServer::start().await;
+        mount_config(&server, "/nonexistent/cfk-syncthing").await;
+        Mock::given(method("GET"))
+            .and(path("/rest/db/browse"))
+            .and(query_param("folder", "docs"))
+            .and(query_param("prefix", "sub"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"name": "a.txt", "type": "FILE_INFO_TYPE_FILE", "size": 3,
+                 "modTime": "2024-01-01T00:00:00Z"},
+                {"name": "img", "type": "FILE_INFO_TYPE_DIRECTORY"}
+            ])))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let listing = backend
+            .list_directory(&VirtualPath::new("st", "docs/sub"), &ListOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 2);
+        assert_eq!(listing.entries[0].path.to_path_string(), "/docs/sub/a.txt");
+        assert!(listing.entries[1].is_directory());
+
+        let err = backend
+            .read_file(
+                &VirtualPath::new("st", "docs/sub/a.txt"),
+                &ReadOptions::default(),
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, CfkError::Unsupported(_)));
+    }
+
+    #[tokio::test]
+    async fn test_local_folder_write_triggers_rescan() {
+        let dir = TempDir::new().unwrap();
+        let server = MockServer::start().await;
+        mount_config(&server, dir.path().to_str().unwrap()).await;
+        Mock::given(method("POST"))
+            .and(path("/rest/db/scan"))
+            .and(query_param("folder", "docs"))
+            .and(query_param("sub", "notes.txt"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let target = VirtualPath::new("st", "docs/notes.txt");
+        let entry = backend
+            .write_file(&target, Bytes::from_static(b"hi"), &WriteOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(entry.path, target);
+        assert_eq!(std::fs::read(dir.path().join("notes.txt")).unwrap(), b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_space_info_sums_folder_bytes() {
+        let server = MockServer::start().await;
+        mount_config(&server, "/nonexistent/cfk-syncthing").await;
+        Mock::given(method("GET"))
+            .and(path("/rest/db/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "state": "idle", "localFiles": 2, "localBytes": 42, "globalFiles": 2,
+                "globalBytes": 42, "needFiles": 0, "needBytes": 0
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.used, Some(42));
+        assert_eq!(space.total, None);
+    }
+}
@@ -0,0 +1,625 @@
+//! OAuth tokens and their persistence
+//!
+//! Every OAuth provider keeps its tokens in a token manager, which hands
+//! out access tokens, refreshes them shortly before they expire and writes
+//! every new grant to a [`TokenStore`]. [`FileTokenStore`] keeps one
+//! encrypted file per remote; the key lives in a separate file next to the
+//! config, so a stray copy of the token directory gives nothing away.
+//!
+//! [`OAuthBackend`] and [`LoopbackListener`] drive the interactive
+//! authorization code + PKCE flow behind `cfk auth`.
+
+use cfk_core::{CfkError, CfkResult};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "oauth2")]
+pub use self::oauth::*;
+
+/// Refresh this long before the provider's expiry time
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+const NONCE_LEN: usize = 24;
+
+/// Tokens from an OAuth authorization
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthTokens {
+    /// Whether the access token is expired or about to be
+    pub fn expires_soon(&self) -> bool {
+        self.expires_at
+            .is_some_and(|at| at - chrono::Duration::seconds(EXPIRY_MARGIN_SECS) <= Utc::now())
+    }
+}
+
+/// Where tokens survive between runs, keyed by backend ID
+pub trait TokenStore: Send + Sync {
+    fn load(&self, id: &str) -> CfkResult<Option<OAuthTokens>>;
+    fn save(&self, id: &str, tokens: &OAuthTokens) -> CfkResult<()>;
+    fn remove(&self, id: &str) -> CfkResult<()>;
+}
+
+/// Tokens encrypted with XChaCha20-Poly1305, one file per backend
+///
+/// The backend ID is authenticated along with each file, so files cannot
+/// be swapped between remotes.
+pub struct FileTokenStore {
+    dir: PathBuf,
+    cipher: XChaCha20Poly1305,
+}
+
+impl FileTokenStore {
+    /// `tokens` in the user data directory
+    pub fn default_dir() -> PathBuf {
+        directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.data_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-data"))
+            .join("tokens")
+    }
+
+    /// `token.key` beside the config file
+    pub fn default_key_path() -> PathBuf {
+        crate::CfkConfig::default_path().with_file_name("token.key")
+    }
+
+    pub fn open_default() -> CfkResult<Self> {
+        Self::open(Self::default_dir(), Self::default_key_path())
+    }
+
+    /// Open the store in `dir`, creating the key at `key_path` on first use
+    pub fn open(dir: impl Into<PathBuf>, key_path: impl AsRef<Path>) -> CfkResult<Self> {
+        let key_path = key_path.as_ref();
+        let key = match std::fs::read(key_path) {
+            Ok(key) if key.len() == 32 => *Key::from_slice(&key),
+            Ok(_) => {
+                return Err(CfkError::Config(format!(
+                    "{}: not a token key",
+                    key_path.display()
+                )))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+                crate::config::write_private(key_path, &key)?;
+                key
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::with_key(dir, key.into()))
+    }
+
+    pub fn with_key(dir: impl Into<PathBuf>, key: [u8; 32]) -> Self {
+        Self {
+            dir: dir.into(),
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        }
+    }
+
+    fn path(&self, id: &str) -> CfkResult<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(CfkError::InvalidPath(format!("token store key {:?}", id)));
+        }
+        Ok(self.dir.join(format!("{}.token", id)))
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self, id: &str) -> CfkResult<Option<OAuthTokens>> {
+        let data = match std::fs::read(self.path(id)?) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if data.len() < NONCE_LEN {
+            return Err(CfkError::Serialization(format!(
+                "token file for {} is truncated",
+                id
+            )));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plain = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                CfkError::AuthFailed(format!(
+                    "stored tokens for {} do not decrypt with this key",
+                    id
+                ))
+            })?;
+        serde_json::from_slice(&plain)
+            .map(Some)
+            .map_err(|e| CfkError::Serialization(e.to_string()))
+    }
+
+    fn save(&self, id: &str, tokens: &OAuthTokens) -> CfkResult<()> {
+        let path = self.path(id)?;
+        let plain =
+            serde_json::to_vec(tokens).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plain,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| CfkError::Other("token encryption failed".to_string()))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        crate::config::write_private(&path, &data)
+    }
+
+    fn remove(&self, id: &str) -> CfkResult<()> {
+        match std::fs::remove_file(self.path(id)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "oauth2")]
+mod oauth {
+    use super::*;
+    use async_trait::async_trait;
+    use cfk_core::StorageBackend;
+    use oauth2::{url::Url, CsrfToken, PkceCodeVerifier};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::{Mutex, RwLock};
+
+    use crate::http;
+
+    /// Where OAuth providers send the browser back to unless configured
+    /// otherwise; `cfk auth` listens here
+    pub const LOOPBACK_REDIRECT_URI: &str = "http://127.0.0.1:8914/callback";
+
+    /// The tokens of one OAuth backend
+    pub(crate) struct TokenManager {
+        id: String,
+        token_url: String,
+        client_id: String,
+        client_secret: Option<String>,
+        tokens: RwLock<Option<OAuthTokens>>,
+        store: Option<Arc<dyn TokenStore>>,
+        /// Held while refreshing, so concurrent callers wait for one refresh
+        refreshing: Mutex<()>,
+    }
+
+    impl TokenManager {
+        pub fn new(
+            id: &str,
+            token_url: &str,
+            client_id: &str,
+            client_secret: Option<&str>,
+        ) -> Self {
+            Self {
+                id: id.to_string(),
+                token_url: token_url.to_string(),
+                client_id: client_id.to_string(),
+                client_secret: client_secret.map(str::to_string),
+                tokens: RwLock::new(None),
+                store: None,
+                refreshing: Mutex::new(()),
+            }
+        }
+
+        /// Persist tokens to `store`, starting from what it holds
+        ///
+        /// Unreadable stored tokens are dropped with a warning; the user
+        /// has to authorize again.
+        pub fn attach_store(&mut self, store: Arc<dyn TokenStore>) {
+            match store.load(&self.id) {
+                Ok(Some(tokens)) => *self.tokens.get_mut() = Some(tokens),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("ignoring stored tokens of {}: {}", self.id, e),
+            }
+            self.store = Some(store);
+        }
+
+        pub async fn get(&self) -> Option<OAuthTokens> {
+            self.tokens.read().await.clone()
+        }
+
+        pub async fn set(&self, tokens: OAuthTokens) -> CfkResult<()> {
+            if let Some(store) = &self.store {
+                store.save(&self.id, &tokens)?;
+            }
+            *self.tokens.write().await = Some(tokens);
+            Ok(())
+        }
+
+        pub async fn is_authenticated(&self) -> bool {
+            self.tokens.read().await.is_some()
+        }
+
+        /// The current access token, refreshed first if it is about to
+        /// expire
+        pub async fn access_token(&self, http: &reqwest::Client) -> CfkResult<String> {
+            let tokens = self.get().await.ok_or_else(|| {
+                CfkError::AuthRequired(format!("Not authenticated; run `cfk auth {}`", self.id))
+            })?;
+            if !tokens.expires_soon() || tokens.refresh_token.is_none() {
+                return Ok(tokens.access_token);
+            }
+
+            let _guard = self.refreshing.lock().await;
+            // Someone else may have refreshed while we waited
+            if let Some(tokens) = self.get().await.filter(|t| !t.expires_soon()) {
+                return Ok(tokens.access_token);
+            }
+            self.refresh_locked(http).await?;
+            self.get()
+                .await
+                .map(|t| t.access_token)
+                .ok_or_else(|| CfkError::AuthRequired("Not authenticated".into()))
+        }
+
+        /// Trade the refresh token for a new access token
+        ///
+        /// `Ok(false)` means there is no refresh token to trade.
+        pub async fn refresh(&self, http: &reqwest::Client) -> CfkResult<bool> {
+            let _guard = self.refreshing.lock().await;
+            self.refresh_locked(http).await
+        }
+
+        async fn refresh_locked(&self, http: &reqwest::Client) -> CfkResult<bool> {
+            let Some(refresh_token) = self.get().await.and_then(|t| t.refresh_token) else {
+                return Ok(false);
+            };
+
+            let grant = http::refresh_grant(
+                http,
+                &self.token_url,
+                &refresh_token,
+                &self.client_id,
+                self.client_secret.as_deref(),
+            )
+            .await?;
+            let tokens = OAuthTokens {
+                expires_at: grant.expires_at(),
+                access_token: grant.access_token,
+                // Google only sends a refresh token with the first grant;
+                // Microsoft and Box rotate it on every refresh
+                refresh_token: grant.refresh_token.or(Some(refresh_token)),
+            };
+            if let Some(store) = &self.store {
+                if let Err(e) = store.save(&self.id, &tokens) {
+                    tracing::warn!("could not store refreshed tokens of {}: {}", self.id, e);
+                }
+            }
+            *self.tokens.write().await = Some(tokens);
+            Ok(true)
+        }
+
+        /// Redeem an authorization code and keep the tokens
+        pub async fn exchange(
+            &self,
+            http: &reqwest::Client,
+            params: &[(&str, &str)],
+        ) -> CfkResult<OAuthTokens> {
+            let grant =
+                http::token_grant(http, &self.token_url, params, "Token exchange failed").await?;
+            let tokens = OAuthTokens {
+                expires_at: grant.expires_at(),
+                access_token: grant.access_token,
+                refresh_token: grant.refresh_token,
+            };
+            self.set(tokens.clone()).await?;
+            Ok(tokens)
+        }
+    }
+
+    /// The start of an authorization: where to send the user, and what to
+    /// check and present when they come back
+    pub struct AuthRequest {
+        pub url: String,
+        /// Must come back unchanged in the redirect's `state`
+        pub state: CsrfToken,
+        pub verifier: PkceCodeVerifier,
+    }
+
+    /// A backend that authorizes through an OAuth authorization code flow
+    #[async_trait]
+    pub trait OAuthBackend: StorageBackend {
+        /// Keep tokens in `store`, starting with the ones stored for this
+        /// backend
+        fn with_token_store(self, store: Arc<dyn TokenStore>) -> Self
+        where
+            Self: Sized;
+
+        /// Where the provider sends the browser after authorization
+        fn redirect_uri(&self) -> &str;
+
+        fn start_auth(&self) -> AuthRequest;
+
+        /// Redeem the code from the redirect; the tokens are kept and
+        /// persisted
+        async fn complete_auth(
+            &self,
+            code: &str,
+            verifier: PkceCodeVerifier,
+        ) -> CfkResult<OAuthTokens>;
+    }
+
+    /// A one-shot HTTP listener on a loopback redirect URI that catches
+    /// the authorization code
+    pub struct LoopbackListener {
+        listener: TcpListener,
+        path: String,
+    }
+
+    impl LoopbackListener {
+        /// Listen on the host and port of `redirect_uri`, which must point at
+        /// this machine over plain HTTP
+        pub async fn bind(redirect_uri: &str) -> CfkResult<Self> {
+            let url = Url::parse(redirect_uri)
+                .map_err(|e| CfkError::Config(format!("redirect_uri {}: {}", redirect_uri, e)))?;
+            let host = match url.host_str() {
+                Some("localhost") | Some("127.0.0.1") => "127.0.0.1",
+                Some("[::1]") => "::1",
+                _ => "",
+            };
+            if url.scheme() != "http" || host.is_empty() {
+                return Err(CfkError::Config(format!(
+                    "redirect_uri {} is not a loopback address (http://127.0.0.1:PORT/...)",
+                    redirect_uri
+                )));
+            }
+
+            let listener = TcpListener::bind((host, url.port().unwrap_or(80))).await?;
+            Ok(Self {
+                listener,
+                path: url.path().to_string(),
+            })
+        }
+
+        pub fn local_addr(&self) -> CfkResult<std::net::SocketAddr> {
+            Ok(self.listener.local_addr()?)
+        }
+
+        /// Wait for the redirect and return the authorization code
+        ///
+        /// Requests for other paths, such as the browser's favicon fetch,
+        /// are turned away and the wait goes on.
+        pub async fn wait(&self, state: &CsrfToken) -> CfkResult<String> {
+            loop {
+                let (socket, _) = self.listener.accept().await?;
+                let mut socket = BufReader::new(socket);
+                let mut line = String::new();
+                socket.read_line(&mut line).await?;
+                let target = line.split_whitespace().nth(1).unwrap_or("/");
+                let Ok(url) = Url::parse("http://localhost").and_then(|base| base.join(target))
+                else {
+                    respond(socket.get_mut(), "400 Bad Request", "Bad request").await;
+                    continue;
+                };
+                if url.path() != self.path {
+                    respond(socket.get_mut(), "404 Not Found", "Not found").await;
+                    continue;
+                }
+
+                let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+                let result = if let Some(error) = params.get("error") {
+                    Err(CfkError::AuthFailed(format!(
+                        "{}: {}",
+                        error,
+                        params
+                            .get("error_description")
+                            .map(String::as_str)
+                            .unwrap_or("authorization refused")
+                    )))
+                } else if params.get("state") != Some(state.secret()) {
+                    Err(CfkError::AuthFailed(
+                        "OAuth state mismatch; the redirect did not come from this request"
+                            .to_string(),
+                    ))
+                } else {
+                    params.get("code").cloned().ok_or_else(|| {
+                        CfkError::AuthFailed("redirect carried no authorization code".to_string())
+                    })
+                };
+
+                match &result {
+                    Ok(_) => {
+                        respond(
+                            socket.get_mut(),
+                            "200 OK",
+                            "cfk is authorized. You can close this window.",
+                        )
+                        .await
+                    }
+                    Err(e) => respond(socket.get_mut(), "400 Bad Request", &e.to_string()).await,
+                }
+                return result;
+            }
+        }
+    }
+
+    async fn respond(socket: &mut tokio::net::TcpStream, status: &str, message: &str) {
+        let body = format!(
+            "<!DOCTYPE html><html><body><p>{}</p></body></html>",
+            message.replace('&', "&amp;").replace('<', "&lt;")
+        );
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        // The browser going away is no reason to fail the flow
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(access: &str) -> OAuthTokens {
+        OAuthTokens {
+            access_token: access.into(),
+            refresh_token: Some("refresh".into()),
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+        }
+    }
+
+    #[test]
+    fn test_file_store_encrypts_and_binds_to_remote() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key_path = tmp.path().join("token.key");
+        let store = FileTokenStore::open(tmp.path().join("tokens"), &key_path).unwrap();
+        assert_eq!(store.load("box").unwrap(), None);
+
+        let saved = tokens("secret-access");
+        store.save("box", &saved).unwrap();
+        let raw = std::fs::read(tmp.path().join("tokens/box.token")).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("secret-access"));
+
+        // The key is kept and reused
+        let reopened = FileTokenStore::open(tmp.path().join("tokens"), &key_path).unwrap();
+        assert_eq!(reopened.load("box").unwrap(), Some(saved));
+
+        // A file copied to another remote does not decrypt
+        std::fs::copy(
+            tmp.path().join("tokens/box.token"),
+            tmp.path().join("tokens/other.token"),
+        )
+        .unwrap();
+        assert!(matches!(
+            reopened.load("other"),
+            Err(CfkError::AuthFailed(_))
+        ));
+
+        let wrong_key = FileTokenStore::with_key(tmp.path().join("tokens"), [7; 32]);
+        assert!(wrong_key.load("box").is_err());
+        assert!(store.load("../box").is_err());
+
+        store.remove("box").unwrap();
+        store.remove("box").unwrap();
+        assert_eq!(store.load("box").unwrap(), None);
+    }
+
+    #[test]
+    fn test_expires_soon() {
+        let mut t = tokens("a");
+        assert!(!t.expires_soon());
+        t.expires_at = Some(Utc::now() + chrono::Duration::seconds(30));
+        assert!(t.expires_soon());
+        t.expires_at = None;
+        assert!(!t.expires_soon());
+    }
+
+    #[cfg(feature = "oauth2")]
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed_and_persisted() {
+        use std::sync::Arc;
+        use wiremock::matchers::{body_string_contains, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("refresh_token=r1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "fresh", "refresh_token": "r2", "expires_in": 3600
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileTokenStore::with_key(tmp.path(), [1; 32]));
+        store
+            .save(
+                "drive",
+                &OAuthTokens {
+                    access_token: "stale".into(),
+                    refresh_token: Some("r1".into()),
+                    expires_at: Some(Utc::now() - chrono::Duration::minutes(5)),
+                },
+            )
+            .unwrap();
+
+        let token_url = format!("{}/token", server.uri());
+        let mut manager = TokenManager::new("drive", &token_url, "client", None);
+        manager.attach_store(store.clone());
+        let http = reqwest::Client::new();
+
+        let (a, b) = tokio::join!(manager.access_token(&http), manager.access_token(&http));
+        assert_eq!(a.unwrap(), "fresh");
+        assert_eq!(b.unwrap(), "fresh");
+        let stored = store.load("drive").unwrap().unwrap();
+        assert_eq!(stored.access_token, "fresh");
+        assert_eq!(stored.refresh_token.as_deref(), Some("r2"));
+    }
+
+    #[cfg(feature = "oauth2")]
+    #[tokio::test]
+    async fn test_loopback_listener_checks_state() {
+        let listener = LoopbackListener::bind("http://127.0.0.1:0/callback")
+            .await
+            .unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let state = oauth2::CsrfToken::new("expected".into());
+
+        let client = tokio::spawn(async move {
+            let http = reqwest::Client::new();
+            let miss = http
+                .get(format!("{}/favicon.ico", base))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(miss.status(), 404);
+            let hit = http
+                .get(format!("{}/callback?code=abc&state=expected", base))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(hit.status(), 200);
+        });
+        assert_eq!(listener.wait(&state).await.unwrap(), "abc");
+        client.await.unwrap();
+
+        let listener = LoopbackListener::bind("http://localhost:0/cb")
+            .await
+            .unwrap();
+        let url = format!(
+            "http://{}/cb?code=abc&state=forged",
+            listener.local_addr().unwrap()
+        );
+        let client = tokio::spawn(async move { reqwest::get(url).await.unwrap().status() });
+        assert!(matches!(
+            listener.wait(&state).await,
+            Err(CfkError::AuthFailed(_))
+        ));
+        assert_eq!(client.await.unwrap(), 400);
+
+        assert!(LoopbackListener::bind("https://example.com/cb")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,881 @@
+//! WebDAV storage backend
+//!
+//! HTTP-based distributed authoring and versioning protocol.
+//! Compatible with NextCloud, ownCloud, SharePoint, Apache mod_dav, etc.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use cfk_core::{
+    backend::{ByteStream, SpaceInfo},
+    entry::DirectoryListing,
+    operations::*,
+    CfkError, CfkResult, Entry, Metadata, StorageBackend, StorageCapabilities, VirtualPath,
+};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Body, Client, Method};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::common;
+use crate::http;
+
+pub mod server;
+pub use server::{WebDavServer, WebDavServerOptions};
+
+/// Properties requested on every PROPFIND
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getlastmodified/>
+    <d:creationdate/>
+    <d:getetag/>
+    <d:getcontenttype/>
+  </d:prop>
+</d:propfind>"#;
+
+/// WebDAV authentication method
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebDavAuth {
+    /// No authentication
+    #[default]
+    None,
+    /// Basic authentication
+    Basic { username: String, password: String },
+    /// Bearer token (OAuth)
+    Bearer(String),
+    /// Digest authentication (handled by reqwest)
+    Digest { username: String, password: String },
+}
+
+/// WebDAV backend configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebDavConfig {
+    /// Base URL (e.g., "https://cloud.example.com/remote.php/dav/files/username")
+    pub base_url: String,
+    /// Authentication method
+    #[serde(default)]
+    pub auth: WebDavAuth,
+    /// Custom headers
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+}
+
+/// WebDAV storage backend
+pub struct WebDavBackend {
+    id: String,
+    config: Arc<RwLock<WebDavConfig>>,
+    http: Client,
+    capabilities: StorageCapabilities,
+}
+
+impl WebDavBackend {
+    pub fn new(id: impl Into<String>, config: WebDavConfig) -> Self {
+        Self {
+            id: id.into(),
+            config: Arc::new(RwLock::new(config)),
+            http: Client::new(),
+            capabilities: StorageCapabilities {
+                read: true,
+                write: true,
+                delete: true,
+                rename: true,
+                copy: true,
+                list: true,
+                search: false,
+                versioning: false, // Some servers support it
+                sharing: false,
+                offline: false,
+                streaming: true,
+                range_reads: true,
+                resumable_uploads: false,
+                content_hashing: false, // ETags are opaque
+                watch: false,
+            },
+        }
+    }
+
+    /// Absolute URL for an encoded relative path
+    async fn url(&self, path: &str) -> String {
+        let config = self.config.read().await;
+        format!(
+            "{}/{}",
+            config.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// Build authenticated request
+    async fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let url = self.url(path).await;
+        let config = self.config.read().await;
+
+        let mut request = self.http.request(method, &url);
+
+        match &config.auth {
+            WebDavAuth::None => {}
+            WebDavAuth::Basic { username, password } => {
+                request = request.basic_auth(username, Some(password));
+            }
+            WebDavAuth::Bearer(token) => {
+                request = request.bearer_auth(token);
+            }
+            WebDavAuth::Digest { username, password } => {
+                // reqwest handles digest auth automatically
+                request = request.basic_auth(username, Some(password));
+            }
+        }
+
+        for (key, value) in &config.headers {
+            request = request.header(key, value);
+        }
+
+        request
+    }
+
+    /// Send a request and map error statuses
+    async fn send(&self, request: reqwest::RequestBuilder) -> CfkResult<reqwest::Response> {
+        let response = request.send().await.map_err(http::network_error)?;
+        http::check_response("webdav", response).await
+    }
+
+    /// Convert VirtualPath to URL path
+    fn to_url_path(&self, path: &VirtualPath) -> String {
+        if path.segments.is_empty() {
+            String::new()
+        } else {
+            path.segments
+                .iter()
+                .map(|s| urlencoding::encode(s).to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        }
+    }
+
+    /// Decoded path component of the base URL, used to relativise hrefs
+    async fn base_path(&self) -> String {
+        let config = self.config.read().await;
+        let path = href_path(&config.base_url);
+        decode(path).trim_end_matches('/').to_string()
+    }
+
+    /// PROPFIND request for listing/metadata
+    async fn propfind(&self, path: &VirtualPath, depth: &str) -> CfkResult<Vec<DavResponse>> {
+        let response = self
+            .send(
+                self.request(dav_method("PROPFIND"), &self.to_url_path(path))
+                    .await
+                    .header("Depth", depth)
+                    .header(header::CONTENT_TYPE, "application/xml")
+                    .body(PROPFIND_BODY),
+            )
+            .await
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+                e => e,
+            })?;
+
+        let text = response.text().await.map_err(http::network_error)?;
+
+        Ok(parse_multistatus(&text))
+    }
+
+    /// Convert a PROPFIND response into an entry
+    fn to_entry(&self, resp: &DavResponse, base_path: &str) -> Entry {
+        // Extract relative path from href
+        let href = decode(href_path(&resp.href));
+        let relative_path = href
+            .strip_prefix(base_path)
+            .unwrap_or(&href)
+            .trim_matches('/');
+
+        let metadata = Metadata {
+            size: resp.content_length,
+            modified: resp.last_modified,
+            created: resp.creation_date,
+            revision: resp.etag.clone(),
+            mime_type: resp.content_type.clone(),
+            ..Default::default()
+        };
+
+        let path = VirtualPath::new(&self.id, relative_path);
+        if resp.is_collection {
+            Entry::directory(path, metadata)
+        } else {
+            Entry::file(path, metadata)
+        }
+    }
+
+    /// Children of a collection (the collection itself is dropped)
+    async fn children(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        let base_path = self.base_path().await;
+        let responses = self.propfind(path, "1").await?;
+
+        Ok(responses
+            .iter()
+            .map(|r| self.to_entry(r, &base_path))
+            .filter(|e| e.path.segments != path.segments)
+            .collect())
+    }
+
+    /// MKCOL every missing ancestor of `path`
+    async fn ensure_parents(&self, path: &VirtualPath) -> CfkResult<()> {
+        let mut missing = Vec::new();
+        let mut current = path.parent();
+
+        while let Some(dir) = current {
+            if dir.is_root() {
+                break;
+            }
+            match self.propfind(&dir, "0").await {
+                Ok(_) => break,
+                Err(CfkError::NotFound(_)) => {
+                    current = dir.parent();
+                    missing.push(dir);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        for dir in missing.into_iter().rev() {
+            match self.create_directory(&dir).await {
+                Ok(_) | Err(CfkError::AlreadyExists(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// PUT a body, honouring `overwrite` and `create_parents`
+    async fn put(
+        &self,
+        path: &VirtualPath,
+        body: Body,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        if options.create_parents {
+            self.ensure_parents(path).await?;
+        }
+
+        let mut request = self
+            .request(Method::PUT, &self.to_url_path(path))
+            .await
+            .header(header::CONTENT_TYPE, "application/octet-stream");
+        if !options.overwrite {
+            request = request.header(header::IF_NONE_MATCH, "*");
+        }
+
+        // WebDAV has no standard content hash, so `content_hash` is not checked
+        self.send(request.body(body)).await.map_err(|e| match e {
+            CfkError::Conflict(_) if !options.overwrite => {
+                CfkError::AlreadyExists(path.to_string())
+            }
+            CfkError::Conflict(_) => CfkError::NotFound(format!("parent of {}", path)),
+            e => e,
+        })?;
+
+        self.get_metadata(path).await
+    }
+
+    /// COPY or MOVE to `dest`
+    async fn transfer(
+        &self,
+        method: &'static str,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        overwrite: bool,
+    ) -> CfkResult<Entry> {
+        let dest_url = self.url(&self.to_url_path(dest)).await;
+
+        self.send(
+            self.request(dav_method(method), &self.to_url_path(source))
+                .await
+                .header("Destination", dest_url)
+                .header("Overwrite", if overwrite { "T" } else { "F" }),
+        )
+        .await
+        .map_err(|e| match e {
+            // 412: destination exists and Overwrite is F
+            CfkError::Conflict(_) if !overwrite => CfkError::AlreadyExists(dest.to_string()),
+            CfkError::NotFound(_) => CfkError::NotFound(source.to_string()),
+            e => e,
+        })?;
+
+        self.get_metadata(dest).await
+    }
+}
+
+/// Extension methods are valid tokens, so this cannot fail
+fn dav_method(name: &'static str) -> Method {
+    Method::from_bytes(name.as_bytes()).expect("valid HTTP method")
+}
+
+/// Strip scheme and authority from an href, leaving the path
+fn href_path(href: &str) -> &str {
+    match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|i| &rest[i..]).unwrap_or("/"),
+        None => href,
+    }
+}
+
+/// Percent-decode, falling back to the raw input
+fn decode(s: &str) -> String {
+    urlencoding::decode(s)
+        .map(|d| d.into_owned())
+        .unwrap_or_else(|_| s.to_string())
+}
+
+/// DAV response from PROPFIND
+#[derive(Debug, Clone, Default)]
+struct DavResponse {
+    href: String,
+    is_collection: bool,
+    content_length: Option<u64>,
+    last_modified: Option<DateTime<Utc>>,
+    creation_date: Option<DateTime<Utc>>,
+    etag: Option<String>,
+    content_type: Option<String>,
+}
+
+/// Parse WebDAV multistatus XML response.
+///
+/// Matching is by local name, so any namespace prefix (`d:`, `D:`, `lp1:`,
+/// none) and any line layout is accepted. Properties inside a propstat with a
+/// non-2xx status are ignored.
+fn parse_multistatus(xml: &str) -> Vec<DavResponse> {
+    let mut responses = Vec::new();
+
+    for block in elements(xml, "response") {
+        let mut resp = DavResponse {
+            href: first_text(block, "href").unwrap_or_default(),
+            ..Default::default()
+        };
+
+        for propstat in elements(block, "propstat") {
+            let ok = first_text(propstat, "status")
+                .map(|s| {
+                    s.split_whitespace()
+                        .nth(1)
+                        .is_some_and(|c| c.starts_with('2'))
+                })
+                .unwrap_or(true);
+            if !ok {
+                continue;
+            }
+
+            if let Some(rt) = elements(propstat, "resourcetype").first() {
+                resp.is_collection = !elements(rt, "collection").is_empty();
+            }
+            if let Some(len) = first_text(propstat, "getcontentlength") {
+                resp.content_length = len.parse().ok();
+            }
+            if let Some(modified) = first_text(propstat, "getlastmodified") {
+                resp.last_modified = parse_http_date(&modified);
+            }
+            if let Some(created) = first_text(propstat, "creationdate") {
+                resp.creation_date = parse_http_date(&created);
+            }
+            if let Some(etag) = first_text(propstat, "getetag") {
+                let etag = etag.trim_start_matches("W/").trim_matches('"');
+                resp.etag = Some(etag.to_string());
+            }
+            if let Some(ct) = first_text(propstat, "getcontenttype") {
+                resp.content_type = Some(ct);
+            }
+        }
+
+        // A response-level 404 means the resource is gone
+        let gone = elements(block, "propstat").is_empty()
+            && first_text(block, "status").is_some_and(|s| s.contains(" 404"));
+        if !gone {
+            responses.push(resp);
+        }
+    }
+
+    responses
+}
+
+/// Inner XML of every element with the given local name.
+///
+/// Self-closing elements yield an empty string.
+fn elements<'a>(xml: &'a str, local: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = xml[pos..].find('<') {
+        let start = pos + offset + 1;
+        let Some(tag_len) = xml[start..].find('>') else {
+            break;
+        };
+        let tag = &xml[start..start + tag_len];
+        let after_tag = start + tag_len + 1;
+        pos = after_tag;
+
+        if tag.starts_with(['/', '?', '!']) {
+            continue;
+        }
+
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let name_local = name.rsplit(':').next().unwrap_or(name);
+        if name_local != local {
+            continue;
+        }
+
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+
+        let close = format!("</{}>", name);
+        if let Some(end) = xml[after_tag..].find(&close) {
+            found.push(&xml[after_tag..after_tag + end]);
+            pos = after_tag + end + close.len();
+        }
+    }
+
+    found
+}
+
+/// Trimmed, entity-decoded text of the first element with the local name
+fn first_text(xml: &str, local: &str) -> Option<String> {
+    elements(xml, local).first().map(|text| {
+        text.trim()
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    })
+}
+
+/// Parse HTTP date format
+fn parse_http_date(s: &str) -> Option<DateTime<Utc>> {
+    // Try RFC 2822 first (most common)
+    if let Ok(dt) = DateTime::parse_from_rfc2822(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    // Try RFC 3339
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    // Try common HTTP date format
+    let formats = [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ];
+
+    for fmt in &formats {
+        if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(s, fmt) {
+            return Some(DateTime::from_naive_utc_and_offset(dt, Utc));
+        }
+    }
+
+    None
+}
+
+#[async_trait]
+impl StorageBackend for WebDavBackend {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn display_name(&self) -> &str {
+        "WebDAV"
+    }
+
+    fn capabilities(&self) -> &StorageCapabilities {
+        &self.capabilities
+    }
+
+    async fn is_available(&self) -> bool {
+        self.propfind(&VirtualPath::root(&self.id), "0")
+            .await
+            .is_ok()
+    }
+
+    async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        let base_path = self.base_path().await;
+        let responses = self.propfind(path, "0").await?;
+
+        responses
+            .first()
+            .map(|r| {
+                // Servers may echo a differently-encoded href; trust the request path
+                let mut entry = self.to_entry(r, &base_path);
+                entry.path = path.clone();
+                entry
+            })
+            .ok_or_else(|| CfkError::NotFound(path.to_string()))
+    }
+
+    /// WebDAV has no paging, so the cursor is an offset into the full
+    /// listing. `recursive` walks with `Depth: 1` since many servers refuse
+    /// `Depth: infinity`.
+    async fn list_directory(
+        &self,
+        path: &VirtualPath,
+        options: &ListOptions,
+    ) -> CfkResult<DirectoryListing> {
+        let mut entries = Vec::new();
+        let mut pending = vec![path.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let children = self.children(&dir).await?;
+            if options.recursive {
+                pending.extend(
+                    children
+                        .iter()
+                        .filter(|e| e.is_directory())
+                        .filter(|e| {
+                            options.include_hidden
+                                || !e.path.name().is_some_and(|n| n.starts_with('.'))
+                        })
+                        .map(|e| e.path.clone()),
+                );
+            }
+            entries.extend(children);
+        }
+
+        Ok(common::page_entries(path, entries, options))
+    }
+
+    async fn read_file(&self, path: &VirtualPath, options: &ReadOptions) -> CfkResult<ByteStream> {
+        let mut request = self.request(Method::GET, &self.to_url_path(path)).await;
+        if let Some(range) = options.range {
+            request = request.header(reqwest::header::RANGE, http::range_header(range));
+        }
+        let response = request.send().await.map_err(http::network_error)?;
+
+        http::ranged_stream("webdav", response, options.range)
+            .await
+            .map_err(|e| match e {
+                CfkError::NotFound(_) => CfkError::NotFound(path.to_string()),
+                e => e,
+            })
+    }
+
+    async fn write_file(
+        &self,
+        path: &VirtualPath,
+        data: Bytes,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.put(path, Body::from(data), options).await
+    }
+
+    async fn write_file_stream(
+        &self,
+        path: &VirtualPath,
+        stream: ByteStream,
+        _size_hint: Option<u64>,
+        options: &WriteOptions,
+    ) -> CfkResult<Entry> {
+        self.put(path, Body::wrap_stream(stream), options).await
+    }
+
+    async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+        if path.is_root() {
+            return Err(CfkError::PermissionDenied(path.to_string()));
+        }
+
+        // DELETE on a collection is always recursive (RFC 4918 §9.6.1)
+        common::check_delete(self, path, options).await?;
+
+        let result = self
+            .send(self.request(Method::DELETE, &self.to_url_path(path)).await)
+            .await
+            .map(|_| ());
+        common::ignore_missing(result, options)
+    }
+
+    async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        self.send(
+            self.request(dav_method("MKCOL"), &self.to_url_path(path))
+                .await,
+        )
+        .await
+        .map_err(|e| match e {
+            // 405: the resource already exists
+            CfkError::ProviderApi { message, .. } if message.starts_with("405") => {
+                CfkError::AlreadyExists(path.to_string())
+            }
+            // 409: an intermediate collection is missing
+            CfkError::Conflict(_) => CfkError::NotFound(format!("parent of {}", path)),
+            e => e,
+        })?;
+
+        Ok(Entry::directory(path.clone(), Metadata::default()))
+    }
+
+    async fn copy(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &CopyOptions,
+    ) -> CfkResult<Entry> {
+        self.transfer("COPY", source, dest, options.overwrite).await
+    }
+
+    async fn rename(
+        &self,
+        source: &VirtualPath,
+        dest: &VirtualPath,
+        options: &MoveOptions,
+    ) -> CfkResult<Entry> {
+        self.transfer("MOVE", source, dest, options.overwrite).await
+    }
+
+    async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+        // WebDAV quota requires RFC 4331 support
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:quota-available-bytes/>
+    <d:quota-used-bytes/>
+  </d:prop>
+</d:propfind>"#;
+
+        let response = self
+            .send(
+                self.request(dav_method("PROPFIND"), "")
+                    .await
+                    .header("Depth", "0")
+                    .header(header::CONTENT_TYPE, "application/xml")
+                    .body(body),
+            )
+            .await?;
+
+        let text = response.text().await.map_err(http::network_error)?;
+
+        // Negative values mean "unknown" / "unlimited"
+        let available = first_text(&text, "quota-available-bytes").and_then(|s| s.parse().ok());
+        let used = first_text(&text, "quota-used-bytes").and_then(|s| s.parse().ok());
+
+        Ok(SpaceInfo {
+            total: available.zip(used).map(|(a, u): (u64, u64)| a + u),
+            used,
+            available,
+        })
+    }
+}
+
+/// NextCloud-specific extensions
+impl WebDavBackend {
+    /// Create a NextCloud backend with standard configuration
+    pub fn nextcloud(
+        id: impl Into<String>,
+        server_url: &str,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        let base_url = format!(
+            "{}/remote.php/dav/files/{}",
+            server_url.trim_end_matches('/'),
+            username
+        );
+
+        Self::new(
+            id,
+            WebDavConfig {
+                base_url,
+                auth: WebDavAuth::Basic {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                },
+                headers: vec![],
+            },
+        )
+    }
+
+    /// Create an ownCloud backend
+    pub fn owncloud(
+        id: impl Into<String>,
+        server_url: &str,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        let base_url = format!("{}/remote.php/webdav", server_url.trim_end_matches('/'));
+
+        Self::new(
+            id,
+            WebDavConfig {
+                base_url,
+                auth: WebDavAuth::Basic {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                },
+                headers: vec![],
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header as header_eq, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn backend(server: &MockServer) -> WebDavBackend {
+        WebDavBackend::new(
+            "dav",
+            WebDavConfig {
+                base_url: format!("{}/remote.php/webdav", server.uri()),
+                auth: WebDavAuth::Basic {
+                    username: "u".into(),
+                    password: "p".into(),
+                },
+                headers: vec![],
+            },
+        )
+    }
+
+    #[test]
+    fn test_parse_multistatus_any_prefix_single_line() {
+        let xml = r#"<?xml version="1.0"?><D:multistatus xmlns:D="DAV:"><D:response><D:href>/dav/My%20Docs/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response><D:response><D:href>http://host/dav/My%20Docs/a.txt</D:href><D:propstat><D:prop><D:resourcetype/><D:getcontentlength>12</D:getcontentlength><D:getetag>"abc"</D:getetag><D:getlastmodified>Mon, 01 Jan 2024 00:00:00 GMT</D:getlastmodified></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat><D:propstat><D:prop><D:getcontenttype/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status></D:propstat></D:response></D:multistatus>"#;
+
+        let responses = parse_multistatus(xml);
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].is_collection);
+        assert!(!responses[1].is_collection);
+        assert_eq!(responses[1].content_length, Some(12));
+        assert_eq!(responses[1].etag.as_deref(), Some("abc"));
+        assert!(responses[1].last_modified.is_some());
+        assert_eq!(href_path(&responses[1].href), "/dav/My%20Docs/a.txt");
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_depth_one_with_offset_cursor() {
+        let server = MockServer::start().await;
+        Mock::given(method("PROPFIND"))
+            .and(path("/remote.php/webdav/docs"))
+            .and(header_eq("Depth", "1"))
+            .respond_with(ResponseTemplate::new(207).set_body_string(
+                r#"<d:multistatus xmlns:d="DAV:">
+                <d:response><d:href>/remote.php/webdav/docs/</d:href>
+                  <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+                <d:response><d:href>/remote.php/webdav/docs/a.txt</d:href>
+                  <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>1</d:getcontentlength></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+                <d:response><d:href>/remote.php/webdav/docs/b%20c.txt</d:href>
+                  <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>2</d:getcontentlength></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+                </d:multistatus>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let options = ListOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let listing = backend
+            .list_directory(&VirtualPath::new("dav", "docs"), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].path.to_path_string(), "/docs/a.txt");
+        assert!(listing.has_more);
+
+        let options = ListOptions {
+            cursor: listing.cursor,
+            ..options
+        };
+        let listing = backend
+            .list_directory(&VirtualPath::new("dav", "docs"), &options)
+            .await
+            .unwrap();
+        assert_eq!(listing.entries[0].path.to_path_string(), "/docs/b c.txt");
+        assert!(!listing.has_more);
+    }
+
+    #[tokio::test]
+    async fn test_write_without_overwrite_maps_412() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/remote.php/webdav/a.txt"))
+            .and(header_eq("If-None-Match", "*"))
+            .respond_with(ResponseTemplate::new(412))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let err = backend
+            .write_file(
+                &VirtualPath::new("dav", "a.txt"),
+                Bytes::from_static(b"x"),
+                &WriteOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CfkError::AlreadyExists(_)));
+    }
+
+    #[tokio::test]
+    async fn test_space_info_from_quota_properties() {
+        let server = MockServer::start().await;
+        Mock::given(method("PROPFIND"))
+            .and(path("/remote.php/webdav/"))
+            .respond_with(ResponseTemplate::new(207).set_body_string(
+                r#"<d:multistatus xmlns:d="DAV:"><d:response><d:href>/remote.php/webdav/</d:href>
+                <d:propstat><d:prop><d:quota-available-bytes>700</d:quota-available-bytes>
+                <d:quota-used-bytes>300</d:quota-used-bytes></d:prop>
+                <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let space = backend.get_space_info().await.unwrap();
+        assert_eq!(space.total, Some(1000));
+        assert_eq!(space.used, Some(300));
+    }
+
+    #[tokio::test]
+    async fn test_range_ignored_by_server_is_applied_locally() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/remote.php/webdav/a.txt"))
+            .and(header_eq("Range", "bytes=2-4"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("abcdefgh"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/remote.php/webdav/a.txt"))
+            .and(header_eq("Range", "bytes=100-109"))
+            .respond_with(ResponseTemplate::new(416))
+            .mount(&server)
+            .await;
+
+        let backend = backend(&server);
+        let file = VirtualPath::new("dav", "a.txt");
+        let read = |range| {
+            let backend = &backend;
+            let file = &file;
+            async move {
+                let options = ReadOptions {
+                    range: Some(range),
+                    ..Default::default()
+                };
+                let stream = backend.read_file(file, &options).await.unwrap();
+                common::collect_stream(stream).await.unwrap()
+            }
+        };
+        assert_eq!(&read((2, 5)).await[..], b"cde");
+        assert!(read((100, 110)).await.is_empty());
+    }
+}
@@ -0,0 +1,1566 @@
+//! WebDAV server exporting a [`StorageBackend`]
+//!
+//! Class 1 and 2 (RFC 4918): PROPFIND at depth 0 and 1, GET with ranges,
+//! PUT, MKCOL, COPY, MOVE, DELETE, and LOCK/UNLOCK, so file managers and
+//! office suites can open files on remotes that do not speak WebDAV.
+//!
+//! Locks live in memory and are only enforced against clients of this
+//! server. Dead properties are not stored.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use cfk_core::{
+    backend::SpaceInfo,
+    operations::*,
+    CfkError, CfkResult, Entry, Metadata, StorageBackend, VirtualPath,
+};
+use chrono::SecondsFormat;
+use hyper::body::Incoming;
+use hyper::header::{self, HeaderMap};
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+use super::{decode, elements, first_text, href_path, parse_http_date};
+use crate::http_server::{
+    self, body_stream, constant_time_eq, empty, full, header_str, http_date, parse_range,
+    read_body, set, status, stream_body, Body,
+};
+
+/// Largest PROPFIND, PROPPATCH or LOCK body read
+const MAX_XML_BODY: usize = 1024 * 1024;
+/// Lock lifetime when the client asks for none, and the most it gets
+const LOCK_TIMEOUT: u64 = 3600;
+const MAX_LOCK_TIMEOUT: u64 = 7 * 24 * 3600;
+
+/// Namespace of the Win32 properties Windows sets after every upload
+const MS_NAMESPACE: &str = "urn:schemas-microsoft-com:";
+
+/// Live properties returned for `allprop`
+const ALLPROP: &[&str] = &[
+    "resourcetype",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getlastmodified",
+    "creationdate",
+    "getetag",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+/// Options for [`WebDavServer`]
+#[derive(Debug, Clone, Default)]
+pub struct WebDavServerOptions {
+    /// Answer every change with 403
+    pub read_only: bool,
+    /// Username and password required as HTTP basic auth
+    pub credentials: Option<(String, String)>,
+}
+
+/// A WebDAV server for one backend directory
+pub struct WebDavServer {
+    backend: Arc<dyn StorageBackend>,
+    root: VirtualPath,
+    options: WebDavServerOptions,
+    /// Active locks by path below the root
+    locks: Mutex<HashMap<Vec<String>, Vec<Lock>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Lock {
+    token: String,
+    exclusive: bool,
+    /// Depth infinity: the lock covers everything below
+    infinite: bool,
+    /// The lock owner as the client gave it, as text
+    owner: Option<String>,
+    timeout: u64,
+    expires: Instant,
+}
+
+/// A property name from a PROPFIND or PROPPATCH body
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PropName {
+    namespace: String,
+    local: String,
+}
+
+impl PropName {
+    fn is_dav(&self) -> bool {
+        self.namespace == "DAV:"
+    }
+
+    /// An empty element for the name, valid inside a `D:`-prefixed document
+    fn empty_element(&self) -> String {
+        if self.is_dav() {
+            format!("<D:{}/>", self.local)
+        } else {
+            format!(
+                "<N:{} xmlns:N=\"{}\"/>",
+                self.local,
+                escape(&self.namespace)
+            )
+        }
+    }
+}
+
+enum PropRequest {
+    All,
+    Names,
+    Props(Vec<PropName>),
+}
+
+impl WebDavServer {
+    /// Serve `root` and everything below it
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        root: VirtualPath,
+        options: WebDavServerOptions,
+    ) -> Self {
+        Self {
+            backend,
+            root,
+            options,
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accept clients until the listener fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> CfkResult<()> {
+        http_server::serve(listener, "webdav", move |request| {
+            let server = self.clone();
+            async move { server.handle(request).await }
+        })
+        .await
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<Body> {
+        let method = request.method().clone();
+        let uri = request.uri().path().to_string();
+
+        if !self.authorized(request.headers()) {
+            let mut response = status(StatusCode::UNAUTHORIZED);
+            set(
+                &mut response,
+                header::WWW_AUTHENTICATE,
+                "Basic realm=\"cfk\"",
+            );
+            return response;
+        }
+
+        let response = self.dispatch(request).await.unwrap_or_else(|e| {
+            tracing::debug!("webdav {} {}: {}", method, uri, e);
+            status(status_of(&e))
+        });
+        tracing::debug!("webdav {} {} -> {}", method, uri, response.status());
+        response
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some((user, password)) = &self.options.credentials else {
+            return true;
+        };
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| BASE64.decode(v.trim()).ok())
+            .unwrap_or_default();
+        let expected = format!("{}:{}", user, password);
+        constant_time_eq(&given, expected.as_bytes())
+    }
+
+    async fn dispatch(&self, request: Request<Incoming>) -> CfkResult<Response<Body>> {
+        let path = self.resolve(request.uri().path())?;
+        let method = request.method().as_str().to_string();
+
+        let writes = matches!(
+            method.as_str(),
+            "PUT" | "MKCOL" | "DELETE" | "COPY" | "MOVE" | "PROPPATCH" | "LOCK" | "UNLOCK"
+        );
+        if writes && self.options.read_only {
+            return Ok(status(StatusCode::FORBIDDEN));
+        }
+
+        match method.as_str() {
+            "OPTIONS" => {
+                let mut response = status(StatusCode::OK);
+                set(&mut response, "DAV", "1, 2");
+                set(&mut response, "MS-Author-Via", "DAV");
+                let allow = if self.options.read_only {
+                    "OPTIONS, GET, HEAD, PROPFIND"
+                } else {
+                    "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND, PROPPATCH, LOCK, UNLOCK"
+                };
+                set(&mut response, header::ALLOW, allow);
+                Ok(response)
+            }
+            "GET" => self.get(&request, &path, false).await,
+            "HEAD" => self.get(&request, &path, true).await,
+            "PROPFIND" => self.propfind(request, &path).await,
+            "PROPPATCH" => self.proppatch(request, &path).await,
+            "PUT" => self.put(request, &path).await,
+            "MKCOL" => self.mkcol(request, &path).await,
+            "DELETE" => self.delete(&request, &path).await,
+            "COPY" => self.copy_or_move(&request, &path, false).await,
+            "MOVE" => self.copy_or_move(&request, &path, true).await,
+            "LOCK" => self.lock(request, &path).await,
+            "UNLOCK" => self.unlock(&request, &path),
+            _ => Ok(status(StatusCode::METHOD_NOT_ALLOWED)),
+        }
+    }
+
+    /// The backend path for a request path
+    fn resolve(&self, uri_path: &str) -> CfkResult<VirtualPath> {
+        let mut path = self.root.clone();
+        for segment in uri_path.split('/').filter(|s| !s.is_empty()) {
+            let segment = decode(segment);
+            // Never above the export
+            if segment == "." || segment == ".." || segment.contains('/') {
+                return Err(CfkError::InvalidPath(uri_path.to_string()));
+            }
+            path = path.join(segment);
+        }
+        Ok(path)
+    }
+
+    /// Path below the root, as locks are keyed
+    fn relative<'a>(&self, path: &'a VirtualPath) -> &'a [String] {
+        &path.segments[self.root.segments.len().min(path.segments.len())..]
+    }
+
+    fn href(&self, path: &VirtualPath, collection: bool) -> String {
+        let mut href = String::new();
+        for segment in self.relative(path) {
+            href.push('/');
+            href.push_str(&urlencoding::encode(segment));
+        }
+        if collection || href.is_empty() {
+            href.push('/');
+        }
+        href
+    }
+
+    /// Metadata of `path`; the export root is a collection whatever the
+    /// backend says about it
+    async fn stat(&self, path: &VirtualPath) -> CfkResult<Entry> {
+        match self.backend.get_metadata(path).await {
+            Err(_) if *path == self.root => Ok(Entry::directory(path.clone(), Metadata::default())),
+            result => result,
+        }
+    }
+
+    /// Whether `path` exists, other errors passed on
+    async fn exists(&self, path: &VirtualPath) -> CfkResult<Option<Entry>> {
+        match self.stat(path).await {
+            Ok(entry) => Ok(Some(entry)),
+            Err(CfkError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// RFC 4918 wants 409 when the parent collection is missing
+    async fn parent_exists(&self, path: &VirtualPath) -> CfkResult<bool> {
+        match path.parent() {
+            Some(parent) if path.segments.len() > self.root.segments.len() => Ok(self
+                .exists(&parent)
+                .await?
+                .is_some_and(|e| e.is_directory())),
+            _ => Ok(false),
+        }
+    }
+
+    async fn get(
+        &self,
+        request: &Request<Incoming>,
+        path: &VirtualPath,
+        head: bool,
+    ) -> CfkResult<Response<Body>> {
+        let entry = self.stat(path).await?;
+        if entry.is_directory() {
+            return self.index(path, head).await;
+        }
+
+        let size = entry.size();
+        let range = request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+            .zip(size)
+            .and_then(|(range, size)| parse_range(range, size));
+        let (code, range) = match range {
+            Some(Ok(range)) => (StatusCode::PARTIAL_CONTENT, Some(range)),
+            Some(Err(())) => {
+                let mut response = status(StatusCode::RANGE_NOT_SATISFIABLE);
+                set(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    format!("bytes */{}", size.unwrap_or(0)),
+                );
+                return Ok(response);
+            }
+            None => (StatusCode::OK, None),
+        };
+
+        let body = if head {
+            empty()
+        } else {
+            let options = ReadOptions {
+                range,
+                ..Default::default()
+            };
+            stream_body(self.backend.read_file(path, &options).await?)
+        };
+        let mut response = Response::new(body);
+        *response.status_mut() = code;
+        let content_type = entry
+            .metadata
+            .mime_type
+            .as_deref()
+            .unwrap_or("application/octet-stream");
+        set(&mut response, header::CONTENT_TYPE, content_type);
+        set(&mut response, header::ACCEPT_RANGES, "bytes");
+        set(&mut response, header::ETAG, etag(&entry));
+        if let Some(modified) = entry.metadata.modified {
+            set(&mut response, header::LAST_MODIFIED, http_date(modified));
+        }
+        match (range, size) {
+            (Some((start, end)), Some(size)) => {
+                set(
+                    &mut response,
+                    header::CONTENT_LENGTH,
+                    (end - start).to_string(),
+                );
+                set(
+                    &mut response,
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end - 1, size),
+                );
+            }
+            (None, Some(size)) => set(&mut response, header::CONTENT_LENGTH, size.to_string()),
+            _ => {}
+        }
+        Ok(response)
+    }
+
+    /// A plain HTML listing, for browsers pointed at a collection
+    async fn index(&self, path: &VirtualPath, head: bool) -> CfkResult<Response<Body>> {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n<ul>\n",
+            escape(&self.href(path, true))
+        );
+        if path.segments.len() > self.root.segments.len() {
+            html.push_str("<li><a href=\"../\">../</a></li>\n");
+        }
+        for entry in self.children(path).await? {
+            let name = entry.name().unwrap_or_default();
+            let slash = if entry.is_directory() { "/" } else { "" };
+            html.push_str(&format!(
+                "<li><a href=\"{}{}\">{}{}</a></li>\n",
+                urlencoding::encode(name),
+                slash,
+                escape(name),
+                slash
+            ));
+        }
+        html.push_str("</ul>\n</body></html>\n");
+
+        let length = html.len();
+        let mut response = Response::new(if head { empty() } else { full(html) });
+        set(
+            &mut response,
+            header::CONTENT_TYPE,
+            "text/html; charset=utf-8",
+        );
+        set(&mut response, header::CONTENT_LENGTH, length.to_string());
+        Ok(response)
+    }
+
+    async fn children(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        let mut options = ListOptions {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let mut entries = Vec::new();
+        loop {
+            let listing = self.backend.list_directory(path, &options).await?;
+            entries.extend(listing.entries);
+            match listing.cursor {
+                Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                _ => return Ok(entries),
+            }
+        }
+    }
+
+    async fn propfind(
+        &self,
+        request: Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        let depth = match header_str(request.headers(), "Depth") {
+            Some("0") => 0,
+            Some("1") => 1,
+            // Infinity, also the default: a listing of the whole remote
+            _ => {
+                let mut response = xml_response(
+                    StatusCode::FORBIDDEN,
+                    "<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>".to_string(),
+                );
+                set(&mut response, "DAV", "1, 2");
+                return Ok(response);
+            }
+        };
+        let body = read_xml(request.into_body()).await?;
+        let wanted = parse_propfind(&body);
+
+        let entry = self.stat(path).await?;
+        let mut entries = Vec::new();
+        if depth == 1 && entry.is_directory() {
+            entries = self.children(path).await?;
+        }
+        entries.insert(0, entry);
+
+        let quota = match &wanted {
+            PropRequest::Props(names) => names
+                .iter()
+                .any(|n| n.is_dav() && n.local.starts_with("quota-")),
+            _ => false,
+        };
+        let space = if quota {
+            self.backend.get_space_info().await.ok()
+        } else {
+            None
+        };
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">",
+        );
+        for entry in &entries {
+            xml.push_str("<D:response><D:href>");
+            xml.push_str(&escape(&self.href(&entry.path, entry.is_directory())));
+            xml.push_str("</D:href>");
+            let (found, missing) = match &wanted {
+                PropRequest::Names => {
+                    let names = ALLPROP.iter().map(|n| format!("<D:{}/>", n)).collect();
+                    (names, Vec::new())
+                }
+                PropRequest::All => {
+                    let found = ALLPROP
+                        .iter()
+                        .filter_map(|name| self.property(entry, name, space.as_ref()))
+                        .collect();
+                    (found, Vec::new())
+                }
+                PropRequest::Props(names) => {
+                    let mut found = Vec::new();
+                    let mut missing = Vec::new();
+                    for name in names {
+                        let value = if name.is_dav() {
+                            self.property(entry, &name.local, space.as_ref())
+                        } else {
+                            None
+                        };
+                        match value {
+                            Some(value) => found.push(value),
+                            None => missing.push(name.empty_element()),
+                        }
+                    }
+                    (found, missing)
+                }
+            };
+            if !found.is_empty() || missing.is_empty() {
+                xml.push_str(&propstat(&found.concat(), "200 OK"));
+            }
+            if !missing.is_empty() {
+                xml.push_str(&propstat(&missing.concat(), "404 Not Found"));
+            }
+            xml.push_str("</D:response>");
+        }
+        xml.push_str("</D:multistatus>\n");
+        Ok(xml_response(StatusCode::MULTI_STATUS, xml))
+    }
+
+    /// A DAV: property of `entry` as XML, if it has it
+    fn property(&self, entry: &Entry, name: &str, space: Option<&SpaceInfo>) -> Option<String> {
+        let directory = entry.is_directory();
+        let value = match name {
+            "resourcetype" if directory => "<D:collection/>".to_string(),
+            "resourcetype" => String::new(),
+            "displayname" => escape(entry.name().unwrap_or_default()),
+            "getcontentlength" if !directory => entry.size().unwrap_or(0).to_string(),
+            "getcontenttype" if !directory => escape(
+                entry
+                    .metadata
+                    .mime_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+            ),
+            "getlastmodified" => http_date(entry.metadata.modified?),
+            "creationdate" => entry
+                .metadata
+                .created
+                .or(entry.metadata.modified)?
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            "getetag" if !directory => escape(&etag(entry)),
+            "supportedlock" => concat!(
+                "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>",
+                "<D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>"
+            )
+            .to_string(),
+            "lockdiscovery" => self
+                .locks_on(self.relative(&entry.path))
+                .iter()
+                .map(|(root, lock)| self.active_lock(root, lock))
+                .collect(),
+            "quota-available-bytes" if directory => space?.available?.to_string(),
+            "quota-used-bytes" if directory => space?.used?.to_string(),
+            _ => return None,
+        };
+        Some(if value.is_empty() {
+            format!("<D:{}/>", name)
+        } else {
+            format!("<D:{0}>{1}</D:{0}>", name, value)
+        })
+    }
+
+    /// Dead properties are not kept, so everything is refused except the
+    /// Win32 times and attributes Windows sets after each upload, which
+    /// fail its copies if refused. Of those, the modification time is
+    /// applied where the backend keeps one.
+    async fn proppatch(
+        &self,
+        request: Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        if let Some(locked) = self.check_locks(request.headers(), path, false) {
+            return Ok(locked);
+        }
+        let entry = self.stat(path).await?;
+        let body = read_xml(request.into_body()).await?;
+        let namespaces = namespaces(&body);
+
+        let mut accepted = Vec::new();
+        let mut refused = Vec::new();
+        for prop in elements(&body, "prop") {
+            for (qualified, value) in child_elements(prop) {
+                let name = prop_name(qualified, &namespaces);
+                if name.namespace != MS_NAMESPACE {
+                    refused.push(name.empty_element());
+                    continue;
+                }
+                if name.local == "Win32LastModifiedTime" {
+                    let modified = parse_http_date(value.trim());
+                    if let Some(modified) = modified {
+                        let metadata = Metadata {
+                            modified: Some(modified),
+                            ..Default::default()
+                        };
+                        match self.backend.set_metadata(path, &metadata).await {
+                            Ok(()) | Err(CfkError::Unsupported(_)) => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
+                }
+                accepted.push(name.empty_element());
+            }
+        }
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\"><D:response><D:href>");
+        xml.push_str(&escape(&self.href(path, entry.is_directory())));
+        xml.push_str("</D:href>");
+        if !accepted.is_empty() {
+            xml.push_str(&propstat(&accepted.concat(), "200 OK"));
+        }
+        if !refused.is_empty() {
+            xml.push_str(&propstat(&refused.concat(), "403 Forbidden"));
+        }
+        xml.push_str("</D:response></D:multistatus>\n");
+        Ok(xml_response(StatusCode::MULTI_STATUS, xml))
+    }
+
+    async fn put(
+        &self,
+        request: Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        if let Some(locked) = self.check_locks(request.headers(), path, false) {
+            return Ok(locked);
+        }
+        let existed = match self.exists(path).await? {
+            Some(entry) if entry.is_directory() => {
+                return Ok(status(StatusCode::METHOD_NOT_ALLOWED))
+            }
+            Some(_) => true,
+            None if !self.parent_exists(path).await? => return Ok(status(StatusCode::CONFLICT)),
+            None => false,
+        };
+
+        let length = header_str(request.headers(), header::CONTENT_LENGTH.as_str())
+            .and_then(|v| v.parse().ok());
+        let options = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        let entry = self
+            .backend
+            .write_file_stream(path, body_stream(request.into_body()), length, &options)
+            .await?;
+
+        let mut response = status(if existed {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        });
+        set(&mut response, header::ETAG, etag(&entry));
+        Ok(response)
+    }
+
+    async fn mkcol(
+        &self,
+        request: Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        if let Some(locked) = self.check_locks(request.headers(), path, false) {
+            return Ok(locked);
+        }
+        // No MKCOL request bodies are defined
+        let body = read_body(request.into_body(), MAX_XML_BODY).await?;
+        if !body.is_empty() {
+            return Ok(status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        }
+        if self.exists(path).await?.is_some() {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+        }
+        if !self.parent_exists(path).await? {
+            return Ok(status(StatusCode::CONFLICT));
+        }
+        self.backend.create_directory(path).await?;
+        Ok(status(StatusCode::CREATED))
+    }
+
+    async fn delete(
+        &self,
+        request: &Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        if *path == self.root {
+            return Ok(status(StatusCode::FORBIDDEN));
+        }
+        if let Some(locked) = self.check_locks(request.headers(), path, true) {
+            return Ok(locked);
+        }
+        self.stat(path).await?;
+        let options = DeleteOptions {
+            recursive: true,
+            force: false,
+        };
+        self.backend.delete(path, &options).await?;
+        self.drop_locks(path);
+        Ok(status(StatusCode::NO_CONTENT))
+    }
+
+    async fn copy_or_move(
+        &self,
+        request: &Request<Incoming>,
+        from: &VirtualPath,
+        moving: bool,
+    ) -> CfkResult<Response<Body>> {
+        let Some(destination) = header_str(request.headers(), "Destination") else {
+            return Ok(status(StatusCode::BAD_REQUEST));
+        };
+        let to = self.resolve(href_path(destination))?;
+        let overwrite = header_str(request.headers(), "Overwrite") != Some("F");
+
+        let source = self.stat(from).await?;
+        // Onto itself, or into itself
+        if to.segments.starts_with(&from.segments) || (moving && *from == self.root) {
+            return Ok(status(StatusCode::FORBIDDEN));
+        }
+        if moving {
+            if let Some(locked) = self.check_locks(request.headers(), from, true) {
+                return Ok(locked);
+            }
+        }
+        if let Some(locked) = self.check_locks(request.headers(), &to, true) {
+            return Ok(locked);
+        }
+
+        let existed = self.exists(&to).await?.is_some();
+        if existed && !overwrite {
+            return Ok(status(StatusCode::PRECONDITION_FAILED));
+        }
+        if !self.parent_exists(&to).await? {
+            return Ok(status(StatusCode::CONFLICT));
+        }
+        // Overwrite means DELETE first, even replacing a file by a folder
+        if existed {
+            let options = DeleteOptions {
+                recursive: true,
+                force: false,
+            };
+            self.backend.delete(&to, &options).await?;
+            self.drop_locks(&to);
+        }
+
+        if moving {
+            let options = MoveOptions { overwrite: true };
+            self.backend.rename(from, &to, &options).await?;
+            self.drop_locks(from);
+        } else if source.is_directory() {
+            let shallow = header_str(request.headers(), "Depth") == Some("0");
+            self.copy_tree(from, &to, shallow).await?;
+        } else {
+            let options = CopyOptions {
+                overwrite: true,
+                preserve_metadata: true,
+            };
+            self.backend.copy(from, &to, &options).await?;
+        }
+
+        Ok(status(if existed {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::CREATED
+        }))
+    }
+
+    /// Copy a collection, only the collection itself if `shallow`
+    async fn copy_tree(
+        &self,
+        from: &VirtualPath,
+        to: &VirtualPath,
+        shallow: bool,
+    ) -> CfkResult<()> {
+        let options = CopyOptions {
+            overwrite: true,
+            preserve_metadata: true,
+        };
+        let mut pending = vec![(from.clone(), to.clone())];
+        while let Some((from, to)) = pending.pop() {
+            self.backend.create_directory(&to).await?;
+            if shallow {
+                break;
+            }
+            for child in self.children(&from).await? {
+                let Some(name) = child.name() else { continue };
+                let target = to.join(name);
+                if child.is_directory() {
+                    pending.push((child.path.clone(), target));
+                } else {
+                    self.backend.copy(&child.path, &target, &options).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn lock(
+        &self,
+        request: Request<Incoming>,
+        path: &VirtualPath,
+    ) -> CfkResult<Response<Body>> {
+        let headers = request.headers().clone();
+        let body = read_xml(request.into_body()).await?;
+        let relative = self.relative(path).to_vec();
+        let timeout = lock_timeout(header_str(&headers, "Timeout"));
+
+        // No body: refresh a lock the client holds
+        if body.trim().is_empty() {
+            let tokens = if_tokens(&headers);
+            let mut locks = self.locks.lock().unwrap();
+            let refreshed = locks.iter_mut().find_map(|(root, locks)| {
+                let lock = locks
+                    .iter_mut()
+                    .find(|l| tokens.contains(&l.token) && covers(root, l, &relative))?;
+                lock.timeout = timeout;
+                lock.expires = Instant::now() + Duration::from_secs(timeout);
+                Some((root.clone(), lock.clone()))
+            });
+            drop(locks);
+            return Ok(match refreshed {
+                Some((root, lock)) => self.lock_response(StatusCode::OK, &root, &lock),
+                None => status(StatusCode::PRECONDITION_FAILED),
+            });
+        }
+
+        let infinite = match header_str(&headers, "Depth") {
+            Some("0") => false,
+            Some("infinity") | None => true,
+            Some(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+        };
+        let owner = elements(&body, "owner").first().map(|owner| {
+            first_text(owner, "href").unwrap_or_else(|| strip_tags(owner).trim().to_string())
+        });
+        let lock = Lock {
+            token: new_token(),
+            exclusive: elements(&body, "shared").is_empty(),
+            infinite,
+            owner,
+            timeout,
+            expires: Instant::now() + Duration::from_secs(timeout),
+        };
+
+        {
+            let mut locks = self.locks.lock().unwrap();
+            prune(&mut locks);
+            // Held above, at, or (for a deep lock) below the resource
+            let conflict = locks.iter().any(|(root, held)| {
+                held.iter().any(|l| {
+                    let overlaps =
+                        covers(root, l, &relative) || (infinite && root.starts_with(&relative));
+                    overlaps && (l.exclusive || lock.exclusive)
+                })
+            });
+            if conflict {
+                return Ok(status(StatusCode::LOCKED));
+            }
+            locks
+                .entry(relative.clone())
+                .or_default()
+                .push(lock.clone());
+        }
+
+        // Locking an unmapped URL creates an empty resource
+        let created = match self.exists(path).await {
+            Ok(Some(_)) => Ok(false),
+            Ok(None) if self.parent_exists(path).await.unwrap_or(false) => {
+                let options = WriteOptions::default();
+                self.backend
+                    .write_file(path, Bytes::new(), &options)
+                    .await
+                    .map(|_| true)
+            }
+            Ok(None) => Err(CfkError::Conflict(path.to_string())),
+            Err(e) => Err(e),
+        };
+        let created = match created {
+            Ok(created) => created,
+            Err(e) => {
+                self.remove_lock(&relative, &lock.token);
+                return Err(e);
+            }
+        };
+
+        let code = if created {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        };
+        Ok(self.lock_response(code, &relative, &lock))
+    }
+
+    fn unlock(&self, request: &Request<Incoming>, path: &VirtualPath) -> CfkResult<Response<Body>> {
+        let Some(token) = header_str(request.headers(), "Lock-Token") else {
+            return Ok(status(StatusCode::BAD_REQUEST));
+        };
+        let token = token.trim().trim_start_matches('<').trim_end_matches('>');
+        let relative = self.relative(path);
+
+        let root = self
+            .locks_on(relative)
+            .into_iter()
+            .find(|(_, lock)| lock.token == token)
+            .map(|(root, _)| root);
+        Ok(match root {
+            Some(root) => {
+                self.remove_lock(&root, token);
+                status(StatusCode::NO_CONTENT)
+            }
+            None => status(StatusCode::CONFLICT),
+        })
+    }
+
+    /// Live locks covering `relative`, with the path each was taken on
+    fn locks_on(&self, relative: &[String]) -> Vec<(Vec<String>, Lock)> {
+        let mut locks = self.locks.lock().unwrap();
+        prune(&mut locks);
+        locks
+            .iter()
+            .flat_map(|(root, held)| {
+                held.iter()
+                    .filter(|l| covers(root, l, relative))
+                    .map(|l| (root.clone(), l.clone()))
+            })
+            .collect()
+    }
+
+    /// A 423 response unless the request holds every lock on `path`, and
+    /// with `deep` every lock below it
+    fn check_locks(
+        &self,
+        headers: &HeaderMap,
+        path: &VirtualPath,
+        deep: bool,
+    ) -> Option<Response<Body>> {
+        let relative = self.relative(path);
+        let tokens = if_tokens(headers);
+        let mut locks = self.locks.lock().unwrap();
+        prune(&mut locks);
+        let blocked = locks.iter().any(|(root, held)| {
+            held.iter().any(|l| {
+                let applies = covers(root, l, relative) || (deep && root.starts_with(relative));
+                applies && !tokens.contains(&l.token)
+            })
+        });
+        blocked.then(|| status(StatusCode::LOCKED))
+    }
+
+    fn remove_lock(&self, root: &[String], token: &str) {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some(held) = locks.get_mut(root) {
+            held.retain(|l| l.token != token);
+            if held.is_empty() {
+                locks.remove(root);
+            }
+        }
+    }
+
+    /// Forget the locks on a resource that is gone
+    fn drop_locks(&self, path: &VirtualPath) {
+        let relative = self.relative(path);
+        self.locks
+            .lock()
+            .unwrap()
+            .retain(|root, _| !root.starts_with(relative));
+    }
+
+    fn active_lock(&self, root: &[String], lock: &Lock) -> String {
+        let mut root_path = self.root.clone();
+        for segment in root {
+            root_path = root_path.join(segment);
+        }
+        let owner = lock
+            .owner
+            .as_ref()
+            .map(|owner| format!("<D:owner>{}</D:owner>", escape(owner)))
+            .unwrap_or_default();
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/></D:lockscope>\
+             <D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if lock.exclusive {
+                "exclusive"
+            } else {
+                "shared"
+            },
+            if lock.infinite { "infinity" } else { "0" },
+            owner,
+            lock.timeout,
+            lock.token,
+            escape(&self.href(&root_path, false)),
+        )
+    }
+
+    fn lock_response(&self, code: StatusCode, root: &[String], lock: &Lock) -> Response<Body> {
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\n",
+            self.active_lock(root, lock)
+        );
+        let mut response = xml_response(code, xml);
+        set(&mut response, "Lock-Token", format!("<{}>", lock.token));
+        response
+    }
+}
+
+/// Whether a lock taken on `root` applies to `path`
+fn covers(root: &[String], lock: &Lock, path: &[String]) -> bool {
+    root == path || (lock.infinite && path.starts_with(root))
+}
+
+fn prune(locks: &mut HashMap<Vec<String>, Vec<Lock>>) {
+    let now = Instant::now();
+    locks.retain(|_, held| {
+        held.retain(|l| l.expires > now);
+        !held.is_empty()
+    });
+}
+
+/// A random `urn:uuid:` lock token
+fn new_token() -> String {
+    let bits = fastrand::u128(..);
+    // Version 4, RFC 4122 variant
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Seconds asked for in a `Timeout` header, within bounds
+fn lock_timeout(header: Option<&str>) -> u64 {
+    let Some(header) = header else {
+        return LOCK_TIMEOUT;
+    };
+    for choice in header.split(',').map(str::trim) {
+        if choice.eq_ignore_ascii_case("Infinite") {
+            return MAX_LOCK_TIMEOUT;
+        }
+        if let Some(seconds) = choice
+            .strip_prefix("Second-")
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            return seconds.clamp(1, MAX_LOCK_TIMEOUT);
+        }
+    }
+    LOCK_TIMEOUT
+}
+
+/// Every `<...>` in an `If` header. Resource tags are URLs and never match
+/// a lock token, so telling the two apart is unnecessary.
+fn if_tokens(headers: &HeaderMap) -> Vec<String> {
+    let Some(header) = header_str(headers, "If") else {
+        return Vec::new();
+    };
+    header
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(token, _)| token.to_string())
+        .collect()
+}
+
+/// What a PROPFIND body asks for; no body means `allprop`
+fn parse_propfind(body: &str) -> PropRequest {
+    if !elements(body, "propname").is_empty() {
+        return PropRequest::Names;
+    }
+    if !elements(body, "allprop").is_empty() {
+        return PropRequest::All;
+    }
+    let Some(prop) = elements(body, "prop").into_iter().next() else {
+        return PropRequest::All;
+    };
+    let namespaces = namespaces(body);
+    let names = child_elements(prop)
+        .into_iter()
+        .map(|(name, _)| prop_name(name, &namespaces))
+        .collect();
+    PropRequest::Props(names)
+}
+
+fn prop_name(qualified: &str, namespaces: &HashMap<String, String>) -> PropName {
+    let (prefix, local) = qualified.rsplit_once(':').unwrap_or(("", qualified));
+    PropName {
+        namespace: namespaces.get(prefix).cloned().unwrap_or_default(),
+        local: local.to_string(),
+    }
+}
+
+/// Namespace declarations anywhere in `xml`, by prefix (`""` for the
+/// default). Clients declare them once on the root element.
+fn namespaces(xml: &str) -> HashMap<String, String> {
+    let mut found = HashMap::new();
+    for part in xml.split("xmlns").skip(1) {
+        let (prefix, rest) = match part.strip_prefix(':') {
+            Some(rest) => match rest.split_once('=') {
+                Some((prefix, rest)) => (prefix.trim(), rest),
+                None => continue,
+            },
+            None => match part.trim_start().strip_prefix('=') {
+                Some(rest) => ("", rest),
+                None => continue,
+            },
+        };
+        let rest = rest.trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some((uri, _)) = rest[1..].split_once(quote) {
+            found.insert(prefix.to_string(), uri.to_string());
+        }
+    }
+    found
+}
+
+/// Qualified names and inner XML of the top-level elements in `xml`
+fn child_elements(xml: &str) -> Vec<(&str, &str)> {
+    let mut found = Vec::new();
+    let mut depth = 0usize;
+    let mut open: Option<(&str, usize)> = None;
+    let mut pos = 0;
+
+    while let Some(offset) = xml[pos..].find('<') {
+        let start = pos + offset + 1;
+        let Some(len) = xml[start..].find('>') else {
+            break;
+        };
+        let tag = &xml[start..start + len];
+        pos = start + len + 1;
+
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        if tag.starts_with('/') {
+            depth = depth.saturating_sub(1);
+            if depth == 0 {
+                if let Some((name, inner)) = open.take() {
+                    found.push((name, &xml[inner..start - 1]));
+                }
+            }
+            continue;
+        }
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if depth == 0 {
+            if tag.ends_with('/') {
+                found.push((name, ""));
+                continue;
+            }
+            open = Some((name, pos));
+        }
+        if !tag.ends_with('/') {
+            depth += 1;
+        }
+    }
+    found
+}
+
+fn strip_tags(xml: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in xml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn propstat(props: &str, status: &str) -> String {
+    format!(
+        "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+        props, status
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn etag(entry: &Entry) -> String {
+    let metadata = &entry.metadata;
+    match metadata
+        .content_hash
+        .as_ref()
+        .or(metadata.revision.as_ref())
+    {
+        Some(tag) => format!("\"{}\"", tag.replace('"', "")),
+        None => format!(
+            "\"{:x}-{:x}\"",
+            metadata.modified.map_or(0, |t| t.timestamp_micros()),
+            entry.size().unwrap_or(0)
+        ),
+    }
+}
+
+/// The status a backend error is reported as
+fn status_of(error: &CfkError) -> StatusCode {
+    match error {
+        CfkError::NotFound(_) | CfkError::OfflineNoCache => StatusCode::NOT_FOUND,
+        CfkError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+        CfkError::InvalidPath(_) | CfkError::Serialization(_) => StatusCode::BAD_REQUEST,
+        CfkError::AlreadyExists(_)
+        | CfkError::NotADirectory(_)
+        | CfkError::NotAFile(_)
+        | CfkError::DirectoryNotEmpty(_)
+        | CfkError::Conflict(_) => StatusCode::CONFLICT,
+        CfkError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+        CfkError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        CfkError::RateLimited { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        CfkError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        // The remote behind us failed, not this server
+        CfkError::Network(_)
+        | CfkError::AuthRequired(_)
+        | CfkError::AuthFailed(_)
+        | CfkError::TokenExpired
+        | CfkError::ProviderApi { .. } => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn xml_response(code: StatusCode, xml: String) -> Response<Body> {
+    let mut response = Response::new(full(xml));
+    *response.status_mut() = code;
+    set(
+        &mut response,
+        header::CONTENT_TYPE,
+        "application/xml; charset=utf-8",
+    );
+    response
+}
+
+async fn read_xml(body: Incoming) -> CfkResult<String> {
+    let body = read_body(body, MAX_XML_BODY).await?;
+    String::from_utf8(body.to_vec())
+        .map_err(|_| CfkError::Serialization("request body is not UTF-8".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webdav::{WebDavAuth, WebDavBackend, WebDavConfig};
+    use crate::LocalBackend;
+    use reqwest::{Client, Method};
+    use tempfile::TempDir;
+
+    /// Base URL of a server exporting `tmp/export`
+    async fn serve(tmp: &TempDir, options: WebDavServerOptions) -> String {
+        std::fs::create_dir_all(tmp.path().join("export")).unwrap();
+        let local: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let root = VirtualPath::new("local", "/export");
+        let server = Arc::new(WebDavServer::new(local, root, options));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(server.serve(listener));
+        url
+    }
+
+    fn client(url: &str, auth: WebDavAuth) -> WebDavBackend {
+        let config = WebDavConfig {
+            base_url: url.to_string(),
+            auth,
+            headers: Vec::new(),
+        };
+        WebDavBackend::new("dav", config)
+    }
+
+    fn path(p: &str) -> VirtualPath {
+        VirtualPath::new("dav", p)
+    }
+
+    fn method(name: &str) -> Method {
+        Method::from_bytes(name.as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backend_round_trip() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, WebDavServerOptions::default()).await;
+        let dav = client(&url, WebDavAuth::None);
+
+        dav.create_directory(&path("/My Docs")).await.unwrap();
+        let data = Bytes::from("0123456789");
+        dav.write_file(
+            &path("/My Docs/a&b.txt"),
+            data.clone(),
+            &WriteOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read(tmp.path().join("export/My Docs/a&b.txt")).unwrap(),
+            data
+        );
+
+        let options = ReadOptions {
+            range: Some((2, 5)),
+            ..Default::default()
+        };
+        let stream = dav
+            .read_file(&path("/My Docs/a&b.txt"), &options)
+            .await
+            .unwrap();
+        assert_eq!(crate::common::collect_stream(stream).await.unwrap(), "234");
+
+        let entry = dav.get_metadata(&path("/My Docs/a&b.txt")).await.unwrap();
+        assert_eq!(entry.size(), Some(10));
+        assert!(entry.metadata.modified.is_some());
+
+        dav.copy(&path("/My Docs"), &path("/Copy"), &CopyOptions::default())
+            .await
+            .unwrap();
+        dav.rename(
+            &path("/Copy/a&b.txt"),
+            &path("/b.txt"),
+            &MoveOptions::default(),
+        )
+        .await
+        .unwrap();
+        let listing = dav
+            .list_directory(&path("/"), &ListOptions::default())
+            .await
+            .unwrap();
+        let mut names: Vec<_> = listing.entries.iter().filter_map(|e| e.name()).collect();
+        names.sort();
+        assert_eq!(names, ["Copy", "My Docs", "b.txt"]);
+
+        let options = DeleteOptions {
+            recursive: true,
+            force: false,
+        };
+        dav.delete(&path("/My Docs"), &options).await.unwrap();
+        assert!(!tmp.path().join("export/My Docs").exists());
+        assert!(tmp.path().join("export/b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_propfind_depth_and_unknown_props() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir_all(tmp.path().join("export/dir/sub")).unwrap();
+        let url = serve(&tmp, WebDavServerOptions::default()).await;
+        let http = Client::new();
+
+        let response = http
+            .request(method("PROPFIND"), format!("{}/dir/", url))
+            .header("Depth", "infinity")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        let body = r#"<?xml version="1.0"?><propfind xmlns="DAV:" xmlns:x="urn:example"><prop><resourcetype/><x:color/></prop></propfind>"#;
+        let response = http
+            .request(method("PROPFIND"), format!("{}/dir/", url))
+            .header("Depth", "1")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 207);
+        let xml = response.text().await.unwrap();
+        assert_eq!(elements(&xml, "response").len(), 2);
+        assert!(xml.contains("<D:href>/dir/sub/</D:href>"));
+        assert!(xml.contains(r#"<N:color xmlns:N="urn:example"/>"#));
+        assert!(xml.contains("404 Not Found"));
+
+        let response = http
+            .request(method("PROPFIND"), format!("{}/missing", url))
+            .header("Depth", "0")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_get_ranges_and_escape() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir_all(tmp.path().join("export")).unwrap();
+        std::fs::write(tmp.path().join("export/file"), "hello world").unwrap();
+        std::fs::write(tmp.path().join("secret"), "outside").unwrap();
+        let url = serve(&tmp, WebDavServerOptions::default()).await;
+        let http = Client::new();
+
+        let response = http
+            .get(format!("{}/file", url))
+            .header("Range", "bytes=-5")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.headers()["content-range"], "bytes 6-10/11");
+        assert_eq!(response.text().await.unwrap(), "world");
+
+        let response = http
+            .get(format!("{}/file", url))
+            .header("Range", "bytes=20-")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 416);
+
+        let response = http
+            .get(format!("{}/%2e%2e%2fsecret", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_locks() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, WebDavServerOptions::default()).await;
+        let http = Client::new();
+        let doc = format!("{}/doc.txt", url);
+
+        let lockinfo = r#"<?xml version="1.0"?><D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype><D:owner><D:href>alice</D:href></D:owner></D:lockinfo>"#;
+        let response = http
+            .request(method("LOCK"), &doc)
+            .header("Timeout", "Second-600")
+            .body(lockinfo)
+            .send()
+            .await
+            .unwrap();
+        // Locking an unmapped URL creates the file
+        assert_eq!(response.status(), 201);
+        let token = response.headers()["lock-token"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let xml = response.text().await.unwrap();
+        assert!(xml.contains("<D:owner>alice</D:owner>"));
+        assert!(xml.contains("Second-600"));
+        assert!(tmp.path().join("export/doc.txt").exists());
+
+        let response = http
+            .request(method("LOCK"), &doc)
+            .body(lockinfo)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 423);
+
+        let response = http.put(&doc).body("blocked").send().await.unwrap();
+        assert_eq!(response.status(), 423);
+        let response = http.delete(format!("{}/", url)).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+
+        let response = http
+            .put(&doc)
+            .header("If", format!("({})", token))
+            .body("edited")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+
+        let response = http
+            .request(method("UNLOCK"), &doc)
+            .header("Lock-Token", &token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        let response = http.delete(&doc).send().await.unwrap();
+        assert_eq!(response.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn test_put_and_mkcol_need_parent() {
+        let tmp = TempDir::new().unwrap();
+        let url = serve(&tmp, WebDavServerOptions::default()).await;
+        let http = Client::new();
+
+        let response = http
+            .put(format!("{}/a/b.txt", url))
+            .body("x")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+        let response = http
+            .request(method("MKCOL"), format!("{}/a/b", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+        let response = http
+            .request(method("MKCOL"), format!("{}/a", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let response = http
+            .request(method("MKCOL"), format!("{}/a", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 405);
+        let response = http
+            .put(format!("{}/a/b.txt", url))
+            .body("x")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+
+        // No overwrite: 412
+        let response = http
+            .request(method("COPY"), format!("{}/a/b.txt", url))
+            .header("Destination", format!("{}/a/b.txt.bak", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let response = http
+            .request(method("MOVE"), format!("{}/a/b.txt", url))
+            .header("Destination", format!("{}/a/b.txt.bak", url))
+            .header("Overwrite", "F")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 412);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let tmp = TempDir::new().unwrap();
+        let options = WebDavServerOptions {
+            credentials: Some(("alice".into(), "s3cret".into())),
+            ..Default::default()
+        };
+        let url = serve(&tmp, options).await;
+
+        let response = Client::new().get(format!("{}/", url)).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(response.headers().contains_key("www-authenticate"));
+
+        let wrong = client(
+            &url,
+            WebDavAuth::Basic {
+                username: "alice".into(),
+                password: "guess".into(),
+            },
+        );
+        assert!(wrong.get_metadata(&path("/")).await.is_err());
+
+        let dav = client(
+            &url,
+            WebDavAuth::Basic {
+                username: "alice".into(),
+                password: "s3cret".into(),
+            },
+        );
+        dav.create_directory(&path("/ok")).await.unwrap();
+        assert!(tmp.path().join("export/ok").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir_all(tmp.path().join("export")).unwrap();
+        std::fs::write(tmp.path().join("export/file"), "data").unwrap();
+        let options = WebDavServerOptions {
+            read_only: true,
+            ..Default::default()
+        };
+        let url = serve(&tmp, options).await;
+        let http = Client::new();
+
+        let response = http.get(format!("{}/file", url)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "data");
+        for (name, target) in [
+            ("PUT", "/other"),
+            ("DELETE", "/file"),
+            ("MKCOL", "/dir"),
+            ("LOCK", "/file"),
+        ] {
+            let response = http
+                .request(method(name), format!("{}{}", url, target))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 403, "{}", name);
+        }
+        assert!(tmp.path().join("export/file").exists());
+    }
+}
@@ -0,0 +1,480 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Incremental crawler feeding a [`SearchIndex`]
+//!
+//! The indexer walks a backend breadth-first with `list_directory`,
+//! following listing cursors, and indexes every entry it finds. Text-like
+//! files also have their content pulled so it can be searched.
+//!
+//! Each backend gets a checkpoint file recording a fingerprint (revision,
+//! content hash, modification time, size) of everything indexed. Later runs
+//! skip entries whose fingerprint has not changed and drop entries that have
+//! disappeared. The checkpoint is saved after every directory, so an
+//! interrupted crawl resumes from the directories it had not reached yet.
+
+use crate::SearchIndex;
+use cfk_core::{
+    operations::{ListOptions, ReadOptions},
+    CfkError, CfkResult, Entry, StorageBackend, VirtualPath,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Indexer configuration
+#[derive(Debug, Clone)]
+pub struct IndexerConfig {
+    /// Directory holding per-backend crawl checkpoints
+    pub checkpoint_dir: PathBuf,
+    /// Maximum number of entries fetched and indexed at once
+    pub concurrency: usize,
+    /// Largest file whose content is pulled into the index (bytes)
+    pub max_content_bytes: u64,
+    /// Index hidden (dot) files and directories
+    pub include_hidden: bool,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        let data_dir = directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.data_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-data"));
+
+        Self {
+            checkpoint_dir: data_dir.join("search").join("checkpoints"),
+            concurrency: 8,
+            max_content_bytes: 10 * 1024 * 1024, // 10MB
+            include_hidden: false,
+        }
+    }
+}
+
+/// What an entry looked like when it was last indexed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    revision: Option<String>,
+    content_hash: Option<String>,
+    modified: Option<i64>,
+    size: Option<u64>,
+}
+
+impl Fingerprint {
+    fn of(entry: &Entry) -> Self {
+        let m = &entry.metadata;
+        Self {
+            revision: m.revision.clone(),
+            content_hash: m.content_hash.clone(),
+            modified: m.modified.map(|t| t.timestamp()),
+            size: m.size,
+        }
+    }
+
+    /// Whether there is anything to compare at all; entries with no
+    /// metadata are always re-indexed
+    fn is_known(&self) -> bool {
+        self.revision.is_some() || self.content_hash.is_some() || self.modified.is_some()
+    }
+}
+
+/// Persisted crawl state for one backend
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    /// Fingerprints of everything indexed, by path
+    entries: HashMap<String, Fingerprint>,
+    /// Directories still to list; non-empty while a crawl is in progress
+    pending: VecDeque<String>,
+    /// Paths seen by the in-progress crawl
+    seen: HashSet<String>,
+    /// When the last full crawl finished
+    completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Outcome of a crawl
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexReport {
+    /// Entries listed
+    pub scanned: u64,
+    /// Entries (re-)indexed
+    pub indexed: u64,
+    /// Entries skipped as unchanged
+    pub unchanged: u64,
+    /// Entries removed from the index because they are gone
+    pub removed: u64,
+    /// Entries or directories that failed; the crawl carries on past them
+    pub errors: Vec<String>,
+}
+
+/// Crawls backends into a search index
+pub struct Indexer {
+    index: Arc<dyn SearchIndex>,
+    config: IndexerConfig,
+}
+
+/// Whether a file's content is worth indexing as text
+pub fn is_text_like(mime: Option<&str>, name: &str) -> bool {
+    if let Some(mime) = mime {
+        let mime = mime.split(';').next().unwrap_or(mime).trim();
+        if mime.starts_with("text/") {
+            return true;
+        }
+        if matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-sh"
+                | "application/toml"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/sql"
+        ) || mime.ends_with("+json")
+            || mime.ends_with("+xml")
+        {
+            return true;
+        }
+        if mime != "application/octet-stream" {
+            return false;
+        }
+    }
+
+    // No (useful) MIME type: go by extension
+    const TEXT_EXTENSIONS: &[&str] = &[
+        "txt", "md", "markdown", "rst", "adoc", "org", "csv", "tsv", "log", "json", "xml", "html",
+        "htm", "css", "js", "ts", "toml", "yaml", "yml", "ini", "cfg", "conf", "sh", "py", "rs",
+        "go", "c", "h", "cpp", "hpp", "java", "rb", "pl", "sql", "tex",
+    ];
+    name.rsplit_once('.')
+        .map(|(_, ext)| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+impl Indexer {
+    pub fn new(index: Arc<dyn SearchIndex>, config: IndexerConfig) -> Self {
+        Self { index, config }
+    }
+
+    fn checkpoint_path(&self, backend_id: &str) -> PathBuf {
+        let name: String = backend_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.config.checkpoint_dir.join(format!("{}.json", name))
+    }
+
+    async fn load_checkpoint(&self, backend_id: &str) -> CfkResult<Checkpoint> {
+        match tokio::fs::read(self.checkpoint_path(backend_id)).await {
+            Ok(data) => {
+                serde_json::from_slice(&data).map_err(|e| CfkError::Serialization(e.to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_checkpoint(&self, backend_id: &str, checkpoint: &Checkpoint) -> CfkResult<()> {
+        let path = self.checkpoint_path(backend_id);
+        tokio::fs::create_dir_all(&self.config.checkpoint_dir).await?;
+
+        // Write then rename, so a crash never leaves a torn checkpoint
+        let data =
+            serde_json::to_vec(checkpoint).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// Forget a backend's checkpoint so the next crawl re-indexes everything
+    pub async fn reset(&self, backend_id: &str) -> CfkResult<()> {
+        match tokio::fs::remove_file(self.checkpoint_path(backend_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Crawl a backend from its root
+    pub async fn crawl(&self, backend: &dyn StorageBackend) -> CfkResult<IndexReport> {
+        self.crawl_from(backend, &VirtualPath::root(backend.id()))
+            .await
+    }
+
+    /// Crawl a backend from `root`
+    ///
+    /// Removal of vanished entries is limited to what lies under `root`.
+    pub async fn crawl_from(
+        &self,
+        backend: &dyn StorageBackend,
+        root: &VirtualPath,
+    ) -> CfkResult<IndexReport> {
+        let backend_id = backend.id().to_string();
+        let mut checkpoint = self.load_checkpoint(&backend_id).await?;
+        let mut report = IndexReport::default();
+
+        if checkpoint.pending.is_empty() {
+            checkpoint.seen.clear();
+            checkpoint.pending.push_back(root.to_path_string());
+        } else {
+            tracing::info!(
+                "Resuming crawl of {} with {} directories pending",
+                backend_id,
+                checkpoint.pending.len()
+            );
+        }
+
+        while let Some(dir) = checkpoint.pending.front().cloned() {
+            let dir_path = VirtualPath::new(&backend_id, &dir);
+
+            match self.list_all(backend, &dir_path).await {
+                Ok(entries) => {
+                    report.scanned += entries.len() as u64;
+                    for entry in &entries {
+                        let key = entry.path.to_path_string();
+                        checkpoint.seen.insert(key.clone());
+                        if entry.is_directory() {
+                            checkpoint.pending.push_back(key);
+                        }
+                    }
+                    self.index_entries(backend, entries, &mut checkpoint, &mut report)
+                        .await;
+                }
+                Err(e) => report.errors.push(format!("{}: {}", dir_path, e)),
+            }
+
+            checkpoint.pending.pop_front();
+            self.save_checkpoint(&backend_id, &checkpoint).await?;
+        }
+
+        // Anything recorded under the crawl root but not seen this time is gone
+        let root_key = root.to_path_string();
+        let prefix = if root_key.ends_with('/') {
+            root_key.clone()
+        } else {
+            format!("{}/", root_key)
+        };
+        let vanished: Vec<String> = checkpoint
+            .entries
+            .keys()
+            .filter(|k| k.starts_with(&prefix) && !checkpoint.seen.contains(*k))
+            .cloned()
+            .collect();
+        for key in vanished {
+            match self
+                .index
+                .remove(&VirtualPath::new(&backend_id, &key))
+                .await
+            {
+                Ok(()) => {
+                    checkpoint.entries.remove(&key);
+                    report.removed += 1;
+                }
+                Err(e) => report.errors.push(format!("{}: {}", key, e)),
+            }
+        }
+
+        checkpoint.seen.clear();
+        checkpoint.completed_at = Some(chrono::Utc::now());
+        self.save_checkpoint(&backend_id, &checkpoint).await?;
+
+        Ok(report)
+    }
+
+    /// Full listing of one directory, following cursors
+    async fn list_all(
+        &self,
+        backend: &dyn StorageBackend,
+        path: &VirtualPath,
+    ) -> CfkResult<Vec<Entry>> {
+        let mut options = ListOptions {
+            include_hidden: self.config.include_hidden,
+            ..Default::default()
+        };
+        let mut entries = Vec::new();
+
+        loop {
+            let listing = backend.list_directory(path, &options).await?;
+            entries.extend(listing.entries);
+            match listing.cursor {
+                Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                _ => return Ok(entries),
+            }
+        }
+    }
+
+    /// Index the changed entries of one listing, `concurrency` at a time
+    async fn index_entries(
+        &self,
+        backend: &dyn StorageBackend,
+        entries: Vec<Entry>,
+        checkpoint: &mut Checkpoint,
+        report: &mut IndexReport,
+    ) {
+        let changed: Vec<(Entry, Fingerprint)> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let fingerprint = Fingerprint::of(&entry);
+                let key = entry.path.to_path_string();
+                if fingerprint.is_known() && checkpoint.entries.get(&key) == Some(&fingerprint) {
+                    report.unchanged += 1;
+                    None
+                } else {
+                    Some((entry, fingerprint))
+                }
+            })
+            .collect();
+
+        let mut results = futures::stream::iter(changed)
+            .map(|(entry, fingerprint)| async move {
+                let result = self.index_entry(backend, &entry).await;
+                (entry, fingerprint, result)
+            })
+            .buffer_unordered(self.config.concurrency.max(1));
+
+        while let Some((entry, fingerprint, result)) = results.next().await {
+            match result {
+                Ok(()) => {
+                    checkpoint
+                        .entries
+                        .insert(entry.path.to_path_string(), fingerprint);
+                    report.indexed += 1;
+                }
+                Err(e) => report.errors.push(format!("{}: {}", entry.path, e)),
+            }
+        }
+    }
+
+    async fn index_entry(&self, backend: &dyn StorageBackend, entry: &Entry) -> CfkResult<()> {
+        let wants_content = entry.is_file()
+            && entry.metadata.size.unwrap_or(0) <= self.config.max_content_bytes
+            && is_text_like(
+                entry.metadata.mime_type.as_deref(),
+                entry.name().unwrap_or_default(),
+            );
+
+        let content = if wants_content {
+            Some(self.read_content(backend, &entry.path).await?)
+        } else {
+            None
+        };
+
+        self.index.index(entry, content.as_deref()).await
+    }
+
+    async fn read_content(
+        &self,
+        backend: &dyn StorageBackend,
+        path: &VirtualPath,
+    ) -> CfkResult<Vec<u8>> {
+        let mut stream = backend.read_file(path, &ReadOptions::default()).await?;
+        let mut data = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+            // Size metadata can be missing or stale; never buffer past the cap
+            if data.len() as u64 > self.config.max_content_bytes {
+                data.truncate(self.config.max_content_bytes as usize);
+                break;
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IndexStats, SearchQuery, SearchResult};
+    use async_trait::async_trait;
+    use cfk_providers::LocalBackend;
+    use std::sync::Mutex;
+
+    /// Records what was indexed
+    #[derive(Default)]
+    struct RecordingIndex {
+        indexed: Mutex<HashMap<String, Option<Vec<u8>>>>,
+        removed: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl SearchIndex for RecordingIndex {
+        async fn index(&self, entry: &Entry, content: Option<&[u8]>) -> CfkResult<()> {
+            self.indexed
+                .lock()
+                .unwrap()
+                .insert(entry.path.to_path_string(), content.map(|c| c.to_vec()));
+            Ok(())
+        }
+
+        async fn remove(&self, path: &VirtualPath) -> CfkResult<()> {
+            self.removed.lock().unwrap().push(path.to_path_string());
+            Ok(())
+        }
+
+        async fn search(&self, _query: &SearchQuery) -> CfkResult<Vec<SearchResult>> {
+            Ok(Vec::new())
+        }
+
+        async fn clear(&self) -> CfkResult<()> {
+            Ok(())
+        }
+
+        async fn stats(&self) -> CfkResult<IndexStats> {
+            Ok(IndexStats::default())
+        }
+    }
+
+    #[test]
+    fn test_is_text_like() {
+        assert!(is_text_like(Some("text/plain; charset=utf-8"), "a"));
+        assert!(is_text_like(Some("application/ld+json"), "a"));
+        assert!(!is_text_like(Some("image/png"), "a.txt"));
+        assert!(is_text_like(Some("application/octet-stream"), "notes.MD"));
+        assert!(is_text_like(None, "main.rs"));
+        assert!(!is_text_like(None, "photo.jpg"));
+    }
+
+    #[tokio::test]
+    async fn test_incremental_crawl() {
+        let data = tempfile::tempdir().unwrap();
+        let state = tempfile::tempdir().unwrap();
+        std::fs::create_dir(data.path().join("docs")).unwrap();
+        std::fs::write(data.path().join("docs/notes.txt"), "hello index").unwrap();
+        std::fs::write(data.path().join("image.bin"), [0u8, 1, 2]).unwrap();
+
+        let backend = LocalBackend::new("local", data.path());
+        let config = IndexerConfig {
+            checkpoint_dir: state.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let index = Arc::new(RecordingIndex::default());
+        let indexer = Indexer::new(index.clone(), config.clone());
+        let report = indexer.crawl(&backend).await.unwrap();
+        assert_eq!(report.indexed, 3);
+        assert!(report.errors.is_empty());
+        {
+            let indexed = index.indexed.lock().unwrap();
+            assert_eq!(
+                indexed.get("/docs/notes.txt").unwrap().as_deref(),
+                Some(&b"hello index"[..])
+            );
+            assert_eq!(indexed.get("/image.bin"), Some(&None));
+        }
+
+        // Second run, fresh indexer: nothing changed, one file removed
+        std::fs::remove_file(data.path().join("image.bin")).unwrap();
+        let index = Arc::new(RecordingIndex::default());
+        let indexer = Indexer::new(index.clone(), config);
+        let report = indexer.crawl(&backend).await.unwrap();
+        assert_eq!(report.indexed, 0);
+        assert_eq!(report.unchanged, 2);
+        assert_eq!(report.removed, 1);
+        assert_eq!(*index.removed.lock().unwrap(), ["/image.bin"]);
+    }
+}
@@ -2,10 +2,19 @@
 //! Full-text search for Czech File Knife
 //!
 //! This module provides full-text search capabilities using Tantivy.
-//! Currently a stub - full implementation coming in a future release.
+//! Enable the `tantivy` feature for the [`TantivyIndex`] implementation.
+
+pub mod indexer;
+#[cfg(feature = "tantivy")]
+mod tantivy_index;
+
+pub use indexer::{IndexReport, Indexer, IndexerConfig};
+
+#[cfg(feature = "tantivy")]
+pub use tantivy_index::TantivyIndex;
 
 use async_trait::async_trait;
-use cfk_core::{CfkResult, Entry, VirtualPath};
+use cfk_core::{CfkError, CfkResult, Entry, VirtualPath};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -25,6 +34,16 @@ pub enum SearchError {
     Io(#[from] std::io::Error),
 }
 
+impl From<SearchError> for CfkError {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::IndexNotFound(path) => CfkError::NotFound(path),
+            SearchError::Io(e) => CfkError::Io(e),
+            other => CfkError::Other(other.to_string()),
+        }
+    }
+}
+
 /// Search result with relevance score
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
@@ -85,54 +104,6 @@ pub struct IndexStats {
     pub last_updated: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-/// Tantivy-based search index (stub)
-/// Enable the `tantivy` feature to use this.
-#[cfg(feature = "tantivy")]
-pub struct TantivyIndex {
-    _path: PathBuf,
-}
-
-#[cfg(feature = "tantivy")]
-impl TantivyIndex {
-    /// Create a new Tantivy index at the given path
-    pub fn new(_path: impl Into<PathBuf>) -> CfkResult<Self> {
-        Err(CfkError::Unsupported(
-            "Tantivy search index not yet implemented".into(),
-        ))
-    }
-
-    /// Open an existing index
-    pub fn open(_path: impl Into<PathBuf>) -> CfkResult<Self> {
-        Err(CfkError::Unsupported(
-            "Tantivy search index not yet implemented".into(),
-        ))
-    }
-}
-
-#[cfg(feature = "tantivy")]
-#[async_trait]
-impl SearchIndex for TantivyIndex {
-    async fn index(&self, _entry: &Entry, _content: Option<&[u8]>) -> CfkResult<()> {
-        Err(CfkError::Unsupported("Search indexing not yet implemented".into()))
-    }
-
-    async fn remove(&self, _path: &VirtualPath) -> CfkResult<()> {
-        Err(CfkError::Unsupported("Search indexing not yet implemented".into()))
-    }
-
-    async fn search(&self, _query: &SearchQuery) -> CfkResult<Vec<SearchResult>> {
-        Err(CfkError::Unsupported("Search not yet implemented".into()))
-    }
-
-    async fn clear(&self) -> CfkResult<()> {
-        Err(CfkError::Unsupported("Search indexing not yet implemented".into()))
-    }
-
-    async fn stats(&self) -> CfkResult<IndexStats> {
-        Err(CfkError::Unsupported("Search indexing not yet implemented".into()))
-    }
-}
-
 /// Simple filename-based search (works without full-text index)
 pub async fn search_by_name(
     pattern: &str,
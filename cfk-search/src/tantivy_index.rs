@@ -0,0 +1,595 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Tantivy-backed search index
+//!
+//! One document per entry, keyed by its `backend:/path` string. Writes are
+//! buffered in the index writer and committed lazily, the next time the
+//! index is searched or its stats are read, so crawling thousands of
+//! entries does not pay for a commit each.
+
+use crate::{IndexStats, SearchError, SearchIndex, SearchQuery, SearchResult};
+use async_trait::async_trait;
+use cfk_core::{CfkResult, Entry, VirtualPath};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, Value, FAST, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{DateTime, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+/// Memory budget for the index writer
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+
+/// Default page size when the query does not set a limit
+const DEFAULT_LIMIT: usize = 20;
+
+/// Schema fields
+#[derive(Clone, Copy)]
+struct Fields {
+    /// `backend:/path`, the document key
+    id: Field,
+    backend: Field,
+    path: Field,
+    /// `backend:/dir` for every directory above the entry, for prefix filters
+    ancestor: Field,
+    name: Field,
+    extension: Field,
+    mime: Field,
+    size: Field,
+    modified: Field,
+    content: Field,
+    /// Serialized `Entry`, returned as-is in results
+    entry: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Self) {
+        let mut builder = Schema::builder();
+        let fields = Self {
+            id: builder.add_text_field("id", STRING | STORED),
+            backend: builder.add_text_field("backend", STRING | STORED),
+            path: builder.add_text_field("path", STRING | STORED),
+            ancestor: builder.add_text_field("ancestor", STRING),
+            name: builder.add_text_field("name", TEXT | STORED),
+            extension: builder.add_text_field("extension", STRING | STORED),
+            mime: builder.add_text_field("mime", STRING | STORED),
+            size: builder.add_u64_field("size", INDEXED | STORED | FAST),
+            modified: builder.add_date_field("modified", INDEXED | STORED | FAST),
+            content: builder.add_text_field("content", TEXT | STORED),
+            entry: builder.add_text_field("entry", STORED),
+        };
+        (builder.build(), fields)
+    }
+
+    fn from_schema(schema: &Schema) -> Result<Self, SearchError> {
+        let field = |name: &str| {
+            schema
+                .get_field(name)
+                .map_err(|_| SearchError::IndexError(format!("index schema lacks `{}`", name)))
+        };
+        Ok(Self {
+            id: field("id")?,
+            backend: field("backend")?,
+            path: field("path")?,
+            ancestor: field("ancestor")?,
+            name: field("name")?,
+            extension: field("extension")?,
+            mime: field("mime")?,
+            size: field("size")?,
+            modified: field("modified")?,
+            content: field("content")?,
+            entry: field("entry")?,
+        })
+    }
+}
+
+/// Tantivy-based search index
+pub struct TantivyIndex {
+    path: Option<PathBuf>,
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+    /// Uncommitted changes are pending in the writer
+    dirty: AtomicBool,
+}
+
+fn index_error(e: impl std::fmt::Display) -> SearchError {
+    SearchError::IndexError(e.to_string())
+}
+
+/// Lower-cased extension of a file name
+fn extension(name: &str) -> Option<String> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    (!stem.is_empty() && !ext.is_empty()).then(|| ext.to_lowercase())
+}
+
+impl TantivyIndex {
+    /// Create a new Tantivy index at the given path
+    ///
+    /// An index already present at `path` is opened rather than replaced.
+    pub fn new(path: impl Into<PathBuf>) -> CfkResult<Self> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+
+        let (schema, _) = Fields::schema();
+        let directory = MmapDirectory::open(&path).map_err(index_error)?;
+        let index = Index::open_or_create(directory, schema).map_err(index_error)?;
+        Ok(Self::from_index(index, Some(path))?)
+    }
+
+    /// Open an existing index
+    pub fn open(path: impl Into<PathBuf>) -> CfkResult<Self> {
+        let path = path.into();
+        let not_found = || SearchError::IndexNotFound(path.display().to_string());
+
+        let directory = MmapDirectory::open(&path).map_err(|_| not_found())?;
+        if !Index::exists(&directory).map_err(index_error)? {
+            return Err(not_found().into());
+        }
+
+        let index = Index::open(directory).map_err(index_error)?;
+        Ok(Self::from_index(index, Some(path))?)
+    }
+
+    /// Default on-disk location, next to the indexer checkpoints
+    pub fn default_path() -> PathBuf {
+        directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.data_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-data"))
+            .join("search")
+            .join("index")
+    }
+
+    /// Create a throwaway index held in memory
+    pub fn in_memory() -> CfkResult<Self> {
+        let (schema, _) = Fields::schema();
+        Ok(Self::from_index(Index::create_in_ram(schema), None)?)
+    }
+
+    fn from_index(index: Index, path: Option<PathBuf>) -> Result<Self, SearchError> {
+        let fields = Fields::from_schema(&index.schema())?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(index_error)?;
+        let writer = index.writer(WRITER_HEAP_BYTES).map_err(index_error)?;
+
+        Ok(Self {
+            path,
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Directory the index lives in, if it is on disk
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn lock_writer(&self) -> Result<std::sync::MutexGuard<'_, IndexWriter>, SearchError> {
+        self.writer
+            .lock()
+            .map_err(|_| SearchError::IndexError("index writer poisoned".into()))
+    }
+
+    /// Commit pending writes and make them visible to searches
+    pub fn commit(&self) -> CfkResult<()> {
+        let mut writer = self.lock_writer()?;
+        let mut prepared = writer.prepare_commit().map_err(index_error)?;
+        prepared.set_payload(&chrono::Utc::now().to_rfc3339());
+        prepared.commit().map_err(index_error)?;
+        drop(writer);
+
+        self.dirty.store(false, Ordering::SeqCst);
+        self.reader.reload().map_err(index_error)?;
+        Ok(())
+    }
+
+    fn commit_if_dirty(&self) -> CfkResult<()> {
+        if self.dirty.load(Ordering::SeqCst) {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn key(path: &VirtualPath) -> String {
+        format!("{}:{}", path.backend, path.to_path_string())
+    }
+
+    fn document(
+        &self,
+        entry: &Entry,
+        content: Option<&[u8]>,
+    ) -> Result<TantivyDocument, SearchError> {
+        let f = &self.fields;
+        let mut doc = TantivyDocument::default();
+
+        doc.add_text(f.id, Self::key(&entry.path));
+        doc.add_text(f.backend, &entry.path.backend);
+        doc.add_text(f.path, entry.path.to_path_string());
+
+        let mut ancestor = entry.path.parent();
+        while let Some(dir) = ancestor {
+            doc.add_text(f.ancestor, Self::key(&dir));
+            ancestor = dir.parent();
+        }
+
+        if let Some(name) = entry.name() {
+            doc.add_text(f.name, name);
+            if entry.is_file() {
+                if let Some(ext) = extension(name) {
+                    doc.add_text(f.extension, ext);
+                }
+            }
+        }
+        if let Some(mime) = &entry.metadata.mime_type {
+            doc.add_text(f.mime, mime);
+        }
+        if let Some(size) = entry.metadata.size {
+            doc.add_u64(f.size, size);
+        }
+        if let Some(modified) = entry.metadata.modified {
+            doc.add_date(
+                f.modified,
+                DateTime::from_timestamp_secs(modified.timestamp()),
+            );
+        }
+        if let Some(content) = content {
+            doc.add_text(f.content, String::from_utf8_lossy(content));
+        }
+
+        let json = serde_json::to_string(entry).map_err(index_error)?;
+        doc.add_text(f.entry, json);
+        Ok(doc)
+    }
+
+    /// Build the query for a `SearchQuery`, text plus filters
+    fn build_query(&self, query: &SearchQuery) -> Result<Box<dyn Query>, SearchError> {
+        let f = &self.fields;
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+
+        let text = query.query.trim();
+        if !text.is_empty() {
+            let mut default_fields = vec![f.name];
+            if query.search_contents {
+                default_fields.push(f.content);
+            }
+            let mut parser = QueryParser::for_index(&self.index, default_fields);
+            parser.set_conjunction_by_default();
+            let parsed = parser
+                .parse_query(text)
+                .map_err(|e| SearchError::QueryError(e.to_string()))?;
+            clauses.push((Occur::Must, parsed));
+        }
+
+        let term_filter = |field: Field, values: Vec<String>| -> Box<dyn Query> {
+            Box::new(BooleanQuery::new(
+                values
+                    .into_iter()
+                    .map(|v| {
+                        let term = Term::from_field_text(field, &v);
+                        (
+                            Occur::Should,
+                            Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+                                as Box<dyn Query>,
+                        )
+                    })
+                    .collect(),
+            ))
+        };
+
+        if let Some(backends) = &query.backends {
+            clauses.push((Occur::Must, term_filter(f.backend, backends.clone())));
+        }
+
+        if let Some(paths) = &query.paths {
+            // An entry is under a prefix if it is the prefix or descends from it
+            let keys: Vec<String> = paths.iter().map(Self::key).collect();
+            let under = BooleanQuery::new(vec![
+                (Occur::Should, term_filter(f.id, keys.clone())),
+                (Occur::Should, term_filter(f.ancestor, keys)),
+            ]);
+            clauses.push((Occur::Must, Box::new(under)));
+        }
+
+        if let Some(types) = &query.file_types {
+            let types = types
+                .iter()
+                .map(|t| t.trim_start_matches('.').to_lowercase())
+                .collect();
+            clauses.push((Occur::Must, term_filter(f.extension, types)));
+        }
+
+        if clauses.is_empty() {
+            return Ok(Box::new(AllQuery));
+        }
+        if clauses.len() == 1 && !text.is_empty() {
+            return Ok(clauses.remove(0).1);
+        }
+        if text.is_empty() {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    fn index_size(&self) -> u64 {
+        match &self.path {
+            Some(path) => std::fs::read_dir(path)
+                .map(|dir| {
+                    dir.filter_map(|e| e.ok()?.metadata().ok())
+                        .filter(|m| m.is_file())
+                        .map(|m| m.len())
+                        .sum()
+                })
+                .unwrap_or(0),
+            None => self
+                .reader
+                .searcher()
+                .space_usage()
+                .map(|u| u.total().get_bytes())
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchIndex for TantivyIndex {
+    async fn index(&self, entry: &Entry, content: Option<&[u8]>) -> CfkResult<()> {
+        let doc = self.document(entry, content)?;
+        let writer = self.lock_writer()?;
+
+        // Re-indexing replaces the previous document for the path
+        writer.delete_term(Term::from_field_text(
+            self.fields.id,
+            &Self::key(&entry.path),
+        ));
+        writer.add_document(doc).map_err(index_error)?;
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn remove(&self, path: &VirtualPath) -> CfkResult<()> {
+        let key = Self::key(path);
+        let writer = self.lock_writer()?;
+
+        // Removing a directory removes everything indexed below it
+        writer.delete_term(Term::from_field_text(self.fields.id, &key));
+        writer.delete_term(Term::from_field_text(self.fields.ancestor, &key));
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn search(&self, query: &SearchQuery) -> CfkResult<Vec<SearchResult>> {
+        self.commit_if_dirty()?;
+
+        let tantivy_query = self.build_query(query)?;
+        let searcher = self.reader.searcher();
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let collector = TopDocs::with_limit(limit).and_offset(query.offset.unwrap_or(0));
+        let hits = searcher
+            .search(&*tantivy_query, &collector)
+            .map_err(index_error)?;
+
+        let mut snippet_fields = vec![self.fields.name];
+        if query.search_contents {
+            snippet_fields.push(self.fields.content);
+        }
+        let generators: Vec<SnippetGenerator> = snippet_fields
+            .into_iter()
+            .filter_map(|field| SnippetGenerator::create(&searcher, &*tantivy_query, field).ok())
+            .collect();
+
+        let mut results = Vec::with_capacity(hits.len());
+        for (score, address) in hits {
+            let doc: TantivyDocument = searcher.doc(address).map_err(index_error)?;
+            let Some(json) = doc.get_first(self.fields.entry).and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let entry: Entry = serde_json::from_str(json).map_err(index_error)?;
+
+            let snippets = generators
+                .iter()
+                .map(|g| g.snippet_from_doc(&doc))
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_html())
+                .collect();
+
+            results.push(SearchResult {
+                entry,
+                // BM25 is unbounded; squash it into 0..1 keeping the order
+                score: score / (1.0 + score),
+                snippets,
+            });
+        }
+
+        Ok(results)
+    }
+
+    async fn clear(&self) -> CfkResult<()> {
+        self.lock_writer()?
+            .delete_all_documents()
+            .map_err(index_error)?;
+        self.commit()
+    }
+
+    async fn stats(&self) -> CfkResult<IndexStats> {
+        self.commit_if_dirty()?;
+
+        let last_updated = self
+            .index
+            .load_metas()
+            .ok()
+            .and_then(|meta| meta.payload)
+            .and_then(|p| chrono::DateTime::parse_from_rfc3339(&p).ok())
+            .map(|t| t.with_timezone(&chrono::Utc));
+
+        Ok(IndexStats {
+            document_count: self.reader.searcher().num_docs(),
+            size_bytes: self.index_size(),
+            last_updated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfk_core::Metadata;
+
+    fn file(backend: &str, path: &str) -> Entry {
+        let metadata = Metadata {
+            size: Some(42),
+            ..Default::default()
+        };
+        Entry::file(VirtualPath::new(backend, path), metadata)
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            query: text.into(),
+            search_contents: true,
+            ..Default::default()
+        }
+    }
+
+    async fn populated() -> TantivyIndex {
+        let index = TantivyIndex::in_memory().unwrap();
+        index
+            .index(
+                &file("local", "/docs/report.txt"),
+                Some(b"quarterly revenue figures"),
+            )
+            .await
+            .unwrap();
+        index
+            .index(
+                &file("dropbox", "/notes/todo.md"),
+                Some(b"buy milk, check revenue"),
+            )
+            .await
+            .unwrap();
+        index
+            .index(&file("local", "/photos/revenue.jpg"), None)
+            .await
+            .unwrap();
+        index
+    }
+
+    #[tokio::test]
+    async fn test_search_content_and_name() {
+        let index = populated().await;
+
+        let results = index.search(&query("revenue")).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.score > 0.0 && r.score < 1.0));
+
+        let report = results
+            .iter()
+            .find(|r| r.entry.path == VirtualPath::new("local", "/docs/report.txt"))
+            .unwrap();
+        assert_eq!(report.entry.metadata.size, Some(42));
+        assert!(report.snippets.iter().any(|s| s.contains("<b>revenue</b>")));
+
+        let names_only = index
+            .search(&SearchQuery {
+                search_contents: false,
+                ..query("revenue")
+            })
+            .await
+            .unwrap();
+        assert_eq!(names_only.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let index = populated().await;
+
+        let by_backend = index
+            .search(&SearchQuery {
+                backends: Some(vec!["dropbox".into()]),
+                ..query("revenue")
+            })
+            .await
+            .unwrap();
+        assert_eq!(by_backend.len(), 1);
+        assert_eq!(by_backend[0].entry.path.backend, "dropbox");
+
+        let by_path = index
+            .search(&SearchQuery {
+                paths: Some(vec![VirtualPath::new("local", "/docs")]),
+                ..query("")
+            })
+            .await
+            .unwrap();
+        assert_eq!(by_path.len(), 1);
+
+        let by_type = index
+            .search(&SearchQuery {
+                file_types: Some(vec![".JPG".into()]),
+                ..query("")
+            })
+            .await
+            .unwrap();
+        assert_eq!(by_type.len(), 1);
+
+        let paged = index
+            .search(&SearchQuery {
+                offset: Some(1),
+                limit: Some(5),
+                ..query("revenue")
+            })
+            .await
+            .unwrap();
+        assert_eq!(paged.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reindex_remove_and_clear() {
+        let index = populated().await;
+        let report = file("local", "/docs/report.txt");
+
+        index.index(&report, Some(b"annual summary")).await.unwrap();
+        assert_eq!(index.stats().await.unwrap().document_count, 3);
+        assert!(index.search(&query("quarterly")).await.unwrap().is_empty());
+
+        index
+            .remove(&VirtualPath::new("local", "/docs"))
+            .await
+            .unwrap();
+        let stats = index.stats().await.unwrap();
+        assert_eq!(stats.document_count, 2);
+        assert!(stats.last_updated.is_some());
+
+        index.clear().await.unwrap();
+        assert_eq!(index.stats().await.unwrap().document_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_persists_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let index = TantivyIndex::new(dir.path()).unwrap();
+            index
+                .index(&file("local", "/a.txt"), Some(b"persisted"))
+                .await
+                .unwrap();
+            index.commit().unwrap();
+        }
+
+        let index = TantivyIndex::open(dir.path()).unwrap();
+        assert_eq!(index.search(&query("persisted")).await.unwrap().len(), 1);
+        assert!(TantivyIndex::open(dir.path().join("missing")).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Segmented downloads
+//!
+//! A large file on a backend with range reads is fetched as fixed-size byte
+//! ranges, several at a time, and handed on in order. Each segment is
+//! retried on its own, so a dropped connection costs one segment rather
+//! than the whole download. Memory use is bounded by the number of segments
+//! in flight.
+//!
+//! Segments are separate requests, so the file could change between them.
+//! Its metadata is looked up again after each segment, and a download that
+//! would mix two versions fails with [`CfkError::Conflict`].
+
+use bytes::{Bytes, BytesMut};
+use cfk_core::{
+    backend::ByteStream, operations::ReadOptions, CfkError, CfkResult, Metadata, StorageBackend,
+    VirtualPath,
+};
+use futures::{stream, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Segmented download options
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Files smaller than this are read in one request
+    pub threshold: u64,
+    pub segment_size: u64,
+    /// Segments fetched at once
+    pub parallelism: usize,
+    /// Attempts per segment after the first
+    pub retries: u32,
+    /// Let reads in one request come from the backend's content cache
+    pub use_cache: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 32 * 1024 * 1024,
+            segment_size: 8 * 1024 * 1024,
+            parallelism: 4,
+            retries: 3,
+            use_cache: false,
+        }
+    }
+}
+
+/// Read a file, in parallel segments when it is large enough and the
+/// backend supports range reads
+///
+/// `size`, which may come from a cache, saves a metadata lookup for files
+/// too small to segment when the caller already knows it.
+pub async fn read_file(
+    backend: Arc<dyn StorageBackend>,
+    path: &VirtualPath,
+    size: Option<u64>,
+    options: &DownloadOptions,
+) -> CfkResult<ByteStream> {
+    let whole = |size: u64| size < options.threshold.max(1) || size <= options.segment_size;
+    let read_options = ReadOptions {
+        use_cache: options.use_cache,
+        ..Default::default()
+    };
+    if !backend.capabilities().range_reads || size.is_some_and(whole) {
+        return backend.read_file(path, &read_options).await;
+    }
+    // A cached size is good enough to tell a file too small to segment
+    let size = match size {
+        Some(size) => Some(size),
+        None => backend.get_metadata(path).await?.metadata.size,
+    };
+    if size.is_none_or(whole) {
+        return backend.read_file(path, &read_options).await;
+    }
+    // Looked up again past any cache: the segments are planned from it and
+    // the checks after each compare with it
+    let pinned = backend.fresh_metadata(path).await?.metadata;
+    match pinned.size {
+        Some(size) if !whole(size) => Ok(segmented(
+            backend,
+            path.clone(),
+            size,
+            pinned,
+            options.clone(),
+        )),
+        _ => backend.read_file(path, &read_options).await,
+    }
+}
+
+/// Fetch `size` bytes of `path` as ordered segments, failing when its
+/// metadata no longer matches `pinned`
+pub fn segmented(
+    backend: Arc<dyn StorageBackend>,
+    path: VirtualPath,
+    size: u64,
+    pinned: Metadata,
+    options: DownloadOptions,
+) -> ByteStream {
+    let segment = options.segment_size.max(1);
+    let ranges = (0..size)
+        .step_by(segment as usize)
+        .map(move |start| (start, (start + segment).min(size)));
+
+    Box::pin(
+        stream::iter(ranges)
+            .map(move |range| {
+                let backend = backend.clone();
+                let path = path.clone();
+                let pinned = pinned.clone();
+                async move {
+                    let data =
+                        fetch_segment(backend.as_ref(), &path, range, options.retries).await?;
+                    check_unchanged(backend.as_ref(), &path, &pinned).await?;
+                    Ok(data)
+                }
+            })
+            .buffered(options.parallelism.max(1)),
+    )
+}
+
+async fn fetch_segment(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+    range: (u64, u64),
+    retries: u32,
+) -> CfkResult<Bytes> {
+    let mut attempt = 0;
+    loop {
+        match read_range(backend, path, range).await {
+            Ok(data) => return Ok(data),
+            Err(e) if e.is_retryable() && attempt < retries => {
+                let delay = match e {
+                    CfkError::RateLimited {
+                        retry_after_secs: Some(secs),
+                    } => Duration::from_secs(secs),
+                    _ => Duration::from_millis(250 << attempt),
+                };
+                tracing::debug!(
+                    "segment {}-{} of {} failed, retrying in {:?}: {}",
+                    range.0,
+                    range.1,
+                    path,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Fail unless `path` still has the version described by `pinned`
+///
+/// Only what the backend reported at the start is compared: the revision,
+/// content hash, modification time and size.
+async fn check_unchanged(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+    pinned: &Metadata,
+) -> CfkResult<()> {
+    let current = backend.fresh_metadata(path).await?.metadata;
+    let differs = (pinned.revision.is_some() && pinned.revision != current.revision)
+        || (pinned.content_hash.is_some() && pinned.content_hash != current.content_hash)
+        || (pinned.modified.is_some() && pinned.modified != current.modified)
+        || pinned.size != current.size;
+    if differs {
+        return Err(CfkError::Conflict(format!(
+            "{} changed during the download",
+            path
+        )));
+    }
+    Ok(())
+}
+
+async fn read_range(
+    backend: &dyn StorageBackend,
+    path: &VirtualPath,
+    (start, end): (u64, u64),
+) -> CfkResult<Bytes> {
+    let options = ReadOptions {
+        range: Some((start, end)),
+        ..Default::default()
+    };
+    let mut stream = backend.read_file(path, &options).await?;
+    let mut data = BytesMut::with_capacity((end - start) as usize);
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    // A cut-off body is a network failure worth retrying
+    if data.len() as u64 != end - start {
+        return Err(CfkError::Network(format!(
+            "short read of {}: {} of {} bytes at {}",
+            path,
+            data.len(),
+            end - start,
+            start
+        )));
+    }
+    Ok(data.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfk_cache::{
+        BlobStoreConfig, CacheStores, CachedBackend, MetadataCacheConfig, PolicyConfig,
+    };
+    use cfk_providers::LocalBackend;
+
+    async fn read_all(
+        backend: Arc<dyn StorageBackend>,
+        path: &VirtualPath,
+        options: &DownloadOptions,
+    ) -> Vec<u8> {
+        let mut stream = read_file(backend, path, None, options).await.unwrap();
+        let mut read = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            read.extend_from_slice(&chunk.unwrap());
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn test_segments_reassemble_in_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(tmp.path().join("big.bin"), &data).unwrap();
+
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let options = DownloadOptions {
+            threshold: 1,
+            segment_size: 7_000,
+            parallelism: 5,
+            retries: 0,
+            use_cache: false,
+        };
+        let path = VirtualPath::new("local", "/big.bin");
+        let mut stream = read_file(backend, &path, None, &options).await.unwrap();
+
+        let mut segments = 0;
+        let mut read = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            read.extend_from_slice(&chunk.unwrap());
+            segments += 1;
+        }
+        assert_eq!(segments, 15);
+        assert_eq!(read, data);
+    }
+
+    #[tokio::test]
+    async fn test_a_file_changed_between_segments_is_a_conflict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("big.bin");
+        std::fs::write(&file, vec![1u8; 30_000]).unwrap();
+
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new("local", tmp.path()));
+        let options = DownloadOptions {
+            threshold: 1,
+            segment_size: 10_000,
+            parallelism: 1,
+            retries: 0,
+            use_cache: false,
+        };
+        let path = VirtualPath::new("local", "/big.bin");
+        let mut stream = read_file(backend, &path, Some(30_000), &options)
+            .await
+            .unwrap();
+        stream.next().await.unwrap().unwrap();
+
+        // Same size, other content
+        std::fs::write(&file, vec![2u8; 30_000]).unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(946_684_800);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert!(matches!(err, CfkError::Conflict(_)), "{}", err);
+    }
+
+    /// A cache in `cache` over the files in `root`
+    async fn cached(root: &std::path::Path, cache: &std::path::Path) -> Arc<dyn StorageBackend> {
+        let stores = CacheStores::open(
+            MetadataCacheConfig {
+                db_path: cache.join("metadata"),
+                ..Default::default()
+            },
+            BlobStoreConfig {
+                path: cache.join("blobs"),
+                ..Default::default()
+            },
+            PolicyConfig::default(),
+        )
+        .await
+        .unwrap();
+        let local = Arc::new(LocalBackend::new("local", root));
+        Arc::new(CachedBackend::new(local, Arc::new(stores)))
+    }
+
+    #[tokio::test]
+    async fn test_whole_reads_can_come_from_the_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("note.txt"), "first").unwrap();
+
+        let backend = cached(tmp.path(), cache.path()).await;
+        let path = VirtualPath::new("local", "/note.txt");
+        let cached = DownloadOptions {
+            use_cache: true,
+            ..Default::default()
+        };
+        assert_eq!(read_all(backend.clone(), &path, &cached).await, b"first");
+
+        // Changed behind the cache's back
+        std::fs::write(tmp.path().join("note.txt"), "later").unwrap();
+        assert_eq!(read_all(backend.clone(), &path, &cached).await, b"first");
+        let uncached = DownloadOptions::default();
+        assert_eq!(read_all(backend, &path, &uncached).await, b"later");
+    }
+
+    #[tokio::test]
+    async fn test_segments_are_planned_from_current_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("big.bin"), vec![1u8; 30_000]).unwrap();
+
+        let backend = cached(tmp.path(), cache.path()).await;
+        let path = VirtualPath::new("local", "/big.bin");
+        assert_eq!(
+            backend.get_metadata(&path).await.unwrap().size(),
+            Some(30_000)
+        );
+
+        // Grown since its size was cached
+        std::fs::write(tmp.path().join("big.bin"), vec![2u8; 50_000]).unwrap();
+        let options = DownloadOptions {
+            threshold: 1,
+            segment_size: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(read_all(backend, &path, &options).await, vec![2u8; 50_000]);
+    }
+}
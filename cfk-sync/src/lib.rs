@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Sync and transfer engines for Czech File Knife
+//!
+//! Everything here works on any pair of [`StorageBackend`]s, local or remote.
+//!
+//! - [`sync`]: two-way sync with a persisted base state and conflict policies
+//! - [`state`]: the sled-backed record of what both sides looked like last time
+//! - [`mirror`]: one-way mirror that makes a destination match a source
+//! - [`transfer`]: parallel recursive copies with progress reporting
+//! - [`download`]: segmented parallel downloads of large files
+
+pub mod download;
+pub mod mirror;
+pub mod state;
+pub mod sync;
+pub mod transfer;
+
+pub use download::DownloadOptions;
+pub use mirror::{
+    CompareMode, MirrorAction, MirrorEngine, MirrorOptions, MirrorPlan, MirrorReport,
+};
+
+pub use state::{Snapshot, SyncRecord, SyncState};
+pub use sync::{
+    Conflict, ConflictPolicy, Resolution, Side, SyncAction, SyncEngine, SyncPlan, SyncReport,
+};
+pub use transfer::{
+    copy_target, NoProgress, TransferEngine, TransferOptions, TransferProgress, TransferReport,
+};
+
+use cfk_cache::ContentId;
+use cfk_core::{
+    operations::{CopyOptions, ListOptions, ReadOptions, WriteOptions},
+    CfkError, CfkResult, Entry, StorageBackend, VirtualPath,
+};
+use futures::StreamExt;
+use std::collections::VecDeque;
+
+/// Every entry below `root`, parents before children
+///
+/// A missing root yields an empty tree rather than an error, so a
+/// destination that does not exist yet looks empty.
+pub async fn walk(
+    backend: &dyn StorageBackend,
+    root: &VirtualPath,
+    include_hidden: bool,
+) -> CfkResult<Vec<Entry>> {
+    match backend.get_metadata(root).await {
+        Ok(entry) if !entry.is_directory() => {
+            return Err(CfkError::NotADirectory(root.to_string()))
+        }
+        Ok(_) => {}
+        Err(CfkError::NotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    }
+
+    let mut entries = Vec::new();
+    let mut pending = VecDeque::from([root.clone()]);
+
+    while let Some(dir) = pending.pop_front() {
+        let mut options = ListOptions {
+            include_hidden,
+            ..Default::default()
+        };
+        loop {
+            let listing = backend.list_directory(&dir, &options).await?;
+            for entry in listing.entries {
+                if entry.is_directory() {
+                    pending.push_back(entry.path.clone());
+                }
+                entries.push(entry);
+            }
+            match listing.cursor {
+                Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                _ => break,
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// `path` relative to `root` as a `/`-separated string, if it lies below it
+pub fn relative(root: &VirtualPath, path: &VirtualPath) -> Option<String> {
+    path.segments
+        .strip_prefix(root.segments.as_slice())
+        .map(|rest| rest.join("/"))
+}
+
+/// Copy one file, within a backend or across two
+pub async fn copy_file(
+    from_backend: &dyn StorageBackend,
+    from: &VirtualPath,
+    to_backend: &dyn StorageBackend,
+    to: &VirtualPath,
+    overwrite: bool,
+) -> CfkResult<Entry> {
+    if from_backend.id() == to_backend.id() {
+        let options = CopyOptions {
+            overwrite,
+            preserve_metadata: true,
+        };
+        return from_backend.copy(from, to, &options).await;
+    }
+
+    let size = from_backend.get_metadata(from).await?.metadata.size;
+    let stream = from_backend
+        .read_file(from, &ReadOptions::default())
+        .await?;
+    let options = WriteOptions {
+        overwrite,
+        create_parents: true,
+        ..Default::default()
+    };
+    to_backend
+        .write_file_stream(to, stream, size, &options)
+        .await
+}
+
+/// BLAKE3 content ID of a file, as the blob store would compute it
+pub async fn hash_file(backend: &dyn StorageBackend, path: &VirtualPath) -> CfkResult<ContentId> {
+    let mut stream = backend.read_file(path, &ReadOptions::default()).await?;
+    let mut hasher = blake3::Hasher::new();
+    while let Some(chunk) = stream.next().await {
+        hasher.update(&chunk?);
+    }
+    Ok(ContentId::from_bytes(*hasher.finalize().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfk_cache::BlobStore;
+    use cfk_providers::LocalBackend;
+
+    #[tokio::test]
+    async fn test_walk_and_relative() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+        std::fs::write(tmp.path().join("a/b/c.txt"), "c").unwrap();
+
+        let backend = LocalBackend::new("local", tmp.path());
+        let root = VirtualPath::root("local");
+        let entries = walk(&backend, &root, false).await.unwrap();
+        let paths: Vec<String> = entries
+            .iter()
+            .filter_map(|e| relative(&root, &e.path))
+            .collect();
+        assert_eq!(paths, ["a", "a/b", "a/b/c.txt"]);
+
+        let missing = walk(&backend, &root.join("nope"), false).await.unwrap();
+        assert!(missing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hash_file_matches_the_blob_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Several read chunks long
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(tmp.path().join("f.bin"), &data).unwrap();
+
+        let backend = LocalBackend::new("local", tmp.path());
+        let id = hash_file(&backend, &VirtualPath::new("local", "/f.bin"))
+            .await
+            .unwrap();
+        assert_eq!(id, BlobStore::hash(&data));
+    }
+}
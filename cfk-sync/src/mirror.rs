@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! One-way mirror
+//!
+//! Makes a destination tree look like a source tree, rsync style: missing
+//! or differing files are transferred, and with `delete` set, anything on
+//! the destination that the source does not have is removed. Unlike
+//! [`sync`](crate::sync) no state is kept; every run compares the two trees
+//! directly.
+
+use crate::{copy_file, hash_file, relative, walk, Snapshot};
+use cfk_core::{operations::DeleteOptions, CfkError, CfkResult, StorageBackend, VirtualPath};
+use cfk_search::matches_glob;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How to decide whether a destination file is up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompareMode {
+    /// Same size, and the destination is no older than the source
+    ///
+    /// A transferred copy either keeps the source's modification time or
+    /// gets a newer one, so an older destination means the source changed
+    /// since.
+    #[default]
+    SizeMtime,
+    /// Provider-native content hashes (`Metadata.content_hash`), falling
+    /// back to BLAKE3 when either side has none
+    ///
+    /// Hashes are only comparable between backends of the same kind.
+    Checksum,
+    /// BLAKE3 of the full content of both files
+    Blake3,
+}
+
+impl FromStr for CompareMode {
+    type Err = CfkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size-mtime" | "mtime" => Ok(CompareMode::SizeMtime),
+            "checksum" | "hash" => Ok(CompareMode::Checksum),
+            "blake3" => Ok(CompareMode::Blake3),
+            other => Err(CfkError::Other(format!("unknown compare mode: {}", other))),
+        }
+    }
+}
+
+/// Mirror options
+#[derive(Debug, Clone, Default)]
+pub struct MirrorOptions {
+    pub compare: CompareMode,
+    /// Remove destination entries the source does not have
+    pub delete: bool,
+    /// Only mirror files matching one of these globs (all files if empty)
+    pub include: Vec<String>,
+    /// Skip entries matching any of these globs; excluded destination
+    /// entries are never deleted
+    pub exclude: Vec<String>,
+    pub include_hidden: bool,
+}
+
+/// One step of a mirror run; paths are relative to the roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorAction {
+    CreateDirectory { path: String },
+    Transfer { path: String, size: Option<u64> },
+    Delete { path: String, directory: bool },
+}
+
+impl fmt::Display for MirrorAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorAction::CreateDirectory { path } => write!(f, "mkdir   {}/", path),
+            MirrorAction::Transfer { path, .. } => write!(f, "send    {}", path),
+            MirrorAction::Delete { path, directory } => {
+                let suffix = if *directory { "/" } else { "" };
+                write!(f, "delete  {}{}", path, suffix)
+            }
+        }
+    }
+}
+
+/// Actions a mirror run would take
+#[derive(Debug, Clone, Default)]
+pub struct MirrorPlan {
+    pub actions: Vec<MirrorAction>,
+    /// Source files the destination already has
+    pub unchanged: u64,
+}
+
+impl MirrorPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Bytes the planned transfers will send, as far as sizes are known
+    pub fn bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|a| match a {
+                MirrorAction::Transfer { size, .. } => size.unwrap_or(0),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// Outcome of a mirror run
+#[derive(Debug, Clone, Default)]
+pub struct MirrorReport {
+    pub files_transferred: u64,
+    pub bytes_transferred: u64,
+    pub files_unchanged: u64,
+    pub directories_created: u64,
+    pub deleted: u64,
+    /// Actions that failed; the run carries on past them
+    pub errors: Vec<String>,
+}
+
+/// One-way mirror from a source tree to a destination tree
+pub struct MirrorEngine {
+    source: Arc<dyn StorageBackend>,
+    source_root: VirtualPath,
+    destination: Arc<dyn StorageBackend>,
+    destination_root: VirtualPath,
+    options: MirrorOptions,
+}
+
+impl MirrorEngine {
+    pub fn new(
+        source: Arc<dyn StorageBackend>,
+        source_root: VirtualPath,
+        destination: Arc<dyn StorageBackend>,
+        destination_root: VirtualPath,
+        options: MirrorOptions,
+    ) -> Self {
+        Self {
+            source,
+            source_root,
+            destination,
+            destination_root,
+            options,
+        }
+    }
+
+    fn matches_any(patterns: &[String], path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        patterns
+            .iter()
+            .any(|p| matches_glob(p, name) || matches_glob(p, path))
+    }
+
+    /// Whether `path` or one of its parents is excluded
+    fn excluded(&self, path: &str) -> bool {
+        let mut prefix = String::new();
+        for segment in path.split('/') {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(segment);
+            if Self::matches_any(&self.options.exclude, &prefix) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether a file takes part in the mirror
+    fn selected(&self, path: &str) -> bool {
+        !self.excluded(path)
+            && (self.options.include.is_empty() || Self::matches_any(&self.options.include, path))
+    }
+
+    async fn scan(
+        &self,
+        backend: &dyn StorageBackend,
+        root: &VirtualPath,
+    ) -> CfkResult<Vec<(String, Snapshot)>> {
+        Ok(walk(backend, root, self.options.include_hidden)
+            .await?
+            .iter()
+            .filter_map(|e| Some((relative(root, &e.path)?, Snapshot::of(e))))
+            .collect())
+    }
+
+    /// Work out what a run would do
+    pub async fn plan(&self) -> CfkResult<MirrorPlan> {
+        let source = self.scan(self.source.as_ref(), &self.source_root).await?;
+        let destination: HashMap<String, Snapshot> = self
+            .scan(self.destination.as_ref(), &self.destination_root)
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut actions = Vec::new();
+        let mut unchanged = 0;
+        let mut wanted = HashMap::new();
+
+        for (path, src) in &source {
+            if self.excluded(path) {
+                continue;
+            }
+            wanted.insert(path.clone(), src.directory);
+            let dst = destination.get(path);
+
+            if src.directory {
+                if !dst.map(|d| d.directory).unwrap_or(false) {
+                    if dst.is_some() {
+                        actions.push(MirrorAction::Delete {
+                            path: path.clone(),
+                            directory: false,
+                        });
+                    }
+                    actions.push(MirrorAction::CreateDirectory { path: path.clone() });
+                }
+                continue;
+            }
+            if !self.selected(path) {
+                continue;
+            }
+
+            let up_to_date = match dst {
+                Some(d) if !d.directory => self.up_to_date(path, src, d).await?,
+                _ => false,
+            };
+            if up_to_date {
+                unchanged += 1;
+            } else {
+                if dst.map(|d| d.directory).unwrap_or(false) {
+                    actions.push(MirrorAction::Delete {
+                        path: path.clone(),
+                        directory: true,
+                    });
+                }
+                actions.push(MirrorAction::Transfer {
+                    path: path.clone(),
+                    size: src.size,
+                });
+            }
+        }
+
+        if self.options.delete {
+            let mut extraneous: Vec<(&String, bool)> = destination
+                .iter()
+                .filter(|(path, d)| wanted.get(*path) != Some(&d.directory) && !self.excluded(path))
+                .map(|(path, d)| (path, d.directory))
+                .collect();
+            extraneous.sort();
+
+            // A removed directory takes its children with it
+            let mut removed_dirs: Vec<&str> = Vec::new();
+            for (path, directory) in extraneous {
+                if removed_dirs
+                    .iter()
+                    .any(|dir| path.starts_with(dir) && path[dir.len()..].starts_with('/'))
+                {
+                    continue;
+                }
+                // Type changes were already scheduled above
+                if wanted.contains_key(path) {
+                    continue;
+                }
+                if directory {
+                    removed_dirs.push(path);
+                }
+                actions.push(MirrorAction::Delete {
+                    path: path.clone(),
+                    directory,
+                });
+            }
+        }
+
+        Ok(MirrorPlan { actions, unchanged })
+    }
+
+    async fn up_to_date(&self, path: &str, src: &Snapshot, dst: &Snapshot) -> CfkResult<bool> {
+        if src.size != dst.size {
+            return Ok(false);
+        }
+        match self.options.compare {
+            CompareMode::SizeMtime => Ok(match (src.modified, dst.modified) {
+                (Some(s), Some(d)) => d >= s,
+                _ => false,
+            }),
+            CompareMode::Checksum => match (&src.content_hash, &dst.content_hash) {
+                (Some(s), Some(d)) => Ok(s == d),
+                _ => self.same_blake3(path).await,
+            },
+            CompareMode::Blake3 => self.same_blake3(path).await,
+        }
+    }
+
+    async fn same_blake3(&self, path: &str) -> CfkResult<bool> {
+        let source_path = self.source_root.join(path);
+        let destination_path = self.destination_root.join(path);
+        let (source, destination) = futures::try_join!(
+            hash_file(self.source.as_ref(), &source_path),
+            hash_file(self.destination.as_ref(), &destination_path),
+        )?;
+        Ok(source == destination)
+    }
+
+    /// Carry out planned actions
+    pub async fn apply(&self, plan: &MirrorPlan) -> CfkResult<MirrorReport> {
+        let mut report = MirrorReport {
+            files_unchanged: plan.unchanged,
+            ..Default::default()
+        };
+
+        if !plan.is_empty() && !self.destination_root.is_root() {
+            if let Err(CfkError::NotFound(_)) =
+                self.destination.get_metadata(&self.destination_root).await
+            {
+                self.destination
+                    .create_directory(&self.destination_root)
+                    .await?;
+            }
+        }
+
+        for action in &plan.actions {
+            let result = match action {
+                MirrorAction::CreateDirectory { path } => self
+                    .destination
+                    .create_directory(&self.destination_root.join(path))
+                    .await
+                    .map(|_| report.directories_created += 1),
+                MirrorAction::Transfer { path, .. } => copy_file(
+                    self.source.as_ref(),
+                    &self.source_root.join(path),
+                    self.destination.as_ref(),
+                    &self.destination_root.join(path),
+                    true,
+                )
+                .await
+                .map(|entry| {
+                    report.files_transferred += 1;
+                    report.bytes_transferred += entry.metadata.size.unwrap_or(0);
+                }),
+                MirrorAction::Delete { path, directory } => {
+                    let options = DeleteOptions {
+                        recursive: *directory,
+                        force: true,
+                    };
+                    self.destination
+                        .delete(&self.destination_root.join(path), &options)
+                        .await
+                        .map(|_| report.deleted += 1)
+                }
+            };
+            if let Err(e) = result {
+                report.errors.push(format!("{}: {}", action, e));
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Plan and apply in one go
+    pub async fn run(&self) -> CfkResult<MirrorReport> {
+        let plan = self.plan().await?;
+        self.apply(&plan).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfk_providers::LocalBackend;
+    use std::path::Path;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn engine(src: &Path, dst: &Path, options: MirrorOptions) -> MirrorEngine {
+        MirrorEngine::new(
+            Arc::new(LocalBackend::new("src", src)),
+            VirtualPath::root("src"),
+            Arc::new(LocalBackend::new("dst", dst)),
+            VirtualPath::root("dst"),
+            options,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_mirror_with_delete_and_filters() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        write(src.path(), "docs/a.txt", "alpha");
+        write(src.path(), "docs/b.log", "noise");
+        write(src.path(), "build/out.o", "binary");
+        write(dst.path(), "stale/old.txt", "old");
+        write(dst.path(), "keep.tmp", "excluded, so protected");
+
+        let options = MirrorOptions {
+            delete: true,
+            exclude: vec!["*.tmp".into(), "build".into()],
+            include: vec!["*.txt".into()],
+            ..Default::default()
+        };
+        let report = engine(src.path(), dst.path(), options.clone())
+            .run()
+            .await
+            .unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.files_transferred, 1);
+        assert_eq!(report.bytes_transferred, 5);
+
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("docs/a.txt")).unwrap(),
+            "alpha"
+        );
+        assert!(!dst.path().join("docs/b.log").exists());
+        assert!(!dst.path().join("build").exists());
+        assert!(!dst.path().join("stale").exists());
+        assert!(dst.path().join("keep.tmp").exists());
+
+        // Second run has nothing to send
+        let report = engine(src.path(), dst.path(), options).run().await.unwrap();
+        assert_eq!(report.files_transferred, 0);
+        assert_eq!(report.files_unchanged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_blake3_catches_same_size_edits() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        write(src.path(), "f.txt", "aaaa");
+        write(dst.path(), "f.txt", "bbbb");
+
+        // The destination is newer and the same size, so size+mtime is fooled
+        let plan = engine(src.path(), dst.path(), MirrorOptions::default())
+            .plan()
+            .await
+            .unwrap();
+        assert!(plan.is_empty());
+        assert_eq!(plan.unchanged, 1);
+
+        let options = MirrorOptions {
+            compare: CompareMode::Blake3,
+            ..Default::default()
+        };
+        let plan = engine(src.path(), dst.path(), options)
+            .plan()
+            .await
+            .unwrap();
+        assert_eq!(
+            plan.actions,
+            [MirrorAction::Transfer {
+                path: "f.txt".into(),
+                size: Some(4)
+            }]
+        );
+    }
+}
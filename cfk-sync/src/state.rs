@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Persisted sync state
+//!
+//! For every path a sync pair has agreed on, the state keeps a snapshot of
+//! both sides as they were right after the last successful sync. That is
+//! the base of the three-way comparison on the next run. Records live in a
+//! sled database shared by all pairs, keyed by the pair's two root URIs.
+
+use cfk_cache::sled_backend::SledBackend;
+use cfk_core::{CfkError, CfkResult, Entry, VirtualPath};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// What one side of a pair looked like at a point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub directory: bool,
+    pub size: Option<u64>,
+    /// Modification time, seconds since the epoch
+    pub modified: Option<i64>,
+    pub content_hash: Option<String>,
+    pub revision: Option<String>,
+}
+
+impl Snapshot {
+    pub fn of(entry: &Entry) -> Self {
+        let m = &entry.metadata;
+        Self {
+            directory: entry.is_directory(),
+            size: m.size,
+            modified: m.modified.map(|t| t.timestamp()),
+            content_hash: m.content_hash.clone(),
+            revision: m.revision.clone(),
+        }
+    }
+
+    /// Whether `other` is the same version of this entry on the same side
+    ///
+    /// Directories carry no version of their own: their timestamps move
+    /// whenever a child changes, and the children are compared separately.
+    pub fn same_version(&self, other: &Snapshot) -> bool {
+        if self.directory || other.directory {
+            return self.directory == other.directory;
+        }
+        if let (Some(a), Some(b)) = (&self.revision, &other.revision) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (&self.content_hash, &other.content_hash) {
+            return a == b && self.size == other.size;
+        }
+        self.size == other.size && self.modified == other.modified
+    }
+}
+
+/// Both sides of one path after the last sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRecord {
+    pub source: Snapshot,
+    pub destination: Snapshot,
+}
+
+/// Sync state of one source/destination pair
+///
+/// Clones share the same open database.
+#[derive(Clone)]
+pub struct SyncState {
+    db: SledBackend,
+    prefix: Vec<u8>,
+}
+
+impl SyncState {
+    /// Open the state database at `path` for the given pair
+    pub fn open(
+        path: impl AsRef<Path>,
+        source: &VirtualPath,
+        destination: &VirtualPath,
+    ) -> CfkResult<Self> {
+        let db = SledBackend::open(path)?;
+        let prefix = format!("sync\0{}\0{}\0", source.to_uri(), destination.to_uri()).into_bytes();
+        Ok(Self { db, prefix })
+    }
+
+    /// Default database location in the user data directory
+    pub fn default_path() -> PathBuf {
+        directories::ProjectDirs::from("com", "cfk", "czech-file-knife")
+            .map(|d| d.data_dir().to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("/tmp/cfk-data"))
+            .join("sync")
+            .join("state.db")
+    }
+
+    fn key(&self, path: &str) -> Vec<u8> {
+        let mut key = self.prefix.clone();
+        key.extend_from_slice(path.as_bytes());
+        key
+    }
+
+    /// All records of the pair, by relative path
+    pub fn records(&self) -> CfkResult<HashMap<String, SyncRecord>> {
+        let mut records = HashMap::new();
+        for item in self.db.scan_prefix(&self.prefix) {
+            let (key, value) = item?;
+            let path = String::from_utf8_lossy(&key[self.prefix.len()..]).into_owned();
+            let record = serde_json::from_slice(&value)
+                .map_err(|e| CfkError::Serialization(e.to_string()))?;
+            records.insert(path, record);
+        }
+        Ok(records)
+    }
+
+    pub fn get(&self, path: &str) -> CfkResult<Option<SyncRecord>> {
+        match self.db.get(&self.key(path))? {
+            Some(value) => serde_json::from_slice(&value)
+                .map(Some)
+                .map_err(|e| CfkError::Serialization(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, path: &str, record: &SyncRecord) -> CfkResult<()> {
+        let value =
+            serde_json::to_vec(record).map_err(|e| CfkError::Serialization(e.to_string()))?;
+        self.db.insert(&self.key(path), &value)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &str) -> CfkResult<()> {
+        self.db.remove(&self.key(path))?;
+        Ok(())
+    }
+
+    /// Drop every record of the pair
+    pub fn clear(&self) -> CfkResult<()> {
+        let keys: Vec<Vec<u8>> = self
+            .db
+            .scan_prefix(&self.prefix)
+            .filter_map(|item| item.ok().map(|(key, _)| key))
+            .collect();
+        for key in keys {
+            self.db.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> CfkResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(size: u64, modified: i64) -> Snapshot {
+        Snapshot {
+            directory: false,
+            size: Some(size),
+            modified: Some(modified),
+            content_hash: None,
+            revision: None,
+        }
+    }
+
+    #[test]
+    fn test_same_version() {
+        assert!(file(1, 10).same_version(&file(1, 10)));
+        assert!(!file(1, 10).same_version(&file(1, 11)));
+
+        let mut a = file(1, 10);
+        let mut b = file(1, 99);
+        a.revision = Some("r1".into());
+        b.revision = Some("r1".into());
+        assert!(a.same_version(&b));
+    }
+
+    #[test]
+    fn test_records_are_per_pair() {
+        let tmp = tempfile::tempdir().unwrap();
+        let db = tmp.path().join("state.db");
+        let a = VirtualPath::new("local", "/a");
+        let b = VirtualPath::new("local", "/b");
+        let record = SyncRecord {
+            source: file(1, 1),
+            destination: file(1, 2),
+        };
+
+        {
+            let state = SyncState::open(&db, &a, &b).unwrap();
+            state.put("x.txt", &record).unwrap();
+            state.flush().unwrap();
+        }
+
+        let state = SyncState::open(&db, &a, &b).unwrap();
+        assert_eq!(state.get("x.txt").unwrap(), Some(record));
+        drop(state);
+
+        let other = SyncState::open(&db, &b, &a).unwrap();
+        assert!(other.records().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,917 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Two-way sync between two backends
+//!
+//! Each run scans both trees and compares every path against the base
+//! recorded in [`SyncState`] after the previous run. A side whose entry
+//! differs from its base has changed; a change on one side is carried over
+//! to the other, and changes on both sides are a conflict, settled by the
+//! engine's [`ConflictPolicy`].
+//!
+//! A file deleted on one side and re-created elsewhere on that same side
+//! with identical size and timestamp (or hash) is treated as a rename and
+//! replayed as one, instead of a delete plus a fresh upload.
+
+use crate::state::{Snapshot, SyncRecord, SyncState};
+use crate::{copy_file, hash_file, relative, walk};
+use cfk_core::{
+    operations::{DeleteOptions, MoveOptions},
+    CfkError, CfkResult, StorageBackend, VirtualPath,
+};
+use futures::future::BoxFuture;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// One side of a sync pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Source,
+    Destination,
+}
+
+impl Side {
+    pub fn other(self) -> Self {
+        match self {
+            Side::Source => Side::Destination,
+            Side::Destination => Side::Source,
+        }
+    }
+
+    /// Arrow pointing at this side, for plan output
+    fn arrow(self) -> &'static str {
+        match self {
+            Side::Source => "<-",
+            Side::Destination => "->",
+        }
+    }
+}
+
+/// How to settle a path changed on both sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// The most recently modified version wins; an edit beats a delete
+    #[default]
+    NewerWins,
+    /// Keep both: the destination's version is saved under a conflict name
+    KeepBoth,
+    /// Ask the prompt handler; conflicts are skipped without one
+    Prompt,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = CfkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newer" | "newer-wins" => Ok(ConflictPolicy::NewerWins),
+            "keep-both" | "both" => Ok(ConflictPolicy::KeepBoth),
+            "prompt" | "ask" => Ok(ConflictPolicy::Prompt),
+            other => Err(CfkError::Other(format!(
+                "unknown conflict policy: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Outcome chosen for a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    KeepSource,
+    KeepDestination,
+    KeepBoth,
+    Skip,
+}
+
+/// A path changed on both sides since the last sync
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    /// Current source entry, `None` if deleted
+    pub source: Option<Snapshot>,
+    /// Current destination entry, `None` if deleted
+    pub destination: Option<Snapshot>,
+}
+
+/// One step of a sync plan; paths are relative to the pair's roots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    /// Create or replace `path` on side `to` from the other side
+    Copy {
+        path: String,
+        to: Side,
+        directory: bool,
+    },
+    /// Delete `path` on side `on`
+    Delete {
+        path: String,
+        on: Side,
+        directory: bool,
+    },
+    /// Replay a rename on side `on`
+    Rename { from: String, to: String, on: Side },
+    /// Keep both versions: the destination's moves to `renamed` on both
+    /// sides and the source's takes `path`
+    KeepBoth { path: String, renamed: String },
+    /// A file on side `on` clashes with a directory on the other: the file
+    /// moves to `renamed` on both sides and the directory takes `path`
+    MoveAside {
+        path: String,
+        renamed: String,
+        on: Side,
+    },
+    /// Both sides already agree; only the state is updated
+    Record { path: String },
+    /// Gone from both sides; only the state is updated
+    Forget { path: String },
+    /// Unresolved conflict, left alone
+    Skip { path: String },
+}
+
+impl SyncAction {
+    /// Whether the action touches either backend
+    pub fn is_change(&self) -> bool {
+        !matches!(self, SyncAction::Record { .. } | SyncAction::Forget { .. })
+    }
+
+    /// Execution order: directories are created top-down before anything
+    /// moves into them, and deletes run bottom-up after everything has
+    /// moved out
+    fn order(&self) -> (u8, isize) {
+        let depth = |p: &str| p.matches('/').count() as isize;
+        match self {
+            // Before the directory taking its place is created
+            SyncAction::MoveAside { path, .. } => (0, 2 * depth(path)),
+            SyncAction::Copy {
+                path,
+                directory: true,
+                ..
+            } => (0, 2 * depth(path) + 1),
+            SyncAction::Rename { to, .. } => (1, depth(to)),
+            SyncAction::Copy { path, .. } | SyncAction::KeepBoth { path, .. } => (2, depth(path)),
+            SyncAction::Delete { path, .. } => (3, -depth(path)),
+            SyncAction::Record { .. } | SyncAction::Forget { .. } | SyncAction::Skip { .. } => {
+                (4, 0)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::Copy {
+                path,
+                to,
+                directory,
+            } => {
+                let suffix = if *directory { "/" } else { "" };
+                write!(f, "copy    {} {}{}", to.arrow(), path, suffix)
+            }
+            SyncAction::Delete {
+                path,
+                on,
+                directory,
+            } => {
+                let suffix = if *directory { "/" } else { "" };
+                write!(f, "delete  {} {}{}", on.arrow(), path, suffix)
+            }
+            SyncAction::Rename { from, to, on } => {
+                write!(f, "rename  {} {} => {}", on.arrow(), from, to)
+            }
+            SyncAction::KeepBoth { path, renamed } => {
+                write!(f, "both    <> {} (destination kept as {})", path, renamed)
+            }
+            SyncAction::MoveAside { path, renamed, on } => {
+                write!(
+                    f,
+                    "aside   {} {} (file kept as {})",
+                    on.arrow(),
+                    path,
+                    renamed
+                )
+            }
+            SyncAction::Record { path } => write!(f, "record     {}", path),
+            SyncAction::Forget { path } => write!(f, "forget     {}", path),
+            SyncAction::Skip { path } => write!(f, "skip    !! {} (conflict)", path),
+        }
+    }
+}
+
+/// Ordered list of actions a run would take
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+}
+
+impl SyncPlan {
+    /// Actions that touch a backend
+    pub fn changes(&self) -> impl Iterator<Item = &SyncAction> {
+        self.actions.iter().filter(|a| a.is_change())
+    }
+
+    /// Whether the two sides are already in sync
+    pub fn is_empty(&self) -> bool {
+        self.changes().next().is_none()
+    }
+}
+
+/// Outcome of applying a plan
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Actions carried out
+    pub applied: usize,
+    /// Conflicts left alone
+    pub skipped: usize,
+    /// Actions that failed; the run carries on past them
+    pub errors: Vec<String>,
+}
+
+/// Handler asked to settle conflicts under [`ConflictPolicy::Prompt`]
+///
+/// It returns a future so that a handler waiting on the user can do so off
+/// the async workers.
+pub type PromptFn = Box<dyn Fn(Conflict) -> BoxFuture<'static, Resolution> + Send + Sync>;
+
+/// How one side changed relative to the base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Not in the base and not present
+    Absent,
+    Created,
+    Modified,
+    Deleted,
+    Unchanged,
+}
+
+fn change(base: Option<&Snapshot>, current: Option<&Snapshot>) -> Change {
+    match (base, current) {
+        (None, None) => Change::Absent,
+        (None, Some(_)) => Change::Created,
+        (Some(_), None) => Change::Deleted,
+        (Some(b), Some(c)) if b.same_version(c) => Change::Unchanged,
+        (Some(_), Some(_)) => Change::Modified,
+    }
+}
+
+/// Whether two snapshots on the same side plausibly hold the same content
+fn same_content(a: &Snapshot, b: &Snapshot) -> bool {
+    if a.directory || b.directory || a.size != b.size {
+        return false;
+    }
+    match (&a.content_hash, &b.content_hash) {
+        (Some(x), Some(y)) => x == y,
+        _ => a.modified.is_some() && a.modified == b.modified,
+    }
+}
+
+/// Name the losing copy of a conflict is kept under
+fn conflict_name(path: &str) -> String {
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => {
+            format!("{}{}.conflict-{}.{}", dir, stem, stamp, ext)
+        }
+        _ => format!("{}{}.conflict-{}", dir, name, stamp),
+    }
+}
+
+/// Two-way sync between a source and a destination tree
+pub struct SyncEngine {
+    source: Arc<dyn StorageBackend>,
+    source_root: VirtualPath,
+    destination: Arc<dyn StorageBackend>,
+    destination_root: VirtualPath,
+    state: SyncState,
+    policy: ConflictPolicy,
+    prompt: Option<PromptFn>,
+    include_hidden: bool,
+}
+
+impl SyncEngine {
+    pub fn new(
+        source: Arc<dyn StorageBackend>,
+        source_root: VirtualPath,
+        destination: Arc<dyn StorageBackend>,
+        destination_root: VirtualPath,
+        state: SyncState,
+    ) -> Self {
+        Self {
+            source,
+            source_root,
+            destination,
+            destination_root,
+            state,
+            policy: ConflictPolicy::default(),
+            prompt: None,
+            include_hidden: false,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: PromptFn) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    pub fn with_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    fn side(&self, side: Side) -> (&dyn StorageBackend, &VirtualPath) {
+        match side {
+            Side::Source => (self.source.as_ref(), &self.source_root),
+            Side::Destination => (self.destination.as_ref(), &self.destination_root),
+        }
+    }
+
+    async fn scan(&self, side: Side) -> CfkResult<HashMap<String, Snapshot>> {
+        let (backend, root) = self.side(side);
+        Ok(walk(backend, root, self.include_hidden)
+            .await?
+            .iter()
+            .filter_map(|e| Some((relative(root, &e.path)?, Snapshot::of(e))))
+            .collect())
+    }
+
+    /// Compare both trees with the base and work out what to do
+    pub async fn plan(&self) -> CfkResult<SyncPlan> {
+        let base = self.state.records()?;
+        let source = self.scan(Side::Source).await?;
+        let destination = self.scan(Side::Destination).await?;
+
+        let paths: BTreeSet<&String> = base
+            .keys()
+            .chain(source.keys())
+            .chain(destination.keys())
+            .collect();
+
+        let mut actions = Vec::new();
+        let mut handled = BTreeSet::new();
+
+        // Renames: on each side, pair deleted files with identical new ones
+        for side in [Side::Source, Side::Destination] {
+            let (current, other) = match side {
+                Side::Source => (&source, &destination),
+                Side::Destination => (&destination, &source),
+            };
+            let base_of = |r: &SyncRecord| match side {
+                Side::Source => r.source.clone(),
+                Side::Destination => r.destination.clone(),
+            };
+            let other_base_of = |r: &SyncRecord| match side {
+                Side::Source => r.destination.clone(),
+                Side::Destination => r.source.clone(),
+            };
+
+            for (old, record) in &base {
+                if current.contains_key(old) || handled.contains(old) {
+                    continue;
+                }
+                // The other side must still hold the old version to rename it
+                let Some(other_now) = other.get(old) else {
+                    continue;
+                };
+                if !other_base_of(record).same_version(other_now) {
+                    continue;
+                }
+
+                let old_snapshot = base_of(record);
+                let new = current.iter().find(|(path, snapshot)| {
+                    !base.contains_key(*path)
+                        && !other.contains_key(*path)
+                        && !handled.contains(*path)
+                        && same_content(&old_snapshot, snapshot)
+                });
+                if let Some((new, _)) = new {
+                    actions.push(SyncAction::Rename {
+                        from: old.clone(),
+                        to: new.clone(),
+                        on: side.other(),
+                    });
+                    handled.insert(old.clone());
+                    handled.insert(new.clone());
+                }
+            }
+        }
+
+        for path in paths {
+            if handled.contains(path) {
+                continue;
+            }
+            let record = base.get(path);
+            let src = source.get(path);
+            let dst = destination.get(path);
+            let src_change = change(record.map(|r| &r.source), src);
+            let dst_change = change(record.map(|r| &r.destination), dst);
+
+            use Change::*;
+            // A file and a directory at one path: unless the file is just
+            // being replaced, neither may overwrite the other, since the
+            // directory's entries are synced on their own
+            if let (Some(s), Some(d)) = (src, dst) {
+                if s.directory != d.directory {
+                    let (on, file_change) = if d.directory {
+                        (Side::Source, src_change)
+                    } else {
+                        (Side::Destination, dst_change)
+                    };
+                    actions.push(match file_change {
+                        Unchanged => SyncAction::Copy {
+                            path: path.clone(),
+                            to: on,
+                            directory: true,
+                        },
+                        _ => SyncAction::MoveAside {
+                            path: path.clone(),
+                            renamed: conflict_name(path),
+                            on,
+                        },
+                    });
+                    continue;
+                }
+            }
+            let action = match (src_change, dst_change) {
+                (Unchanged, Unchanged) | (Absent, Absent) => None,
+                (Created | Modified, Unchanged | Absent) => {
+                    Some(Self::copy(path, Side::Destination, src))
+                }
+                (Unchanged | Absent, Created | Modified) => {
+                    Some(Self::copy(path, Side::Source, dst))
+                }
+                (Deleted, Unchanged) => Some(Self::delete(path, Side::Destination, dst)),
+                (Unchanged, Deleted) => Some(Self::delete(path, Side::Source, src)),
+                (Deleted, Deleted) => Some(SyncAction::Forget { path: path.clone() }),
+                _ => {
+                    // Changed on both sides; fine if they changed alike
+                    let agree = match (src, dst) {
+                        (Some(s), Some(d)) if s.directory && d.directory => true,
+                        (Some(s), Some(d)) if !s.directory && !d.directory => {
+                            self.identical(path, s, d).await?
+                        }
+                        _ => false,
+                    };
+                    if agree {
+                        Some(SyncAction::Record { path: path.clone() })
+                    } else {
+                        Some(self.resolve(path, src, dst).await)
+                    }
+                }
+            };
+            actions.extend(action);
+        }
+
+        actions.sort_by_key(|a| a.order());
+        Ok(SyncPlan { actions })
+    }
+
+    fn copy(path: &str, to: Side, from: Option<&Snapshot>) -> SyncAction {
+        SyncAction::Copy {
+            path: path.to_string(),
+            to,
+            directory: from.map(|s| s.directory).unwrap_or(false),
+        }
+    }
+
+    fn delete(path: &str, on: Side, current: Option<&Snapshot>) -> SyncAction {
+        SyncAction::Delete {
+            path: path.to_string(),
+            on,
+            directory: current.map(|s| s.directory).unwrap_or(false),
+        }
+    }
+
+    /// Whether both sides hold the same bytes at `path`
+    async fn identical(&self, path: &str, src: &Snapshot, dst: &Snapshot) -> CfkResult<bool> {
+        if src.size != dst.size {
+            return Ok(false);
+        }
+        let source = hash_file(self.source.as_ref(), &self.source_root.join(path)).await?;
+        let destination =
+            hash_file(self.destination.as_ref(), &self.destination_root.join(path)).await?;
+        Ok(source == destination)
+    }
+
+    async fn resolve(
+        &self,
+        path: &str,
+        src: Option<&Snapshot>,
+        dst: Option<&Snapshot>,
+    ) -> SyncAction {
+        let conflict = Conflict {
+            path: path.to_string(),
+            source: src.cloned(),
+            destination: dst.cloned(),
+        };
+
+        let resolution = match self.policy {
+            ConflictPolicy::NewerWins => match (src, dst) {
+                (Some(s), Some(d)) if d.modified > s.modified => Resolution::KeepDestination,
+                (None, Some(_)) => Resolution::KeepDestination,
+                _ => Resolution::KeepSource,
+            },
+            ConflictPolicy::KeepBoth => match (src, dst) {
+                (Some(_), Some(_)) => Resolution::KeepBoth,
+                (None, Some(_)) => Resolution::KeepDestination,
+                _ => Resolution::KeepSource,
+            },
+            ConflictPolicy::Prompt => match &self.prompt {
+                Some(prompt) => prompt(conflict).await,
+                None => Resolution::Skip,
+            },
+        };
+
+        match resolution {
+            Resolution::KeepSource if src.is_some() => Self::copy(path, Side::Destination, src),
+            Resolution::KeepSource => Self::delete(path, Side::Destination, dst),
+            Resolution::KeepDestination if dst.is_some() => Self::copy(path, Side::Source, dst),
+            Resolution::KeepDestination => Self::delete(path, Side::Source, src),
+            Resolution::KeepBoth if src.is_some() && dst.is_some() => SyncAction::KeepBoth {
+                path: path.to_string(),
+                renamed: conflict_name(path),
+            },
+            // Only one version survives; keep it
+            Resolution::KeepBoth if src.is_some() => Self::copy(path, Side::Destination, src),
+            Resolution::KeepBoth => Self::copy(path, Side::Source, dst),
+            Resolution::Skip => SyncAction::Skip {
+                path: path.to_string(),
+            },
+        }
+    }
+
+    /// Carry out a plan, recording every completed action in the state
+    pub async fn apply(&self, plan: &SyncPlan) -> CfkResult<SyncReport> {
+        let mut report = SyncReport::default();
+
+        if !plan.is_empty() {
+            for side in [Side::Source, Side::Destination] {
+                let (backend, root) = self.side(side);
+                if !root.is_root() {
+                    ensure_directory(backend, root).await?;
+                }
+            }
+        }
+
+        for action in &plan.actions {
+            if let SyncAction::Skip { .. } = action {
+                report.skipped += 1;
+                continue;
+            }
+            match self.apply_action(action).await {
+                Ok(()) => {
+                    if action.is_change() {
+                        report.applied += 1;
+                    }
+                }
+                Err(e) => report.errors.push(format!("{}: {}", action, e)),
+            }
+        }
+
+        self.state.flush()?;
+        Ok(report)
+    }
+
+    /// Plan and apply in one go
+    pub async fn run(&self) -> CfkResult<SyncReport> {
+        let plan = self.plan().await?;
+        self.apply(&plan).await
+    }
+
+    async fn apply_action(&self, action: &SyncAction) -> CfkResult<()> {
+        match action {
+            SyncAction::Copy {
+                path,
+                to,
+                directory,
+            } => {
+                let (from_backend, from_root) = self.side(to.other());
+                let (to_backend, to_root) = self.side(*to);
+                let target = to_root.join(path);
+
+                // A file replaced by a directory. A directory is never
+                // deleted here: what is in it may still have to be synced
+                if let Ok(existing) = to_backend.get_metadata(&target).await {
+                    if existing.is_directory() && !*directory {
+                        return Err(CfkError::Conflict(format!(
+                            "{} became a directory since the plan was made",
+                            target
+                        )));
+                    }
+                    if !existing.is_directory() && *directory {
+                        to_backend
+                            .delete(&target, &DeleteOptions::default())
+                            .await?;
+                    }
+                }
+
+                if *directory {
+                    ensure_directory(to_backend, &target).await?;
+                } else {
+                    copy_file(
+                        from_backend,
+                        &from_root.join(path),
+                        to_backend,
+                        &target,
+                        true,
+                    )
+                    .await?;
+                }
+                self.record(path).await
+            }
+            SyncAction::Delete { path, on, .. } => {
+                let (backend, root) = self.side(*on);
+                match backend
+                    .delete(&root.join(path), &DeleteOptions::default())
+                    .await
+                {
+                    Ok(()) | Err(CfkError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+                self.state.remove(path)
+            }
+            SyncAction::Rename { from, to, on } => {
+                let (backend, root) = self.side(*on);
+                backend
+                    .rename(&root.join(from), &root.join(to), &MoveOptions::default())
+                    .await?;
+                self.state.remove(from)?;
+                self.record(to).await
+            }
+            SyncAction::KeepBoth { path, renamed } => {
+                let (dst, dst_root) = self.side(Side::Destination);
+                let (src, src_root) = self.side(Side::Source);
+
+                dst.rename(
+                    &dst_root.join(path),
+                    &dst_root.join(renamed),
+                    &MoveOptions::default(),
+                )
+                .await?;
+                copy_file(src, &src_root.join(path), dst, &dst_root.join(path), true).await?;
+                copy_file(
+                    dst,
+                    &dst_root.join(renamed),
+                    src,
+                    &src_root.join(renamed),
+                    false,
+                )
+                .await?;
+                self.record(path).await?;
+                self.record(renamed).await
+            }
+            SyncAction::MoveAside { path, renamed, on } => {
+                let (backend, root) = self.side(*on);
+                let (other, other_root) = self.side(on.other());
+
+                backend
+                    .rename(
+                        &root.join(path),
+                        &root.join(renamed),
+                        &MoveOptions::default(),
+                    )
+                    .await?;
+                copy_file(
+                    backend,
+                    &root.join(renamed),
+                    other,
+                    &other_root.join(renamed),
+                    false,
+                )
+                .await?;
+                ensure_directory(backend, &root.join(path)).await?;
+                self.record(renamed).await?;
+                self.record(path).await
+            }
+            SyncAction::Record { path } => self.record(path).await,
+            SyncAction::Forget { path } => self.state.remove(path),
+            SyncAction::Skip { .. } => Ok(()),
+        }
+    }
+
+    /// Store both sides of `path` as the new base
+    async fn record(&self, path: &str) -> CfkResult<()> {
+        let source = self
+            .source
+            .get_metadata(&self.source_root.join(path))
+            .await?;
+        let destination = self
+            .destination
+            .get_metadata(&self.destination_root.join(path))
+            .await?;
+        self.state.put(
+            path,
+            &SyncRecord {
+                source: Snapshot::of(&source),
+                destination: Snapshot::of(&destination),
+            },
+        )
+    }
+}
+
+async fn ensure_directory(backend: &dyn StorageBackend, path: &VirtualPath) -> CfkResult<()> {
+    match backend.get_metadata(path).await {
+        Ok(entry) if entry.is_directory() => Ok(()),
+        Ok(_) => Err(CfkError::NotADirectory(path.to_string())),
+        Err(CfkError::NotFound(_)) => match backend.create_directory(path).await {
+            Ok(_) | Err(CfkError::AlreadyExists(_)) => Ok(()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfk_providers::LocalBackend;
+    use futures::{future, FutureExt};
+    use std::path::Path;
+
+    struct Pair {
+        _dirs: Vec<tempfile::TempDir>,
+        source: std::path::PathBuf,
+        destination: std::path::PathBuf,
+        // sled releases its lock from a background thread, so reopening the
+        // database right after a drop can fail; engines share one handle
+        state: SyncState,
+    }
+
+    impl Pair {
+        fn new() -> Self {
+            let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+            Self {
+                source: dirs[0].path().to_path_buf(),
+                destination: dirs[1].path().to_path_buf(),
+                state: SyncState::open(
+                    dirs[2].path().join("state.db"),
+                    &VirtualPath::root("src"),
+                    &VirtualPath::root("dst"),
+                )
+                .unwrap(),
+                _dirs: dirs,
+            }
+        }
+
+        fn engine(&self, policy: ConflictPolicy) -> SyncEngine {
+            let source = Arc::new(LocalBackend::new("src", &self.source));
+            let destination = Arc::new(LocalBackend::new("dst", &self.destination));
+            let source_root = VirtualPath::root("src");
+            let destination_root = VirtualPath::root("dst");
+            SyncEngine::new(
+                source,
+                source_root,
+                destination,
+                destination_root,
+                self.state.clone(),
+            )
+            .with_policy(policy)
+        }
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn read(root: &Path, path: &str) -> String {
+        std::fs::read_to_string(root.join(path)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_initial_sync_merges_both_sides() {
+        let pair = Pair::new();
+        write(&pair.source, "docs/a.txt", "a");
+        write(&pair.destination, "b.txt", "b");
+
+        let report = pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        assert_eq!(read(&pair.destination, "docs/a.txt"), "a");
+        assert_eq!(read(&pair.source, "b.txt"), "b");
+
+        // Nothing left to do on a second run
+        let plan = pair.engine(ConflictPolicy::NewerWins).plan().await.unwrap();
+        assert!(plan.is_empty(), "{:?}", plan.actions);
+    }
+
+    #[tokio::test]
+    async fn test_updates_deletes_and_renames_propagate() {
+        let pair = Pair::new();
+        write(&pair.source, "keep.txt", "v1");
+        write(&pair.source, "gone.txt", "bye");
+        write(&pair.source, "old.txt", "moving");
+        pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+
+        write(&pair.destination, "keep.txt", "v2 from destination");
+        std::fs::remove_file(pair.source.join("gone.txt")).unwrap();
+        std::fs::rename(pair.source.join("old.txt"), pair.source.join("new.txt")).unwrap();
+
+        let engine = pair.engine(ConflictPolicy::NewerWins);
+        let plan = engine.plan().await.unwrap();
+        assert!(plan.actions.contains(&SyncAction::Rename {
+            from: "old.txt".into(),
+            to: "new.txt".into(),
+            on: Side::Destination,
+        }));
+
+        let report = engine.apply(&plan).await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(read(&pair.source, "keep.txt"), "v2 from destination");
+        assert!(!pair.destination.join("gone.txt").exists());
+        assert!(!pair.destination.join("old.txt").exists());
+        assert_eq!(read(&pair.destination, "new.txt"), "moving");
+    }
+
+    #[tokio::test]
+    async fn test_conflict_policies() {
+        let pair = Pair::new();
+        write(&pair.source, "c.txt", "base");
+        pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+
+        write(&pair.source, "c.txt", "source edit");
+        write(&pair.destination, "c.txt", "destination edit!");
+
+        // Prompt without a handler leaves the conflict alone
+        let report = pair.engine(ConflictPolicy::Prompt).run().await.unwrap();
+        assert_eq!(report.skipped, 1);
+        assert_eq!(read(&pair.destination, "c.txt"), "destination edit!");
+
+        let prompted = pair
+            .engine(ConflictPolicy::Prompt)
+            .with_prompt(Box::new(|_| {
+                future::ready(Resolution::KeepDestination).boxed()
+            }));
+        prompted.run().await.unwrap();
+        assert_eq!(read(&pair.source, "c.txt"), "destination edit!");
+
+        write(&pair.source, "c.txt", "source again");
+        write(&pair.destination, "c.txt", "destination again!");
+        let report = pair.engine(ConflictPolicy::KeepBoth).run().await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(read(&pair.destination, "c.txt"), "source again");
+
+        let kept: Vec<String> = std::fs::read_dir(&pair.source)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.starts_with("c.conflict-"))
+            .collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(read(&pair.source, &kept[0]), "destination again!");
+        assert_eq!(read(&pair.destination, &kept[0]), "destination again!");
+    }
+
+    #[tokio::test]
+    async fn test_a_file_against_a_directory_loses_nothing() {
+        let pair = Pair::new();
+        write(&pair.source, "x", "source file");
+        write(&pair.destination, "x/a.txt", "a");
+        write(&pair.destination, "x/sub/b.txt", "b");
+
+        let report = pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        for root in [&pair.source, &pair.destination] {
+            assert_eq!(read(root, "x/a.txt"), "a");
+            assert_eq!(read(root, "x/sub/b.txt"), "b");
+            let kept: Vec<String> = std::fs::read_dir(root)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .filter(|n| n.starts_with("x.conflict-"))
+                .collect();
+            assert_eq!(kept.len(), 1);
+            assert_eq!(read(root, &kept[0]), "source file");
+        }
+        let plan = pair.engine(ConflictPolicy::NewerWins).plan().await.unwrap();
+        assert!(plan.is_empty(), "{:?}", plan.actions);
+
+        // A file replaced by a directory on purpose is simply replaced
+        std::fs::remove_dir_all(pair.source.join("x")).unwrap();
+        std::fs::remove_dir_all(pair.destination.join("x")).unwrap();
+        pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+        write(&pair.source, "y", "old");
+        pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+        std::fs::remove_file(pair.source.join("y")).unwrap();
+        write(&pair.source, "y/new.txt", "new");
+        let report = pair.engine(ConflictPolicy::NewerWins).run().await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(read(&pair.destination, "y/new.txt"), "new");
+    }
+
+    #[test]
+    fn test_conflict_name() {
+        assert!(conflict_name("a/report.txt").starts_with("a/report.conflict-"));
+        assert!(conflict_name("a/report.txt").ends_with(".txt"));
+        assert!(conflict_name(".bashrc").starts_with(".bashrc.conflict-"));
+    }
+}
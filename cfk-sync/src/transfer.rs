@@ -0,0 +1,691 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Parallel transfer engine
+//!
+//! Copies a file or a whole directory tree from one place to another, on the
+//! same backend or across two. Directories are created up front, then file
+//! copies run concurrently up to the configured parallelism. A failed file
+//! does not stop the others; failures are collected in the report.
+//!
+//! Large files going to a backend with resumable uploads are sent in chunks
+//! through an upload session. With an [`UploadStore`] attached, the session
+//! is saved after every chunk, and a later copy of the same unchanged file
+//! picks up where the interrupted one stopped.
+
+use crate::{download, relative, walk, DownloadOptions};
+use bytes::{Bytes, BytesMut};
+use cfk_cache::{PendingUpload, SourceFingerprint, UploadStore};
+use cfk_core::{
+    operations::{CopyOptions, ReadOptions, WriteOptions},
+    CfkError, CfkResult, Entry, Metadata, ResumableUpload, StorageBackend, UploadSession,
+    VirtualPath,
+};
+use futures::{stream, StreamExt};
+use std::sync::Arc;
+
+/// Transfer options
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    /// Number of files copied at once
+    pub parallelism: usize,
+    /// Replace existing destination files
+    pub overwrite: bool,
+    /// Carry modification times and permissions over, where the
+    /// destination backend supports it
+    pub preserve_metadata: bool,
+    pub include_hidden: bool,
+    /// Files at least this large use a resumable upload session when the
+    /// destination supports one
+    pub resumable_threshold: u64,
+    /// How large files are read from backends with range reads
+    pub download: DownloadOptions,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            parallelism: 4,
+            overwrite: false,
+            preserve_metadata: true,
+            include_hidden: true,
+            resumable_threshold: 64 * 1024 * 1024,
+            download: DownloadOptions::default(),
+        }
+    }
+}
+
+/// Progress callbacks of a transfer
+///
+/// Paths are relative to the transfer roots (empty for a single file).
+/// Callbacks come from concurrent copies and must not block.
+pub trait TransferProgress: Send + Sync {
+    /// Everything to copy is known
+    fn queued(&self, _files: u64, _bytes: u64) {}
+    fn file_started(&self, _path: &str, _size: Option<u64>) {}
+    fn file_progress(&self, _path: &str, _bytes: u64) {}
+    fn file_finished(&self, _path: &str, _result: &CfkResult<u64>) {}
+}
+
+/// Progress sink that ignores everything
+pub struct NoProgress;
+
+impl TransferProgress for NoProgress {}
+
+/// Outcome of a transfer
+#[derive(Debug, Clone, Default)]
+pub struct TransferReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub directories: u64,
+    /// Paths that failed, with the reason
+    pub errors: Vec<String>,
+}
+
+/// Copies trees between backends
+pub struct TransferEngine {
+    source: Arc<dyn StorageBackend>,
+    destination: Arc<dyn StorageBackend>,
+    options: TransferOptions,
+    progress: Arc<dyn TransferProgress>,
+    uploads: Option<UploadStore>,
+}
+
+struct Job {
+    relative: String,
+    from: VirtualPath,
+    to: VirtualPath,
+    metadata: Metadata,
+}
+
+impl TransferEngine {
+    pub fn new(
+        source: Arc<dyn StorageBackend>,
+        destination: Arc<dyn StorageBackend>,
+        options: TransferOptions,
+    ) -> Self {
+        Self {
+            source,
+            destination,
+            options,
+            progress: Arc::new(NoProgress),
+            uploads: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: Arc<dyn TransferProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Keep resumable upload sessions in `uploads` so they outlive the process
+    pub fn with_uploads(mut self, uploads: UploadStore) -> Self {
+        self.uploads = Some(uploads);
+        self
+    }
+
+    /// Copy `from` to `to`, recursing into directories
+    ///
+    /// A directory's contents end up directly below `to`, which is created
+    /// if needed.
+    pub async fn copy(&self, from: &VirtualPath, to: &VirtualPath) -> CfkResult<TransferReport> {
+        let root = self.source.get_metadata(from).await?;
+        let mut report = TransferReport::default();
+
+        if !root.is_directory() {
+            let job = Job {
+                relative: String::new(),
+                from: from.clone(),
+                to: to.clone(),
+                metadata: root.metadata,
+            };
+            self.progress.queued(1, job.metadata.size.unwrap_or(0));
+            self.run(vec![job], &mut report).await;
+            return Ok(report);
+        }
+
+        let entries = walk(self.source.as_ref(), from, self.options.include_hidden).await?;
+        let mut directories = vec![(to.clone(), root.metadata)];
+        let mut jobs = Vec::new();
+        for entry in entries {
+            let Some(rel) = relative(from, &entry.path) else {
+                continue;
+            };
+            let target = to.join(&rel);
+            match entry.is_directory() {
+                true => directories.push((target, entry.metadata)),
+                false => jobs.push(Job {
+                    relative: rel,
+                    from: entry.path,
+                    to: target,
+                    metadata: entry.metadata,
+                }),
+            }
+        }
+
+        // Parents come first, so each level exists before its children
+        let mut created = Vec::new();
+        for (dir, metadata) in directories {
+            match self.ensure_directory(&dir).await {
+                Ok(()) => {
+                    report.directories += 1;
+                    created.push((dir, metadata));
+                }
+                Err(e) => report.errors.push(format!("{}: {}", dir, e)),
+            }
+        }
+
+        let bytes = jobs.iter().filter_map(|j| j.metadata.size).sum();
+        self.progress.queued(jobs.len() as u64, bytes);
+        self.run(jobs, &mut report).await;
+
+        // Children touch their parent's mtime, so directories go last,
+        // deepest first
+        if self.options.preserve_metadata {
+            for (dir, metadata) in created.iter().rev() {
+                self.preserve(dir, metadata).await;
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn ensure_directory(&self, path: &VirtualPath) -> CfkResult<()> {
+        match self.destination.get_metadata(path).await {
+            Ok(entry) if entry.is_directory() => Ok(()),
+            Ok(_) => Err(CfkError::NotADirectory(path.to_string())),
+            Err(CfkError::NotFound(_)) => self.destination.create_directory(path).await.map(drop),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn run(&self, jobs: Vec<Job>, report: &mut TransferReport) {
+        let mut results = stream::iter(jobs)
+            .map(|job| async move {
+                self.progress.file_started(&job.relative, job.metadata.size);
+                let result = self.copy_file(&job).await;
+                self.progress.file_finished(&job.relative, &result);
+                (job, result)
+            })
+            .buffer_unordered(self.options.parallelism.max(1));
+
+        while let Some((job, result)) = results.next().await {
+            match result {
+                Ok(bytes) => {
+                    report.files += 1;
+                    report.bytes += bytes;
+                }
+                Err(e) => report.errors.push(format!("{}: {}", job.from, e)),
+            }
+        }
+    }
+
+    async fn copy_file(&self, job: &Job) -> CfkResult<u64> {
+        // Server-side copy when both ends are the same backend
+        if self.source.id() == self.destination.id() {
+            let options = CopyOptions {
+                overwrite: self.options.overwrite,
+                preserve_metadata: self.options.preserve_metadata,
+            };
+            let entry = self.source.copy(&job.from, &job.to, &options).await?;
+            let bytes = entry.metadata.size.or(job.metadata.size).unwrap_or(0);
+            self.progress.file_progress(&job.relative, bytes);
+            return Ok(bytes);
+        }
+
+        if !self.options.overwrite {
+            match self.destination.get_metadata(&job.to).await {
+                Ok(_) => return Err(CfkError::AlreadyExists(job.to.to_string())),
+                Err(CfkError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let entry = match self.destination.resumable() {
+            Some(uploader)
+                if job.metadata.size.unwrap_or(0) >= self.options.resumable_threshold.max(1) =>
+            {
+                self.upload_resumable(job, uploader).await?
+            }
+            _ => self.upload_stream(job).await?,
+        };
+
+        if self.options.preserve_metadata {
+            self.preserve(&job.to, &job.metadata).await;
+        }
+        Ok(entry.metadata.size.or(job.metadata.size).unwrap_or(0))
+    }
+
+    async fn upload_stream(&self, job: &Job) -> CfkResult<Entry> {
+        let progress = self.progress.clone();
+        let relative = job.relative.clone();
+        let source = self.source.clone();
+        let stream =
+            download::read_file(source, &job.from, job.metadata.size, &self.options.download)
+                .await?
+                .inspect(move |chunk| {
+                    if let Ok(chunk) = chunk {
+                        progress.file_progress(&relative, chunk.len() as u64);
+                    }
+                });
+
+        let options = WriteOptions {
+            overwrite: self.options.overwrite,
+            create_parents: true,
+            ..Default::default()
+        };
+        self.destination
+            .write_file_stream(&job.to, Box::pin(stream), job.metadata.size, &options)
+            .await
+    }
+
+    async fn upload_resumable(
+        &self,
+        job: &Job,
+        uploader: &dyn ResumableUpload,
+    ) -> CfkResult<Entry> {
+        let size = job.metadata.size.unwrap_or(0);
+        let source = job.from.to_uri();
+        let fingerprint = SourceFingerprint {
+            size: job.metadata.size,
+            modified: job.metadata.modified.map(|t| t.timestamp()),
+            content_hash: job.metadata.content_hash.clone(),
+            revision: job.metadata.revision.clone(),
+        };
+
+        let mut session = match self.resume(job, uploader, &source, &fingerprint).await? {
+            Some(session) => session,
+            None => {
+                let options = WriteOptions {
+                    overwrite: self.options.overwrite,
+                    create_parents: true,
+                    ..Default::default()
+                };
+                uploader.create_session(&job.to, size, &options).await?
+            }
+        };
+        let save = |session: &UploadSession| match &self.uploads {
+            Some(uploads) => uploads.put(&PendingUpload {
+                source: source.clone(),
+                fingerprint: fingerprint.clone(),
+                session: session.clone(),
+            }),
+            None => Ok(()),
+        };
+        save(&session)?;
+        self.progress.file_progress(&job.relative, session.offset);
+
+        while !session.is_complete() {
+            let len = session.next_chunk_len();
+            let chunk = self.read_chunk(&job.from, session.offset, len).await?;
+            uploader.upload_chunk(&mut session, chunk).await?;
+            save(&session)?;
+            self.progress.file_progress(&job.relative, len);
+        }
+
+        let entry = uploader.finalize(&session).await?;
+        if let Some(uploads) = &self.uploads {
+            uploads.remove(&job.to)?;
+        }
+        Ok(entry)
+    }
+
+    /// A stored session for this exact source that the server still has
+    ///
+    /// Sessions for a different or changed source are aborted and dropped.
+    async fn resume(
+        &self,
+        job: &Job,
+        uploader: &dyn ResumableUpload,
+        source: &str,
+        fingerprint: &SourceFingerprint,
+    ) -> CfkResult<Option<UploadSession>> {
+        let Some(uploads) = &self.uploads else {
+            return Ok(None);
+        };
+        let Some(pending) = uploads.get(&job.to)? else {
+            return Ok(None);
+        };
+
+        let mut session = pending.session;
+        if pending.source == source
+            && pending.fingerprint == *fingerprint
+            && fingerprint.size == Some(session.size)
+        {
+            match uploader.query_offset(&mut session).await {
+                Ok(offset) => {
+                    tracing::info!("resuming upload of {} at {} bytes", job.to, offset);
+                    return Ok(Some(session));
+                }
+                Err(e) => tracing::debug!("cannot resume upload of {}: {}", job.to, e),
+            }
+        }
+
+        if let Err(e) = uploader.abort(&session).await {
+            tracing::debug!("could not abort stale upload of {}: {}", job.to, e);
+        }
+        uploads.remove(&job.to)?;
+        Ok(None)
+    }
+
+    /// Exactly `len` bytes of `path` starting at `offset`
+    async fn read_chunk(&self, path: &VirtualPath, offset: u64, len: u64) -> CfkResult<Bytes> {
+        let options = ReadOptions {
+            range: Some((offset, offset + len)),
+            ..Default::default()
+        };
+        let mut stream = self.source.read_file(path, &options).await?;
+        let mut chunk = BytesMut::with_capacity(len as usize);
+        while let Some(data) = stream.next().await {
+            chunk.extend_from_slice(&data?);
+        }
+        if chunk.len() as u64 != len {
+            return Err(CfkError::Other(format!(
+                "{} changed during upload: read {} of {} bytes at {}",
+                path,
+                chunk.len(),
+                len,
+                offset
+            )));
+        }
+        Ok(chunk.freeze())
+    }
+
+    /// Best effort: backends that cannot set metadata keep their own
+    async fn preserve(&self, path: &VirtualPath, metadata: &Metadata) {
+        match self.destination.set_metadata(path, metadata).await {
+            Ok(()) | Err(CfkError::Unsupported(_)) => {}
+            Err(e) => tracing::debug!("could not preserve metadata of {}: {}", path, e),
+        }
+    }
+}
+
+/// Where a copy of `source` into `destination` should land, `cp` style
+///
+/// Copying into an existing directory puts the source inside it under its
+/// own name; otherwise `destination` is the new name.
+pub async fn copy_target(
+    source: &VirtualPath,
+    destination_backend: &dyn StorageBackend,
+    destination: &VirtualPath,
+) -> CfkResult<VirtualPath> {
+    match destination_backend.get_metadata(destination).await {
+        Ok(entry) if entry.is_directory() => match source.name() {
+            Some(name) => Ok(destination.join(name)),
+            None => Ok(destination.clone()),
+        },
+        Ok(_) | Err(CfkError::NotFound(_)) => Ok(destination.clone()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use cfk_core::{
+        backend::{ByteStream, SpaceInfo, StorageCapabilities},
+        entry::DirectoryListing,
+        operations::{DeleteOptions, ListOptions, MoveOptions},
+    };
+    use cfk_providers::LocalBackend;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Counter {
+        queued: AtomicU64,
+        progressed: AtomicU64,
+        finished: AtomicU64,
+    }
+
+    impl TransferProgress for Counter {
+        fn queued(&self, files: u64, _bytes: u64) {
+            self.queued.store(files, Ordering::SeqCst);
+        }
+        fn file_progress(&self, _path: &str, bytes: u64) {
+            self.progressed.fetch_add(bytes, Ordering::SeqCst);
+        }
+        fn file_finished(&self, _path: &str, _result: &CfkResult<u64>) {
+            self.finished.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recursive_copy_across_backends() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            let dir = src.path().join(format!("d{}", i % 3)).join("nested");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(format!("f{}.txt", i)), "0123456789").unwrap();
+        }
+        std::fs::create_dir_all(src.path().join("empty")).unwrap();
+
+        let counter = Arc::new(Counter::default());
+        let engine = TransferEngine::new(
+            Arc::new(LocalBackend::new("src", src.path())),
+            Arc::new(LocalBackend::new("dst", dst.path())),
+            TransferOptions::default(),
+        )
+        .with_progress(counter.clone());
+
+        let report = engine
+            .copy(&VirtualPath::root("src"), &VirtualPath::new("dst", "/copy"))
+            .await
+            .unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.files, 10);
+        assert_eq!(report.bytes, 100);
+        assert_eq!(counter.queued.load(Ordering::SeqCst), 10);
+        assert_eq!(counter.progressed.load(Ordering::SeqCst), 100);
+        assert_eq!(counter.finished.load(Ordering::SeqCst), 10);
+        assert!(dst.path().join("copy/d2/nested/f8.txt").is_file());
+        assert!(dst.path().join("copy/empty").is_dir());
+
+        // Metadata came along
+        let a = std::fs::metadata(src.path().join("d0/nested/f0.txt")).unwrap();
+        let b = std::fs::metadata(dst.path().join("copy/d0/nested/f0.txt")).unwrap();
+        assert_eq!(a.modified().unwrap(), b.modified().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failures_do_not_stop_the_rest() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        std::fs::write(src.path().join("a.txt"), "a").unwrap();
+        std::fs::write(src.path().join("b.txt"), "b").unwrap();
+        std::fs::write(dst.path().join("a.txt"), "existing").unwrap();
+
+        let engine = TransferEngine::new(
+            Arc::new(LocalBackend::new("src", src.path())),
+            Arc::new(LocalBackend::new("dst", dst.path())),
+            TransferOptions::default(),
+        );
+        let report = engine
+            .copy(&VirtualPath::root("src"), &VirtualPath::root("dst"))
+            .await
+            .unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("a.txt")).unwrap(),
+            "existing"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("b.txt")).unwrap(),
+            "b"
+        );
+    }
+
+    /// A local backend with an in-memory upload session that can be made
+    /// to fail partway through
+    struct Chunked {
+        inner: LocalBackend,
+        staged: Mutex<Vec<u8>>,
+        chunks: AtomicUsize,
+        fail_at: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ResumableUpload for Chunked {
+        async fn create_session(
+            &self,
+            path: &VirtualPath,
+            size: u64,
+            options: &WriteOptions,
+        ) -> CfkResult<UploadSession> {
+            self.staged.lock().unwrap().clear();
+            Ok(UploadSession::new(
+                path.clone(),
+                size,
+                "s",
+                1024,
+                options.overwrite,
+            ))
+        }
+
+        async fn upload_chunk(&self, session: &mut UploadSession, data: Bytes) -> CfkResult<()> {
+            if self.chunks.fetch_add(1, Ordering::SeqCst) + 1 == self.fail_at.load(Ordering::SeqCst)
+            {
+                return Err(CfkError::Network("connection reset".into()));
+            }
+            self.staged.lock().unwrap().extend_from_slice(&data);
+            session.offset += data.len() as u64;
+            Ok(())
+        }
+
+        async fn query_offset(&self, session: &mut UploadSession) -> CfkResult<u64> {
+            session.offset = self.staged.lock().unwrap().len() as u64;
+            Ok(session.offset)
+        }
+
+        async fn finalize(&self, session: &UploadSession) -> CfkResult<Entry> {
+            let data = Bytes::from(self.staged.lock().unwrap().clone());
+            self.inner
+                .write_file(&session.path, data, &WriteOptions::default())
+                .await
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for Chunked {
+        fn id(&self) -> &str {
+            self.inner.id()
+        }
+        fn display_name(&self) -> &str {
+            self.inner.display_name()
+        }
+        fn capabilities(&self) -> &StorageCapabilities {
+            self.inner.capabilities()
+        }
+        fn resumable(&self) -> Option<&dyn ResumableUpload> {
+            Some(self)
+        }
+        async fn is_available(&self) -> bool {
+            true
+        }
+        async fn get_metadata(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            self.inner.get_metadata(path).await
+        }
+        async fn list_directory(
+            &self,
+            path: &VirtualPath,
+            options: &ListOptions,
+        ) -> CfkResult<DirectoryListing> {
+            self.inner.list_directory(path, options).await
+        }
+        async fn read_file(
+            &self,
+            path: &VirtualPath,
+            options: &ReadOptions,
+        ) -> CfkResult<ByteStream> {
+            self.inner.read_file(path, options).await
+        }
+        async fn write_file(
+            &self,
+            path: &VirtualPath,
+            data: Bytes,
+            options: &WriteOptions,
+        ) -> CfkResult<Entry> {
+            self.inner.write_file(path, data, options).await
+        }
+        async fn write_file_stream(
+            &self,
+            path: &VirtualPath,
+            stream: ByteStream,
+            size_hint: Option<u64>,
+            options: &WriteOptions,
+        ) -> CfkResult<Entry> {
+            self.inner
+                .write_file_stream(path, stream, size_hint, options)
+                .await
+        }
+        async fn create_directory(&self, path: &VirtualPath) -> CfkResult<Entry> {
+            self.inner.create_directory(path).await
+        }
+        async fn delete(&self, path: &VirtualPath, options: &DeleteOptions) -> CfkResult<()> {
+            self.inner.delete(path, options).await
+        }
+        async fn copy(
+            &self,
+            source: &VirtualPath,
+            dest: &VirtualPath,
+            options: &CopyOptions,
+        ) -> CfkResult<Entry> {
+            self.inner.copy(source, dest, options).await
+        }
+        async fn rename(
+            &self,
+            source: &VirtualPath,
+            dest: &VirtualPath,
+            options: &MoveOptions,
+        ) -> CfkResult<Entry> {
+            self.inner.rename(source, dest, options).await
+        }
+        async fn get_space_info(&self) -> CfkResult<SpaceInfo> {
+            self.inner.get_space_info().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_upload_resumes() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        std::fs::write(src.path().join("big.bin"), &data).unwrap();
+
+        let destination = Arc::new(Chunked {
+            inner: LocalBackend::new("dst", dst.path()),
+            staged: Mutex::new(Vec::new()),
+            chunks: AtomicUsize::new(0),
+            fail_at: AtomicUsize::new(4),
+        });
+        let uploads = UploadStore::open(src.path().join("uploads.db")).unwrap();
+        let options = TransferOptions {
+            resumable_threshold: 1,
+            ..Default::default()
+        };
+        let engine = TransferEngine::new(
+            Arc::new(LocalBackend::new("src", src.path())),
+            destination.clone(),
+            options,
+        )
+        .with_uploads(uploads.clone());
+        let from = VirtualPath::new("src", "/big.bin");
+        let to = VirtualPath::new("dst", "/big.bin");
+
+        let report = engine.copy(&from, &to).await.unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(uploads.get(&to).unwrap().unwrap().session.offset, 3072);
+        assert!(!dst.path().join("big.bin").exists());
+
+        // The second run only sends the seven chunks that are missing
+        destination.fail_at.store(0, Ordering::SeqCst);
+        destination.chunks.store(0, Ordering::SeqCst);
+        let report = engine.copy(&from, &to).await.unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.bytes, 10_000);
+        assert_eq!(destination.chunks.load(Ordering::SeqCst), 7);
+        assert_eq!(std::fs::read(dst.path().join("big.bin")).unwrap(), data);
+        assert_eq!(uploads.get(&to).unwrap(), None);
+    }
+}
@@ -0,0 +1,986 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! `fuser::Filesystem` over a `StorageBackend`
+//!
+//! FUSE callbacks are synchronous and run on the session thread, so every
+//! backend call is driven to completion on the tokio runtime handle captured
+//! at mount time.
+//!
+//! Open files are buffered: reads go straight to the backend (ranged when the
+//! backend supports it), while the first write pulls the whole file into
+//! memory and the buffer is uploaded again on flush, fsync or release.
+//! Files that would outgrow [`MountOptions::max_file_size`] fail with EFBIG.
+
+use bytes::Bytes;
+use cfk_core::{
+    backend::ByteStream,
+    operations::{DeleteOptions, ListOptions, MoveOptions, ReadOptions, WriteOptions},
+    ChangeEvent, ChangeKind, CfkError, CfkResult, Entry, EntryKind, StorageBackend, VirtualPath,
+};
+use dashmap::DashMap;
+use fuser::{
+    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request, TimeOrNow,
+};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+
+use crate::inode::{InodeTable, ROOT_INODE};
+use crate::MountOptions;
+
+/// Kernel attribute TTL when caching is on but no timeout was given
+const DEFAULT_CACHE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BLOCK_SIZE: u32 = 4096;
+
+/// Largest file writes may buffer when no limit was given
+const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// A file opened through FUSE
+struct OpenFile {
+    ino: u64,
+    path: VirtualPath,
+    /// Whole contents, once a write (or a backend without range support)
+    /// required loading them
+    buffer: Option<Vec<u8>>,
+    /// Buffer differs from the backend copy
+    dirty: bool,
+}
+
+/// A directory snapshot taken at opendir
+struct OpenDir {
+    entries: Vec<(u64, FileType, String)>,
+}
+
+/// Drops attributes, here and in the kernel, that backend changes made
+/// stale
+pub struct Invalidator {
+    inodes: Arc<InodeTable>,
+    attrs: Arc<DashMap<u64, (FileAttr, Instant)>>,
+}
+
+impl Invalidator {
+    /// Watch the whole backend until the stream ends or the mount goes
+    /// away
+    pub async fn follow(
+        self,
+        backend: Arc<dyn StorageBackend>,
+        notifier: fuser::Notifier,
+        alive: Arc<AtomicBool>,
+    ) {
+        let root = VirtualPath::root(backend.id());
+        let mut changes = match backend.watch(&root, true).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                tracing::warn!("not following changes to {}: {}", backend.id(), e);
+                return;
+            }
+        };
+        while let Some(change) = changes.next().await {
+            if !alive.load(Ordering::SeqCst) {
+                break;
+            }
+            match change {
+                Ok(change) => self.apply(&change, &notifier),
+                Err(e) => tracing::debug!("change notification from {}: {}", backend.id(), e),
+            }
+        }
+    }
+
+    fn apply(&self, change: &ChangeEvent, notifier: &fuser::Notifier) {
+        let stale = match change.kind {
+            ChangeKind::Rescan => self.inodes.subtree(&change.path),
+            _ => change
+                .stale_paths()
+                .iter()
+                .filter_map(|p| self.inodes.find(p))
+                .collect(),
+        };
+        for ino in stale {
+            self.attrs.remove(&ino);
+            // Attributes and all cached data; fails for inodes the kernel
+            // has already forgotten
+            let _ = notifier.inval_inode(ino, 0, 0);
+        }
+
+        // Names looked up before must be looked up again
+        let names = match &change.kind {
+            ChangeKind::Renamed { from } => vec![&change.path, from],
+            ChangeKind::Rescan => Vec::new(),
+            _ => vec![&change.path],
+        };
+        for path in names {
+            if let (Some(parent), Some(name)) = (path.parent(), path.name()) {
+                if let Some(parent) = self.inodes.find(&parent) {
+                    let _ = notifier.inval_entry(parent, OsStr::new(name));
+                }
+            }
+        }
+
+        if let ChangeKind::Renamed { from } = &change.kind {
+            self.inodes.rename(from, &change.path);
+        }
+    }
+}
+
+/// FUSE filesystem serving one backend
+pub struct CfkFilesystem {
+    backend: Arc<dyn StorageBackend>,
+    runtime: Handle,
+    options: MountOptions,
+    inodes: Arc<InodeTable>,
+    /// Attributes fetched from the backend, when `options.cache` is set
+    attrs: Arc<DashMap<u64, (FileAttr, Instant)>>,
+    files: HashMap<u64, OpenFile>,
+    dirs: HashMap<u64, OpenDir>,
+    next_handle: u64,
+    uid: u32,
+    gid: u32,
+    /// Cleared when the kernel tears the session down
+    alive: Arc<AtomicBool>,
+}
+
+impl CfkFilesystem {
+    pub fn new(
+        backend: Arc<dyn StorageBackend>,
+        runtime: Handle,
+        options: MountOptions,
+        alive: Arc<AtomicBool>,
+    ) -> Self {
+        let root = VirtualPath::root(backend.id());
+
+        // SAFETY: getuid/getgid cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        Self {
+            backend,
+            runtime,
+            options,
+            inodes: Arc::new(InodeTable::new(root)),
+            attrs: Arc::new(DashMap::new()),
+            files: HashMap::new(),
+            dirs: HashMap::new(),
+            next_handle: 1,
+            uid,
+            gid,
+            alive,
+        }
+    }
+
+    /// Something to keep the caches of this filesystem current from the
+    /// backend's change notifications once it is mounted
+    pub fn invalidator(&self) -> Invalidator {
+        Invalidator {
+            inodes: self.inodes.clone(),
+            attrs: self.attrs.clone(),
+        }
+    }
+
+    /// How long the kernel (and this filesystem) may trust attributes
+    fn ttl(&self) -> Duration {
+        if self.options.cache {
+            self.options
+                .cache_timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CACHE_TIMEOUT)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn max_file_size(&self) -> u64 {
+        self.options.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE)
+    }
+
+    fn trace(&self, op: &str, detail: impl std::fmt::Display) {
+        if self.options.debug {
+            tracing::info!("fuse {} {}", op, detail);
+        }
+    }
+
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    fn handle(&mut self) -> u64 {
+        let fh = self.next_handle;
+        self.next_handle += 1;
+        fh
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<VirtualPath, i32> {
+        let parent = self.inodes.path(parent).ok_or(libc::ENOENT)?;
+        let name = name.to_str().ok_or(libc::EINVAL)?;
+        Ok(parent.join(name))
+    }
+
+    fn to_attr(&self, ino: u64, entry: &Entry) -> FileAttr {
+        let (kind, default_perm, nlink) = match entry.kind {
+            EntryKind::Directory => (FileType::Directory, 0o755, 2),
+            EntryKind::Symlink => (FileType::Symlink, 0o777, 1),
+            _ => (FileType::RegularFile, 0o644, 1),
+        };
+
+        let mut perm = entry
+            .metadata
+            .permissions
+            .map(|p| (p.mode & 0o7777) as u16)
+            .unwrap_or(default_perm);
+        if self.options.read_only {
+            perm &= !0o222;
+        }
+
+        let size = entry.metadata.size.unwrap_or(0);
+        let mtime = entry
+            .metadata
+            .modified
+            .map(SystemTime::from)
+            .unwrap_or(UNIX_EPOCH);
+
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: entry
+                .metadata
+                .accessed
+                .map(SystemTime::from)
+                .unwrap_or(mtime),
+            mtime,
+            ctime: mtime,
+            crtime: entry
+                .metadata
+                .created
+                .map(SystemTime::from)
+                .unwrap_or(mtime),
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        }
+    }
+
+    /// Remember an entry's attributes and return them
+    fn record(&self, entry: &Entry) -> FileAttr {
+        let ino = self.inodes.inode(&entry.path);
+        let attr = self.to_attr(ino, entry);
+        if self.options.cache {
+            self.attrs.insert(ino, (attr, Instant::now()));
+        }
+        attr
+    }
+
+    fn cached_attr(&self, ino: u64) -> Option<FileAttr> {
+        let (attr, at) = *self.attrs.get(&ino)?;
+        (at.elapsed() < self.ttl()).then_some(attr)
+    }
+
+    fn invalidate(&self, ino: u64) {
+        self.attrs.remove(&ino);
+    }
+
+    /// Attributes for a path, from cache or the backend
+    fn lookup_path(&self, path: &VirtualPath) -> CfkResult<FileAttr> {
+        let ino = self.inodes.inode(path);
+        if let Some(attr) = self.cached_attr(ino) {
+            return Ok(attr);
+        }
+
+        let entry = self.block_on(self.backend.get_metadata(path))?;
+        Ok(self.record(&entry))
+    }
+
+    /// Size of any unflushed buffer for an inode
+    fn buffered_size(&self, ino: u64) -> Option<u64> {
+        self.files
+            .values()
+            .find(|f| f.ino == ino && f.dirty)
+            .and_then(|f| f.buffer.as_ref())
+            .map(|b| b.len() as u64)
+    }
+
+    fn read_options(&self, range: Option<(u64, u64)>) -> ReadOptions {
+        ReadOptions {
+            range,
+            use_cache: self.options.cache,
+        }
+    }
+
+    /// The whole file, as long as it fits in a write buffer
+    fn read_all(&self, path: &VirtualPath) -> CfkResult<Vec<u8>> {
+        self.block_on(async {
+            let stream = self
+                .backend
+                .read_file(path, &self.read_options(None))
+                .await?;
+            collect(stream, self.max_file_size()).await
+        })
+    }
+
+    /// Load the whole file into a handle's buffer if it is not there yet
+    fn ensure_buffer(&mut self, fh: u64) -> CfkResult<()> {
+        let Some(file) = self.files.get(&fh) else {
+            return Err(CfkError::Other("stale file handle".into()));
+        };
+        if file.buffer.is_some() {
+            return Ok(());
+        }
+
+        let data = self.read_all(&file.path)?;
+        if let Some(file) = self.files.get_mut(&fh) {
+            file.buffer = Some(data);
+        }
+        Ok(())
+    }
+
+    /// Upload a dirty buffer
+    fn flush_handle(&mut self, fh: u64) -> CfkResult<()> {
+        let Some(file) = self.files.get(&fh) else {
+            return Ok(());
+        };
+        if !file.dirty {
+            return Ok(());
+        }
+
+        let data = Bytes::from(file.buffer.clone().unwrap_or_default());
+        let path = file.path.clone();
+        let options = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        let entry = self.block_on(self.backend.write_file(&path, data, &options))?;
+
+        self.invalidate(file.ino);
+        self.record(&entry);
+        if let Some(file) = self.files.get_mut(&fh) {
+            file.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Full listing of a directory, following cursors
+    fn list_all(&self, path: &VirtualPath) -> CfkResult<Vec<Entry>> {
+        self.block_on(async {
+            let mut options = ListOptions {
+                include_hidden: true,
+                ..Default::default()
+            };
+            let mut entries = Vec::new();
+
+            loop {
+                let listing = self.backend.list_directory(path, &options).await?;
+                entries.extend(listing.entries);
+                match listing.cursor {
+                    Some(cursor) if listing.has_more => options.cursor = Some(cursor),
+                    _ => return Ok(entries),
+                }
+            }
+        })
+    }
+}
+
+/// Up to `limit` bytes of a stream; longer ones fail with EFBIG
+async fn collect(mut stream: ByteStream, limit: u64) -> CfkResult<Vec<u8>> {
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if (data.len() + chunk.len()) as u64 > limit {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn too_large() -> CfkError {
+    CfkError::Io(std::io::Error::from_raw_os_error(libc::EFBIG))
+}
+
+/// Map a backend error onto an errno
+fn errno(error: &CfkError) -> i32 {
+    match error {
+        CfkError::NotFound(_) | CfkError::BackendNotFound(_) => libc::ENOENT,
+        CfkError::AlreadyExists(_) => libc::EEXIST,
+        CfkError::PermissionDenied(_)
+        | CfkError::AuthRequired(_)
+        | CfkError::AuthFailed(_)
+        | CfkError::TokenExpired => libc::EACCES,
+        CfkError::NotADirectory(_) => libc::ENOTDIR,
+        CfkError::NotAFile(_) => libc::EISDIR,
+        CfkError::DirectoryNotEmpty(_) => libc::ENOTEMPTY,
+        CfkError::InvalidPath(_) => libc::EINVAL,
+        CfkError::QuotaExceeded(_) => libc::ENOSPC,
+        CfkError::Unsupported(_) => libc::ENOSYS,
+        CfkError::Timeout => libc::ETIMEDOUT,
+        CfkError::RateLimited { .. } => libc::EAGAIN,
+        CfkError::Io(e) => e.raw_os_error().unwrap_or(libc::EIO),
+        _ => libc::EIO,
+    }
+}
+
+impl Filesystem for CfkFilesystem {
+    fn init(&mut self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), i32> {
+        self.trace("init", self.backend.id());
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.trace("destroy", self.backend.id());
+        self.alive.store(false, Ordering::SeqCst);
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        self.trace("lookup", &path);
+
+        match self.lookup_path(&path) {
+            Ok(mut attr) => {
+                if let Some(size) = self.buffered_size(attr.ino) {
+                    attr.size = size;
+                }
+                reply.entry(&self.ttl(), &attr, 0)
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        self.trace("getattr", &path);
+
+        let result = if path.is_root() {
+            Ok(self.to_attr(ROOT_INODE, &Entry::directory(path, Default::default())))
+        } else {
+            self.lookup_path(&path)
+        };
+
+        match result {
+            Ok(mut attr) => {
+                if let Some(size) = self.buffered_size(ino) {
+                    attr.size = size;
+                }
+                reply.attr(&self.ttl(), &attr)
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        self.trace("setattr", &path);
+
+        // Only truncation maps onto a backend operation; ownership, mode
+        // and timestamps belong to the provider
+        if let Some(size) = size {
+            if self.options.read_only {
+                return reply.error(libc::EROFS);
+            }
+            if size > self.max_file_size() {
+                return reply.error(libc::EFBIG);
+            }
+
+            let result = match fh.filter(|fh| self.files.contains_key(fh)) {
+                Some(fh) => self.ensure_buffer(fh).map(|_| {
+                    let file = self.files.get_mut(&fh).expect("handle checked above");
+                    if let Some(buffer) = file.buffer.as_mut() {
+                        buffer.resize(size as usize, 0);
+                    }
+                    file.dirty = true;
+                }),
+                None => {
+                    let data = if size == 0 {
+                        Ok(Vec::new())
+                    } else {
+                        self.read_all(&path)
+                    };
+                    data.and_then(|mut data| {
+                        data.resize(size as usize, 0);
+                        let options = WriteOptions {
+                            overwrite: true,
+                            ..Default::default()
+                        };
+                        let entry = self.block_on(self.backend.write_file(
+                            &path,
+                            Bytes::from(data),
+                            &options,
+                        ))?;
+                        self.record(&entry);
+                        Ok(())
+                    })
+                }
+            };
+
+            if let Err(e) = result {
+                return reply.error(errno(&e));
+            }
+            self.invalidate(ino);
+        }
+
+        match self.lookup_path(&path) {
+            Ok(mut attr) => {
+                if let Some(size) = self.buffered_size(ino) {
+                    attr.size = size;
+                }
+                reply.attr(&self.ttl(), &attr)
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        self.trace("mkdir", &path);
+
+        match self.block_on(self.backend.create_directory(&path)) {
+            Ok(entry) => {
+                let attr = self.record(&entry);
+                reply.entry(&self.ttl(), &attr, 0)
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        self.trace("unlink", &path);
+
+        match self.block_on(self.backend.delete(&path, &DeleteOptions::default())) {
+            Ok(()) => {
+                self.invalidate(self.inodes.inode(&path));
+                self.inodes.remove(&path);
+                reply.ok()
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        self.trace("rmdir", &path);
+
+        // Non-recursive: the backend refuses populated directories
+        match self.block_on(self.backend.delete(&path, &DeleteOptions::default())) {
+            Ok(()) => {
+                self.invalidate(self.inodes.inode(&path));
+                self.inodes.remove(&path);
+                reply.ok()
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let (from, to) = match (
+            self.child_path(parent, name),
+            self.child_path(newparent, newname),
+        ) {
+            (Ok(from), Ok(to)) => (from, to),
+            (Err(e), _) | (_, Err(e)) => return reply.error(e),
+        };
+        self.trace("rename", format!("{} -> {}", from, to));
+
+        if flags & libc::RENAME_EXCHANGE != 0 {
+            return reply.error(libc::EINVAL);
+        }
+        let options = MoveOptions {
+            overwrite: flags & libc::RENAME_NOREPLACE == 0,
+        };
+
+        match self.block_on(self.backend.rename(&from, &to, &options)) {
+            Ok(_) => {
+                self.invalidate(self.inodes.inode(&from));
+                self.invalidate(self.inodes.inode(&to));
+                self.inodes.rename(&from, &to);
+                for file in self.files.values_mut() {
+                    if let Some(path) = self.inodes.path(file.ino) {
+                        file.path = path;
+                    }
+                }
+                reply.ok()
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        self.trace("open", &path);
+
+        let writable = flags & libc::O_ACCMODE != libc::O_RDONLY;
+        if writable && self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+
+        // O_TRUNC: start from an empty buffer rather than the old contents
+        let truncate = writable && flags & libc::O_TRUNC != 0;
+        let fh = self.handle();
+        self.files.insert(
+            fh,
+            OpenFile {
+                ino,
+                path,
+                buffer: truncate.then(Vec::new),
+                dirty: truncate,
+            },
+        );
+
+        reply.opened(fh, 0)
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(file) = self.files.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let offset = offset.max(0) as usize;
+        let size = size as usize;
+
+        if let Some(buffer) = &file.buffer {
+            let start = offset.min(buffer.len());
+            let end = offset.saturating_add(size).min(buffer.len());
+            return reply.data(&buffer[start..end]);
+        }
+
+        let path = file.path.clone();
+        self.trace("read", format!("{} @{}+{}", path, offset, size));
+
+        let range = Some((offset as u64, (offset + size) as u64));
+        let result = self.block_on(async {
+            let stream = self
+                .backend
+                .read_file(&path, &self.read_options(range))
+                .await?;
+            collect(stream, self.max_file_size()).await
+        });
+
+        match result {
+            // A ranged read never returns more than asked for, so a longer
+            // body means the backend ignored the range and sent the file
+            Ok(data) if data.len() > size => {
+                let start = offset.min(data.len());
+                let end = offset.saturating_add(size).min(data.len());
+                reply.data(&data[start..end]);
+                if let Some(file) = self.files.get_mut(&fh) {
+                    file.buffer = Some(data);
+                }
+            }
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let end = (offset.max(0) as u64)
+            .checked_add(data.len() as u64)
+            .filter(|&end| end <= self.max_file_size())
+            .and_then(|end| usize::try_from(end).ok());
+        let Some(end) = end else {
+            return reply.error(libc::EFBIG);
+        };
+        if let Err(e) = self.ensure_buffer(fh) {
+            return reply.error(errno(&e));
+        }
+
+        let Some(file) = self.files.get_mut(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let buffer = file.buffer.get_or_insert_with(Vec::new);
+        let offset = end - data.len();
+        if buffer.len() < end {
+            buffer.resize(end, 0);
+        }
+        buffer[offset..end].copy_from_slice(data);
+        file.dirty = true;
+
+        reply.written(data.len() as u32)
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        match self.flush_handle(fh) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.flush_handle(fh) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.flush_handle(fh);
+        self.files.remove(&fh);
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        if self.options.read_only {
+            return reply.error(libc::EROFS);
+        }
+        let path = match self.child_path(parent, name) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+        self.trace("create", &path);
+
+        let options = WriteOptions {
+            overwrite: flags & libc::O_EXCL == 0,
+            ..Default::default()
+        };
+        let entry = match self.block_on(self.backend.write_file(&path, Bytes::new(), &options)) {
+            Ok(entry) => entry,
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        let attr = self.record(&entry);
+        let fh = self.handle();
+        self.files.insert(
+            fh,
+            OpenFile {
+                ino: attr.ino,
+                path,
+                buffer: Some(Vec::new()),
+                dirty: false,
+            },
+        );
+
+        reply.created(&self.ttl(), &attr, 0, fh, 0)
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let Some(path) = self.inodes.path(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        self.trace("opendir", &path);
+
+        let listing = match self.list_all(&path) {
+            Ok(listing) => listing,
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        let parent_ino = path
+            .parent()
+            .map(|p| self.inodes.inode(&p))
+            .unwrap_or(ROOT_INODE);
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (parent_ino, FileType::Directory, "..".to_string()),
+        ];
+        for entry in &listing {
+            let Some(name) = entry.name() else {
+                continue;
+            };
+            let attr = self.record(entry);
+            entries.push((attr.ino, attr.kind, name.to_string()));
+        }
+
+        let fh = self.handle();
+        self.dirs.insert(fh, OpenDir { entries });
+        reply.opened(fh, 0)
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(dir) = self.dirs.get(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+
+        for (i, (ino, kind, name)) in dir.entries.iter().enumerate().skip(offset.max(0) as usize) {
+            // The offset handed back is that of the *next* entry
+            if reply.add(*ino, (i + 1) as i64, *kind, name) {
+                break;
+            }
+        }
+        reply.ok()
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.dirs.remove(&fh);
+        reply.ok()
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let space = self
+            .block_on(self.backend.get_space_info())
+            .unwrap_or_default();
+
+        let block = BLOCK_SIZE as u64;
+        let total = space.total.unwrap_or(0) / block;
+        let free = space.available.unwrap_or(0) / block;
+        reply.statfs(total, free, free, 0, 0, BLOCK_SIZE, 255, BLOCK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno_mapping() {
+        assert_eq!(errno(&CfkError::NotFound("x".into())), libc::ENOENT);
+        assert_eq!(
+            errno(&CfkError::DirectoryNotEmpty("x".into())),
+            libc::ENOTEMPTY
+        );
+        assert_eq!(errno(&CfkError::QuotaExceeded("x".into())), libc::ENOSPC);
+        assert_eq!(errno(&CfkError::Network("x".into())), libc::EIO);
+        assert_eq!(errno(&too_large()), libc::EFBIG);
+    }
+
+    #[tokio::test]
+    async fn test_collect_stops_at_the_limit() {
+        let stream = || -> ByteStream {
+            let chunks = vec![Ok(Bytes::from_static(b"abcd")), Ok(Bytes::from_static(b"efgh"))];
+            Box::pin(futures::stream::iter(chunks))
+        };
+        assert_eq!(collect(stream(), 8).await.unwrap(), b"abcdefgh");
+        let err = collect(stream(), 7).await.unwrap_err();
+        assert_eq!(errno(&err), libc::EFBIG);
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//! Inode allocation for mounted backends
+//!
+//! Backends address entries by path, the kernel by inode number. The table
+//! hands out a stable inode per path for the lifetime of the mount and keeps
+//! the mapping consistent across renames and deletes.
+
+use cfk_core::VirtualPath;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Inode number of the mount root, fixed by FUSE
+pub const ROOT_INODE: u64 = 1;
+
+/// Bidirectional inode <-> path mapping
+pub struct InodeTable {
+    paths: DashMap<u64, VirtualPath>,
+    inodes: DashMap<VirtualPath, u64>,
+    next: AtomicU64,
+}
+
+impl InodeTable {
+    /// Create a table with `root` at [`ROOT_INODE`]
+    pub fn new(root: VirtualPath) -> Self {
+        let table = Self {
+            paths: DashMap::new(),
+            inodes: DashMap::new(),
+            next: AtomicU64::new(ROOT_INODE + 1),
+        };
+        table.paths.insert(ROOT_INODE, root.clone());
+        table.inodes.insert(root, ROOT_INODE);
+        table
+    }
+
+    /// Path for an inode, if it is known
+    pub fn path(&self, ino: u64) -> Option<VirtualPath> {
+        self.paths.get(&ino).map(|p| p.clone())
+    }
+
+    /// Inode for a path, allocating one on first sight
+    pub fn inode(&self, path: &VirtualPath) -> u64 {
+        if let Some(ino) = self.inodes.get(path) {
+            return *ino;
+        }
+
+        *self.inodes.entry(path.clone()).or_insert_with(|| {
+            let ino = self.next.fetch_add(1, Ordering::Relaxed);
+            self.paths.insert(ino, path.clone());
+            ino
+        })
+    }
+
+    /// Inode of a path, without allocating one
+    pub fn find(&self, path: &VirtualPath) -> Option<u64> {
+        self.inodes.get(path).map(|ino| *ino)
+    }
+
+    /// Inodes of a path and everything below it that has one
+    pub fn subtree(&self, path: &VirtualPath) -> Vec<u64> {
+        self.inodes
+            .iter()
+            .filter(|e| e.key().segments.starts_with(&path.segments))
+            .map(|e| *e.value())
+            .collect()
+    }
+
+    /// Forget a path and everything below it
+    pub fn remove(&self, path: &VirtualPath) {
+        let doomed: Vec<VirtualPath> = self
+            .inodes
+            .iter()
+            .filter(|e| e.key().segments.starts_with(&path.segments))
+            .map(|e| e.key().clone())
+            .collect();
+
+        for p in doomed {
+            if let Some((_, ino)) = self.inodes.remove(&p) {
+                self.paths.remove(&ino);
+            }
+        }
+    }
+
+    /// Re-point `from` and its descendants at `to`, keeping inode numbers
+    pub fn rename(&self, from: &VirtualPath, to: &VirtualPath) {
+        // Whatever the destination held is gone
+        self.remove(to);
+
+        let moved: Vec<(VirtualPath, u64)> = self
+            .inodes
+            .iter()
+            .filter(|e| e.key().segments.starts_with(&from.segments))
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+
+        for (old, ino) in moved {
+            self.inodes.remove(&old);
+
+            let mut segments = to.segments.clone();
+            segments.extend_from_slice(&old.segments[from.segments.len()..]);
+            let new = VirtualPath {
+                backend: to.backend.clone(),
+                segments,
+            };
+
+            self.paths.insert(ino, new.clone());
+            self.inodes.insert(new, ino);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vp(path: &str) -> VirtualPath {
+        VirtualPath::new("t", path)
+    }
+
+    #[test]
+    fn test_inodes_are_stable() {
+        let table = InodeTable::new(VirtualPath::root("t"));
+        let a = table.inode(&vp("a"));
+        assert_eq!(table.inode(&vp("a")), a);
+        assert_ne!(table.inode(&vp("b")), a);
+        assert_eq!(table.path(a), Some(vp("a")));
+        assert_eq!(table.inode(&VirtualPath::root("t")), ROOT_INODE);
+    }
+
+    #[test]
+    fn test_rename_moves_descendants() {
+        let table = InodeTable::new(VirtualPath::root("t"));
+        let dir = table.inode(&vp("dir"));
+        let file = table.inode(&vp("dir/file"));
+        let other = table.inode(&vp("dirty"));
+
+        table.rename(&vp("dir"), &vp("moved"));
+
+        assert_eq!(table.path(dir), Some(vp("moved")));
+        assert_eq!(table.path(file), Some(vp("moved/file")));
+        assert_eq!(table.path(other), Some(vp("dirty")));
+        assert_eq!(table.inode(&vp("moved/file")), file);
+    }
+
+    #[test]
+    fn test_remove_drops_subtree() {
+        let table = InodeTable::new(VirtualPath::root("t"));
+        let file = table.inode(&vp("dir/file"));
+        let dir = table.inode(&vp("dir"));
+
+        table.remove(&vp("dir"));
+
+        assert!(table.path(file).is_none());
+        assert!(table.path(dir).is_none());
+        assert_eq!(table.path(ROOT_INODE), Some(VirtualPath::root("t")));
+    }
+}
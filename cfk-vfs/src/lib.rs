@@ -3,10 +3,19 @@
 //!
 //! This module provides FUSE mounting capabilities to access
 //! any CFK backend as a local filesystem.
-//! Currently a stub - full implementation coming in a future release.
+//! Mounting requires the `fuse` feature; without it `VfsMount::mount`
+//! returns `Unsupported`.
+
+#[cfg(feature = "fuse")]
+mod fs;
+#[cfg(feature = "fuse")]
+mod inode;
 
 use cfk_core::{CfkError, CfkResult};
-use std::path::PathBuf;
+use cfk_providers::BackendRegistry;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 /// VFS errors
@@ -44,31 +53,171 @@ pub struct MountOptions {
     pub cache: bool,
     /// Cache timeout in seconds
     pub cache_timeout_secs: Option<u64>,
+    /// Largest file that can be written, in bytes; files being written are
+    /// held in memory
+    pub max_file_size: Option<u64>,
     /// Debug mode
     pub debug: bool,
 }
 
+/// A live mount, as reported by [`list_mounts`]
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// Mounted backend
+    pub backend_id: String,
+    /// Local path the backend is mounted at
+    pub mount_point: PathBuf,
+    /// Options the mount was made with
+    pub options: MountOptions,
+}
+
+/// Process-wide table of mounts; the flag is cleared once the kernel
+/// session ends, including through an external `umount`
+struct MountEntry {
+    id: u64,
+    info: MountInfo,
+    alive: Arc<AtomicBool>,
+}
+
+static MOUNTS: Mutex<Vec<MountEntry>> = Mutex::new(Vec::new());
+static NEXT_MOUNT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn forget_mount(id: u64) {
+    if let Ok(mut mounts) = MOUNTS.lock() {
+        mounts.retain(|m| m.id != id);
+    }
+}
+
+impl From<VfsError> for CfkError {
+    fn from(e: VfsError) -> Self {
+        match e {
+            VfsError::MountPointNotFound(p) => CfkError::NotFound(p),
+            VfsError::MountPointNotDirectory(p) => CfkError::NotADirectory(p),
+            VfsError::AlreadyMounted(p) => CfkError::AlreadyExists(p),
+            VfsError::Io(e) => CfkError::Io(e),
+            other => CfkError::Other(other.to_string()),
+        }
+    }
+}
+
 /// VFS mount handle
+///
+/// The filesystem stays mounted until the handle is dropped or
+/// [`unmount`](Self::unmount) is called.
 pub struct VfsMount {
+    id: u64,
     mount_point: PathBuf,
-    _options: MountOptions,
+    alive: Arc<AtomicBool>,
+    #[cfg(feature = "fuse")]
+    session: Option<fuser::BackgroundSession>,
+    /// Runtime owned by the mount when none was running at mount time
+    #[cfg(feature = "fuse")]
+    runtime: Option<tokio::runtime::Runtime>,
 }
 
 impl VfsMount {
     /// Mount a CFK backend at the given path
     ///
     /// # Arguments
+    /// * `registry` - Registry to look the backend up in
     /// * `backend_id` - The backend to mount (e.g., "local", "dropbox")
     /// * `mount_point` - The local path to mount at
     /// * `options` - Mount options
     pub fn mount(
-        _backend_id: &str,
-        _mount_point: impl Into<PathBuf>,
-        _options: MountOptions,
+        registry: &BackendRegistry,
+        backend_id: &str,
+        mount_point: impl Into<PathBuf>,
+        options: MountOptions,
     ) -> CfkResult<Self> {
-        Err(CfkError::Unsupported(
-            "FUSE VFS mounting not yet implemented".into(),
-        ))
+        let mount_point = mount_point.into();
+        let backend = registry.get_or_err(backend_id)?;
+        check_mount_point(&mount_point)?;
+
+        #[cfg(feature = "fuse")]
+        {
+            Self::mount_fuse(backend, mount_point, options)
+        }
+
+        #[cfg(not(feature = "fuse"))]
+        {
+            let _ = (backend, options);
+            Err(CfkError::Unsupported(
+                "FUSE support not compiled in (enable the `fuse` feature)".into(),
+            ))
+        }
+    }
+
+    #[cfg(feature = "fuse")]
+    fn mount_fuse(
+        backend: Arc<dyn cfk_core::StorageBackend>,
+        mount_point: PathBuf,
+        options: MountOptions,
+    ) -> CfkResult<Self> {
+        use fuser::MountOption;
+
+        let (handle, runtime) = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => (handle, None),
+            Err(_) => {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()?;
+                (runtime.handle().clone(), Some(runtime))
+            }
+        };
+
+        let mut fuse_options = vec![
+            MountOption::FSName(format!("cfk:{}", backend.id())),
+            MountOption::Subtype("cfk".into()),
+            MountOption::DefaultPermissions,
+            if options.read_only {
+                MountOption::RO
+            } else {
+                MountOption::RW
+            },
+        ];
+        if options.allow_other {
+            fuse_options.push(MountOption::AllowOther);
+        }
+        if options.allow_root {
+            fuse_options.push(MountOption::AllowRoot);
+        }
+
+        let backend_id = backend.id().to_string();
+        let alive = Arc::new(AtomicBool::new(true));
+        let filesystem =
+            fs::CfkFilesystem::new(backend.clone(), handle.clone(), options.clone(), alive.clone());
+        let invalidator = filesystem.invalidator();
+        let session = fuser::spawn_mount2(filesystem, &mount_point, &fuse_options)
+            .map_err(|e| CfkError::from(VfsError::Fuse(e.to_string())))?;
+
+        // Cached attributes would otherwise hide changes made elsewhere
+        // until they time out
+        if options.cache && backend.capabilities().watch {
+            handle.spawn(invalidator.follow(backend, session.notifier(), alive.clone()));
+        }
+
+        tracing::info!("Mounted {} at {}", backend_id, mount_point.display());
+
+        let id = NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut mounts) = MOUNTS.lock() {
+            mounts.push(MountEntry {
+                id,
+                info: MountInfo {
+                    backend_id,
+                    mount_point: mount_point.clone(),
+                    options,
+                },
+                alive: alive.clone(),
+            });
+        }
+
+        Ok(Self {
+            id,
+            mount_point,
+            alive,
+            session: Some(session),
+            runtime,
+        })
     }
 
     /// Get the mount point path
@@ -78,27 +227,72 @@ pub fn mount_point(&self) -> &PathBuf {
 
     /// Check if the mount is still active
     pub fn is_mounted(&self) -> bool {
-        false
+        self.alive.load(Ordering::SeqCst)
     }
 
     /// Unmount the filesystem
-    pub fn unmount(self) -> CfkResult<()> {
-        Err(CfkError::Unsupported(
-            "FUSE VFS mounting not yet implemented".into(),
-        ))
+    pub fn unmount(mut self) -> CfkResult<()> {
+        if !self.is_mounted() {
+            return Err(VfsError::NotMounted.into());
+        }
+        self.release();
+        Ok(())
+    }
+
+    /// Tear the session down and drop the registry entry
+    fn release(&mut self) {
+        #[cfg(feature = "fuse")]
+        {
+            // Dropping the session unmounts, which ends the FUSE thread
+            drop(self.session.take());
+            if let Some(runtime) = self.runtime.take() {
+                runtime.shutdown_background();
+            }
+        }
+
+        self.alive.store(false, Ordering::SeqCst);
+        forget_mount(self.id);
     }
 }
 
 impl Drop for VfsMount {
     fn drop(&mut self) {
-        // Attempt to unmount on drop
-        // In real implementation, this would call fuser::unmount
+        self.release();
+    }
+}
+
+fn check_mount_point(path: &Path) -> Result<(), VfsError> {
+    let display = path.display().to_string();
+    let metadata = std::fs::metadata(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => VfsError::MountPointNotFound(display.clone()),
+        _ => VfsError::Io(e),
+    })?;
+    if !metadata.is_dir() {
+        return Err(VfsError::MountPointNotDirectory(display));
+    }
+
+    let canonical = path.canonicalize()?;
+    if list_mounts()
+        .iter()
+        .any(|m| m.mount_point.canonicalize().ok().as_ref() == Some(&canonical))
+    {
+        return Err(VfsError::AlreadyMounted(display));
     }
+    Ok(())
 }
 
 /// List active mounts
-pub fn list_mounts() -> Vec<VfsMount> {
-    Vec::new()
+pub fn list_mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .lock()
+        .map(|mounts| {
+            mounts
+                .iter()
+                .filter(|m| m.alive.load(Ordering::SeqCst))
+                .map(|m| m.info.clone())
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 /// Check if FUSE is available on this system
@@ -132,7 +326,64 @@ fn test_is_fuse_available() {
 
     #[test]
     fn test_mount_not_implemented() {
-        let result = VfsMount::mount("local", "/tmp/test", MountOptions::default());
+        let registry = BackendRegistry::new();
+        let result = VfsMount::mount(&registry, "local", "/tmp/test", MountOptions::default());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_mount_point_must_exist() {
+        let mut registry = BackendRegistry::new();
+        registry.register(Arc::new(cfk_providers::LocalBackend::new("local", "/tmp")));
+
+        let result = VfsMount::mount(
+            &registry,
+            "local",
+            "/nonexistent/cfk-mount-point",
+            MountOptions::default(),
+        );
+        assert!(matches!(result, Err(CfkError::NotFound(_))));
+    }
+
+    #[cfg(feature = "fuse")]
+    #[test]
+    fn test_mount_roundtrip() {
+        let backing = tempfile::tempdir().unwrap();
+        let mount_point = tempfile::tempdir().unwrap();
+        std::fs::write(backing.path().join("hello.txt"), b"hello").unwrap();
+
+        let mut registry = BackendRegistry::new();
+        registry.register(Arc::new(cfk_providers::LocalBackend::new("local", backing.path())));
+
+        // Mounting needs /dev/fuse and the right privileges; skip where the
+        // sandbox does not provide them
+        let mount = match VfsMount::mount(&registry, "local", mount_point.path(), MountOptions::default()) {
+            Ok(mount) => mount,
+            Err(_) => return,
+        };
+        assert!(mount.is_mounted());
+        assert!(list_mounts().iter().any(|m| m.mount_point == mount_point.path()));
+
+        let root = mount_point.path();
+        assert_eq!(std::fs::read(root.join("hello.txt")).unwrap(), b"hello");
+
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::fs::write(root.join("dir/new.txt"), b"written").unwrap();
+        std::fs::rename(root.join("dir/new.txt"), root.join("moved.txt")).unwrap();
+        assert_eq!(std::fs::read(backing.path().join("moved.txt")).unwrap(), b"written");
+
+        let mut names: Vec<String> = std::fs::read_dir(root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["dir", "hello.txt", "moved.txt"]);
+
+        std::fs::remove_file(root.join("moved.txt")).unwrap();
+        std::fs::remove_dir(root.join("dir")).unwrap();
+        assert!(!backing.path().join("dir").exists());
+
+        mount.unmount().unwrap();
+        assert!(list_mounts().iter().all(|m| m.mount_point != mount_point.path()));
+    }
 }